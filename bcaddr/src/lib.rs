@@ -1,9 +1,14 @@
 use blockchain_core::SecretAddress;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
 use std::fmt::{self, Display, Formatter};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
+pub mod output;
+
 pub fn read_address(path: impl AsRef<Path>) -> Result<SecretAddress, Error> {
     let file = File::open(path)?;
     let mut reader = BufReader::new(file);
@@ -17,16 +22,80 @@ pub fn read_address(path: impl AsRef<Path>) -> Result<SecretAddress, Error> {
 pub fn write_address(path: impl AsRef<Path>, addr: &SecretAddress) -> Result<(), Error> {
     let file = File::create(path)?;
     let mut writer = BufWriter::new(file);
-    let mut buf = bincode::serialize(addr)?;
-    writer.write_all(&mut buf)?;
+    let buf = bincode::serialize(addr)?;
+    writer.write_all(&buf)?;
 
     Ok(())
 }
 
+/// Message signed by the new address to derive the key of an archived address.
+const ARCHIVE_CONTEXT: &[u8] = b"bcaddr archive";
+
+const NONCE_LEN: usize = 12;
+
+/// Write `addr` encrypted by `owner`, so that the old key is kept only for its new owner.
+pub fn archive_address(
+    path: impl AsRef<Path>,
+    addr: &SecretAddress,
+    owner: &SecretAddress,
+) -> Result<(), Error> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    let buf = encrypt(owner, ARCHIVE_CONTEXT, &bincode::serialize(addr)?)?;
+    writer.write_all(&buf)?;
+
+    Ok(())
+}
+
+pub fn read_archived_address(
+    path: impl AsRef<Path>,
+    owner: &SecretAddress,
+) -> Result<SecretAddress, Error> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+    let address = bincode::deserialize(&decrypt(owner, ARCHIVE_CONTEXT, &buf)?)?;
+
+    Ok(address)
+}
+
+/// Encrypt `plaintext` by a key only `owner` can derive.
+/// `context` separates keys by purpose.
+/// Signatures are deterministic, so the same key comes back on every call.
+pub fn encrypt(owner: &SecretAddress, context: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(owner, context)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| Error::Cipher)?;
+
+    Ok(nonce.into_iter().chain(ciphertext).collect())
+}
+
+/// Reverse of `encrypt`.
+pub fn decrypt(owner: &SecretAddress, context: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < NONCE_LEN {
+        return Err(Error::Cipher);
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher(owner, context)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Cipher)
+}
+
+fn cipher(owner: &SecretAddress, context: &[u8]) -> ChaCha20Poly1305 {
+    let sign = owner.sign(context);
+    let key = Sha256::digest(sign.as_ref().to_bytes());
+    ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+}
+
 #[derive(Debug)]
 pub enum Error {
     IO(std::io::Error),
     Serde(bincode::Error),
+    /// Data is corrupted or encrypted by another address.
+    Cipher,
 }
 
 impl From<std::io::Error> for Error {
@@ -46,6 +115,7 @@ impl Display for Error {
         match self {
             Error::IO(e) => e.fmt(f),
             Error::Serde(e) => e.fmt(f),
+            Error::Cipher => write!(f, "Data cannot be decrypted by this address"),
         }
     }
 }
@@ -55,6 +125,7 @@ impl std::error::Error for Error {
         match self {
             Error::IO(e) => Some(e),
             Error::Serde(e) => Some(e),
+            Error::Cipher => None,
         }
     }
 }
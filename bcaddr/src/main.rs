@@ -1,6 +1,10 @@
 use anyhow::bail;
-use blockchain_core::SecretAddress;
+use bcaddr::output::{self, OutputFormat};
+use blockchain_core::psbt::SignTarget;
+use blockchain_core::signer::SigningRequest;
+use blockchain_core::{Address, SecretAddress};
 use clap::Parser;
+use serde::Serialize;
 
 #[derive(Debug, Parser)]
 struct BcAddrArgs {
@@ -15,12 +19,75 @@ struct BcAddrArgs {
     /// File path to secret address
     #[clap(short, long)]
     output: Option<String>,
+
+    /// File path to an address archived by key rotation, decrypted by --address.
+    /// Restored into --output if provided.
+    #[clap(long)]
+    archived: Option<String>,
+
+    /// Hex blob printed by `wallet export-signing-request`, signed by --address.
+    /// Prints the answer for `wallet import-signature`.
+    #[clap(long)]
+    sign_request: Option<String>,
+
+    /// Print results as text or json
+    #[clap(long, default_value = "text")]
+    output_format: OutputFormat,
+}
+
+/// Address printed by `--output-format json`.
+#[derive(Debug, Serialize)]
+struct AddressOutput {
+    /// Public address
+    address: Address,
+    /// File the secret address was written to, if any
+    file: Option<String>,
+}
+
+/// Answer printed by `--sign-request`.
+#[derive(Debug, Serialize)]
+struct SignatureOutput {
+    signer: Address,
+    targets: Vec<SignTarget>,
+    /// Hex blob for `wallet import-signature`
+    signature: String,
+}
+
+/// Sign a request of an online host with the address file, which never leaves this device.
+fn sign_request(args: &BcAddrArgs, blob: &str) -> anyhow::Result<()> {
+    let secret = match &args.address {
+        Some(i) => bcaddr::read_address(i)?,
+        None => bail!("Provide address file to sign with."),
+    };
+    let request = SigningRequest::from_hex(blob)?;
+    let response = request.sign(&secret)?;
+    let result = SignatureOutput {
+        signer: request.signer().clone(),
+        targets: request.messages().iter().map(|(t, _)| *t).collect(),
+        signature: response.to_hex(),
+    };
+
+    match args.output_format {
+        OutputFormat::Text => {
+            let targets = result.targets.iter().map(ToString::to_string);
+            let targets = targets.collect::<Vec<_>>().join(", ");
+            println!("Signed {} by {}:", targets, result.signer);
+            println!("{}", result.signature);
+        }
+        OutputFormat::Json => output::print_json(&result)?,
+    }
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
     let args = BcAddrArgs::parse();
 
-    if args.create {
+    if let Some(blob) = &args.sign_request {
+        return sign_request(&args, blob);
+    }
+
+    // Result, with its sentence for text output if any
+    let (result, text) = if args.create {
         let output = match &args.output {
             Some(o) => o,
             None => bail!("Provide output destination."),
@@ -28,13 +95,44 @@ fn main() -> anyhow::Result<()> {
 
         let address = SecretAddress::create();
         bcaddr::write_address(output, &address)?;
+        let result = AddressOutput {
+            address: address.to_public_address(),
+            file: Some(output.clone()),
+        };
+        (result, None)
+    } else if let Some(archived) = &args.archived {
+        let owner = match &args.address {
+            Some(i) => bcaddr::read_address(i)?,
+            None => bail!("Provide address file which the archive was rotated to."),
+        };
+        let address = bcaddr::read_archived_address(archived, &owner)?;
+        if let Some(output) = &args.output {
+            bcaddr::write_address(output, &address)?;
+        }
+        let address = address.to_public_address();
+        let text = format!("Archived address: {}", address);
+        let result = AddressOutput {
+            address,
+            file: args.output.clone(),
+        };
+        (result, Some(text))
     } else {
         let input = match &args.address {
             Some(i) => i,
             None => bail!("Provide address file."),
         };
         let address = bcaddr::read_address(input).map(|addr| addr.to_public_address())?;
-        println!("Public address: {}", address);
+        let text = format!("Public address: {}", address);
+        let result = AddressOutput {
+            address,
+            file: None,
+        };
+        (result, Some(text))
+    };
+
+    match args.output_format {
+        OutputFormat::Text => text.iter().for_each(|text| println!("{}", text)),
+        OutputFormat::Json => output::print_json(&result)?,
     }
 
     Ok(())
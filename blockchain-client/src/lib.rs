@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use blockchain_core::compact::CompactBlock;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::tracker::SendTransactionError;
+use blockchain_core::{Address, BlockHeight, Transition};
+use blockchain_core::{UnverifiedBlock, VerifiedTransaction, Yet};
+use blockchain_net::impl_zeromq::NetError;
+use blockchain_net::sync::SyncError;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub mod mock;
+pub mod remote;
+
+pub use blockchain_net::impl_zeromq::RpcClient;
+pub use mock::MockNode;
+pub use remote::RemoteNode;
+
+/// Blocks found after subscription, in order of arrival.
+pub type BlockSubscription = UnboundedReceiver<CompactBlock>;
+
+/// What a wallet or other tool asks a fullnode, independent of how it reaches the node.
+#[async_trait]
+pub trait NodeClient {
+    /// UTXO of `address` in the longest chain.
+    async fn get_utxos(&mut self, address: &Address) -> Result<Vec<Transition<Yet>>, ClientError>;
+
+    /// Submit `transaction` to the mempool of the node, which relays it to miners.
+    async fn send_transaction(
+        &mut self,
+        transaction: &VerifiedTransaction,
+    ) -> Result<BlockDigest, ClientError>;
+
+    /// Block of the longest chain at `height`, or `None` if the chain is not so long.
+    async fn get_block(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<Option<UnverifiedBlock>, ClientError>;
+
+    /// Receive blocks found from now on.
+    async fn subscribe_blocks(&mut self) -> Result<BlockSubscription, ClientError>;
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Net(NetError),
+    /// Node cannot serve the requested block.
+    Sync(SyncError),
+    /// Node denied the submitted transaction.
+    Rejected(SendTransactionError),
+}
+
+impl From<NetError> for ClientError {
+    fn from(e: NetError) -> Self {
+        ClientError::Net(e)
+    }
+}
+
+impl From<SyncError> for ClientError {
+    fn from(e: SyncError) -> Self {
+        ClientError::Sync(e)
+    }
+}
+
+impl From<SendTransactionError> for ClientError {
+    fn from(e: SendTransactionError) -> Self {
+        ClientError::Rejected(e)
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Net(e) => e.fmt(f),
+            ClientError::Sync(e) => e.fmt(f),
+            ClientError::Rejected(e) => write!(f, "Node denied the transaction. {}", e),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Net(e) => Some(e),
+            ClientError::Sync(e) => Some(e),
+            ClientError::Rejected(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{BlockSubscription, ClientError, NodeClient};
+use async_trait::async_trait;
+use blockchain_core::compact::CompactBlock;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::ledger::{Ledger, LedgerError};
+use blockchain_core::mempool::Mempool;
+use blockchain_core::tracker::SendTransactionError;
+use blockchain_core::{Address, BlockHeight, BlockSource, ChainParams, Difficulty, SecretAddress};
+use blockchain_core::{Transition, UnverifiedBlock, VerifiedTransaction, Yet};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Upper limit of the mempool size, in bytes.
+const MEMPOOL_BYTES: usize = 1024 * 1024;
+
+/// In-memory node to test wallets and other tools without networking or Proof-of-Work.
+/// Sent transactions wait in the mempool until `mine` puts them into a block.
+pub struct MockNode {
+    ledger: Ledger,
+    mempool: Mempool,
+    miner: SecretAddress,
+    subscribers: Vec<UnboundedSender<CompactBlock>>,
+}
+
+impl MockNode {
+    /// Node of the main chain rules, whose block rewards go to `miner`.
+    pub fn new(miner: SecretAddress) -> Self {
+        Self::with_params(ChainParams::default(), miner)
+    }
+
+    pub fn with_params(params: ChainParams, miner: SecretAddress) -> Self {
+        Self {
+            ledger: Ledger::with_params(params),
+            mempool: Mempool::new(MEMPOOL_BYTES),
+            miner,
+            subscribers: vec![],
+        }
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Receiver of block rewards.
+    pub fn miner(&self) -> &SecretAddress {
+        &self.miner
+    }
+
+    /// Append a block of all mempool transactions to the longest chain, then notify subscribers.
+    /// Mempool is kept if the block is denied.
+    pub fn mine(&mut self) -> Result<BlockDigest, LedgerError> {
+        let (height, previous_digest) = match self.ledger.search_latest_block() {
+            Some(block) => (block.height().next(), Some(block.digest().clone())),
+            None => (BlockHeight::genesis(), None),
+        };
+        let params = *self.ledger.params();
+
+        let mut source = BlockSource::new(
+            height,
+            self.mempool.snapshot(),
+            previous_digest.clone().unwrap_or(BlockDigest::digest(&[])),
+            Difficulty::new(0),
+            0,
+            &self.miner,
+            params.generation_rule(),
+        )
+        .expect("Generation transaction is valid");
+        let commitment = self
+            .ledger
+            .utxo_commitment_after(previous_digest.as_ref(), source.transactions());
+        if let Some(commitment) = commitment {
+            source.commit_utxos(commitment);
+        }
+        let block = match source.try_into_block() {
+            Ok(block) => block,
+            Err(_) => unreachable!("Difficulty 0 accepts any digest"),
+        };
+        let block = block
+            .verify_transaction_relation_with(&params)
+            .and_then(|b| b.verify_difficulty(&Difficulty::new(0)))
+            .and_then(|b| b.verify_digest())?;
+        let block = self.ledger.verify_block(block)?;
+
+        let compact = self.ledger.compact_block(&block);
+        let digest = block.digest().clone();
+        self.ledger.entry(block)?;
+        self.mempool.clear();
+
+        if let Some(compact) = compact {
+            self.subscribers
+                .retain(|subscriber| subscriber.send(compact.clone()).is_ok());
+        }
+
+        Ok(digest)
+    }
+}
+
+#[async_trait]
+impl NodeClient for MockNode {
+    async fn get_utxos(&mut self, address: &Address) -> Result<Vec<Transition<Yet>>, ClientError> {
+        let utxos = match self.ledger.search_latest_block() {
+            Some(block) => self.ledger.build_utxos(block.digest(), address),
+            None => vec![],
+        };
+        Ok(utxos.iter().map(Transition::to_unverified).collect())
+    }
+
+    async fn send_transaction(
+        &mut self,
+        transaction: &VerifiedTransaction,
+    ) -> Result<BlockDigest, ClientError> {
+        self.mempool
+            .insert(Arc::new(transaction.clone()))
+            .map_err(SendTransactionError::from)?;
+        Ok(transaction.txid())
+    }
+
+    async fn get_block(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<Option<UnverifiedBlock>, ClientError> {
+        let block = self
+            .ledger
+            .search_latest_chain()
+            .find(|block| block.height() == height)
+            .map(|block| block.to_unverified());
+        Ok(block)
+    }
+
+    async fn subscribe_blocks(&mut self) -> Result<BlockSubscription, ClientError> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.subscribers.push(sender);
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::{Coin, Transaction, Transfer};
+
+    #[tokio::test]
+    async fn test_mock_node_send_and_mine() {
+        let bob = SecretAddress::create().to_public_address();
+        let mut node = MockNode::new(SecretAddress::create());
+        let alice = node.miner().to_public_address();
+        let mut blocks = node.subscribe_blocks().await.unwrap();
+
+        node.mine().unwrap();
+        assert_eq!(
+            BlockHeight::genesis(),
+            blocks.recv().await.unwrap().height()
+        );
+        let utxos = node
+            .get_utxos(&alice)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|utxo| utxo.verify().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(1, utxos.len());
+
+        let quantity = utxos[0].quantity();
+        let output = Transfer::offer(node.miner(), bob.clone(), quantity);
+        let tx = Transaction::offer(node.miner(), utxos, vec![output])
+            .verify_transaction()
+            .unwrap();
+        let txid = node.send_transaction(&tx).await.unwrap();
+        assert!(matches!(
+            node.send_transaction(&tx).await,
+            Err(ClientError::Rejected(_))
+        ));
+        assert!(node.mempool().contains(&txid));
+
+        let tip = node.mine().unwrap();
+        assert!(node.mempool().is_empty());
+        let block = node.get_block(BlockHeight::from(1)).await.unwrap().unwrap();
+        assert_eq!(&tip, block.digest());
+        assert_eq!(tip, *blocks.recv().await.unwrap().digest());
+
+        let received = node.get_utxos(&bob).await.unwrap();
+        assert_eq!(
+            vec![quantity],
+            received
+                .iter()
+                .map(Transition::quantity)
+                .collect::<Vec<Coin>>()
+        );
+        assert_eq!(None, node.get_block(BlockHeight::from(2)).await.unwrap());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{BlockSubscription, ClientError, NodeClient};
+use async_trait::async_trait;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::{Address, BlockHeight, Transition};
+use blockchain_core::{UnverifiedBlock, VerifiedTransaction, Yet};
+use blockchain_net::async_net::Subscriber;
+use blockchain_net::impl_zeromq::{RpcClient, TopicSubscriber, DEFAULT_BROKER};
+use blockchain_net::sync::SyncError;
+use blockchain_net::topic::NotifyBlock;
+
+/// Fullnode reached through proxies.
+pub struct RemoteNode {
+    brokers: Vec<String>,
+    rpc: RpcClient,
+}
+
+impl RemoteNode {
+    /// Connect through the proxy on the default broker.
+    pub async fn connect() -> Result<Self, ClientError> {
+        Self::connect_to(&[DEFAULT_BROKER]).await
+    }
+
+    /// Connect through all of `brokers`, so that any node reachable from them may serve requests.
+    pub async fn connect_to(brokers: &[impl AsRef<str>]) -> Result<Self, ClientError> {
+        let node = Self {
+            brokers: brokers.iter().map(|b| b.as_ref().to_string()).collect(),
+            rpc: RpcClient::new(brokers),
+        };
+        Ok(node)
+    }
+
+    /// Send `token` along with every request, for nodes requiring auth.
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            rpc: self.rpc.with_token(token),
+            ..self
+        }
+    }
+
+    /// Typed client of every service, for requests beyond `NodeClient`.
+    pub fn rpc(&mut self) -> &mut RpcClient {
+        &mut self.rpc
+    }
+}
+
+#[async_trait]
+impl NodeClient for RemoteNode {
+    async fn get_utxos(&mut self, address: &Address) -> Result<Vec<Transition<Yet>>, ClientError> {
+        let utxos = self.rpc.utxo_by_address(address).await?;
+        Ok(utxos)
+    }
+
+    async fn send_transaction(
+        &mut self,
+        transaction: &VerifiedTransaction,
+    ) -> Result<BlockDigest, ClientError> {
+        let txid = self
+            .rpc
+            .send_transaction(&transaction.to_unverified())
+            .await??;
+        Ok(txid)
+    }
+
+    async fn get_block(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<Option<UnverifiedBlock>, ClientError> {
+        match self.rpc.block_by_height(&height).await? {
+            Ok(block) => Ok(Some(block)),
+            Err(SyncError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn subscribe_blocks(&mut self) -> Result<BlockSubscription, ClientError> {
+        let mut subscriber = TopicSubscriber::<NotifyBlock>::connect_to(&self.brokers).await?;
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        // Runs until the subscription is dropped
+        tokio::spawn(async move {
+            while let Ok(block) = subscriber.recv().await {
+                if sender.send(block).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+}
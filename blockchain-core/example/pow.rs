@@ -3,8 +3,10 @@ use blockchain_core::block::*;
 use blockchain_core::coin::Coin;
 use blockchain_core::difficulty::Difficulty;
 use blockchain_core::digest::BlockDigest;
+use blockchain_core::params::ChainParams;
 use blockchain_core::transaction::*;
 use blockchain_core::transition::*;
+use std::sync::Arc;
 
 fn main() {
     let input_sender = SecretAddress::create();
@@ -29,7 +31,7 @@ fn main() {
 
     let mut block_source = BlockSource::new(
         height,
-        vec![tx],
+        vec![Arc::new(tx)],
         previous_digest,
         difficulty.clone(),
         nonce,
@@ -52,7 +54,9 @@ fn main() {
     };
 
     // Block verification
-    let block = block.verify_transaction_relation(gen_rule).unwrap();
+    let block = block
+        .verify_transaction_relation_with(&ChainParams::new(gen_rule))
+        .unwrap();
     let block = block.verify_utxo(|_| true).unwrap();
     let block = block.verify_digest().unwrap();
     let block = block.verify_previous_block(|_, _| true).unwrap();
@@ -66,7 +70,9 @@ fn main() {
     let de = serde_json::from_str::<Block<_, _, _, _, _, _>>(&ser).unwrap();
 
     let de = de.verify_transaction_itself().unwrap();
-    let de = de.verify_transaction_relation(gen_rule).unwrap();
+    let de = de
+        .verify_transaction_relation_with(&ChainParams::new(gen_rule))
+        .unwrap();
     let de = de.verify_utxo(|_| true).unwrap();
     let de = de.verify_digest().unwrap();
     let de = de.verify_previous_block(|_, _| true).unwrap();
@@ -1,8 +1,12 @@
 use crate::signature::{Signature, SignatureBuilder, SignatureSource};
 use apply::Apply;
-use ed25519_dalek::{Keypair, PublicKey, Signer, Verifier};
-use serde::{Deserialize, Serialize};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer, Verifier};
+use rand::{CryptoRng, RngCore};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,7 +16,13 @@ pub struct SecretAddress {
 
 impl SecretAddress {
     pub fn create() -> Self {
-        let keypair = Keypair::generate(&mut rand::rngs::OsRng {});
+        Self::create_with_rng(&mut rand::rngs::OsRng {})
+    }
+
+    /// Create from the given randomness. A seeded `rng` always creates the same address,
+    /// which only tests and simulations may rely on.
+    pub fn create_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let keypair = Keypair::generate(rng);
         SecretAddress { keypair }
     }
 
@@ -20,6 +30,17 @@ impl SecretAddress {
         self.keypair.sign(message).apply(Signature::from)
     }
 
+    /// Derive another address, which only this one can derive again from the same `context`.
+    /// Signs are deterministic, so a lost key file of a derived address can be derived again.
+    pub fn derive(&self, context: &[u8]) -> Self {
+        let seed = Sha256::digest(self.keypair.sign(context).to_bytes());
+        let secret = SecretKey::from_bytes(&seed).expect("SHA-256 digest is a secret key");
+        let public = PublicKey::from(&secret);
+        SecretAddress {
+            keypair: Keypair { secret, public },
+        }
+    }
+
     pub fn to_public_address(&self) -> Address {
         Address {
             publickey: self.keypair.public,
@@ -27,7 +48,7 @@ impl SecretAddress {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Address {
     publickey: PublicKey,
 }
@@ -38,6 +59,15 @@ impl Address {
     }
 }
 
+impl Hash for Address {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.publickey.as_bytes().hash(state);
+    }
+}
+
 impl SignatureSource for Address {
     fn write_bytes(&self, builder: &mut SignatureBuilder) {
         builder.write_bytes(self.publickey.as_bytes().as_slice());
@@ -46,7 +76,7 @@ impl SignatureSource for Address {
 
 impl Display for Address {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let s = hex::encode(&self.publickey.as_bytes());
+        let s = hex::encode(self.publickey.as_bytes());
         s.fmt(f)
     }
 }
@@ -61,6 +91,67 @@ impl FromStr for Address {
     }
 }
 
+/// Human-readable formats such as JSON get a hex string.
+/// Binary formats keep the ed25519 byte representation.
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.publickey.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AddressVisitor;
+
+        impl<'de> Visitor<'de> for AddressVisitor {
+            type Value = Address;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "an address as a hex string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Address::from_str(v).map_err(E::custom)
+            }
+
+            // Older versions emitted `{"publickey": [...]}`
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut publickey = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "publickey" => publickey = Some(map.next_value::<PublicKey>()?),
+                        _ => return Err(de::Error::unknown_field(&key, &["publickey"])),
+                    }
+                }
+                let publickey = publickey.ok_or_else(|| de::Error::missing_field("publickey"))?;
+                Ok(Address { publickey })
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(AddressVisitor)
+        } else {
+            PublicKey::deserialize(deserializer).map(|publickey| Address { publickey })
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum AddressError {
     HexDecode(hex::FromHexError),
@@ -100,8 +191,32 @@ impl std::error::Error for AddressError {
 #[cfg(test)]
 mod tests {
     use crate::{Address, SecretAddress};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
     use std::str::FromStr;
 
+    #[test]
+    fn test_create_with_seed() {
+        let create = |seed| SecretAddress::create_with_rng(&mut StdRng::seed_from_u64(seed));
+        assert_eq!(create(1).to_public_address(), create(1).to_public_address());
+        assert_ne!(create(1).to_public_address(), create(2).to_public_address());
+    }
+
+    #[test]
+    fn test_derive() {
+        let owner = SecretAddress::create();
+        let derive =
+            |owner: &SecretAddress, context: &[u8]| owner.derive(context).to_public_address();
+        assert_eq!(derive(&owner, b"a"), derive(&owner, b"a"));
+        assert_ne!(derive(&owner, b"a"), derive(&owner, b"b"));
+        assert_ne!(derive(&owner, b"a"), derive(&SecretAddress::create(), b"a"));
+        assert_ne!(owner.to_public_address(), derive(&owner, b"a"));
+
+        let derived = owner.derive(b"a");
+        let sign = derived.sign(b"message");
+        assert!(derived.to_public_address().verify(b"message", &sign));
+    }
+
     #[test]
     fn test_sign() {
         let secret_address = SecretAddress::create();
@@ -144,4 +259,29 @@ mod tests {
 
         assert_eq!(address, from_str);
     }
+
+    #[test]
+    fn test_serde_hex_string() {
+        let address = SecretAddress::create().to_public_address();
+
+        let ser = serde_json::to_string(&address).unwrap();
+        let de = serde_json::from_str::<Address>(&ser).unwrap();
+
+        assert_eq!(format!("\"{}\"", address), ser);
+        assert_eq!(address, de);
+    }
+
+    #[test]
+    fn test_serde_legacy_map() {
+        let address = SecretAddress::create().to_public_address();
+        let bytes = address.publickey.as_bytes().to_vec();
+
+        let ser = format!(
+            "{{\"publickey\":{}}}",
+            serde_json::to_string(&bytes).unwrap()
+        );
+        let de = serde_json::from_str::<Address>(&ser).unwrap();
+
+        assert_eq!(address, de);
+    }
 }
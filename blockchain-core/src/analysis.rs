@@ -0,0 +1,382 @@
+use crate::digest::BlockDigest;
+use crate::ledger::Ledger;
+use crate::transition::Transition;
+use crate::verification::Verified;
+use crate::{Address, BlockHeight, Coin, VerifiedBlock};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Statistics over a chain, from genesis to a given block.
+#[derive(Debug, Clone)]
+pub struct ChainAnalysis<'a> {
+    /// Blocks ordered from genesis
+    blocks: Vec<&'a VerifiedBlock>,
+}
+
+impl<'a> ChainAnalysis<'a> {
+    pub fn new(ledger: &'a Ledger, digest: &BlockDigest) -> Self {
+        let blocks = ledger.downstream_chain_to(digest).collect_vec();
+        Self { blocks }
+    }
+
+    /// Statistics over the longest chain, which are empty before genesis.
+    pub fn of_best_chain(ledger: &'a Ledger) -> Self {
+        match ledger.search_latest_block() {
+            Some(tip) => Self::new(ledger, tip.digest()),
+            None => Self { blocks: vec![] },
+        }
+    }
+
+    /// Balance of `address` after each block which changed it.
+    pub fn balance_history(&self, address: &Address) -> Vec<(BlockHeight, Coin)> {
+        let mut balance = Coin::default();
+        let mut history = vec![];
+
+        for block in self.blocks.iter() {
+            let received = block
+                .outputs()
+                .filter(|o| o.receiver() == address)
+                .map(Transition::quantity)
+                .sum::<Coin>();
+            let spent = block
+                .inputs()
+                .filter(|i| i.receiver() == address)
+                .map(Transition::quantity)
+                .sum::<Coin>();
+
+            if received != spent {
+                balance = balance + received - spent;
+                history.push((block.height(), balance));
+            }
+        }
+
+        history
+    }
+
+    /// The `count` largest holders at the tip, in descending order of balance.
+    pub fn rich_list(&self, count: usize) -> Vec<(Address, Coin)> {
+        let mut balances = HashMap::<&Address, Coin>::new();
+        for utxo in self.utxos().map(|(_, utxo)| utxo) {
+            let balance = balances.entry(utxo.receiver()).or_default();
+            *balance = *balance + utxo.quantity();
+        }
+
+        balances
+            .into_iter()
+            .sorted_by(|(_, a), (_, b)| b.cmp(a))
+            .take(count)
+            .map(|(address, balance)| (address.clone(), balance))
+            .collect()
+    }
+
+    /// Sum of transferred coins per day. Coin generation is not counted.
+    pub fn daily_volume(&self) -> DailyVolume {
+        let mut volumes = BTreeMap::new();
+        for block in self.blocks.iter() {
+            let volume = block
+                .outputs()
+                .filter_map(Transition::try_as_transfer)
+                .map(|t| t.quantity())
+                .sum::<Coin>();
+            let daily = volumes.entry(block.timestamp().date()).or_default();
+            *daily = *daily + volume;
+        }
+        volumes
+    }
+
+    /// Total UTXO quantity keyed by age, which is how many blocks ago the UTXO was created.
+    pub fn utxo_age_distribution(&self) -> BTreeMap<u64, Coin> {
+        let tip = match self.blocks.last() {
+            Some(block) => block.height(),
+            None => return BTreeMap::new(),
+        };
+
+        let mut distribution = BTreeMap::new();
+        for (height, utxo) in self.utxos() {
+            let quantity = distribution.entry(height.distance(tip)).or_default();
+            *quantity = *quantity + utxo.quantity();
+        }
+        distribution
+    }
+
+    /// UTXOs at the tip with the height where they were created.
+    fn utxos(&self) -> impl Iterator<Item = (BlockHeight, &'a Transition<Verified>)> {
+        let mut utxos = vec![];
+        for block in self.blocks.iter() {
+            for tx in block.transactions() {
+                utxos.retain(|(_, u): &(_, &Transition<Verified>)| !tx.inputs().contains(u));
+                utxos.extend(tx.outputs().iter().map(|o| (block.height(), o)));
+            }
+        }
+        utxos.into_iter()
+    }
+}
+
+/// Transferred coins per day.
+pub type DailyVolume = BTreeMap<NaiveDate, Coin>;
+
+/// Statistics of the best chain on one day, as a point of charts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyStats {
+    pub date: NaiveDate,
+    pub blocks: u64,
+    /// Coins minted from genesis to the end of the day
+    pub total_supply: Coin,
+    pub fees: Coin,
+    /// Addresses which sent or received coins on the day
+    pub active_addresses: usize,
+}
+
+/// Sums of one day, kept apart for each address so that a block can be taken back.
+#[derive(Debug, Clone, Default)]
+struct DaySums {
+    blocks: u64,
+    minted: Coin,
+    fees: Coin,
+    /// Transactions of the day which each address sends or receives in
+    addresses: HashMap<Address, usize>,
+}
+
+/// Daily statistics of the best chain, updated block by block as the chain moves.
+#[derive(Debug, Clone, Default)]
+pub struct ChainStats {
+    days: BTreeMap<NaiveDate, DaySums>,
+    tip: Option<(BlockHeight, BlockDigest)>,
+}
+
+impl ChainStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block up to which this is counted, or `None` before genesis.
+    pub fn tip(&self) -> Option<&(BlockHeight, BlockDigest)> {
+        self.tip.as_ref()
+    }
+
+    /// Count `block`, which must follow the tip.
+    pub fn connect_block(&mut self, block: &VerifiedBlock) -> Result<(), ChainStatsError> {
+        let follows = match &self.tip {
+            Some((_, digest)) => block.previous_digest() == digest,
+            None => block.height().is_genesis(),
+        };
+        if !follows {
+            return Err(ChainStatsError::NotOnTip);
+        }
+
+        let (minted, fees) = Self::minted_and_fees(block);
+        let day = self.days.entry(block.timestamp().date()).or_default();
+        day.blocks += 1;
+        day.minted = day.minted + minted;
+        day.fees = day.fees + fees;
+        for address in Self::addresses_of(block) {
+            *day.addresses.entry(address.clone()).or_default() += 1;
+        }
+        self.tip = Some((block.height(), block.digest().clone()));
+        Ok(())
+    }
+
+    /// Take back `block`, which must be the tip.
+    pub fn disconnect_block(&mut self, block: &VerifiedBlock) -> Result<(), ChainStatsError> {
+        if self.tip.as_ref().map(|(_, digest)| digest) != Some(block.digest()) {
+            return Err(ChainStatsError::NotOnTip);
+        }
+
+        let date = block.timestamp().date();
+        if let Some(day) = self.days.get_mut(&date) {
+            let (minted, fees) = Self::minted_and_fees(block);
+            day.blocks -= 1;
+            day.minted = day.minted - minted;
+            day.fees = day.fees - fees;
+            for address in Self::addresses_of(block) {
+                if let Some(count) = day.addresses.get_mut(address) {
+                    *count -= 1;
+                    if *count == 0 {
+                        day.addresses.remove(address);
+                    }
+                }
+            }
+            if day.blocks == 0 {
+                self.days.remove(&date);
+            }
+        }
+        self.tip = block
+            .height()
+            .previous()
+            .map(|height| (height, block.previous_digest().clone()));
+        Ok(())
+    }
+
+    /// Statistics of each day which has a block, from genesis.
+    pub fn daily(&self) -> Vec<DailyStats> {
+        let mut total_supply = Coin::default();
+        self.days
+            .iter()
+            .map(|(date, day)| {
+                total_supply = total_supply + day.minted;
+                DailyStats {
+                    date: *date,
+                    blocks: day.blocks,
+                    total_supply,
+                    fees: day.fees,
+                    active_addresses: day.addresses.len(),
+                }
+            })
+            .collect()
+    }
+
+    /// Coins minted by `block`, and fees paid to its miner.
+    /// Fees are moved between holders, so they are not counted as minted coins.
+    fn minted_and_fees(block: &VerifiedBlock) -> (Coin, Coin) {
+        let inputs = block.inputs().map(Transition::quantity).sum::<Coin>();
+        let outputs = block.outputs().map(Transition::quantity).sum::<Coin>();
+        let minted = outputs.checked_sub(inputs).unwrap_or_default();
+        let fees = block.transactions().iter().map(|tx| tx.fee()).sum::<Coin>();
+        (minted, fees)
+    }
+
+    /// Addresses which each transaction of `block` sends or receives in, once per transaction.
+    fn addresses_of(block: &VerifiedBlock) -> impl Iterator<Item = &Address> {
+        block.transactions().iter().flat_map(|tx| {
+            tx.inputs()
+                .iter()
+                .chain(tx.outputs())
+                .map(Transition::receiver)
+                .collect::<HashSet<_>>()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainStatsError {
+    /// Block does not follow, or is not, the tip of the statistics.
+    NotOnTip,
+}
+
+impl Display for ChainStatsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainStatsError::NotOnTip => write!(f, "Block is not on the tip of chain statistics"),
+        }
+    }
+}
+
+impl Error for ChainStatsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    /// Miner mines genesis, then sends 60 coins to alice in the next block.
+    fn create_ledger(miner: &SecretAddress, alice: &Address) -> Ledger {
+        let mut ledger = Ledger::new();
+        mine(&mut ledger, vec![], miner).unwrap();
+
+        let reward = reward(ledger.search_latest_block().unwrap());
+        let outputs = vec![
+            Transfer::offer(miner, alice.clone(), Coin::from(60)),
+            Transfer::offer(miner, miner.to_public_address(), Coin::from(40)),
+        ];
+        let tx = Transaction::offer(miner, vec![reward], outputs)
+            .verify_transaction()
+            .unwrap();
+        mine(&mut ledger, vec![tx], miner).unwrap();
+
+        ledger
+    }
+
+    #[test]
+    fn test_chain_analysis() {
+        let miner = SecretAddress::create();
+        let alice = SecretAddress::create().to_public_address();
+        let ledger = create_ledger(&miner, &alice);
+        let tip = ledger.search_latest_block().unwrap().digest().clone();
+        let analysis = ChainAnalysis::new(&ledger, &tip);
+
+        let genesis = BlockHeight::genesis();
+        assert_eq!(
+            vec![
+                (genesis, Coin::from(100)),
+                (genesis.next(), Coin::from(140))
+            ],
+            analysis.balance_history(&miner.to_public_address())
+        );
+        assert_eq!(
+            vec![(genesis.next(), Coin::from(60))],
+            analysis.balance_history(&alice)
+        );
+
+        assert_eq!(
+            vec![
+                (miner.to_public_address(), Coin::from(140)),
+                (alice.clone(), Coin::from(60))
+            ],
+            analysis.rich_list(10)
+        );
+        assert_eq!(
+            vec![(miner.to_public_address(), Coin::from(140))],
+            analysis.rich_list(1)
+        );
+
+        let volume = analysis.daily_volume();
+        assert_eq!(
+            Some(Coin::from(100)),
+            volume.values().copied().reduce(|a, b| a + b)
+        );
+
+        let ages = analysis.utxo_age_distribution();
+        assert_eq!(Some(&Coin::from(200)), ages.get(&0));
+        assert_eq!(None, ages.get(&1));
+
+        // Same as the analysis up to the tip, and empty before genesis
+        let best = ChainAnalysis::of_best_chain(&ledger);
+        assert_eq!(analysis.rich_list(10), best.rich_list(10));
+        let empty = Ledger::new();
+        assert!(ChainAnalysis::of_best_chain(&empty)
+            .rich_list(10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chain_stats() {
+        let miner = SecretAddress::create();
+        let alice = SecretAddress::create().to_public_address();
+        let ledger = create_ledger(&miner, &alice);
+        let tip = ledger.search_latest_block().unwrap();
+        let blocks = ledger.downstream_chain_to(tip.digest()).collect_vec();
+
+        let mut stats = ChainStats::new();
+        assert_eq!(Err(ChainStatsError::NotOnTip), stats.connect_block(tip));
+        for block in blocks.iter() {
+            stats.connect_block(block).unwrap();
+        }
+        let daily = stats.daily();
+        assert_eq!(2, daily.iter().map(|day| day.blocks).sum::<u64>());
+        assert_eq!(
+            Some(Coin::from(200)),
+            daily.last().map(|day| day.total_supply)
+        );
+        assert!(daily.iter().all(|day| day.fees == Coin::default()));
+        // Both blocks are mined within a day, unless midnight passed in between
+        if let [day] = daily.as_slice() {
+            assert_eq!(2, day.active_addresses);
+        }
+
+        stats.disconnect_block(tip).unwrap();
+        assert_eq!(
+            Some(blocks[0].digest()),
+            stats.tip().map(|(_, digest)| digest)
+        );
+        let daily = stats.daily();
+        assert_eq!(1, daily.len());
+        assert_eq!(1, daily[0].blocks);
+        assert_eq!(Coin::from(100), daily[0].total_supply);
+        assert_eq!(1, daily[0].active_addresses);
+    }
+}
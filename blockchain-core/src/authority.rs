@@ -0,0 +1,83 @@
+//! Proof-of-authority, the alternative to Proof-of-Work for private networks.
+//!
+//! A fixed set of authorities takes turns by height: the block at height `h` must be sealed by
+//! authority `h % n`, which signs the header instead of searching a nonce. Blocks carry
+//! `SEALED_DIFFICULTY`, so every block adds the same work and the longest chain wins.
+//! An authority which is down halts the chain until it is back, since nobody else may seal its turn.
+
+use crate::account::Address;
+use crate::block::BlockHeight;
+use crate::difficulty::Difficulty;
+use std::fmt::{self, Display, Formatter};
+
+/// Difficulty of every block of a proof-of-authority chain.
+pub const SEALED_DIFFICULTY: Difficulty = Difficulty::new(0);
+
+/// Addresses allowed to seal blocks, in the order of their turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Authorities(&'static [Address]);
+
+impl Authorities {
+    /// `None` if `addresses` is empty, since nobody could seal a block.
+    pub fn new(addresses: &'static [Address]) -> Option<Self> {
+        (!addresses.is_empty()).then_some(Self(addresses))
+    }
+
+    pub fn addresses(&self) -> &'static [Address] {
+        self.0
+    }
+
+    /// Authority whose turn it is to seal the block at `height`.
+    pub fn in_turn(&self, height: BlockHeight) -> &'static Address {
+        let turn = u64::from(height) % self.0.len() as u64;
+        &self.0[turn as usize]
+    }
+
+    pub fn contains(&self, address: &Address) -> bool {
+        self.0.contains(address)
+    }
+}
+
+/// How blocks of a chain earn their place. See `ChainParams::consensus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consensus {
+    #[default]
+    ProofOfWork,
+    ProofOfAuthority(Authorities),
+}
+
+impl Display for Consensus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Consensus::ProofOfWork => write!(f, "proof-of-work"),
+            Consensus::ProofOfAuthority(authorities) => write!(
+                f,
+                "proof-of-authority by {} authorities",
+                authorities.addresses().len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::SecretAddress;
+
+    #[test]
+    fn test_authorities_take_turns() {
+        assert_eq!(None, Authorities::new(&[]));
+
+        let addresses = (0..3)
+            .map(|_| SecretAddress::create().to_public_address())
+            .collect::<Vec<_>>();
+        let authorities = Authorities::new(addresses.clone().leak()).unwrap();
+
+        for height in 0..7 {
+            let expected = &addresses[height as usize % 3];
+            assert_eq!(expected, authorities.in_turn(BlockHeight::new(height)));
+        }
+        assert!(authorities.contains(&addresses[1]));
+        assert!(!authorities.contains(&SecretAddress::create().to_public_address()));
+    }
+}
@@ -1,18 +1,26 @@
 use crate::account::SecretAddress;
+use crate::authority::{Authorities, SEALED_DIFFICULTY};
+use crate::clock::{Clock, SystemClock};
 use crate::coin::Coin;
+use crate::compact::OutPoint;
 use crate::difficulty::Difficulty;
-use crate::digest::BlockDigest;
-use crate::signature::{SignatureBuilder, SignatureSource};
+use crate::digest::{BlockDigest, MerkleRoot, PowAlgorithm};
+use crate::light::{merkle_root, MerkleProof};
+use crate::params::ChainParams;
+use crate::signature::{
+    SighashDomain, SighashFlag, SighashVersion, Signature, SignatureBuilder, SignatureSource,
+};
 use crate::timestamp::Timestamp;
 use crate::transaction::TransactionError;
-use crate::transition::{Generation, Transition, Transfer};
-use crate::verification::{Verified, Yet};
-use apply::Apply;
+use crate::transition::{Generation, Transfer, Transition};
+use crate::verification::{Stage, Verified, Yet};
 use itertools::Itertools;
-use serde::{Deserialize, Deserializer, Serialize};
+use rand::RngCore;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 type Transaction<T> = crate::transaction::Transaction<T, T>;
 
@@ -20,6 +28,10 @@ type Transaction<T> = crate::transaction::Transaction<T, T>;
 pub struct BlockHeight(u64);
 
 impl BlockHeight {
+    pub const fn new(height: u64) -> Self {
+        Self(height)
+    }
+
     pub const fn genesis() -> Self {
         Self(0)
     }
@@ -31,6 +43,45 @@ impl BlockHeight {
     pub fn previous(self) -> Option<Self> {
         self.0.checked_sub(1).map(Self)
     }
+
+    pub const fn is_genesis(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, count: u64) -> Option<Self> {
+        self.0.checked_add(count).map(Self)
+    }
+
+    pub fn checked_sub(self, count: u64) -> Option<Self> {
+        self.0.checked_sub(count).map(Self)
+    }
+
+    /// Same as `checked_sub`, but returns genesis height on underflow.
+    pub fn saturating_sub(self, count: u64) -> Self {
+        Self(self.0.saturating_sub(count))
+    }
+
+    /// Number of blocks between two heights regardless of their order.
+    pub fn distance(self, other: Self) -> u64 {
+        self.0.abs_diff(other.0)
+    }
+
+    /// Range from this height to `end`, both inclusive.
+    pub fn to_inclusive(self, end: Self) -> HeightRange {
+        HeightRange::inclusive(self, end)
+    }
+}
+
+impl From<u64> for BlockHeight {
+    fn from(height: u64) -> Self {
+        Self(height)
+    }
+}
+
+impl From<BlockHeight> for u64 {
+    fn from(height: BlockHeight) -> Self {
+        height.0
+    }
 }
 
 impl SignatureSource for BlockHeight {
@@ -45,26 +96,368 @@ impl Display for BlockHeight {
     }
 }
 
+/// Half-open range of block heights `[start, end)`, which iterates heights in ascending order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HeightRange {
+    start: BlockHeight,
+    end: BlockHeight,
+}
+
+impl HeightRange {
+    pub fn new(start: BlockHeight, end: BlockHeight) -> Self {
+        Self { start, end }
+    }
+
+    /// Range whose both ends are inclusive.
+    /// The range is empty if `end` is the maximum height.
+    pub fn inclusive(start: BlockHeight, end: BlockHeight) -> Self {
+        let end = end.checked_add(1).unwrap_or(end);
+        Self { start, end }
+    }
+
+    pub fn start(&self) -> BlockHeight {
+        self.start
+    }
+
+    pub fn end(&self) -> BlockHeight {
+        self.end
+    }
+
+    pub fn contains(&self, height: BlockHeight) -> bool {
+        self.start <= height && height < self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Number of heights in the range.
+    pub fn count_heights(&self) -> u64 {
+        self.end.0.saturating_sub(self.start.0)
+    }
+}
+
+impl Iterator for HeightRange {
+    type Item = BlockHeight;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            None
+        } else {
+            let height = self.start;
+            self.start = height.next();
+            Some(height)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = usize::try_from(self.count_heights()).ok();
+        (count.unwrap_or(usize::MAX), count)
+    }
+}
+
+impl DoubleEndedIterator for HeightRange {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            None
+        } else {
+            self.end = BlockHeight(self.end.0 - 1);
+            Some(self.end)
+        }
+    }
+}
+
+/// Nonces to try for Proof-of-Work, drawn from `rng`.
+/// A seeded `rng` tries the same nonces on every run.
 #[derive(Debug, Clone)]
-pub struct BlockSource {
+pub struct NonceIter<R> {
+    rng: R,
+}
+
+impl<R: RngCore> NonceIter<R> {
+    pub fn new(rng: R) -> Self {
+        Self { rng }
+    }
+}
+
+impl<R: RngCore> Iterator for NonceIter<R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        Some(self.rng.next_u64())
+    }
+}
+
+/// Block without its transactions, which is enough to check Proof-of-Work and chain linkage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
     height: BlockHeight,
-    transactions: Vec<Transaction<Verified>>,
+    /// Merkle root of the transaction ids
+    merkle_root: MerkleRoot,
+    /// Block creation time, which must be later than any transactions in the block.
     timestamp: Timestamp,
+    /// Digest of the previous block.
     previous_digest: BlockDigest,
+    /// Difficulty in finding the block.
     difficulty: Difficulty,
+    /// PoW key.
     nonce: u64,
+    /// Digest of all data of the block except for this block's digest.
+    digest: BlockDigest,
+    /// Encoding which `digest` commits to.
+    version: SighashVersion,
+    /// Digest of the UTXO set after applying the block, if the miner committed to it.
+    #[serde(default)]
+    utxo_commitment: Option<BlockDigest>,
+    /// Deployments which the miner is ready for, one bit each. See `deployment`.
+    #[serde(default)]
+    signals: u32,
+    /// Signature of the authority in turn on a proof-of-authority chain. See `authority`.
+    #[serde(default)]
+    seal: Option<Signature>,
+}
+
+impl BlockHeader {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        height: BlockHeight,
+        merkle_root: MerkleRoot,
+        timestamp: Timestamp,
+        previous_digest: BlockDigest,
+        difficulty: Difficulty,
+        nonce: u64,
+        digest: BlockDigest,
+        version: SighashVersion,
+    ) -> Self {
+        Self {
+            height,
+            merkle_root,
+            timestamp,
+            previous_digest,
+            difficulty,
+            nonce,
+            digest,
+            version,
+            utxo_commitment: None,
+            signals: 0,
+            seal: None,
+        }
+    }
+
+    pub fn with_utxo_commitment(self, utxo_commitment: Option<BlockDigest>) -> Self {
+        Self {
+            utxo_commitment,
+            ..self
+        }
+    }
+
+    pub fn with_signals(self, signals: u32) -> Self {
+        Self { signals, ..self }
+    }
+
+    pub fn with_seal(self, seal: Option<Signature>) -> Self {
+        Self { seal, ..self }
+    }
+
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    pub fn merkle_root(&self) -> &MerkleRoot {
+        &self.merkle_root
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn previous_digest(&self) -> &BlockDigest {
+        &self.previous_digest
+    }
+
+    pub fn difficulty(&self) -> &Difficulty {
+        &self.difficulty
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn digest(&self) -> &BlockDigest {
+        &self.digest
+    }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
+
+    pub fn utxo_commitment(&self) -> Option<&BlockDigest> {
+        self.utxo_commitment.as_ref()
+    }
+
+    pub fn signals(&self) -> u32 {
+        self.signals
+    }
+
+    pub fn seal(&self) -> Option<&Signature> {
+        self.seal.as_ref()
+    }
+
+    /// Whether the miner signals the deployment of `bit`.
+    pub fn signals_bit(&self, bit: u8) -> bool {
+        bit < 32 && self.signals & (1 << bit) != 0
+    }
+
+    /// Digest derived from the other fields by SHA-256.
+    /// `None` for headers older than `SighashVersion::V2`,
+    /// whose digest commits to the transactions themselves instead of their Merkle root.
+    pub fn compute_digest(&self) -> Option<BlockDigest> {
+        self.compute_digest_by(PowAlgorithm::Sha256)
+    }
+
+    /// Same as `compute_digest`, for chains hashing blocks by `algorithm`.
+    pub fn compute_digest_by(&self, algorithm: PowAlgorithm) -> Option<BlockDigest> {
+        if self.version != SighashVersion::V2 {
+            return None;
+        }
+        let except_nonce = self.digest_source_except_nonce().finalize();
+        Some(digest_with_nonce(except_nonce, self.nonce, algorithm))
+    }
+
+    /// Whether the digest is derived from the other fields and satisfies the difficulty.
+    /// Headers older than `SighashVersion::V2` cannot be verified without transactions.
+    pub fn verify(&self) -> bool {
+        self.verify_by(PowAlgorithm::Sha256)
+    }
+
+    /// Same as `verify`, for chains hashing blocks by `algorithm`.
+    pub fn verify_by(&self, algorithm: PowAlgorithm) -> bool {
+        self.compute_digest_by(algorithm).as_ref() == Some(&self.digest)
+            && self.difficulty.verify_digest(&self.digest)
+    }
+
+    /// Whether the authority in turn at the height sealed this header.
+    /// The digest is not checked, though it commits to the seal.
+    pub fn verify_seal(&self, authorities: &Authorities) -> bool {
+        match &self.seal {
+            Some(seal) => authorities
+                .in_turn(self.height)
+                .verify(&self.seal_message(), seal),
+            None => false,
+        }
+    }
+
+    /// Message signed by the seal, which is the digest source except the seal itself.
+    /// The nonce is covered too, so that a sealed block cannot be re-hashed under another digest.
+    fn seal_message(&self) -> Vec<u8> {
+        let unsealed = Self {
+            seal: None,
+            ..self.clone()
+        };
+        let mut builder = unsealed.digest_source_except_nonce();
+        builder.write_bytes(&self.nonce.to_le_bytes());
+        builder.finalize_sighash()
+    }
+
+    /// Digest source which commits to the Merkle root of transactions.
+    fn digest_source_except_nonce(&self) -> SignatureBuilder {
+        let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Block);
+        self.height.write_bytes(&mut builder);
+        self.merkle_root.write_bytes(&mut builder);
+        self.timestamp.write_bytes(&mut builder);
+        self.previous_digest.write_bytes(&mut builder);
+        self.difficulty.write_bytes(&mut builder);
+        self.write_optional_fields(&mut builder);
+        builder
+    }
+
+    /// Optional fields are written only if present,
+    /// so that blocks without them keep the digest they had before the fields were introduced.
+    fn write_optional_fields(&self, builder: &mut SignatureBuilder) {
+        if let Some(commitment) = &self.utxo_commitment {
+            builder.write_variant(1);
+            commitment.write_bytes(builder);
+        }
+        if self.signals != 0 {
+            builder.write_variant(2);
+            builder.write_bytes(&self.signals.to_le_bytes());
+        }
+        if let Some(seal) = &self.seal {
+            builder.write_variant(3);
+            builder.write_bytes(&seal.as_ref().to_bytes());
+        }
+    }
+
+    /// Serialize along with `transactions` in the format of `Block`.
+    fn serialize_block<S, T>(&self, transactions: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        BlockFormat {
+            format: BLOCK_FORMAT_VERSION,
+            height: self.height,
+            transactions,
+            timestamp: self.timestamp,
+            previous_digest: &self.previous_digest,
+            difficulty: &self.difficulty,
+            nonce: self.nonce,
+            digest: &self.digest,
+            version: self.version,
+            utxo_commitment: self.utxo_commitment.as_ref(),
+            signals: self.signals,
+            seal: self.seal.as_ref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Block under mining, which always commits to the Merkle root of its transactions.
+#[derive(Debug, Clone)]
+pub struct BlockSource {
+    /// Digest of the header is updated when a nonce is found.
+    header: BlockHeader,
+    transactions: Vec<Arc<Transaction<Verified>>>,
     digest_source_except_nonce: Vec<u8>,
+    pow_algorithm: PowAlgorithm,
 }
 
 impl BlockSource {
     pub fn new<F>(
         height: BlockHeight,
-        transactions: Vec<Transaction<Verified>>,
+        transactions: Vec<Arc<Transaction<Verified>>>,
+        previous_digest: BlockDigest,
+        difficulty: Difficulty,
+        nonce: u64,
+        reward_receiver: &SecretAddress,
+        gen_rule: F,
+    ) -> Result<Self, TransactionError>
+    where
+        F: FnMut(BlockHeight) -> Coin,
+    {
+        Self::new_with_clock(
+            height,
+            transactions,
+            previous_digest,
+            difficulty,
+            nonce,
+            reward_receiver,
+            gen_rule,
+            &SystemClock,
+        )
+    }
+
+    /// Block source whose block and generation transaction are timestamped by `clock`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_clock<F>(
+        height: BlockHeight,
+        transactions: Vec<Arc<Transaction<Verified>>>,
         previous_digest: BlockDigest,
         difficulty: Difficulty,
         nonce: u64,
         reward_receiver: &SecretAddress,
         mut gen_rule: F,
+        clock: &dyn Clock,
     ) -> Result<Self, TransactionError>
     where
         F: FnMut(BlockHeight) -> Coin,
@@ -72,73 +465,162 @@ impl BlockSource {
         let gen_tx = {
             let in_qty = transactions
                 .iter()
-                .flat_map(Transaction::inputs)
+                .flat_map(|tx| tx.inputs())
                 .map(Transition::quantity)
                 .sum::<Coin>();
             let o_qty = transactions
                 .iter()
-                .flat_map(Transaction::outputs)
+                .flat_map(|tx| tx.outputs())
                 .map(Transition::quantity)
                 .sum::<Coin>();
             let r_qty = gen_rule(height) + in_qty - o_qty;
 
             // Generation transaction
             let inputs: Vec<Transfer<_>> = vec![];
-            let outputs = vec![Generation::offer(&reward_receiver, r_qty)];
-            crate::transaction::Transaction::offer(reward_receiver, inputs, outputs)
-                .verify_transaction()?
+            let outputs = vec![Generation::offer_with_clock(reward_receiver, r_qty, clock)];
+            crate::transaction::Transaction::offer_with_clock(
+                reward_receiver,
+                inputs,
+                outputs,
+                SighashFlag::All,
+                None,
+                clock,
+            )
+            .verify_transaction()?
         };
 
         let transactions = transactions
             .into_iter()
-            .chain(std::iter::once(gen_tx))
-            .sorted_by_key(Transaction::timestamp)
+            .chain(std::iter::once(Arc::new(gen_tx)))
+            .sorted_by_key(|tx| tx.timestamp())
             .collect_vec();
 
-        let timestamp = Timestamp::now();
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
+        let merkle_root = merkle_root(&transactions.iter().map(|tx| tx.txid()).collect_vec());
 
-        let digest_source_except_nonce = builde_digest_source_except_nonce(
+        // The digest is replaced once the digest source is built
+        let header = BlockHeader::new(
             height,
-            &transactions,
-            &timestamp,
-            &previous_digest,
-            &difficulty,
-        )
-        .finalize();
-
-        let source = Self {
-            height,
-            transactions,
+            merkle_root,
             timestamp,
-            previous_digest,
+            previous_digest.clone(),
             difficulty,
             nonce,
-            digest_source_except_nonce,
+            previous_digest,
+            version,
+        );
+
+        let mut source = Self {
+            header,
+            transactions,
+            digest_source_except_nonce: vec![],
+            pow_algorithm: PowAlgorithm::Sha256,
         };
+        source.refresh_digest_source();
+        source.refresh_digest();
         Ok(source)
     }
 
+    /// Search nonces by `algorithm` instead of SHA-256.
+    /// See `ChainParams::pow_algorithm` for the algorithm of the chain.
+    pub fn with_pow_algorithm(mut self, algorithm: PowAlgorithm) -> Self {
+        self.pow_algorithm = algorithm;
+        self.refresh_digest();
+        self
+    }
+
     pub fn nonce_mut(&mut self) -> &mut u64 {
-        &mut self.nonce
+        &mut self.header.nonce
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Transactions including the generation transaction, sorted by timestamp.
+    pub fn transactions(&self) -> &[Arc<Transaction<Verified>>] {
+        &self.transactions
     }
 
-    pub fn try_into_block(self) -> Result<Block<Verified, Yet, Yet, Yet, Yet, Yet>, BlockSource> {
-        let digest = build_digest_source_from_except_nonce(
+    /// Commit to the UTXO set after applying this block.
+    /// See `Ledger::utxo_commitment_after` for computing `commitment`.
+    pub fn commit_utxos(&mut self, commitment: BlockDigest) {
+        self.header.utxo_commitment = Some(commitment);
+        self.refresh_digest_source();
+    }
+
+    /// Signal readiness for deployments, one bit each.
+    /// See `Ledger::signals_for_next` for the bits to signal.
+    pub fn signal(&mut self, signals: u32) {
+        self.header.signals = signals;
+        self.refresh_digest_source();
+    }
+
+    /// Forge the block timestamp for simulated misbehavior.
+    #[cfg(test)]
+    pub(crate) fn set_timestamp(&mut self, timestamp: Timestamp) {
+        self.header.timestamp = timestamp;
+        self.refresh_digest_source();
+    }
+
+    fn refresh_digest_source(&mut self) {
+        self.digest_source_except_nonce = self.header.digest_source_except_nonce().finalize();
+    }
+
+    fn refresh_digest(&mut self) {
+        self.header.digest = digest_with_nonce(
             self.digest_source_except_nonce.clone(),
-            self.nonce,
-        )
-        .finalize()
-        .apply(|bytes| BlockDigest::digest(&bytes));
+            self.header.nonce,
+            self.pow_algorithm,
+        );
+    }
 
-        if self.difficulty.verify_digest(&digest) {
+    /// Sign the block as `authority` in place of Proof-of-Work, for proof-of-authority chains.
+    /// The block needs `SEALED_DIFFICULTY` and the turn of `authority` to pass `verify_authority`.
+    pub fn seal(mut self, authority: &SecretAddress) -> Block<Verified, Yet, Yet, Yet, Yet, Yet> {
+        self.header.seal = Some(authority.sign(&self.header.seal_message()));
+        self.refresh_digest_source();
+        self.refresh_digest();
+        Block {
+            header: self.header,
+            transactions: self.transactions,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Try `nonces` in order until one satisfies the difficulty.
+    /// Returns the source back if they run out first.
+    #[allow(clippy::result_large_err)]
+    pub fn search_nonce(
+        mut self,
+        nonces: impl IntoIterator<Item = u64>,
+    ) -> Result<Block<Verified, Yet, Yet, Yet, Yet, Yet>, BlockSource> {
+        for nonce in nonces {
+            self.header.nonce = nonce;
+            match self.try_into_block() {
+                Ok(block) => return Ok(block),
+                Err(source) => self = source,
+            }
+        }
+        Err(self)
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn try_into_block(
+        mut self,
+    ) -> Result<Block<Verified, Yet, Yet, Yet, Yet, Yet>, BlockSource> {
+        let digest = digest_with_nonce(
+            self.digest_source_except_nonce.clone(),
+            self.header.nonce,
+            self.pow_algorithm,
+        );
+
+        if self.header.difficulty.verify_digest(&digest) {
+            self.header.digest = digest;
             let block = Block {
-                height: self.height,
+                header: self.header,
                 transactions: self.transactions,
-                timestamp: self.timestamp,
-                previous_digest: self.previous_digest,
-                difficulty: self.difficulty,
-                nonce: self.nonce,
-                digest,
                 _phantom: PhantomData,
             };
             Ok(block)
@@ -155,59 +637,137 @@ impl BlockSource {
 /// - VU: transaction-Utxo judge using utxo history
 /// - VP: previous block check by using previous digest and timestamp
 /// - VDG: digest matching
-/// - VDI: difficulty check using block history and Proof-of-Work
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// - VDI: difficulty check using block history and Proof-of-Work,
+///   or the seal on a proof-of-authority chain
+///
+/// Serialized with the header fields flattened and without the Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block<VT, VTS, VU, VP, VDG, VDI> {
-    height: BlockHeight,
+    /// Its Merkle root is always that of `transactions`.
+    header: BlockHeader,
     /// All transfers must be UTXO.
     /// Transactions must be sorted by its timestamp.
-    transactions: Vec<Transaction<VT>>,
-    /// Block creation time, which must be later than any transactions in the block.
-    timestamp: Timestamp,
-    /// Digest of the previous block.
-    previous_digest: BlockDigest,
-    /// Difficulty in finding the block.
-    difficulty: Difficulty,
-    /// PoW key.
-    nonce: u64,
-    /// Digest of all data of the block except for this block's digest.
-    digest: BlockDigest,
+    transactions: Vec<Arc<Transaction<VT>>>,
     /// Verification process
-    #[serde(skip_serializing)]
+    #[allow(clippy::type_complexity)]
     _phantom: PhantomData<fn() -> (VTS, VU, VP, VDG, VDI)>,
 }
 
 impl<VT, VTS, VU, VP, VDG, VDI> Block<VT, VTS, VU, VP, VDG, VDI> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Block<Yet, Yet, Yet, Yet, Yet, Yet> {
+        Block {
+            header: self.header.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|tx| Arc::new(tx.to_unverified()))
+                .collect(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Block of `transactions` under `header`, whose Merkle root is replaced with theirs.
+    /// The digest is kept as it is, so that digest verification catches wrong transactions.
+    pub(crate) fn from_header(
+        header: BlockHeader,
+        transactions: Vec<Arc<Transaction<VT>>>,
+    ) -> Self {
+        let txids = transactions.iter().map(|tx| tx.txid()).collect_vec();
+        let header = BlockHeader {
+            merkle_root: merkle_root(&txids),
+            ..header
+        };
+        Self {
+            header,
+            transactions,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
     pub fn height(&self) -> BlockHeight {
-        self.height
+        self.header.height
     }
 
-    pub fn transactions(&self) -> &[Transaction<VT>] {
+    pub fn transactions(&self) -> &[Arc<Transaction<VT>>] {
         &self.transactions
     }
 
     pub fn inputs(&self) -> impl Iterator<Item = &Transition<VT>> + '_ {
-        self.transactions.iter().flat_map(Transaction::inputs)
+        self.transactions.iter().flat_map(|tx| tx.inputs())
     }
 
     pub fn outputs(&self) -> impl Iterator<Item = &Transition<VT>> + '_ {
-        self.transactions.iter().flat_map(Transaction::outputs)
+        self.transactions.iter().flat_map(|tx| tx.outputs())
+    }
+
+    /// Outputs with their outpoints, in order of transactions.
+    pub fn created_outputs(&self) -> impl Iterator<Item = (OutPoint, &Transition<VT>)> + '_ {
+        self.transactions.iter().flat_map(|tx| tx.created_outputs())
     }
 
     pub fn timestamp(&self) -> Timestamp {
-        self.timestamp
+        self.header.timestamp
     }
 
     pub fn previous_digest(&self) -> &BlockDigest {
-        &self.previous_digest
+        &self.header.previous_digest
     }
 
     pub fn difficulty(&self) -> &Difficulty {
-        &self.difficulty
+        &self.header.difficulty
     }
 
     pub fn digest(&self) -> &BlockDigest {
-        &self.digest
+        &self.header.digest
+    }
+
+    pub fn version(&self) -> SighashVersion {
+        self.header.version
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.header.nonce
+    }
+
+    pub fn utxo_commitment(&self) -> Option<&BlockDigest> {
+        self.header.utxo_commitment()
+    }
+
+    pub fn signals(&self) -> u32 {
+        self.header.signals
+    }
+
+    pub fn seal(&self) -> Option<&Signature> {
+        self.header.seal()
+    }
+
+    /// Merkle root of the transaction ids.
+    pub fn merkle_root(&self) -> &MerkleRoot {
+        &self.header.merkle_root
+    }
+
+    /// Proof that the transaction at `index` is in this block, which `light::verify_inclusion`
+    /// checks against the header alone. Returns `None` if `index` is out of range.
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        let txids = self.transactions.iter().map(|tx| tx.txid()).collect_vec();
+        MerkleProof::new(&txids, index)
+    }
+}
+
+impl<VT, VTS, VU, VP, VDG, VDI> Serialize for Block<VT, VTS, VU, VP, VDG, VDI>
+where
+    Transaction<VT>: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.header.serialize_block(&self.transactions, serializer)
     }
 }
 
@@ -219,18 +779,34 @@ impl<VTS, VU, VP, VDG, VDI> Block<Yet, VTS, VU, VP, VDG, VDI> {
         let transactions = self
             .transactions
             .into_iter()
-            .map(Transaction::verify)
+            .map(|tx| Arc::unwrap_or_clone(tx).verify().map(Arc::new))
             .collect::<Result<Vec<_>, _>>()
             .map_err(BlockError::Transaction)?;
 
         let block = Block {
-            height: self.height,
+            header: self.header,
+            transactions,
+            _phantom: PhantomData,
+        };
+
+        Ok(block)
+    }
+
+    /// Same as `verify_transaction_itself` except signs, which the chain up to an
+    /// assumed-valid checkpoint has been trusted with.
+    pub fn verify_transaction_itself_except_signs(
+        self,
+    ) -> Result<Block<Verified, VTS, VU, VP, VDG, VDI>, BlockError> {
+        let transactions = self
+            .transactions
+            .into_iter()
+            .map(|tx| Arc::unwrap_or_clone(tx).verify_except_signs().map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(BlockError::Transaction)?;
+
+        let block = Block {
+            header: self.header,
             transactions,
-            timestamp: self.timestamp,
-            previous_digest: self.previous_digest,
-            difficulty: self.difficulty,
-            nonce: self.nonce,
-            digest: self.digest,
             _phantom: PhantomData,
         };
 
@@ -238,8 +814,27 @@ impl<VTS, VU, VP, VDG, VDI> Block<Yet, VTS, VU, VP, VDG, VDI> {
     }
 }
 
-impl<VT, VU, VP, VDG, VDI> Block<VT, Yet, VU, VP, VDG, VDI> {
+impl<VT, VU, VP, VDG, VDI> Block<VT, Yet, VU, VP, VDG, VDI> {
+    /// Verify transactions relationship, taking coin generation from the chain rules.
+    pub fn verify_transaction_relation_with(
+        self,
+        params: &ChainParams,
+    ) -> Result<Block<VT, Verified, VU, VP, VDG, VDI>, BlockError> {
+        self.verify_relation(params.generation_rule())
+    }
+
+    #[deprecated(note = "Nodes may disagree on the rule. Use `verify_transaction_relation_with`")]
     pub fn verify_transaction_relation<F>(
+        self,
+        gen_rule: F,
+    ) -> Result<Block<VT, Verified, VU, VP, VDG, VDI>, BlockError>
+    where
+        F: FnMut(BlockHeight) -> Coin,
+    {
+        self.verify_relation(gen_rule)
+    }
+
+    fn verify_relation<F>(
         self,
         mut gen_rule: F,
     ) -> Result<Block<VT, Verified, VU, VP, VDG, VDI>, BlockError>
@@ -250,15 +845,13 @@ impl<VT, VU, VP, VDG, VDI> Block<VT, Yet, VU, VP, VDG, VDI> {
         if self
             .transactions
             .iter()
-            .map(Transaction::timestamp)
-            .any(|stamp| stamp > self.timestamp)
+            .map(|tx| tx.timestamp())
+            .any(|stamp| stamp > self.header.timestamp)
         {
             return Err(BlockError::TransactionTimestamp);
         }
         // Timestamp sorted check
-        if !is_sorted::IsSorted::is_sorted(
-            &mut self.transactions.iter().map(Transaction::timestamp),
-        ) {
+        if !is_sorted::IsSorted::is_sorted(&mut self.transactions.iter().map(|tx| tx.timestamp())) {
             return Err(BlockError::TransactionTimestamp);
         }
 
@@ -266,29 +859,24 @@ impl<VT, VU, VP, VDG, VDI> Block<VT, Yet, VU, VP, VDG, VDI> {
         let in_qty = self
             .transactions
             .iter()
-            .flat_map(Transaction::inputs)
+            .flat_map(|tx| tx.inputs())
             .map(Transition::quantity)
             .sum::<Coin>();
         let o_qty = self
             .transactions
             .iter()
-            .flat_map(Transaction::outputs)
+            .flat_map(|tx| tx.outputs())
             .map(Transition::quantity)
             .sum::<Coin>();
-        let r_qty = gen_rule(self.height);
+        let r_qty = gen_rule(self.header.height);
 
         if in_qty + r_qty != o_qty {
             return Err(BlockError::TransactionQuantity);
         }
 
         let block = Block {
-            height: self.height,
+            header: self.header,
             transactions: self.transactions,
-            timestamp: self.timestamp,
-            previous_digest: self.previous_digest,
-            difficulty: self.difficulty,
-            nonce: self.nonce,
-            digest: self.digest,
             _phantom: PhantomData,
         };
 
@@ -302,19 +890,14 @@ impl<VP, VDG, VDI> Block<Verified, Verified, Yet, VP, VDG, VDI> {
         mut utxo_judge: F,
     ) -> Result<Block<Verified, Verified, Verified, VP, VDG, VDI>, BlockError>
     where
-        F: FnMut(&[Transaction<Verified>]) -> bool,
+        F: FnMut(&[Arc<Transaction<Verified>>]) -> bool,
     {
         let all_utxo = utxo_judge(&self.transactions);
 
         if all_utxo {
             let block = Block {
-                height: self.height,
+                header: self.header,
                 transactions: self.transactions,
-                timestamp: self.timestamp,
-                previous_digest: self.previous_digest,
-                difficulty: self.difficulty,
-                nonce: self.nonce,
-                digest: self.digest,
                 _phantom: PhantomData,
             };
             Ok(block)
@@ -333,15 +916,10 @@ impl<VT, VTS, VU, VDG, VDI> Block<VT, VTS, VU, Yet, VDG, VDI> {
     where
         F: FnMut(BlockHeight, &BlockDigest) -> bool,
     {
-        if ledger(self.height, &self.previous_digest) {
+        if ledger(self.header.height, &self.header.previous_digest) {
             let block = Block {
-                height: self.height,
+                header: self.header,
                 transactions: self.transactions,
-                timestamp: self.timestamp,
-                previous_digest: self.previous_digest,
-                difficulty: self.difficulty,
-                nonce: self.nonce,
-                digest: self.digest,
                 _phantom: PhantomData,
             };
             Ok(block)
@@ -352,27 +930,29 @@ impl<VT, VTS, VU, VDG, VDI> Block<VT, VTS, VU, Yet, VDG, VDI> {
 }
 
 impl<VT, VTS, VU, VP, VDI> Block<VT, VTS, VU, VP, Yet, VDI> {
+    /// Check the digest as SHA-256, the algorithm of the main chain.
     pub fn verify_digest(self) -> Result<Block<VT, VTS, VU, VP, Verified, VDI>, BlockError> {
-        let digest_source = build_digest_source(
-            self.height,
-            &self.transactions,
-            &self.timestamp,
-            &self.previous_digest,
-            &self.difficulty,
-            self.nonce,
-        )
-        .finalize();
-        let digest = BlockDigest::digest(&digest_source);
+        self.verify_digest_by(PowAlgorithm::Sha256)
+    }
+
+    pub fn verify_digest_with(
+        self,
+        params: &ChainParams,
+    ) -> Result<Block<VT, VTS, VU, VP, Verified, VDI>, BlockError> {
+        self.verify_digest_by(params.pow_algorithm())
+    }
+
+    fn verify_digest_by(
+        self,
+        algorithm: PowAlgorithm,
+    ) -> Result<Block<VT, VTS, VU, VP, Verified, VDI>, BlockError> {
+        let digest_source = build_digest_source(&self.header, &self.transactions).finalize();
+        let digest = algorithm.digest(&digest_source);
 
-        if digest == self.digest {
+        if digest == self.header.digest {
             let block = Block {
-                height: self.height,
+                header: self.header,
                 transactions: self.transactions,
-                timestamp: self.timestamp,
-                previous_digest: self.previous_digest,
-                difficulty: self.difficulty,
-                nonce: self.nonce,
-                digest: self.digest,
                 _phantom: PhantomData,
             };
             Ok(block)
@@ -387,19 +967,14 @@ impl<VT, VTS, VU, VP, VDG> Block<VT, VTS, VU, VP, VDG, Yet> {
         self,
         expected_difficulty: &Difficulty,
     ) -> Result<Block<VT, VTS, VU, VP, VDG, Verified>, BlockError> {
-        if &self.difficulty < expected_difficulty {
+        if &self.header.difficulty < expected_difficulty {
             return Err(BlockError::InsufficientDifficulty);
         }
 
-        if expected_difficulty.verify_digest(&self.digest) {
+        if expected_difficulty.verify_digest(&self.header.digest) {
             let block = Block {
-                height: self.height,
+                header: self.header,
                 transactions: self.transactions,
-                timestamp: self.timestamp,
-                previous_digest: self.previous_digest,
-                difficulty: self.difficulty,
-                nonce: self.nonce,
-                digest: self.digest,
                 _phantom: PhantomData,
             };
             Ok(block)
@@ -409,6 +984,29 @@ impl<VT, VTS, VU, VP, VDG> Block<VT, VTS, VU, VP, VDG, Yet> {
     }
 }
 
+impl<VT, VTS, VU, VP, VDG> Block<VT, VTS, VU, VP, VDG, Yet> {
+    /// Check the seal in place of `verify_difficulty` on a proof-of-authority chain.
+    pub fn verify_authority(
+        self,
+        authorities: &Authorities,
+    ) -> Result<Block<VT, VTS, VU, VP, VDG, Verified>, BlockError> {
+        if self.header.difficulty != SEALED_DIFFICULTY {
+            return Err(BlockError::UnexpectedDifficulty);
+        }
+
+        if self.header.verify_seal(authorities) {
+            let block = Block {
+                header: self.header,
+                transactions: self.transactions,
+                _phantom: PhantomData,
+            };
+            Ok(block)
+        } else {
+            Err(BlockError::Seal)
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Block<Yet, Yet, Yet, Yet, Yet, Yet> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -417,31 +1015,424 @@ impl<'de> Deserialize<'de> for Block<Yet, Yet, Yet, Yet, Yet, Yet> {
         // Temporary tipe for deserialization
         #[derive(Deserialize)]
         struct Inner {
+            // Missing only from older JSON, whose fields are named so they cannot be misread
+            #[serde(default = "current_block_format")]
+            format: u8,
             height: BlockHeight,
-            transactions: Vec<Transaction<Yet>>,
+            transactions: Vec<Arc<Transaction<Yet>>>,
             timestamp: Timestamp,
             previous_digest: BlockDigest,
             difficulty: Difficulty,
             nonce: u64,
             digest: BlockDigest,
+            #[serde(default)]
+            version: SighashVersion,
+            #[serde(default)]
+            utxo_commitment: Option<BlockDigest>,
+            #[serde(default)]
+            signals: u32,
+            #[serde(default)]
+            seal: Option<Signature>,
+        }
+
+        fn current_block_format() -> u8 {
+            BLOCK_FORMAT_VERSION
         }
 
         let inner = Inner::deserialize(deserializer)?;
+        if inner.format != BLOCK_FORMAT_VERSION {
+            return Err(de::Error::custom(format_args!(
+                "unsupported block format {}, expected {}",
+                inner.format, BLOCK_FORMAT_VERSION
+            )));
+        }
 
+        let txids = inner.transactions.iter().map(|tx| tx.txid()).collect_vec();
+        let header = BlockHeader::new(
+            inner.height,
+            merkle_root(&txids),
+            inner.timestamp,
+            inner.previous_digest,
+            inner.difficulty,
+            inner.nonce,
+            inner.digest,
+            inner.version,
+        )
+        .with_utxo_commitment(inner.utxo_commitment)
+        .with_signals(inner.signals)
+        .with_seal(inner.seal);
         let block = Block {
-            height: inner.height,
+            header,
             transactions: inner.transactions,
-            timestamp: inner.timestamp,
-            previous_digest: inner.previous_digest,
-            difficulty: inner.difficulty,
-            nonce: inner.nonce,
-            digest: inner.digest,
             _phantom: PhantomData,
         };
         Ok(block)
     }
 }
 
+/// Version of the block encoding, which leads every encoded block.
+/// Positional formats such as bincode cannot skip fields they do not know,
+/// so decoders refuse other versions instead of misreading them.
+pub const BLOCK_FORMAT_VERSION: u8 = 1;
+
+/// Serialized form of `Block` and `DynBlock`, which leaves out the Merkle root of the header
+/// since it is derived from the transactions.
+#[derive(Serialize)]
+#[serde(rename = "Block")]
+struct BlockFormat<'a, T> {
+    format: u8,
+    height: BlockHeight,
+    transactions: &'a T,
+    timestamp: Timestamp,
+    previous_digest: &'a BlockDigest,
+    difficulty: &'a Difficulty,
+    nonce: u64,
+    digest: &'a BlockDigest,
+    version: SighashVersion,
+    utxo_commitment: Option<&'a BlockDigest>,
+    signals: u32,
+    seal: Option<&'a Signature>,
+}
+
+/// Which verification processes a block has passed. See `Block` for each process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct VerificationState {
+    pub transaction_itself: bool,
+    pub transaction_relation: bool,
+    pub utxo: bool,
+    pub previous_block: bool,
+    pub digest: bool,
+    pub difficulty: bool,
+}
+
+impl VerificationState {
+    /// State represented by the generic parameters of `Block`.
+    pub fn of<VT, VTS, VU, VP, VDG, VDI>() -> Self
+    where
+        VT: Stage,
+        VTS: Stage,
+        VU: Stage,
+        VP: Stage,
+        VDG: Stage,
+        VDI: Stage,
+    {
+        Self {
+            transaction_itself: VT::VERIFIED,
+            transaction_relation: VTS::VERIFIED,
+            utxo: VU::VERIFIED,
+            previous_block: VP::VERIFIED,
+            digest: VDG::VERIFIED,
+            difficulty: VDI::VERIFIED,
+        }
+    }
+
+    pub fn is_fully_verified(&self) -> bool {
+        *self == Self::of::<Verified, Verified, Verified, Verified, Verified, Verified>()
+    }
+}
+
+/// Transactions of a `DynBlock`, which may not have passed self check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum DynTransactions {
+    Yet(Vec<Arc<Transaction<Yet>>>),
+    Verified(Vec<Arc<Transaction<Verified>>>),
+}
+
+impl DynTransactions {
+    pub fn len(&self) -> usize {
+        match self {
+            DynTransactions::Yet(transactions) => transactions.len(),
+            DynTransactions::Verified(transactions) => transactions.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn txids(&self) -> Vec<BlockDigest> {
+        match self {
+            DynTransactions::Yet(transactions) => transactions.iter().map(|tx| tx.txid()).collect(),
+            DynTransactions::Verified(transactions) => {
+                transactions.iter().map(|tx| tx.txid()).collect()
+            }
+        }
+    }
+}
+
+/// Marker of transaction self check, which moves transactions in and out of `DynTransactions`.
+pub trait TransactionStage: Stage + Sized {
+    fn erase(transactions: Vec<Arc<Transaction<Self>>>) -> DynTransactions;
+
+    fn restore(
+        transactions: DynTransactions,
+    ) -> Result<Vec<Arc<Transaction<Self>>>, DynTransactions>;
+}
+
+impl TransactionStage for Yet {
+    fn erase(transactions: Vec<Arc<Transaction<Self>>>) -> DynTransactions {
+        DynTransactions::Yet(transactions)
+    }
+
+    fn restore(
+        transactions: DynTransactions,
+    ) -> Result<Vec<Arc<Transaction<Self>>>, DynTransactions> {
+        match transactions {
+            DynTransactions::Yet(transactions) => Ok(transactions),
+            other => Err(other),
+        }
+    }
+}
+
+impl TransactionStage for Verified {
+    fn erase(transactions: Vec<Arc<Transaction<Self>>>) -> DynTransactions {
+        DynTransactions::Verified(transactions)
+    }
+
+    fn restore(
+        transactions: DynTransactions,
+    ) -> Result<Vec<Arc<Transaction<Self>>>, DynTransactions> {
+        match transactions {
+            DynTransactions::Verified(transactions) => Ok(transactions),
+            other => Err(other),
+        }
+    }
+}
+
+/// `Block` whose verification state is kept at runtime instead of in generic parameters,
+/// for storage and RPC layers which only carry blocks around.
+/// Serialized in the same format as `Block`. The state is not serialized,
+/// so a deserialized `DynBlock` is unverified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynBlock {
+    header: BlockHeader,
+    transactions: DynTransactions,
+    state: VerificationState,
+}
+
+impl DynBlock {
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn height(&self) -> BlockHeight {
+        self.header.height
+    }
+
+    pub fn transactions(&self) -> &DynTransactions {
+        &self.transactions
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.header.timestamp
+    }
+
+    pub fn previous_digest(&self) -> &BlockDigest {
+        &self.header.previous_digest
+    }
+
+    pub fn difficulty(&self) -> &Difficulty {
+        &self.header.difficulty
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.header.nonce
+    }
+
+    pub fn digest(&self) -> &BlockDigest {
+        &self.header.digest
+    }
+
+    pub fn version(&self) -> SighashVersion {
+        self.header.version
+    }
+
+    pub fn utxo_commitment(&self) -> Option<&BlockDigest> {
+        self.header.utxo_commitment()
+    }
+
+    pub fn signals(&self) -> u32 {
+        self.header.signals
+    }
+
+    pub fn verification_state(&self) -> VerificationState {
+        self.state
+    }
+
+    /// Recover the typed block. Fails unless the block passed exactly the processes of the type.
+    #[allow(clippy::type_complexity, clippy::result_large_err)]
+    pub fn try_into_block<VT, VTS, VU, VP, VDG, VDI>(
+        self,
+    ) -> Result<Block<VT, VTS, VU, VP, VDG, VDI>, DynBlock>
+    where
+        VT: TransactionStage,
+        VTS: Stage,
+        VU: Stage,
+        VP: Stage,
+        VDG: Stage,
+        VDI: Stage,
+    {
+        if self.state != VerificationState::of::<VT, VTS, VU, VP, VDG, VDI>() {
+            return Err(self);
+        }
+
+        match VT::restore(self.transactions) {
+            Ok(transactions) => Ok(Block {
+                header: self.header,
+                transactions,
+                _phantom: PhantomData,
+            }),
+            Err(transactions) => Err(DynBlock {
+                transactions,
+                ..self
+            }),
+        }
+    }
+}
+
+impl<VT, VTS, VU, VP, VDG, VDI> From<Block<VT, VTS, VU, VP, VDG, VDI>> for DynBlock
+where
+    VT: TransactionStage,
+    VTS: Stage,
+    VU: Stage,
+    VP: Stage,
+    VDG: Stage,
+    VDI: Stage,
+{
+    fn from(block: Block<VT, VTS, VU, VP, VDG, VDI>) -> Self {
+        Self {
+            header: block.header,
+            transactions: VT::erase(block.transactions),
+            state: VerificationState::of::<VT, VTS, VU, VP, VDG, VDI>(),
+        }
+    }
+}
+
+impl Serialize for DynBlock {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.header.serialize_block(&self.transactions, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DynBlock {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Block::<Yet, Yet, Yet, Yet, Yet, Yet>::deserialize(deserializer).map(DynBlock::from)
+    }
+}
+
+/// Assemble arbitrary blocks without Proof-of-Work, for negative testing of verification.
+/// Transactions are kept in the given order, and the digest is computed unless overridden.
+#[cfg(any(test, feature = "test-construct"))]
+#[derive(Debug, Clone)]
+pub struct BlockBuilder<VT> {
+    height: BlockHeight,
+    transactions: Vec<Arc<Transaction<VT>>>,
+    timestamp: Timestamp,
+    previous_digest: BlockDigest,
+    difficulty: Difficulty,
+    nonce: u64,
+    version: SighashVersion,
+    utxo_commitment: Option<BlockDigest>,
+    signals: u32,
+    digest: Option<BlockDigest>,
+}
+
+#[cfg(any(test, feature = "test-construct"))]
+impl<VT> BlockBuilder<VT> {
+    pub fn new(height: BlockHeight, previous_digest: BlockDigest) -> Self {
+        Self {
+            height,
+            transactions: vec![],
+            timestamp: Timestamp::now(),
+            previous_digest,
+            difficulty: Difficulty::new(0),
+            nonce: 0,
+            version: SighashVersion::CURRENT,
+            utxo_commitment: None,
+            signals: 0,
+            digest: None,
+        }
+    }
+
+    pub fn transaction(mut self, transaction: Transaction<VT>) -> Self {
+        self.transactions.push(Arc::new(transaction));
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: Difficulty) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn version(mut self, version: SighashVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn utxo_commitment(mut self, commitment: BlockDigest) -> Self {
+        self.utxo_commitment = Some(commitment);
+        self
+    }
+
+    pub fn signals(mut self, signals: u32) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Use `digest` instead of the computed one.
+    pub fn digest(mut self, digest: BlockDigest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    pub fn build(self) -> Block<VT, Yet, Yet, Yet, Yet, Yet> {
+        let txids = self.transactions.iter().map(|tx| tx.txid()).collect_vec();
+        // The digest is replaced unless overridden
+        let mut header = BlockHeader::new(
+            self.height,
+            merkle_root(&txids),
+            self.timestamp,
+            self.previous_digest.clone(),
+            self.difficulty,
+            self.nonce,
+            self.previous_digest,
+            self.version,
+        )
+        .with_utxo_commitment(self.utxo_commitment)
+        .with_signals(self.signals);
+        header.digest = match self.digest {
+            Some(digest) => digest,
+            None => {
+                let digest_source = build_digest_source(&header, &self.transactions).finalize();
+                BlockDigest::digest(&digest_source)
+            }
+        };
+
+        Block {
+            header,
+            transactions: self.transactions,
+            _phantom: PhantomData,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum BlockError {
     Transaction(TransactionError),
@@ -452,6 +1443,8 @@ pub enum BlockError {
     Digest,
     InsufficientDifficulty,
     PoWFailure,
+    UnexpectedDifficulty,
+    Seal,
 }
 
 impl Display for BlockError {
@@ -467,6 +1460,8 @@ impl Display for BlockError {
             BlockError::Digest => write!(f, "Digest mismatch"),
             BlockError::InsufficientDifficulty => write!(f, "Insufficient difficulty"),
             BlockError::PoWFailure => write!(f, "Proof-of-Work verification failure"),
+            BlockError::UnexpectedDifficulty => write!(f, "Difficulty differs from the chain's"),
+            BlockError::Seal => write!(f, "Block is not sealed by the authority in turn"),
         }
     }
 }
@@ -486,20 +1481,33 @@ pub fn block_coin_generation_rule(height: BlockHeight) -> Coin {
     Coin::from(quantity)
 }
 
-fn builde_digest_source_except_nonce<VT>(
-    height: BlockHeight,
-    transactions: &[Transaction<VT>],
-    timestamp: &Timestamp,
-    previous_digest: &BlockDigest,
-    difficulty: &Difficulty,
+/// Digest source of a block except its nonce. Blocks older than `SighashVersion::V2`
+/// commit to the transactions themselves instead of the Merkle root in the header.
+fn build_digest_source_except_nonce<VT>(
+    header: &BlockHeader,
+    transactions: &[Arc<Transaction<VT>>],
 ) -> SignatureBuilder {
-    let mut builder = SignatureBuilder::new();
-    height.write_bytes(&mut builder);
-    transactions.write_bytes(&mut builder);
-    timestamp.write_bytes(&mut builder);
-    previous_digest.write_bytes(&mut builder);
-    difficulty.write_bytes(&mut builder);
-    builder
+    match header.version {
+        SighashVersion::Legacy | SighashVersion::V1 => {
+            let mut builder = SignatureBuilder::sighash(header.version, SighashDomain::Block);
+            header.height.write_bytes(&mut builder);
+            transactions.write_bytes(&mut builder);
+            header.timestamp.write_bytes(&mut builder);
+            header.previous_digest.write_bytes(&mut builder);
+            header.difficulty.write_bytes(&mut builder);
+            header.write_optional_fields(&mut builder);
+            builder
+        }
+        SighashVersion::V2 => {
+            // The root is derived again, so that the digest still checks the transactions
+            let txids = transactions.iter().map(|tx| tx.txid()).collect_vec();
+            let header = BlockHeader {
+                merkle_root: merkle_root(&txids),
+                ..header.clone()
+            };
+            header.digest_source_except_nonce()
+        }
+    }
 }
 
 fn build_digest_source_from_except_nonce(
@@ -511,22 +1519,22 @@ fn build_digest_source_from_except_nonce(
     builder
 }
 
-fn build_digest_source<VT>(
-    height: BlockHeight,
-    transactions: &[Transaction<VT>],
-    timestamp: &Timestamp,
-    previous_digest: &BlockDigest,
-    difficulty: &Difficulty,
+fn digest_with_nonce(
+    digest_source_except_nonce: Vec<u8>,
     nonce: u64,
+    algorithm: PowAlgorithm,
+) -> BlockDigest {
+    let digest_source =
+        build_digest_source_from_except_nonce(digest_source_except_nonce, nonce).finalize();
+    algorithm.digest(&digest_source)
+}
+
+fn build_digest_source<VT>(
+    header: &BlockHeader,
+    transactions: &[Arc<Transaction<VT>>],
 ) -> SignatureBuilder {
-    let builder = builde_digest_source_except_nonce(
-        height,
-        transactions,
-        timestamp,
-        previous_digest,
-        difficulty,
-    );
-    build_digest_source_from_except_nonce(builder.finalize(), nonce)
+    let builder = build_digest_source_except_nonce(header, transactions);
+    build_digest_source_from_except_nonce(builder.finalize(), header.nonce)
 }
 
 #[cfg(test)]
@@ -541,6 +1549,10 @@ mod tests {
         Coin::from(1)
     }
 
+    fn params() -> ChainParams {
+        ChainParams::new(generation_rule)
+    }
+
     fn create_unverified_genesis_block() -> Block<Verified, Yet, Yet, Yet, Yet, Yet> {
         let input_sender = SecretAddress::create();
         let reliever = SecretAddress::create();
@@ -562,9 +1574,9 @@ mod tests {
         let previous_digest = BlockDigest::digest(&[]);
         let nonce = 0;
 
-        let mut block_source = BlockSource::new(
+        let block_source = BlockSource::new(
             height,
-            vec![tx],
+            vec![Arc::new(tx)],
             previous_digest,
             difficulty(),
             nonce,
@@ -574,14 +1586,79 @@ mod tests {
         .unwrap();
 
         // Proof of work
-        loop {
-            *block_source.nonce_mut() = rand::random();
+        block_source
+            .search_nonce(NonceIter::new(rand::thread_rng()))
+            .unwrap()
+    }
 
-            match block_source.try_into_block() {
-                Ok(block) => break block,
-                Err(source) => block_source = source,
-            }
-        }
+    #[test]
+    fn test_seeded_nonces() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let nonces = |seed| NonceIter::new(StdRng::seed_from_u64(seed)).take(3);
+        assert!(nonces(1).eq(nonces(1)));
+        assert!(!nonces(1).eq(nonces(2)));
+    }
+
+    #[test]
+    fn test_search_nonce_exhausted() {
+        let miner = SecretAddress::create();
+        let source = BlockSource::new(
+            BlockHeight::genesis(),
+            vec![],
+            BlockDigest::digest(&[]),
+            Difficulty::new(u8::MAX),
+            0,
+            &miner,
+            generation_rule,
+        )
+        .unwrap();
+        assert!(source.search_nonce(0..10).is_err());
+    }
+
+    #[test]
+    fn test_height_arithmetic() {
+        let height = BlockHeight::from(10);
+
+        assert_eq!(Some(BlockHeight(13)), height.checked_add(3));
+        assert_eq!(None, height.checked_add(u64::MAX));
+        assert_eq!(Some(BlockHeight(7)), height.checked_sub(3));
+        assert_eq!(None, height.checked_sub(11));
+        assert_eq!(BlockHeight::genesis(), height.saturating_sub(11));
+        assert_eq!(4, height.distance(BlockHeight(14)));
+        assert_eq!(4, BlockHeight(14).distance(height));
+        assert_eq!(10, u64::from(height));
+        assert!(BlockHeight::genesis().is_genesis());
+        assert!(!height.is_genesis());
+    }
+
+    #[test]
+    fn test_height_range() {
+        let range = HeightRange::new(BlockHeight(2), BlockHeight(5));
+
+        assert!(range.contains(BlockHeight(2)));
+        assert!(!range.contains(BlockHeight(5)));
+        assert_eq!(3, range.count_heights());
+        assert_eq!(
+            vec![BlockHeight(2), BlockHeight(3), BlockHeight(4)],
+            range.collect_vec()
+        );
+        assert_eq!(
+            vec![BlockHeight(4), BlockHeight(3), BlockHeight(2)],
+            range.rev().collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_height_range_inclusive() {
+        let range = BlockHeight(2).to_inclusive(BlockHeight(4));
+
+        assert_eq!(
+            vec![BlockHeight(2), BlockHeight(3), BlockHeight(4)],
+            range.collect_vec()
+        );
+        assert!(BlockHeight(3).to_inclusive(BlockHeight(2)).is_empty());
     }
 
     #[test]
@@ -589,7 +1666,7 @@ mod tests {
         let difficulty = difficulty();
         let block = create_unverified_genesis_block();
 
-        let block = block.verify_transaction_relation(generation_rule).unwrap();
+        let block = block.verify_transaction_relation_with(&params()).unwrap();
         let block = block.verify_utxo(|_| true).unwrap();
         let block = block.verify_digest().unwrap();
         let block = block.verify_previous_block(|_, _| true).unwrap();
@@ -600,7 +1677,7 @@ mod tests {
         let de = serde_json::from_str::<Block<_, _, _, _, _, _>>(&ser).unwrap();
 
         let de = de.verify_transaction_itself().unwrap();
-        let de = de.verify_transaction_relation(generation_rule).unwrap();
+        let de = de.verify_transaction_relation_with(&params()).unwrap();
         let de = de.verify_utxo(|_| true).unwrap();
         let de = de.verify_digest().unwrap();
         let de = de.verify_previous_block(|_, _| true).unwrap();
@@ -609,12 +1686,166 @@ mod tests {
         assert_eq!(de, block);
     }
 
+    #[test]
+    fn test_default_pow_algorithm() {
+        let block = create_unverified_genesis_block();
+        let header = block.header().clone();
+        assert_eq!(
+            header.compute_digest(),
+            header.compute_digest_by(params().pow_algorithm())
+        );
+        assert!(block.verify_digest_with(&params()).is_ok());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_pow() {
+        use crate::light::{HeaderChain, HeaderChainError};
+
+        let params = params().with_pow_algorithm(PowAlgorithm::Blake3);
+        let block = BlockSource::new(
+            BlockHeight::genesis(),
+            vec![],
+            BlockDigest::digest(&[]),
+            difficulty(),
+            0,
+            &SecretAddress::create(),
+            generation_rule,
+        )
+        .unwrap()
+        .with_pow_algorithm(params.pow_algorithm())
+        .search_nonce(NonceIter::new(rand::thread_rng()))
+        .unwrap();
+
+        assert!(difficulty().verify_digest(block.digest()));
+        assert_eq!(
+            Some(block.digest()),
+            block
+                .header()
+                .compute_digest_by(PowAlgorithm::Blake3)
+                .as_ref()
+        );
+        assert_eq!(
+            Err(BlockError::Digest),
+            block.clone().verify_digest().map(|_| ())
+        );
+        assert!(block.header().verify_by(PowAlgorithm::Blake3));
+        assert!(!block.header().verify());
+
+        // Header-only nodes follow the chain only when they share its algorithm
+        let mut headers = HeaderChain::new(difficulty());
+        assert_eq!(
+            Err(HeaderChainError::InvalidHeader),
+            headers.push(block.header().clone())
+        );
+        let mut headers = HeaderChain::new(difficulty()).with_pow_algorithm(PowAlgorithm::Blake3);
+        assert_eq!(Ok(()), headers.push(block.header().clone()));
+        assert!(block.verify_digest_with(&params).is_ok());
+    }
+
+    #[test]
+    fn test_verify_authority() {
+        let keys = (0..2).map(|_| SecretAddress::create()).collect::<Vec<_>>();
+        let addresses = keys.iter().map(SecretAddress::to_public_address).collect();
+        let authorities = Authorities::new(Vec::leak(addresses)).unwrap();
+        let source = |difficulty| {
+            BlockSource::new(
+                BlockHeight::new(1),
+                vec![],
+                BlockDigest::digest(&[]),
+                difficulty,
+                0,
+                &keys[1],
+                generation_rule,
+            )
+            .unwrap()
+        };
+
+        let block = source(SEALED_DIFFICULTY).seal(&keys[1]);
+        assert!(block.header().verify_seal(&authorities));
+        let block = block.verify_digest().unwrap();
+        assert!(block.clone().verify_authority(&authorities).is_ok());
+        assert_eq!(
+            Err(BlockError::InsufficientDifficulty),
+            block.verify_difficulty(&difficulty()).map(|_| ())
+        );
+
+        // Out of turn
+        let block = source(SEALED_DIFFICULTY).seal(&keys[0]);
+        assert_eq!(
+            Err(BlockError::Seal),
+            block.verify_authority(&authorities).map(|_| ())
+        );
+
+        // Heavier than other blocks of the chain
+        let block = source(difficulty()).seal(&keys[1]);
+        assert_eq!(
+            Err(BlockError::UnexpectedDifficulty),
+            block.verify_authority(&authorities).map(|_| ())
+        );
+
+        // Unsealed, while the seal is covered by the digest
+        let block = source(SEALED_DIFFICULTY).seal(&keys[1]);
+        let header = block.header().clone().with_seal(None);
+        assert!(!header.verify_seal(&authorities));
+        assert_ne!(header.compute_digest().as_ref(), Some(block.digest()));
+
+        // Re-hashed under another nonce
+        let block = source(SEALED_DIFFICULTY).seal(&keys[1]);
+        let mut header = block.header().clone();
+        header.nonce += 1;
+        header.digest = header.compute_digest().unwrap();
+        assert!(!header.verify_seal(&authorities));
+        let block = Block::<Verified, Yet, Yet, Yet, Yet, Yet> {
+            header,
+            transactions: block.transactions,
+            _phantom: PhantomData,
+        };
+        let block = block.verify_digest().unwrap();
+        assert_eq!(
+            Err(BlockError::Seal),
+            block.verify_authority(&authorities).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_block_header() {
+        let block = create_unverified_genesis_block();
+        let header = block.header();
+        assert!(header.verify());
+        assert_eq!(Some(block.digest()), header.compute_digest().as_ref());
+
+        // Blocks stay flat without the Merkle root, which is derived back from the transactions
+        let json = serde_json::to_value(&block).unwrap();
+        assert!(json.get("header").is_none() && json.get("merkle_root").is_none());
+        let de = serde_json::from_value::<Block<_, _, _, _, _, _>>(json).unwrap();
+        assert_eq!(header, de.header());
+
+        let json = serde_json::to_string(header).unwrap();
+        assert_eq!(header, &serde_json::from_str::<BlockHeader>(&json).unwrap());
+
+        // Positional encodings lead with the format, which decoders check
+        let mut bytes = bincode::serialize(&block).unwrap();
+        assert_eq!(BLOCK_FORMAT_VERSION, bytes[0]);
+        let de = bincode::deserialize::<Block<_, _, _, _, _, _>>(&bytes).unwrap();
+        assert_eq!(header, de.header());
+        bytes[0] += 1;
+        assert!(bincode::deserialize::<Block<_, _, _, _, _, _>>(&bytes).is_err());
+
+        // Legacy digests commit to the transactions themselves
+        let legacy = BlockBuilder::<Yet>::new(BlockHeight::genesis(), BlockDigest::digest(&[]))
+            .version(SighashVersion::Legacy)
+            .build();
+        assert_eq!(None, legacy.header().compute_digest());
+        assert!(!legacy.header().verify());
+    }
+
     #[test]
     fn test_verify_transaction_relation_too_much_quantity() {
         let block = create_unverified_genesis_block();
         let zero_gen_rule = |_: BlockHeight| Coin::from(0);
         // Block coin generation is too much under zero_gen_rule
-        let block = block.verify_transaction_relation(zero_gen_rule);
+        let block = block.verify_transaction_relation_with(&ChainParams::new(zero_gen_rule));
 
         assert_eq!(Err(BlockError::TransactionQuantity), block);
     }
@@ -624,7 +1855,7 @@ mod tests {
         let block = create_unverified_genesis_block();
         let much_gen_rule = |_: BlockHeight| Coin::from(10000);
         // Block coin generation is too few under much_gen_rule
-        let block = block.verify_transaction_relation(much_gen_rule);
+        let block = block.verify_transaction_relation_with(&ChainParams::new(much_gen_rule));
 
         assert_eq!(Err(BlockError::TransactionQuantity), block);
     }
@@ -632,9 +1863,9 @@ mod tests {
     #[test]
     fn test_verify_utxo_fail() {
         let block = create_unverified_genesis_block();
-        let block = block.verify_transaction_relation(generation_rule).unwrap();
+        let block = block.verify_transaction_relation_with(&params()).unwrap();
 
-        let utxo_judge_always_fail = |_: &[Transaction<_>]| false;
+        let utxo_judge_always_fail = |_: &[Arc<Transaction<_>>]| false;
         let block = block.verify_utxo(utxo_judge_always_fail);
 
         assert_eq!(Err(BlockError::Utxo), block);
@@ -643,9 +1874,9 @@ mod tests {
     #[test]
     fn test_verify_digest_fail() {
         let block = create_unverified_genesis_block();
-        let mut block = block.verify_transaction_relation(generation_rule).unwrap();
+        let mut block = block.verify_transaction_relation_with(&params()).unwrap();
 
-        block.height = block.height.next(); // Data tampering!
+        block.header.height = block.header.height.next(); // Data tampering!
 
         let block = block.verify_digest();
 
@@ -655,7 +1886,7 @@ mod tests {
     #[test]
     fn test_verify_difficulty_fail() {
         let block = create_unverified_genesis_block();
-        let block = block.verify_transaction_relation(generation_rule).unwrap();
+        let block = block.verify_transaction_relation_with(&params()).unwrap();
 
         let too_difficult = Difficulty::new(255);
 
@@ -663,4 +1894,81 @@ mod tests {
 
         assert_eq!(Err(BlockError::InsufficientDifficulty), block);
     }
+
+    #[test]
+    fn test_built_block_verification() {
+        let miner = SecretAddress::create();
+        let offer = || {
+            let inputs: Vec<Transfer<_>> = vec![];
+            let outputs = vec![Generation::offer(&miner, Coin::from(1))];
+            crate::transaction::Transaction::offer(&miner, inputs, outputs)
+                .verify_transaction()
+                .unwrap()
+        };
+        let older = offer();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let newer = offer();
+        let builder = || BlockBuilder::new(BlockHeight::genesis(), BlockDigest::digest(&[]));
+
+        let outdated = builder()
+            .transaction(older.clone())
+            .timestamp(Timestamp::enix_epoch())
+            .build()
+            .verify_transaction_relation_with(&ChainParams::new(|_| Coin::default()));
+        assert_eq!(Err(BlockError::TransactionTimestamp), outdated);
+
+        let unordered = builder()
+            .transaction(newer.clone())
+            .transaction(older.clone())
+            .build()
+            .verify_transaction_relation_with(&ChainParams::new(|_| Coin::default()));
+        assert_eq!(Err(BlockError::TransactionTimestamp), unordered);
+
+        let ordered = builder()
+            .transaction(older)
+            .transaction(newer)
+            .build()
+            .verify_transaction_relation_with(&ChainParams::new(|_| Coin::from(2)));
+        assert!(ordered.is_ok());
+
+        let forged = builder()
+            .digest(BlockDigest::digest(b"forged"))
+            .build()
+            .verify_digest();
+        assert_eq!(Err(BlockError::Digest), forged);
+
+        let valid = builder().build().verify_digest();
+        assert!(valid.is_ok());
+    }
+
+    #[test]
+    fn test_dyn_block() {
+        let block = create_unverified_genesis_block();
+        let record = DynBlock::from(block.clone());
+
+        let state = record.verification_state();
+        assert!(state.transaction_itself);
+        assert!(!state.transaction_relation);
+        assert!(!state.is_fully_verified());
+        assert_eq!(block.digest(), record.digest());
+
+        // Wrong stages are refused without losing the record
+        let record = record
+            .try_into_block::<Verified, Verified, Yet, Yet, Yet, Yet>()
+            .unwrap_err();
+        let record = record
+            .try_into_block::<Yet, Yet, Yet, Yet, Yet, Yet>()
+            .unwrap_err();
+        assert_eq!(
+            Ok(block.clone()),
+            record.try_into_block::<Verified, Yet, Yet, Yet, Yet, Yet>()
+        );
+
+        // Same format as Block, but deserialized as unverified
+        let json = serde_json::to_string(&DynBlock::from(block.clone())).unwrap();
+        assert_eq!(serde_json::to_string(&block).unwrap(), json);
+        let record = serde_json::from_str::<DynBlock>(&json).unwrap();
+        assert_eq!(VerificationState::default(), record.verification_state());
+        assert_eq!(block.transactions().len(), record.transactions().len());
+    }
 }
@@ -0,0 +1,418 @@
+use crate::account::{Address, SecretAddress};
+use crate::block::BlockHeight;
+use crate::coin::Coin;
+use crate::signature::{
+    SighashDomain, SighashVersion, Signature, SignatureBuilder, SignatureSource,
+};
+use crate::transaction::{Transaction, TransactionError};
+use crate::transition::{Multisig, Transfer, Transition};
+use crate::verification::{Verified, Yet};
+use crate::VerifiedTransaction;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Unidirectional payment channel from a payer to a payee.
+/// The payer locks coin into a multisig output, then pays by signing newer states off-chain.
+/// The payee closes the channel by cosigning the latest state.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    /// Multisig output of the funding transaction
+    funding: Transition<Verified>,
+    latest: Option<ChannelUpdate>,
+}
+
+impl Channel {
+    /// Build a transaction locking `capacity` into a channel until `timeout`.
+    /// The rest of `inputs` goes back to the payer.
+    pub fn funding_transaction(
+        payer: &SecretAddress,
+        payee: Address,
+        inputs: Vec<Transition<Verified>>,
+        capacity: Coin,
+        timeout: BlockHeight,
+    ) -> Result<VerifiedTransaction, ChannelError> {
+        let input_sum = inputs.iter().map(Transition::quantity).sum::<Coin>();
+        let change = input_sum
+            .checked_sub(capacity)
+            .ok_or(ChannelError::InsufficientFund)?;
+
+        let mut outputs: Vec<Transition<Verified>> =
+            vec![Multisig::offer(payer, payee, capacity, timeout).into()];
+        if change > Coin::default() {
+            outputs.push(Transfer::offer(payer, payer.to_public_address(), change).into());
+        }
+
+        let tx = Transaction::offer(payer, inputs, outputs).verify_transaction()?;
+        Ok(tx)
+    }
+
+    /// Open a channel funded by `funding_transaction`. Both of the payer and the payee open it.
+    pub fn open(funding_transaction: &VerifiedTransaction) -> Result<Channel, ChannelError> {
+        let funding = funding_transaction
+            .outputs()
+            .iter()
+            .find(|o| o.try_as_multisig().is_some())
+            .cloned()
+            .ok_or(ChannelError::NoFunding)?;
+        Ok(Channel {
+            funding,
+            latest: None,
+        })
+    }
+
+    pub fn payer(&self) -> &Address {
+        self.multisig().sender()
+    }
+
+    pub fn payee(&self) -> &Address {
+        self.multisig().receiver()
+    }
+
+    pub fn capacity(&self) -> Coin {
+        self.multisig().quantity()
+    }
+
+    pub fn timeout(&self) -> BlockHeight {
+        self.multisig().timeout()
+    }
+
+    /// Sequence number of the latest state. 0 before any payment.
+    pub fn sequence(&self) -> u64 {
+        self.latest.as_ref().map_or(0, ChannelUpdate::sequence)
+    }
+
+    /// Total coin paid to the payee in the latest state.
+    pub fn paid(&self) -> Coin {
+        self.latest
+            .as_ref()
+            .map_or_else(Coin::default, ChannelUpdate::paid)
+    }
+
+    /// Sign a new state paying `paid` in total to the payee, then apply it.
+    /// The returned update is to be sent to the payee.
+    pub fn update(
+        &mut self,
+        payer: &SecretAddress,
+        paid: Coin,
+    ) -> Result<ChannelUpdate, ChannelError> {
+        let rest = self
+            .capacity()
+            .checked_sub(paid)
+            .ok_or(ChannelError::ExceedCapacity)?;
+
+        let mut outputs = vec![];
+        if paid > Coin::default() {
+            outputs.push(Transfer::offer(payer, self.payee().clone(), paid));
+        }
+        if rest > Coin::default() {
+            outputs.push(Transfer::offer(payer, payer.to_public_address(), rest));
+        }
+        let commitment = Transaction::offer(payer, vec![self.funding.clone()], outputs);
+
+        let sequence = self.sequence() + 1;
+        let sign = {
+            let builder = build_update_sighash(&self.funding, sequence, paid, &commitment);
+            payer.sign(&builder.finalize_sighash())
+        };
+
+        let update = ChannelUpdate {
+            sequence,
+            paid,
+            commitment,
+            sign,
+        };
+        self.apply(update.clone())?;
+        Ok(update)
+    }
+
+    /// Accept a state signed by the payer.
+    /// States which are older or pay less than the latest one are rejected.
+    pub fn apply(&mut self, update: ChannelUpdate) -> Result<(), ChannelError> {
+        if update.sequence <= self.sequence() || update.paid < self.paid() {
+            return Err(ChannelError::StaleUpdate);
+        }
+        if update.paid > self.capacity() {
+            return Err(ChannelError::ExceedCapacity);
+        }
+
+        let signature_source = {
+            let builder = build_update_sighash(
+                &self.funding,
+                update.sequence,
+                update.paid,
+                &update.commitment,
+            );
+            builder.finalize_sighash()
+        };
+        if !self.payer().verify(&signature_source, &update.sign) {
+            return Err(ChannelError::InvalidSign);
+        }
+
+        // Commitment must spend only the funding, and pay the payee as the state says
+        let commitment = &update.commitment;
+        let paid_to_payee = commitment
+            .outputs()
+            .iter()
+            .filter(|o| o.receiver() == self.payee())
+            .map(Transition::quantity)
+            .sum::<Coin>();
+        if commitment.contractor() != self.payer()
+            || commitment.inputs() != [self.funding.clone()]
+            || paid_to_payee != update.paid
+        {
+            return Err(ChannelError::InvalidCommitment);
+        }
+        commitment.clone().verify_transaction()?;
+
+        self.latest = Some(update);
+        Ok(())
+    }
+
+    /// Close the channel cooperatively by cosigning the latest state as the payee.
+    pub fn cooperative_close(
+        &self,
+        payee: &SecretAddress,
+    ) -> Result<VerifiedTransaction, ChannelError> {
+        let update = self.latest.as_ref().ok_or(ChannelError::NoUpdate)?;
+        let tx = update
+            .commitment
+            .clone()
+            .cosign(payee)
+            .verify_transaction()?;
+        Ok(tx)
+    }
+
+    /// Take back the whole capacity as the payer. Accepted by the ledger at or after timeout.
+    pub fn refund_transaction(
+        &self,
+        payer: &SecretAddress,
+    ) -> Result<VerifiedTransaction, ChannelError> {
+        let output = Transfer::offer(payer, payer.to_public_address(), self.capacity());
+        let tx = Transaction::offer(payer, vec![self.funding.clone()], vec![output])
+            .verify_transaction()?;
+        Ok(tx)
+    }
+
+    fn multisig(&self) -> &Multisig<Verified> {
+        self.funding
+            .try_as_multisig()
+            .expect("Channel is funded by multisig")
+    }
+}
+
+/// State of a channel signed by the payer.
+#[derive(Debug, Clone)]
+pub struct ChannelUpdate {
+    sequence: u64,
+    /// Total coin paid to the payee
+    paid: Coin,
+    /// Transaction spending the funding. Signed by the payer, waiting for the payee's cosign.
+    commitment: Transaction<Verified, Yet>,
+    sign: Signature,
+}
+
+impl ChannelUpdate {
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn paid(&self) -> Coin {
+        self.paid
+    }
+
+    pub fn commitment(&self) -> &Transaction<Verified, Yet> {
+        &self.commitment
+    }
+
+    pub fn sign(&self) -> &Signature {
+        &self.sign
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    Transaction(TransactionError),
+    /// Inputs are smaller than the capacity.
+    InsufficientFund,
+    /// Funding transaction has no multisig output.
+    NoFunding,
+    /// Payment is larger than the capacity.
+    ExceedCapacity,
+    /// State is older or pays less than the latest one.
+    StaleUpdate,
+    /// Payer's sign of the state is invalid.
+    InvalidSign,
+    /// Commitment does not match the state.
+    InvalidCommitment,
+    /// No state to close the channel with.
+    NoUpdate,
+}
+
+impl From<TransactionError> for ChannelError {
+    fn from(e: TransactionError) -> Self {
+        ChannelError::Transaction(e)
+    }
+}
+
+impl Display for ChannelError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelError::Transaction(e) => e.fmt(f),
+            ChannelError::InsufficientFund => write!(f, "Inputs are smaller than the capacity"),
+            ChannelError::NoFunding => write!(f, "No multisig output in funding transaction"),
+            ChannelError::ExceedCapacity => write!(f, "Payment exceeds channel capacity"),
+            ChannelError::StaleUpdate => write!(f, "Channel state is stale"),
+            ChannelError::InvalidSign => write!(f, "Payer's sign of channel state is invalid"),
+            ChannelError::InvalidCommitment => write!(f, "Commitment mismatches channel state"),
+            ChannelError::NoUpdate => write!(f, "No channel state to close with"),
+        }
+    }
+}
+
+impl Error for ChannelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChannelError::Transaction(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Message which the payer signs for a state.
+fn build_update_sighash(
+    funding: &Transition<Verified>,
+    sequence: u64,
+    paid: Coin,
+    commitment: &Transaction<Verified, Yet>,
+) -> SignatureBuilder {
+    let mut builder = SignatureBuilder::sighash(SighashVersion::CURRENT, SighashDomain::Channel);
+    funding.write_bytes(&mut builder);
+    builder.write_bytes(&sequence.to_le_bytes());
+    paid.write_bytes(&mut builder);
+    commitment.write_bytes(&mut builder);
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::ChainAnalysis;
+    use crate::ledger::{Ledger, LedgerError};
+    use crate::test_utils::{mine, reward};
+
+    const TIMEOUT: u64 = 4;
+
+    /// Payer mines genesis, then funds a channel of 60 coins from the reward.
+    /// The funding is mined by someone else.
+    fn create_channel(payer: &SecretAddress, payee: &Address) -> (Ledger, Channel) {
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], payer).unwrap();
+
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let funding = Channel::funding_transaction(
+            payer,
+            payee.clone(),
+            vec![reward],
+            Coin::from(60),
+            BlockHeight::from(TIMEOUT),
+        )
+        .unwrap();
+        mine(&mut ledger, vec![funding.clone()], &SecretAddress::create()).unwrap();
+
+        (ledger, Channel::open(&funding).unwrap())
+    }
+
+    fn balance(ledger: &Ledger, address: &Address) -> Coin {
+        let tip = ledger.search_latest_block().unwrap().digest().clone();
+        ChainAnalysis::new(ledger, &tip)
+            .balance_history(address)
+            .last()
+            .map(|(_, balance)| *balance)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_channel_lifecycle() {
+        let (payer, payee) = (SecretAddress::create(), SecretAddress::create());
+        let miner = SecretAddress::create();
+        let (mut ledger, mut payer_channel) = create_channel(&payer, &payee.to_public_address());
+        let mut payee_channel = payer_channel.clone();
+
+        // Off-chain payments
+        let mut updates = vec![];
+        for paid in [10, 25, 40] {
+            let update = payer_channel.update(&payer, Coin::from(paid)).unwrap();
+            payee_channel.apply(update.clone()).unwrap();
+            updates.push(update);
+        }
+        assert_eq!(3, payee_channel.sequence());
+        assert_eq!(Coin::from(40), payee_channel.paid());
+
+        // Older state cannot replace the latest one
+        assert_eq!(
+            Err(ChannelError::StaleUpdate),
+            payee_channel.apply(updates[0].clone())
+        );
+
+        let close = payee_channel.cooperative_close(&payee).unwrap();
+        mine(&mut ledger, vec![close], &miner).unwrap();
+
+        assert_eq!(Coin::from(40), balance(&ledger, &payee.to_public_address()));
+        assert_eq!(Coin::from(60), balance(&ledger, &payer.to_public_address()));
+    }
+
+    #[test]
+    fn test_channel_commitment_without_cosign() {
+        let (payer, payee) = (SecretAddress::create(), SecretAddress::create());
+        let (mut ledger, mut channel) = create_channel(&payer, &payee.to_public_address());
+
+        let update = channel.update(&payer, Coin::from(10)).unwrap();
+        let tx = update.commitment().clone().verify_transaction().unwrap();
+
+        assert_eq!(
+            Err(LedgerError::Timelock),
+            mine(&mut ledger, vec![tx], &payer)
+        );
+    }
+
+    #[test]
+    fn test_channel_refund() {
+        let (payer, payee) = (SecretAddress::create(), SecretAddress::create());
+        let (mut ledger, channel) = create_channel(&payer, &payee.to_public_address());
+
+        // Before timeout
+        let tx = channel.refund_transaction(&payer).unwrap();
+        assert_eq!(
+            Err(LedgerError::Timelock),
+            mine(&mut ledger, vec![tx], &payer)
+        );
+
+        // After timeout
+        while ledger.search_latest_block().unwrap().height().next() < channel.timeout() {
+            mine(&mut ledger, vec![], &payer).unwrap();
+        }
+        let tx = channel.refund_transaction(&payer).unwrap();
+        assert!(mine(&mut ledger, vec![tx], &payer).is_ok());
+    }
+
+    #[test]
+    fn test_channel_invalid_update() {
+        let (payer, payee) = (SecretAddress::create(), SecretAddress::create());
+        let (_, channel) = create_channel(&payer, &payee.to_public_address());
+
+        // Signed by someone other than the payer
+        let mut forged_channel = channel.clone();
+        let forged = forged_channel
+            .update(&SecretAddress::create(), Coin::from(10))
+            .unwrap_err();
+        assert_eq!(ChannelError::InvalidSign, forged);
+
+        let mut payer_channel = channel;
+        assert_eq!(
+            Err(ChannelError::ExceedCapacity),
+            payer_channel
+                .update(&payer, Coin::from(61))
+                .map(|u| u.sequence())
+        );
+    }
+}
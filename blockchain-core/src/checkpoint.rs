@@ -0,0 +1,116 @@
+//! Assumed-valid checkpoint, below which signs are not checked on first sync.
+//!
+//! Checking signs is most of the cost of verifying a block. A node trusting that the chain up to
+//! a known block is signed correctly skips signs below it, while still checking Proof-of-Work,
+//! quantities and UTXO consistency. The block at the checkpoint height must be the checkpoint,
+//! so a branch accepted without signs cannot grow past it.
+
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssumeValid {
+    height: BlockHeight,
+    digest: BlockDigest,
+}
+
+impl AssumeValid {
+    pub fn new(height: BlockHeight, digest: BlockDigest) -> Self {
+        Self { height, digest }
+    }
+
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    pub fn digest(&self) -> &BlockDigest {
+        &self.digest
+    }
+
+    /// Whether signs of the block may be skipped.
+    pub fn covers(&self, height: BlockHeight, digest: &BlockDigest) -> bool {
+        height < self.height || (height == self.height && digest == &self.digest)
+    }
+
+    /// Deny a block at the checkpoint height other than the checkpoint.
+    pub fn check(&self, height: BlockHeight, digest: &BlockDigest) -> Result<(), CheckpointError> {
+        if height == self.height && digest != &self.digest {
+            Err(CheckpointError(self.clone()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Display for AssumeValid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.height, self.digest)
+    }
+}
+
+/// Parse `HEIGHT:DIGEST`, where the digest is in hex.
+impl FromStr for AssumeValid {
+    type Err = ParseAssumeValidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (height, digest) = s.split_once(':').ok_or(ParseAssumeValidError)?;
+        let height = height.parse::<u64>().map_err(|_| ParseAssumeValidError)?;
+        let digest = digest.parse().map_err(|_| ParseAssumeValidError)?;
+        Ok(Self::new(BlockHeight::from(height), digest))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAssumeValidError;
+
+impl Display for ParseAssumeValidError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Checkpoint must be HEIGHT:DIGEST with the digest in hex")
+    }
+}
+
+impl Error for ParseAssumeValidError {}
+
+/// Block at the checkpoint height is not the checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointError(AssumeValid);
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Block differs from checkpoint {}", self.0)
+    }
+}
+
+impl Error for CheckpointError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covers() {
+        let digest = BlockDigest::digest(b"checkpoint");
+        let other = BlockDigest::digest(b"other");
+        let checkpoint = AssumeValid::new(BlockHeight::from(10), digest.clone());
+
+        assert!(checkpoint.covers(BlockHeight::from(9), &other));
+        assert!(checkpoint.covers(BlockHeight::from(10), &digest));
+        assert!(!checkpoint.covers(BlockHeight::from(10), &other));
+        assert!(!checkpoint.covers(BlockHeight::from(11), &digest));
+
+        assert!(checkpoint.check(BlockHeight::from(9), &other).is_ok());
+        assert!(checkpoint.check(BlockHeight::from(10), &other).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let digest = BlockDigest::digest(b"checkpoint");
+        let checkpoint = AssumeValid::new(BlockHeight::from(10), digest);
+        assert_eq!(Ok(checkpoint.clone()), checkpoint.to_string().parse());
+        assert!("10".parse::<AssumeValid>().is_err());
+        assert!("ten:00".parse::<AssumeValid>().is_err());
+    }
+}
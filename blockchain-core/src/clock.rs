@@ -0,0 +1,69 @@
+//! Source of the current time for creating transactions and blocks and for checking their timestamps.
+//!
+//! Everything takes `SystemClock` by default.
+//! Tests pass a `MockClock` to check time-dependent rules at chosen times.
+
+use crate::timestamp::Timestamp;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+/// Wall clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+/// Clock which stands still until it is moved. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Timestamp>>,
+}
+
+impl MockClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        *self.now.lock().expect("Lock failure") = now;
+    }
+
+    /// Move `secs` seconds forward, or backward if negative.
+    pub fn advance_secs(&self, secs: i64) {
+        let mut now = self.now.lock().expect("Lock failure");
+        *now = now.checked_add_secs(secs).expect("Timestamp out of range");
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        *self.now.lock().expect("Lock failure")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let start = Timestamp::enix_epoch();
+        let clock = MockClock::new(start);
+        let shared = clock.clone();
+
+        shared.advance_secs(90);
+        assert_eq!(start.checked_add_secs(90), Some(clock.now()));
+        clock.set(start);
+        assert_eq!(start, shared.now());
+    }
+}
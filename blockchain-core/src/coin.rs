@@ -1,17 +1,132 @@
 use crate::signature::{SignatureBuilder, SignatureSource};
 use serde::{Deserialize, Serialize};
+use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::iter::Sum;
+use std::num::ParseIntError;
 use std::ops::{Add, Sub};
 use std::str::FromStr;
 
+/// Number of decimal places of one coin.
+pub const DECIMALS: u32 = 8;
+
+/// Base units in one coin.
+pub const BASE_UNITS_PER_COIN: u64 = 10_u64.pow(DECIMALS);
+
+/// Unit suffix used on display.
+pub const UNIT_SYMBOL: &str = "COIN";
+
+/// Quantity of coin in base units.
+/// All consensus calculations are done in base units.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Coin(u64);
 
 impl Coin {
+    /// Create from base units.
     pub const fn from(quantity: u64) -> Self {
         Self(quantity)
     }
+
+    /// Create from whole coins. Returns `None` on overflow.
+    pub const fn from_coins(coins: u64) -> Option<Self> {
+        match coins.checked_mul(BASE_UNITS_PER_COIN) {
+            Some(quantity) => Some(Self(quantity)),
+            None => None,
+        }
+    }
+
+    pub const fn base_units(self) -> u64 {
+        self.0
+    }
+
+    pub const fn checked_add(self, rhs: Coin) -> Option<Coin> {
+        match self.0.checked_add(rhs.0) {
+            Some(quantity) => Some(Self(quantity)),
+            None => None,
+        }
+    }
+
+    pub const fn checked_sub(self, rhs: Coin) -> Option<Coin> {
+        match self.0.checked_sub(rhs.0) {
+            Some(quantity) => Some(Self(quantity)),
+            None => None,
+        }
+    }
+
+    /// Parse a decimal coin quantity such as "1.5", which is 150000000 base units.
+    pub fn from_decimal_str(s: &str) -> Result<Self, CoinError> {
+        let (integer, fraction) = match s.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (s, ""),
+        };
+
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(CoinError::Empty);
+        }
+        if fraction.len() > DECIMALS as usize {
+            return Err(CoinError::TooManyDecimals);
+        }
+        // Reject signs, which u64::from_str accepts partially
+        if !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(CoinError::InvalidDigit);
+        }
+
+        let integer = match integer {
+            "" => 0,
+            integer => u64::from_str(integer)?,
+        };
+        let fraction = match fraction {
+            "" => 0,
+            fraction => {
+                let scale = 10_u64.pow(DECIMALS - fraction.len() as u32);
+                u64::from_str(fraction)? * scale
+            }
+        };
+
+        integer
+            .checked_mul(BASE_UNITS_PER_COIN)
+            .and_then(|quantity| quantity.checked_add(fraction))
+            .map(Coin)
+            .ok_or(CoinError::Overflow)
+    }
+
+    /// Split into at most `max_parts` quantities of 1, 2 or 5 times a power of ten base units,
+    /// the largest first, but the last part which takes the rest. Nothing of zero.
+    pub fn split_denominations(self, max_parts: usize) -> Vec<Coin> {
+        let mut parts = vec![];
+        let mut rest = self.0;
+        while rest > 0 && parts.len() + 1 < max_parts {
+            let power = 10_u64.pow(rest.ilog10());
+            let denomination = [5, 2, 1]
+                .into_iter()
+                .filter_map(|m| power.checked_mul(m))
+                .find(|&d| d <= rest)
+                .unwrap_or(power);
+            parts.push(Coin(denomination));
+            rest -= denomination;
+        }
+        if rest > 0 {
+            parts.push(Coin(rest));
+        }
+        parts
+    }
+
+    /// Decimal representation without unit suffix. Trailing zeros of the fraction are omitted.
+    pub fn to_decimal_string(self) -> String {
+        let integer = self.0 / BASE_UNITS_PER_COIN;
+        let fraction = self.0 % BASE_UNITS_PER_COIN;
+
+        if fraction == 0 {
+            integer.to_string()
+        } else {
+            let fraction = format!("{:0width$}", fraction, width = DECIMALS as usize);
+            format!("{}.{}", integer, fraction.trim_end_matches('0'))
+        }
+    }
 }
 
 impl SignatureSource for Coin {
@@ -22,7 +137,7 @@ impl SignatureSource for Coin {
 
 impl Display for Coin {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        write!(f, "{} {}", self.to_decimal_string(), UNIT_SYMBOL)
     }
 }
 
@@ -32,11 +147,14 @@ impl Default for Coin {
     }
 }
 
+/// Parse a decimal coin quantity with optional unit suffix, such as "1.5" or "1.5 COIN".
 impl FromStr for Coin {
-    type Err = <u64 as FromStr>::Err;
+    type Err = CoinError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        u64::from_str(s).map(Coin)
+        let s = s.trim();
+        let s = s.strip_suffix(UNIT_SYMBOL).map(str::trim_end).unwrap_or(s);
+        Coin::from_decimal_str(s)
     }
 }
 
@@ -65,9 +183,104 @@ impl Sum<Coin> for Coin {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinError {
+    Empty,
+    InvalidDigit,
+    /// More fractional digits than `DECIMALS`.
+    TooManyDecimals,
+    Overflow,
+    Parse(ParseIntError),
+}
+
+impl From<ParseIntError> for CoinError {
+    fn from(e: ParseIntError) -> Self {
+        CoinError::Parse(e)
+    }
+}
+
+impl Display for CoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinError::Empty => write!(f, "Empty coin quantity"),
+            CoinError::InvalidDigit => write!(f, "Coin quantity contains an invalid digit"),
+            CoinError::TooManyDecimals => {
+                write!(f, "Coin quantity has more than {} decimals", DECIMALS)
+            }
+            CoinError::Overflow => write!(f, "Coin quantity is too large"),
+            CoinError::Parse(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CoinError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoinError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[test]
 fn test_sum() {
     let sum = (1..).take(10).map(Coin::from).sum::<Coin>();
 
     assert_eq!(Coin(55), sum);
 }
+
+#[test]
+fn test_from_decimal_str() {
+    assert_eq!(Ok(Coin(150_000_000)), Coin::from_decimal_str("1.5"));
+    assert_eq!(Ok(Coin(100_000_000)), Coin::from_decimal_str("1"));
+    assert_eq!(Ok(Coin(50_000_000)), Coin::from_decimal_str(".5"));
+    assert_eq!(Ok(Coin(1)), Coin::from_decimal_str("0.00000001"));
+    assert_eq!(Ok(Coin(0)), Coin::from_decimal_str("0"));
+}
+
+#[test]
+fn test_from_decimal_str_error() {
+    assert_eq!(Err(CoinError::Empty), Coin::from_decimal_str(""));
+    assert_eq!(Err(CoinError::Empty), Coin::from_decimal_str("."));
+    assert_eq!(
+        Err(CoinError::TooManyDecimals),
+        Coin::from_decimal_str("0.000000001")
+    );
+    assert_eq!(Err(CoinError::InvalidDigit), Coin::from_decimal_str("-1"));
+    assert_eq!(
+        Err(CoinError::InvalidDigit),
+        Coin::from_decimal_str("1.2.3")
+    );
+    assert_eq!(
+        Err(CoinError::Overflow),
+        Coin::from_decimal_str("184467440737.09551616")
+    );
+}
+
+#[test]
+fn test_split_denominations() {
+    let coins = |parts: &[u64]| parts.iter().copied().map(Coin).collect::<Vec<_>>();
+    assert_eq!(coins(&[50, 20, 5, 2]), Coin(77).split_denominations(8));
+    assert_eq!(coins(&[50, 27]), Coin(77).split_denominations(2));
+    assert_eq!(coins(&[77]), Coin(77).split_denominations(1));
+    assert_eq!(coins(&[100]), Coin(100).split_denominations(8));
+    assert!(Coin(0).split_denominations(8).is_empty());
+    let max = Coin(u64::MAX).split_denominations(64);
+    assert_eq!(Coin(u64::MAX), max.into_iter().sum());
+}
+
+#[test]
+fn test_display() {
+    assert_eq!("1.5 COIN", Coin(150_000_000).to_string());
+    assert_eq!("2 COIN", Coin(200_000_000).to_string());
+    assert_eq!("0.00065536 COIN", Coin(65536).to_string());
+    assert_eq!("0 COIN", Coin(0).to_string());
+}
+
+#[test]
+fn test_from_str_round_trip() {
+    let coin = Coin(123_456_789);
+
+    assert_eq!(Ok(coin), Coin::from_str(&coin.to_string()));
+    assert_eq!(Ok(coin), Coin::from_str("1.23456789"));
+}
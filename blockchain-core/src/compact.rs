@@ -0,0 +1,211 @@
+use crate::block::{BlockHeader, BlockHeight};
+use crate::digest::BlockDigest;
+use crate::ledger::LedgerError;
+use crate::signature::Signature;
+use crate::timestamp::Timestamp;
+use crate::transition::Transition;
+use crate::{UnverifiedBlock, UnverifiedTransaction, VerifiedBlock, Yet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+/// Output of a transaction, which an input of a compact block spends.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub txid: BlockDigest,
+    /// Position in the outputs of the transaction
+    pub index: u32,
+}
+
+impl Display for OutPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.txid.fmt_short(), self.index)
+    }
+}
+
+/// Block whose transaction inputs are replaced with the outpoints they spend.
+/// Built and expanded back by `Ledger::compact_block` and `Ledger::expand_block`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactBlock {
+    /// Header of the expanded block
+    header: BlockHeader,
+    /// Transactions without inputs
+    transactions: Vec<Arc<UnverifiedTransaction>>,
+    /// Outpoints spent by each transaction of the block, in the same order
+    outpoints: Vec<Vec<OutPoint>>,
+}
+
+impl CompactBlock {
+    pub(crate) fn new(block: UnverifiedBlock, outpoints: Vec<Vec<OutPoint>>) -> Self {
+        let transactions = block
+            .transactions()
+            .iter()
+            .map(|tx| Arc::new(UnverifiedTransaction::clone(tx).with_inputs(vec![])))
+            .collect();
+        Self {
+            header: block.header().clone(),
+            transactions,
+            outpoints,
+        }
+    }
+
+    /// Put expanded transactions back under the header.
+    pub(crate) fn into_block(
+        self,
+        transactions: Vec<Arc<UnverifiedTransaction>>,
+    ) -> UnverifiedBlock {
+        UnverifiedBlock::from_header(self.header, transactions)
+    }
+
+    /// Claimed header of the expanded block.
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn height(&self) -> BlockHeight {
+        self.header.height()
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.header.timestamp()
+    }
+
+    pub fn previous_digest(&self) -> &BlockDigest {
+        self.header.previous_digest()
+    }
+
+    /// Claimed digest of the expanded block.
+    pub fn digest(&self) -> &BlockDigest {
+        self.header.digest()
+    }
+
+    /// Transactions without inputs. Their txids differ from the expanded ones.
+    pub fn transactions(&self) -> &[Arc<UnverifiedTransaction>] {
+        &self.transactions
+    }
+
+    pub fn outpoints(&self) -> &[Vec<OutPoint>] {
+        &self.outpoints
+    }
+
+    /// Outpoints spent by all transactions, in order of transactions.
+    pub fn spent_outpoints(&self) -> impl Iterator<Item = &OutPoint> + '_ {
+        self.outpoints.iter().flatten()
+    }
+}
+
+/// Outputs left unspent by the blocks of one chain so far, to compact and expand its blocks in
+/// order without a ledger, as in chain files.
+#[derive(Debug, Default)]
+pub struct UnspentOutputs {
+    outputs: HashMap<OutPoint, Transition<Yet>>,
+    outpoints: HashMap<Signature, OutPoint>,
+}
+
+impl UnspentOutputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compact the next block of the chain, as `Ledger::compact_block` does.
+    /// Returns `None` if any input is not unspent, after which the outputs no longer follow the chain.
+    pub fn compact(&mut self, block: &VerifiedBlock) -> Option<CompactBlock> {
+        let mut outpoints = vec![];
+        for tx in block.transactions() {
+            let spent = tx
+                .inputs()
+                .iter()
+                .map(|input| self.outpoints.get(input.sign()).cloned())
+                .collect::<Option<Vec<_>>>()?;
+            self.spend(&spent);
+            for (outpoint, output) in tx.created_outputs() {
+                self.create(outpoint, output.to_unverified());
+            }
+            outpoints.push(spent);
+        }
+
+        Some(CompactBlock::new(block.to_unverified(), outpoints))
+    }
+
+    /// Expand the next block of the chain, as `Ledger::expand_block` does.
+    /// After an error, the outputs no longer follow the chain.
+    pub fn expand(&mut self, block: CompactBlock) -> Result<UnverifiedBlock, LedgerError> {
+        let mut transactions = vec![];
+        for (i, tx) in block.transactions().iter().enumerate() {
+            let spent = block.outpoints().get(i).map(Vec::as_slice).unwrap_or(&[]);
+            let inputs = spent
+                .iter()
+                .map(|outpoint| {
+                    self.outputs
+                        .get(outpoint)
+                        .cloned()
+                        .ok_or_else(|| LedgerError::UnknownOutPoint(outpoint.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            self.spend(spent);
+
+            let tx = UnverifiedTransaction::clone(tx).with_inputs(inputs);
+            for (outpoint, output) in tx.created_outputs() {
+                self.create(outpoint, output.clone());
+            }
+            transactions.push(Arc::new(tx));
+        }
+
+        Ok(block.into_block(transactions))
+    }
+
+    fn spend(&mut self, outpoints: &[OutPoint]) {
+        for outpoint in outpoints {
+            if let Some(output) = self.outputs.remove(outpoint) {
+                self.outpoints.remove(output.sign());
+            }
+        }
+    }
+
+    fn create(&mut self, outpoint: OutPoint, output: Transition<Yet>) {
+        self.outpoints
+            .insert(output.sign().clone(), outpoint.clone());
+        self.outputs.insert(outpoint, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Ledger;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    #[test]
+    fn test_unspent_outputs() {
+        let alice = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let output = Transfer::offer(&alice, alice.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let tip = mine(&mut ledger, vec![tx], &alice).unwrap();
+        let chain = ledger.downstream_chain_to(&tip).collect::<Vec<_>>();
+
+        let mut compactor = UnspentOutputs::new();
+        let compact = chain
+            .iter()
+            .map(|block| compactor.compact(block).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ledger.compact_block(chain[1]), Some(compact[1].clone()));
+
+        let mut expander = UnspentOutputs::new();
+        for (block, compact) in chain.iter().zip(compact.iter()) {
+            assert_eq!(Ok(block.to_unverified()), expander.expand(compact.clone()));
+        }
+
+        // Outputs spent are no longer expanded
+        assert!(matches!(
+            expander.expand(compact[1].clone()),
+            Err(LedgerError::UnknownOutPoint(_))
+        ));
+    }
+}
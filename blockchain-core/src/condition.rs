@@ -0,0 +1,219 @@
+use crate::account::Address;
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Blocks in which a timelocked condition may be met.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timelock {
+    /// Blocks below the height
+    Before(BlockHeight),
+    /// Blocks at or above the height
+    From(BlockHeight),
+}
+
+impl Timelock {
+    pub fn is_open_at(&self, height: BlockHeight) -> bool {
+        match *self {
+            Timelock::Before(timeout) => height < timeout,
+            Timelock::From(timeout) => height >= timeout,
+        }
+    }
+}
+
+/// Condition which a transaction must meet to spend an output.
+/// Every kind of output has one, so that all of them are checked alike.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpendCondition {
+    /// Sign of the key
+    Key(Address),
+    /// Signs of all the keys
+    Multisig(Vec<Address>),
+    /// Sign of the key, and a preimage of the hash lock
+    HashLock {
+        key: Address,
+        hash_lock: BlockDigest,
+    },
+    /// Condition which is met only within the timelock
+    Timelock(Timelock, Box<SpendCondition>),
+    /// Any one of the conditions
+    Any(Vec<SpendCondition>),
+}
+
+impl SpendCondition {
+    /// Check whether `spend` meets this.
+    /// When no condition of `Any` is met, the error is of the one which was met furthest.
+    pub fn check(&self, spend: &Spend<'_>) -> Result<(), SpendError> {
+        match self {
+            SpendCondition::Key(key) => spend.check_sign(key),
+            SpendCondition::Multisig(keys) => keys.iter().try_for_each(|key| spend.check_sign(key)),
+            SpendCondition::HashLock { key, hash_lock } => {
+                spend.check_sign(key)?;
+                let unlocked = spend
+                    .preimages
+                    .iter()
+                    .any(|preimage| &BlockDigest::digest(preimage) == hash_lock);
+                match unlocked {
+                    true => Ok(()),
+                    false => Err(SpendError::MissingPreimage),
+                }
+            }
+            SpendCondition::Timelock(timelock, condition) => {
+                condition.check(spend)?;
+                match spend.height {
+                    Some(height) if !timelock.is_open_at(height) => Err(SpendError::Timelock),
+                    _ => Ok(()),
+                }
+            }
+            SpendCondition::Any(conditions) => {
+                let mut furthest = SpendError::MissingSign;
+                for condition in conditions {
+                    match condition.check(spend) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => furthest = furthest.max(e),
+                    }
+                }
+                Err(furthest)
+            }
+        }
+    }
+
+    /// Whether `preimage` unlocks any hash lock of this.
+    pub fn is_unlocked_by(&self, preimage: &[u8]) -> bool {
+        match self {
+            SpendCondition::Key(_) | SpendCondition::Multisig(_) => false,
+            SpendCondition::HashLock { hash_lock, .. } => {
+                &BlockDigest::digest(preimage) == hash_lock
+            }
+            SpendCondition::Timelock(_, condition) => condition.is_unlocked_by(preimage),
+            SpendCondition::Any(conditions) => {
+                conditions.iter().any(|c| c.is_unlocked_by(preimage))
+            }
+        }
+    }
+
+    /// Whether `key` takes part in any way to meet this.
+    pub fn involves(&self, key: &Address) -> bool {
+        match self {
+            SpendCondition::Key(k) => k == key,
+            SpendCondition::Multisig(keys) => keys.contains(key),
+            SpendCondition::HashLock { key: k, .. } => k == key,
+            SpendCondition::Timelock(_, condition) => condition.involves(key),
+            SpendCondition::Any(conditions) => conditions.iter().any(|c| c.involves(key)),
+        }
+    }
+}
+
+/// What a transaction offers to meet spend conditions of its inputs.
+#[derive(Debug, Clone)]
+pub struct Spend<'a> {
+    pub signers: Vec<&'a Address>,
+    pub preimages: &'a [Vec<u8>],
+    /// Height of the block including the transaction. Timelocks are not checked if `None`.
+    pub height: Option<BlockHeight>,
+}
+
+impl Spend<'_> {
+    fn check_sign(&self, key: &Address) -> Result<(), SpendError> {
+        match self.signers.contains(&key) {
+            true => Ok(()),
+            false => Err(SpendError::MissingSign),
+        }
+    }
+}
+
+/// Why a spend condition is not met, ordered by how far the condition was met.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpendError {
+    MissingSign,
+    MissingPreimage,
+    /// Everything but the timelock is met.
+    Timelock,
+}
+
+impl Display for SpendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SpendError::MissingSign => write!(f, "Required sign is missing"),
+            SpendError::MissingPreimage => write!(f, "Hash lock is not unlocked"),
+            SpendError::Timelock => write!(f, "Spent outside the timelock"),
+        }
+    }
+}
+
+impl Error for SpendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::SecretAddress;
+
+    fn address() -> Address {
+        SecretAddress::create().to_public_address()
+    }
+
+    fn spend<'a>(signers: &[&'a Address], preimages: &'a [Vec<u8>], height: u64) -> Spend<'a> {
+        Spend {
+            signers: signers.to_vec(),
+            preimages,
+            height: Some(BlockHeight::from(height)),
+        }
+    }
+
+    #[test]
+    fn test_multisig_requires_all_keys() {
+        let (a, b) = (address(), address());
+        let condition = SpendCondition::Multisig(vec![a.clone(), b.clone()]);
+        assert_eq!(Ok(()), condition.check(&spend(&[&a, &b], &[], 0)));
+        assert_eq!(
+            Err(SpendError::MissingSign),
+            condition.check(&spend(&[&a], &[], 0))
+        );
+    }
+
+    #[test]
+    fn test_any_reports_furthest_error() {
+        let (receiver, sender) = (address(), address());
+        let preimage = b"secret".to_vec();
+        let timeout = BlockHeight::from(10);
+        let htlc = SpendCondition::Any(vec![
+            SpendCondition::Timelock(
+                Timelock::Before(timeout),
+                Box::new(SpendCondition::HashLock {
+                    key: receiver.clone(),
+                    hash_lock: BlockDigest::digest(&preimage),
+                }),
+            ),
+            SpendCondition::Timelock(
+                Timelock::From(timeout),
+                Box::new(SpendCondition::Key(sender.clone())),
+            ),
+        ]);
+
+        let preimages = [preimage];
+        assert_eq!(Ok(()), htlc.check(&spend(&[&receiver], &preimages, 9)));
+        assert_eq!(
+            Err(SpendError::Timelock),
+            htlc.check(&spend(&[&receiver], &preimages, 10))
+        );
+        assert_eq!(
+            Err(SpendError::MissingPreimage),
+            htlc.check(&spend(&[&receiver], &[], 9))
+        );
+        assert_eq!(Ok(()), htlc.check(&spend(&[&sender], &[], 10)));
+        assert_eq!(
+            Err(SpendError::Timelock),
+            htlc.check(&spend(&[&sender], &[], 9))
+        );
+
+        // Timelocks are left unchecked without height
+        let unchecked = Spend {
+            height: None,
+            ..spend(&[&sender], &[], 0)
+        };
+        assert_eq!(Ok(()), htlc.check(&unchecked));
+        assert!(htlc.involves(&receiver) && htlc.involves(&sender));
+        assert!(!htlc.involves(&address()));
+    }
+}
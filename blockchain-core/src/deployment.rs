@@ -0,0 +1,228 @@
+//! Soft-fork deployments, which miners activate by signalling readiness in block headers.
+//!
+//! The chain is split into windows of `DeploymentSchedule::window` blocks, and every block of a
+//! window shares one state for each deployment. A deployment starts at the first window from its
+//! start height. Once `threshold` blocks of one window signal its bit, it is locked in for the
+//! next window and active from the one after, so that lagging nodes have a window to upgrade.
+//! A deployment which is not locked in by its timeout fails, and its bit can be reused.
+
+use crate::block::BlockHeight;
+use crate::signature::SighashVersion;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// Rule which a deployment enforces once active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentRule {
+    /// Blocks and transactions must not use `SighashVersion::Legacy`.
+    RejectLegacySighash,
+}
+
+impl DeploymentRule {
+    /// Whether a block or transaction signed with `version` follows this rule.
+    pub fn allows(&self, version: SighashVersion) -> bool {
+        match self {
+            DeploymentRule::RejectLegacySighash => version != SighashVersion::Legacy,
+        }
+    }
+}
+
+impl Display for DeploymentRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentRule::RejectLegacySighash => write!(f, "Legacy sighash is rejected"),
+        }
+    }
+}
+
+/// Rule change which miners vote for by one bit of block headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: &'static str,
+    /// Bit of `BlockHeader::signals`, below 32
+    pub bit: u8,
+    /// Height from which the deployment starts at a window boundary
+    pub start: BlockHeight,
+    /// Height from which the deployment fails unless locked in
+    pub timeout: BlockHeight,
+    pub rule: DeploymentRule,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentState {
+    /// Before the start height
+    Defined,
+    /// Miners signal readiness
+    Started,
+    /// Enough miners signalled. The rule is active from the next window.
+    LockedIn,
+    /// The rule is enforced.
+    Active,
+    /// Timed out without being locked in
+    Failed,
+}
+
+impl Display for DeploymentState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            DeploymentState::Defined => "defined",
+            DeploymentState::Started => "started",
+            DeploymentState::LockedIn => "locked in",
+            DeploymentState::Active => "active",
+            DeploymentState::Failed => "failed",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+/// Deployments of a chain, with the window and threshold they share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeploymentSchedule {
+    /// Blocks per window
+    pub window: u64,
+    /// Signalling blocks in one window which lock a deployment in
+    pub threshold: u64,
+    pub deployments: &'static [Deployment],
+}
+
+impl DeploymentSchedule {
+    /// Schedule without deployments.
+    pub const NONE: DeploymentSchedule = DeploymentSchedule {
+        window: 144,
+        threshold: 108,
+        deployments: &[],
+    };
+
+    /// Deployments of the main chain.
+    pub const MAIN: DeploymentSchedule = DeploymentSchedule {
+        window: 144,
+        threshold: 108,
+        deployments: &[Deployment {
+            name: "reject-legacy-sighash",
+            bit: 0,
+            start: BlockHeight::genesis(),
+            timeout: BlockHeight::new(144 * 365),
+            rule: DeploymentRule::RejectLegacySighash,
+        }],
+    };
+
+    /// Index of the window which `height` belongs to.
+    pub fn window_of(&self, height: BlockHeight) -> u64 {
+        u64::from(height) / self.window
+    }
+
+    /// State of `deployment` for blocks of the window at `index`.
+    /// `signalling` counts blocks of an earlier window which signal the deployment,
+    /// and is asked only about windows in which it was started.
+    pub fn state(
+        &self,
+        deployment: &Deployment,
+        index: u64,
+        mut signalling: impl FnMut(u64) -> u64,
+    ) -> DeploymentState {
+        let mut state = DeploymentState::Defined;
+        for window in 1..=index {
+            state = self.next_state(deployment, state, window, || signalling(window - 1));
+            // Nothing changes after these, so the remaining windows need not be walked
+            if matches!(state, DeploymentState::Active | DeploymentState::Failed) {
+                break;
+            }
+        }
+        state
+    }
+
+    /// State of `deployment` for blocks of the window at `index`, which follows `state` of the
+    /// window before it. `signalling` counts blocks of the window before which signal the
+    /// deployment, and is asked only if it was started.
+    pub fn next_state(
+        &self,
+        deployment: &Deployment,
+        state: DeploymentState,
+        index: u64,
+        mut signalling: impl FnMut() -> u64,
+    ) -> DeploymentState {
+        let start = u64::from(deployment.start);
+        let timeout = u64::from(deployment.timeout);
+        let first = index.saturating_mul(self.window);
+        match state {
+            DeploymentState::Defined if first >= timeout => DeploymentState::Failed,
+            DeploymentState::Defined if first >= start => DeploymentState::Started,
+            DeploymentState::Started if signalling() >= self.threshold => DeploymentState::LockedIn,
+            DeploymentState::Started if first >= timeout => DeploymentState::Failed,
+            DeploymentState::LockedIn => DeploymentState::Active,
+            state => state,
+        }
+    }
+}
+
+impl Default for DeploymentSchedule {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Progress of a deployment at the tip of a chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentStatus {
+    pub name: String,
+    pub bit: u8,
+    /// State for the block following the tip
+    pub state: DeploymentState,
+    /// Blocks of the current window which signal the deployment so far
+    pub signalling: u64,
+    /// Blocks of the current window so far
+    pub elapsed: u64,
+    pub window: u64,
+    pub threshold: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: Deployment = Deployment {
+        name: "test",
+        bit: 1,
+        start: BlockHeight::new(10),
+        timeout: BlockHeight::new(40),
+        rule: DeploymentRule::RejectLegacySighash,
+    };
+
+    const SCHEDULE: DeploymentSchedule = DeploymentSchedule {
+        window: 10,
+        threshold: 8,
+        deployments: &[DEPLOYMENT],
+    };
+
+    #[test]
+    fn test_deployment_states() {
+        let states = |counts: &[u64]| {
+            (0..6)
+                .map(|index| SCHEDULE.state(&DEPLOYMENT, index, |w| counts[w as usize]))
+                .collect::<Vec<_>>()
+        };
+        use DeploymentState::*;
+
+        // Signals before the start do not count
+        assert_eq!(
+            vec![Defined, Started, Started, LockedIn, Active, Active],
+            states(&[10, 7, 8, 0, 0, 0])
+        );
+        assert_eq!(
+            vec![Defined, Started, Started, Started, Failed, Failed],
+            states(&[0, 7, 7, 7, 10, 10])
+        );
+        // Locking in within the last window before the timeout is in time
+        assert_eq!(
+            vec![Defined, Started, Started, Started, LockedIn, Active],
+            states(&[0, 0, 0, 8, 0, 0])
+        );
+    }
+
+    #[test]
+    fn test_rule_allows() {
+        let rule = DeploymentRule::RejectLegacySighash;
+        assert!(!rule.allows(SighashVersion::Legacy));
+        assert!(rule.allows(SighashVersion::CURRENT));
+    }
+}
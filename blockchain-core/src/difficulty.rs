@@ -1,6 +1,69 @@
 use super::digest::BlockDigest;
 use crate::signature::{SignatureBuilder, SignatureSource};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Add, AddAssign};
+
+/// Digests at or below a target meet it, comparing both as 256-bit big-endian integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target([u8; 32]);
+
+impl Target {
+    /// Target which every digest meets.
+    pub const MAX: Target = Target([u8::MAX; 32]);
+
+    pub const fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn to_be_bytes(self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Largest target whose digests start with `bits` zero bits.
+    pub fn from_leading_zeros(bits: u8) -> Self {
+        let mut bytes = [u8::MAX; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let zeros = usize::from(bits).saturating_sub(i * 8).min(8);
+            *byte = u8::MAX.checked_shr(zeros as u32).unwrap_or(0);
+        }
+        Self(bytes)
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_be_bytes(&self.0, &other.0)
+    }
+}
+
+/// Whether `digest` is at or below `target`.
+/// Takes the same time wherever the digest first differs from the target.
+pub fn meets_target(digest: &BlockDigest, target: &Target) -> bool {
+    let bytes = <[u8; 32]>::try_from(digest.as_ref()).expect("Digest has 32 bytes");
+    cmp_be_bytes(&bytes, &target.0) != Ordering::Greater
+}
+
+/// Compare big-endian integers, visiting every byte without branching on their values.
+fn cmp_be_bytes(lhs: &[u8; 32], rhs: &[u8; 32]) -> Ordering {
+    // From the least significant byte, so that more significant differences override
+    let mut sign = 0_i16;
+    for (&l, &r) in lhs.iter().zip(rhs.iter()).rev() {
+        let diff = i16::from(l) - i16::from(r);
+        // -1, 0 or 1 from the sign bits of -diff and diff
+        let byte_sign = ((-diff >> 15) & 1) - ((diff >> 15) & 1);
+        let differs = byte_sign & 1;
+        sign = byte_sign * differs + sign * (1 - differs);
+    }
+    sign.cmp(&0)
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Difficulty(u8);
@@ -11,61 +74,155 @@ impl Difficulty {
     }
 
     pub fn raise(self) -> Self {
-        Self(self.0.checked_add(1).unwrap_or(u8::MAX))
+        Self(self.0.saturating_add(1))
     }
 
     pub fn ease(self) -> Self {
-        Self(self.0.checked_sub(1).unwrap_or_default())
+        Self(self.0.saturating_sub(1))
+    }
+
+    /// Expected number of hashes to find a block, which saturates beyond `u128`.
+    pub fn work(&self) -> u128 {
+        work_from(self).to_u128().unwrap_or(u128::MAX)
+    }
+
+    /// Target of digests with at least this many leading zero bits.
+    pub fn target(&self) -> Target {
+        Target::from_leading_zeros(self.0)
     }
 
     pub fn verify_digest(&self, digest: &BlockDigest) -> bool {
         self.verify_bytes(digest.as_ref())
     }
 
+    /// Check leading zero bits of `bytes`. Bits beyond them count as ones.
     fn verify_bytes(&self, bytes: &[u8]) -> bool {
-        count_first_0_bits(bytes) >= self.0
+        let mut padded = [u8::MAX; 32];
+        let len = bytes.len().min(padded.len());
+        padded[..len].copy_from_slice(&bytes[..len]);
+        cmp_be_bytes(&padded, &self.target().0) != Ordering::Greater
     }
 }
 
-impl SignatureSource for Difficulty {
-    fn write_bytes(&self, builder: &mut SignatureBuilder) {
-        builder.write_bytes(&[self.0]);
+/// Sum of expected hashes over blocks, as a 256-bit unsigned integer.
+/// Compared numerically, so that the chain with the most work wins.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ChainWork([u64; 4]); // Most significant limb first
+
+impl ChainWork {
+    pub const ZERO: ChainWork = ChainWork([0; 4]);
+    pub const MAX: ChainWork = ChainWork([u64::MAX; 4]);
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let mut limbs = [0; 4];
+        let mut carry = 0_u128;
+        for i in (0..limbs.len()).rev() {
+            let sum = u128::from(self.0[i]) + u128::from(other.0[i]) + carry;
+            limbs[i] = sum as u64;
+            carry = sum >> 64;
+        }
+        (carry == 0).then_some(Self(limbs))
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or(Self::MAX)
+    }
+
+    /// Value if it fits in `u128`.
+    pub fn to_u128(self) -> Option<u128> {
+        match self.0 {
+            [0, 0, high, low] => Some(u128::from(high) << 64 | u128::from(low)),
+            _ => None,
+        }
+    }
+
+    fn div_rem(self, divisor: u64) -> (Self, u64) {
+        let mut limbs = [0; 4];
+        let mut rem = 0_u128;
+        for (quotient, &limb) in limbs.iter_mut().zip(self.0.iter()) {
+            let current = rem << 64 | u128::from(limb);
+            *quotient = (current / u128::from(divisor)) as u64;
+            rem = current % u128::from(divisor);
+        }
+        (Self(limbs), rem as u64)
     }
 }
 
-fn count_first_0_bits(bytes: &[u8]) -> u8 {
-    let mut count = 0;
+/// Expected number of hashes to find a block of `difficulty`.
+pub fn work_from(difficulty: &Difficulty) -> ChainWork {
+    let bits = usize::from(difficulty.0);
+    let mut limbs = [0; 4];
+    limbs[3 - bits / 64] = 1 << (bits % 64);
+    ChainWork(limbs)
+}
 
-    for &byte in bytes {
-        match count_first_0_bit(byte) {
-            8 => count += 8,
-            c => {
-                count += c;
+impl From<u128> for ChainWork {
+    fn from(work: u128) -> Self {
+        Self([0, 0, (work >> 64) as u64, work as u64])
+    }
+}
+
+impl Add for ChainWork {
+    type Output = ChainWork;
+
+    /// Saturates at `ChainWork::MAX`.
+    fn add(self, other: Self) -> Self {
+        self.saturating_add(other)
+    }
+}
+
+impl AddAssign for ChainWork {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Display for ChainWork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+
+        // Decimal digits in chunks of 19, least significant first
+        let mut chunks = vec![];
+        let mut rest = *self;
+        loop {
+            let (quotient, rem) = rest.div_rem(CHUNK);
+            chunks.push(rem);
+            if quotient == Self::ZERO {
                 break;
             }
+            rest = quotient;
         }
-    }
 
-    count
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:019}", chunk)?;
+        }
+        Ok(())
+    }
 }
 
-const fn count_first_0_bit(x: u8) -> u8 {
-    let mut count = 0;
-    let mut flag = 1 << (8 - 1);
-    while flag > 0 {
-        if x & flag == 0 {
-            count += 1;
-            flag >>= 1;
-        } else {
-            break;
-        }
+impl SignatureSource for Difficulty {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        builder.write_bytes(&[self.0]);
     }
-    count
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Difficulty;
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_work() {
+        assert_eq!(1, Difficulty(0).work());
+        assert_eq!(1024, Difficulty(10).work());
+        assert_eq!(u128::MAX, Difficulty(128).work());
+    }
 
     #[test]
     fn test_difficulty_zero() {
@@ -99,4 +256,94 @@ mod tests {
         assert!(!d.verify_bytes(&[0]));
         assert!(!d.verify_bytes(&[1]));
     }
+
+    #[test]
+    fn test_target_from_leading_zeros() {
+        assert_eq!(Target::MAX, Target::from_leading_zeros(0));
+
+        let mut expected = [u8::MAX; 32];
+        expected[0] = 0;
+        expected[1] = 0b0111_1111;
+        assert_eq!(expected, Target::from_leading_zeros(9).to_be_bytes());
+
+        let mut expected = [0; 32];
+        expected[31] = 1;
+        assert_eq!(expected, Target::from_leading_zeros(255).to_be_bytes());
+    }
+
+    #[test]
+    fn test_meets_target_boundary() {
+        let target = Target::from_leading_zeros(12);
+        let digest = BlockDigest::digest(b"boundary");
+        let as_target = |bytes: [u8; 32]| BlockDigest::from_str(&hex::encode(bytes)).unwrap();
+
+        // Equal to the target
+        let equal = as_target(target.to_be_bytes());
+        assert!(meets_target(&equal, &target));
+
+        // One above the target, carrying across bytes
+        let mut above = [0; 32];
+        above[1] = 0b0001_0000;
+        assert!(!meets_target(&as_target(above), &target));
+
+        // One below the target
+        let mut below = target.to_be_bytes();
+        below[31] -= 1;
+        assert!(meets_target(&as_target(below), &target));
+
+        // Only the most significant difference matters
+        let mut high = [0; 32];
+        high[0] = 1;
+        assert!(!meets_target(&as_target(high), &target));
+
+        assert!(meets_target(&digest, &Target::MAX));
+        assert!(!meets_target(&digest, &Target::from_be_bytes([0; 32])));
+    }
+
+    #[test]
+    fn test_target_order() {
+        assert!(Target::from_leading_zeros(1) < Target::from_leading_zeros(0));
+        assert!(Target::from_leading_zeros(200) < Target::from_leading_zeros(199));
+        assert_eq!(
+            Ordering::Equal,
+            Target::from_leading_zeros(7).cmp(&Target::from_leading_zeros(7))
+        );
+    }
+
+    #[test]
+    fn test_chain_work_from_difficulty() {
+        assert_eq!(ChainWork::from(1), work_from(&Difficulty(0)));
+        assert_eq!(ChainWork::from(1024), work_from(&Difficulty(10)));
+        assert_eq!(ChainWork([0, 1, 0, 0]), work_from(&Difficulty(128)));
+        assert_eq!(ChainWork([1 << 63, 0, 0, 0]), work_from(&Difficulty(255)));
+    }
+
+    #[test]
+    fn test_chain_work_add() {
+        let mut work = ChainWork::from(u128::MAX);
+        assert_eq!(Some(u128::MAX), work.to_u128());
+
+        // Carry into the upper half
+        work += ChainWork::from(1);
+        assert_eq!(ChainWork([0, 1, 0, 0]), work);
+        assert_eq!(None, work.to_u128());
+        assert!(ChainWork::from(u128::MAX) < work);
+
+        assert_eq!(None, ChainWork::MAX.checked_add(ChainWork::from(1)));
+        assert_eq!(ChainWork::MAX, ChainWork::MAX + work);
+    }
+
+    #[test]
+    fn test_chain_work_display() {
+        assert_eq!("0", ChainWork::ZERO.to_string());
+        assert_eq!("1024", ChainWork::from(1024).to_string());
+        assert_eq!(
+            u128::MAX.to_string(),
+            ChainWork::from(u128::MAX).to_string()
+        );
+        assert_eq!(
+            "340282366920938463463374607431768211456",
+            work_from(&Difficulty(128)).to_string()
+        );
+    }
 }
@@ -1,17 +1,130 @@
 use crate::signature::{SignatureBuilder, SignatureSource};
 use apply::{Also, Apply};
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use hex::FromHex;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Digest;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct BlockDigest(#[serde(with = "serde_arrays")] [u8; 32]);
+/// Hex characters displayed by `fmt_short`.
+const SHORT_HEX_LEN: usize = 8;
+
+/// First few hex characters of `value`, which is enough to distinguish it in logs.
+/// Shared by the `fmt_short` of digests and signatures.
+pub(crate) fn short_hex(value: &impl Display) -> String {
+    let mut s = value.to_string();
+    s.truncate(SHORT_HEX_LEN);
+    s
+}
+
+/// Hash function producing 32-byte digests.
+pub trait DigestAlgorithm {
+    /// Identifier of the algorithm in `ChainParams`
+    const ID: PowAlgorithm;
+
+    fn hash(input: &[u8]) -> [u8; 32];
+}
+
+pub struct Sha256;
+
+impl DigestAlgorithm for Sha256 {
+    const ID: PowAlgorithm = PowAlgorithm::Sha256;
+
+    fn hash(input: &[u8]) -> [u8; 32] {
+        sha2::Sha256::new()
+            .also(|hasher| hasher.update(input))
+            .apply(sha2::Sha256::finalize)
+            .into()
+    }
+}
+
+#[cfg(feature = "blake3")]
+pub struct Blake3;
+
+#[cfg(feature = "blake3")]
+impl DigestAlgorithm for Blake3 {
+    const ID: PowAlgorithm = PowAlgorithm::Blake3;
+
+    fn hash(input: &[u8]) -> [u8; 32] {
+        blake3::hash(input).into()
+    }
+}
+
+/// Hash function of block digests, and so of Proof-of-Work.
+/// Transaction IDs and Merkle roots are SHA-256 on every chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum PowAlgorithm {
+    /// Algorithm of the main chain
+    #[default]
+    Sha256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl PowAlgorithm {
+    pub fn digest(&self, input: &[u8]) -> BlockDigest {
+        match self {
+            PowAlgorithm::Sha256 => BlockDigest::digest_by::<Sha256>(input),
+            #[cfg(feature = "blake3")]
+            PowAlgorithm::Blake3 => BlockDigest::digest_by::<Blake3>(input),
+        }
+    }
+}
+
+impl Display for PowAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PowAlgorithm::Sha256 => "sha256",
+            #[cfg(feature = "blake3")]
+            PowAlgorithm::Blake3 => "blake3",
+        };
+        f.pad(name)
+    }
+}
+
+impl FromStr for PowAlgorithm {
+    type Err = UnknownPowAlgorithm;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(PowAlgorithm::Sha256),
+            #[cfg(feature = "blake3")]
+            "blake3" => Ok(PowAlgorithm::Blake3),
+            _ => Err(UnknownPowAlgorithm(s.to_string())),
+        }
+    }
+}
+
+/// Name of an algorithm which is unknown or not enabled by the features of this build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPowAlgorithm(pub String);
+
+impl Display for UnknownPowAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown or disabled Proof-of-Work algorithm {}", self.0)
+    }
+}
+
+impl Error for UnknownPowAlgorithm {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockDigest([u8; 32]);
 
 impl BlockDigest {
+    /// SHA-256 digest of `input`.
     pub fn digest(input: &[u8]) -> Self {
-        Sha256::new()
-            .also(|hasher| hasher.update(input))
-            .apply(Sha256::finalize)
-            .apply(|inner| Self(inner.into()))
+        Self::digest_by::<Sha256>(input)
+    }
+
+    pub fn digest_by<A: DigestAlgorithm>(input: &[u8]) -> Self {
+        Self(A::hash(input))
+    }
+
+    /// See `short_hex`.
+    pub fn fmt_short(&self) -> String {
+        short_hex(self)
     }
 }
 
@@ -27,6 +140,113 @@ impl SignatureSource for BlockDigest {
     }
 }
 
+impl Display for BlockDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        hex::encode(self.0).fmt(f)
+    }
+}
+
+impl FromStr for BlockDigest {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <[u8; 32]>::from_hex(s).map(Self)
+    }
+}
+
+/// Human-readable formats such as JSON get a hex string.
+/// Binary formats keep a fixed-length byte array.
+impl Serialize for BlockDigest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serde_arrays::serialize(&self.0, serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockDigest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct DigestVisitor;
+
+        impl<'de> Visitor<'de> for DigestVisitor {
+            type Value = BlockDigest;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "a 32-byte digest as a hex string or an array")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                BlockDigest::from_str(v).map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let bytes = serde_arrays::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                Ok(BlockDigest(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            // Also accept the array form, which was emitted by older versions
+            deserializer.deserialize_any(DigestVisitor)
+        } else {
+            deserializer.deserialize_tuple(32, DigestVisitor)
+        }
+    }
+}
+
+/// Root of the Merkle tree of transaction ids, which a block header commits to.
+/// Encoded the same as `BlockDigest`, but not interchangeable with block digests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MerkleRoot(BlockDigest);
+
+impl MerkleRoot {
+    pub fn new(digest: BlockDigest) -> Self {
+        Self(digest)
+    }
+
+    pub fn as_digest(&self) -> &BlockDigest {
+        &self.0
+    }
+
+    /// See `short_hex`.
+    pub fn fmt_short(&self) -> String {
+        short_hex(self)
+    }
+}
+
+impl AsRef<[u8]> for MerkleRoot {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl SignatureSource for MerkleRoot {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        self.0.write_bytes(builder);
+    }
+}
+
+impl Display for MerkleRoot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,4 +281,78 @@ mod tests {
 
         assert!(de.is_err());
     }
+
+    #[test]
+    fn test_serde_hex_string() {
+        let digest = BlockDigest::digest(&[42, 255, 0]);
+
+        let ser = serde_json::to_string(&digest).unwrap();
+
+        assert_eq!(format!("\"{}\"", digest), ser);
+    }
+
+    #[test]
+    fn test_merkle_root_encoded_as_digest() {
+        let digest = BlockDigest::digest(&[42, 255, 0]);
+        let root = MerkleRoot::new(digest.clone());
+
+        assert_eq!(
+            serde_json::to_string(&digest).unwrap(),
+            serde_json::to_string(&root).unwrap()
+        );
+        assert_eq!(
+            bincode::serialize(&digest).unwrap(),
+            bincode::serialize(&root).unwrap()
+        );
+        assert_eq!(digest.to_string(), root.to_string());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let digest = BlockDigest::digest(&[42, 255, 0]);
+
+        let s = digest.to_string();
+
+        assert_eq!(64, s.len());
+        assert_eq!(Ok(digest), BlockDigest::from_str(&s));
+    }
+
+    #[test]
+    fn test_from_str_invalid_length() {
+        let s = "00".repeat(31);
+
+        assert!(BlockDigest::from_str(&s).is_err());
+    }
+
+    #[test]
+    fn test_pow_algorithm_digest() {
+        let input = [42, 255, 0];
+        assert_eq!(
+            BlockDigest::digest(&input),
+            PowAlgorithm::Sha256.digest(&input)
+        );
+        assert_eq!(PowAlgorithm::Sha256, Sha256::ID);
+
+        assert_eq!("sha256", PowAlgorithm::Sha256.to_string());
+        assert_eq!(Ok(PowAlgorithm::Sha256), "sha256".parse());
+        assert!("md5".parse::<PowAlgorithm>().is_err());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_digest() {
+        let input = [42, 255, 0];
+        let digest = PowAlgorithm::Blake3.digest(&input);
+
+        assert_ne!(BlockDigest::digest(&input), digest);
+        assert_eq!(blake3::hash(&input).as_bytes(), digest.as_ref());
+        assert_eq!(Ok(PowAlgorithm::Blake3), "blake3".parse());
+    }
+
+    #[test]
+    fn test_fmt_short() {
+        let digest = BlockDigest::digest(&[42, 255, 0]);
+
+        assert_eq!(&digest.to_string()[..8], digest.fmt_short());
+    }
 }
@@ -1,38 +1,25 @@
 use crate::block::BlockError;
+use crate::clock::{Clock, SystemClock};
+use crate::compact::{CompactBlock, OutPoint};
+use crate::deployment::{Deployment, DeploymentRule, DeploymentState, DeploymentStatus};
+use crate::difficulty::{work_from, ChainWork};
 use crate::digest::BlockDigest;
+use crate::light::utxo_commitment;
+use crate::params::ChainParams;
+use crate::signature::Signature;
+use crate::snapshot::ChainSnapshot;
+use crate::timestamp::Timestamp;
 use crate::transition::Transition;
 use crate::verification::Verified;
-use crate::{Address, Block, Transaction, VerifiedBlock, Yet};
-use apply::Also;
+use crate::{Address, Block, BlockHeight, Coin, UnverifiedBlock, UnverifiedTransaction};
+use crate::{VerifiedBlock, VerifiedTransaction, Yet};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use slab_tree::{Ancestors, NodeId, NodeMut, NodeRef, RemoveBehavior, Tree};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
-use std::hash::Hash;
-
-/// Wrapper for implementation of Hash.
-/// Hasher uses sign of transition.
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct TransitionWrapper(Transition<Verified>);
-
-impl Hash for TransitionWrapper {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.sign().hash(state)
-    }
-}
-
-impl From<Transition<Verified>> for TransitionWrapper {
-    fn from(t: Transition<Verified>) -> Self {
-        Self(t)
-    }
-}
-
-impl AsRef<Transition<Verified>> for TransitionWrapper {
-    fn as_ref(&self) -> &Transition<Verified> {
-        &self.0
-    }
-}
+use std::sync::Arc;
 
 #[derive(Debug)]
 struct TransferHistory {
@@ -55,10 +42,19 @@ impl TransferHistory {
             .is_some()
     }
 
+    #[cfg(feature = "invariants")]
     fn push_block(&mut self, block: &VerifiedBlock) -> Result<(), TransferHistoryError> {
+        self.push_transactions(block.transactions())
+    }
+
+    fn push_transactions(
+        &mut self,
+        transactions: &[Arc<VerifiedTransaction>],
+    ) -> Result<(), TransferHistoryError> {
         // A block contains double-spending input?
-        if !block
-            .inputs()
+        if !transactions
+            .iter()
+            .flat_map(|tx| tx.inputs())
             .map(crate::transition::Transition::sign)
             .all_unique()
         {
@@ -68,7 +64,7 @@ impl TransferHistory {
         let mut next_utxos = self.utxos.clone();
 
         // Verify transactions in order of timestamp
-        for tx in block.transactions() {
+        for tx in transactions {
             for input in tx.inputs() {
                 match next_utxos.iter().find(|u| *u == input) {
                     Some(_) => next_utxos.retain(|u| u != input),
@@ -110,49 +106,263 @@ impl Display for TransferHistoryError {
 
 impl Error for TransferHistoryError {}
 
+/// Change of the UTXO set by a block, to disconnect the block without replaying its chain.
+/// Outputs both created and spent in the block are in neither list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockUndo {
+    /// Outputs of earlier blocks which the block spends, restored on disconnection
+    spent: Vec<Transition<Verified>>,
+    /// Outputs of the block left unspent, removed on disconnection
+    created: Vec<Transition<Verified>>,
+}
+
+impl BlockUndo {
+    pub fn new(block: &VerifiedBlock) -> Self {
+        let inputs = block.inputs().collect_vec();
+        let outputs = block.outputs().collect_vec();
+        let spent = inputs
+            .iter()
+            .filter(|input| !outputs.contains(input))
+            .map(|&input| input.clone())
+            .collect();
+        let created = outputs
+            .iter()
+            .filter(|output| !inputs.contains(output))
+            .map(|&output| output.clone())
+            .collect();
+        Self { spent, created }
+    }
+
+    pub fn spent(&self) -> &[Transition<Verified>] {
+        &self.spent
+    }
+
+    pub fn created(&self) -> &[Transition<Verified>] {
+        &self.created
+    }
+
+    /// Apply the block to the UTXO set of its previous block.
+    fn connect(&self, utxos: &mut Vec<Transition<Verified>>) {
+        utxos.retain(|utxo| !self.spent.contains(utxo));
+        utxos.extend(self.created.iter().cloned());
+    }
+
+    /// Revert the block from the UTXO set after it.
+    fn disconnect(&self, utxos: &mut Vec<Transition<Verified>>) {
+        utxos.retain(|utxo| !self.created.contains(utxo));
+        utxos.extend(self.spent.iter().cloned());
+    }
+}
+
+/// Number of blocks whose median timestamp is the median time past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far a block timestamp may be ahead of the local clock.
+pub const MAX_FUTURE_DRIFT_SECS: i64 = 2 * 60 * 60;
+
 /// Block tree ledger
 #[derive(Debug)]
 pub struct Ledger {
     block_tree: Tree<VerifiedBlock>,
     digest_map: HashMap<BlockDigest, NodeId>,
+    /// Each block refers to one far ancestor at `skip_height`, to find ancestors in O(log n).
+    skip_map: HashMap<NodeId, NodeId>,
+    /// Work from genesis to each block
+    work_map: HashMap<NodeId, ChainWork>,
+    /// Blocks by their timestamp, to query time ranges without walking the chain
+    time_index: BTreeMap<Timestamp, Vec<NodeId>>,
+    undo_map: HashMap<NodeId, BlockUndo>,
+    /// Deployment states for the window after the one which each block ends, in schedule order.
+    /// Each is derived from the states cached a window before, so no chain is replayed.
+    deployment_map: HashMap<NodeId, Vec<DeploymentState>>,
+    /// Blocks of every branch creating each outpoint, with the position of the transaction
+    outpoint_map: HashMap<OutPoint, Vec<(NodeId, usize)>>,
+    /// Outpoints of every branch by the sign of their output, to compact blocks without a walk
+    sign_map: HashMap<Signature, Vec<OutPoint>>,
+    /// UTXO set after the best tip, from which other blocks' sets are reached by undo records
+    utxo_cache: Option<(NodeId, Arc<Vec<Transition<Verified>>>)>,
+    /// Best chain handed out to readers, which is copied on write while they hold it
+    snapshot: Arc<ChainSnapshot>,
+    params: ChainParams,
+    /// Local time which block timestamps are checked against
+    clock: Arc<dyn Clock>,
 }
 
 impl Ledger {
-    /// Create empty ledger
+    /// Create empty ledger of the main chain
     pub fn new() -> Self {
+        Self::with_params(ChainParams::default())
+    }
+
+    /// Create empty ledger of the chain following `params`
+    pub fn with_params(params: ChainParams) -> Self {
         Self {
             block_tree: Tree::new(),
             digest_map: HashMap::new(),
+            skip_map: HashMap::new(),
+            work_map: HashMap::new(),
+            time_index: BTreeMap::new(),
+            undo_map: HashMap::new(),
+            deployment_map: HashMap::new(),
+            outpoint_map: HashMap::new(),
+            sign_map: HashMap::new(),
+            utxo_cache: None,
+            snapshot: Arc::default(),
+            params,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Check block timestamps against `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Rules which blocks in this ledger follow.
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Best chain at the current tip, which can be read after releasing the ledger.
+    pub fn snapshot(&self) -> Arc<ChainSnapshot> {
+        self.snapshot.clone()
+    }
+
     pub fn get(&self, digest: &BlockDigest) -> Option<&VerifiedBlock> {
         self.node_by_digest(digest).map(|node| node.data())
     }
 
     pub fn build_utxos(&self, digest: &BlockDigest, holder: &Address) -> Vec<Transition<Verified>> {
+        self.utxo_snapshot(digest)
+            .into_iter()
+            .filter(|utxo| utxo.receiver() == holder)
+            .collect()
+    }
+
+    /// All UTXOs after applying the chain up to the block of `digest`, served as a snapshot for fast sync.
+    pub fn utxo_snapshot(&self, digest: &BlockDigest) -> Vec<Transition<Verified>> {
+        match self.digest_map.get(digest) {
+            Some(&id) => self.utxos_at(id),
+            None => vec![],
+        }
+    }
+
+    /// Blocks to disconnect from `from` down to the fork, then to connect from the fork up to `to`,
+    /// which move a UTXO set after `from` to the one after `to`. `None` is before genesis.
+    /// Returns `None` if either block is unknown.
+    #[allow(clippy::type_complexity)]
+    pub fn fork_path(
+        &self,
+        from: Option<&BlockDigest>,
+        to: &BlockDigest,
+    ) -> Option<(Vec<&VerifiedBlock>, Vec<&VerifiedBlock>)> {
+        let from = match from {
+            Some(from) => Some(*self.digest_map.get(from)?),
+            None => None,
+        };
+        let to = *self.digest_map.get(to)?;
+        let (disconnected, connected) = self.fork_ids(from, to);
+        let block = |id: NodeId| self.block_tree.get(id).map(|node| node.data());
+        Some((
+            disconnected.into_iter().filter_map(block).collect(),
+            connected.into_iter().rev().filter_map(block).collect(),
+        ))
+    }
+
+    /// Record to disconnect the block of `digest` from the UTXO set.
+    pub fn undo_record(&self, digest: &BlockDigest) -> Option<&BlockUndo> {
+        self.digest_map
+            .get(digest)
+            .and_then(|id| self.undo_map.get(id))
+    }
+
+    /// UTXO commitment of a block which has `transactions` on the block of `previous_digest`,
+    /// or on nothing if `previous_digest` is `None`.
+    /// Returns `None` if the transactions spend something other than UTXOs.
+    pub fn utxo_commitment_after(
+        &self,
+        previous_digest: Option<&BlockDigest>,
+        transactions: &[Arc<VerifiedTransaction>],
+    ) -> Option<BlockDigest> {
         let mut transfer_history = TransferHistory::new();
+        transfer_history.utxos = match previous_digest {
+            Some(digest) => {
+                self.node_by_digest(digest)?;
+                self.utxo_snapshot(digest)
+            }
+            None => vec![],
+        };
+        transfer_history.push_transactions(transactions).ok()?;
+        Some(utxo_commitment(transfer_history.utxos()))
+    }
 
-        let blocks = self
-            .upstream_chain_from(digest)
-            .collect_vec()
-            .also(|blocks| blocks.reverse());
-        for block in blocks.into_iter() {
-            transfer_history.push_block(block).ok();
+    /// Replace inputs of `block` with the outpoints they spend, to relay or store it in less bytes.
+    /// Outpoints are searched in the chain up to the previous block and earlier transactions of `block`.
+    /// Returns `None` if any input is not found.
+    pub fn compact_block(&self, block: &VerifiedBlock) -> Option<CompactBlock> {
+        let previous = self.digest_map.get(block.previous_digest()).copied();
+
+        let mut created = HashMap::new();
+        let mut outpoints = vec![];
+        for tx in block.transactions() {
+            let spent = tx
+                .inputs()
+                .iter()
+                .map(|input| match created.get(input.sign()) {
+                    Some(outpoint) => Some(OutPoint::clone(outpoint)),
+                    None => self.outpoint_on_chain(input.sign(), previous?),
+                })
+                .collect::<Option<Vec<_>>>()?;
+            outpoints.push(spent);
+            created.extend(
+                tx.created_outputs()
+                    .map(|(outpoint, output)| (output.sign(), outpoint)),
+            );
         }
 
-        transfer_history
-            .utxos()
-            .filter(|utxo| utxo.receiver() == holder)
-            .cloned()
-            .collect()
+        Some(CompactBlock::new(block.to_unverified(), outpoints))
     }
 
+    /// Restore inputs of a compact block from the chain up to its previous block
+    /// and earlier transactions of the block itself.
+    /// The restored block still needs verification, whose digest check catches wrong outpoints.
+    pub fn expand_block(&self, block: CompactBlock) -> Result<UnverifiedBlock, LedgerError> {
+        let previous = self.digest_map.get(block.previous_digest()).copied();
+
+        let mut created = HashMap::new();
+        let mut transactions = vec![];
+        for (i, tx) in block.transactions().iter().enumerate() {
+            let outpoints = block.outpoints().get(i).map(Vec::as_slice).unwrap_or(&[]);
+            let inputs = outpoints
+                .iter()
+                .map(|outpoint| match created.get(outpoint) {
+                    Some(output) => Ok(Transition::clone(output)),
+                    None => previous
+                        .and_then(|previous| self.output_on_chain(outpoint, previous))
+                        .map(Transition::to_unverified)
+                        .ok_or_else(|| LedgerError::UnknownOutPoint(outpoint.clone())),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let tx = UnverifiedTransaction::clone(tx).with_inputs(inputs);
+            created.extend(
+                tx.created_outputs()
+                    .map(|(outpoint, output)| (outpoint, output.clone())),
+            );
+            transactions.push(Arc::new(tx));
+        }
+
+        Ok(block.into_block(transactions))
+    }
+
+    /// Tip of the chain with the most work. The higher one wins a tie.
     pub fn search_latest_block(&self) -> Option<&VerifiedBlock> {
         self.digest_map
             .values()
-            .map(|&id| self.block_tree.get(id).expect("Invalid id").data())
-            .max_by_key(|block| block.height())
+            .map(|&id| (id, self.block_tree.get(id).expect("Invalid id").data()))
+            .max_by_key(|(id, block)| (self.work_map.get(id).copied(), block.height()))
+            .map(|(_, block)| block)
     }
 
     pub fn upstream_chain_from(&self, digest: &BlockDigest) -> BlockchainUpstream<'_> {
@@ -162,17 +372,295 @@ impl Ledger {
         }
     }
 
+    /// Iterate blocks from genesis to the given block, without collecting the chain.
+    pub fn downstream_chain_to(&self, digest: &BlockDigest) -> BlockchainDownstream<'_> {
+        let tip = self
+            .node_by_digest(digest)
+            .map(|node| (node.node_id(), node.data().height()));
+        BlockchainDownstream {
+            ledger: self,
+            tip,
+            next_height: BlockHeight::genesis(),
+        }
+    }
+
     pub fn search_latest_chain(&self) -> BlockchainUpstream<'_> {
         self.search_latest_block()
             .map(|block| self.upstream_chain_from(block.digest()))
             .unwrap_or(BlockchainUpstream::Empty)
     }
 
+    /// All blocks of every branch, each after its parent.
+    pub fn blocks(&self) -> impl Iterator<Item = &VerifiedBlock> + '_ {
+        self.block_tree
+            .root()
+            .into_iter()
+            .flat_map(|root| root.traverse_pre_order())
+            .map(|node| node.data())
+    }
+
+    /// Blocks which no block follows. Each of them is the tip of a branch.
+    pub fn leaf_blocks(&self) -> impl Iterator<Item = &VerifiedBlock> + '_ {
+        self.block_tree
+            .root()
+            .into_iter()
+            .flat_map(|root| root.traverse_pre_order())
+            .filter(|node| node.first_child().is_none())
+            .map(|node| node.data())
+    }
+
+    /// Blocks of the best chain whose timestamp is in `[from, to)`, in the order of timestamp.
+    /// Block timestamps need not increase along the chain, so this may differ from height order.
+    pub fn blocks_between(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> impl Iterator<Item = &VerifiedBlock> + '_ {
+        let tip = self
+            .search_latest_block()
+            .and_then(|block| self.digest_map.get(block.digest()))
+            .copied();
+        let range = match tip {
+            Some(_) if from < to => Some(self.time_index.range(from..to)),
+            _ => None,
+        };
+        range
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .filter_map(move |id| {
+                let block = self.block_tree.get(id)?.data();
+                let tip = tip?;
+                (self.ancestor_id(tip, block.height()) == Some(id)).then_some(block)
+            })
+    }
+
+    /// Transactions of the blocks given by `blocks_between`, with the block confirming each.
+    pub fn transactions_between(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> impl Iterator<Item = (&VerifiedBlock, &VerifiedTransaction)> + '_ {
+        self.blocks_between(from, to).flat_map(|block| {
+            block
+                .transactions()
+                .iter()
+                .map(move |tx| (block, tx.as_ref()))
+        })
+    }
+
+    /// Sum of expected hashes from genesis to the given block.
+    pub fn cumulative_work(&self, digest: &BlockDigest) -> ChainWork {
+        self.digest_map
+            .get(digest)
+            .and_then(|id| self.work_map.get(id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Median timestamp of the latest `MEDIAN_TIME_SPAN` blocks up to the given block.
+    pub fn median_time_past(&self, digest: &BlockDigest) -> Option<Timestamp> {
+        let timestamps = self
+            .upstream_chain_from(digest)
+            .take(MEDIAN_TIME_SPAN)
+            .map(Block::timestamp)
+            .sorted()
+            .collect_vec();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    /// Sum coins minted from genesis to the given block.
+    /// Each block must not mint more than the generation rule allows at its height.
+    /// Fees are moved between holders, so they are not counted as minted coins.
+    pub fn total_supply_at(&self, digest: &BlockDigest) -> Result<Coin, SupplyError> {
+        if self.node_by_digest(digest).is_none() {
+            return Err(SupplyError::UnknownBlock);
+        }
+
+        let mut total = Coin::default();
+        for block in self.upstream_chain_from(digest) {
+            let in_qty = block
+                .inputs()
+                .map(Transition::quantity)
+                .try_fold(Coin::default(), Coin::checked_add)
+                .ok_or(SupplyError::Overflow)?;
+            let o_qty = block
+                .outputs()
+                .map(Transition::quantity)
+                .try_fold(Coin::default(), Coin::checked_add)
+                .ok_or(SupplyError::Overflow)?;
+            let minted = o_qty.checked_sub(in_qty).unwrap_or_default();
+            let allowed = self.params.generation(block.height());
+
+            if minted > allowed {
+                return Err(SupplyError::ExcessEmission {
+                    height: block.height(),
+                    minted,
+                    allowed,
+                });
+            }
+
+            total = total.checked_add(minted).ok_or(SupplyError::Overflow)?;
+        }
+
+        Ok(total)
+    }
+
+    /// State of `deployment` for a block on `previous`, or for a genesis block if `None`.
+    pub fn deployment_state(
+        &self,
+        deployment: &Deployment,
+        previous: Option<&BlockDigest>,
+    ) -> DeploymentState {
+        let schedule = self.params.deployments();
+        let previous = previous.and_then(|digest| self.node_by_digest(digest));
+        let height = match &previous {
+            Some(node) => node.data().height().next(),
+            None => BlockHeight::genesis(),
+        };
+        let position = schedule.deployments.iter().position(|d| d == deployment);
+        let cached = previous
+            .as_ref()
+            .zip(position)
+            .and_then(|(node, position)| {
+                let boundary = self.window_boundary(node.node_id(), height)?;
+                Some(self.deployment_map.get(&boundary)?[position])
+            });
+        if let Some(state) = cached {
+            return state;
+        }
+        // Only the first window and deployments out of the schedule have no cached state
+        schedule.state(
+            deployment,
+            schedule.window_of(height),
+            |window| match &previous {
+                Some(node) => self.count_signalling(node.node_id(), deployment.bit, window),
+                None => 0,
+            },
+        )
+    }
+
+    /// Deployment bits which a block on `previous` should signal.
+    pub fn signals_for_next(&self, previous: Option<&BlockDigest>) -> u32 {
+        self.params
+            .deployments()
+            .deployments
+            .iter()
+            .filter(|deployment| {
+                matches!(
+                    self.deployment_state(deployment, previous),
+                    DeploymentState::Started | DeploymentState::LockedIn
+                )
+            })
+            .fold(0, |signals, deployment| signals | 1 << deployment.bit)
+    }
+
+    /// Rules which a block on `previous` and its transactions must follow.
+    pub fn active_rules(&self, previous: Option<&BlockDigest>) -> Vec<DeploymentRule> {
+        self.params
+            .deployments()
+            .deployments
+            .iter()
+            .filter(|deployment| {
+                self.deployment_state(deployment, previous) == DeploymentState::Active
+            })
+            .map(|deployment| deployment.rule)
+            .collect()
+    }
+
+    /// Progress of every deployment on the best chain.
+    pub fn deployment_statuses(&self) -> Vec<DeploymentStatus> {
+        let schedule = self.params.deployments();
+        let tip = self.search_latest_block();
+        let height = tip.map_or(BlockHeight::genesis(), |tip| tip.height().next());
+        let elapsed = u64::from(height) % schedule.window;
+        let window = tip
+            .map(|tip| {
+                self.upstream_chain_from(tip.digest())
+                    .take(elapsed as usize)
+                    .collect_vec()
+            })
+            .unwrap_or_default();
+
+        schedule
+            .deployments
+            .iter()
+            .map(|deployment| DeploymentStatus {
+                name: deployment.name.to_string(),
+                bit: deployment.bit,
+                state: self.deployment_state(deployment, tip.map(Block::digest)),
+                signalling: window
+                    .iter()
+                    .filter(|block| block.header().signals_bit(deployment.bit))
+                    .count() as u64,
+                elapsed,
+                window: schedule.window,
+                threshold: schedule.threshold,
+            })
+            .collect()
+    }
+
+    /// Last block of the window before the one of `height`, on the chain up to `id`.
+    fn window_boundary(&self, id: NodeId, height: BlockHeight) -> Option<NodeId> {
+        let schedule = self.params.deployments();
+        let first = schedule.window_of(height).checked_mul(schedule.window)?;
+        self.ancestor_id(id, BlockHeight::new(first.checked_sub(1)?))
+    }
+
+    /// Cache the deployment states for the window after `id` if it ends its window.
+    fn cache_deployment_states(&mut self, id: NodeId, height: BlockHeight) {
+        let schedule = self.params.deployments();
+        if (u64::from(height) + 1) % schedule.window != 0 {
+            return;
+        }
+        let index = schedule.window_of(height);
+        let previous = self
+            .block_tree
+            .get(id)
+            .map(|node| node.data().previous_digest().clone());
+        let states = schedule
+            .deployments
+            .iter()
+            .map(|deployment| {
+                // Genesis refers to no block of the ledger, as if `None`
+                let state = self.deployment_state(deployment, previous.as_ref());
+                schedule.next_state(deployment, state, index + 1, || {
+                    self.count_signalling(id, deployment.bit, index)
+                })
+            })
+            .collect();
+        self.deployment_map.insert(id, states);
+    }
+
+    /// Blocks of the window at `index` which signal `bit`, on the chain up to `tip`.
+    /// `tip` must be at or above the last block of the window.
+    fn count_signalling(&self, tip: NodeId, bit: u8, index: u64) -> u64 {
+        let size = self.params.deployments().window;
+        let last = BlockHeight::new((index + 1) * size - 1);
+        let last = match self
+            .ancestor_id(tip, last)
+            .and_then(|id| self.block_tree.get(id))
+        {
+            Some(node) => node.data().digest(),
+            None => return 0,
+        };
+        self.upstream_chain_from(last)
+            .take(size as usize)
+            .filter(|block| block.header().signals_bit(bit))
+            .count() as u64
+    }
+
     /// Verify block UTXO and digest chain
     pub fn verify_block(
         &self,
         block: Block<Verified, Verified, Yet, Yet, Verified, Verified>,
     ) -> Result<VerifiedBlock, LedgerError> {
+        // Deny blocks from the future, which would keep their branch ahead of honest miners
+        let limit = self.clock.now().checked_add_secs(MAX_FUTURE_DRIFT_SECS);
+        if limit.is_some_and(|limit| block.timestamp() > limit) {
+            return Err(LedgerError::FutureBlock);
+        }
+
         let previous_block = self.node_by_digest(block.previous_digest());
 
         // Verify previous block info
@@ -185,37 +673,73 @@ impl Ledger {
             None => previous_block.is_none(),
         })?;
 
-        // Build transfer history fron genesis to previous block
-        let transfer_history = {
-            let blocks = match previous_block {
-                Some(block) => self
-                    .upstream_chain_from(block.data().digest())
-                    .collect_vec()
-                    .also(|blocks| blocks.reverse()),
-                None => vec![],
-            };
+        // UTXO set after the previous block, whose chain has been verified on entry
+        let transfer_history = TransferHistory {
+            utxos: previous_block
+                .as_ref()
+                .map(|block| self.utxos_at(block.node_id()))
+                .unwrap_or_default(),
+        };
 
-            let mut transfer_history = TransferHistory::new();
-            // Append blocks from genesis to last-verified block into history
-            for block in blocks.into_iter() {
-                if let Err(e) = transfer_history.push_block(&block) {
-                    return Err(LedgerError::Transfer(e));
-                }
+        // Inputs must meet their spend conditions, timelocks included, at the block height
+        let height = block.height();
+        if block
+            .transactions()
+            .iter()
+            .any(|tx| tx.check_spend_conditions(Some(height)).is_err())
+        {
+            return Err(LedgerError::Timelock);
+        }
+
+        // Transactions must wait for their lock time.
+        // Median time past cannot be pushed ahead by the miner of this block.
+        let time = self
+            .median_time_past(block.previous_digest())
+            .unwrap_or(block.timestamp());
+        if !block
+            .transactions()
+            .iter()
+            .all(|tx| tx.is_final(height, time))
+        {
+            return Err(LedgerError::LockTime);
+        }
+
+        // Blocks and transactions must follow the rules of active deployments
+        let previous = previous_block.as_ref().map(|node| node.data().digest());
+        for rule in self.active_rules(previous) {
+            let versions = std::iter::once(block.version())
+                .chain(block.transactions().iter().map(|tx| tx.version()));
+            if !versions.into_iter().all(|version| rule.allows(version)) {
+                return Err(LedgerError::Deployment(rule));
             }
-            transfer_history
+        }
+
+        // Applying the block must not spend an output twice, even across its transactions
+        let mut next_history = TransferHistory {
+            utxos: transfer_history.utxos.clone(),
         };
+        next_history
+            .push_transactions(block.transactions())
+            .map_err(LedgerError::Transfer)?;
+
+        // Committed UTXO set must match the one after applying the block
+        if let Some(commitment) = block.utxo_commitment() {
+            if &utxo_commitment(next_history.utxos()) != commitment {
+                return Err(LedgerError::UtxoCommitment);
+            }
+        }
 
         // Verify transaction
         let block = block.verify_utxo(|transactions| {
             // All transaction inputs must be UTXO
             let cond_in = transactions
                 .iter()
-                .flat_map(Transaction::inputs)
+                .flat_map(|tx| tx.inputs())
                 .all(|i| transfer_history.is_utxo(i));
             // All transaction outputs must not be UTXO
             let cond_out = transactions
                 .iter()
-                .flat_map(Transaction::outputs)
+                .flat_map(|tx| tx.outputs())
                 .all(|o| !transfer_history.is_utxo(o));
 
             cond_in && cond_out
@@ -243,17 +767,45 @@ impl Ledger {
                     return Err(LedgerError::DuplicatedBlock);
                 }
                 //
+                let previous_id = previous_node.node_id();
+                let skip_height = skip_height(block.height());
                 let digest = block.digest().clone();
+                let work = work_from(block.difficulty());
+                let timestamp = block.timestamp();
+                let undo = BlockUndo::new(&block);
                 let id = previous_node.append(block).node_id();
                 self.digest_map.insert(digest, id);
+                self.time_index.entry(timestamp).or_default().push(id);
+                self.undo_map.insert(id, undo);
+                let previous_work = self.work_map.get(&previous_id).copied().unwrap_or_default();
+                self.work_map.insert(id, previous_work + work);
+                if let Some(skip_id) = self.ancestor_id(previous_id, skip_height) {
+                    self.skip_map.insert(id, skip_id);
+                }
+                self.index_outputs(id);
+                self.cache_deployment_states(id, previous_height.next());
+                self.follow_best_tip();
+                #[cfg(feature = "invariants")]
+                self.debug_assert_invariants();
                 Ok(())
             }
             // Given block is genesis block
             None => {
                 if self.block_tree.root().is_none() {
                     let digest = block.digest().clone();
+                    let work = work_from(block.difficulty());
+                    let timestamp = block.timestamp();
+                    let undo = BlockUndo::new(&block);
                     let id = self.block_tree.set_root(block);
                     self.digest_map.insert(digest, id);
+                    self.time_index.entry(timestamp).or_default().push(id);
+                    self.work_map.insert(id, work);
+                    self.undo_map.insert(id, undo);
+                    self.index_outputs(id);
+                    self.cache_deployment_states(id, BlockHeight::genesis());
+                    self.follow_best_tip();
+                    #[cfg(feature = "invariants")]
+                    self.debug_assert_invariants();
                     Ok(())
                 } else {
                     Err(LedgerError::DuplicatedGenesisBlock)
@@ -263,9 +815,528 @@ impl Ledger {
     }
 
     pub fn remove_branch(&mut self, digest: &BlockDigest) -> Option<VerifiedBlock> {
-        self.digest_map
-            .get(digest)
-            .and_then(|&id| self.block_tree.remove(id, RemoveBehavior::DropChildren))
+        // Forget digests of the block and all its descendants
+        let removed = self
+            .node_by_digest(digest)?
+            .traverse_pre_order()
+            .map(|node| {
+                let block = node.data();
+                (block.digest().clone(), block.timestamp(), node.node_id())
+            })
+            .collect_vec();
+        let id = self.digest_map.get(digest).copied()?;
+
+        // Rewind the UTXO cache out of the branch while its undo records remain
+        let cache_removed = self
+            .utxo_cache
+            .as_ref()
+            .is_some_and(|(cache_id, _)| removed.iter().any(|(_, _, id)| id == cache_id));
+        if cache_removed {
+            let parent = self
+                .block_tree
+                .get(id)
+                .and_then(|node| node.parent().map(|parent| parent.node_id()));
+            self.utxo_cache = parent.map(|parent| (parent, Arc::new(self.utxos_at(parent))));
+        }
+
+        for (removed_digest, timestamp, removed_id) in removed.iter() {
+            self.digest_map.remove(removed_digest);
+            self.skip_map.remove(removed_id);
+            self.work_map.remove(removed_id);
+            self.undo_map.remove(removed_id);
+            self.deployment_map.remove(removed_id);
+            self.unindex_outputs(*removed_id);
+            if let Some(ids) = self.time_index.get_mut(timestamp) {
+                ids.retain(|id| id != removed_id);
+                if ids.is_empty() {
+                    self.time_index.remove(timestamp);
+                }
+            }
+        }
+
+        let removed = self.block_tree.remove(id, RemoveBehavior::DropChildren);
+        self.follow_best_tip();
+        #[cfg(feature = "invariants")]
+        self.debug_assert_invariants();
+        removed
+    }
+
+    /// Remove branches off the best chain whose tips are all more than `min_depth_behind` blocks
+    /// below the best tip. Returns the number of removed blocks.
+    pub fn prune_stale_branches(&mut self, min_depth_behind: u64) -> usize {
+        let tip_id = match self.search_latest_block() {
+            Some(tip) => self.digest_map[tip.digest()],
+            None => return 0,
+        };
+        let tip_height = u64::from(
+            self.block_tree
+                .get(tip_id)
+                .expect("Invalid id")
+                .data()
+                .height(),
+        );
+        let on_best_chain = |id: NodeId| {
+            let height = self.block_tree.get(id).expect("Invalid id").data().height();
+            self.ancestor_id(tip_id, height) == Some(id)
+        };
+        let parent_off_best_chain = |id: NodeId| {
+            let parent = self
+                .block_tree
+                .get(id)?
+                .parent()
+                .map(|node| node.node_id())?;
+            (!on_best_chain(parent)).then_some(parent)
+        };
+
+        // First block off the best chain of each branch, with the height of its highest tip
+        let mut branches = HashMap::<NodeId, (BlockDigest, u64)>::new();
+        let leaves = self
+            .block_tree
+            .root()
+            .into_iter()
+            .flat_map(|root| root.traverse_pre_order())
+            .filter(|node| node.first_child().is_none() && node.node_id() != tip_id);
+        for leaf in leaves {
+            let leaf_height = u64::from(leaf.data().height());
+            let mut root = leaf.node_id();
+            while let Some(parent) = parent_off_best_chain(root) {
+                root = parent;
+            }
+            let (_, highest) = branches.entry(root).or_insert_with(|| {
+                let block = self.block_tree.get(root).expect("Invalid id").data();
+                (block.digest().clone(), leaf_height)
+            });
+            *highest = leaf_height.max(*highest);
+        }
+
+        let mut pruned = 0;
+        for (root, (digest, highest)) in branches {
+            if tip_height.saturating_sub(highest) > min_depth_behind {
+                let root = self.block_tree.get(root).expect("Invalid id");
+                pruned += root.traverse_pre_order().count();
+                self.remove_branch(&digest);
+            }
+        }
+        pruned
+    }
+
+    /// Panic if the block tree and digest map are inconsistent,
+    /// or if any branch spends coins which do not exist.
+    #[cfg(feature = "invariants")]
+    pub fn debug_assert_invariants(&self) {
+        let root = match self.block_tree.root() {
+            Some(root) => root,
+            None => {
+                assert!(self.digest_map.is_empty(), "Digest map outlives block tree");
+                return;
+            }
+        };
+        assert!(root.data().height().is_genesis(), "Root is not genesis");
+
+        let window = self.params.deployments().window;
+        let mut node_count = 0;
+        let mut window_end_count = 0;
+        let mut output_count = 0;
+        for node in root.traverse_pre_order() {
+            node_count += 1;
+            let block = node.data();
+
+            // Deployment states are cached at the last block of each window, and agree with
+            // replaying every window
+            if (u64::from(block.height()) + 1) % window == 0 {
+                window_end_count += 1;
+                let states = self
+                    .deployment_map
+                    .get(&node.node_id())
+                    .expect("Missing deployment states");
+                let index = u64::from(block.height()) / window + 1;
+                for (deployment, state) in self.params.deployments().deployments.iter().zip(states)
+                {
+                    let replayed = self.params.deployments().state(deployment, index, |w| {
+                        self.count_signalling(node.node_id(), deployment.bit, w)
+                    });
+                    assert_eq!(
+                        &replayed,
+                        state,
+                        "Deployment state after block {} is stale",
+                        block.digest()
+                    );
+                }
+            }
+
+            // Outpoint and sign maps refer to outputs of this node
+            for (outpoint, sign, position) in created_outpoints(block) {
+                assert!(
+                    self.outpoint_map
+                        .get(&outpoint)
+                        .is_some_and(|blocks| blocks.contains(&(node.node_id(), position))),
+                    "Outpoint map misses output {} of block {}",
+                    outpoint,
+                    block.digest()
+                );
+                assert!(
+                    self.sign_map
+                        .get(&sign)
+                        .is_some_and(|outpoints| outpoints.contains(&outpoint)),
+                    "Sign map misses output {} of block {}",
+                    outpoint,
+                    block.digest()
+                );
+                output_count += 1;
+            }
+
+            // Digest map points to this node
+            assert_eq!(
+                Some(&node.node_id()),
+                self.digest_map.get(block.digest()),
+                "Digest map is inconsistent at block {}",
+                block.digest()
+            );
+
+            // Time index refers to this node
+            assert!(
+                self.time_index
+                    .get(&block.timestamp())
+                    .is_some_and(|ids| ids.contains(&node.node_id())),
+                "Time index misses block {}",
+                block.digest()
+            );
+
+            // Parent/child relationship
+            for child in node.children() {
+                let child = child.data();
+                assert_eq!(
+                    Some(block.height()),
+                    child.height().previous(),
+                    "Child height does not follow its parent"
+                );
+                assert_eq!(
+                    block.digest(),
+                    child.previous_digest(),
+                    "Child does not refer to its parent"
+                );
+            }
+
+            // Skip pointer refers to the ancestor at skip height
+            if let Some(&skip_id) = self.skip_map.get(&node.node_id()) {
+                let skip = self.block_tree.get(skip_id).expect("Dangling skip pointer");
+                assert_eq!(
+                    skip_height(block.height()),
+                    skip.data().height(),
+                    "Skip pointer of block {} has a wrong height",
+                    block.digest()
+                );
+                assert!(
+                    node.ancestors().any(|a| a.node_id() == skip_id),
+                    "Skip pointer of block {} is not its ancestor",
+                    block.digest()
+                );
+            }
+
+            // UTXO non-negativity of the branch ending at leaf
+            if node.first_child().is_none() {
+                let mut transfer_history = TransferHistory::new();
+                for block in self.downstream_chain_to(block.digest()) {
+                    if let Err(e) = transfer_history.push_block(block) {
+                        panic!("Block {} breaks UTXO set: {}", block.height(), e);
+                    }
+                    let in_qty = block.inputs().map(Transition::quantity).sum::<Coin>();
+                    let o_qty = block.outputs().map(Transition::quantity).sum::<Coin>();
+                    assert!(
+                        in_qty <= o_qty,
+                        "Block {} spends more than it has",
+                        block.height()
+                    );
+                }
+            }
+        }
+
+        assert_eq!(
+            node_count,
+            self.digest_map.len(),
+            "Digest map contains removed blocks"
+        );
+        assert_eq!(
+            node_count.saturating_sub(1),
+            self.skip_map.len(),
+            "Skip map does not cover all non-genesis blocks"
+        );
+        assert_eq!(
+            node_count,
+            self.work_map.len(),
+            "Work map does not cover all blocks"
+        );
+        assert_eq!(
+            node_count,
+            self.time_index.values().map(Vec::len).sum::<usize>(),
+            "Time index contains removed blocks"
+        );
+        assert_eq!(
+            node_count,
+            self.undo_map.len(),
+            "Undo map does not cover all blocks"
+        );
+        assert_eq!(
+            window_end_count,
+            self.deployment_map.len(),
+            "Deployment map contains removed blocks"
+        );
+        assert_eq!(
+            output_count,
+            self.outpoint_map.values().map(Vec::len).sum::<usize>(),
+            "Outpoint map contains removed outputs"
+        );
+        assert_eq!(
+            self.outpoint_map.len(),
+            self.sign_map.values().map(Vec::len).sum::<usize>(),
+            "Sign map contains removed outputs"
+        );
+
+        // UTXO cache is at the best tip and agrees with replaying its chain
+        let (cache_id, cache) = self.utxo_cache.as_ref().expect("Missing UTXO cache");
+        let tip = self.search_latest_block().expect("Missing best tip");
+        assert_eq!(
+            Some(cache_id),
+            self.digest_map.get(tip.digest()),
+            "UTXO cache is not at the best tip"
+        );
+        let mut transfer_history = TransferHistory::new();
+        for block in self.downstream_chain_to(tip.digest()) {
+            transfer_history.push_block(block).ok();
+        }
+        assert_eq!(
+            utxo_commitment(transfer_history.utxos()),
+            utxo_commitment(cache.iter()),
+            "UTXO cache differs from its chain"
+        );
+
+        // Snapshot is the best chain
+        assert!(
+            self.snapshot
+                .blocks()
+                .rev()
+                .map(Block::digest)
+                .eq(self.upstream_chain_from(tip.digest()).map(Block::digest)),
+            "Snapshot differs from the best chain"
+        );
+    }
+
+    /// UTXO set after the node, reached from the cached set by undo records along the fork,
+    /// or by connecting blocks from genesis if that is shorter.
+    fn utxos_at(&self, id: NodeId) -> Vec<Transition<Verified>> {
+        let (from, mut utxos) = match &self.utxo_cache {
+            Some((cache_id, utxos)) => (Some(*cache_id), Vec::clone(utxos)),
+            None => (None, vec![]),
+        };
+        let (mut disconnected, mut connected) = self.fork_ids(from, id);
+
+        // Starting over from genesis is cheaper than undoing a long way
+        let from_genesis = self
+            .block_tree
+            .get(id)
+            .map_or(0, |node| u64::from(node.data().height()) as usize + 1);
+        if disconnected.len() + connected.len() > from_genesis {
+            utxos.clear();
+            disconnected.clear();
+            connected = self
+                .block_tree
+                .get(id)
+                .into_iter()
+                .flat_map(|node| node.ancestors())
+                .map(|node| node.node_id())
+                .collect();
+            connected.insert(0, id);
+        }
+
+        for id in disconnected {
+            self.undo_map[&id].disconnect(&mut utxos);
+        }
+        for id in connected.into_iter().rev() {
+            self.undo_map[&id].connect(&mut utxos);
+        }
+        utxos
+    }
+
+    /// Nodes from `from` up to the fork, and from `to` up to the fork, each excluding the fork.
+    /// `from` of `None` is before genesis.
+    fn fork_ids(&self, from: Option<NodeId>, to: NodeId) -> (Vec<NodeId>, Vec<NodeId>) {
+        let height = |id: NodeId| self.block_tree.get(id).map(|node| node.data().height());
+        let parent = |id: NodeId| self.block_tree.get(id)?.parent().map(|node| node.node_id());
+
+        let mut from = from;
+        let mut to = Some(to);
+        let mut disconnected = vec![];
+        let mut connected = vec![];
+        // Walk up both sides until they meet at the fork
+        while from != to {
+            match (from, to) {
+                (Some(f), Some(t)) if height(f) >= height(t) => {
+                    disconnected.push(f);
+                    from = parent(f);
+                }
+                (_, Some(t)) => {
+                    connected.push(t);
+                    to = parent(t);
+                }
+                (Some(f), None) => {
+                    disconnected.push(f);
+                    from = parent(f);
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+        (disconnected, connected)
+    }
+
+    /// Move the UTXO cache to the best tip.
+    fn follow_best_tip(&mut self) {
+        let tip = self
+            .search_latest_block()
+            .and_then(|block| self.digest_map.get(block.digest()))
+            .copied();
+        match tip {
+            Some(tip) if self.utxo_cache.as_ref().map(|(id, _)| *id) != Some(tip) => {
+                self.utxo_cache = Some((tip, Arc::new(self.utxos_at(tip))));
+            }
+            Some(_) => {}
+            None => self.utxo_cache = None,
+        }
+        self.follow_snapshot(tip);
+    }
+
+    /// Move the snapshot to `tip`, replacing only its blocks above the fork.
+    fn follow_snapshot(&mut self, tip: Option<NodeId>) {
+        let tip = match tip.and_then(|tip| self.block_tree.get(tip)) {
+            Some(tip) => tip,
+            None => {
+                self.snapshot = Arc::default();
+                return;
+            }
+        };
+        if self.snapshot.tip().map(Block::digest) == Some(tip.data().digest()) {
+            return;
+        }
+
+        let snapshot = &self.snapshot;
+        let blocks = self
+            .upstream_chain_from(tip.data().digest())
+            .take_while(|block| {
+                snapshot.block_at(block.height()).map(Block::digest) != Some(block.digest())
+            })
+            .map(|block| Arc::new(block.clone()))
+            .collect_vec();
+        let fork = blocks
+            .last()
+            .map_or(tip.data().height().next(), |block| block.height());
+        let utxos = self
+            .utxo_cache
+            .as_ref()
+            .map(|(_, utxos)| utxos.clone())
+            .unwrap_or_default();
+        let work = self
+            .work_map
+            .get(&tip.node_id())
+            .copied()
+            .unwrap_or_default();
+
+        Arc::make_mut(&mut self.snapshot).move_tip(fork, blocks.into_iter().rev(), utxos, work);
+    }
+
+    /// Ancestor of the node at `height`, following skip pointers where they do not overshoot.
+    fn ancestor_id(&self, id: NodeId, height: BlockHeight) -> Option<NodeId> {
+        let target = u64::from(height);
+        let mut id = id;
+        let mut walk = u64::from(self.block_tree.get(id)?.data().height());
+        if walk < target {
+            return None;
+        }
+
+        while walk > target {
+            let skip = u64::from(skip_height(BlockHeight::from(walk)));
+            let skip_prev = u64::from(skip_height(BlockHeight::from(walk - 1)));
+            // Prefer the parent when its skip pointer reaches closer to the target
+            let take_skip =
+                skip == target || (skip > target && !(skip_prev + 2 < skip && skip_prev >= target));
+            match self.skip_map.get(&id) {
+                Some(&skip_id) if take_skip => {
+                    id = skip_id;
+                    walk = skip;
+                }
+                _ => {
+                    id = self.block_tree.get(id)?.parent()?.node_id();
+                    walk -= 1;
+                }
+            }
+        }
+        Some(id)
+    }
+
+    /// Whether the node is `tip` or one of its ancestors.
+    fn is_on_chain(&self, id: NodeId, tip: NodeId) -> bool {
+        self.block_tree
+            .get(id)
+            .is_some_and(|node| self.ancestor_id(tip, node.data().height()) == Some(id))
+    }
+
+    /// Output at `outpoint` created in the chain up to `tip`.
+    fn output_on_chain(&self, outpoint: &OutPoint, tip: NodeId) -> Option<&Transition<Verified>> {
+        let &(id, position) = self
+            .outpoint_map
+            .get(outpoint)?
+            .iter()
+            .find(|(id, _)| self.is_on_chain(*id, tip))?;
+        let tx = self
+            .block_tree
+            .get(id)?
+            .data()
+            .transactions()
+            .get(position)?;
+        tx.outputs().get(outpoint.index as usize)
+    }
+
+    /// Outpoint of the output signed `sign` in the chain up to `tip`.
+    fn outpoint_on_chain(&self, sign: &Signature, tip: NodeId) -> Option<OutPoint> {
+        self.sign_map
+            .get(sign)?
+            .iter()
+            .find(|outpoint| self.output_on_chain(outpoint, tip).is_some())
+            .cloned()
+    }
+
+    /// Index outputs of the node, which the same transaction in another branch may share.
+    fn index_outputs(&mut self, id: NodeId) {
+        let block = self.block_tree.get(id).expect("Invalid id").data();
+        let created = created_outpoints(block);
+        for (outpoint, sign, position) in created {
+            let blocks = self.outpoint_map.entry(outpoint.clone()).or_default();
+            if blocks.is_empty() {
+                self.sign_map.entry(sign).or_default().push(outpoint);
+            }
+            blocks.push((id, position));
+        }
+    }
+
+    /// Forget outputs of the node, and the outpoints which no other block creates.
+    fn unindex_outputs(&mut self, id: NodeId) {
+        let block = self.block_tree.get(id).expect("Invalid id").data();
+        let created = created_outpoints(block);
+        for (outpoint, sign, _) in created {
+            let blocks = match self.outpoint_map.get_mut(&outpoint) {
+                Some(blocks) => blocks,
+                None => continue,
+            };
+            blocks.retain(|(block_id, _)| *block_id != id);
+            if !blocks.is_empty() {
+                continue;
+            }
+            self.outpoint_map.remove(&outpoint);
+            if let Some(outpoints) = self.sign_map.get_mut(&sign) {
+                outpoints.retain(|o| *o != outpoint);
+                if outpoints.is_empty() {
+                    self.sign_map.remove(&sign);
+                }
+            }
+        }
     }
 
     fn node_by_digest(&self, digest: &BlockDigest) -> Option<NodeRef<'_, VerifiedBlock>> {
@@ -281,6 +1352,12 @@ impl Ledger {
     }
 }
 
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Iterate blocks from latest to genesis.
 pub enum BlockchainUpstream<'a> {
     Empty,
@@ -305,11 +1382,109 @@ impl<'a> Iterator for BlockchainUpstream<'a> {
     }
 }
 
+/// Iterate blocks from genesis to a given block.
+pub struct BlockchainDownstream<'a> {
+    ledger: &'a Ledger,
+    tip: Option<(NodeId, BlockHeight)>,
+    next_height: BlockHeight,
+}
+
+impl<'a> Iterator for BlockchainDownstream<'a> {
+    type Item = &'a VerifiedBlock;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tip, tip_height) = self.tip?;
+        if self.next_height > tip_height {
+            return None;
+        }
+
+        let id = self.ledger.ancestor_id(tip, self.next_height)?;
+        self.next_height = self.next_height.next();
+        self.ledger.block_tree.get(id).map(|node| node.data())
+    }
+}
+
+/// Outpoints created by the block, with the sign of each output and the position of its transaction.
+fn created_outpoints(block: &VerifiedBlock) -> Vec<(OutPoint, Signature, usize)> {
+    block
+        .transactions()
+        .iter()
+        .enumerate()
+        .flat_map(|(position, tx)| {
+            tx.created_outputs()
+                .map(move |(outpoint, output)| (outpoint, output.sign().clone(), position))
+        })
+        .collect()
+}
+
+/// Height of the ancestor which a block at `height` skips to.
+/// Same as Bitcoin's, which makes reaching any ancestor O(log n).
+fn skip_height(height: BlockHeight) -> BlockHeight {
+    fn invert_lowest_one(n: u64) -> u64 {
+        n & n.wrapping_sub(1)
+    }
+
+    let height = u64::from(height);
+    let skip = if height < 2 {
+        0
+    } else if height & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(height - 1)) + 1
+    } else {
+        invert_lowest_one(height)
+    };
+    BlockHeight::from(skip)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SupplyError {
+    UnknownBlock,
+    /// A block minted more coins than the emission schedule allows.
+    ExcessEmission {
+        height: BlockHeight,
+        minted: Coin,
+        allowed: Coin,
+    },
+    Overflow,
+}
+
+impl Display for SupplyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SupplyError::UnknownBlock => write!(f, "The block is not in ledger"),
+            SupplyError::ExcessEmission {
+                height,
+                minted,
+                allowed,
+            } => write!(
+                f,
+                "Block {} minted {}, but only {} is allowed",
+                height, minted, allowed
+            ),
+            SupplyError::Overflow => write!(f, "Total supply overflows"),
+        }
+    }
+}
+
+impl Error for SupplyError {}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum LedgerError {
     IsolatedBlock,
     DuplicatedBlock,
     DuplicatedGenesisBlock,
+    /// Input's spend condition is not met at the block height,
+    /// such as an HTLC claimed after timeout, or a multisig refunded before timeout.
+    Timelock,
+    /// Transaction is included before its lock time.
+    LockTime,
+    /// Block timestamp is too far ahead of the local clock.
+    FutureBlock,
+    /// UTXO set after applying the block differs from the committed one.
+    UtxoCommitment,
+    /// Compact block spends an output which is not in its chain.
+    UnknownOutPoint(OutPoint),
+    /// Block breaks the rule of an active deployment.
+    Deployment(DeploymentRule),
     Transfer(TransferHistoryError),
     Block(BlockError),
 }
@@ -332,6 +1507,16 @@ impl Display for LedgerError {
             LedgerError::DuplicatedGenesisBlock => {
                 write!(f, "This ledger already has genesis block")
             }
+            LedgerError::Timelock => write!(f, "Locked output is spent outside its timelock"),
+            LedgerError::LockTime => write!(f, "Transaction is included before its lock time"),
+            LedgerError::FutureBlock => write!(f, "Block timestamp is too far in the future"),
+            LedgerError::UtxoCommitment => write!(f, "Block commits to a wrong UTXO set"),
+            LedgerError::UnknownOutPoint(outpoint) => {
+                write!(f, "Outpoint {} is not in the chain", outpoint)
+            }
+            LedgerError::Deployment(rule) => {
+                write!(f, "Block breaks an active deployment. {}", rule)
+            }
             LedgerError::Transfer(e) => e.fmt(f),
             LedgerError::Block(e) => e.fmt(f),
         }
@@ -344,8 +1529,688 @@ impl Error for LedgerError {
             LedgerError::IsolatedBlock => None,
             LedgerError::DuplicatedBlock => None,
             LedgerError::DuplicatedGenesisBlock => None,
+            LedgerError::Timelock => None,
+            LedgerError::LockTime => None,
+            LedgerError::FutureBlock => None,
+            LedgerError::UtxoCommitment => None,
+            LedgerError::UnknownOutPoint(_) => None,
+            LedgerError::Deployment(_) => None,
             LedgerError::Transfer(e) => Some(e),
             LedgerError::Block(e) => Some(e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::clock::{MockClock, SystemClock};
+    use crate::deployment::DeploymentSchedule;
+    use crate::light::verify_utxo_snapshot;
+    use crate::signature::{SighashFlag, SighashVersion};
+    use crate::test_utils::{fabricate, params, reward};
+    use crate::test_utils::{generation_rule, mine, mine_on, mine_signalling, mine_with_clock};
+    use crate::transaction::{LockTime, TransactionError};
+    use crate::{BlockHeight, BlockSource, Difficulty, Htlc, SecretAddress, Transaction, Transfer};
+    use apply::Also;
+
+    const PREIMAGE: &[u8] = b"swap secret";
+
+    /// Alice locks her genesis reward for bob until height 3.
+    /// Returns the ledger and the HTLC output.
+    fn create_htlc(alice: &SecretAddress, bob: &SecretAddress) -> (Ledger, Transition<Verified>) {
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], alice).unwrap();
+
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let timeout = BlockHeight::from(3);
+        let htlc = Htlc::offer(
+            alice,
+            bob.to_public_address(),
+            reward.quantity(),
+            BlockDigest::digest(PREIMAGE),
+            timeout,
+        );
+        let tx = Transaction::offer(alice, vec![reward], vec![htlc])
+            .verify_transaction()
+            .unwrap();
+        let digest = mine(&mut ledger, vec![tx], alice).unwrap();
+
+        let htlc = ledger
+            .get(&digest)
+            .unwrap()
+            .outputs()
+            .find(|o| o.try_as_htlc().is_some())
+            .cloned()
+            .unwrap();
+        (ledger, htlc)
+    }
+
+    #[test]
+    fn test_lock_time_height() {
+        let alice = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+
+        // Pre-signed payment not valid before block 2
+        let output = Transfer::offer(&alice, alice.to_public_address(), reward.quantity());
+        let tx = Transaction::offer_with_lock_time(
+            &alice,
+            vec![reward],
+            vec![output],
+            LockTime::Height(BlockHeight::from(2)),
+        )
+        .verify_transaction()
+        .unwrap();
+
+        assert_eq!(
+            Err(LedgerError::LockTime),
+            mine(&mut ledger, vec![tx.clone()], &alice)
+        );
+        mine(&mut ledger, vec![], &alice).unwrap();
+        assert!(mine(&mut ledger, vec![tx], &alice).is_ok());
+    }
+
+    #[test]
+    fn test_future_block_by_clock() {
+        let miner = SecretAddress::create();
+        let start = Timestamp::now();
+        let clock = MockClock::new(start);
+        let mut ledger = Ledger::new().with_clock(clock.clone());
+
+        // Miner whose clock runs a minute beyond the allowed drift
+        let ahead = MockClock::new(start.checked_add_secs(MAX_FUTURE_DRIFT_SECS + 60).unwrap());
+        assert_eq!(
+            Err(LedgerError::FutureBlock),
+            mine_with_clock(&mut ledger, vec![], &miner, &ahead)
+        );
+
+        clock.advance_secs(60);
+        assert!(mine_with_clock(&mut ledger, vec![], &miner, &ahead).is_ok());
+    }
+
+    #[test]
+    fn test_lock_time_median_time_past() {
+        let alice = SecretAddress::create();
+        let start = Timestamp::now().checked_add_secs(-3600).unwrap();
+        let clock = MockClock::new(start);
+        let mut ledger = Ledger::new();
+        let genesis = mine_with_clock(&mut ledger, vec![], &alice, &clock).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+
+        // Pre-signed payment not valid until 10 minutes after genesis
+        let lock = start.checked_add_secs(600).unwrap();
+        clock.advance_secs(1);
+        let output = Transfer::offer_with_clock(
+            &alice,
+            alice.to_public_address(),
+            reward.quantity(),
+            &clock,
+        );
+        let tx = Transaction::offer_with_clock(
+            &alice,
+            vec![reward],
+            vec![output],
+            SighashFlag::All,
+            Some(LockTime::Time(lock)),
+            &clock,
+        )
+        .verify_transaction()
+        .unwrap();
+
+        // A block a minute, until the median of recent blocks reaches the lock
+        for _ in 0..30 {
+            clock.advance_secs(60);
+            let tip = ledger.search_latest_block().unwrap().digest().clone();
+            let reached = ledger.median_time_past(&tip).unwrap() >= lock;
+            let res = mine_with_clock(&mut ledger, vec![tx.clone()], &alice, &clock);
+            if reached {
+                assert!(res.is_ok());
+                // The median lags behind the tip, which passed the lock several blocks ago
+                let height = ledger.search_latest_block().unwrap().height();
+                assert!(height > BlockHeight::from(10));
+                return;
+            }
+            assert_eq!(Err(LedgerError::LockTime), res);
+            mine_with_clock(&mut ledger, vec![], &alice, &clock).unwrap();
+        }
+        panic!("Median time past never reached the lock time");
+    }
+
+    fn spend(spender: &SecretAddress, htlc: Transition<Verified>) -> Transaction<Verified, Yet> {
+        let quantity = htlc.quantity();
+        let output = Transfer::offer(spender, spender.to_public_address(), quantity);
+        Transaction::offer(spender, vec![htlc], vec![output])
+    }
+
+    #[test]
+    fn test_htlc_claim_with_preimage() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let (mut ledger, htlc) = create_htlc(&alice, &bob);
+
+        let tx = spend(&bob, htlc)
+            .with_preimage(PREIMAGE.to_vec())
+            .verify_transaction()
+            .unwrap();
+
+        assert!(mine(&mut ledger, vec![tx], &alice).is_ok());
+    }
+
+    #[test]
+    fn test_htlc_claim_without_preimage() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let (_, htlc) = create_htlc(&alice, &bob);
+
+        let tx = spend(&bob, htlc)
+            .with_preimage(b"wrong secret".to_vec())
+            .verify_transaction();
+
+        assert_eq!(Err(TransactionError::MissingPreimage), tx);
+    }
+
+    #[test]
+    fn test_htlc_claim_with_extra_preimage() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let (_, htlc) = create_htlc(&alice, &bob);
+        let tx = spend(&bob, htlc).with_preimage(PREIMAGE.to_vec());
+
+        // Revealing the same preimage again keeps the txid
+        let txid = tx.txid();
+        assert_eq!(txid, tx.clone().with_preimage(PREIMAGE.to_vec()).txid());
+
+        // A preimage which unlocks nothing would change the txid without a sign
+        let tx = tx.with_preimage(b"extra".to_vec());
+        assert_ne!(txid, tx.txid());
+        assert_eq!(
+            Some(TransactionError::InvalidPreimages),
+            tx.verify_transaction().err()
+        );
+    }
+
+    #[test]
+    fn test_htlc_claim_after_timeout() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let (mut ledger, htlc) = create_htlc(&alice, &bob);
+        mine(&mut ledger, vec![], &alice).unwrap();
+
+        let tx = spend(&bob, htlc)
+            .with_preimage(PREIMAGE.to_vec())
+            .verify_transaction()
+            .unwrap();
+
+        assert_eq!(
+            Err(LedgerError::Timelock),
+            mine(&mut ledger, vec![tx], &alice)
+        );
+    }
+
+    #[test]
+    fn test_htlc_refund() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let (mut ledger, htlc) = create_htlc(&alice, &bob);
+
+        // Before timeout
+        let tx = spend(&alice, htlc.clone()).verify_transaction().unwrap();
+        assert_eq!(
+            Err(LedgerError::Timelock),
+            mine(&mut ledger, vec![tx], &alice)
+        );
+
+        // After timeout
+        mine(&mut ledger, vec![], &alice).unwrap();
+        let tx = spend(&alice, htlc).verify_transaction().unwrap();
+        assert!(mine(&mut ledger, vec![tx], &alice).is_ok());
+    }
+
+    #[test]
+    fn test_chain_info() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        assert_eq!(1, ledger.leaf_blocks().count());
+
+        let tip = mine(&mut ledger, vec![], &miner).unwrap();
+        mine_on(&mut ledger, Some(&genesis), vec![], &miner).unwrap();
+        assert_eq!(2, ledger.leaf_blocks().count());
+
+        // Difficulty 0 makes each block worth 1 hash
+        assert_eq!(ChainWork::from(2), ledger.cumulative_work(&tip));
+
+        let timestamps = ledger
+            .upstream_chain_from(&tip)
+            .map(Block::timestamp)
+            .collect_vec();
+        assert_eq!(Some(timestamps[0]), ledger.median_time_past(&tip));
+        assert_eq!(Some(timestamps[1]), ledger.median_time_past(&genesis));
+    }
+
+    #[test]
+    fn test_blocks_between() {
+        let miner = SecretAddress::create();
+        let start = Timestamp::now().checked_add_secs(-3600).unwrap();
+        let at = |secs| start.checked_add_secs(secs).unwrap();
+        let clock = MockClock::new(start);
+        let mut ledger = Ledger::new();
+        let genesis = mine_with_clock(&mut ledger, vec![], &miner, &clock).unwrap();
+        let mut chain = vec![genesis.clone()];
+        for _ in 0..3 {
+            clock.advance_secs(60);
+            chain.push(mine_with_clock(&mut ledger, vec![], &miner, &clock).unwrap());
+        }
+        // Shorter branch, whose block is in the range but not in the best chain
+        mine_on(&mut ledger, Some(&genesis), vec![], &miner).unwrap();
+
+        let between = |ledger: &Ledger, from, to| {
+            ledger
+                .blocks_between(from, to)
+                .map(|block| block.digest().clone())
+                .collect_vec()
+        };
+        assert_eq!(chain[1..3], between(&ledger, at(60), at(180))[..]);
+        assert_eq!(chain, between(&ledger, start, Timestamp::now()));
+        assert!(between(&ledger, at(180), at(60)).is_empty());
+        // Each block has its generation transaction
+        assert_eq!(2, ledger.transactions_between(at(60), at(180)).count());
+
+        ledger.remove_branch(&chain[3]).unwrap();
+        assert_eq!(chain[..3], between(&ledger, start, Timestamp::now())[..]);
+    }
+
+    #[test]
+    fn test_downstream_chain() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        let mut tip = genesis.clone();
+        for _ in 0..40 {
+            tip = mine(&mut ledger, vec![], &miner).unwrap();
+        }
+        let fork = mine_on(&mut ledger, Some(&genesis), vec![], &miner).unwrap();
+
+        for digest in [&genesis, &tip, &fork] {
+            let upstream = ledger
+                .upstream_chain_from(digest)
+                .collect_vec()
+                .also(|blocks| blocks.reverse());
+            let downstream = ledger.downstream_chain_to(digest).collect_vec();
+            assert_eq!(upstream, downstream);
+        }
+
+        let unknown = BlockDigest::digest(&[]);
+        assert_eq!(0, ledger.downstream_chain_to(&unknown).count());
+    }
+
+    #[test]
+    fn test_utxo_commitment() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        mine(&mut ledger, vec![], &miner).unwrap();
+        let tip = mine(&mut ledger, vec![], &miner).unwrap();
+
+        let header = ledger.get(&tip).unwrap().header();
+        assert!(header.utxo_commitment().is_some());
+
+        let snapshot = ledger.utxo_snapshot(&tip);
+        assert_eq!(2, snapshot.len());
+        assert!(verify_utxo_snapshot(header, snapshot.iter().rev()));
+        assert!(!verify_utxo_snapshot(header, &snapshot[1..]));
+
+        // Commitment to the UTXO set before the block
+        let mut source = BlockSource::new(
+            BlockHeight::from(2),
+            vec![],
+            tip.clone(),
+            Difficulty::new(0),
+            0,
+            &miner,
+            generation_rule,
+        )
+        .unwrap();
+        source.commit_utxos(utxo_commitment(&snapshot));
+        let block = source
+            .try_into_block()
+            .unwrap()
+            .verify_transaction_relation_with(&params())
+            .and_then(|b| b.verify_difficulty(&Difficulty::new(0)))
+            .and_then(|b| b.verify_digest())
+            .unwrap();
+        assert_eq!(Err(LedgerError::UtxoCommitment), ledger.verify_block(block));
+    }
+
+    #[test]
+    fn test_deployment_activation() {
+        const SCHEDULE: DeploymentSchedule = DeploymentSchedule {
+            window: 4,
+            threshold: 3,
+            deployments: &[Deployment {
+                name: "test",
+                bit: 2,
+                start: BlockHeight::genesis(),
+                timeout: BlockHeight::new(100),
+                rule: DeploymentRule::RejectLegacySighash,
+            }],
+        };
+        let deployment = &SCHEDULE.deployments[0];
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::with_params(params().with_deployments(SCHEDULE));
+        let state = |ledger: &Ledger| {
+            let tip = ledger.search_latest_block().map(|b| b.digest().clone());
+            ledger.deployment_state(deployment, tip.as_ref())
+        };
+
+        for _ in 0..4 {
+            assert_eq!(DeploymentState::Defined, state(&ledger));
+            mine_signalling(&mut ledger, 1 << 2, &miner).unwrap();
+        }
+        // Signals of the window before the start do not count
+        assert_eq!(DeploymentState::Started, state(&ledger));
+        let tip = ledger.search_latest_block().unwrap().digest().clone();
+        assert_eq!(1 << 2, ledger.signals_for_next(Some(&tip)));
+
+        for signals in [1 << 2, 0, 1 << 2, 1 << 2] {
+            mine_signalling(&mut ledger, signals, &miner).unwrap();
+        }
+        let status = &ledger.deployment_statuses()[0];
+        assert_eq!(DeploymentState::LockedIn, status.state);
+        assert_eq!((0, 0), (status.signalling, status.elapsed));
+
+        // Legacy blocks are still valid while locked in, but not once active
+        let legacy = |ledger: &Ledger| {
+            let tip = ledger.search_latest_block().unwrap();
+            let generation = fabricate(
+                ledger,
+                Some(tip.digest()),
+                vec![],
+                &miner,
+                Difficulty::new(0),
+                &SystemClock,
+            )
+            .unwrap()
+            .transactions()[0]
+                .as_ref()
+                .clone();
+            let block = BlockBuilder::new(tip.height().next(), tip.digest().clone())
+                .transaction(generation)
+                .version(SighashVersion::Legacy)
+                .build()
+                .verify_transaction_relation_with(&params())
+                .and_then(|b| b.verify_difficulty(&Difficulty::new(0)))
+                .and_then(|b| b.verify_digest())
+                .unwrap();
+            ledger.verify_block(block).map(|_| ())
+        };
+        for _ in 0..4 {
+            assert_eq!(Ok(()), legacy(&ledger));
+            mine_signalling(&mut ledger, 0, &miner).unwrap();
+        }
+        assert_eq!(DeploymentState::Active, state(&ledger));
+        assert_eq!(
+            Err(LedgerError::Deployment(DeploymentRule::RejectLegacySighash)),
+            legacy(&ledger)
+        );
+        let tip = ledger.search_latest_block().unwrap().digest().clone();
+        assert_eq!(0, ledger.signals_for_next(Some(&tip)));
+        mine(&mut ledger, vec![], &miner).unwrap();
+    }
+
+    #[test]
+    fn test_deployment_states_follow_branches() {
+        const SCHEDULE: DeploymentSchedule = DeploymentSchedule {
+            window: 4,
+            threshold: 3,
+            deployments: &[Deployment {
+                name: "test",
+                bit: 2,
+                start: BlockHeight::genesis(),
+                timeout: BlockHeight::new(100),
+                rule: DeploymentRule::RejectLegacySighash,
+            }],
+        };
+        let deployment = &SCHEDULE.deployments[0];
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::with_params(params().with_deployments(SCHEDULE));
+        for _ in 0..4 {
+            mine_signalling(&mut ledger, 0, &miner).unwrap();
+        }
+        let fork = ledger.search_latest_block().unwrap().digest().clone();
+
+        // Branch which signals through a whole window
+        let mut signalling = fork.clone();
+        for _ in 0..4 {
+            signalling = mine_signalling(&mut ledger, 1 << 2, &miner).unwrap();
+        }
+        // Longer branch which does not
+        let mut silent = fork.clone();
+        for _ in 0..5 {
+            let block = fabricate(
+                &ledger,
+                Some(&silent),
+                vec![],
+                &miner,
+                Difficulty::new(0),
+                &SystemClock,
+            )
+            .unwrap();
+            silent = block.digest().clone();
+            ledger.entry(block).unwrap();
+        }
+        assert_eq!(
+            Some(&silent),
+            ledger.search_latest_block().map(Block::digest)
+        );
+
+        assert_eq!(
+            DeploymentState::Started,
+            ledger.deployment_state(deployment, Some(&fork))
+        );
+        assert_eq!(
+            DeploymentState::LockedIn,
+            ledger.deployment_state(deployment, Some(&signalling))
+        );
+        assert_eq!(
+            DeploymentState::Started,
+            ledger.deployment_state(deployment, Some(&silent))
+        );
+
+        // States of removed blocks go with them
+        let first_signalling = ledger.downstream_chain_to(&signalling).nth(4).unwrap();
+        let first_signalling = first_signalling.digest().clone();
+        ledger.remove_branch(&first_signalling).unwrap();
+        // Ends of the first window and of the silent branch's
+        assert_eq!(2, ledger.deployment_map.len());
+        assert_eq!(
+            DeploymentState::Started,
+            ledger.deployment_state(deployment, Some(&silent))
+        );
+    }
+
+    #[test]
+    fn test_reorg_by_undo_records() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+
+        let output = Transfer::offer(&alice, bob.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward.clone()], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let paid = mine(&mut ledger, vec![tx.clone()], &alice).unwrap();
+        let undo = ledger.undo_record(&paid).unwrap();
+        assert_eq!(std::slice::from_ref(&reward), undo.spent());
+        assert!(tx.outputs().iter().all(|o| undo.created().contains(o)));
+
+        // Longer branch without the payment becomes the best chain
+        let branch = mine_on(&mut ledger, Some(&genesis), vec![], &alice).unwrap();
+        let fork = mine_on(&mut ledger, Some(&branch), vec![], &alice).unwrap();
+        assert_eq!(&fork, ledger.search_latest_block().unwrap().digest());
+        let (disconnected, connected) = ledger.fork_path(Some(&paid), &fork).unwrap();
+        let digests =
+            |blocks: Vec<&VerifiedBlock>| blocks.iter().map(|b| b.digest().clone()).collect_vec();
+        assert_eq!(vec![paid.clone()], digests(disconnected));
+        assert_eq!(vec![branch, fork.clone()], digests(connected));
+
+        let snapshot = ledger.utxo_snapshot(&fork);
+        assert!(snapshot.contains(&reward));
+        assert!(!snapshot.iter().any(|utxo| tx.outputs().contains(utxo)));
+        let snapshot = ledger.utxo_snapshot(&paid);
+        assert!(!snapshot.contains(&reward));
+        assert!(tx.outputs().iter().all(|o| snapshot.contains(o)));
+
+        // Payment is valid again on the best chain
+        assert!(mine(&mut ledger, vec![tx], &alice).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        assert!(ledger.snapshot().is_empty());
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let old_tip = mine(&mut ledger, vec![], &alice).unwrap();
+        let held = ledger.snapshot();
+
+        // Longer branch takes over while the old snapshot is held
+        let branch = mine_on(&mut ledger, Some(&genesis), vec![], &bob).unwrap();
+        let tip = mine_on(&mut ledger, Some(&branch), vec![], &bob).unwrap();
+        assert_eq!(Some(&old_tip), held.tip().map(Block::digest));
+        assert_eq!(2, held.blocks().count());
+
+        let snapshot = ledger.snapshot();
+        let digests = snapshot.blocks().map(|b| b.digest().clone()).collect_vec();
+        assert_eq!(vec![genesis, branch, tip.clone()], digests);
+        assert_eq!(ledger.cumulative_work(&tip), snapshot.cumulative_work());
+        assert_eq!(ledger.median_time_past(&tip), snapshot.median_time_past());
+        let bob = bob.to_public_address();
+        assert_eq!(ledger.build_utxos(&tip, &bob), snapshot.utxos_of(&bob));
+        assert!(Arc::ptr_eq(&snapshot, &ledger.snapshot()));
+    }
+
+    #[test]
+    fn test_prune_stale_branches() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        let stale = mine_on(&mut ledger, Some(&genesis), vec![], &miner).unwrap();
+        let stale_child = mine_on(&mut ledger, Some(&stale), vec![], &miner).unwrap();
+        // Second tip of the stale branch
+        mine_on(&mut ledger, Some(&stale), vec![], &miner).unwrap();
+        let main = mine_on(&mut ledger, Some(&genesis), vec![], &miner).unwrap();
+        let main = mine_on(&mut ledger, Some(&main), vec![], &miner).unwrap();
+        let recent = mine_on(&mut ledger, Some(&main), vec![], &miner).unwrap();
+        let mut tip = main.clone();
+        for _ in 0..3 {
+            tip = mine_on(&mut ledger, Some(&tip), vec![], &miner).unwrap();
+        }
+        assert_eq!(&tip, ledger.search_latest_block().unwrap().digest());
+        assert_eq!(4, ledger.leaf_blocks().count());
+
+        // Tips of the stale branch are 3 blocks behind, and the recent one is 2 behind
+        assert_eq!(0, ledger.prune_stale_branches(3));
+        assert_eq!(3, ledger.prune_stale_branches(2));
+        assert!(ledger.get(&stale).is_none() && ledger.get(&stale_child).is_none());
+        assert!(ledger.get(&recent).is_some());
+        assert_eq!(&tip, ledger.search_latest_block().unwrap().digest());
+        assert_eq!(2, ledger.leaf_blocks().count());
+        assert_eq!(6, ledger.downstream_chain_to(&tip).count());
+    }
+
+    #[test]
+    fn test_double_spending_in_block() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+
+        let transactions = [&alice, &bob]
+            .iter()
+            .map(|receiver| {
+                let output =
+                    Transfer::offer(&alice, receiver.to_public_address(), reward.quantity());
+                Transaction::offer(&alice, vec![reward.clone()], vec![output])
+                    .verify_transaction()
+                    .unwrap()
+            })
+            .collect_vec();
+
+        assert_eq!(
+            Err(LedgerError::Transfer(TransferHistoryError::DoubleSpending)),
+            mine(&mut ledger, transactions, &alice)
+        );
+        assert_eq!(
+            Some(&genesis),
+            ledger.search_latest_block().map(Block::digest)
+        );
+    }
+
+    #[test]
+    fn test_total_supply() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::with_params(params());
+        mine(&mut ledger, vec![], &miner).unwrap();
+        let tip = mine(&mut ledger, vec![], &miner).unwrap();
+        assert_eq!(Ok(Coin::from(200)), ledger.total_supply_at(&tip));
+
+        // Blocks mint more than the rule of this ledger
+        let mut ledger = Ledger::with_params(ChainParams::new(|_| Coin::from(50)));
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        assert_eq!(
+            Err(SupplyError::ExcessEmission {
+                height: BlockHeight::genesis(),
+                minted: Coin::from(100),
+                allowed: Coin::from(50),
+            }),
+            ledger.total_supply_at(&genesis)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "invariants")]
+    #[should_panic(expected = "Digest map is inconsistent")]
+    fn test_invariants_detect_corruption() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        mine(&mut ledger, vec![], &miner).unwrap();
+        ledger.debug_assert_invariants();
+
+        // Genesis is still in the tree, but no longer reachable by its digest
+        ledger.digest_map.remove(&genesis);
+        ledger.debug_assert_invariants();
+    }
+
+    #[test]
+    fn test_compact_block() {
+        let alice = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+
+        let output = Transfer::offer(&alice, alice.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let tip = mine(&mut ledger, vec![tx.clone()], &alice).unwrap();
+
+        // The same transaction on a removed sibling branch leaves the outpoints of this one
+        let sibling = mine_on(&mut ledger, Some(&genesis), vec![tx], &alice).unwrap();
+        ledger.remove_branch(&sibling);
+        let block = ledger.get(&tip).unwrap();
+
+        let compact = ledger.compact_block(block).unwrap();
+        let full_size = bincode::serialize(&block.to_unverified()).unwrap().len();
+        let compact_size = bincode::serialize(&compact).unwrap().len();
+        assert!(compact_size < full_size);
+
+        assert_eq!(
+            Ok(block.to_unverified()),
+            ledger.expand_block(compact.clone())
+        );
+        assert!(matches!(
+            Ledger::new().expand_block(compact),
+            Err(LedgerError::UnknownOutPoint(_))
+        ));
+    }
+}
@@ -1,21 +1,48 @@
 pub mod account;
+pub mod analysis;
+pub mod authority;
 pub mod block;
+pub mod channels;
+pub mod checkpoint;
+pub mod clock;
 pub mod coin;
+pub mod compact;
+pub mod condition;
+pub mod deployment;
 pub mod difficulty;
 pub mod digest;
 pub mod ledger;
+pub mod light;
+pub mod mempool;
+pub mod params;
+pub mod persistent_ledger;
+pub mod psbt;
+pub mod rejection;
+pub mod search;
 pub mod signature;
+pub mod signer;
+pub mod snapshot;
 pub mod timestamp;
+pub mod tracker;
 pub mod transaction;
 pub mod transition;
+pub mod utxo_db;
 pub mod verification;
 
+#[cfg(test)]
+mod properties;
+#[cfg(test)]
+mod simulation;
+#[cfg(test)]
+mod test_utils;
+
 pub use account::{Address, SecretAddress};
-pub use block::{Block, BlockHeight, BlockSource};
+pub use block::{Block, BlockHeader, BlockHeight, BlockSource, DynBlock, HeightRange, NonceIter};
 pub use coin::Coin;
 pub use difficulty::Difficulty;
+pub use params::ChainParams;
 pub use transaction::Transaction;
-pub use transition::{Generation, Transfer, Transition};
+pub use transition::{Generation, Htlc, Multisig, Transfer, Transition};
 pub use verification::{Verified, Yet};
 
 pub type UnverifiedTransaction = Transaction<Yet, Yet>;
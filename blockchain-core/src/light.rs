@@ -0,0 +1,395 @@
+use crate::block::{BlockHeader, BlockHeight};
+use crate::difficulty::Difficulty;
+use crate::digest::{BlockDigest, MerkleRoot, PowAlgorithm};
+use crate::signature::SignatureSource;
+use crate::transition::Transition;
+use crate::verification::Verified;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Tree of verified headers, which keeps no transaction.
+/// The highest header is the tip of the best chain.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    headers: HashMap<BlockDigest, BlockHeader>,
+    tip: Option<BlockDigest>,
+    min_difficulty: Difficulty,
+    pow_algorithm: PowAlgorithm,
+}
+
+impl HeaderChain {
+    /// Headers must have at least `min_difficulty`.
+    pub fn new(min_difficulty: Difficulty) -> Self {
+        Self {
+            headers: HashMap::new(),
+            tip: None,
+            min_difficulty,
+            pow_algorithm: PowAlgorithm::default(),
+        }
+    }
+
+    /// Check header digests by `algorithm` instead of SHA-256, for chains hashing blocks by it.
+    pub fn with_pow_algorithm(mut self, algorithm: PowAlgorithm) -> Self {
+        self.pow_algorithm = algorithm;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn get(&self, digest: &BlockDigest) -> Option<&BlockHeader> {
+        self.headers.get(digest)
+    }
+
+    pub fn contains(&self, digest: &BlockDigest) -> bool {
+        self.headers.contains_key(digest)
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.tip.as_ref().and_then(|digest| self.get(digest))
+    }
+
+    /// Headers of the best chain, from the tip to genesis.
+    pub fn best_chain(&self) -> impl Iterator<Item = &BlockHeader> + '_ {
+        std::iter::successors(self.tip(), |header| match header.height().is_genesis() {
+            true => None,
+            false => self.get(header.previous_digest()),
+        })
+    }
+
+    /// Header at `height` on the best chain.
+    pub fn header_at(&self, height: BlockHeight) -> Option<&BlockHeader> {
+        self.best_chain().find(|header| header.height() == height)
+    }
+
+    pub fn push(&mut self, header: BlockHeader) -> Result<(), HeaderChainError> {
+        if self.contains(header.digest()) {
+            return Err(HeaderChainError::DuplicatedHeader);
+        }
+        if header.difficulty() < &self.min_difficulty {
+            return Err(HeaderChainError::InsufficientDifficulty);
+        }
+        if !header.verify_by(self.pow_algorithm) {
+            return Err(HeaderChainError::InvalidHeader);
+        }
+
+        if header.height().is_genesis() {
+            if self.headers.values().any(|h| h.height().is_genesis()) {
+                return Err(HeaderChainError::DuplicatedGenesisHeader);
+            }
+        } else {
+            let previous = self
+                .get(header.previous_digest())
+                .ok_or(HeaderChainError::IsolatedHeader)?;
+            if previous.height().next() != header.height()
+                || previous.timestamp() >= header.timestamp()
+            {
+                return Err(HeaderChainError::Chain);
+            }
+        }
+
+        if self.tip().is_none_or(|tip| tip.height() < header.height()) {
+            self.tip = Some(header.digest().clone());
+        }
+        self.headers.insert(header.digest().clone(), header);
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderChainError {
+    IsolatedHeader,
+    DuplicatedHeader,
+    DuplicatedGenesisHeader,
+    InsufficientDifficulty,
+    /// Digest or Proof-of-Work mismatch.
+    InvalidHeader,
+    /// Height or timestamp mismatch with the previous header.
+    Chain,
+}
+
+impl Display for HeaderChainError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderChainError::IsolatedHeader => {
+                write!(f, "The header is isolated from any branch of chain")
+            }
+            HeaderChainError::DuplicatedHeader => write!(f, "Duplicated header"),
+            HeaderChainError::DuplicatedGenesisHeader => {
+                write!(f, "This chain already has genesis header")
+            }
+            HeaderChainError::InsufficientDifficulty => write!(f, "Insufficient difficulty"),
+            HeaderChainError::InvalidHeader => write!(f, "Invalid header digest"),
+            HeaderChainError::Chain => write!(f, "Header mismatches its previous header"),
+        }
+    }
+}
+
+impl Error for HeaderChainError {}
+
+/// Sibling on the path from a transaction to the Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MerkleBranch {
+    Left(BlockDigest),
+    Right(BlockDigest),
+}
+
+/// Proof that a transaction id is a leaf of a Merkle tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Ordered from the leaf
+    branches: Vec<MerkleBranch>,
+}
+
+impl MerkleProof {
+    /// Proof of `txids[index]`. Returns `None` if `index` is out of range.
+    pub fn new(txids: &[BlockDigest], index: usize) -> Option<Self> {
+        if index >= txids.len() {
+            return None;
+        }
+
+        let mut level = txids.iter().map(leaf).collect_vec();
+        let mut index = index;
+        let mut branches = vec![];
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            // The last node of an odd level has no sibling
+            if let Some(node) = level.get(sibling) {
+                let branch = if sibling < index {
+                    MerkleBranch::Left(node.clone())
+                } else {
+                    MerkleBranch::Right(node.clone())
+                };
+                branches.push(branch);
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+
+        Some(Self { branches })
+    }
+
+    pub fn branches(&self) -> &[MerkleBranch] {
+        &self.branches
+    }
+
+    /// Merkle root derived from `txid` and this proof.
+    pub fn root(&self, txid: &BlockDigest) -> MerkleRoot {
+        let root = self
+            .branches
+            .iter()
+            .fold(leaf(txid), |digest, branch| match branch {
+                MerkleBranch::Left(sibling) => node(sibling, &digest),
+                MerkleBranch::Right(sibling) => node(&digest, sibling),
+            });
+        MerkleRoot::new(root)
+    }
+}
+
+/// Merkle root of transaction ids.
+pub fn merkle_root(txids: &[BlockDigest]) -> MerkleRoot {
+    MerkleRoot::new(tree_root(txids))
+}
+
+/// Root of a Merkle tree of `leaves`.
+/// The last node of an odd level is carried up as it is.
+fn tree_root(leaves: &[BlockDigest]) -> BlockDigest {
+    let mut level = leaves.iter().map(leaf).collect_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.pop().unwrap_or_else(|| BlockDigest::digest(&[]))
+}
+
+/// Verify that the transaction of `txid` is included in the block of `header`,
+/// without any transaction data.
+pub fn verify_inclusion(header: &BlockHeader, txid: &BlockDigest, proof: &MerkleProof) -> bool {
+    header.verify() && &proof.root(txid) == header.merkle_root()
+}
+
+/// Digest of a UTXO set, which does not depend on the order of `utxos`.
+/// Each UTXO is identified with its data and sign.
+pub fn utxo_commitment<'a, I>(utxos: I) -> BlockDigest
+where
+    I: IntoIterator<Item = &'a Transition<Verified>>,
+{
+    let leaves = utxos
+        .into_iter()
+        .map(|utxo| {
+            let sign = utxo.sign().as_ref().to_bytes();
+            BlockDigest::digest(&[utxo.build_signature_source().as_slice(), &sign].concat())
+        })
+        .sorted_by(|a, b| a.as_ref().cmp(b.as_ref()))
+        .collect_vec();
+    tree_root(&leaves)
+}
+
+/// Verify a downloaded UTXO snapshot against the commitment of a verified header,
+/// so that fast-sync clients need not trust the snapshot provider.
+pub fn verify_utxo_snapshot<'a, I>(header: &BlockHeader, utxos: I) -> bool
+where
+    I: IntoIterator<Item = &'a Transition<Verified>>,
+{
+    match header.utxo_commitment() {
+        Some(commitment) => header.verify() && utxo_commitment(utxos) == *commitment,
+        None => false,
+    }
+}
+
+/// Leaves and inner nodes are prefixed differently, so an inner node cannot pose as a leaf.
+fn leaf(txid: &BlockDigest) -> BlockDigest {
+    BlockDigest::digest(&[&[0], txid.as_ref()].concat())
+}
+
+fn node(left: &BlockDigest, right: &BlockDigest) -> BlockDigest {
+    BlockDigest::digest(&[&[1], left.as_ref(), right.as_ref()].concat())
+}
+
+fn next_level(level: &[BlockDigest]) -> Vec<BlockDigest> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            _ => pair[0].clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Ledger;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    fn txids(count: u8) -> Vec<BlockDigest> {
+        (0..count).map(|i| BlockDigest::digest(&[i])).collect()
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        for count in 1..=7 {
+            let txids = txids(count);
+            let root = merkle_root(&txids);
+            for (index, txid) in txids.iter().enumerate() {
+                let proof = MerkleProof::new(&txids, index).unwrap();
+                assert_eq!(root, proof.root(txid));
+            }
+            assert_eq!(None, MerkleProof::new(&txids, txids.len()));
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_wrong_txid() {
+        let txids = txids(5);
+        let root = merkle_root(&txids);
+        let proof = MerkleProof::new(&txids, 2).unwrap();
+
+        assert_ne!(root, proof.root(&txids[3]));
+    }
+
+    #[test]
+    fn test_header_chain() {
+        let miner = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &miner).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let output = Transfer::offer(&miner, miner.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&miner, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let tip = mine(&mut ledger, vec![tx], &miner).unwrap();
+
+        let genesis = ledger.get(&genesis).unwrap().header().clone();
+        let tip = ledger.get(&tip).unwrap().header().clone();
+        let mut chain = HeaderChain::new(Difficulty::new(0));
+
+        assert_eq!(
+            Err(HeaderChainError::IsolatedHeader),
+            chain.push(tip.clone())
+        );
+        assert_eq!(Ok(()), chain.push(genesis.clone()));
+        assert_eq!(
+            Err(HeaderChainError::DuplicatedHeader),
+            chain.push(genesis.clone())
+        );
+
+        let forged = BlockHeader::new(
+            tip.height(),
+            tip.merkle_root().clone(),
+            tip.timestamp(),
+            tip.previous_digest().clone(),
+            tip.difficulty().clone(),
+            tip.nonce() + 1,
+            tip.digest().clone(),
+            tip.version(),
+        )
+        .with_utxo_commitment(tip.utxo_commitment().cloned())
+        .with_signals(tip.signals());
+        assert_eq!(Err(HeaderChainError::InvalidHeader), chain.push(forged));
+
+        assert_eq!(Ok(()), chain.push(tip.clone()));
+        assert_eq!(Some(&tip), chain.tip());
+        assert_eq!(Some(&genesis), chain.header_at(BlockHeight::genesis()));
+        assert_eq!(2, chain.best_chain().count());
+
+        let mut strict = HeaderChain::new(Difficulty::new(1));
+        assert_eq!(
+            Err(HeaderChainError::InsufficientDifficulty),
+            strict.push(genesis)
+        );
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let output = Transfer::offer(&alice, bob.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let digest = mine(&mut ledger, vec![tx.clone()], &alice).unwrap();
+        let block = ledger.get(&digest).unwrap();
+
+        let index = block
+            .transactions()
+            .iter()
+            .position(|t| t.txid() == tx.txid())
+            .unwrap();
+        let proof = block.merkle_proof(index).unwrap();
+        let header = block.header();
+
+        assert!(verify_inclusion(header, &tx.txid(), &proof));
+        assert_eq!(None, block.merkle_proof(block.transactions().len()));
+
+        // Not in the block
+        let other = ledger.get(&genesis).unwrap().transactions()[0].txid();
+        assert!(!verify_inclusion(header, &other, &proof));
+
+        // Header not committing to the root
+        let forged = BlockHeader::new(
+            header.height(),
+            proof.root(&other),
+            header.timestamp(),
+            header.previous_digest().clone(),
+            header.difficulty().clone(),
+            header.nonce(),
+            header.digest().clone(),
+            header.version(),
+        )
+        .with_utxo_commitment(header.utxo_commitment().cloned())
+        .with_signals(header.signals());
+        assert!(!verify_inclusion(&forged, &other, &proof));
+    }
+}
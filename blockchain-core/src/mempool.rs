@@ -0,0 +1,428 @@
+use crate::digest::BlockDigest;
+use crate::{Address, Coin, Transition, UnverifiedTransaction, Verified, VerifiedTransaction};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    transaction: Arc<VerifiedTransaction>,
+    txid: BlockDigest,
+    size: usize,
+    fee: Coin,
+}
+
+impl Entry {
+    /// Compare fee per byte without rounding.
+    fn cmp_fee_rate(&self, other: &Entry) -> Ordering {
+        let lhs = self.fee.base_units() as u128 * other.size as u128;
+        let rhs = other.fee.base_units() as u128 * self.size as u128;
+        lhs.cmp(&rhs)
+    }
+
+    /// Whether fee per byte is below `rate` base units per 1000 bytes, without rounding.
+    fn is_below_fee_rate(&self, rate: u64) -> bool {
+        (self.fee.base_units() as u128 * 1000) < rate as u128 * self.size as u128
+    }
+
+    /// Fee in base units per 1000 bytes, rounded down.
+    fn fee_rate(&self) -> u64 {
+        let rate = self.fee.base_units() as u128 * 1000 / self.size.max(1) as u128;
+        u64::try_from(rate).unwrap_or(u64::MAX)
+    }
+}
+
+/// Lower bounds of fee rate histogram buckets, in base units per 1000 bytes.
+pub const FEE_RATE_BUCKETS: &[u64] = &[
+    0, 1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000,
+];
+
+/// Transactions whose fee rate is at least `min_rate` and below the next bucket's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeRateBucket {
+    /// Base units per 1000 bytes
+    pub min_rate: u64,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Mempool usage and pending demand by fee rate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolInfo {
+    pub usage: MempoolUsage,
+    /// Transactions paying less are not accepted, in base units per 1000 bytes
+    pub min_fee_rate: u64,
+    /// One bucket for each of `FEE_RATE_BUCKETS`, including empty ones
+    pub histogram: Vec<FeeRateBucket>,
+}
+
+/// Transaction in mempool, with the full transaction only if requested.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolEntry {
+    pub txid: BlockDigest,
+    /// Serialized size in bytes
+    pub size: usize,
+    pub fee: Coin,
+    pub transaction: Option<UnverifiedTransaction>,
+}
+
+/// Current memory usage of a mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolUsage {
+    pub count: usize,
+    /// Sum of serialized sizes of transactions
+    pub bytes: usize,
+    pub capacity: usize,
+    /// Number of transactions evicted since the mempool was created
+    pub evicted: u64,
+}
+
+/// Unconfirmed transactions ordered by timestamp, whose total serialized size is capped.
+/// When full, transactions paying the lowest fee per byte are evicted first.
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    entries: Vec<Entry>,
+    bytes: usize,
+    capacity: usize,
+    evicted: u64,
+    min_fee_rate: u64,
+}
+
+impl Mempool {
+    /// Create empty mempool which holds up to `capacity` bytes of transactions.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: vec![],
+            bytes: 0,
+            capacity,
+            evicted: 0,
+            min_fee_rate: 0,
+        }
+    }
+
+    /// Deny transactions paying less than `rate` base units per 1000 bytes.
+    pub fn with_min_fee_rate(mut self, rate: u64) -> Self {
+        self.min_fee_rate = rate;
+        self
+    }
+
+    /// Base units per 1000 bytes which accepted transactions pay at least.
+    pub fn min_fee_rate(&self) -> u64 {
+        self.min_fee_rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn usage(&self) -> MempoolUsage {
+        MempoolUsage {
+            count: self.entries.len(),
+            bytes: self.bytes,
+            capacity: self.capacity,
+            evicted: self.evicted,
+        }
+    }
+
+    pub fn contains(&self, txid: &BlockDigest) -> bool {
+        self.entries.iter().any(|e| &e.txid == txid)
+    }
+
+    /// Transactions in order of timestamp.
+    pub fn transactions(&self) -> impl Iterator<Item = &Arc<VerifiedTransaction>> + '_ {
+        self.entries.iter().map(|e| &e.transaction)
+    }
+
+    pub fn info(&self) -> MempoolInfo {
+        let mut histogram = FEE_RATE_BUCKETS
+            .iter()
+            .map(|&min_rate| FeeRateBucket {
+                min_rate,
+                count: 0,
+                bytes: 0,
+            })
+            .collect_vec();
+        for entry in self.entries.iter() {
+            let rate = entry.fee_rate();
+            let index = FEE_RATE_BUCKETS.partition_point(|&min_rate| min_rate <= rate) - 1;
+            histogram[index].count += 1;
+            histogram[index].bytes += entry.size;
+        }
+
+        MempoolInfo {
+            usage: self.usage(),
+            min_fee_rate: self.min_fee_rate,
+            histogram,
+        }
+    }
+
+    /// Transactions in order of timestamp. `verbose` includes full transactions.
+    pub fn entries(&self, verbose: bool) -> Vec<MempoolEntry> {
+        self.entries
+            .iter()
+            .map(|e| MempoolEntry {
+                txid: e.txid.clone(),
+                size: e.size,
+                fee: e.fee,
+                transaction: verbose.then(|| e.transaction.to_unverified()),
+            })
+            .collect()
+    }
+
+    /// Balance of `address` once every transaction here is included, from its `confirmed` one.
+    pub fn pending_balance(&self, address: &Address, confirmed: Coin) -> Coin {
+        let sum_to = |transitions: &[Transition<Verified>]| {
+            transitions
+                .iter()
+                .filter(|t| t.receiver() == address)
+                .map(Transition::quantity)
+                .sum::<Coin>()
+        };
+        let (received, spent) = self.transactions().fold(
+            (Coin::default(), Coin::default()),
+            |(received, spent), tx| (received + sum_to(tx.outputs()), spent + sum_to(tx.inputs())),
+        );
+        (confirmed + received)
+            .checked_sub(spent)
+            .unwrap_or_default()
+    }
+
+    /// Shared references to all transactions, for block assembly.
+    pub fn snapshot(&self) -> Vec<Arc<VerifiedTransaction>> {
+        self.transactions().cloned().collect()
+    }
+
+    /// Add a transaction, evicting ones paying a lower fee rate if there is no room.
+    /// Returns the evicted transactions.
+    pub fn insert(
+        &mut self,
+        transaction: Arc<VerifiedTransaction>,
+    ) -> Result<Vec<Arc<VerifiedTransaction>>, MempoolError> {
+        let txid = transaction.txid();
+        if self.contains(&txid) {
+            return Err(MempoolError::Duplicated);
+        }
+
+        let size = bincode::serialized_size(transaction.as_ref())
+            .expect("Transaction is serializable") as usize;
+        if size > self.capacity {
+            return Err(MempoolError::TooLarge);
+        }
+
+        let entry = Entry {
+            fee: transaction.fee(),
+            transaction,
+            txid,
+            size,
+        };
+        if entry.is_below_fee_rate(self.min_fee_rate) {
+            return Err(MempoolError::FeeTooLow {
+                min_fee_rate: self.min_fee_rate,
+            });
+        }
+
+        // Find cheapest transactions to make room
+        let mut freed = 0;
+        let mut victims = vec![];
+        for (index, victim) in self
+            .entries
+            .iter()
+            .enumerate()
+            .sorted_by(|(_, a), (_, b)| a.cmp_fee_rate(b))
+        {
+            if self.bytes - freed + size <= self.capacity {
+                break;
+            }
+            if victim.cmp_fee_rate(&entry) != Ordering::Less {
+                return Err(MempoolError::Full);
+            }
+            freed += victim.size;
+            victims.push(index);
+        }
+
+        victims.sort_unstable_by(|a, b| b.cmp(a));
+        let evicted = victims
+            .into_iter()
+            .map(|index| self.entries.remove(index).transaction)
+            .collect_vec();
+        self.bytes -= freed;
+        self.evicted += evicted.len() as u64;
+
+        let position = self
+            .entries
+            .partition_point(|e| e.transaction.timestamp() <= entry.transaction.timestamp());
+        self.bytes += entry.size;
+        self.entries.insert(position, entry);
+
+        Ok(evicted)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MempoolError {
+    Duplicated,
+    /// Transaction alone exceeds the capacity.
+    TooLarge,
+    /// No room even after evicting all transactions paying a lower fee rate.
+    Full,
+    /// Transaction pays less than the minimum fee rate of the node.
+    FeeTooLow {
+        min_fee_rate: u64,
+    },
+}
+
+impl Display for MempoolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Duplicated => write!(f, "Transaction is already in mempool"),
+            MempoolError::TooLarge => write!(f, "Transaction exceeds mempool capacity"),
+            MempoolError::Full => write!(f, "Mempool is full of transactions paying more fee"),
+            MempoolError::FeeTooLow { min_fee_rate } => write!(
+                f,
+                "Transaction pays less than the minimum fee rate {} per 1000 bytes",
+                min_fee_rate
+            ),
+        }
+    }
+}
+
+impl Error for MempoolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    /// Transaction spending 100 coins, paying `fee` to the miner.
+    fn offer(fee: u64) -> Arc<VerifiedTransaction> {
+        let alice = SecretAddress::create();
+        let input = Transfer::offer(&alice, alice.to_public_address(), Coin::from(100));
+        let output = Transfer::offer(&alice, alice.to_public_address(), Coin::from(100 - fee));
+        let tx = Transaction::offer(&alice, vec![input], vec![output])
+            .verify_transaction()
+            .unwrap();
+        Arc::new(tx)
+    }
+
+    fn size(tx: &VerifiedTransaction) -> usize {
+        bincode::serialized_size(tx).unwrap() as usize
+    }
+
+    #[test]
+    fn test_mempool_eviction() {
+        let cheap = offer(1);
+        let rich = offer(10);
+        let size = size(&cheap);
+        let mut mempool = Mempool::new(size * 2);
+
+        assert_eq!(Ok(vec![]), mempool.insert(cheap.clone()));
+        assert_eq!(Ok(vec![]), mempool.insert(rich.clone()));
+        assert_eq!(Err(MempoolError::Duplicated), mempool.insert(rich.clone()));
+
+        // Lower fee rate than every transaction in mempool
+        assert_eq!(Err(MempoolError::Full), mempool.insert(offer(0)));
+
+        let richer = offer(20);
+        assert_eq!(Ok(vec![cheap.clone()]), mempool.insert(richer.clone()));
+        assert!(!mempool.contains(&cheap.txid()));
+        assert_eq!(vec![rich.clone(), richer.clone()], mempool.snapshot());
+
+        let usage = mempool.usage();
+        assert_eq!(2, usage.count);
+        assert_eq!(size * 2, usage.bytes);
+        assert_eq!(1, usage.evicted);
+
+        let info = mempool.info();
+        assert_eq!(usage, info.usage);
+        assert_eq!(FEE_RATE_BUCKETS.len(), info.histogram.len());
+        assert_eq!(2, info.histogram.iter().map(|b| b.count).sum::<usize>());
+        assert_eq!(
+            size * 2,
+            info.histogram.iter().map(|b| b.bytes).sum::<usize>()
+        );
+        // Rich pays 10 base units, which falls in the lowest non-empty bucket
+        let lowest = info.histogram.iter().position(|b| b.count > 0).unwrap();
+        let rich_rate = 10 * 1000 / size as u64;
+        assert!(FEE_RATE_BUCKETS[lowest] <= rich_rate);
+        assert!(FEE_RATE_BUCKETS
+            .get(lowest + 1)
+            .is_none_or(|&next| rich_rate < next));
+
+        let entries = mempool.entries(false);
+        assert_eq!(
+            vec![rich.txid(), richer.txid()],
+            entries.iter().map(|e| e.txid.clone()).collect_vec()
+        );
+        assert!(entries.iter().all(|e| e.transaction.is_none()));
+        let entries = mempool.entries(true);
+        assert_eq!(Some(rich.to_unverified()), entries[0].transaction);
+
+        mempool.clear();
+        assert!(mempool.is_empty());
+        assert_eq!(0, mempool.usage().bytes);
+    }
+
+    #[test]
+    fn test_mempool_min_fee_rate() {
+        let tx = offer(10);
+        let rate = 10 * 1000 / size(&tx) as u64;
+        let mut mempool = Mempool::new(1 << 20).with_min_fee_rate(rate + 1);
+        assert_eq!(rate + 1, mempool.info().min_fee_rate);
+        assert_eq!(
+            Err(MempoolError::FeeTooLow {
+                min_fee_rate: rate + 1
+            }),
+            mempool.insert(tx.clone())
+        );
+
+        let mut mempool = Mempool::new(1 << 20).with_min_fee_rate(rate);
+        assert_eq!(Ok(vec![]), mempool.insert(tx));
+    }
+
+    #[test]
+    fn test_mempool_too_large() {
+        let tx = offer(1);
+        let mut mempool = Mempool::new(size(&tx) - 1);
+        assert_eq!(Err(MempoolError::TooLarge), mempool.insert(tx));
+    }
+
+    #[test]
+    fn test_pending_balance() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create();
+        let input = Transfer::offer(&alice, alice.to_public_address(), Coin::from(100));
+        let outputs = vec![
+            Transfer::offer(&alice, bob.to_public_address(), Coin::from(60)),
+            Transfer::offer(&alice, alice.to_public_address(), Coin::from(39)),
+        ];
+        let tx = Transaction::offer(&alice, vec![input], outputs)
+            .verify_transaction()
+            .unwrap();
+        let mut mempool = Mempool::new(1 << 20);
+        mempool.insert(Arc::new(tx)).unwrap();
+
+        let (alice, bob) = (alice.to_public_address(), bob.to_public_address());
+        assert_eq!(
+            Coin::from(139),
+            mempool.pending_balance(&alice, Coin::from(200))
+        );
+        assert_eq!(Coin::from(65), mempool.pending_balance(&bob, Coin::from(5)));
+        // Confirmed balance out of date with the mempool
+        assert_eq!(
+            Coin::default(),
+            mempool.pending_balance(&alice, Coin::default())
+        );
+    }
+}
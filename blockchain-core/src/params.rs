@@ -0,0 +1,77 @@
+use crate::authority::{Authorities, Consensus};
+use crate::block::{block_coin_generation_rule, BlockHeight};
+use crate::coin::Coin;
+use crate::deployment::DeploymentSchedule;
+use crate::digest::PowAlgorithm;
+
+/// Consensus rules which every node of a chain must agree on.
+#[derive(Debug, Clone, Copy)]
+pub struct ChainParams {
+    generation_rule: fn(BlockHeight) -> Coin,
+    deployments: DeploymentSchedule,
+    pow_algorithm: PowAlgorithm,
+    consensus: Consensus,
+}
+
+impl ChainParams {
+    /// `generation_rule`: Coins a block may mint at its height
+    pub fn new(generation_rule: fn(BlockHeight) -> Coin) -> Self {
+        Self {
+            generation_rule,
+            deployments: DeploymentSchedule::NONE,
+            pow_algorithm: PowAlgorithm::Sha256,
+            consensus: Consensus::ProofOfWork,
+        }
+    }
+
+    pub fn with_deployments(self, deployments: DeploymentSchedule) -> Self {
+        Self {
+            deployments,
+            ..self
+        }
+    }
+
+    /// Hash blocks by `pow_algorithm` instead of SHA-256, as a private network may.
+    pub fn with_pow_algorithm(self, pow_algorithm: PowAlgorithm) -> Self {
+        Self {
+            pow_algorithm,
+            ..self
+        }
+    }
+
+    /// Let `authorities` seal blocks in turn instead of Proof-of-Work, as a private network may.
+    pub fn with_authorities(self, authorities: Authorities) -> Self {
+        Self {
+            consensus: Consensus::ProofOfAuthority(authorities),
+            ..self
+        }
+    }
+
+    pub fn deployments(&self) -> &DeploymentSchedule {
+        &self.deployments
+    }
+
+    pub fn pow_algorithm(&self) -> PowAlgorithm {
+        self.pow_algorithm
+    }
+
+    pub fn consensus(&self) -> Consensus {
+        self.consensus
+    }
+
+    pub fn generation_rule(&self) -> fn(BlockHeight) -> Coin {
+        self.generation_rule
+    }
+
+    /// Coins a block may mint at `height`.
+    pub fn generation(&self, height: BlockHeight) -> Coin {
+        (self.generation_rule)(height)
+    }
+}
+
+impl Default for ChainParams {
+    /// Rules of the main chain.
+    fn default() -> Self {
+        Self::new(block_coin_generation_rule).with_deployments(DeploymentSchedule::MAIN)
+    }
+}
@@ -0,0 +1,688 @@
+//! Ledger whose blocks are kept on disk, so that a node restarts with the chain it had.
+//!
+//! Blocks are appended to a log file in the order they enter the ledger, in which every block
+//! follows its parent. Each block is synced to disk before `entry` returns. A block cut off by a
+//! crash is dropped on open, and the blocks before it are verified and entered again.
+//! Blocks are stored compact, whose inputs are expanded on open from the blocks entered before.
+//! Blocks removed from the ledger stay in the file until it is rewritten without them.
+//!
+//! The file starts with `MAGIC` and the format version of its records. A file of another
+//! version, or a complete record which does not decode, fails the open instead of being dropped.
+
+use crate::block::BlockHeight;
+use crate::compact::CompactBlock;
+use crate::digest::BlockDigest;
+use crate::ledger::{Ledger, LedgerError};
+use crate::{UnverifiedBlock, VerifiedBlock};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = "blocks.db";
+
+/// First bytes of a block store, followed by `FORMAT_VERSION` in little endian.
+const MAGIC: [u8; 4] = *b"BCBK";
+
+/// Version of the record encoding, bumped whenever the block encoding changes.
+const FORMAT_VERSION: u32 = 3;
+
+const HEADER_LEN: u64 = 8;
+
+/// Removed blocks are rewritten away once they outnumber both this and the blocks in the ledger.
+const COMPACTION_THRESHOLD: usize = 1024;
+
+/// `Ledger` which writes every block entered to a directory, if it has one.
+/// Reads go to the ledger through `Deref`, while changes go through this.
+#[derive(Debug)]
+pub struct PersistentLedger {
+    ledger: Ledger,
+    store: Option<BlockStore>,
+}
+
+#[derive(Debug)]
+struct BlockStore {
+    path: PathBuf,
+    file: File,
+    /// Blocks in the file, of which those not in the ledger were removed from it
+    records: usize,
+}
+
+impl PersistentLedger {
+    /// Keep blocks in memory only, as `Ledger` does.
+    pub fn in_memory(ledger: Ledger) -> Self {
+        Self {
+            ledger,
+            store: None,
+        }
+    }
+
+    /// Open the blocks stored in `dir` into `ledger`, creating an empty store if there is none.
+    /// Blocks already in `ledger` but not stored, such as reindexed ones, are stored too.
+    ///
+    /// `verify` checks each stored block against the ledger so far, as for a received block.
+    /// A denied or undecodable block fails the open, unless `recover`, which drops it and every
+    /// later block from the file instead. So does a file of another format version, which
+    /// `recover` empties.
+    pub fn open<E: Display>(
+        dir: impl AsRef<Path>,
+        ledger: Ledger,
+        recover: bool,
+        mut verify: impl FnMut(UnverifiedBlock, &Ledger) -> Result<VerifiedBlock, E>,
+    ) -> Result<Self, PersistentLedgerError> {
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(FILE_NAME);
+        let file = open_file(&path)?;
+
+        let mut ledger = ledger;
+        let mut stored = HashSet::new();
+        let mut records = 0;
+        let mut reader = BufReader::new(&file);
+        // Offset of the next record, or 0 to start the file over
+        let mut len = match read_header(&mut reader)? {
+            // New file, or one whose header a crash cut off
+            Header::Short => 0,
+            Header::Version(FORMAT_VERSION) => HEADER_LEN,
+            Header::Version(version) if !recover => {
+                return Err(PersistentLedgerError::UnsupportedVersion(version))
+            }
+            Header::Foreign if !recover => {
+                return Err(PersistentLedgerError::Corrupt {
+                    offset: 0,
+                    reason: "Not a block store".to_string(),
+                })
+            }
+            Header::Version(_) | Header::Foreign => 0,
+        };
+        while len > 0 {
+            let (bytes, size) = match read_record(&mut reader)? {
+                Some(record) => record,
+                None => break,
+            };
+            let block = match bincode::deserialize::<CompactBlock>(&bytes) {
+                Ok(block) => block,
+                Err(_) if recover => break,
+                Err(e) => {
+                    return Err(PersistentLedgerError::Corrupt {
+                        offset: len,
+                        reason: e.to_string(),
+                    })
+                }
+            };
+            let (height, digest) = (block.height(), block.digest().clone());
+            let res = match ledger.expand_block(block) {
+                Ok(block) => match verify(block, &ledger) {
+                    Ok(block) => match ledger.entry(block) {
+                        // Stored before, then entered again by a reindex
+                        Ok(()) | Err(LedgerError::DuplicatedBlock) => Ok(()),
+                        Err(e) => Err(e.to_string()),
+                    },
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(reason) = res {
+                if !recover {
+                    return Err(PersistentLedgerError::Denied {
+                        height,
+                        digest,
+                        reason,
+                    });
+                }
+                break;
+            }
+            stored.insert(digest);
+            records += 1;
+            len += size;
+        }
+        // Drop a block which a crash cut off, or which is denied
+        file.set_len(len)?;
+        if len == 0 {
+            write_header(&file)?;
+        }
+
+        let mut store = BlockStore {
+            path,
+            file,
+            records,
+        };
+        let unstored = ledger
+            .blocks()
+            .filter(|block| !stored.contains(block.digest()))
+            .collect::<Vec<_>>();
+        if !unstored.is_empty() {
+            let mut writer = BufWriter::new(&store.file);
+            for block in unstored.iter() {
+                write_record(&mut writer, &ledger, block)?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_data()?;
+            store.records += unstored.len();
+        }
+
+        Ok(Self {
+            ledger,
+            store: Some(store),
+        })
+    }
+
+    /// Directory of the stored blocks, or `None` if kept in memory only.
+    pub fn dir(&self) -> Option<&Path> {
+        self.store.as_ref().and_then(|store| store.path.parent())
+    }
+
+    /// Enter `block` into the ledger, then store it.
+    /// If storing fails, the block is removed from the ledger again.
+    pub fn entry(&mut self, block: VerifiedBlock) -> Result<(), PersistentLedgerError> {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return Ok(self.ledger.entry(block)?),
+        };
+
+        let digest = block.digest().clone();
+        self.ledger.entry(block)?;
+        let block = self.ledger.get(&digest).expect("Block is just entered");
+        let res = append_synced(&mut store.file, |file| {
+            write_record(file, &self.ledger, block)
+        });
+        if let Err(e) = res {
+            self.ledger.remove_branch(&digest);
+            return Err(e);
+        }
+        store.records += 1;
+        Ok(())
+    }
+
+    /// Remove the block and its descendants, as `Ledger::remove_branch` does.
+    pub fn remove_branch(
+        &mut self,
+        digest: &BlockDigest,
+    ) -> Result<Option<VerifiedBlock>, PersistentLedgerError> {
+        let removed = self.ledger.remove_branch(digest);
+        self.compact_if_due()?;
+        Ok(removed)
+    }
+
+    /// Remove stale branches, as `Ledger::prune_stale_branches` does.
+    pub fn prune_stale_branches(
+        &mut self,
+        min_depth_behind: u64,
+    ) -> Result<usize, PersistentLedgerError> {
+        let pruned = self.ledger.prune_stale_branches(min_depth_behind);
+        self.compact_if_due()?;
+        Ok(pruned)
+    }
+
+    /// Rewrite the file with only the blocks in the ledger, once removed ones pile up.
+    fn compact_if_due(&mut self) -> Result<(), PersistentLedgerError> {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let blocks = self.ledger.blocks().count();
+        if store.records - blocks <= COMPACTION_THRESHOLD.max(blocks) {
+            return Ok(());
+        }
+
+        let temp_path = store.path.with_extension("compact");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write_header(&mut writer)?;
+        for block in self.ledger.blocks() {
+            write_record(&mut writer, &self.ledger, block)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+
+        std::fs::rename(&temp_path, &store.path)?;
+        store.file = open_file(&store.path)?;
+        store.records = blocks;
+        Ok(())
+    }
+}
+
+impl Deref for PersistentLedger {
+    type Target = Ledger;
+
+    fn deref(&self) -> &Ledger {
+        &self.ledger
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+}
+
+fn write_header(mut writer: impl Write) -> io::Result<()> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    writer.write_all(&header)?;
+    writer.flush()
+}
+
+enum Header {
+    /// File is shorter than the header
+    Short,
+    /// File is not a block store, such as one written before the header existed
+    Foreign,
+    Version(u32),
+}
+
+fn read_header(reader: &mut impl Read) -> io::Result<Header> {
+    let mut header = [0; HEADER_LEN as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Header::Short),
+        Err(e) => return Err(e),
+    }
+    let (magic, version) = header.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Ok(Header::Foreign);
+    }
+    let version = u32::from_le_bytes(version.try_into().expect("Header is 8 bytes"));
+    Ok(Header::Version(version))
+}
+
+/// Append by `write` and sync the file. If either fails, the file is cut back to its length
+/// before, so that no part of the record is left for later ones to follow.
+fn append_synced(
+    file: &mut File,
+    write: impl FnOnce(&mut File) -> Result<(), PersistentLedgerError>,
+) -> Result<(), PersistentLedgerError> {
+    let len = file.metadata()?.len();
+    let res = write(file).and_then(|_| Ok(file.sync_data()?));
+    if res.is_err() {
+        file.set_len(len)?;
+    }
+    res
+}
+
+/// Write a block in `ledger` compact, prefixed by its length.
+fn write_record(
+    writer: &mut impl Write,
+    ledger: &Ledger,
+    block: &VerifiedBlock,
+) -> Result<(), PersistentLedgerError> {
+    let block = ledger
+        .compact_block(block)
+        .expect("Block in the ledger spends outputs of its chain");
+    let bytes = bincode::serialize(&block)?;
+    write_bytes(writer, &bytes)?;
+    Ok(())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    // One write, so that a record is not split between appends
+    let mut record = Vec::with_capacity(4 + bytes.len());
+    record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    record.extend_from_slice(bytes);
+    writer.write_all(&record)
+}
+
+/// Read the bytes of a record and its size, or `None` at the end or at a record cut off midway.
+/// The bytes are read as far as they exist, so a garbage length allocates no more than the file.
+fn read_record(reader: &mut impl Read) -> io::Result<Option<(Vec<u8>, u64)>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u64::from(u32::from_le_bytes(len));
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    match bytes.len() as u64 == len {
+        true => Ok(Some((bytes, 4 + len))),
+        false => Ok(None),
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistentLedgerError {
+    Io(io::Error),
+    Encode(bincode::Error),
+    Ledger(LedgerError),
+    /// Complete record at the offset, or the header, cannot be decoded.
+    Corrupt {
+        offset: u64,
+        reason: String,
+    },
+    /// File was written in another format version.
+    UnsupportedVersion(u32),
+    /// Stored block is denied on open, such as after the rules changed.
+    Denied {
+        height: BlockHeight,
+        digest: BlockDigest,
+        reason: String,
+    },
+}
+
+impl From<io::Error> for PersistentLedgerError {
+    fn from(e: io::Error) -> Self {
+        PersistentLedgerError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for PersistentLedgerError {
+    fn from(e: bincode::Error) -> Self {
+        PersistentLedgerError::Encode(e)
+    }
+}
+
+impl From<LedgerError> for PersistentLedgerError {
+    fn from(e: LedgerError) -> Self {
+        PersistentLedgerError::Ledger(e)
+    }
+}
+
+impl Display for PersistentLedgerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistentLedgerError::Io(e) => write!(f, "Block store I/O failed: {}", e),
+            PersistentLedgerError::Encode(e) => write!(f, "Cannot encode block: {}", e),
+            PersistentLedgerError::Ledger(e) => e.fmt(f),
+            PersistentLedgerError::Corrupt { offset, reason } => {
+                write!(f, "Block store is corrupt at offset {}: {}", offset, reason)
+            }
+            PersistentLedgerError::UnsupportedVersion(version) => write!(
+                f,
+                "Block store has format version {}, but this node reads {}",
+                version, FORMAT_VERSION
+            ),
+            PersistentLedgerError::Denied {
+                height,
+                digest,
+                reason,
+            } => write!(
+                f,
+                "Stored block {} at height {} is denied: {}",
+                digest.fmt_short(),
+                height,
+                reason
+            ),
+        }
+    }
+}
+
+impl Error for PersistentLedgerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PersistentLedgerError::Io(e) => Some(e),
+            PersistentLedgerError::Encode(e) => Some(e),
+            PersistentLedgerError::Ledger(e) => Some(e),
+            PersistentLedgerError::Corrupt { .. }
+            | PersistentLedgerError::UnsupportedVersion(_)
+            | PersistentLedgerError::Denied { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::test_utils::{fabricate, mine, params, reward};
+    use crate::{Difficulty, SecretAddress, Transaction, Transfer};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("persistent_ledger_{}_{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn verify(block: UnverifiedBlock, ledger: &Ledger) -> Result<VerifiedBlock, String> {
+        let block = block
+            .verify_transaction_itself()
+            .and_then(|b| b.verify_transaction_relation_with(ledger.params()))
+            .and_then(|b| b.verify_difficulty(&Difficulty::new(0)))
+            .and_then(|b| b.verify_digest())
+            .map_err(|e| e.to_string())?;
+        ledger.verify_block(block).map_err(|e| e.to_string())
+    }
+
+    /// Mine `count` empty blocks on the tip.
+    fn extend(ledger: &mut PersistentLedger, miner: &SecretAddress, count: usize) {
+        for _ in 0..count {
+            let previous = ledger.search_latest_block().map(|b| b.digest().clone());
+            let block = fabricate(
+                ledger,
+                previous.as_ref(),
+                vec![],
+                miner,
+                Difficulty::new(0),
+                &SystemClock,
+            )
+            .unwrap();
+            ledger.entry(block).unwrap();
+        }
+    }
+
+    fn tip(ledger: &Ledger) -> Option<BlockDigest> {
+        ledger.search_latest_block().map(|b| b.digest().clone())
+    }
+
+    #[test]
+    fn test_reopen_after_crash() {
+        let dir = temp_dir("reopen");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 3);
+        let expected = tip(&ledger);
+        drop(ledger);
+
+        // A block cut off midway by a crash
+        let mut file = open_file(&dir.join(FILE_NAME)).unwrap();
+        file.write_all(&100u32.to_le_bytes()).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        assert_eq!(3, ledger.blocks().count());
+        extend(&mut ledger, &miner, 1);
+        let expected = tip(&ledger);
+        drop(ledger);
+
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_failed_write() {
+        let dir = temp_dir("failed_write");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 1);
+
+        // A write which fails after a part of the record, as on a full disk
+        let file = &mut ledger.store.as_mut().unwrap().file;
+        let res = append_synced(file, |file| {
+            file.write_all(&100u32.to_le_bytes())?;
+            file.write_all(&[1, 2, 3])?;
+            Err(io::Error::new(io::ErrorKind::StorageFull, "disk full").into())
+        });
+        assert!(matches!(res, Err(PersistentLedgerError::Io(_))));
+
+        // Later blocks do not follow the part written
+        extend(&mut ledger, &miner, 1);
+        let expected = tip(&ledger);
+        drop(ledger);
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        assert_eq!(2, ledger.blocks().count());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_undecodable_block() {
+        let dir = temp_dir("undecodable");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 2);
+        let expected = tip(&ledger);
+        drop(ledger);
+
+        // A complete record in an encoding this node does not know, with a huge garbage one after it
+        let path = dir.join(FILE_NAME);
+        let mut file = open_file(&path).unwrap();
+        write_bytes(&mut file, &[0xff; 16]).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        let len = file.metadata().unwrap().len();
+
+        let res = PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify);
+        assert!(matches!(res, Err(PersistentLedgerError::Corrupt { .. })));
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), true, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        assert!(std::fs::metadata(&path).unwrap().len() < len);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let dir = temp_dir("version");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 1);
+        drop(ledger);
+
+        let path = dir.join(FILE_NAME);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[MAGIC.len()..HEADER_LEN as usize]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let res = PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify);
+        assert!(matches!(
+            res,
+            Err(PersistentLedgerError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+
+        // Recovery starts the file over in the current version
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), true, verify).unwrap();
+        assert_eq!(0, ledger.blocks().count());
+        drop(ledger);
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(0, ledger.blocks().count());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reopen_spending_block() {
+        let dir = temp_dir("spending");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 1);
+        let reward = reward(ledger.search_latest_block().unwrap());
+        let output = Transfer::offer(&miner, miner.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&miner, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let previous = tip(&ledger);
+        let block = fabricate(
+            &ledger,
+            previous.as_ref(),
+            vec![tx],
+            &miner,
+            Difficulty::new(0),
+            &SystemClock,
+        )
+        .unwrap();
+        let full = bincode::serialize(&block).unwrap().len() as u64;
+        ledger.entry(block).unwrap();
+        let expected = tip(&ledger);
+        drop(ledger);
+
+        // Spent transitions are stored as outpoints, and expanded again on open
+        let path = dir.join(FILE_NAME);
+        let mut reader = BufReader::new(File::open(&path).unwrap());
+        read_header(&mut reader).unwrap();
+        read_record(&mut reader).unwrap().unwrap();
+        let (_, size) = read_record(&mut reader).unwrap().unwrap();
+        assert!(size < full);
+
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_store_reindexed_blocks() {
+        let dir = temp_dir("reindexed");
+        let miner = SecretAddress::create();
+        let mut reindexed = Ledger::with_params(params());
+        mine(&mut reindexed, vec![], &miner).unwrap();
+        mine(&mut reindexed, vec![], &miner).unwrap();
+        let expected = tip(&reindexed);
+
+        let ledger = PersistentLedger::open(&dir, reindexed, false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        drop(ledger);
+
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(expected, tip(&ledger));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_denied_block() {
+        let dir = temp_dir("denied");
+        let miner = SecretAddress::create();
+        let mut ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        extend(&mut ledger, &miner, 3);
+        let genesis = ledger
+            .search_latest_chain()
+            .last()
+            .unwrap()
+            .digest()
+            .clone();
+        drop(ledger);
+
+        let deny_later = |block: UnverifiedBlock, ledger: &Ledger| match block.height().is_genesis()
+        {
+            true => verify(block, ledger),
+            false => Err("denied".to_string()),
+        };
+        let res = PersistentLedger::open(&dir, Ledger::with_params(params()), false, deny_later);
+        assert!(matches!(
+            res,
+            Err(PersistentLedgerError::Denied { height, .. }) if height == BlockHeight::from(1)
+        ));
+
+        // Recovery drops the denied block and every later one
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), true, deny_later).unwrap();
+        assert_eq!(Some(genesis.clone()), tip(&ledger));
+        drop(ledger);
+        let ledger =
+            PersistentLedger::open(&dir, Ledger::with_params(params()), false, verify).unwrap();
+        assert_eq!(Some(genesis), tip(&ledger));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,251 @@
+//! Property tests of ledger invariants over random block trees.
+//! Blocks are fabricated deterministically, so a failing case shrinks and replays.
+
+use crate::clock::MockClock;
+use crate::digest::BlockDigest;
+use crate::ledger::{Ledger, LedgerError};
+use crate::signature::SighashFlag;
+use crate::test_utils::{fabricate, generation_rule, params};
+use crate::timestamp::Timestamp;
+use crate::{Coin, Difficulty, SecretAddress, Transaction, Transfer, Transition};
+use crate::{Verified, VerifiedBlock};
+use proptest::prelude::*;
+use proptest::sample::Index;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashSet;
+
+const KEY_COUNT: usize = 3;
+
+/// Block to be fabricated on an earlier block.
+#[derive(Debug, Clone)]
+struct Step {
+    /// Which of the blocks fabricated so far to build on
+    parent: Index,
+    /// Leading zero bits, so that branches differ in work and not only in length
+    difficulty: u8,
+    miner: Index,
+    /// Payer, payee among the others, and percentage of the payer's first coin to pay
+    payment: Option<(Index, Index, u8)>,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    (
+        any::<Index>(),
+        0..3_u8,
+        any::<Index>(),
+        proptest::option::of((any::<Index>(), any::<Index>(), 1..=100_u8)),
+    )
+        .prop_map(|(parent, difficulty, miner, payment)| Step {
+            parent,
+            difficulty,
+            miner,
+            payment,
+        })
+}
+
+/// Block tree grown by `steps`, with its blocks in the order they were entered.
+struct Tree {
+    ledger: Ledger,
+    blocks: Vec<VerifiedBlock>,
+}
+
+fn keys() -> Vec<SecretAddress> {
+    (0..KEY_COUNT)
+        .map(|i| SecretAddress::create_with_rng(&mut StdRng::seed_from_u64(i as u64)))
+        .collect()
+}
+
+fn grow(steps: &[Step]) -> Tree {
+    let keys = keys();
+    let start = Timestamp::enix_epoch()
+        .checked_add_secs(1_700_000_000)
+        .unwrap();
+    let clock = MockClock::new(start);
+    let mut ledger = Ledger::with_params(params());
+
+    let genesis = fabricate(&ledger, None, vec![], &keys[0], Difficulty::new(0), &clock).unwrap();
+    ledger.entry(genesis.clone()).unwrap();
+    let mut blocks = vec![genesis];
+
+    for step in steps {
+        // Every block is a second later than the previous one, whichever branch it is on
+        clock.advance_secs(1);
+        let parent = step.parent.get(&blocks).digest().clone();
+
+        let transactions = step
+            .payment
+            .and_then(|(payer, payee, percent)| {
+                // Paying oneself half of a coin would create two identical outputs
+                let payer_index = payer.index(KEY_COUNT);
+                let payee_index = (payer_index + 1 + payee.index(KEY_COUNT - 1)) % KEY_COUNT;
+                let payer = &keys[payer_index];
+                let payee = keys[payee_index].to_public_address();
+                let coin = ledger
+                    .build_utxos(&parent, &payer.to_public_address())
+                    .into_iter()
+                    .next()?;
+                let paid = coin.quantity().base_units() * u64::from(percent) / 100;
+                let change = coin.quantity().base_units() - paid;
+                let outputs = vec![
+                    Transfer::offer_with_clock(payer, payee, Coin::from(paid), &clock),
+                    Transfer::offer_with_clock(
+                        payer,
+                        payer.to_public_address(),
+                        Coin::from(change),
+                        &clock,
+                    ),
+                ];
+                Transaction::offer_with_clock(
+                    payer,
+                    vec![coin],
+                    outputs,
+                    SighashFlag::All,
+                    None,
+                    &clock,
+                )
+                .verify_transaction()
+                .ok()
+            })
+            .into_iter()
+            .collect();
+
+        let block = fabricate(
+            &ledger,
+            Some(&parent),
+            transactions,
+            step.miner.get(&keys),
+            Difficulty::new(step.difficulty),
+            &clock,
+        )
+        .unwrap();
+        ledger.entry(block.clone()).unwrap();
+        blocks.push(block);
+    }
+
+    Tree { ledger, blocks }
+}
+
+/// UTXOs of the chain up to `tip`, by applying each transaction in order.
+fn replay_utxos(ledger: &Ledger, tip: &BlockDigest) -> Vec<Transition<Verified>> {
+    let mut utxos: Vec<Transition<Verified>> = vec![];
+    for block in ledger.downstream_chain_to(tip) {
+        for tx in block.transactions() {
+            for input in tx.inputs() {
+                let spent = utxos.iter().position(|utxo| utxo == input);
+                utxos.swap_remove(spent.expect("Input is unspent"));
+            }
+            utxos.extend(tx.outputs().iter().cloned());
+        }
+    }
+    utxos
+}
+
+fn same_multiset(a: &[Transition<Verified>], b: &[Transition<Verified>]) -> bool {
+    let mut b = b.to_vec();
+    a.iter()
+        .all(|item| match b.iter().position(|other| other == item) {
+            Some(i) => {
+                b.swap_remove(i);
+                true
+            }
+            None => false,
+        })
+        && b.is_empty()
+}
+
+fn leaves(ledger: &Ledger) -> HashSet<BlockDigest> {
+    ledger
+        .leaf_blocks()
+        .map(|block| block.digest().clone())
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn best_tip_has_most_work(steps in proptest::collection::vec(step(), 0..16)) {
+        let tree = grow(&steps);
+        let ledger = &tree.ledger;
+
+        let tip = ledger.search_latest_block().unwrap();
+        let tip_work = ledger.cumulative_work(tip.digest());
+        for leaf in ledger.leaf_blocks() {
+            prop_assert!(ledger.cumulative_work(leaf.digest()) <= tip_work);
+        }
+    }
+
+    #[test]
+    fn utxos_are_sum_of_chain(steps in proptest::collection::vec(step(), 0..16)) {
+        let tree = grow(&steps);
+        let ledger = &tree.ledger;
+
+        for leaf in ledger.leaf_blocks() {
+            let snapshot = ledger.utxo_snapshot(leaf.digest());
+            prop_assert!(same_multiset(&snapshot, &replay_utxos(ledger, leaf.digest())));
+
+            // Fees return to miners, so coins are exactly what was generated
+            let supply = snapshot.iter().map(Transition::quantity).sum::<Coin>();
+            let generated = ledger
+                .downstream_chain_to(leaf.digest())
+                .map(|block| generation_rule(block.height()))
+                .sum::<Coin>();
+            prop_assert_eq!(generated, supply);
+        }
+    }
+
+    #[test]
+    fn reorg_then_reapplication_is_idempotent(
+        steps in proptest::collection::vec(step(), 1..16),
+        removed in any::<Index>(),
+    ) {
+        let Tree { mut ledger, blocks } = grow(&steps);
+        let leaves_before = leaves(&ledger);
+        let tip_work = ledger.cumulative_work(ledger.search_latest_block().unwrap().digest());
+        let snapshots = leaves_before
+            .iter()
+            .map(|leaf| (leaf.clone(), ledger.utxo_snapshot(leaf)))
+            .collect::<Vec<_>>();
+
+        // Every block is known already
+        for block in blocks.iter() {
+            prop_assert!(matches!(
+                ledger.entry(block.clone()),
+                Err(LedgerError::DuplicatedBlock | LedgerError::DuplicatedGenesisBlock)
+            ));
+        }
+
+        // Remove a branch other than genesis, which may hold the best tip
+        let root = removed.get(&blocks[1..]).digest().clone();
+        ledger.remove_branch(&root).unwrap();
+        let mut branch = HashSet::from([root]);
+        let removed_blocks = blocks
+            .iter()
+            .filter(|block| {
+                let in_branch = branch.contains(block.digest())
+                    || branch.contains(block.previous_digest());
+                if in_branch {
+                    branch.insert(block.digest().clone());
+                }
+                in_branch
+            })
+            .collect::<Vec<_>>();
+        for block in removed_blocks.iter() {
+            prop_assert!(ledger.get(block.digest()).is_none());
+        }
+
+        // Entering the branch again restores the same tree
+        for block in removed_blocks {
+            ledger.entry(block.clone()).unwrap();
+        }
+        prop_assert_eq!(leaves_before, leaves(&ledger));
+        prop_assert_eq!(
+            tip_work,
+            ledger.cumulative_work(ledger.search_latest_block().unwrap().digest())
+        );
+        for (leaf, snapshot) in snapshots {
+            prop_assert!(same_multiset(&snapshot, &ledger.utxo_snapshot(&leaf)));
+        }
+    }
+}
@@ -0,0 +1,601 @@
+//! Partially signed transaction, which is passed between parties until everyone has signed.
+//!
+//! The unsigned parts are fixed when it is created.
+//! Each party signs its own copy, and the copies are merged and finalized into a transaction.
+//! A watch-only wallet can create one without any secret, leaving signing to the key holder.
+
+use crate::account::{Address, SecretAddress};
+use crate::coin::Coin;
+use crate::signature::{SighashFlag, Signature, SignatureBuilder, SignatureSource};
+use crate::timestamp::Timestamp;
+use crate::transaction::{LockTime, SighashOutput};
+use crate::transition::{build_transfer_signature_source, Transfer, Transition};
+use crate::verification::Yet;
+use crate::{Transaction, UnverifiedTransaction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Transfer output whose sender, the contractor, has not signed yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsignedTransfer {
+    pub receiver: Address,
+    pub quantity: Coin,
+}
+
+/// Message which a signer has to sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignTarget {
+    /// Output at the index, signed by the contractor.
+    Output(usize),
+    /// Transaction itself, signed by the contractor and cosigners.
+    Transaction,
+}
+
+impl Display for SignTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignTarget::Output(index) => write!(f, "output {}", index),
+            SignTarget::Transaction => write!(f, "transaction"),
+        }
+    }
+}
+
+/// Transaction with the signs collected so far.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartiallySignedTransaction {
+    contractor: Address,
+    inputs: Vec<Transition<Yet>>,
+    outputs: Vec<UnsignedTransfer>,
+    timestamp: Timestamp,
+    flag: SighashFlag,
+    lock_time: Option<LockTime>,
+    /// Contractor's sign of each output
+    output_signs: Vec<Option<Signature>>,
+    /// Contractor's sign of the transaction
+    sign: Option<Signature>,
+    /// Signs of cosigners over the transaction
+    cosigns: Vec<(Address, Signature)>,
+    /// Preimages unlocking HTLC inputs
+    preimages: Vec<Vec<u8>>,
+    /// Notes for the parties, such as a description of the payment. Not signed.
+    metadata: BTreeMap<String, String>,
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(
+        contractor: Address,
+        inputs: Vec<Transition<Yet>>,
+        outputs: Vec<UnsignedTransfer>,
+    ) -> Self {
+        let output_signs = vec![None; outputs.len()];
+        Self {
+            contractor,
+            inputs,
+            outputs,
+            timestamp: Timestamp::now(),
+            flag: SighashFlag::All,
+            lock_time: None,
+            output_signs,
+            sign: None,
+            cosigns: vec![],
+            preimages: vec![],
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Commit the signs only to the parts selected by `flag`. Call before signing.
+    pub fn with_flag(mut self, flag: SighashFlag) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    /// Keep blocks before `lock_time` from including the transaction. Call before signing.
+    pub fn with_lock_time(mut self, lock_time: LockTime) -> Self {
+        self.lock_time = Some(lock_time);
+        self
+    }
+
+    /// Reveal a preimage to claim HTLC inputs.
+    pub fn with_preimage(mut self, preimage: Vec<u8>) -> Self {
+        if !self.preimages.contains(&preimage) {
+            self.preimages.push(preimage);
+        }
+        self
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn contractor(&self) -> &Address {
+        &self.contractor
+    }
+
+    pub fn inputs(&self) -> &[Transition<Yet>] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[UnsignedTransfer] {
+        &self.outputs
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn flag(&self) -> SighashFlag {
+        self.flag
+    }
+
+    pub fn lock_time(&self) -> Option<LockTime> {
+        self.lock_time
+    }
+
+    pub fn cosigns(&self) -> &[(Address, Signature)] {
+        &self.cosigns
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Owners of inputs other than the contractor and counterparties of multisig inputs,
+    /// who cosign the transaction.
+    pub fn cosigners(&self) -> Vec<&Address> {
+        let mut cosigners = vec![];
+        for input in self.inputs.iter() {
+            let parties = match input {
+                Transition::Transfer(t) => vec![t.receiver()],
+                Transition::Generation(g) => vec![g.receiver()],
+                Transition::Multisig(m) => vec![m.sender(), m.receiver()],
+                // Claimed by the receiver with the preimage, or refunded to the sender
+                Transition::Htlc(_) => vec![],
+            };
+            for party in parties {
+                if party != &self.contractor && !cosigners.contains(&party) {
+                    cosigners.push(party);
+                }
+            }
+        }
+        cosigners
+    }
+
+    /// Messages which `signer` has not signed yet, with what each of them is for.
+    pub fn messages(&self, signer: &Address) -> Vec<(SignTarget, Vec<u8>)> {
+        let mut messages = vec![];
+        if signer == &self.contractor {
+            for (index, sign) in self.output_signs.iter().enumerate() {
+                if sign.is_none() {
+                    messages.push((SignTarget::Output(index), self.output_sighash(index)));
+                }
+            }
+            if self.sign.is_none() {
+                messages.push((SignTarget::Transaction, self.sighash(signer)));
+            }
+        } else if self.cosigners().contains(&signer) && !self.is_cosigned_by(signer) {
+            messages.push((SignTarget::Transaction, self.sighash(signer)));
+        }
+        messages
+    }
+
+    /// Add a sign of `target` made by `signer` elsewhere, such as by a hardware signer.
+    pub fn add_signature(
+        &mut self,
+        signer: &Address,
+        target: SignTarget,
+        sign: Signature,
+    ) -> Result<(), PsbtError> {
+        let is_contractor = signer == &self.contractor;
+        if !is_contractor && !self.cosigners().contains(&signer) {
+            return Err(PsbtError::NotAParty);
+        }
+        match target {
+            SignTarget::Output(index) => {
+                if !is_contractor {
+                    return Err(PsbtError::NotAParty);
+                }
+                if index >= self.outputs.len() {
+                    return Err(PsbtError::UnknownOutput(index));
+                }
+                if !signer.verify(&self.output_sighash(index), &sign) {
+                    return Err(PsbtError::InvalidSign);
+                }
+                self.output_signs[index] = Some(sign);
+            }
+            SignTarget::Transaction => {
+                if !signer.verify(&self.sighash(signer), &sign) {
+                    return Err(PsbtError::InvalidSign);
+                }
+                if is_contractor {
+                    self.sign = Some(sign);
+                } else {
+                    self.cosigns.retain(|(cosigner, _)| cosigner != signer);
+                    self.cosigns.push((signer.clone(), sign));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sign everything which `signer` has not signed yet.
+    pub fn sign(&mut self, signer: &SecretAddress) -> Result<(), PsbtError> {
+        let address = signer.to_public_address();
+        let messages = self.messages(&address);
+        if messages.is_empty()
+            && address != self.contractor
+            && !self.cosigners().contains(&&address)
+        {
+            return Err(PsbtError::NotAParty);
+        }
+        for (target, message) in messages {
+            self.add_signature(&address, target, signer.sign(&message))?;
+        }
+        Ok(())
+    }
+
+    /// Collect signs, preimages and metadata of another copy of the same transaction.
+    /// Metadata of this copy is kept on conflict.
+    pub fn merge(&mut self, other: &PartiallySignedTransaction) -> Result<(), PsbtError> {
+        if self.contractor != other.contractor
+            || self.inputs != other.inputs
+            || self.outputs != other.outputs
+            || self.timestamp != other.timestamp
+            || self.flag != other.flag
+            || self.lock_time != other.lock_time
+        {
+            return Err(PsbtError::Mismatch);
+        }
+
+        for (sign, other_sign) in self.output_signs.iter_mut().zip(other.output_signs.iter()) {
+            if sign.is_none() {
+                sign.clone_from(other_sign);
+            }
+        }
+        if self.sign.is_none() {
+            self.sign.clone_from(&other.sign);
+        }
+        for (cosigner, sign) in other.cosigns.iter() {
+            if !self.is_cosigned_by(cosigner) {
+                self.cosigns.push((cosigner.clone(), sign.clone()));
+            }
+        }
+        for preimage in other.preimages.iter() {
+            if !self.preimages.contains(preimage) {
+                self.preimages.push(preimage.clone());
+            }
+        }
+        for (key, value) in other.metadata.iter() {
+            self.metadata
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        Ok(())
+    }
+
+    /// Parties whose signs are still missing.
+    pub fn missing_signers(&self) -> Vec<&Address> {
+        let mut missing = vec![];
+        if self.sign.is_none() || self.output_signs.iter().any(Option::is_none) {
+            missing.push(&self.contractor);
+        }
+        missing.extend(
+            self.cosigners()
+                .into_iter()
+                .filter(|cosigner| !self.is_cosigned_by(cosigner)),
+        );
+        missing
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing_signers().is_empty()
+    }
+
+    /// Transaction of the collected signs, which is ready to be sent.
+    /// Every party must have signed. A multisig refund after its timeout needs no PSBT,
+    /// since the sender alone can offer it.
+    pub fn finalize(&self) -> Result<UnverifiedTransaction, PsbtError> {
+        let missing = self.missing_signers();
+        if !missing.is_empty() {
+            return Err(PsbtError::Incomplete(
+                missing.into_iter().cloned().collect(),
+            ));
+        }
+
+        let outputs = self
+            .outputs
+            .iter()
+            .zip(self.output_signs.iter().flatten())
+            .map(|(output, sign)| {
+                Transfer::from_parts(
+                    self.contractor.clone(),
+                    output.receiver.clone(),
+                    output.quantity,
+                    self.timestamp,
+                    sign.clone(),
+                )
+                .into()
+            })
+            .collect();
+        // Transactions list preimages in ascending order
+        let mut preimages = self.preimages.clone();
+        preimages.sort();
+        let tx = Transaction::from_parts(
+            self.contractor.clone(),
+            self.inputs.clone(),
+            outputs,
+            self.timestamp,
+            self.sign.clone().ok_or(PsbtError::Incomplete(vec![]))?,
+            self.flag,
+            preimages,
+            self.cosigns.clone(),
+            self.lock_time,
+        );
+        Ok(tx)
+    }
+
+    fn is_cosigned_by(&self, cosigner: &Address) -> bool {
+        self.cosigns.iter().any(|(c, _)| c == cosigner)
+    }
+
+    fn drafts(&self) -> Vec<DraftOutput<'_>> {
+        self.outputs
+            .iter()
+            .map(|output| DraftOutput {
+                sender: &self.contractor,
+                output,
+                timestamp: self.timestamp,
+            })
+            .collect()
+    }
+
+    fn output_sighash(&self, index: usize) -> Vec<u8> {
+        let output = &self.outputs[index];
+        Transfer::sighash_of(
+            &self.contractor,
+            &output.receiver,
+            output.quantity,
+            self.timestamp,
+        )
+    }
+
+    fn sighash(&self, signer: &Address) -> Vec<u8> {
+        Transaction::sighash_of(
+            signer,
+            &self.contractor,
+            &self.inputs,
+            &self.drafts(),
+            self.timestamp,
+            self.flag,
+            self.lock_time.as_ref(),
+        )
+    }
+}
+
+/// Output as the finalized transaction will write it into the sighash.
+struct DraftOutput<'a> {
+    sender: &'a Address,
+    output: &'a UnsignedTransfer,
+    timestamp: Timestamp,
+}
+
+impl SignatureSource for DraftOutput<'_> {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        // Variant of `Transition::Transfer`
+        builder.write_variant(0);
+        build_transfer_signature_source(
+            self.sender,
+            &self.output.receiver,
+            self.output.quantity,
+            self.timestamp,
+            builder,
+        );
+    }
+}
+
+impl SighashOutput for DraftOutput<'_> {
+    fn sender(&self) -> Option<&Address> {
+        Some(self.sender)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PsbtError {
+    /// Signer is neither the contractor nor a multisig counterparty.
+    NotAParty,
+    /// No output at the index.
+    UnknownOutput(usize),
+    /// Sign does not match the message.
+    InvalidSign,
+    /// Merged copies are of different transactions.
+    Mismatch,
+    /// Signs of these parties are missing.
+    Incomplete(Vec<Address>),
+}
+
+impl Display for PsbtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PsbtError::NotAParty => write!(f, "Signer is not a party of the transaction"),
+            PsbtError::UnknownOutput(index) => write!(f, "No output at index {}", index),
+            PsbtError::InvalidSign => write!(f, "Sign does not match the message"),
+            PsbtError::Mismatch => write!(f, "Partially signed transactions differ"),
+            PsbtError::Incomplete(missing) => {
+                write!(f, "Signs are missing from")?;
+                for address in missing {
+                    write!(f, " {}", address)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for PsbtError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockHeight, Generation, Multisig};
+
+    fn payment(alice: &SecretAddress, bob: &Address) -> PartiallySignedTransaction {
+        let gen = Generation::offer(alice, Coin::from(10)).to_unverified();
+        let outputs = vec![
+            UnsignedTransfer {
+                receiver: bob.clone(),
+                quantity: Coin::from(6),
+            },
+            UnsignedTransfer {
+                receiver: alice.to_public_address(),
+                quantity: Coin::from(3),
+            },
+        ];
+        PartiallySignedTransaction::new(alice.to_public_address(), vec![gen.into()], outputs)
+    }
+
+    #[test]
+    fn test_sign_finalize() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create().to_public_address();
+        let mut psbt = payment(&alice, &bob).with_metadata("memo", "rent");
+        assert_eq!(psbt.missing_signers(), vec![&alice.to_public_address()]);
+        assert!(matches!(psbt.finalize(), Err(PsbtError::Incomplete(_))));
+
+        psbt.sign(&alice).unwrap();
+        assert!(psbt.is_complete());
+        assert!(psbt.messages(&alice.to_public_address()).is_empty());
+
+        let tx = psbt.finalize().unwrap().verify().unwrap();
+        assert_eq!(tx.outputs()[0].receiver(), &bob);
+        assert_eq!(tx.fee(), Coin::from(1));
+    }
+
+    #[test]
+    fn test_watch_only_signing() {
+        // Created from the public address only, signed where the secret is
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create().to_public_address();
+        let mut psbt = payment(&alice, &bob).with_flag(SighashFlag::Single);
+
+        let address = alice.to_public_address();
+        for (target, message) in psbt.messages(&address) {
+            psbt.add_signature(&address, target, alice.sign(&message))
+                .unwrap();
+        }
+        psbt.finalize().unwrap().verify().unwrap();
+    }
+
+    #[test]
+    fn test_multisig_merge() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create();
+        let multisig = Multisig::offer(
+            &alice,
+            bob.to_public_address(),
+            Coin::from(10),
+            BlockHeight::from(100),
+        )
+        .to_unverified();
+        let outputs = vec![UnsignedTransfer {
+            receiver: bob.to_public_address(),
+            quantity: Coin::from(10),
+        }];
+        let unsigned = PartiallySignedTransaction::new(
+            alice.to_public_address(),
+            vec![multisig.into()],
+            outputs,
+        );
+        assert_eq!(unsigned.cosigners(), vec![&bob.to_public_address()]);
+
+        // Either party may sign first
+        let mut by_alice = unsigned.clone();
+        by_alice.sign(&alice).unwrap();
+        assert_eq!(by_alice.missing_signers(), vec![&bob.to_public_address()]);
+        let mut by_bob = unsigned.clone().with_metadata("memo", "close");
+        by_bob.sign(&bob).unwrap();
+        assert_eq!(by_bob.missing_signers(), vec![&alice.to_public_address()]);
+
+        by_alice.merge(&by_bob).unwrap();
+        assert!(by_alice.is_complete());
+        assert_eq!(by_alice.metadata().get("memo").unwrap(), "close");
+
+        let tx = by_alice.finalize().unwrap();
+        assert!(multisig_spendable(&tx));
+        tx.verify().unwrap();
+    }
+
+    #[test]
+    fn test_inputs_of_several_keys() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create();
+        let carol = SecretAddress::create().to_public_address();
+        let inputs = vec![
+            Generation::offer(&alice, Coin::from(6))
+                .to_unverified()
+                .into(),
+            Generation::offer(&bob, Coin::from(4))
+                .to_unverified()
+                .into(),
+        ];
+        let outputs = vec![UnsignedTransfer {
+            receiver: carol,
+            quantity: Coin::from(10),
+        }];
+        let mut psbt = PartiallySignedTransaction::new(alice.to_public_address(), inputs, outputs);
+        assert_eq!(psbt.cosigners(), vec![&bob.to_public_address()]);
+
+        psbt.sign(&alice).unwrap();
+        assert_eq!(psbt.missing_signers(), vec![&bob.to_public_address()]);
+        psbt.sign(&bob).unwrap();
+        psbt.finalize().unwrap().verify().unwrap();
+    }
+
+    fn multisig_spendable(tx: &UnverifiedTransaction) -> bool {
+        tx.check_spend_conditions(Some(BlockHeight::from(0)))
+            .is_ok()
+    }
+
+    #[test]
+    fn test_merge_mismatch() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create().to_public_address();
+        let mut psbt = payment(&alice, &bob);
+        let other = payment(&alice, &alice.to_public_address());
+        assert_eq!(psbt.merge(&other), Err(PsbtError::Mismatch));
+    }
+
+    #[test]
+    fn test_reject_stranger() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create();
+        let mut psbt = payment(&alice, &bob.to_public_address());
+        assert_eq!(psbt.sign(&bob), Err(PsbtError::NotAParty));
+
+        // Sign of another message
+        let address = alice.to_public_address();
+        let sign = alice.sign(b"other");
+        assert_eq!(
+            psbt.add_signature(&address, SignTarget::Transaction, sign.clone()),
+            Err(PsbtError::InvalidSign)
+        );
+        assert_eq!(
+            psbt.add_signature(&address, SignTarget::Output(2), sign),
+            Err(PsbtError::UnknownOutput(2))
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let alice = SecretAddress::create();
+        let bob = SecretAddress::create().to_public_address();
+        let mut psbt = payment(&alice, &bob);
+        psbt.sign(&alice).unwrap();
+
+        let json = serde_json::to_string(&psbt).unwrap();
+        let decoded = serde_json::from_str::<PartiallySignedTransaction>(&json).unwrap();
+        assert_eq!(decoded, psbt);
+        decoded.finalize().unwrap().verify().unwrap();
+    }
+}
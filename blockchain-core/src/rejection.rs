@@ -0,0 +1,125 @@
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Step of block validation, in the order a received block passes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ValidationStage {
+    /// Block at the assumed-valid checkpoint height
+    Checkpoint,
+    TransactionItself,
+    TransactionRelation,
+    Difficulty,
+    /// Seal in place of the difficulty on a proof-of-authority chain
+    Authority,
+    Digest,
+    /// UTXO, timelock and chain linkage against the ledger
+    Ledger,
+    /// Appending the verified block to the ledger
+    Entry,
+}
+
+impl Display for ValidationStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValidationStage::Checkpoint => "checkpoint",
+            ValidationStage::TransactionItself => "transaction-itself",
+            ValidationStage::TransactionRelation => "transaction-relation",
+            ValidationStage::Difficulty => "difficulty",
+            ValidationStage::Authority => "authority",
+            ValidationStage::Digest => "digest",
+            ValidationStage::Ledger => "ledger",
+            ValidationStage::Entry => "entry",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Why and where a block was denied, with enough context to find the block.
+/// `digest` is the claimed one, which may not be derived from the block if the digest stage failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRejection {
+    digest: BlockDigest,
+    height: BlockHeight,
+    stage: ValidationStage,
+    reason: String,
+}
+
+impl BlockRejection {
+    pub fn new<E>(
+        digest: BlockDigest,
+        height: BlockHeight,
+        stage: ValidationStage,
+        reason: E,
+    ) -> Self
+    where
+        E: Display,
+    {
+        Self {
+            digest,
+            height,
+            stage,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn digest(&self) -> &BlockDigest {
+        &self.digest
+    }
+
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    pub fn stage(&self) -> ValidationStage {
+        self.stage
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl Display for BlockRejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Block {} at height {} denied at {} stage: {}",
+            self.digest.fmt_short(),
+            self.height,
+            self.stage,
+            self.reason
+        )
+    }
+}
+
+impl Error for BlockRejection {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockError;
+
+    #[test]
+    fn test_block_rejection() {
+        let digest = BlockDigest::digest(b"block");
+        let rejection = BlockRejection::new(
+            digest.clone(),
+            BlockHeight::from(3),
+            ValidationStage::Digest,
+            BlockError::Digest,
+        );
+
+        assert_eq!(&digest, rejection.digest());
+        assert_eq!(BlockError::Digest.to_string(), rejection.reason());
+        let message = rejection.to_string();
+        assert!(message.contains(&digest.fmt_short()));
+        assert!(message.contains("height 3"));
+        assert!(message.contains("digest stage"));
+
+        let json = serde_json::to_string(&rejection).unwrap();
+        assert_eq!(rejection, serde_json::from_str(&json).unwrap());
+    }
+}
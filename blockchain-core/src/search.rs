@@ -0,0 +1,276 @@
+//! Prefix search over identifiers of the best chain, so that a partial block digest, txid or
+//! address copied from a log still finds what it names.
+//!
+//! Identifiers are kept as lowercase hex in ordered maps, where every key with a prefix
+//! lies in one range.
+
+use crate::account::Address;
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use crate::{VerifiedBlock, VerifiedTransaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Shortest prefix searched, below which nearly everything would match.
+pub const MIN_PREFIX_LEN: usize = 4;
+
+/// Hits returned at most by one search.
+pub const MAX_HITS: usize = 20;
+
+/// Object whose identifier starts with the searched prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchHit {
+    Block {
+        digest: BlockDigest,
+        height: BlockHeight,
+    },
+    Transaction {
+        txid: BlockDigest,
+        block: BlockDigest,
+        height: BlockHeight,
+    },
+    Address {
+        address: Address,
+        /// Transactions of the best chain which it sends or receives in
+        transactions: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchResults {
+    /// Blocks, then transactions, then addresses, each in order of their identifiers
+    pub hits: Vec<SearchHit>,
+    /// Whether more objects match than returned
+    pub truncated: bool,
+}
+
+/// Block digests, txids and addresses of the best chain up to its tip.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    blocks: BTreeMap<String, (BlockDigest, BlockHeight)>,
+    transactions: BTreeMap<String, (BlockDigest, BlockDigest, BlockHeight)>,
+    addresses: BTreeMap<String, (Address, usize)>,
+    tip: Option<(BlockHeight, BlockDigest)>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block up to which this is indexed, or `None` before genesis.
+    pub fn tip(&self) -> Option<&(BlockHeight, BlockDigest)> {
+        self.tip.as_ref()
+    }
+
+    /// Index `block`, which must follow the tip.
+    pub fn connect_block(&mut self, block: &VerifiedBlock) -> Result<(), SearchError> {
+        let follows = match &self.tip {
+            Some((_, digest)) => block.previous_digest() == digest,
+            None => block.height().is_genesis(),
+        };
+        if !follows {
+            return Err(SearchError::NotOnTip);
+        }
+
+        let digest = block.digest().clone();
+        self.blocks
+            .insert(digest.to_string(), (digest.clone(), block.height()));
+        for tx in block.transactions() {
+            let txid = tx.txid();
+            self.transactions
+                .insert(txid.to_string(), (txid, digest.clone(), block.height()));
+            for address in Self::addresses_of(tx) {
+                self.addresses
+                    .entry(address.to_string())
+                    .or_insert_with(|| (address.clone(), 0))
+                    .1 += 1;
+            }
+        }
+        self.tip = Some((block.height(), digest));
+        Ok(())
+    }
+
+    /// Remove `block`, which must be the tip.
+    pub fn disconnect_block(&mut self, block: &VerifiedBlock) -> Result<(), SearchError> {
+        if self.tip.as_ref().map(|(_, digest)| digest) != Some(block.digest()) {
+            return Err(SearchError::NotOnTip);
+        }
+
+        self.blocks.remove(&block.digest().to_string());
+        for tx in block.transactions() {
+            self.transactions.remove(&tx.txid().to_string());
+            for address in Self::addresses_of(tx) {
+                let key = address.to_string();
+                if let Some((_, count)) = self.addresses.get_mut(&key) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.addresses.remove(&key);
+                    }
+                }
+            }
+        }
+        self.tip = block
+            .height()
+            .previous()
+            .map(|height| (height, block.previous_digest().clone()));
+        Ok(())
+    }
+
+    /// Objects whose identifiers start with `prefix`, a case-insensitive hex string.
+    pub fn search(&self, prefix: &str) -> Result<SearchResults, SearchError> {
+        let prefix = prefix.trim().to_ascii_lowercase();
+        if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(SearchError::NotHex);
+        }
+        if prefix.len() < MIN_PREFIX_LEN {
+            return Err(SearchError::TooShort);
+        }
+
+        let blocks = Self::range(&self.blocks, &prefix).map(|(digest, height)| SearchHit::Block {
+            digest: digest.clone(),
+            height: *height,
+        });
+        let transactions = Self::range(&self.transactions, &prefix).map(|(txid, block, height)| {
+            SearchHit::Transaction {
+                txid: txid.clone(),
+                block: block.clone(),
+                height: *height,
+            }
+        });
+        let addresses = Self::range(&self.addresses, &prefix).map(|(address, transactions)| {
+            SearchHit::Address {
+                address: address.clone(),
+                transactions: *transactions,
+            }
+        });
+        let mut hits = blocks
+            .chain(transactions)
+            .chain(addresses)
+            .take(MAX_HITS + 1)
+            .collect::<Vec<_>>();
+        let truncated = hits.len() > MAX_HITS;
+        hits.truncate(MAX_HITS);
+        Ok(SearchResults { hits, truncated })
+    }
+
+    fn range<'a, T>(
+        map: &'a BTreeMap<String, T>,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = &'a T> + 'a {
+        map.range(prefix.to_string()..)
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(_, value)| value)
+    }
+
+    /// Senders and receivers of `tx`, each once.
+    fn addresses_of(tx: &VerifiedTransaction) -> HashSet<&Address> {
+        tx.inputs()
+            .iter()
+            .chain(tx.outputs())
+            .map(|transition| transition.receiver())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchError {
+    /// Prefix is shorter than `MIN_PREFIX_LEN`.
+    TooShort,
+    NotHex,
+    /// Block does not follow, or is not, the tip of the index.
+    NotOnTip,
+}
+
+impl Display for SearchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::TooShort => {
+                write!(f, "Search needs at least {} hex digits", MIN_PREFIX_LEN)
+            }
+            SearchError::NotHex => write!(f, "Search is not a hex string"),
+            SearchError::NotOnTip => write!(f, "Block is not on the tip of the search index"),
+        }
+    }
+}
+
+impl Error for SearchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Ledger;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    #[test]
+    fn test_search_follows_chain() {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let output = Transfer::offer(&alice, bob.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let txid = tx.txid();
+        let paid = mine(&mut ledger, vec![tx], &alice).unwrap();
+
+        let mut index = SearchIndex::new();
+        let paid_block = ledger.get(&paid).unwrap();
+        assert_eq!(Err(SearchError::NotOnTip), index.connect_block(paid_block));
+        index.connect_block(ledger.get(&genesis).unwrap()).unwrap();
+        index.connect_block(paid_block).unwrap();
+
+        let prefix = |s: String| s[..8].to_uppercase();
+        let hits = index.search(&prefix(paid.to_string())).unwrap().hits;
+        assert!(hits.contains(&SearchHit::Block {
+            digest: paid.clone(),
+            height: paid_block.height(),
+        }));
+        let hits = index.search(&prefix(txid.to_string())).unwrap().hits;
+        assert!(hits.contains(&SearchHit::Transaction {
+            txid: txid.clone(),
+            block: paid.clone(),
+            height: paid_block.height(),
+        }));
+        // Alice received the reward of both blocks and paid bob
+        let alice = alice.to_public_address();
+        let hits = index.search(&prefix(alice.to_string())).unwrap().hits;
+        assert!(hits.contains(&SearchHit::Address {
+            address: alice.clone(),
+            transactions: 3,
+        }));
+
+        index.disconnect_block(paid_block).unwrap();
+        assert_eq!(Some(&genesis), index.tip().map(|(_, digest)| digest));
+        let hits = index.search(&txid.to_string()).unwrap().hits;
+        assert!(hits.is_empty());
+        let bob = bob.to_public_address().to_string();
+        assert!(index.search(&bob).unwrap().hits.is_empty());
+        let hits = index.search(&alice.to_string()).unwrap().hits;
+        assert_eq!(
+            vec![SearchHit::Address {
+                address: alice,
+                transactions: 1,
+            }],
+            hits
+        );
+    }
+
+    #[test]
+    fn test_search_rejects_bad_prefix() {
+        let index = SearchIndex::new();
+        assert_eq!(Err(SearchError::TooShort), index.search("abc"));
+        assert_eq!(Err(SearchError::NotHex), index.search("abcdefg"));
+        assert_eq!(
+            Ok(SearchResults {
+                hits: vec![],
+                truncated: false,
+            }),
+            index.search(" ABCD ")
+        );
+    }
+}
@@ -1,10 +1,22 @@
-use serde::{Deserialize, Serialize};
+use crate::digest::short_hex;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Signature(ed25519_dalek::Signature);
 
+impl Signature {
+    /// See `digest::short_hex`.
+    pub fn fmt_short(&self) -> String {
+        short_hex(self)
+    }
+}
+
 impl Hash for Signature {
     fn hash<H>(&self, state: &mut H)
     where
@@ -33,27 +45,273 @@ impl Display for Signature {
     }
 }
 
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        let signature = ed25519_dalek::Signature::try_from(bytes.as_slice())?;
+        Ok(Self(signature))
+    }
+}
+
+/// Human-readable formats such as JSON get a hex string.
+/// Binary formats keep the ed25519 byte representation.
+impl Serialize for Signature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SignatureVisitor;
+
+        impl<'de> Visitor<'de> for SignatureVisitor {
+            type Value = Signature;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "an ed25519 signature as a hex string or bytes")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Signature::from_str(v).map_err(E::custom)
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                ed25519_dalek::Signature::try_from(v)
+                    .map(Signature)
+                    .map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut bytes = vec![];
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            // Also accept the byte array form, which was emitted by older versions
+            deserializer.deserialize_any(SignatureVisitor)
+        } else {
+            ed25519_dalek::Signature::deserialize(deserializer).map(Signature)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    HexDecode(hex::FromHexError),
+    Ed25519(ed25519_dalek::ed25519::Error),
+}
+
+impl From<hex::FromHexError> for SignatureError {
+    fn from(e: hex::FromHexError) -> Self {
+        SignatureError::HexDecode(e)
+    }
+}
+
+impl From<ed25519_dalek::ed25519::Error> for SignatureError {
+    fn from(e: ed25519_dalek::ed25519::Error) -> Self {
+        SignatureError::Ed25519(e)
+    }
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::HexDecode(e) => e.fmt(f),
+            SignatureError::Ed25519(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SignatureError::HexDecode(e) => Some(e),
+            SignatureError::Ed25519(e) => Some(e),
+        }
+    }
+}
+
+/// Encoding of the message which a signature or block digest commits to.
+///
+/// Each signed object carries its version, so verification uses the encoding it was created with.
+/// New objects are always created with `CURRENT`.
+/// Legacy objects stay verifiable until the network agrees to reject them,
+/// after which `Legacy` can be removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum SighashVersion {
+    /// Raw concatenation of field bytes.
+    /// JSON of objects from before versioning, which has no version field, is deserialized as this.
+    /// Bincode has no field names, so their bincode encodings do not decode at all.
+    #[default]
+    Legacy,
+    /// Domain-tagged, length-prefixed fields, hashed by SHA-256 before signing.
+    V1,
+    /// Same as `V1`, except that a block digest commits to the Merkle root of its transactions
+    /// instead of the transactions themselves.
+    V2,
+}
+
+impl SighashVersion {
+    pub const CURRENT: SighashVersion = SighashVersion::V2;
+}
+
+/// Which parts of a transaction the signs of the contractor and cosigners commit to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum SighashFlag {
+    /// All inputs and all outputs.
+    #[default]
+    All,
+    /// Only the signer's own inputs and the outputs they send.
+    /// Others can join later with their own inputs and outputs, and cosign them.
+    Single,
+}
+
+impl SighashFlag {
+    pub const fn to_byte(self) -> u8 {
+        match self {
+            SighashFlag::All => 0,
+            SighashFlag::Single => 1,
+        }
+    }
+}
+
+/// Kind of object being signed.
+/// Including it in the message prevents a signature of one kind from being valid as another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SighashDomain {
+    Transfer,
+    Generation,
+    Htlc,
+    Multisig,
+    Transaction,
+    Block,
+    Channel,
+    TransactionId,
+    /// Gossip message signed by a node identity rather than a coin owner.
+    NodeMessage,
+}
+
+impl SighashDomain {
+    pub const fn tag(self) -> &'static [u8] {
+        match self {
+            SighashDomain::Transfer => b"blockchain-scratch/transfer/v1",
+            SighashDomain::Generation => b"blockchain-scratch/generation/v1",
+            SighashDomain::Htlc => b"blockchain-scratch/htlc/v1",
+            SighashDomain::Multisig => b"blockchain-scratch/multisig/v1",
+            SighashDomain::Transaction => b"blockchain-scratch/transaction/v1",
+            SighashDomain::Block => b"blockchain-scratch/block/v1",
+            SighashDomain::Channel => b"blockchain-scratch/channel/v1",
+            SighashDomain::TransactionId => b"blockchain-scratch/txid/v1",
+            SighashDomain::NodeMessage => b"blockchain-scratch/node-message/v1",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SignatureBuilder {
     bytes: Vec<u8>,
+    /// Prefix each write with its length
+    framed: bool,
 }
 
 impl SignatureBuilder {
+    /// Builder of the legacy encoding.
     pub fn new() -> Self {
-        Self { bytes: vec![] }
+        Self {
+            bytes: vec![],
+            framed: false,
+        }
     }
 
     pub fn from(bytes: Vec<u8>) -> Self {
-        Self { bytes }
+        Self {
+            bytes,
+            framed: false,
+        }
+    }
+
+    /// Builder of a message in the given encoding, starting with the domain tag.
+    pub fn sighash(version: SighashVersion, domain: SighashDomain) -> Self {
+        match version {
+            SighashVersion::Legacy => Self::new(),
+            SighashVersion::V1 | SighashVersion::V2 => {
+                let mut builder = Self {
+                    bytes: vec![],
+                    framed: true,
+                };
+                builder.write_bytes(domain.tag());
+                builder
+            }
+        }
     }
 
     pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.framed {
+            self.bytes
+                .extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        }
         self.bytes.extend_from_slice(bytes);
     }
 
+    /// Write the number of following items. The legacy encoding writes nothing.
+    pub fn write_len(&mut self, len: usize) {
+        if self.framed {
+            self.bytes.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+    }
+
+    /// Write which enum variant follows. The legacy encoding writes nothing.
+    pub fn write_variant(&mut self, variant: u8) {
+        if self.framed {
+            self.bytes.push(variant);
+        }
+    }
+
     pub fn finalize(self) -> Vec<u8> {
         self.bytes
     }
+
+    /// Finish as the message to be signed.
+    /// Framed encodings are hashed, the legacy encoding is signed as it is.
+    pub fn finalize_sighash(self) -> Vec<u8> {
+        if self.framed {
+            Sha256::digest(&self.bytes).to_vec()
+        } else {
+            self.bytes
+        }
+    }
+}
+
+impl Default for SignatureBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub trait SignatureSource {
@@ -71,8 +329,104 @@ where
     T: SignatureSource,
 {
     fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        builder.write_len(self.len());
         for item in self.iter() {
             item.write_bytes(builder);
         }
     }
 }
+
+impl<T> SignatureSource for Arc<T>
+where
+    T: SignatureSource,
+{
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        self.as_ref().write_bytes(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SecretAddress;
+
+    fn signature() -> Signature {
+        SecretAddress::create().sign("The altimate answer=42".as_bytes())
+    }
+
+    #[test]
+    fn test_from_str() {
+        let sign = signature();
+
+        let s = sign.to_string();
+
+        assert_eq!(Signature::from_str(&s).unwrap(), sign);
+    }
+
+    #[test]
+    fn test_from_str_invalid_hex() {
+        assert!(Signature::from_str("xyz").is_err());
+        assert!(Signature::from_str("00").is_err());
+    }
+
+    #[test]
+    fn test_serde_hex_string() {
+        let sign = signature();
+
+        let ser = serde_json::to_string(&sign).unwrap();
+        let de = serde_json::from_str::<Signature>(&ser).unwrap();
+
+        assert_eq!(format!("\"{}\"", sign), ser);
+        assert_eq!(sign, de);
+    }
+
+    #[test]
+    fn test_serde_byte_array() {
+        let sign = signature();
+
+        let ser = serde_json::to_string(&sign.0.to_bytes().to_vec()).unwrap();
+        let de = serde_json::from_str::<Signature>(&ser).unwrap();
+
+        assert_eq!(sign, de);
+    }
+
+    #[test]
+    fn test_framed_encoding_separates_fields() {
+        let build = |version, fields: &[&[u8]]| {
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Transfer);
+            for field in fields {
+                builder.write_bytes(field);
+            }
+            builder.finalize_sighash()
+        };
+
+        let legacy_a = build(SighashVersion::Legacy, &[b"ab", b"c"]);
+        let legacy_b = build(SighashVersion::Legacy, &[b"a", b"bc"]);
+        assert_eq!(legacy_a, legacy_b);
+
+        let v1_a = build(SighashVersion::V1, &[b"ab", b"c"]);
+        let v1_b = build(SighashVersion::V1, &[b"a", b"bc"]);
+        assert_ne!(v1_a, v1_b);
+    }
+
+    #[test]
+    fn test_domain_separation() {
+        let build = |domain| {
+            let mut builder = SignatureBuilder::sighash(SighashVersion::V1, domain);
+            builder.write_bytes(b"same fields");
+            builder.finalize_sighash()
+        };
+
+        assert_ne!(
+            build(SighashDomain::Transfer),
+            build(SighashDomain::Generation)
+        );
+    }
+
+    #[test]
+    fn test_fmt_short() {
+        let sign = signature();
+
+        assert_eq!(&sign.to_string()[..8], sign.fmt_short());
+    }
+}
@@ -0,0 +1,269 @@
+//! Protocol of an external signer, such as an air-gapped device holding the secret address.
+//!
+//! The online host exports a `SigningRequest` of a partially signed transaction as a hex blob,
+//! which is short enough for a QR code. The signer answers with a `SignatureResponse` blob,
+//! which the host imports into the transaction. No secret leaves the signer.
+//!
+//! The signer only sees message digests. Check the transaction on the host before exporting it.
+
+use crate::account::{Address, SecretAddress};
+use crate::psbt::{PartiallySignedTransaction, PsbtError, SignTarget};
+use crate::signature::Signature;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Version of the blob encoding, checked by both sides.
+pub const SIGNER_PROTOCOL_VERSION: u8 = 1;
+
+/// Messages which one signer has to sign for a partially signed transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningRequest {
+    version: u8,
+    /// Address whose secret signs, which tells the device which key to use
+    signer: Address,
+    messages: Vec<(SignTarget, Vec<u8>)>,
+}
+
+impl SigningRequest {
+    /// Request of what `signer` has not signed of `psbt` yet.
+    pub fn new(psbt: &PartiallySignedTransaction, signer: Address) -> Result<Self, SignerError> {
+        let messages = psbt.messages(&signer);
+        if messages.is_empty() {
+            return Err(SignerError::NothingToSign);
+        }
+        Ok(Self {
+            version: SIGNER_PROTOCOL_VERSION,
+            signer,
+            messages,
+        })
+    }
+
+    pub fn signer(&self) -> &Address {
+        &self.signer
+    }
+
+    pub fn messages(&self) -> &[(SignTarget, Vec<u8>)] {
+        &self.messages
+    }
+
+    /// Sign every message, which is done on the device.
+    pub fn sign(&self, secret: &SecretAddress) -> Result<SignatureResponse, SignerError> {
+        if secret.to_public_address() != self.signer {
+            return Err(SignerError::WrongSigner);
+        }
+        let signs = self
+            .messages
+            .iter()
+            .map(|(target, message)| (*target, secret.sign(message)))
+            .collect();
+        Ok(SignatureResponse {
+            version: SIGNER_PROTOCOL_VERSION,
+            signer: self.signer.clone(),
+            signs,
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode(self)
+    }
+
+    pub fn from_hex(blob: &str) -> Result<Self, SignerError> {
+        let request = decode::<Self>(blob)?;
+        check_version(request.version)?;
+        Ok(request)
+    }
+}
+
+/// Signs made by the external signer in answer to a `SigningRequest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureResponse {
+    version: u8,
+    signer: Address,
+    signs: Vec<(SignTarget, Signature)>,
+}
+
+impl SignatureResponse {
+    pub fn signer(&self) -> &Address {
+        &self.signer
+    }
+
+    pub fn signs(&self) -> &[(SignTarget, Signature)] {
+        &self.signs
+    }
+
+    /// Add the signs to `psbt`, each of which is checked against its message.
+    pub fn apply_to(&self, psbt: &mut PartiallySignedTransaction) -> Result<(), SignerError> {
+        for (target, sign) in self.signs.iter() {
+            psbt.add_signature(&self.signer, *target, sign.clone())?;
+        }
+        Ok(())
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode(self)
+    }
+
+    pub fn from_hex(blob: &str) -> Result<Self, SignerError> {
+        let response = decode::<Self>(blob)?;
+        check_version(response.version)?;
+        Ok(response)
+    }
+}
+
+fn encode(value: &impl Serialize) -> String {
+    let bytes = bincode::serialize(value).expect("Request and response are always serializable");
+    hex::encode(bytes)
+}
+
+fn decode<T: DeserializeOwned>(blob: &str) -> Result<T, SignerError> {
+    let bytes = hex::decode(blob.trim())?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+fn check_version(version: u8) -> Result<(), SignerError> {
+    match version {
+        SIGNER_PROTOCOL_VERSION => Ok(()),
+        _ => Err(SignerError::UnsupportedVersion(version)),
+    }
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+    HexDecode(hex::FromHexError),
+    Decode(bincode::Error),
+    UnsupportedVersion(u8),
+    /// Signer has signed everything already, or is not a party.
+    NothingToSign,
+    /// Secret address is not of the requested signer.
+    WrongSigner,
+    Psbt(PsbtError),
+}
+
+impl From<hex::FromHexError> for SignerError {
+    fn from(e: hex::FromHexError) -> Self {
+        SignerError::HexDecode(e)
+    }
+}
+
+impl From<bincode::Error> for SignerError {
+    fn from(e: bincode::Error) -> Self {
+        SignerError::Decode(e)
+    }
+}
+
+impl From<PsbtError> for SignerError {
+    fn from(e: PsbtError) -> Self {
+        SignerError::Psbt(e)
+    }
+}
+
+impl Display for SignerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerError::HexDecode(e) => write!(f, "Blob is not hex: {}", e),
+            SignerError::Decode(e) => write!(f, "Malformed blob: {}", e),
+            SignerError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported signer protocol version {}", version)
+            }
+            SignerError::NothingToSign => write!(f, "Nothing to sign by the signer"),
+            SignerError::WrongSigner => write!(f, "Secret address is not of the requested signer"),
+            SignerError::Psbt(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SignerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SignerError::HexDecode(e) => Some(e),
+            SignerError::Decode(e) => Some(e),
+            SignerError::Psbt(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psbt::UnsignedTransfer;
+    use crate::{Coin, Generation};
+
+    fn payment(alice: &SecretAddress) -> PartiallySignedTransaction {
+        let gen = Generation::offer(alice, Coin::from(10)).to_unverified();
+        let outputs = vec![UnsignedTransfer {
+            receiver: SecretAddress::create().to_public_address(),
+            quantity: Coin::from(9),
+        }];
+        PartiallySignedTransaction::new(alice.to_public_address(), vec![gen.into()], outputs)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let alice = SecretAddress::create();
+        let mut psbt = payment(&alice);
+
+        // Host
+        let request = SigningRequest::new(&psbt, alice.to_public_address()).unwrap();
+        let blob = request.to_hex();
+        // Device
+        let request = SigningRequest::from_hex(&blob).unwrap();
+        let blob = request.sign(&alice).unwrap().to_hex();
+        // Host
+        SignatureResponse::from_hex(&blob)
+            .unwrap()
+            .apply_to(&mut psbt)
+            .unwrap();
+
+        assert!(psbt.is_complete());
+        psbt.finalize().unwrap().verify().unwrap();
+        assert!(matches!(
+            SigningRequest::new(&psbt, alice.to_public_address()),
+            Err(SignerError::NothingToSign)
+        ));
+    }
+
+    #[test]
+    fn test_wrong_signer() {
+        let alice = SecretAddress::create();
+        let psbt = payment(&alice);
+        let request = SigningRequest::new(&psbt, alice.to_public_address()).unwrap();
+        assert!(matches!(
+            request.sign(&SecretAddress::create()),
+            Err(SignerError::WrongSigner)
+        ));
+    }
+
+    #[test]
+    fn test_reject_response_of_other_transaction() {
+        let alice = SecretAddress::create();
+        let psbt = payment(&alice);
+        let response = SigningRequest::new(&psbt, alice.to_public_address())
+            .unwrap()
+            .sign(&alice)
+            .unwrap();
+
+        let mut other = payment(&alice);
+        assert!(matches!(
+            response.apply_to(&mut other),
+            Err(SignerError::Psbt(PsbtError::InvalidSign))
+        ));
+    }
+
+    #[test]
+    fn test_reject_unknown_version() {
+        let alice = SecretAddress::create();
+        let mut request = SigningRequest::new(&payment(&alice), alice.to_public_address()).unwrap();
+        request.version = SIGNER_PROTOCOL_VERSION + 1;
+        assert!(matches!(
+            SigningRequest::from_hex(&request.to_hex()),
+            Err(SignerError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            SigningRequest::from_hex("not hex"),
+            Err(SignerError::HexDecode(_))
+        ));
+    }
+}
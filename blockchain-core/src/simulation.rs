@@ -0,0 +1,337 @@
+//! In-process network of nodes for scripting consensus scenarios.
+//! Blocks are delivered to every other node at once, without Proof-of-Work.
+
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use crate::ledger::{Ledger, LedgerError, MAX_FUTURE_DRIFT_SECS};
+use crate::test_utils::{generation_rule, params};
+use crate::timestamp::Timestamp;
+use crate::{Block, BlockSource, Coin, Difficulty, SecretAddress, Transaction, Transfer};
+use crate::{Verified, VerifiedBlock, VerifiedTransaction, Yet};
+use apply::Apply;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+type MinedBlock = Block<Verified, Yet, Yet, Yet, Yet, Yet>;
+
+const DIFFICULTY: Difficulty = Difficulty::new(0);
+
+/// Transactions sent by a spammer on each tick.
+const SPAM_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+    Honest,
+    /// Mines valid blocks, but keeps them until released.
+    WithholdBlocks,
+    /// Mines blocks which pay more than the generation rule allows.
+    InvalidQuantity,
+    /// Mines blocks timestamped beyond the allowed drift.
+    FutureTimestamp,
+    /// Floods other nodes' mempools with transactions spending nonexistent coins instead of mining.
+    SpamTransactions,
+}
+
+#[derive(Debug)]
+pub struct SimNode {
+    secret: SecretAddress,
+    behavior: Behavior,
+    ledger: Ledger,
+    mempool: Vec<Arc<VerifiedTransaction>>,
+    withheld: Vec<MinedBlock>,
+    /// Number of blocks received from others and denied
+    rejected: usize,
+}
+
+impl SimNode {
+    fn new(behavior: Behavior, rng: &mut StdRng) -> Self {
+        Self {
+            secret: SecretAddress::create_with_rng(rng),
+            behavior,
+            ledger: Ledger::with_params(params()),
+            mempool: vec![],
+            withheld: vec![],
+            rejected: 0,
+        }
+    }
+
+    pub fn tip(&self) -> Option<&VerifiedBlock> {
+        self.ledger.search_latest_block()
+    }
+
+    pub fn tip_height(&self) -> Option<BlockHeight> {
+        self.tip().map(Block::height)
+    }
+
+    pub fn mempool(&self) -> &[Arc<VerifiedTransaction>] {
+        &self.mempool
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    /// Mine a block on the local tip. `forge` may tamper with the source before mining.
+    fn mine<F, G>(
+        &self,
+        transactions: Vec<Arc<VerifiedTransaction>>,
+        gen_rule: G,
+        forge: F,
+    ) -> MinedBlock
+    where
+        F: FnOnce(&mut BlockSource),
+        G: FnMut(BlockHeight) -> Coin,
+    {
+        let (height, previous_digest) = match self.tip() {
+            Some(block) => (block.height().next(), block.digest().clone()),
+            None => (BlockHeight::genesis(), BlockDigest::digest(&[])),
+        };
+        let mut source = BlockSource::new(
+            height,
+            transactions,
+            previous_digest,
+            DIFFICULTY,
+            0,
+            &self.secret,
+            gen_rule,
+        )
+        .expect("Transactions in mempool are verified");
+        forge(&mut source);
+        source
+            .try_into_block()
+            .expect("Difficulty 0 accepts any digest")
+    }
+
+    /// Verify a block as the fullnode does, then entry it.
+    fn receive(&mut self, block: MinedBlock) -> Result<(), LedgerError> {
+        let block = block
+            .verify_transaction_relation_with(self.ledger.params())
+            .and_then(|b| b.verify_difficulty(&DIFFICULTY))
+            .and_then(|b| b.verify_digest())?;
+        let block = self.ledger.verify_block(block)?;
+        self.ledger.entry(block)
+    }
+}
+
+#[derive(Debug)]
+pub struct Simulation {
+    nodes: Vec<SimNode>,
+    /// Seed of `rng`, which reproduces the addresses of a run
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    /// Create nodes sharing a genesis block mined by the first node, with a random seed.
+    pub fn new(behaviors: &[Behavior]) -> Self {
+        Self::with_seed(behaviors, rand::random())
+    }
+
+    /// Create nodes whose addresses are derived from `seed`.
+    pub fn with_seed(behaviors: &[Behavior], seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let nodes = behaviors
+            .iter()
+            .map(|&behavior| SimNode::new(behavior, &mut rng))
+            .collect();
+        let mut sim = Self { nodes, seed, rng };
+        let genesis = sim.nodes[0].mine(vec![], generation_rule, |_| {});
+        sim.broadcast(None, genesis);
+        sim
+    }
+
+    pub fn node(&self, index: usize) -> &SimNode {
+        &self.nodes[index]
+    }
+
+    pub fn nodes(&self) -> &[SimNode] {
+        &self.nodes
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Let the node act once according to its behavior.
+    pub fn tick(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        match node.behavior {
+            Behavior::Honest => {
+                let transactions = std::mem::take(&mut node.mempool);
+                let block = node.mine(transactions, generation_rule, |_| {});
+                // Same as fullnode, drop all transactions if any of them is invalid
+                let block = match node.receive(block.clone()) {
+                    Ok(()) => block,
+                    Err(_) => {
+                        let block = node.mine(vec![], generation_rule, |_| {});
+                        node.receive(block.clone())
+                            .expect("Empty block is always valid");
+                        block
+                    }
+                };
+                self.broadcast(Some(index), block);
+            }
+            Behavior::WithholdBlocks => {
+                let block = node.mine(vec![], generation_rule, |_| {});
+                node.receive(block.clone())
+                    .expect("Empty block is always valid");
+                node.withheld.push(block);
+            }
+            Behavior::InvalidQuantity => {
+                let gen_rule = |height| generation_rule(height) + Coin::from(1);
+                let block = node.mine(vec![], gen_rule, |_| {});
+                self.broadcast(Some(index), block);
+            }
+            Behavior::FutureTimestamp => {
+                let future = Timestamp::now()
+                    .checked_add_secs(MAX_FUTURE_DRIFT_SECS * 2)
+                    .expect("Timestamp out of range");
+                let block = node.mine(vec![], generation_rule, |source| {
+                    source.set_timestamp(future)
+                });
+                self.broadcast(Some(index), block);
+            }
+            Behavior::SpamTransactions => {
+                let spam = (0..SPAM_COUNT)
+                    .map(|_| {
+                        // Coin which does not exist in any ledger
+                        let fake = SecretAddress::create_with_rng(&mut self.rng);
+                        let input =
+                            Transfer::offer(&fake, node.secret.to_public_address(), Coin::from(1));
+                        let output = Transfer::offer(
+                            &node.secret,
+                            node.secret.to_public_address(),
+                            Coin::from(1),
+                        );
+                        Transaction::offer(&node.secret, vec![input], vec![output])
+                            .verify_transaction()
+                            .expect("Spam is well-formed")
+                            .apply(Arc::new)
+                    })
+                    .collect::<Vec<_>>();
+                for (i, other) in self.nodes.iter_mut().enumerate() {
+                    if i != index {
+                        other.mempool.extend(spam.iter().cloned());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Publish blocks withheld by the node.
+    pub fn release(&mut self, index: usize) {
+        let withheld = std::mem::take(&mut self.nodes[index].withheld);
+        for block in withheld {
+            self.broadcast(Some(index), block);
+        }
+    }
+
+    /// Whether all honest nodes agree on the same tip.
+    pub fn honest_nodes_agree(&self) -> bool {
+        let mut tips = self
+            .nodes
+            .iter()
+            .filter(|node| node.behavior == Behavior::Honest)
+            .map(|node| node.tip().map(Block::digest));
+        match tips.next() {
+            Some(first) => tips.all(|tip| tip == first),
+            None => true,
+        }
+    }
+
+    fn broadcast(&mut self, from: Option<usize>, block: MinedBlock) {
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            if Some(i) == from {
+                continue;
+            }
+            match node.receive(block.clone()) {
+                Ok(()) | Err(LedgerError::DuplicatedBlock) => {}
+                Err(_) => node.rejected += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Behavior::*;
+
+    #[test]
+    fn test_honest_nodes_converge() {
+        let mut sim = Simulation::new(&[Honest, Honest, Honest]);
+        for i in [0, 1, 2, 1] {
+            sim.tick(i);
+        }
+
+        assert!(sim.honest_nodes_agree());
+        assert_eq!(Some(BlockHeight::from(4)), sim.node(2).tip_height());
+        assert!(sim.nodes().iter().all(|node| node.rejected() == 0));
+    }
+
+    #[test]
+    fn test_seed_reproduces_run() {
+        let addresses = |sim: &Simulation| {
+            sim.nodes()
+                .iter()
+                .map(|node| node.secret.to_public_address())
+                .collect::<Vec<_>>()
+        };
+        let sim = Simulation::new(&[Honest, Honest]);
+        let replay = Simulation::with_seed(&[Honest, Honest], sim.seed());
+        assert_eq!(addresses(&sim), addresses(&replay));
+        assert_ne!(addresses(&sim)[0], addresses(&sim)[1]);
+    }
+
+    #[test]
+    fn test_invalid_quantity_is_rejected() {
+        let mut sim = Simulation::new(&[Honest, Honest, InvalidQuantity]);
+        sim.tick(2);
+
+        assert_eq!(1, sim.node(0).rejected());
+        assert_eq!(1, sim.node(1).rejected());
+        assert_eq!(Some(BlockHeight::genesis()), sim.node(0).tip_height());
+        assert!(sim.honest_nodes_agree());
+    }
+
+    #[test]
+    fn test_future_timestamp_is_rejected() {
+        let mut sim = Simulation::new(&[Honest, FutureTimestamp]);
+        sim.tick(1);
+        sim.tick(0);
+
+        assert_eq!(1, sim.node(0).rejected());
+        assert_eq!(Some(BlockHeight::from(1)), sim.node(0).tip_height());
+    }
+
+    #[test]
+    fn test_withheld_chain_overtakes() {
+        let mut sim = Simulation::new(&[Honest, Honest, WithholdBlocks]);
+        for _ in 0..3 {
+            sim.tick(2);
+        }
+        sim.tick(0);
+        assert_eq!(Some(BlockHeight::from(1)), sim.node(1).tip_height());
+
+        // The longer private chain replaces the honest tip on release
+        sim.release(2);
+        assert!(sim.honest_nodes_agree());
+        assert_eq!(sim.node(2).tip(), sim.node(0).tip());
+        assert_eq!(Some(BlockHeight::from(3)), sim.node(0).tip_height());
+    }
+
+    #[test]
+    fn test_spam_does_not_stall_chain() {
+        let mut sim = Simulation::new(&[Honest, SpamTransactions, Honest]);
+        sim.tick(1);
+        assert_eq!(SPAM_COUNT, sim.node(0).mempool().len());
+
+        sim.tick(0);
+
+        assert!(sim.node(0).mempool().is_empty());
+        assert!(sim.honest_nodes_agree());
+        assert_eq!(Some(BlockHeight::from(1)), sim.node(2).tip_height());
+        assert_eq!(0, sim.node(2).rejected());
+    }
+}
@@ -0,0 +1,111 @@
+//! Read-only views of the best chain, for queries which must not hold the ledger.
+//!
+//! The ledger keeps one shared snapshot of its best chain and moves it along with the tip.
+//! Taking a snapshot only clones an `Arc`, and the ledger copies the snapshot on its next write
+//! only while someone still holds it, so a long query never delays blocks from being connected.
+
+use crate::account::Address;
+use crate::block::BlockHeight;
+use crate::difficulty::ChainWork;
+use crate::digest::BlockDigest;
+use crate::ledger::MEDIAN_TIME_SPAN;
+use crate::timestamp::Timestamp;
+use crate::transition::Transition;
+use crate::verification::Verified;
+use crate::{VerifiedBlock, VerifiedTransaction};
+use itertools::Itertools;
+use std::sync::Arc;
+
+/// Best chain of a ledger at one tip, which stays the same however the ledger changes later.
+#[derive(Debug, Clone, Default)]
+pub struct ChainSnapshot {
+    /// Blocks from genesis to the tip, indexed by height
+    blocks: Vec<Arc<VerifiedBlock>>,
+    /// UTXO set after the tip
+    utxos: Arc<Vec<Transition<Verified>>>,
+    /// Work from genesis to the tip
+    work: ChainWork,
+}
+
+impl ChainSnapshot {
+    /// Move the snapshot to a new tip, keeping the blocks below `fork` and appending `blocks`.
+    pub(crate) fn move_tip(
+        &mut self,
+        fork: BlockHeight,
+        blocks: impl IntoIterator<Item = Arc<VerifiedBlock>>,
+        utxos: Arc<Vec<Transition<Verified>>>,
+        work: ChainWork,
+    ) {
+        self.blocks.truncate(u64::from(fork) as usize);
+        self.blocks.extend(blocks);
+        self.utxos = utxos;
+        self.work = work;
+    }
+
+    pub fn tip(&self) -> Option<&VerifiedBlock> {
+        self.blocks.last().map(Arc::as_ref)
+    }
+
+    pub fn height(&self) -> Option<BlockHeight> {
+        self.tip().map(VerifiedBlock::height)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn block_at(&self, height: BlockHeight) -> Option<&VerifiedBlock> {
+        let index = usize::try_from(u64::from(height)).ok()?;
+        self.blocks.get(index).map(Arc::as_ref)
+    }
+
+    /// Blocks from genesis to the tip. Reverse it to walk from the tip.
+    pub fn blocks(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = &VerifiedBlock> + ExactSizeIterator + '_ {
+        self.blocks.iter().map(Arc::as_ref)
+    }
+
+    /// Transaction of `txid` with the block confirming it, searched from the tip.
+    pub fn find_transaction(
+        &self,
+        txid: &BlockDigest,
+    ) -> Option<(&VerifiedBlock, &VerifiedTransaction)> {
+        self.blocks().rev().find_map(|block| {
+            block
+                .transactions()
+                .iter()
+                .find(|tx| &tx.txid() == txid)
+                .map(|tx| (block, tx.as_ref()))
+        })
+    }
+
+    pub fn utxos(&self) -> &[Transition<Verified>] {
+        &self.utxos
+    }
+
+    pub fn utxos_of(&self, holder: &Address) -> Vec<Transition<Verified>> {
+        self.utxos
+            .iter()
+            .filter(|utxo| utxo.receiver() == holder)
+            .cloned()
+            .collect()
+    }
+
+    /// Sum of expected hashes from genesis to the tip.
+    pub fn cumulative_work(&self) -> ChainWork {
+        self.work
+    }
+
+    /// Median timestamp of the latest `MEDIAN_TIME_SPAN` blocks.
+    pub fn median_time_past(&self) -> Option<Timestamp> {
+        let timestamps = self
+            .blocks()
+            .rev()
+            .take(MEDIAN_TIME_SPAN)
+            .map(VerifiedBlock::timestamp)
+            .sorted()
+            .collect_vec();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+}
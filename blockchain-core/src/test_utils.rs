@@ -0,0 +1,150 @@
+use crate::block::BlockHeight;
+use crate::clock::{Clock, SystemClock};
+use crate::digest::BlockDigest;
+use crate::ledger::{Ledger, LedgerError};
+use crate::{BlockSource, ChainParams, Coin, Difficulty, NonceIter, SecretAddress};
+use crate::{Transition, Verified};
+use crate::{VerifiedBlock, VerifiedTransaction};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+pub fn generation_rule(_: BlockHeight) -> Coin {
+    Coin::from(100)
+}
+
+pub fn params() -> ChainParams {
+    ChainParams::new(generation_rule)
+}
+
+/// Mine a block on the latest block of `ledger` without Proof-of-Work, then entry it.
+pub fn mine(
+    ledger: &mut Ledger,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+) -> Result<BlockDigest, LedgerError> {
+    let previous = ledger.search_latest_block().map(|b| b.digest().clone());
+    mine_on(ledger, previous.as_ref(), transactions, miner)
+}
+
+/// Mine a block on `previous`, or a genesis block if `None`.
+pub fn mine_on(
+    ledger: &mut Ledger,
+    previous: Option<&BlockDigest>,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+) -> Result<BlockDigest, LedgerError> {
+    mine_on_with_clock(ledger, previous, transactions, miner, &SystemClock)
+}
+
+/// Mine a block timestamped by `clock` on the latest block of `ledger`, then entry it.
+pub fn mine_with_clock(
+    ledger: &mut Ledger,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+    clock: &dyn Clock,
+) -> Result<BlockDigest, LedgerError> {
+    let previous = ledger.search_latest_block().map(|b| b.digest().clone());
+    mine_on_with_clock(ledger, previous.as_ref(), transactions, miner, clock)
+}
+
+fn mine_on_with_clock(
+    ledger: &mut Ledger,
+    previous: Option<&BlockDigest>,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+    clock: &dyn Clock,
+) -> Result<BlockDigest, LedgerError> {
+    let block = fabricate(
+        ledger,
+        previous,
+        transactions,
+        miner,
+        Difficulty::new(0),
+        clock,
+    )?;
+    let digest = block.digest().clone();
+    ledger.entry(block)?;
+    Ok(digest)
+}
+
+/// Mine an empty block signalling `signals` on the latest block of `ledger`, then entry it.
+pub fn mine_signalling(
+    ledger: &mut Ledger,
+    signals: u32,
+    miner: &SecretAddress,
+) -> Result<BlockDigest, LedgerError> {
+    let previous = ledger.search_latest_block().map(|b| b.digest().clone());
+    let block = fabricate_signalling(
+        ledger,
+        previous.as_ref(),
+        vec![],
+        miner,
+        Difficulty::new(0),
+        &SystemClock,
+        signals,
+    )?;
+    let digest = block.digest().clone();
+    ledger.entry(block)?;
+    Ok(digest)
+}
+
+/// Block on `previous` verified against `ledger` but not entered, for entering it elsewhere.
+/// The same ledger, arguments and clock time fabricate the same block,
+/// since nonces are searched from a fixed seed.
+pub fn fabricate(
+    ledger: &Ledger,
+    previous: Option<&BlockDigest>,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+    difficulty: Difficulty,
+    clock: &dyn Clock,
+) -> Result<VerifiedBlock, LedgerError> {
+    fabricate_signalling(ledger, previous, transactions, miner, difficulty, clock, 0)
+}
+
+fn fabricate_signalling(
+    ledger: &Ledger,
+    previous: Option<&BlockDigest>,
+    transactions: Vec<VerifiedTransaction>,
+    miner: &SecretAddress,
+    difficulty: Difficulty,
+    clock: &dyn Clock,
+    signals: u32,
+) -> Result<VerifiedBlock, LedgerError> {
+    let (height, previous_digest) = match previous.and_then(|digest| ledger.get(digest)) {
+        Some(block) => (block.height().next(), block.digest().clone()),
+        None => (BlockHeight::genesis(), BlockDigest::digest(&[])),
+    };
+    let mut source = BlockSource::new_with_clock(
+        height,
+        transactions.into_iter().map(Arc::new).collect(),
+        previous_digest,
+        difficulty.clone(),
+        0,
+        miner,
+        generation_rule,
+        clock,
+    )
+    .unwrap();
+    if let Some(commitment) = ledger.utxo_commitment_after(previous, source.transactions()) {
+        source.commit_utxos(commitment);
+    }
+    source.signal(signals);
+    let block = source
+        .search_nonce(NonceIter::new(StdRng::seed_from_u64(0)))
+        .unwrap()
+        .verify_transaction_relation_with(&params())
+        .and_then(|b| b.verify_difficulty(&difficulty))
+        .and_then(|b| b.verify_digest())?;
+    ledger.verify_block(block)
+}
+
+/// Coin generation output of the block.
+pub fn reward(block: &VerifiedBlock) -> Transition<Verified> {
+    block
+        .outputs()
+        .find(|o| o.sender().is_none())
+        .cloned()
+        .unwrap()
+}
@@ -1,8 +1,9 @@
 use crate::signature::{SignatureBuilder, SignatureSource};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Timestamp(DateTime<Utc>);
@@ -13,10 +14,26 @@ impl Timestamp {
     }
 
     pub fn enix_epoch() -> Self {
-        let timestamp = NaiveDateTime::from_timestamp(0, 0);
-        let datetime = DateTime::from_utc(timestamp, Utc);
+        let datetime = DateTime::from_timestamp(0, 0).expect("Unix epoch is valid");
         Self(datetime)
     }
+
+    /// Calendar date in UTC.
+    pub fn date(&self) -> NaiveDate {
+        self.0.date_naive()
+    }
+
+    /// `secs` seconds later, or earlier if negative. Returns `None` on overflow.
+    pub fn checked_add_secs(self, secs: i64) -> Option<Self> {
+        TimeDelta::try_seconds(secs)
+            .and_then(|delta| self.0.checked_add_signed(delta))
+            .map(Self)
+    }
+
+    /// Time from `earlier` to this, or `None` if `earlier` is later.
+    pub fn duration_since(&self, earlier: Timestamp) -> Option<Duration> {
+        (self.0 - earlier.0).to_std().ok()
+    }
 }
 
 impl Hash for Timestamp {
@@ -36,6 +53,10 @@ impl Display for Timestamp {
 
 impl SignatureSource for Timestamp {
     fn write_bytes(&self, builder: &mut SignatureBuilder) {
-        builder.write_bytes(&self.0.naive_utc().timestamp_nanos().to_le_bytes());
+        let nanos = self
+            .0
+            .timestamp_nanos_opt()
+            .expect("Timestamp out of range");
+        builder.write_bytes(&nanos.to_le_bytes());
     }
 }
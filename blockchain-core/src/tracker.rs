@@ -0,0 +1,209 @@
+use crate::block::BlockHeight;
+use crate::digest::BlockDigest;
+use crate::ledger::Ledger;
+use crate::mempool::{Mempool, MempoolError};
+use crate::transaction::LockTime;
+use crate::VerifiedTransaction;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+/// Where a transaction is in its lifecycle, seen from a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    /// Neither in mempool, the latest chain nor tracked transactions.
+    Unknown,
+    InMempool,
+    /// Included in the latest chain. The including block counts as 1 confirmation.
+    Mined {
+        height: BlockHeight,
+        confirmations: u64,
+    },
+    /// Not mined, and another transaction in the latest chain spent its input.
+    Conflicted,
+}
+
+/// Recently accepted transactions, kept after leaving mempool to tell conflicts from unknown ones.
+#[derive(Debug, Clone)]
+pub struct TransactionTracker {
+    capacity: usize,
+    transactions: VecDeque<Arc<VerifiedTransaction>>,
+}
+
+impl TransactionTracker {
+    /// Tracks up to `capacity` transactions, forgetting the oldest one first.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            transactions: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn track(&mut self, transaction: Arc<VerifiedTransaction>) {
+        let txid = transaction.txid();
+        if self.get(&txid).is_some() {
+            return;
+        }
+
+        self.transactions.push_back(transaction);
+        while self.transactions.len() > self.capacity {
+            self.transactions.pop_front();
+        }
+    }
+
+    pub fn get(&self, txid: &BlockDigest) -> Option<&Arc<VerifiedTransaction>> {
+        self.transactions.iter().find(|tx| &tx.txid() == txid)
+    }
+
+    pub fn status(
+        &self,
+        txid: &BlockDigest,
+        mempool: &Mempool,
+        ledger: &Ledger,
+    ) -> TransactionStatus {
+        // Mined transactions spent their inputs, so look for them before conflicts
+        let tip_height = ledger.search_latest_block().map(|block| block.height());
+        let mined = ledger
+            .search_latest_chain()
+            .find(|block| block.transactions().iter().any(|tx| &tx.txid() == txid));
+        if let (Some(block), Some(tip_height)) = (mined, tip_height) {
+            return TransactionStatus::Mined {
+                height: block.height(),
+                confirmations: tip_height.distance(block.height()) + 1,
+            };
+        }
+
+        if mempool.contains(txid) {
+            return TransactionStatus::InMempool;
+        }
+
+        match (self.get(txid), ledger.search_latest_block()) {
+            (Some(transaction), Some(tip)) => {
+                let utxos = ledger.utxo_snapshot(tip.digest());
+                if transaction.inputs().iter().all(|i| utxos.contains(i)) {
+                    TransactionStatus::Unknown
+                } else {
+                    TransactionStatus::Conflicted
+                }
+            }
+            _ => TransactionStatus::Unknown,
+        }
+    }
+}
+
+/// Why a submitted transaction was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendTransactionError {
+    /// Transaction failed verification, for the reason held.
+    Invalid(String),
+    Mempool(MempoolError),
+    /// Next block cannot include the transaction yet. Submit it again after its lock time.
+    Locked(LockTime),
+}
+
+impl Display for SendTransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTransactionError::Invalid(reason) => write!(f, "Invalid transaction: {}", reason),
+            SendTransactionError::Mempool(e) => e.fmt(f),
+            SendTransactionError::Locked(lock_time) => {
+                write!(f, "Transaction is locked until {}", lock_time)
+            }
+        }
+    }
+}
+
+impl Error for SendTransactionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SendTransactionError::Invalid(_) => None,
+            SendTransactionError::Mempool(e) => Some(e),
+            SendTransactionError::Locked(_) => None,
+        }
+    }
+}
+
+impl From<MempoolError> for SendTransactionError {
+    fn from(e: MempoolError) -> Self {
+        SendTransactionError::Mempool(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+
+    /// Transaction moving the genesis reward of `alice` to a new address.
+    fn spend(ledger: &Ledger, alice: &SecretAddress) -> Arc<VerifiedTransaction> {
+        let genesis = ledger.search_latest_chain().last().unwrap();
+        let input = reward(genesis);
+        let output = Transfer::offer(
+            alice,
+            SecretAddress::create().to_public_address(),
+            input.quantity(),
+        );
+        let tx = Transaction::offer(alice, vec![input], vec![output])
+            .verify_transaction()
+            .unwrap();
+        Arc::new(tx)
+    }
+
+    #[test]
+    fn test_transaction_status() {
+        let alice = SecretAddress::create();
+        let mut ledger = Ledger::new();
+        mine(&mut ledger, vec![], &alice).unwrap();
+
+        let mut mempool = Mempool::new(1 << 20);
+        let mut tracker = TransactionTracker::new(2);
+        let tx = spend(&ledger, &alice);
+        let txid = tx.txid();
+        assert_eq!(
+            TransactionStatus::Unknown,
+            tracker.status(&txid, &mempool, &ledger)
+        );
+
+        mempool.insert(tx.clone()).unwrap();
+        tracker.track(tx.clone());
+        assert_eq!(
+            TransactionStatus::InMempool,
+            tracker.status(&txid, &mempool, &ledger)
+        );
+
+        mine(&mut ledger, vec![(*tx).clone()], &alice).unwrap();
+        mine(&mut ledger, vec![], &alice).unwrap();
+        mempool.clear();
+        assert_eq!(
+            TransactionStatus::Mined {
+                height: BlockHeight::from(1),
+                confirmations: 2,
+            },
+            tracker.status(&txid, &mempool, &ledger)
+        );
+
+        // Double spending of the same reward
+        let conflict = spend(&ledger, &alice);
+        tracker.track(conflict.clone());
+        assert_eq!(
+            TransactionStatus::Conflicted,
+            tracker.status(&conflict.txid(), &mempool, &ledger)
+        );
+
+        // Oldest one is forgotten
+        tracker.track(spend(&ledger, &alice));
+        assert_eq!(2, tracker.len());
+        assert!(tracker.get(&txid).is_none());
+    }
+}
@@ -1,14 +1,67 @@
 use crate::account::{Address, SecretAddress};
+use crate::block::BlockHeight;
+use crate::clock::{Clock, SystemClock};
 use crate::coin::Coin;
-use crate::signature::{Signature, SignatureBuilder, SignatureSource};
+use crate::compact::OutPoint;
+use crate::condition::{Spend, SpendError};
+use crate::digest::BlockDigest;
+use crate::signature::{
+    SighashDomain, SighashFlag, SighashVersion, Signature, SignatureBuilder, SignatureSource,
+};
 use crate::timestamp::Timestamp;
-use crate::transition::{Transfer, TransferError, Transition};
+use crate::transition::{TransferError, Transition};
 use crate::verification::{Verified, Yet};
 use serde::{Deserialize, Deserializer, Serialize};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
 
+/// Encoding of txids, fixed apart from the versions of signs.
+const TXID_VERSION: SighashVersion = SighashVersion::V2;
+
+/// Earliest block which may include a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockTime {
+    /// Block at this height or later.
+    Height(BlockHeight),
+    /// Block whose median time past is at or after this time.
+    Time(Timestamp),
+}
+
+impl LockTime {
+    /// Whether a block at `height`, whose median time past is `time`, may include the transaction.
+    pub fn is_reached(&self, height: BlockHeight, time: Timestamp) -> bool {
+        match *self {
+            LockTime::Height(lock) => height >= lock,
+            LockTime::Time(lock) => time >= lock,
+        }
+    }
+}
+
+impl Display for LockTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockTime::Height(height) => write!(f, "block {}", height),
+            LockTime::Time(time) => time.fmt(f),
+        }
+    }
+}
+
+impl SignatureSource for LockTime {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        match self {
+            LockTime::Height(height) => {
+                builder.write_variant(0);
+                height.write_bytes(builder);
+            }
+            LockTime::Time(time) => {
+                builder.write_variant(1);
+                time.write_bytes(builder);
+            }
+        }
+    }
+}
+
 /// ## Verification process using Generics:
 /// Each generic parameter is `Verified` or `Yet`.
 /// - VTF: TransFer check.
@@ -16,20 +69,50 @@ use std::marker::PhantomData;
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Transaction<VTF, VTX> {
     contractor: Address,
-    /// At least 1 input is required.
-    /// All receiver of inputs are contractor.
+    /// Each input is spent by the contractor or by cosigners, so that several keys can fund one transaction.
     inputs: Vec<Transition<VTF>>,
     /// At least 1 output is required.
-    /// All signer of outputs are contractor.
+    /// Transfer outputs are sent by the contractor, or by cosigners who joined a `SighashFlag::Single` transaction.
     outputs: Vec<Transition<VTF>>,
     timestamp: Timestamp,
     /// Contractor's sign
     sign: Signature,
+    /// Encoding which `sign` commits to.
+    version: SighashVersion,
+    /// Parts of the transaction which `sign` commits to.
+    flag: SighashFlag,
+    /// Preimages unlocking HTLC inputs. Not covered by `sign`.
+    preimages: Vec<Vec<u8>>,
+    /// Signs of other parties over the same message as `sign`, which unlock their own or multisig inputs.
+    cosigns: Vec<(Address, Signature)>,
+    /// Blocks before this cannot include the transaction. Covered by `sign`.
+    lock_time: Option<LockTime>,
     #[serde(skip_serializing)]
     _phantom: PhantomData<fn() -> VTX>,
 }
 
 impl<VTR, VTX> Transaction<VTR, VTX> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Transaction<Yet, Yet> {
+        Transaction {
+            contractor: self.contractor.clone(),
+            inputs: self.inputs.iter().map(Transition::to_unverified).collect(),
+            outputs: self.outputs.iter().map(Transition::to_unverified).collect(),
+            timestamp: self.timestamp,
+            sign: self.sign.clone(),
+            version: self.version,
+            flag: self.flag,
+            preimages: self.preimages.clone(),
+            cosigns: self.cosigns.clone(),
+            lock_time: self.lock_time,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn contractor(&self) -> &Address {
+        &self.contractor
+    }
+
     pub fn inputs(&self) -> &[Transition<VTR>] {
         &self.inputs
     }
@@ -41,6 +124,162 @@ impl<VTR, VTX> Transaction<VTR, VTX> {
     pub fn timestamp(&self) -> Timestamp {
         self.timestamp
     }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
+
+    pub fn flag(&self) -> SighashFlag {
+        self.flag
+    }
+
+    pub fn preimages(&self) -> &[Vec<u8>] {
+        &self.preimages
+    }
+
+    pub fn cosigns(&self) -> &[(Address, Signature)] {
+        &self.cosigns
+    }
+
+    pub fn lock_time(&self) -> Option<LockTime> {
+        self.lock_time
+    }
+
+    /// Whether a block at `height`, whose median time past is `time`, may include this.
+    pub fn is_final(&self, height: BlockHeight, time: Timestamp) -> bool {
+        self.lock_time
+            .is_none_or(|lock_time| lock_time.is_reached(height, time))
+    }
+
+    /// Identifier of the transaction, which is the digest of what a block digest commits to.
+    /// Always encoded as `TXID_VERSION`, so that bumping `SighashVersion::CURRENT`
+    /// keeps the txids, Merkle roots and outpoints of mined blocks.
+    pub fn txid(&self) -> BlockDigest {
+        let mut builder = SignatureBuilder::sighash(TXID_VERSION, SighashDomain::TransactionId);
+        self.write_bytes(&mut builder);
+        BlockDigest::digest(&builder.finalize())
+    }
+
+    /// Outputs with the outpoints which later transactions spend them by.
+    pub fn created_outputs(&self) -> impl Iterator<Item = (OutPoint, &Transition<VTR>)> + '_ {
+        let txid = self.txid();
+        self.outputs.iter().enumerate().map(move |(index, output)| {
+            let outpoint = OutPoint {
+                txid: txid.clone(),
+                index: index as u32,
+            };
+            (outpoint, output)
+        })
+    }
+
+    /// Coins left to the miner, which are inputs not sent by transfer outputs.
+    pub fn fee(&self) -> Coin {
+        let input_sum = self.inputs.iter().map(Transition::quantity).sum::<Coin>();
+        let output_sum_except_gen = self
+            .outputs
+            .iter()
+            .filter(|o| o.sender().is_some())
+            .map(Transition::quantity)
+            .sum::<Coin>();
+        input_sum
+            .checked_sub(output_sum_except_gen)
+            .unwrap_or_default()
+    }
+
+    /// Contractor and cosigners.
+    pub fn signers(&self) -> impl Iterator<Item = &Address> + Clone {
+        std::iter::once(&self.contractor).chain(self.cosigns.iter().map(|(a, _)| a))
+    }
+
+    /// Check spend conditions of the inputs in a block at `height`, or except timelocks if `None`.
+    pub fn check_spend_conditions(&self, height: Option<BlockHeight>) -> Result<(), SpendError> {
+        let spend = Spend {
+            signers: self.signers().collect(),
+            preimages: &self.preimages,
+            height,
+        };
+        self.inputs
+            .iter()
+            .try_for_each(|input| input.spend_condition().check(&spend))
+    }
+
+    /// Replace inputs, keeping the signs as they are.
+    pub(crate) fn with_inputs(mut self, inputs: Vec<Transition<VTR>>) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    /// Message which `signer`, the contractor or a cosigner, signs.
+    fn sighash_for(&self, signer: &Address) -> Vec<u8> {
+        let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Transaction);
+        build_sighash_source(
+            signer,
+            &self.contractor,
+            &self.inputs,
+            &self.outputs,
+            self.timestamp,
+            self.flag,
+            self.lock_time.as_ref(),
+            &mut builder,
+        );
+        builder.finalize_sighash()
+    }
+}
+
+impl Transaction<Yet, Yet> {
+    /// Message which `signer` of a transaction of these parts signs,
+    /// in the current encoding. Outputs may be unsigned yet.
+    pub(crate) fn sighash_of<O: SighashOutput>(
+        signer: &Address,
+        contractor: &Address,
+        inputs: &[Transition<Yet>],
+        outputs: &[O],
+        timestamp: Timestamp,
+        flag: SighashFlag,
+        lock_time: Option<&LockTime>,
+    ) -> Vec<u8> {
+        let mut builder =
+            SignatureBuilder::sighash(SighashVersion::CURRENT, SighashDomain::Transaction);
+        build_sighash_source(
+            signer,
+            contractor,
+            inputs,
+            outputs,
+            timestamp,
+            flag,
+            lock_time,
+            &mut builder,
+        );
+        builder.finalize_sighash()
+    }
+
+    /// Transaction of signs made separately, in the current encoding. Nothing is verified.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_parts(
+        contractor: Address,
+        inputs: Vec<Transition<Yet>>,
+        outputs: Vec<Transition<Yet>>,
+        timestamp: Timestamp,
+        sign: Signature,
+        flag: SighashFlag,
+        preimages: Vec<Vec<u8>>,
+        cosigns: Vec<(Address, Signature)>,
+        lock_time: Option<LockTime>,
+    ) -> Self {
+        Transaction {
+            contractor,
+            inputs,
+            outputs,
+            timestamp,
+            sign,
+            version: SighashVersion::CURRENT,
+            flag,
+            preimages,
+            cosigns,
+            lock_time,
+            _phantom: PhantomData,
+        }
+    }
 }
 
 impl<VTR> Transaction<VTR, Yet> {
@@ -49,24 +288,80 @@ impl<VTR> Transaction<VTR, Yet> {
         inputs: Vec<T>,
         outputs: Vec<U>,
     ) -> Transaction<VTR, Yet>
+    where
+        T: Into<Transition<VTR>>,
+        U: Into<Transition<VTR>>,
+    {
+        Self::offer_with_flag(contractor, inputs, outputs, SighashFlag::All)
+    }
+
+    /// Offer a transaction which blocks cannot include until `lock_time`, such as a future payment.
+    pub fn offer_with_lock_time<T, U>(
+        contractor: &SecretAddress,
+        inputs: Vec<T>,
+        outputs: Vec<U>,
+        lock_time: LockTime,
+    ) -> Transaction<VTR, Yet>
+    where
+        T: Into<Transition<VTR>>,
+        U: Into<Transition<VTR>>,
+    {
+        Self::offer_with_clock(
+            contractor,
+            inputs,
+            outputs,
+            SighashFlag::All,
+            Some(lock_time),
+            &SystemClock,
+        )
+    }
+
+    /// Offer a transaction whose sign commits only to the parts selected by `flag`.
+    pub fn offer_with_flag<T, U>(
+        contractor: &SecretAddress,
+        inputs: Vec<T>,
+        outputs: Vec<U>,
+        flag: SighashFlag,
+    ) -> Transaction<VTR, Yet>
+    where
+        T: Into<Transition<VTR>>,
+        U: Into<Transition<VTR>>,
+    {
+        Self::offer_with_clock(contractor, inputs, outputs, flag, None, &SystemClock)
+    }
+
+    /// Offer a transaction timestamped by `clock`, with any flag and lock time.
+    pub fn offer_with_clock<T, U>(
+        contractor: &SecretAddress,
+        inputs: Vec<T>,
+        outputs: Vec<U>,
+        flag: SighashFlag,
+        lock_time: Option<LockTime>,
+        clock: &dyn Clock,
+    ) -> Transaction<VTR, Yet>
     where
         T: Into<Transition<VTR>>,
         U: Into<Transition<VTR>>,
     {
         let inputs = inputs.into_iter().map(Into::into).collect::<Vec<_>>();
         let outputs = outputs.into_iter().map(Into::into).collect::<Vec<_>>();
-        let timestamp = Timestamp::now();
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
 
         let sign = {
-            let mut builder = SignatureBuilder::new();
-            build_signature_source(
-                &contractor.to_public_address(),
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Transaction);
+            let address = contractor.to_public_address();
+            build_sighash_source(
+                &address,
+                &address,
                 &inputs,
                 &outputs,
                 timestamp,
+                flag,
+                lock_time.as_ref(),
                 &mut builder,
             );
-            contractor.sign(&builder.finalize())
+            contractor.sign(&builder.finalize_sighash())
         };
 
         Transaction {
@@ -75,27 +370,91 @@ impl<VTR> Transaction<VTR, Yet> {
             outputs,
             timestamp,
             sign,
+            version,
+            flag,
+            preimages: vec![],
+            cosigns: vec![],
+            lock_time,
             _phantom: PhantomData,
         }
     }
 
+    /// Reveal a preimage to claim HTLC inputs. Preimages are kept in ascending order.
+    pub fn with_preimage(mut self, preimage: Vec<u8>) -> Self {
+        if let Err(i) = self.preimages.binary_search(&preimage) {
+            self.preimages.insert(i, preimage);
+        }
+        self
+    }
+
+    /// Add a sign of `cosigner` to spend its own or multisig inputs together with the contractor.
+    /// Under `SighashFlag::Single`, the sign commits only to the inputs and outputs of `cosigner`,
+    /// who may join by adding them to a transaction which the contractor has already signed.
+    pub fn cosign(mut self, cosigner: &SecretAddress) -> Self {
+        let address = cosigner.to_public_address();
+        let sign = cosigner.sign(&self.sighash_for(&address));
+        self.cosigns.push((address, sign));
+        self
+    }
+
     pub fn verify_transaction(self) -> Result<Transaction<VTR, Verified>, TransactionError> {
+        self.verify_rules()?;
+
+        if !self
+            .contractor
+            .verify(&self.sighash_for(&self.contractor), &self.sign)
+        {
+            return Err(TransactionError::InvalidSign);
+        }
+        if self
+            .cosigns
+            .iter()
+            .any(|(cosigner, sign)| !cosigner.verify(&self.sighash_for(cosigner), sign))
+        {
+            return Err(TransactionError::InvalidCosign);
+        }
+
+        Ok(self.assume_signed())
+    }
+
+    /// Check everything but signs.
+    fn verify_rules(&self) -> Result<(), TransactionError> {
         // At least 1 output is required
         if self.outputs.is_empty() {
             return Err(TransactionError::EmptyOutput);
         }
 
-        // Input's receiver = contractor
-        if !self.inputs.is_empty() && self.inputs.iter().any(|i| i.receiver() != &self.contractor) {
-            return Err(TransactionError::SenderMismatch);
+        // Inputs' spend conditions, whose timelocks are checked by the ledger
+        self.check_spend_conditions(None).map_err(|e| match e {
+            SpendError::MissingPreimage => TransactionError::MissingPreimage,
+            SpendError::MissingSign | SpendError::Timelock => TransactionError::SenderMismatch,
+        })?;
+        // Preimages are not signed but change the txid, so only one list of them is allowed:
+        // those unlocking inputs, in ascending order without duplicates
+        let conditions = self
+            .inputs
+            .iter()
+            .map(Transition::spend_condition)
+            .collect::<Vec<_>>();
+        if self.preimages.windows(2).any(|pair| pair[0] >= pair[1])
+            || self.preimages.iter().any(|preimage| {
+                !conditions
+                    .iter()
+                    .any(|condition| condition.is_unlocked_by(preimage))
+            })
+        {
+            return Err(TransactionError::InvalidPreimages);
         }
-        // Transfer output's sender = contractor
+        // Transfer output's sender = contractor, or a cosigner who joined by a single sign
         // Note: generations in outputs are not checked.
         if self
             .outputs
             .iter()
-            .filter_map(Transition::try_as_transfer)
-            .any(|i| i.sender() != &self.contractor)
+            .filter_map(Transition::sender)
+            .any(|sender| match self.flag {
+                SighashFlag::All => sender != &self.contractor,
+                SighashFlag::Single => self.signers().all(|signer| signer != sender),
+            })
         {
             return Err(TransactionError::ReceiverMismatch);
         }
@@ -105,8 +464,8 @@ impl<VTR> Transaction<VTR, Yet> {
         let output_sum_except_gen = self
             .outputs
             .iter()
-            .filter_map(Transition::try_as_transfer)
-            .map(Transfer::quantity)
+            .filter(|o| o.sender().is_some())
+            .map(Transition::quantity)
             .sum::<Coin>();
         if input_sum < output_sum_except_gen {
             return Err(TransactionError::QuantityMismatch);
@@ -123,20 +482,73 @@ impl<VTR> Transaction<VTR, Yet> {
         }
 
         // Sign
-        let signature_source = self.build_signature_source();
-        if !self.contractor.verify(&signature_source, &self.sign) {
-            return Err(TransactionError::InvalidSign);
+        // The legacy encoding cannot express flags
+        if self.version == SighashVersion::Legacy && self.flag != SighashFlag::All {
+            return Err(TransactionError::InvalidSighashFlag);
+        }
+        // Nor tell height locks from time locks
+        if self.version == SighashVersion::Legacy && self.lock_time.is_some() {
+            return Err(TransactionError::InvalidLockTime);
         }
+        if self.flag == SighashFlag::Single {
+            self.verify_single_sign_rules()?;
+        }
+        Ok(())
+    }
 
-        let tx = Transaction {
+    /// Each signer of a single sign commits only to their own inputs and outputs,
+    /// so none of them may send more than their own inputs.
+    fn verify_single_sign_rules(&self) -> Result<(), TransactionError> {
+        // Contractor's sign must commit to at least one output
+        if !self
+            .outputs
+            .iter()
+            .any(|o| o.sender() == Some(&self.contractor))
+        {
+            return Err(TransactionError::InvalidSighashFlag);
+        }
+        // Every input must be committed to by the sign of its owner
+        if !self
+            .inputs
+            .iter()
+            .all(|i| self.signers().any(|signer| is_own_input(i, signer)))
+        {
+            return Err(TransactionError::InvalidSighashFlag);
+        }
+        for signer in self.signers() {
+            let input_sum = self
+                .inputs
+                .iter()
+                .filter(|i| is_own_input(i, signer))
+                .map(Transition::quantity)
+                .sum::<Coin>();
+            let output_sum = self
+                .outputs
+                .iter()
+                .filter(|o| o.sender() == Some(signer))
+                .map(Transition::quantity)
+                .sum::<Coin>();
+            if input_sum < output_sum {
+                return Err(TransactionError::QuantityMismatch);
+            }
+        }
+        Ok(())
+    }
+
+    fn assume_signed(self) -> Transaction<VTR, Verified> {
+        Transaction {
             contractor: self.contractor,
             inputs: self.inputs,
             outputs: self.outputs,
             timestamp: self.timestamp,
             sign: self.sign,
+            version: self.version,
+            flag: self.flag,
+            preimages: self.preimages,
+            cosigns: self.cosigns,
+            lock_time: self.lock_time,
             _phantom: PhantomData,
-        };
-        Ok(tx)
+        }
     }
 }
 
@@ -145,6 +557,36 @@ impl Transaction<Yet, Yet> {
         self.verify_transition()
             .and_then(Transaction::verify_transaction)
     }
+
+    /// Check everything but the signs of the transaction and its transitions,
+    /// for blocks under an assumed-valid checkpoint.
+    pub fn verify_except_signs(self) -> Result<Transaction<Verified, Verified>, TransactionError> {
+        let inputs = self
+            .inputs
+            .into_iter()
+            .map(Transition::assume_signed)
+            .collect();
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(Transition::assume_signed)
+            .collect();
+        let tx = Transaction {
+            contractor: self.contractor,
+            inputs,
+            outputs,
+            timestamp: self.timestamp,
+            sign: self.sign,
+            version: self.version,
+            flag: self.flag,
+            preimages: self.preimages,
+            cosigns: self.cosigns,
+            lock_time: self.lock_time,
+            _phantom: PhantomData,
+        };
+        tx.verify_rules()?;
+        Ok(tx.assume_signed())
+    }
 }
 
 impl<VTX> Transaction<Yet, VTX> {
@@ -168,6 +610,11 @@ impl<VTX> Transaction<Yet, VTX> {
             outputs,
             timestamp: self.timestamp,
             sign: self.sign,
+            version: self.version,
+            flag: self.flag,
+            preimages: self.preimages,
+            cosigns: self.cosigns,
+            lock_time: self.lock_time,
             _phantom: PhantomData,
         };
         Ok(tx)
@@ -176,13 +623,20 @@ impl<VTX> Transaction<Yet, VTX> {
 
 impl<VTR, VTX> SignatureSource for Transaction<VTR, VTX> {
     fn write_bytes(&self, builder: &mut SignatureBuilder) {
-        build_signature_source(
-            &self.contractor,
-            &self.inputs,
-            &self.outputs,
-            self.timestamp,
-            builder,
-        );
+        self.contractor.write_bytes(builder);
+        self.inputs.as_slice().write_bytes(builder);
+        self.outputs.as_slice().write_bytes(builder);
+        self.timestamp.write_bytes(builder);
+        builder.write_variant(self.flag.to_byte());
+        builder.write_len(self.preimages.len());
+        for preimage in self.preimages.iter() {
+            builder.write_bytes(preimage);
+        }
+        builder.write_len(self.cosigns.len());
+        for (cosigner, _) in self.cosigns.iter() {
+            cosigner.write_bytes(builder);
+        }
+        write_lock_time(builder, self.lock_time.as_ref());
     }
 }
 
@@ -198,6 +652,18 @@ impl<'de> Deserialize<'de> for Transaction<Yet, Yet> {
             outputs: Vec<Transition<Yet>>,
             timestamp: Timestamp,
             sign: Signature,
+            // Defaults fill fields missing from older JSON only.
+            // Bincode, as used on the wire and on disk, requires every field.
+            #[serde(default)]
+            version: SighashVersion,
+            #[serde(default)]
+            flag: SighashFlag,
+            #[serde(default)]
+            preimages: Vec<Vec<u8>>,
+            #[serde(default)]
+            cosigns: Vec<(Address, Signature)>,
+            #[serde(default)]
+            lock_time: Option<LockTime>,
         }
 
         let inner = Inner::deserialize(deserializer)?;
@@ -208,6 +674,11 @@ impl<'de> Deserialize<'de> for Transaction<Yet, Yet> {
             outputs: inner.outputs,
             timestamp: inner.timestamp,
             sign: inner.sign,
+            version: inner.version,
+            flag: inner.flag,
+            preimages: inner.preimages,
+            cosigns: inner.cosigns,
+            lock_time: inner.lock_time,
             _phantom: PhantomData,
         };
 
@@ -229,6 +700,16 @@ pub enum TransactionError {
     InvalidTimestamp,
     /// Contractor's sign is invalid.
     InvalidSign,
+    /// Sighash flag is not allowed for the transaction.
+    InvalidSighashFlag,
+    /// HTLC input is claimed without its preimage.
+    MissingPreimage,
+    /// Preimages unlock no input, or are not in ascending order without duplicates.
+    InvalidPreimages,
+    /// Any cosigner's sign is invalid.
+    InvalidCosign,
+    /// Lock time is not allowed for the transaction.
+    InvalidLockTime,
 }
 
 impl Display for TransactionError {
@@ -243,6 +724,11 @@ impl Display for TransactionError {
             TransactionError::QuantityMismatch => write!(f, "Quantity mismatch"),
             TransactionError::InvalidTimestamp => write!(f, "Transaction contains newer transfer"),
             TransactionError::InvalidSign => write!(f, "Contractor's sign is invald"),
+            TransactionError::InvalidSighashFlag => write!(f, "Sighash flag is not allowed"),
+            TransactionError::MissingPreimage => write!(f, "HTLC is claimed without preimage"),
+            TransactionError::InvalidPreimages => write!(f, "Preimages are not the ones needed"),
+            TransactionError::InvalidCosign => write!(f, "Cosigner's sign is invalid"),
+            TransactionError::InvalidLockTime => write!(f, "Lock time is not allowed"),
         }
     }
 }
@@ -256,23 +742,92 @@ impl Error for TransactionError {
     }
 }
 
-fn build_signature_source<T>(
+/// Output as the transaction sighash sees it, which leaves out its own sign.
+/// Lets outputs be committed to before their sender signs them.
+pub(crate) trait SighashOutput: SignatureSource {
+    fn sender(&self) -> Option<&Address>;
+}
+
+impl<T> SighashOutput for Transition<T> {
+    fn sender(&self) -> Option<&Address> {
+        Transition::sender(self)
+    }
+}
+
+/// Input which only `signer` spends, so that a single sign of them commits to it.
+fn is_own_input<T>(input: &Transition<T>, signer: &Address) -> bool {
+    matches!(input, Transition::Transfer(_) | Transition::Generation(_))
+        && input.receiver() == signer
+}
+
+/// Message which `signer` signs. Inputs and outputs not selected by `flag` are left out.
+#[allow(clippy::too_many_arguments)]
+fn build_sighash_source<T, O: SighashOutput>(
+    signer: &Address,
     contractor: &Address,
     inputs: &[Transition<T>],
-    outputs: &[Transition<T>],
+    outputs: &[O],
     timestamp: Timestamp,
+    flag: SighashFlag,
+    lock_time: Option<&LockTime>,
     builder: &mut SignatureBuilder,
 ) {
     contractor.write_bytes(builder);
-    inputs.write_bytes(builder);
-    outputs.write_bytes(builder);
+    match flag {
+        SighashFlag::All => {
+            inputs.write_bytes(builder);
+            outputs.write_bytes(builder);
+        }
+        SighashFlag::Single => {
+            let own_inputs = inputs.iter().filter(|i| is_own_input(i, signer));
+            builder.write_len(own_inputs.clone().count());
+            for input in own_inputs {
+                input.write_bytes(builder);
+            }
+            let own_outputs = outputs.iter().filter(|o| o.sender() == Some(signer));
+            builder.write_len(own_outputs.clone().count());
+            for output in own_outputs {
+                output.write_bytes(builder);
+            }
+        }
+    }
     timestamp.write_bytes(builder);
+    builder.write_variant(flag.to_byte());
+    write_lock_time(builder, lock_time);
+}
+
+/// Transactions without lock time keep the sign and txid they had before lock times were introduced.
+fn write_lock_time(builder: &mut SignatureBuilder, lock_time: Option<&LockTime>) {
+    if let Some(lock_time) = lock_time {
+        builder.write_variant(1);
+        lock_time.write_bytes(builder);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Generation;
+    use crate::clock::MockClock;
+    use crate::{Generation, Transfer};
+
+    #[test]
+    fn test_created_outputs() {
+        let alice = SecretAddress::create();
+        let gen = Generation::offer(&alice, Coin::from(10));
+        let outputs = vec![
+            Transfer::offer(&alice, alice.to_public_address(), Coin::from(6)),
+            Transfer::offer(&alice, alice.to_public_address(), Coin::from(4)),
+        ];
+        let tx = Transaction::offer(&alice, vec![gen], outputs);
+
+        let created = tx.created_outputs().collect::<Vec<_>>();
+        assert_eq!(2, created.len());
+        for (i, (outpoint, output)) in created.into_iter().enumerate() {
+            assert_eq!(tx.txid(), outpoint.txid);
+            assert_eq!(i as u32, outpoint.index);
+            assert_eq!(&tx.outputs()[i], output);
+        }
+    }
 
     #[test]
     fn test_sign_verify() {
@@ -475,4 +1030,266 @@ mod tests {
 
         assert_eq!(Err(TransactionError::InvalidSign), tx);
     }
+
+    #[test]
+    fn test_verify_except_signs() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+        let quantity = Coin::from(42);
+
+        let input = Transfer::offer(&input_sender, contractor.to_public_address(), quantity);
+        let output = Transfer::offer(&contractor, output_receiver.clone(), quantity);
+        let tampered = Transfer::offer(&contractor, output_receiver, Coin::from(1));
+        let mut tx = Transaction::offer(&contractor, vec![input], vec![output]);
+        tx.outputs[0] = tampered.into();
+
+        // Signs are not checked, but the rest is
+        let tx = tx.to_unverified();
+        assert_eq!(Err(TransactionError::InvalidSign), tx.clone().verify());
+        assert!(tx.clone().verify_except_signs().is_ok());
+        let mut empty = tx;
+        empty.outputs.clear();
+        assert_eq!(
+            Err(TransactionError::EmptyOutput),
+            empty.verify_except_signs()
+        );
+    }
+
+    #[test]
+    fn test_sighash_single_allows_joining() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let joiner = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let payment = Transfer::offer(&contractor, output_receiver.clone(), Coin::from(6));
+        let tx = Transaction::offer_with_flag(
+            &contractor,
+            vec![input],
+            vec![payment],
+            SighashFlag::Single,
+        );
+        assert!(tx.clone().verify_transaction().is_ok());
+
+        // Another party joins with their own input and output, keeping the contractor's sign.
+        // Their output must not be later than the transaction.
+        let clock = MockClock::new(tx.timestamp());
+        let joined_input = Transfer::offer_with_clock(
+            &input_sender,
+            joiner.to_public_address(),
+            Coin::from(5),
+            &clock,
+        );
+        let joined_output =
+            Transfer::offer_with_clock(&joiner, output_receiver, Coin::from(4), &clock);
+        let mut joined = tx.clone();
+        joined.inputs.push(joined_input.into());
+        joined.outputs.push(joined_output.into());
+        assert_eq!(
+            Err(TransactionError::SenderMismatch),
+            joined.clone().verify_transaction()
+        );
+        let joined = joined.cosign(&joiner);
+        assert_eq!(2, joined.signers().count());
+        assert_eq!(Coin::from(5), joined.fee());
+        assert!(joined.clone().verify_transaction().is_ok());
+
+        // The joiner's sign commits to their own parts
+        let mut tampered = joined.clone();
+        tampered.outputs[1] =
+            Transfer::offer_with_clock(&joiner, joiner.to_public_address(), Coin::from(4), &clock)
+                .into();
+        assert_eq!(
+            Err(TransactionError::InvalidCosign),
+            tampered.verify_transaction()
+        );
+
+        // Nor can the contractor's own parts change
+        let mut tampered = joined;
+        tampered.outputs[0] = Transfer::offer_with_clock(
+            &contractor,
+            contractor.to_public_address(),
+            Coin::from(6),
+            &clock,
+        )
+        .into();
+        assert_eq!(
+            Err(TransactionError::InvalidSign),
+            tampered.verify_transaction()
+        );
+    }
+
+    #[test]
+    fn test_sighash_single_denies_spending_others_inputs() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let joiner = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let payment = Transfer::offer(&contractor, output_receiver.clone(), Coin::from(6));
+        let mut tx = Transaction::offer_with_flag(
+            &contractor,
+            vec![input],
+            vec![payment],
+            SighashFlag::Single,
+        );
+
+        // Left over coins of the contractor are the fee, not the joiner's
+        let clock = MockClock::new(tx.timestamp());
+        let joined_input = Transfer::offer_with_clock(
+            &input_sender,
+            joiner.to_public_address(),
+            Coin::from(1),
+            &clock,
+        );
+        let joined_output =
+            Transfer::offer_with_clock(&joiner, output_receiver, Coin::from(4), &clock);
+        tx.inputs.push(joined_input.into());
+        tx.outputs.push(joined_output.into());
+        let tx = tx.cosign(&joiner);
+
+        assert_eq!(
+            Err(TransactionError::QuantityMismatch),
+            tx.verify_transaction()
+        );
+    }
+
+    #[test]
+    fn test_sighash_all_denies_attaching_output() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let change = Transfer::offer(&contractor, contractor.to_public_address(), Coin::from(6));
+        let attached = Transfer::offer(&contractor, output_receiver, Coin::from(4));
+
+        let mut tx = Transaction::offer(&contractor, vec![input], vec![change]);
+        tx.outputs.push(attached.into());
+
+        assert_eq!(Err(TransactionError::InvalidSign), tx.verify_transaction());
+    }
+
+    #[test]
+    fn test_sighash_flag_tampered() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let change = Transfer::offer(&contractor, contractor.to_public_address(), Coin::from(10));
+
+        let mut tx = Transaction::offer(&contractor, vec![input], vec![change]);
+        tx.flag = SighashFlag::Single; // Tamper!
+
+        assert_eq!(Err(TransactionError::InvalidSign), tx.verify_transaction());
+    }
+
+    #[test]
+    fn test_lock_time_signed() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let change = Transfer::offer(&contractor, contractor.to_public_address(), Coin::from(10));
+        let lock_time = LockTime::Height(BlockHeight::from(5));
+
+        let tx =
+            Transaction::offer_with_lock_time(&contractor, vec![input], vec![change], lock_time);
+        let json = serde_json::to_string(&tx).unwrap();
+        let tx = serde_json::from_str::<Transaction<Yet, Yet>>(&json).unwrap();
+        assert_eq!(Some(lock_time), tx.lock_time());
+        assert!(!tx.is_final(BlockHeight::from(4), tx.timestamp()));
+        assert!(tx.is_final(BlockHeight::from(5), tx.timestamp()));
+
+        let mut tampered = tx.clone();
+        tampered.lock_time = None; // Tamper!
+        assert_ne!(tx.txid(), tampered.txid());
+        assert_eq!(Err(TransactionError::InvalidSign), tampered.verify());
+
+        assert!(tx.verify().is_ok());
+    }
+
+    #[test]
+    fn test_sighash_single_requires_own_output() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let joiner = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+
+        let input = Transfer::offer(
+            &input_sender,
+            contractor.to_public_address(),
+            Coin::from(10),
+        );
+        let joined_input =
+            Transfer::offer(&input_sender, joiner.to_public_address(), Coin::from(10));
+        let output = Transfer::offer(&joiner, output_receiver, Coin::from(10));
+
+        // Contractor's sign commits to nothing but their input
+        let tx = Transaction::offer_with_flag(
+            &contractor,
+            vec![input, joined_input],
+            vec![output],
+            SighashFlag::Single,
+        )
+        .cosign(&joiner);
+
+        assert_eq!(
+            Err(TransactionError::InvalidSighashFlag),
+            tx.verify_transaction()
+        );
+    }
+
+    #[test]
+    fn test_inputs_of_several_keys() {
+        let input_sender = SecretAddress::create();
+        let contractor = SecretAddress::create();
+        let funder = SecretAddress::create();
+        let output_receiver = SecretAddress::create().to_public_address();
+
+        let inputs = vec![
+            Transfer::offer(&input_sender, contractor.to_public_address(), Coin::from(6)),
+            Transfer::offer(&input_sender, funder.to_public_address(), Coin::from(4)),
+        ];
+        let output = Transfer::offer(&contractor, output_receiver, Coin::from(10));
+        let tx = Transaction::offer(&contractor, inputs, vec![output]);
+
+        // The funder must sign for its input
+        assert_eq!(
+            Err(TransactionError::SenderMismatch),
+            tx.clone().verify_transaction()
+        );
+        let mut forged = tx.clone();
+        forged
+            .cosigns
+            .push((funder.to_public_address(), funder.sign(b"other")));
+        assert_eq!(
+            Err(TransactionError::InvalidCosign),
+            forged.verify_transaction()
+        );
+        assert!(tx.cosign(&funder).verify_transaction().is_ok());
+    }
 }
@@ -1,7 +1,13 @@
 use crate::account::Address;
 use crate::account::SecretAddress;
+use crate::block::BlockHeight;
+use crate::clock::{Clock, SystemClock};
 use crate::coin::Coin;
-use crate::signature::{Signature, SignatureBuilder, SignatureSource};
+use crate::condition::{SpendCondition, Timelock};
+use crate::digest::BlockDigest;
+use crate::signature::{
+    SighashDomain, SighashVersion, Signature, SignatureBuilder, SignatureSource,
+};
 use crate::timestamp::Timestamp;
 use crate::verification::{Verified, Yet};
 use serde::{Deserialize, Deserializer, Serialize};
@@ -17,11 +23,26 @@ pub struct Transfer<T> {
     quantity: Coin,
     timestamp: Timestamp,
     sign: Signature,
+    /// Encoding which `sign` commits to.
+    version: SighashVersion,
     #[serde(skip_serializing)]
     _phantom: PhantomData<fn(T)>,
 }
 
 impl<T> Transfer<T> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Transfer<Yet> {
+        Transfer {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            quantity: self.quantity,
+            timestamp: self.timestamp,
+            sign: self.sign.clone(),
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+
     pub fn sender(&self) -> &Address {
         &self.sender
     }
@@ -41,12 +62,16 @@ impl<T> Transfer<T> {
     pub fn sign(&self) -> &Signature {
         &self.sign
     }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
 }
 
 impl Transfer<Yet> {
     pub fn verify(self) -> Result<Transfer<Verified>, TransferError> {
         let signature_source = {
-            let mut builder = SignatureBuilder::new();
+            let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Transfer);
             build_transfer_signature_source(
                 &self.sender,
                 &self.receiver,
@@ -54,30 +79,81 @@ impl Transfer<Yet> {
                 self.timestamp,
                 &mut builder,
             );
-            builder.finalize()
+            builder.finalize_sighash()
         };
 
         if self.sender.verify(&signature_source, &self.sign) {
-            Ok(Transfer {
-                sender: self.sender,
-                receiver: self.receiver,
-                quantity: self.quantity,
-                timestamp: self.timestamp,
-                sign: self.sign,
-                _phantom: PhantomData,
-            })
+            Ok(self.assume_signed())
         } else {
             Err(TransferError)
         }
     }
+
+    /// Take the sign as valid without checking it, for blocks under an assumed-valid checkpoint.
+    pub(crate) fn assume_signed(self) -> Transfer<Verified> {
+        Transfer {
+            sender: self.sender,
+            receiver: self.receiver,
+            quantity: self.quantity,
+            timestamp: self.timestamp,
+            sign: self.sign,
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Transfer<Yet> {
+    /// Message which `sender` signs to offer a transfer, in the current encoding.
+    pub(crate) fn sighash_of(
+        sender: &Address,
+        receiver: &Address,
+        quantity: Coin,
+        timestamp: Timestamp,
+    ) -> Vec<u8> {
+        let mut builder =
+            SignatureBuilder::sighash(SighashVersion::CURRENT, SighashDomain::Transfer);
+        build_transfer_signature_source(sender, receiver, quantity, timestamp, &mut builder);
+        builder.finalize_sighash()
+    }
+
+    /// Transfer of a sign made separately, in the current encoding. Nothing is verified.
+    pub(crate) fn from_parts(
+        sender: Address,
+        receiver: Address,
+        quantity: Coin,
+        timestamp: Timestamp,
+        sign: Signature,
+    ) -> Self {
+        Transfer {
+            sender,
+            receiver,
+            quantity,
+            timestamp,
+            sign,
+            version: SighashVersion::CURRENT,
+            _phantom: PhantomData,
+        }
+    }
 }
 
 impl Transfer<Verified> {
     pub fn offer(sender: &SecretAddress, receiver: Address, quantity: Coin) -> Transfer<Verified> {
-        let timestamp = Timestamp::now();
+        Self::offer_with_clock(sender, receiver, quantity, &SystemClock)
+    }
+
+    /// Offer a transfer timestamped by `clock`.
+    pub fn offer_with_clock(
+        sender: &SecretAddress,
+        receiver: Address,
+        quantity: Coin,
+        clock: &dyn Clock,
+    ) -> Transfer<Verified> {
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
 
         let sign = {
-            let mut builder = SignatureBuilder::new();
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Transfer);
             build_transfer_signature_source(
                 &sender.to_public_address(),
                 &receiver,
@@ -85,7 +161,7 @@ impl Transfer<Verified> {
                 timestamp,
                 &mut builder,
             );
-            let signature_source = builder.finalize();
+            let signature_source = builder.finalize_sighash();
             sender.sign(&signature_source)
         };
 
@@ -95,6 +171,7 @@ impl Transfer<Verified> {
             quantity,
             timestamp,
             sign,
+            version,
             _phantom: PhantomData,
         }
     }
@@ -104,7 +181,7 @@ impl<T> Display for Transfer<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Transfer {} coin from {} to {}, timestamp: {}, sign: {}",
+            "Transfer {} from {} to {}, timestamp: {}, sign: {}",
             self.quantity, self.sender, self.receiver, self.timestamp, self.sign
         )
     }
@@ -123,6 +200,8 @@ impl<'de> Deserialize<'de> for Transfer<Yet> {
             quantity: Coin,
             timestamp: Timestamp,
             sign: Signature,
+            #[serde(default)]
+            version: SighashVersion,
         }
 
         let inner = Inner::deserialize(deserializer)?;
@@ -133,6 +212,7 @@ impl<'de> Deserialize<'de> for Transfer<Yet> {
             quantity: inner.quantity,
             timestamp: inner.timestamp,
             sign: inner.sign,
+            version: inner.version,
             _phantom: PhantomData,
         };
         Ok(transfer)
@@ -158,11 +238,25 @@ pub struct Generation<T> {
     quantity: Coin,
     timestamp: Timestamp,
     sign: Signature,
+    /// Encoding which `sign` commits to.
+    version: SighashVersion,
     #[serde(skip_serializing)]
     _phantom: PhantomData<fn(T)>,
 }
 
 impl<T> Generation<T> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Generation<Yet> {
+        Generation {
+            receiver: self.receiver.clone(),
+            quantity: self.quantity,
+            timestamp: self.timestamp,
+            sign: self.sign.clone(),
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+
     pub fn receiver(&self) -> &Address {
         &self.receiver
     }
@@ -178,48 +272,68 @@ impl<T> Generation<T> {
     pub fn sign(&self) -> &Signature {
         &self.sign
     }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
 }
 
 impl Generation<Yet> {
     pub fn verify(self) -> Result<Generation<Verified>, TransferError> {
         let signature_source = {
-            let mut builder = SignatureBuilder::new();
+            let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Generation);
             build_generation_signature_source(
                 &self.receiver,
                 self.quantity,
                 self.timestamp,
                 &mut builder,
             );
-            builder.finalize()
+            builder.finalize_sighash()
         };
 
         if self.receiver.verify(&signature_source, &self.sign) {
-            Ok(Generation {
-                receiver: self.receiver,
-                quantity: self.quantity,
-                timestamp: self.timestamp,
-                sign: self.sign,
-                _phantom: PhantomData,
-            })
+            Ok(self.assume_signed())
         } else {
             Err(TransferError)
         }
     }
+
+    /// Take the sign as valid without checking it.
+    pub(crate) fn assume_signed(self) -> Generation<Verified> {
+        Generation {
+            receiver: self.receiver,
+            quantity: self.quantity,
+            timestamp: self.timestamp,
+            sign: self.sign,
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
 }
 
 impl Generation<Verified> {
     pub fn offer(receiver: &SecretAddress, quantity: Coin) -> Generation<Verified> {
-        let timestamp = Timestamp::now();
+        Self::offer_with_clock(receiver, quantity, &SystemClock)
+    }
+
+    /// Offer a generation timestamped by `clock`.
+    pub fn offer_with_clock(
+        receiver: &SecretAddress,
+        quantity: Coin,
+        clock: &dyn Clock,
+    ) -> Generation<Verified> {
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
 
         let sign = {
-            let mut builder = SignatureBuilder::new();
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Generation);
             build_generation_signature_source(
                 &receiver.to_public_address(),
                 quantity,
                 timestamp,
                 &mut builder,
             );
-            let signature_source = builder.finalize();
+            let signature_source = builder.finalize_sighash();
             receiver.sign(&signature_source)
         };
 
@@ -228,6 +342,7 @@ impl Generation<Verified> {
             quantity,
             timestamp,
             sign,
+            version,
             _phantom: PhantomData,
         }
     }
@@ -237,7 +352,7 @@ impl<T> Display for Generation<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Generation {} coin to {}, timestamp: {}, sign: {}",
+            "Generation {} to {}, timestamp: {}, sign: {}",
             self.quantity, self.receiver, self.timestamp, self.sign
         )
     }
@@ -255,6 +370,8 @@ impl<'de> Deserialize<'de> for Generation<Yet> {
             quantity: Coin,
             timestamp: Timestamp,
             sign: Signature,
+            #[serde(default)]
+            version: SighashVersion,
         }
 
         let inner = Inner::deserialize(deserializer)?;
@@ -264,6 +381,7 @@ impl<'de> Deserialize<'de> for Generation<Yet> {
             quantity: inner.quantity,
             timestamp: inner.timestamp,
             sign: inner.sign,
+            version: inner.version,
             _phantom: PhantomData,
         };
         Ok(gen)
@@ -276,18 +394,468 @@ impl<T> SignatureSource for Generation<T> {
     }
 }
 
+/// Hashed timelock contract, which locks coin for an atomic swap.
+/// The receiver can spend it with a preimage of `hash_lock` before `timeout`.
+/// The sender can take it back at or after `timeout`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Htlc<T> {
+    sender: Address,
+    receiver: Address,
+    quantity: Coin,
+    /// SHA-256 digest of the preimage
+    hash_lock: BlockDigest,
+    /// First block height where the sender can refund
+    timeout: BlockHeight,
+    timestamp: Timestamp,
+    sign: Signature,
+    /// Encoding which `sign` commits to.
+    version: SighashVersion,
+    #[serde(skip_serializing)]
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T> Htlc<T> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Htlc<Yet> {
+        Htlc {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            quantity: self.quantity,
+            hash_lock: self.hash_lock.clone(),
+            timeout: self.timeout,
+            timestamp: self.timestamp,
+            sign: self.sign.clone(),
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn sender(&self) -> &Address {
+        &self.sender
+    }
+
+    pub fn receiver(&self) -> &Address {
+        &self.receiver
+    }
+
+    pub fn quantity(&self) -> Coin {
+        self.quantity
+    }
+
+    pub fn hash_lock(&self) -> &BlockDigest {
+        &self.hash_lock
+    }
+
+    pub fn timeout(&self) -> BlockHeight {
+        self.timeout
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn sign(&self) -> &Signature {
+        &self.sign
+    }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
+
+    /// Claim by the receiver with a preimage before timeout, or refund to the sender after.
+    pub fn spend_condition(&self) -> SpendCondition {
+        let claim = SpendCondition::HashLock {
+            key: self.receiver.clone(),
+            hash_lock: self.hash_lock.clone(),
+        };
+        let refund = SpendCondition::Key(self.sender.clone());
+        SpendCondition::Any(vec![
+            SpendCondition::Timelock(Timelock::Before(self.timeout), Box::new(claim)),
+            SpendCondition::Timelock(Timelock::From(self.timeout), Box::new(refund)),
+        ])
+    }
+}
+
+impl Htlc<Yet> {
+    pub fn verify(self) -> Result<Htlc<Verified>, TransferError> {
+        let signature_source = {
+            let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Htlc);
+            self.write_bytes(&mut builder);
+            builder.finalize_sighash()
+        };
+
+        if self.sender.verify(&signature_source, &self.sign) {
+            Ok(self.assume_signed())
+        } else {
+            Err(TransferError)
+        }
+    }
+
+    /// Take the sign as valid without checking it.
+    pub(crate) fn assume_signed(self) -> Htlc<Verified> {
+        Htlc {
+            sender: self.sender,
+            receiver: self.receiver,
+            quantity: self.quantity,
+            hash_lock: self.hash_lock,
+            timeout: self.timeout,
+            timestamp: self.timestamp,
+            sign: self.sign,
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Htlc<Verified> {
+    pub fn offer(
+        sender: &SecretAddress,
+        receiver: Address,
+        quantity: Coin,
+        hash_lock: BlockDigest,
+        timeout: BlockHeight,
+    ) -> Htlc<Verified> {
+        Self::offer_with_clock(sender, receiver, quantity, hash_lock, timeout, &SystemClock)
+    }
+
+    /// Offer an HTLC timestamped by `clock`.
+    pub fn offer_with_clock(
+        sender: &SecretAddress,
+        receiver: Address,
+        quantity: Coin,
+        hash_lock: BlockDigest,
+        timeout: BlockHeight,
+        clock: &dyn Clock,
+    ) -> Htlc<Verified> {
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
+
+        let sign = {
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Htlc);
+            build_htlc_signature_source(
+                &sender.to_public_address(),
+                &receiver,
+                quantity,
+                &hash_lock,
+                timeout,
+                timestamp,
+                &mut builder,
+            );
+            let signature_source = builder.finalize_sighash();
+            sender.sign(&signature_source)
+        };
+
+        Htlc {
+            sender: sender.to_public_address(),
+            receiver,
+            quantity,
+            hash_lock,
+            timeout,
+            timestamp,
+            sign,
+            version,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Display for Htlc<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HTLC {} from {} to {}, hash lock: {}, timeout: {}, timestamp: {}, sign: {}",
+            self.quantity,
+            self.sender,
+            self.receiver,
+            self.hash_lock,
+            self.timeout,
+            self.timestamp,
+            self.sign
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Htlc<Yet> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Temporary tipe for deserialization
+        #[derive(Deserialize)]
+        struct Inner {
+            sender: Address,
+            receiver: Address,
+            quantity: Coin,
+            hash_lock: BlockDigest,
+            timeout: BlockHeight,
+            timestamp: Timestamp,
+            sign: Signature,
+            version: SighashVersion,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+
+        let htlc = Htlc {
+            sender: inner.sender,
+            receiver: inner.receiver,
+            quantity: inner.quantity,
+            hash_lock: inner.hash_lock,
+            timeout: inner.timeout,
+            timestamp: inner.timestamp,
+            sign: inner.sign,
+            version: inner.version,
+            _phantom: PhantomData,
+        };
+        Ok(htlc)
+    }
+}
+
+impl<T> SignatureSource for Htlc<T> {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        build_htlc_signature_source(
+            &self.sender,
+            &self.receiver,
+            self.quantity,
+            &self.hash_lock,
+            self.timeout,
+            self.timestamp,
+            builder,
+        );
+    }
+}
+
+/// 2-of-2 multisig output, which locks coin for a payment channel.
+/// It can be spent with signs of both the sender and the receiver.
+/// The sender alone can take it back at or after `timeout`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Multisig<T> {
+    sender: Address,
+    receiver: Address,
+    quantity: Coin,
+    /// First block height where the sender can refund
+    timeout: BlockHeight,
+    timestamp: Timestamp,
+    sign: Signature,
+    /// Encoding which `sign` commits to.
+    version: SighashVersion,
+    #[serde(skip_serializing)]
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T> Multisig<T> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Multisig<Yet> {
+        Multisig {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            quantity: self.quantity,
+            timeout: self.timeout,
+            timestamp: self.timestamp,
+            sign: self.sign.clone(),
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn sender(&self) -> &Address {
+        &self.sender
+    }
+
+    pub fn receiver(&self) -> &Address {
+        &self.receiver
+    }
+
+    pub fn quantity(&self) -> Coin {
+        self.quantity
+    }
+
+    pub fn timeout(&self) -> BlockHeight {
+        self.timeout
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn sign(&self) -> &Signature {
+        &self.sign
+    }
+
+    pub fn version(&self) -> SighashVersion {
+        self.version
+    }
+
+    /// Signs of both parties, or refund to the sender after timeout.
+    pub fn spend_condition(&self) -> SpendCondition {
+        let both = SpendCondition::Multisig(vec![self.sender.clone(), self.receiver.clone()]);
+        let refund = SpendCondition::Key(self.sender.clone());
+        SpendCondition::Any(vec![
+            both,
+            SpendCondition::Timelock(Timelock::From(self.timeout), Box::new(refund)),
+        ])
+    }
+}
+
+impl Multisig<Yet> {
+    pub fn verify(self) -> Result<Multisig<Verified>, TransferError> {
+        let signature_source = {
+            let mut builder = SignatureBuilder::sighash(self.version, SighashDomain::Multisig);
+            self.write_bytes(&mut builder);
+            builder.finalize_sighash()
+        };
+
+        if self.sender.verify(&signature_source, &self.sign) {
+            Ok(self.assume_signed())
+        } else {
+            Err(TransferError)
+        }
+    }
+
+    /// Take the sign as valid without checking it.
+    pub(crate) fn assume_signed(self) -> Multisig<Verified> {
+        Multisig {
+            sender: self.sender,
+            receiver: self.receiver,
+            quantity: self.quantity,
+            timeout: self.timeout,
+            timestamp: self.timestamp,
+            sign: self.sign,
+            version: self.version,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Multisig<Verified> {
+    pub fn offer(
+        sender: &SecretAddress,
+        receiver: Address,
+        quantity: Coin,
+        timeout: BlockHeight,
+    ) -> Multisig<Verified> {
+        Self::offer_with_clock(sender, receiver, quantity, timeout, &SystemClock)
+    }
+
+    /// Offer a multisig output timestamped by `clock`.
+    pub fn offer_with_clock(
+        sender: &SecretAddress,
+        receiver: Address,
+        quantity: Coin,
+        timeout: BlockHeight,
+        clock: &dyn Clock,
+    ) -> Multisig<Verified> {
+        let timestamp = clock.now();
+        let version = SighashVersion::CURRENT;
+
+        let sign = {
+            let mut builder = SignatureBuilder::sighash(version, SighashDomain::Multisig);
+            build_multisig_signature_source(
+                &sender.to_public_address(),
+                &receiver,
+                quantity,
+                timeout,
+                timestamp,
+                &mut builder,
+            );
+            let signature_source = builder.finalize_sighash();
+            sender.sign(&signature_source)
+        };
+
+        Multisig {
+            sender: sender.to_public_address(),
+            receiver,
+            quantity,
+            timeout,
+            timestamp,
+            sign,
+            version,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Display for Multisig<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Multisig {} from {} to {}, timeout: {}, timestamp: {}, sign: {}",
+            self.quantity, self.sender, self.receiver, self.timeout, self.timestamp, self.sign
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Multisig<Yet> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Temporary tipe for deserialization
+        #[derive(Deserialize)]
+        struct Inner {
+            sender: Address,
+            receiver: Address,
+            quantity: Coin,
+            timeout: BlockHeight,
+            timestamp: Timestamp,
+            sign: Signature,
+            version: SighashVersion,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+
+        let multisig = Multisig {
+            sender: inner.sender,
+            receiver: inner.receiver,
+            quantity: inner.quantity,
+            timeout: inner.timeout,
+            timestamp: inner.timestamp,
+            sign: inner.sign,
+            version: inner.version,
+            _phantom: PhantomData,
+        };
+        Ok(multisig)
+    }
+}
+
+impl<T> SignatureSource for Multisig<T> {
+    fn write_bytes(&self, builder: &mut SignatureBuilder) {
+        build_multisig_signature_source(
+            &self.sender,
+            &self.receiver,
+            self.quantity,
+            self.timeout,
+            self.timestamp,
+            builder,
+        );
+    }
+}
+
 /// Represents tranfer or generation of coin.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum Transition<T> {
     Transfer(Transfer<T>),
     Generation(Generation<T>),
+    Htlc(Htlc<T>),
+    Multisig(Multisig<T>),
 }
 
 impl<T> Transition<T> {
+    /// Copy without the verification state, as a receiver of this over the network would see it.
+    pub fn to_unverified(&self) -> Transition<Yet> {
+        match self {
+            Transition::Transfer(t) => Transition::Transfer(t.to_unverified()),
+            Transition::Generation(g) => Transition::Generation(g.to_unverified()),
+            Transition::Htlc(h) => Transition::Htlc(h.to_unverified()),
+            Transition::Multisig(m) => Transition::Multisig(m.to_unverified()),
+        }
+    }
+
     pub fn receiver(&self) -> &Address {
         match self {
             Transition::Transfer(t) => t.receiver(),
             Transition::Generation(g) => g.receiver(),
+            Transition::Htlc(h) => h.receiver(),
+            Transition::Multisig(m) => m.receiver(),
         }
     }
 
@@ -295,6 +863,8 @@ impl<T> Transition<T> {
         match self {
             Transition::Transfer(t) => t.quantity(),
             Transition::Generation(g) => g.quantity(),
+            Transition::Htlc(h) => h.quantity(),
+            Transition::Multisig(m) => m.quantity(),
         }
     }
 
@@ -302,6 +872,8 @@ impl<T> Transition<T> {
         match self {
             Transition::Transfer(t) => t.timestamp(),
             Transition::Generation(g) => g.timestamp(),
+            Transition::Htlc(h) => h.timestamp(),
+            Transition::Multisig(m) => m.timestamp(),
         }
     }
 
@@ -309,13 +881,55 @@ impl<T> Transition<T> {
         match self {
             Transition::Transfer(t) => t.sign(),
             Transition::Generation(g) => g.sign(),
+            Transition::Htlc(h) => h.sign(),
+            Transition::Multisig(m) => m.sign(),
+        }
+    }
+
+    /// Condition which a transaction must meet to spend this.
+    pub fn spend_condition(&self) -> SpendCondition {
+        match self {
+            Transition::Transfer(t) => SpendCondition::Key(t.receiver().clone()),
+            Transition::Generation(g) => SpendCondition::Key(g.receiver().clone()),
+            Transition::Htlc(h) => h.spend_condition(),
+            Transition::Multisig(m) => m.spend_condition(),
+        }
+    }
+
+    /// Whether `address` takes part in the spend condition of this.
+    /// HTLC and multisig belong to either party.
+    pub fn is_owned_by(&self, address: &Address) -> bool {
+        self.spend_condition().involves(address)
+    }
+
+    /// Address which offered the coin. Generation has no sender.
+    pub fn sender(&self) -> Option<&Address> {
+        match self {
+            Transition::Transfer(t) => Some(t.sender()),
+            Transition::Generation(_) => None,
+            Transition::Htlc(h) => Some(h.sender()),
+            Transition::Multisig(m) => Some(m.sender()),
         }
     }
 
     pub fn try_as_transfer(&self) -> Option<&Transfer<T>> {
         match self {
             Transition::Transfer(t) => Some(t),
-            Transition::Generation(_) => None,
+            _ => None,
+        }
+    }
+
+    pub fn try_as_htlc(&self) -> Option<&Htlc<T>> {
+        match self {
+            Transition::Htlc(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    pub fn try_as_multisig(&self) -> Option<&Multisig<T>> {
+        match self {
+            Transition::Multisig(m) => Some(m),
+            _ => None,
         }
     }
 }
@@ -325,6 +939,17 @@ impl Transition<Yet> {
         match self {
             Transition::Transfer(t) => t.verify().map(Into::into),
             Transition::Generation(g) => g.verify().map(Into::into),
+            Transition::Htlc(h) => h.verify().map(Into::into),
+            Transition::Multisig(m) => m.verify().map(Into::into),
+        }
+    }
+
+    pub(crate) fn assume_signed(self) -> Transition<Verified> {
+        match self {
+            Transition::Transfer(t) => t.assume_signed().into(),
+            Transition::Generation(g) => g.assume_signed().into(),
+            Transition::Htlc(h) => h.assume_signed().into(),
+            Transition::Multisig(m) => m.assume_signed().into(),
         }
     }
 }
@@ -341,11 +966,25 @@ impl<T> From<Generation<T>> for Transition<T> {
     }
 }
 
+impl<T> From<Htlc<T>> for Transition<T> {
+    fn from(h: Htlc<T>) -> Self {
+        Transition::Htlc(h)
+    }
+}
+
+impl<T> From<Multisig<T>> for Transition<T> {
+    fn from(m: Multisig<T>) -> Self {
+        Transition::Multisig(m)
+    }
+}
+
 impl<T> Display for Transition<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Transition::Transfer(t) => t.fmt(f),
             Transition::Generation(g) => g.fmt(f),
+            Transition::Htlc(h) => h.fmt(f),
+            Transition::Multisig(m) => m.fmt(f),
         }
     }
 }
@@ -360,6 +999,8 @@ impl<'de> Deserialize<'de> for Transition<Yet> {
         pub enum Inner {
             Transfer(Transfer<Yet>),
             Generation(Generation<Yet>),
+            Htlc(Htlc<Yet>),
+            Multisig(Multisig<Yet>),
         }
 
         let inner = Inner::deserialize(deserializer)?;
@@ -367,6 +1008,8 @@ impl<'de> Deserialize<'de> for Transition<Yet> {
         let transition = match inner {
             Inner::Transfer(t) => Transition::Transfer(t),
             Inner::Generation(g) => Transition::Generation(g),
+            Inner::Htlc(h) => Transition::Htlc(h),
+            Inner::Multisig(m) => Transition::Multisig(m),
         };
         Ok(transition)
     }
@@ -375,8 +1018,22 @@ impl<'de> Deserialize<'de> for Transition<Yet> {
 impl<T> SignatureSource for Transition<T> {
     fn write_bytes(&self, builder: &mut SignatureBuilder) {
         match self {
-            Transition::Transfer(t) => t.write_bytes(builder),
-            Transition::Generation(g) => g.write_bytes(builder),
+            Transition::Transfer(t) => {
+                builder.write_variant(0);
+                t.write_bytes(builder)
+            }
+            Transition::Generation(g) => {
+                builder.write_variant(1);
+                g.write_bytes(builder)
+            }
+            Transition::Htlc(h) => {
+                builder.write_variant(2);
+                h.write_bytes(builder)
+            }
+            Transition::Multisig(m) => {
+                builder.write_variant(3);
+                m.write_bytes(builder)
+            }
         }
     }
 }
@@ -393,7 +1050,7 @@ impl Display for TransferError {
 
 impl Error for TransferError {}
 
-fn build_transfer_signature_source(
+pub(crate) fn build_transfer_signature_source(
     sender: &Address,
     receiver: &Address,
     quantity: Coin,
@@ -417,6 +1074,38 @@ fn build_generation_signature_source(
     timestamp.write_bytes(builder);
 }
 
+fn build_htlc_signature_source(
+    sender: &Address,
+    receiver: &Address,
+    quantity: Coin,
+    hash_lock: &BlockDigest,
+    timeout: BlockHeight,
+    timestamp: Timestamp,
+    builder: &mut SignatureBuilder,
+) {
+    sender.write_bytes(builder);
+    receiver.write_bytes(builder);
+    quantity.write_bytes(builder);
+    hash_lock.write_bytes(builder);
+    timeout.write_bytes(builder);
+    timestamp.write_bytes(builder);
+}
+
+fn build_multisig_signature_source(
+    sender: &Address,
+    receiver: &Address,
+    quantity: Coin,
+    timeout: BlockHeight,
+    timestamp: Timestamp,
+    builder: &mut SignatureBuilder,
+) {
+    sender.write_bytes(builder);
+    receiver.write_bytes(builder);
+    quantity.write_bytes(builder);
+    timeout.write_bytes(builder);
+    timestamp.write_bytes(builder);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -450,6 +1139,60 @@ mod tests {
         assert!(verified.is_err());
     }
 
+    #[test]
+    fn test_transfer_sign_verify_version_downgrade() {
+        let sender = SecretAddress::create();
+        let receiver = SecretAddress::create().to_public_address();
+        let quantity = Coin::from(42);
+
+        let mut transfer = Transfer::offer(&sender, receiver, quantity);
+        transfer.version = SighashVersion::Legacy; // Tampering!!!
+
+        let json = serde_json::to_string(&transfer).unwrap();
+        let verified = serde_json::from_str::<Transfer<_>>(&json).unwrap().verify();
+
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn test_transfer_legacy_sign_verify() {
+        let sender = SecretAddress::create();
+        let receiver = SecretAddress::create().to_public_address();
+        let quantity = Coin::from(42);
+        let timestamp = Timestamp::now();
+
+        let sign = {
+            let mut builder = SignatureBuilder::new();
+            let sender_address = sender.to_public_address();
+            build_transfer_signature_source(
+                &sender_address,
+                &receiver,
+                quantity,
+                timestamp,
+                &mut builder,
+            );
+            sender.sign(&builder.finalize())
+        };
+        let transfer = Transfer::<Verified> {
+            sender: sender.to_public_address(),
+            receiver,
+            quantity,
+            timestamp,
+            sign,
+            version: SighashVersion::Legacy,
+            _phantom: PhantomData,
+        };
+
+        // JSON from older versions has no version field
+        let mut json = serde_json::to_value(&transfer).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let verified = serde_json::from_value::<Transfer<_>>(json)
+            .unwrap()
+            .verify();
+
+        assert_eq!(Ok(transfer), verified);
+    }
+
     #[test]
     fn test_generation_sign_verify() {
         let receiver = SecretAddress::create();
@@ -0,0 +1,704 @@
+//! UTXO set on disk, from which UTXO queries are answered and which survives restarts.
+//! The ledger still holds its own UTXO sets in memory to verify blocks, so this does not
+//! bound the memory of a node.
+//!
+//! UTXOs are keyed by their outpoints. Inputs carry the output they spend rather than its
+//! outpoint, so the ledger resolves them into `BlockChanges`, as it does for compact blocks.
+//! The changes are applied afterwards, without holding the ledger during disk I/O.
+//! Keys and file offsets are kept in memory. Outputs stay on disk, except changes which
+//! wait in the dirty cache until they are written in one batch.
+//!
+//! The file is a log of records. A batch ends with a commit record, and a batch cut off
+//! by a crash is discarded on open, which leaves the set at the tip of the last batch.
+
+use crate::compact::OutPoint;
+use crate::digest::BlockDigest;
+use crate::ledger::Ledger;
+use crate::transition::Transition;
+use crate::{Address, BlockHeight, VerifiedBlock, Yet};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const FILE_NAME: &str = "utxos.db";
+
+/// Superseded records are rewritten away once they outnumber both this and the live ones.
+const COMPACTION_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtxoDbConfig {
+    /// Changed UTXOs held in memory, beyond which they are written at once
+    pub cache_size: usize,
+    /// Blocks connected or disconnected between writes
+    pub flush_blocks: u64,
+    /// Time between writes, checked by `flush_if_due`
+    pub flush_interval: Duration,
+}
+
+impl Default for UtxoDbConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: 100_000,
+            flush_blocks: 100,
+            flush_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    Put(OutPoint, Box<Transition<Yet>>),
+    Delete(OutPoint),
+    Tip(BlockHeight, BlockDigest),
+    Commit,
+}
+
+/// UTXO set after one block, stored in a directory.
+#[derive(Debug)]
+pub struct UtxoDb {
+    path: PathBuf,
+    file: File,
+    /// Length of the file up to the last commit
+    len: u64,
+    config: UtxoDbConfig,
+    /// Offset of the record of each written UTXO
+    index: HashMap<OutPoint, u64>,
+    /// UTXOs created (`Some`) or spent (`None`) since the last write
+    dirty: HashMap<OutPoint, Option<Transition<Yet>>>,
+    tip: Option<(BlockHeight, BlockDigest)>,
+    written_tip: Option<(BlockHeight, BlockDigest)>,
+    /// Records in the file, of which those not in `index` are superseded
+    records: usize,
+    blocks_since_flush: u64,
+    last_flush: Instant,
+}
+
+impl UtxoDb {
+    /// Open the UTXO set in `dir`, creating an empty one if there is none.
+    pub fn open(dir: impl AsRef<Path>, config: UtxoDbConfig) -> Result<Self, UtxoDbError> {
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(FILE_NAME);
+        let file = open_file(&path)?;
+
+        let mut index = HashMap::new();
+        let mut tip = None;
+        let mut records = 0;
+        let mut len = 0;
+        let mut batch = vec![];
+        let mut offset = 0;
+        let mut reader = BufReader::new(&file);
+        while let Some((record, size)) = read_record(&mut reader, offset)? {
+            match record {
+                Record::Commit => {
+                    for (offset, record) in batch.drain(..) {
+                        match record {
+                            Record::Put(key, _) => {
+                                index.insert(key, offset);
+                            }
+                            Record::Delete(key) => {
+                                index.remove(&key);
+                            }
+                            Record::Tip(height, digest) => tip = Some((height, digest)),
+                            Record::Commit => {}
+                        }
+                        records += 1;
+                    }
+                    records += 1;
+                    len = offset + size;
+                }
+                record => batch.push((offset, record)),
+            }
+            offset += size;
+        }
+        // Drop a batch which a crash cut off
+        file.set_len(len)?;
+
+        Ok(Self {
+            path,
+            file,
+            len,
+            config,
+            index,
+            dirty: HashMap::new(),
+            written_tip: tip.clone(),
+            tip,
+            records,
+            blocks_since_flush: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Block after which this is the UTXO set, or `None` before genesis.
+    pub fn tip(&self) -> Option<&(BlockHeight, BlockDigest)> {
+        self.tip.as_ref()
+    }
+
+    pub fn config(&self) -> &UtxoDbConfig {
+        &self.config
+    }
+
+    /// Number of UTXOs.
+    pub fn len(&self) -> usize {
+        let written = self
+            .index
+            .keys()
+            .filter(|key| !self.dirty.contains_key(*key))
+            .count();
+        written + self.dirty.values().filter(|utxo| utxo.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of changed UTXOs waiting to be written.
+    pub fn dirty_len(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Whether the output at `outpoint` is unspent.
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        match self.dirty.get(outpoint) {
+            Some(utxo) => utxo.is_some(),
+            None => self.index.contains_key(outpoint),
+        }
+    }
+
+    /// All UTXOs, read from disk unless they are in the dirty cache.
+    pub fn utxos(&self) -> impl Iterator<Item = Result<Transition<Yet>, UtxoDbError>> + '_ {
+        let written = self
+            .index
+            .iter()
+            .filter(|(key, _)| !self.dirty.contains_key(*key))
+            .map(|(_, &offset)| self.read_utxo(offset));
+        let dirty = self.dirty.values().flatten().cloned().map(Ok);
+        written.chain(dirty)
+    }
+
+    /// UTXOs received by `holder`.
+    pub fn utxos_of(&self, holder: &Address) -> Result<Vec<Transition<Yet>>, UtxoDbError> {
+        let mut utxos = vec![];
+        for utxo in self.utxos() {
+            let utxo = utxo?;
+            if utxo.receiver() == holder {
+                utxos.push(utxo);
+            }
+        }
+        Ok(utxos)
+    }
+
+    /// Apply `block`, which must follow the tip.
+    /// `ledger` must contain the block, and resolves the outpoints it spends.
+    pub fn connect_block(
+        &mut self,
+        block: &VerifiedBlock,
+        ledger: &Ledger,
+    ) -> Result<(), UtxoDbError> {
+        self.connect(BlockChanges::new(block, ledger)?)
+    }
+
+    /// Revert `block`, which must be the tip.
+    /// `ledger` must contain the block, and resolves the outpoints it spends.
+    pub fn disconnect_block(
+        &mut self,
+        block: &VerifiedBlock,
+        ledger: &Ledger,
+    ) -> Result<(), UtxoDbError> {
+        self.disconnect(BlockChanges::new(block, ledger)?)
+    }
+
+    /// Apply changes of a block which must follow the tip.
+    pub fn connect(&mut self, changes: BlockChanges) -> Result<(), UtxoDbError> {
+        let follows = match &self.tip {
+            Some((_, digest)) => &changes.previous_digest == digest,
+            None => changes.height.is_genesis(),
+        };
+        if !follows {
+            return Err(UtxoDbError::NotOnTip);
+        }
+
+        for (outpoint, _) in changes.spent {
+            self.dirty.insert(outpoint, None);
+        }
+        for (outpoint, output) in changes.created {
+            self.dirty.insert(outpoint, Some(output));
+        }
+        self.tip = Some((changes.height, changes.digest));
+        self.block_done()
+    }
+
+    /// Revert changes of a block which must be the tip.
+    pub fn disconnect(&mut self, changes: BlockChanges) -> Result<(), UtxoDbError> {
+        if self.tip.as_ref().map(|(_, digest)| digest) != Some(&changes.digest) {
+            return Err(UtxoDbError::NotOnTip);
+        }
+
+        for (outpoint, _) in changes.created {
+            self.dirty.insert(outpoint, None);
+        }
+        for (outpoint, output) in changes.spent {
+            self.dirty.insert(outpoint, Some(output));
+        }
+        self.tip = changes
+            .height
+            .previous()
+            .map(|height| (height, changes.previous_digest));
+        self.block_done()
+    }
+
+    /// Empty the set, to build it again from genesis.
+    pub fn clear(&mut self) -> Result<(), UtxoDbError> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        self.len = 0;
+        self.index.clear();
+        self.dirty.clear();
+        self.tip = None;
+        self.written_tip = None;
+        self.records = 0;
+        self.blocks_since_flush = 0;
+        Ok(())
+    }
+
+    /// Write changes if `flush_interval` has passed since the last write.
+    pub fn flush_if_due(&mut self) -> Result<(), UtxoDbError> {
+        if self.last_flush.elapsed() >= self.config.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write changes in one batch, which survives a crash as a whole or not at all.
+    pub fn flush(&mut self) -> Result<(), UtxoDbError> {
+        self.last_flush = Instant::now();
+        self.blocks_since_flush = 0;
+        if self.dirty.is_empty() && self.tip == self.written_tip {
+            return Ok(());
+        }
+
+        let mut batch = vec![];
+        let mut offsets = vec![];
+        for (key, utxo) in self.dirty.iter() {
+            let record = match utxo {
+                Some(utxo) => Record::Put(key.clone(), Box::new(utxo.clone())),
+                // Created and spent before being written
+                None if !self.index.contains_key(key) => continue,
+                None => Record::Delete(key.clone()),
+            };
+            offsets.push((key, self.len + batch.len() as u64));
+            write_record(&mut batch, &record)?;
+        }
+        let mut records = offsets.len() + 1;
+        if let Some((height, digest)) = &self.tip {
+            write_record(&mut batch, &Record::Tip(*height, digest.clone()))?;
+            records += 1;
+        }
+        write_record(&mut batch, &Record::Commit)?;
+        let res = self
+            .file
+            .write_all(&batch)
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = res {
+            // Drop the part of the batch written, so that the next one starts at the last
+            // commit as its offsets do. Appends go to the end of the file wherever it is sought.
+            self.file.set_len(self.len)?;
+            self.file.seek(SeekFrom::Start(self.len))?;
+            return Err(e.into());
+        }
+
+        self.len += batch.len() as u64;
+        self.records += records;
+        for (key, offset) in offsets {
+            match self.dirty[key] {
+                Some(_) => self.index.insert(key.clone(), offset),
+                None => self.index.remove(key),
+            };
+        }
+        self.dirty.clear();
+        self.written_tip = self.tip.clone();
+
+        if self.records - self.index.len() > COMPACTION_THRESHOLD.max(self.index.len()) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the file with only the UTXOs, dropping superseded records.
+    fn compact(&mut self) -> Result<(), UtxoDbError> {
+        let temp_path = self.path.with_extension("compact");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        let mut index = HashMap::new();
+        let mut len = 0;
+        for (key, &offset) in self.index.iter() {
+            let record = Record::Put(key.clone(), Box::new(self.read_utxo(offset)?));
+            index.insert(key.clone(), len);
+            len += write_record(&mut writer, &record)?;
+        }
+        if let Some((height, digest)) = &self.written_tip {
+            len += write_record(&mut writer, &Record::Tip(*height, digest.clone()))?;
+        }
+        len += write_record(&mut writer, &Record::Commit)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+
+        std::fs::rename(&temp_path, &self.path)?;
+        self.file = open_file(&self.path)?;
+        self.records = index.len() + usize::from(self.written_tip.is_some()) + 1;
+        self.index = index;
+        self.len = len;
+        Ok(())
+    }
+
+    fn block_done(&mut self) -> Result<(), UtxoDbError> {
+        self.blocks_since_flush += 1;
+        if self.blocks_since_flush >= self.config.flush_blocks
+            || self.dirty.len() >= self.config.cache_size
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn read_utxo(&self, offset: u64) -> Result<Transition<Yet>, UtxoDbError> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        match read_record(&mut file, offset)? {
+            Some((Record::Put(_, utxo), _)) => Ok(*utxo),
+            _ => Err(UtxoDbError::Corrupted(offset)),
+        }
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+}
+
+type Changes = Vec<(OutPoint, Transition<Yet>)>;
+
+/// Outputs which a block spends and creates with their outpoints,
+/// leaving out those both created and spent within the block.
+#[derive(Debug, Clone)]
+pub struct BlockChanges {
+    height: BlockHeight,
+    digest: BlockDigest,
+    previous_digest: BlockDigest,
+    spent: Changes,
+    created: Changes,
+}
+
+impl BlockChanges {
+    /// `ledger` must contain `block`, and resolves the outpoints it spends.
+    pub fn new(block: &VerifiedBlock, ledger: &Ledger) -> Result<Self, UtxoDbError> {
+        let compact = ledger
+            .compact_block(block)
+            .ok_or(UtxoDbError::UnknownOutputs)?;
+        let mut spent = compact
+            .spent_outpoints()
+            .cloned()
+            .zip(block.inputs().map(Transition::to_unverified))
+            .collect::<Vec<_>>();
+        let mut created = block
+            .created_outputs()
+            .map(|(outpoint, output)| (outpoint, output.to_unverified()))
+            .collect::<Vec<_>>();
+
+        let spent_outpoints = spent.iter().map(|(o, _)| o.clone()).collect::<HashSet<_>>();
+        let created_outpoints = created
+            .iter()
+            .map(|(o, _)| o.clone())
+            .collect::<HashSet<_>>();
+        spent.retain(|(outpoint, _)| !created_outpoints.contains(outpoint));
+        created.retain(|(outpoint, _)| !spent_outpoints.contains(outpoint));
+        Ok(Self {
+            height: block.height(),
+            digest: block.digest().clone(),
+            previous_digest: block.previous_digest().clone(),
+            spent,
+            created,
+        })
+    }
+}
+
+/// Write a record prefixed by its length, returning the number of bytes written.
+fn write_record(writer: &mut impl Write, record: &Record) -> Result<u64, UtxoDbError> {
+    let bytes = bincode::serialize(record)?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    Ok(4 + bytes.len() as u64)
+}
+
+/// Read a record and its size in bytes, or `None` at the end or at a record cut off midway.
+/// `offset` is where the record starts, reported if it is complete but cannot be decoded.
+fn read_record(reader: &mut impl Read, offset: u64) -> Result<Option<(Record, u64)>, UtxoDbError> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u64::from(u32::from_le_bytes(len));
+    // Read as far as the bytes exist, so that a garbage length does not allocate much
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Ok(None);
+    }
+    match bincode::deserialize(&bytes) {
+        Ok(record) => Ok(Some((record, 4 + len))),
+        Err(_) => Err(UtxoDbError::Corrupted(offset)),
+    }
+}
+
+#[derive(Debug)]
+pub enum UtxoDbError {
+    Io(io::Error),
+    Encode(bincode::Error),
+    /// Record at the offset is not a UTXO, or cannot be decoded.
+    Corrupted(u64),
+    /// Block spends outputs which the ledger does not have.
+    UnknownOutputs,
+    /// Block does not follow, or is not, the tip of the UTXO set.
+    NotOnTip,
+}
+
+impl From<io::Error> for UtxoDbError {
+    fn from(e: io::Error) -> Self {
+        UtxoDbError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for UtxoDbError {
+    fn from(e: bincode::Error) -> Self {
+        UtxoDbError::Encode(e)
+    }
+}
+
+impl Display for UtxoDbError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UtxoDbError::Io(e) => write!(f, "UTXO database I/O failed: {}", e),
+            UtxoDbError::Encode(e) => write!(f, "Cannot encode UTXO record: {}", e),
+            UtxoDbError::Corrupted(offset) => {
+                write!(f, "UTXO database is corrupted at offset {}", offset)
+            }
+            UtxoDbError::NotOnTip => write!(f, "Block is not on the tip of the UTXO database"),
+            UtxoDbError::UnknownOutputs => write!(f, "Block spends outputs not in the ledger"),
+        }
+    }
+}
+
+impl Error for UtxoDbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UtxoDbError::Io(e) => Some(e),
+            UtxoDbError::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Ledger;
+    use crate::test_utils::{mine, reward};
+    use crate::{SecretAddress, Transaction, Transfer};
+    use itertools::Itertools;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("utxo_db_{}_{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    /// Genesis, a payment from alice to bob, then an empty block.
+    fn chain() -> (Ledger, Vec<BlockDigest>) {
+        let (alice, bob) = (SecretAddress::create(), SecretAddress::create());
+        let mut ledger = Ledger::new();
+        let genesis = mine(&mut ledger, vec![], &alice).unwrap();
+        let reward = reward(ledger.get(&genesis).unwrap());
+        let output = Transfer::offer(&alice, bob.to_public_address(), reward.quantity());
+        let tx = Transaction::offer(&alice, vec![reward], vec![output])
+            .verify_transaction()
+            .unwrap();
+        let paid = mine(&mut ledger, vec![tx], &alice).unwrap();
+        let tip = mine(&mut ledger, vec![], &bob).unwrap();
+        (ledger, vec![genesis, paid, tip])
+    }
+
+    fn assert_same_set(db: &UtxoDb, ledger: &Ledger, digest: &BlockDigest) {
+        let snapshot = ledger.utxo_snapshot(digest);
+        let expected = snapshot.iter().map(Transition::to_unverified).collect_vec();
+        let actual = db.utxos().map(Result::unwrap).collect_vec();
+        assert_eq!(snapshot.len(), db.len());
+        assert_eq!(expected.len(), actual.len());
+        assert!(expected.iter().all(|utxo| actual.contains(utxo)));
+
+        // Every UTXO is found by its outpoint
+        let outpoints = ledger
+            .downstream_chain_to(digest)
+            .flat_map(|block| block.created_outputs())
+            .filter(|(_, output)| snapshot.contains(output))
+            .map(|(outpoint, _)| outpoint)
+            .collect::<HashSet<_>>();
+        assert_eq!(snapshot.len(), outpoints.len());
+        assert!(outpoints.iter().all(|outpoint| db.contains(outpoint)));
+    }
+
+    #[test]
+    fn test_batched_flush() {
+        let dir = temp_dir("batched_flush");
+        let (ledger, digests) = chain();
+        let config = UtxoDbConfig {
+            flush_blocks: 2,
+            ..UtxoDbConfig::default()
+        };
+        let mut db = UtxoDb::open(&dir, config).unwrap();
+        for digest in digests.iter() {
+            db.connect_block(ledger.get(digest).unwrap(), &ledger)
+                .unwrap();
+            assert_same_set(&db, &ledger, digest);
+        }
+        assert_eq!(Some(&digests[2]), db.tip().map(|(_, digest)| digest));
+        // Last block waits in the cache
+        assert!(db.dirty_len() > 0);
+
+        // Unwritten block is lost, but the set stays consistent with its tip
+        drop(db);
+        let db = UtxoDb::open(&dir, config).unwrap();
+        assert_eq!(Some(&digests[1]), db.tip().map(|(_, digest)| digest));
+        assert_same_set(&db, &ledger, &digests[1]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let dir = temp_dir("disconnect");
+        let (ledger, digests) = chain();
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        for digest in digests.iter() {
+            db.connect_block(ledger.get(digest).unwrap(), &ledger)
+                .unwrap();
+        }
+        db.flush().unwrap();
+
+        let tip = ledger.get(&digests[2]).unwrap();
+        assert!(matches!(
+            db.connect_block(tip, &ledger),
+            Err(UtxoDbError::NotOnTip)
+        ));
+        db.disconnect_block(tip, &ledger).unwrap();
+        db.disconnect_block(ledger.get(&digests[1]).unwrap(), &ledger)
+            .unwrap();
+        assert_same_set(&db, &ledger, &digests[0]);
+
+        db.flush().unwrap();
+        let db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        assert_same_set(&db, &ledger, &digests[0]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = temp_dir("clear");
+        let (ledger, digests) = chain();
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        for digest in digests.iter() {
+            db.connect_block(ledger.get(digest).unwrap(), &ledger)
+                .unwrap();
+        }
+        db.flush().unwrap();
+
+        db.clear().unwrap();
+        assert_eq!(None, db.tip());
+        assert!(db.is_empty());
+        let genesis = ledger.get(&digests[0]).unwrap();
+        db.connect(BlockChanges::new(genesis, &ledger).unwrap())
+            .unwrap();
+        db.flush().unwrap();
+
+        let db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        assert_same_set(&db, &ledger, &digests[0]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cut_off_batch() {
+        let dir = temp_dir("cut_off_batch");
+        let (ledger, digests) = chain();
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        db.connect_block(ledger.get(&digests[0]).unwrap(), &ledger)
+            .unwrap();
+        db.flush().unwrap();
+        let len = db.len;
+
+        // Batch whose commit record never made it to disk
+        db.connect_block(ledger.get(&digests[1]).unwrap(), &ledger)
+            .unwrap();
+        db.flush().unwrap();
+        db.file.set_len(db.len - 1).unwrap();
+        drop(db);
+
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        assert_eq!(len, db.len);
+        assert_same_set(&db, &ledger, &digests[0]);
+        db.connect_block(ledger.get(&digests[1]).unwrap(), &ledger)
+            .unwrap();
+        assert_same_set(&db, &ledger, &digests[1]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_undecodable_record() {
+        let dir = temp_dir("undecodable");
+        let (ledger, digests) = chain();
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        db.connect_block(ledger.get(&digests[0]).unwrap(), &ledger)
+            .unwrap();
+        db.flush().unwrap();
+        let len = db.len;
+
+        // A complete record which is not a record of this database
+        db.file.write_all(&4u32.to_le_bytes()).unwrap();
+        db.file.write_all(&[0xff; 4]).unwrap();
+        drop(db);
+        assert!(matches!(
+            UtxoDb::open(&dir, UtxoDbConfig::default()),
+            Err(UtxoDbError::Corrupted(offset)) if offset == len
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = temp_dir("compact");
+        let (ledger, digests) = chain();
+        let mut db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        for digest in digests.iter() {
+            db.connect_block(ledger.get(digest).unwrap(), &ledger)
+                .unwrap();
+            db.flush().unwrap();
+        }
+        let len = db.len;
+
+        db.compact().unwrap();
+        assert!(db.len < len);
+        assert_same_set(&db, &ledger, &digests[2]);
+        drop(db);
+        let db = UtxoDb::open(&dir, UtxoDbConfig::default()).unwrap();
+        assert_eq!(Some(&digests[2]), db.tip().map(|(_, digest)| digest));
+        assert_same_set(&db, &ledger, &digests[2]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
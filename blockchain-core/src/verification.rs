@@ -7,3 +7,16 @@ pub struct Yet;
 /// A marker type that represents something has not passed verification process yet.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Verified;
+
+/// Runtime view of the markers, for code which erases them.
+pub trait Stage: 'static {
+    const VERIFIED: bool;
+}
+
+impl Stage for Yet {
+    const VERIFIED: bool = false;
+}
+
+impl Stage for Verified {
+    const VERIFIED: bool = true;
+}
@@ -0,0 +1,27 @@
+use blockchain_net::async_net::{Publisher, Subscriber};
+use blockchain_net::impl_zeromq::MuxConnection;
+use blockchain_net::topic::{PubsubExample, RequestUtxoByAddress};
+use std::time::Duration;
+
+/// Publish and subscribe two topics over one multiplexed connection.
+/// Requires a running proxy.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Connecting...");
+    let connection = MuxConnection::connect().await?;
+    let mut publisher = connection.publisher::<PubsubExample>();
+    let mut subscriber = connection.subscriber::<PubsubExample>();
+    // Messages of other topics never reach this subscriber
+    let _other = connection.subscriber::<RequestUtxoByAddress>();
+    println!("Done");
+
+    // Wait for the subscription to reach the proxy
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    for i in 0..10 {
+        publisher.publish(&i).await?;
+        println!("Received: {}", subscriber.recv().await?);
+    }
+
+    Ok(())
+}
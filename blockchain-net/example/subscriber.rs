@@ -1,8 +1,9 @@
+use blockchain_net::address_book::AddressBook;
 use blockchain_net::blocking::{Backend, Endpoint, HeartbeatConfig, Subscriber};
 use blockchain_net::topic::PubsubExample;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 fn main() {
     let local_addr = std::env::args()
@@ -17,16 +18,37 @@ fn main() {
     let entrance_addr = SocketAddr::from_str(&entrance_addr).expect("Address:port parse error");
     let entrance_endpoint = Endpoint::from(entrance_addr);
 
+    // Peers learned in earlier runs, used if the entrance is gone
+    let address_book_path = std::env::args().nth(3);
+    let address_book = address_book_path
+        .as_ref()
+        .and_then(|path| AddressBook::load(path).ok())
+        .unwrap_or_default();
+
     let heartbeat_config = HeartbeatConfig::default_config();
 
-    let backend = Backend::bind(entrance_endpoint, local_endpoint, heartbeat_config).unwrap();
+    let backend = Backend::bind_with_address_book(
+        entrance_endpoint,
+        local_endpoint,
+        heartbeat_config,
+        address_book,
+    )
+    .unwrap();
 
     let subscriber = Subscriber::<PubsubExample>::new(&backend);
 
+    let mut last_save = Instant::now();
     loop {
         while let Ok(topic) = subscriber.try_recv() {
             println!("Subscribed: {}", topic);
         }
+        if let Some(path) = address_book_path
+            .as_ref()
+            .filter(|_| last_save.elapsed().as_secs() >= 10)
+        {
+            backend.address_book().save(path).ok();
+            last_save = Instant::now();
+        }
         std::thread::sleep(Duration::from_millis(100));
     }
 }
@@ -0,0 +1,277 @@
+use crate::blocking::{Endpoint, NetError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// Addresses kept at most, forgetting the least recently seen ones beyond.
+pub const MAX_ADDRESSES: usize = 1000;
+
+/// Addresses not seen for this long are neither shared nor connected to.
+pub const ADDRESS_HORIZON: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Address of a peer and when it was last known to be alive, in seconds since the UNIX epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAddress {
+    pub endpoint: Endpoint,
+    pub last_seen: u64,
+}
+
+/// Peer addresses learned from heartbeats and from other peers,
+/// so that a node finds the network again without the entrance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressBook {
+    last_seen: HashMap<Endpoint, u64>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+
+    pub fn last_seen(&self, endpoint: &Endpoint) -> Option<u64> {
+        self.last_seen.get(endpoint).copied()
+    }
+
+    /// Record that `endpoint` was alive at `at`, unless it is known to be alive later.
+    pub fn seen(&mut self, endpoint: Endpoint, at: u64) {
+        let last_seen = self.last_seen.entry(endpoint).or_insert(at);
+        *last_seen = (*last_seen).max(at);
+        self.evict();
+    }
+
+    /// Add addresses shared by a peer.
+    /// Their times are not trusted beyond `now`, so that a peer cannot pin an address forever.
+    pub fn merge(&mut self, addresses: impl IntoIterator<Item = PeerAddress>, now: u64) {
+        for address in addresses {
+            if !Self::is_stale(address.last_seen, now) {
+                self.seen(address.endpoint, address.last_seen.min(now));
+            }
+        }
+    }
+
+    /// At most `count` addresses seen within the horizon, the most recently seen first.
+    pub fn sample(&self, count: usize, now: u64) -> Vec<PeerAddress> {
+        let mut addresses = self
+            .addresses()
+            .filter(|address| !Self::is_stale(address.last_seen, now))
+            .collect::<Vec<_>>();
+        addresses.sort_by_key(|address| std::cmp::Reverse(address.last_seen));
+        addresses.truncate(count);
+        addresses
+    }
+
+    /// At most `count` addresses within the horizon to connect to, except `skip` and `used`.
+    /// The most recently seen come first, but one per address group before a second of any group,
+    /// counting groups of `used` too, so that a single network cannot easily take all connections.
+    pub fn select_outbound(
+        &self,
+        count: usize,
+        now: u64,
+        used: &[Endpoint],
+        skip: impl Fn(&Endpoint) -> bool,
+    ) -> Vec<Endpoint> {
+        let mut candidates = self
+            .sample(usize::MAX, now)
+            .into_iter()
+            .map(|address| address.endpoint)
+            .filter(|endpoint| !skip(endpoint) && !used.contains(endpoint))
+            .collect::<Vec<_>>();
+        let mut groups = used.iter().map(address_group).collect::<HashSet<_>>();
+
+        let mut selected = vec![];
+        candidates.retain(|endpoint| {
+            let diverse = selected.len() < count && groups.insert(address_group(endpoint));
+            if diverse {
+                selected.push(*endpoint);
+            }
+            !diverse
+        });
+        let rest = count - selected.len();
+        selected.extend(candidates.into_iter().take(rest));
+        selected
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = PeerAddress> + '_ {
+        self.last_seen
+            .iter()
+            .map(|(&endpoint, &last_seen)| PeerAddress {
+                endpoint,
+                last_seen,
+            })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NetError> {
+        let bytes = std::fs::read(path)?;
+        let addresses = serde_json::from_slice::<Vec<PeerAddress>>(&bytes)?;
+        let mut book = Self::new();
+        for address in addresses {
+            book.seen(address.endpoint, address.last_seen);
+        }
+        Ok(book)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NetError> {
+        let bytes = serde_json::to_vec_pretty(&self.addresses().collect::<Vec<_>>())?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn is_stale(last_seen: u64, now: u64) -> bool {
+        now.saturating_sub(last_seen) > ADDRESS_HORIZON.as_secs()
+    }
+
+    fn evict(&mut self) {
+        while self.last_seen.len() > MAX_ADDRESSES {
+            let oldest = self
+                .last_seen
+                .iter()
+                .min_by_key(|(_, &last_seen)| last_seen)
+                .map(|(&endpoint, _)| endpoint);
+            if let Some(oldest) = oldest {
+                self.last_seen.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Network which an address belongs to: the /16 prefix of IPv4, or the /32 prefix of IPv6.
+/// An IPv4-mapped IPv6 address is in the group of its IPv4 address.
+pub fn address_group(endpoint: &Endpoint) -> Vec<u8> {
+    match endpoint.as_ref().ip().to_canonical() {
+        IpAddr::V4(ip) => [&[4], &ip.octets()[..2]].concat(),
+        IpAddr::V6(ip) => [&[6], &ip.octets()[..4]].concat(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    fn endpoint(port: u16) -> Endpoint {
+        SocketAddr::from_str(&format!("127.0.0.1:{}", port))
+            .unwrap()
+            .into()
+    }
+
+    fn parse(s: &str) -> Endpoint {
+        SocketAddr::from_str(s).unwrap().into()
+    }
+
+    #[test]
+    fn test_address_group() {
+        let group = |s| address_group(&parse(s));
+        assert_eq!(group("192.0.2.1:1"), group("192.0.3.1:2"));
+        assert_ne!(group("192.0.2.1:1"), group("192.1.2.1:1"));
+        assert_eq!(group("192.0.2.1:1"), group("[::ffff:192.0.9.9]:1"));
+        assert_eq!(group("[2001:db8::1]:1"), group("[2001:db8:ffff::1]:1"));
+        assert_ne!(group("[2001:db8::1]:1"), group("[2001:db9::1]:1"));
+    }
+
+    #[test]
+    fn test_select_outbound_prefers_diverse_groups() {
+        let now = ADDRESS_HORIZON.as_secs();
+        let mut book = AddressBook::new();
+        // Many recent addresses of one network, and a few older ones elsewhere
+        for port in 0..10 {
+            book.seen(parse(&format!("10.0.0.{}:1", port)), now);
+        }
+        book.seen(parse("10.1.0.1:1"), now - 1);
+        book.seen(parse("10.2.0.1:1"), now - 2);
+        book.seen(parse("10.3.0.1:1"), now - 3);
+
+        let used = [parse("10.3.0.2:1")];
+        let selected = book.select_outbound(3, now, &used, |e| *e == parse("10.0.0.0:1"));
+        assert_eq!(3, selected.len());
+        let groups = selected.iter().map(address_group).collect::<HashSet<_>>();
+        assert_eq!(3, groups.len());
+        assert!(!groups.contains(&address_group(&used[0])));
+
+        // Falls back to the same groups when there are no others
+        let selected = book.select_outbound(6, now, &used, |_| false);
+        assert_eq!(6, selected.len());
+        assert!(!selected.contains(&used[0]));
+    }
+
+    #[test]
+    fn test_seen_keeps_latest() {
+        let mut book = AddressBook::new();
+        book.seen(endpoint(1), 100);
+        book.seen(endpoint(1), 50);
+        assert_eq!(Some(100), book.last_seen(&endpoint(1)));
+        book.seen(endpoint(1), 200);
+        assert_eq!(Some(200), book.last_seen(&endpoint(1)));
+    }
+
+    #[test]
+    fn test_merge_distrusts_future_and_stale() {
+        let now = ADDRESS_HORIZON.as_secs() * 2;
+        let mut book = AddressBook::new();
+        book.merge(
+            [
+                PeerAddress {
+                    endpoint: endpoint(1),
+                    last_seen: now + 1000,
+                },
+                PeerAddress {
+                    endpoint: endpoint(2),
+                    last_seen: 0,
+                },
+            ],
+            now,
+        );
+        assert_eq!(Some(now), book.last_seen(&endpoint(1)));
+        assert_eq!(None, book.last_seen(&endpoint(2)));
+    }
+
+    #[test]
+    fn test_sample_prefers_recent() {
+        let now = ADDRESS_HORIZON.as_secs() * 2;
+        let mut book = AddressBook::new();
+        book.seen(endpoint(1), now - 10);
+        book.seen(endpoint(2), now);
+        book.seen(endpoint(3), now - 5);
+        // Known, but too old to share
+        book.seen(endpoint(4), 0);
+
+        let sample = book.sample(10, now);
+        let endpoints = sample.iter().map(|a| a.endpoint).collect::<Vec<_>>();
+        assert_eq!(vec![endpoint(2), endpoint(3), endpoint(1)], endpoints);
+        assert_eq!(2, book.sample(2, now).len());
+    }
+
+    #[test]
+    fn test_evict_least_recent() {
+        let mut book = AddressBook::new();
+        for port in 0..=MAX_ADDRESSES as u16 {
+            book.seen(endpoint(port), 1000 + port as u64);
+        }
+        assert_eq!(MAX_ADDRESSES, book.len());
+        assert_eq!(None, book.last_seen(&endpoint(0)));
+        assert!(book.last_seen(&endpoint(1)).is_some());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut book = AddressBook::new();
+        book.seen(endpoint(1), 100);
+        book.seen(endpoint(2), 200);
+
+        let path = std::env::temp_dir().join(format!("address-book-{}.json", std::process::id()));
+        book.save(&path).unwrap();
+        let loaded = AddressBook::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(book, loaded);
+    }
+}
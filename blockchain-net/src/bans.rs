@@ -0,0 +1,246 @@
+use crate::identity::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Node whose messages are ignored, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub node: NodeId,
+    pub reason: String,
+    /// End of the ban in seconds since the UNIX epoch, or never if `None`
+    pub until: Option<u64>,
+}
+
+impl Ban {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
+
+/// Banned nodes, which can be saved to survive restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BanList {
+    bans: HashMap<NodeId, Ban>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ban `ban.node`, replacing its earlier ban.
+    pub fn ban(&mut self, ban: Ban) {
+        self.bans.insert(ban.node.clone(), ban);
+    }
+
+    pub fn unban(&mut self, node: &NodeId) -> Option<Ban> {
+        self.bans.remove(node)
+    }
+
+    pub fn is_banned(&self, node: &NodeId, now: u64) -> bool {
+        self.bans.get(node).is_some_and(|ban| !ban.is_expired(now))
+    }
+
+    /// Bans in effect at `now`.
+    pub fn bans(&self, now: u64) -> Vec<Ban> {
+        self.bans
+            .values()
+            .filter(|ban| !ban.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget expired bans, returning them.
+    pub fn remove_expired(&mut self, now: u64) -> Vec<Ban> {
+        let expired = self
+            .bans
+            .values()
+            .filter(|ban| ban.is_expired(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        for ban in expired.iter() {
+            self.bans.remove(&ban.node);
+        }
+        expired
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BanListError> {
+        let json = std::fs::read(path)?;
+        let bans = serde_json::from_slice::<Vec<Ban>>(&json)?;
+        let mut list = Self::new();
+        for ban in bans {
+            list.ban(ban);
+        }
+        Ok(list)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BanListError> {
+        let bans = self.bans.values().collect::<Vec<_>>();
+        let json = serde_json::to_vec_pretty(&bans)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Misbehavior scores of nodes, halving every `half_life`, so that rare faults are forgiven
+/// while repeated ones add up.
+#[derive(Debug, Clone)]
+pub struct MisbehaviorScores {
+    half_life: Duration,
+    /// Score and when it was last decayed
+    scores: HashMap<NodeId, (f64, Instant)>,
+}
+
+impl MisbehaviorScores {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Add `points` to the score of `node`, returning the new score.
+    pub fn add(&mut self, node: &NodeId, points: u32, now: Instant) -> f64 {
+        let score = self.score(node, now) + f64::from(points);
+        self.scores.insert(node.clone(), (score, now));
+        score
+    }
+
+    pub fn score(&self, node: &NodeId, now: Instant) -> f64 {
+        match self.scores.get(node) {
+            Some(&(score, at)) => {
+                let half_lives =
+                    now.saturating_duration_since(at).as_secs_f64() / self.half_life.as_secs_f64();
+                score * 0.5_f64.powf(half_lives)
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn forget(&mut self, node: &NodeId) {
+        self.scores.remove(node);
+    }
+
+    /// Forget scores which have decayed below `min`.
+    pub fn remove_below(&mut self, min: f64, now: Instant) {
+        let forgotten = self
+            .scores
+            .keys()
+            .filter(|node| self.score(node, now) < min)
+            .cloned()
+            .collect::<Vec<_>>();
+        for node in forgotten {
+            self.scores.remove(&node);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BanListError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<std::io::Error> for BanListError {
+    fn from(e: std::io::Error) -> Self {
+        BanListError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for BanListError {
+    fn from(e: serde_json::Error) -> Self {
+        BanListError::Json(e)
+    }
+}
+
+impl Display for BanListError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BanListError::Io(e) => write!(f, "Cannot access ban list. {}", e),
+            BanListError::Json(e) => write!(f, "Ban list is malformed. {}", e),
+        }
+    }
+}
+
+impl Error for BanListError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BanListError::Io(e) => Some(e),
+            BanListError::Json(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::SecretAddress;
+
+    fn node() -> NodeId {
+        SecretAddress::create().to_public_address()
+    }
+
+    #[test]
+    fn test_ban_expires() {
+        let (permanent, temporary) = (node(), node());
+        let mut list = BanList::new();
+        list.ban(Ban {
+            node: permanent.clone(),
+            reason: "configured".to_string(),
+            until: None,
+        });
+        list.ban(Ban {
+            node: temporary.clone(),
+            reason: "misbehavior".to_string(),
+            until: Some(100),
+        });
+
+        assert!(list.is_banned(&temporary, 99));
+        assert_eq!(2, list.bans(99).len());
+        assert!(!list.is_banned(&temporary, 100));
+        assert!(list.is_banned(&permanent, u64::MAX));
+
+        let expired = list.remove_expired(100);
+        assert_eq!(
+            vec![temporary.clone()],
+            expired.into_iter().map(|b| b.node).collect::<Vec<_>>()
+        );
+        assert!(list.unban(&permanent).is_some());
+        assert!(list.bans(0).is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut list = BanList::new();
+        list.ban(Ban {
+            node: node(),
+            reason: "flood".to_string(),
+            until: Some(1000),
+        });
+
+        let path = std::env::temp_dir().join(format!("ban-list-{}.json", std::process::id()));
+        list.save(&path).unwrap();
+        let loaded = BanList::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(list, loaded);
+    }
+
+    #[test]
+    fn test_score_decays() {
+        let now = Instant::now();
+        let node = node();
+        let mut scores = MisbehaviorScores::new(Duration::from_secs(10));
+        assert_eq!(40.0, scores.add(&node, 40, now));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(20.0, scores.score(&node, later));
+        assert_eq!(30.0, scores.add(&node, 10, later));
+
+        scores.remove_below(1.0, later + Duration::from_secs(100));
+        assert_eq!(0.0, scores.score(&node, later));
+    }
+}
@@ -1,12 +1,16 @@
+use crate::address_book::{self, AddressBook, PeerAddress};
 use crate::create_topic;
 use crate::Topic;
 use apply::Apply;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
@@ -15,9 +19,33 @@ use warp::Filter;
 
 type Result<T> = std::result::Result<T, NetError>;
 
+/// Received messages by topic name, with their senders.
+type TopicQueues = Arc<Mutex<HashMap<String, VecDeque<(Endpoint, Vec<u8>)>>>>;
+
 create_topic!(NotifyHeartbeat; Heartbeat);
+create_topic!(NotifyGetAddr; GetAddr);
+create_topic!(NotifyAddr; Vec<PeerAddress>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Addresses sent in reply to one `GetAddr`.
+pub const ADDR_REPLY_COUNT: usize = 100;
+
+/// Outbound connections a backend keeps by dialing addresses of its address book, unless set otherwise.
+pub const DEFAULT_OUTBOUND_TARGET: usize = 8;
+
+/// Inbound neighbors a backend accepts, unless set otherwise.
+pub const DEFAULT_INBOUND_LIMIT: usize = 32;
+
+/// An outbound neighbor dropped as inactive is not dialed again for this long,
+/// and an evicted inbound neighbor is not accepted again.
+pub const REDIAL_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Inbound neighbors connected for the longest which are never evicted for a newcomer.
+const PROTECTED_LONG_LIVED: usize = 4;
+
+/// Inbound neighbors which most recently delivered a message which are never evicted for a newcomer.
+const PROTECTED_USEFUL: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Endpoint {
     addr: SocketAddr,
 }
@@ -45,6 +73,12 @@ impl Heartbeat {
     }
 }
 
+/// Request for peer addresses known to the receiver, which replies with `NotifyAddr`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetAddr {
+    from: Endpoint,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HeartbeatConfig {
     period: Duration,
@@ -65,13 +99,29 @@ impl HeartbeatConfig {
 struct EndpointState {
     endpoint: Endpoint,
     last_heartbeat: Instant,
+    /// Chosen by this node, rather than having announced itself by a heartbeat
+    outbound: bool,
+    connected: Instant,
+    /// When it last delivered a message other than of the backend's own topics
+    last_useful: Option<Instant>,
 }
 
 impl EndpointState {
     fn new(endpoint: Endpoint) -> Self {
+        let now = Instant::now();
         Self {
             endpoint,
-            last_heartbeat: Instant::now(),
+            last_heartbeat: now,
+            outbound: false,
+            connected: now,
+            last_useful: None,
+        }
+    }
+
+    fn outbound(endpoint: Endpoint) -> Self {
+        Self {
+            outbound: true,
+            ..Self::new(endpoint)
         }
     }
 
@@ -129,44 +179,52 @@ impl Entrance {
                 res
             });
 
+        let listener = crate::dual_stack::bind_async(*self.config.entrance_endpoint.as_ref())
+            .expect("Cannot bind the entrance endpoint");
         warp::serve(service)
-            .run(self.config.entrance_endpoint.as_ref().clone())
+            .run_incoming(crate::dual_stack::incoming(listener))
             .await;
         // endpoints data must be alive until server running
         drop(self);
     }
 
     fn request_neighbors(entrance: Endpoint, my: Endpoint) -> Result<Vec<Endpoint>> {
-        let url = format!(
-            "http:///{}/blockchain-net-connector?{}={}",
-            entrance.as_ref().to_string(),
-            "addr",
-            my.as_ref().to_string()
-        );
+        let url = format!("http://{}/blockchain-net-connector", entrance.as_ref());
+        let url = reqwest::Url::parse_with_params(&url, &[("addr", my.as_ref().to_string())])
+            .expect("Entrance URL is valid");
         let res = reqwest::blocking::get(url)?;
         let bytes = res.bytes()?;
         let neighbors = serde_json::from_slice(&bytes)?;
         Ok(neighbors)
     }
 
+    /// Number of bits after the longest common prefix of addresses,
+    /// then squared distance between their octets plus that of ports.
+    /// IPv4 addresses are compared as IPv4-mapped IPv6 ones, so that they are far from any IPv6 address
+    /// but equal to the same host seen by a dual-stack listener.
     fn distance(ep1: Endpoint, ep2: Endpoint) -> impl Copy + Ord {
         let ep1 = ep1.as_ref();
         let ep2 = ep2.as_ref();
+        let (ip1, ip2) = (Self::octets(ep1.ip()), Self::octets(ep2.ip()));
 
-        let port_distance = (ep1.port() as i32 - ep2.port() as i32).apply(|d| d * d);
+        let prefix_distance =
+            128 - (u128::from_be_bytes(ip1) ^ u128::from_be_bytes(ip2)).leading_zeros();
 
-        if let (IpAddr::V4(ep1), IpAddr::V4(ep2)) = (ep1.ip(), ep2.ip()) {
-            let addr_distance = ep1
-                .octets()
-                .iter()
-                .zip(ep2.octets().iter())
-                .map(|(&o1, &o2)| o1 as i32 - o2 as i32)
-                .map(|x| x * x)
-                .sum::<i32>();
+        let port_distance = (ep1.port() as i64 - ep2.port() as i64).apply(|d| d * d);
+        let addr_distance = ip1
+            .iter()
+            .zip(ip2.iter())
+            .map(|(&o1, &o2)| o1 as i64 - o2 as i64)
+            .map(|x| x * x)
+            .sum::<i64>();
+
+        (prefix_distance, port_distance + addr_distance)
+    }
 
-            port_distance + addr_distance
-        } else {
-            port_distance
+    fn octets(ip: IpAddr) -> [u8; 16] {
+        match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
         }
     }
 }
@@ -190,16 +248,29 @@ impl EntranceConfig {
 struct BackendInner {
     endpoint: Endpoint,
     neighbors: Mutex<Vec<EndpointState>>,
-    topics_map: Arc<Mutex<HashMap<String, VecDeque<Vec<u8>>>>>,
+    /// One connection per neighbor carrying all topics, reconnected after a write failure
+    streams: Mutex<HashMap<Endpoint, TcpStream>>,
+    topics_map: TopicQueues,
+    address_book: Mutex<AddressBook>,
+    outbound_target: AtomicUsize,
+    /// Outbound neighbors dropped as inactive, and when
+    dropped: Mutex<HashMap<Endpoint, Instant>>,
+    inbound_limit: AtomicUsize,
+    /// Inbound neighbors evicted for newcomers, and when
+    evicted: Mutex<HashMap<Endpoint, Instant>>,
     join_handle: Option<BackendJoinHandle>,
 }
 
 impl BackendInner {
-    fn bind(endpoint: Endpoint, neighbors: Vec<Endpoint>) -> Result<Self> {
-        let listener = TcpListener::bind(endpoint.as_ref())?;
+    fn bind(
+        endpoint: Endpoint,
+        neighbors: Vec<Endpoint>,
+        address_book: AddressBook,
+    ) -> Result<Self> {
+        let listener = crate::dual_stack::bind(*endpoint.as_ref())?;
         listener.set_nonblocking(true)?;
 
-        let neighbors = neighbors.into_iter().map(EndpointState::new).collect();
+        let neighbors = neighbors.into_iter().map(EndpointState::outbound).collect();
         let topics_map = Arc::new(Mutex::new(HashMap::new()));
 
         let join_handle = Self::start_listening(listener, topics_map.clone());
@@ -207,7 +278,13 @@ impl BackendInner {
         let backend = Self {
             endpoint,
             neighbors: Mutex::new(neighbors),
+            streams: Mutex::new(HashMap::new()),
             topics_map,
+            address_book: Mutex::new(address_book),
+            outbound_target: AtomicUsize::new(DEFAULT_OUTBOUND_TARGET),
+            dropped: Mutex::new(HashMap::new()),
+            inbound_limit: AtomicUsize::new(DEFAULT_INBOUND_LIMIT),
+            evicted: Mutex::new(HashMap::new()),
             join_handle: Some(join_handle),
         };
 
@@ -215,39 +292,219 @@ impl BackendInner {
     }
 
     fn publish<T: Topic>(&self, topic: &T::Pub) -> Result<()> {
-        let buf = Self::serialize_to_bytes::<T>(topic)?;
+        let buf = Self::serialize_to_bytes::<T>(self.endpoint, topic)?;
         let neighbors = self.neighbors.lock().expect("Lock failure");
+        let mut streams = self.streams.lock().expect("Lock failure");
+
+        // Forget connections to inactive neighbors
+        streams.retain(|endpoint, _| neighbors.iter().any(|n| &n.endpoint == endpoint));
 
         for neighbor in neighbors.iter() {
-            let addr = neighbor.endpoint.as_ref();
-            if let Ok(mut stream) = TcpStream::connect(addr) {
-                stream.write_all(&buf).ok();
+            Self::write_to(&mut streams, neighbor.endpoint, &buf);
+        }
+
+        Ok(())
+    }
+
+    /// Send `topic` to `endpoint` only, whether it is a neighbor or not.
+    fn send_to<T: Topic>(&self, endpoint: Endpoint, topic: &T::Pub) -> Result<()> {
+        let buf = Self::serialize_to_bytes::<T>(self.endpoint, topic)?;
+        let mut streams = self.streams.lock().expect("Lock failure");
+        Self::write_to(&mut streams, endpoint, &buf);
+        Ok(())
+    }
+
+    /// Write `buf` to `endpoint`, connecting first if needed. Returns whether it was written.
+    fn write_to(
+        streams: &mut HashMap<Endpoint, TcpStream>,
+        endpoint: Endpoint,
+        buf: &[u8],
+    ) -> bool {
+        if let Entry::Vacant(entry) = streams.entry(endpoint) {
+            match TcpStream::connect(endpoint.as_ref()) {
+                Ok(stream) => {
+                    entry.insert(stream);
+                }
+                Err(_) => return false,
             }
         }
 
+        let written = streams
+            .get_mut(&endpoint)
+            .map(|stream| Self::write_frame(stream, buf).is_ok());
+        if written == Some(false) {
+            streams.remove(&endpoint);
+        }
+        written == Some(true)
+    }
+
+    /// Answer requests for addresses, and learn addresses from replies.
+    fn exchange_addresses(&self) {
+        let now = crate::unix_now();
+
+        while let Ok(request) = self.try_recv::<NotifyGetAddr>() {
+            let reply = {
+                let mut book = self.address_book.lock().expect("Lock failure");
+                book.seen(request.from, now);
+                book.sample(ADDR_REPLY_COUNT + 1, now)
+                    .into_iter()
+                    .filter(|a| a.endpoint != request.from)
+                    .take(ADDR_REPLY_COUNT)
+                    .collect::<Vec<_>>()
+            };
+            self.send_to::<NotifyAddr>(request.from, &reply).ok();
+        }
+
+        while let Ok(addresses) = self.try_recv::<NotifyAddr>() {
+            let addresses = addresses
+                .into_iter()
+                .filter(|a| a.endpoint != self.endpoint);
+            self.address_book
+                .lock()
+                .expect("Lock failure")
+                .merge(addresses, now);
+        }
+    }
+
+    /// Dial addresses of the address book while outbound neighbors are fewer than the target,
+    /// preferring networks which no outbound neighbor is in.
+    fn maintain_outbound(&self) {
+        let target = self.outbound_target.load(Ordering::Relaxed);
+        let mut neighbors = self.neighbors.lock().expect("Lock failure");
+        let outbound = neighbors
+            .iter()
+            .filter(|n| n.outbound)
+            .map(|n| n.endpoint)
+            .collect::<Vec<_>>();
+        if outbound.len() >= target {
+            return;
+        }
+
+        let mut dropped = self.dropped.lock().expect("Lock failure");
+        dropped.retain(|_, at| at.elapsed() < REDIAL_DELAY);
+        let candidates = self
+            .address_book
+            .lock()
+            .expect("Lock failure")
+            .select_outbound(target - outbound.len(), crate::unix_now(), &outbound, |e| {
+                *e == self.endpoint
+                    || dropped.contains_key(e)
+                    || neighbors.iter().any(|n| n.endpoint == *e)
+            });
+        // A candidate which does not answer heartbeats is dropped again as inactive
+        neighbors.extend(candidates.into_iter().map(EndpointState::outbound));
+    }
+
+    fn connection_stats(&self) -> ConnectionStats {
+        let neighbors = self.neighbors.lock().expect("Lock failure");
+        let outbound = neighbors.iter().filter(|n| n.outbound).count();
+        ConnectionStats {
+            outbound,
+            inbound: neighbors.len() - outbound,
+            outbound_target: self.outbound_target.load(Ordering::Relaxed),
+            inbound_limit: self.inbound_limit.load(Ordering::Relaxed),
+            known_addresses: self.address_book.lock().expect("Lock failure").len(),
+        }
+    }
+
+    /// Accept a neighbor which announced itself by a heartbeat, or refresh a known one.
+    /// A newcomer beyond the inbound limit takes the slot of an evicted neighbor, if any can be evicted.
+    fn accept_inbound(&self, endpoint: Endpoint) {
+        let mut neighbors = self.neighbors.lock().expect("Lock failure");
+        if let Some(neighbor) = neighbors.iter_mut().find(|n| n.endpoint == endpoint) {
+            neighbor.update_heartbeat();
+            return;
+        }
+
+        let mut evicted = self.evicted.lock().expect("Lock failure");
+        evicted.retain(|_, at| at.elapsed() < REDIAL_DELAY);
+        if evicted.contains_key(&endpoint) {
+            return;
+        }
+        let inbound = neighbors.iter().filter(|n| !n.outbound).count();
+        if inbound >= self.inbound_limit.load(Ordering::Relaxed) {
+            match eviction_candidate(&neighbors) {
+                Some(i) => {
+                    let removed = neighbors.remove(i);
+                    println!("Evict {} for {}", removed.endpoint.addr, endpoint.addr);
+                    evicted.insert(removed.endpoint, Instant::now());
+                }
+                None => return,
+            }
+        }
+        neighbors.push(EndpointState::new(endpoint));
+    }
+
+    fn try_recv<T: Topic>(&self) -> Result<T::Sub> {
+        self.try_recv_with_origin::<T>().map(|(topic, _)| topic)
+    }
+
+    fn try_recv_with_origin<T: Topic>(&self) -> Result<(T::Sub, Origin)> {
+        let (from, bytes) = {
+            let mut map = self.topics_map.lock().expect("Lock failure");
+            let queue = map.get_mut(T::NAME).ok_or(NetError::NoMessage)?;
+            queue.pop_front().ok_or(NetError::NoMessage)?
+        };
+        let topic = bincode::deserialize(&bytes)?;
+
+        let own_topics = [NotifyHeartbeat::NAME, NotifyGetAddr::NAME, NotifyAddr::NAME];
+        let mut neighbors = self.neighbors.lock().expect("Lock failure");
+        let neighbor = neighbors.iter_mut().find(|n| n.endpoint == from);
+        let outbound = neighbor.as_ref().is_some_and(|n| n.outbound);
+        if let Some(neighbor) = neighbor.filter(|_| !own_topics.contains(&T::NAME)) {
+            neighbor.last_useful = Some(Instant::now());
+        }
+
+        Ok((
+            topic,
+            Origin {
+                endpoint: from,
+                outbound,
+            },
+        ))
+    }
+
+    /// Write `buf` prefixed with its length, so that one stream carries many messages.
+    fn write_frame(stream: &mut TcpStream, buf: &[u8]) -> std::io::Result<()> {
+        stream.write_all(&(buf.len() as u64).to_le_bytes())?;
+        stream.write_all(buf)
+    }
+
+    fn read_frame(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+        let mut len = [0; 8];
+        stream.read_exact(&mut len)?;
+        let mut buf = vec![0; u64::from_le_bytes(len) as usize];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read messages from a neighbor until it disconnects.
+    fn spawn_reader(mut stream: TcpStream, topics: TopicQueues) -> std::io::Result<()> {
+        stream.set_nonblocking(false)?;
+        std::thread::spawn(move || {
+            while let Ok(buf) = Self::read_frame(&mut stream) {
+                if let Ok((name, from, topic_bytes)) = Self::deserialize_to_tuple(&buf) {
+                    topics
+                        .lock()
+                        .expect("Lock failure")
+                        .entry(name)
+                        .or_default()
+                        .push_back((from, topic_bytes));
+                }
+            }
+        });
         Ok(())
     }
 
-    fn start_listening(
-        listener: TcpListener,
-        topics: Arc<Mutex<HashMap<String, VecDeque<Vec<u8>>>>>,
-    ) -> BackendJoinHandle {
+    fn start_listening(listener: TcpListener, topics: TopicQueues) -> BackendJoinHandle {
         let (terminate_sender, terminate_receiver) = std::sync::mpsc::channel();
 
         let join_handle = std::thread::spawn(move || {
-            while let Err(_) = terminate_receiver.try_recv() {
+            while terminate_receiver.try_recv().is_err() {
                 match listener.accept().map(|(stream, _)| stream) {
-                    Ok(mut s) => {
-                        let mut buf = vec![];
-                        if let Ok(_) = s.read_to_end(&mut buf) {
-                            if let Ok((name, topic_bytes)) = Self::deserialize_to_tuple(&buf) {
-                                topics
-                                    .lock()
-                                    .expect("Lock failure")
-                                    .entry(name)
-                                    .or_insert(VecDeque::new())
-                                    .push_back(topic_bytes);
-                            }
+                    Ok(s) => {
+                        if let Err(e) = Self::spawn_reader(s, topics.clone()) {
+                            eprintln!("{}", e);
                         }
                     }
                     Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
@@ -267,18 +524,23 @@ impl BackendInner {
         }
     }
 
-    fn serialize_to_tuple<T: Topic>(data: &T::Pub) -> Result<(&'static str, Vec<u8>)> {
+    /// Messages carry the endpoint of their sender, since the source port of the connection
+    /// tells nothing about where the sender listens.
+    fn serialize_to_tuple<T: Topic>(
+        from: Endpoint,
+        data: &T::Pub,
+    ) -> Result<(&'static str, Endpoint, Vec<u8>)> {
         let bytes = bincode::serialize(data)?;
-        Ok((T::NAME, bytes))
+        Ok((T::NAME, from, bytes))
     }
 
-    fn serialize_to_bytes<T: Topic>(data: &T::Pub) -> Result<Vec<u8>> {
-        let tuple = Self::serialize_to_tuple::<T>(data)?;
+    fn serialize_to_bytes<T: Topic>(from: Endpoint, data: &T::Pub) -> Result<Vec<u8>> {
+        let tuple = Self::serialize_to_tuple::<T>(from, data)?;
         let bytes = bincode::serialize(&tuple)?;
         Ok(bytes)
     }
 
-    fn deserialize_to_tuple(data: &[u8]) -> Result<(String, Vec<u8>)> {
+    fn deserialize_to_tuple(data: &[u8]) -> Result<(String, Endpoint, Vec<u8>)> {
         let tuple = bincode::deserialize(data)?;
         Ok(tuple)
     }
@@ -305,12 +567,70 @@ impl Drop for BackendJoinHandle {
     }
 }
 
+/// Index of the inbound neighbor to evict for a newcomer, if any is unprotected.
+/// The longest connected and the most recently useful are kept, since an attacker opening many
+/// connections can be neither. Of the rest, the newest one in the network holding the most is evicted.
+fn eviction_candidate(neighbors: &[EndpointState]) -> Option<usize> {
+    let mut candidates = neighbors
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| !n.outbound)
+        .collect::<Vec<_>>();
+
+    candidates.sort_by_key(|(_, n)| n.connected);
+    candidates.drain(..PROTECTED_LONG_LIVED.min(candidates.len()));
+    candidates.sort_by_key(|(_, n)| Reverse(n.last_useful));
+    let useful = candidates
+        .iter()
+        .take(PROTECTED_USEFUL)
+        .filter(|(_, n)| n.last_useful.is_some())
+        .count();
+    candidates.drain(..useful);
+
+    let mut group_sizes = HashMap::<_, usize>::new();
+    for (_, n) in candidates.iter() {
+        *group_sizes
+            .entry(address_book::address_group(&n.endpoint))
+            .or_default() += 1;
+    }
+    candidates
+        .into_iter()
+        .max_by_key(|(_, n)| {
+            (
+                group_sizes[&address_book::address_group(&n.endpoint)],
+                n.connected,
+            )
+        })
+        .map(|(i, _)| i)
+}
+
+/// Sender of a received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub endpoint: Endpoint,
+    /// Whether this node chose the sender as a neighbor. Anyone can become an inbound neighbor.
+    pub outbound: bool,
+}
+
+/// Neighbors of a backend by who chose them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub outbound: usize,
+    pub inbound: usize,
+    pub outbound_target: usize,
+    pub inbound_limit: usize,
+    /// Addresses in the address book
+    pub known_addresses: usize,
+}
+
 pub struct Backend {
     inner: Arc<BackendInner>,
     /// Close heartbeat publisher thread on drop
     _join_handle_heartbeat_publisher: BackendJoinHandle,
     /// Close heartbeat subscriber thread on drop
     _join_handle_heartbeat_subscriber: BackendJoinHandle,
+    /// Close peer exchange thread on drop
+    _join_handle_peer_exchange: BackendJoinHandle,
 }
 
 impl Backend {
@@ -319,23 +639,78 @@ impl Backend {
         my: Endpoint,
         heartbeat_config: HeartbeatConfig,
     ) -> Result<Self> {
-        let neighbors = Entrance::request_neighbors(entrance, my)?;
-        let inner = BackendInner::bind(my, neighbors)?;
+        Self::bind_with_address_book(entrance, my, heartbeat_config, AddressBook::new())
+    }
+
+    /// Bind with addresses learned in an earlier run, which are connected to instead
+    /// if the entrance is unreachable.
+    pub fn bind_with_address_book(
+        entrance: Endpoint,
+        my: Endpoint,
+        heartbeat_config: HeartbeatConfig,
+        mut address_book: AddressBook,
+    ) -> Result<Self> {
+        let now = crate::unix_now();
+        let neighbors = match Entrance::request_neighbors(entrance, my) {
+            Ok(neighbors) => {
+                // The entrance hands out addresses it has just heard of
+                for &neighbor in neighbors.iter().filter(|&&n| n != entrance) {
+                    address_book.seen(neighbor, now);
+                }
+                neighbors
+            }
+            Err(e) if address_book.is_empty() => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "Entrance {} is unreachable, connecting to known peers instead. {:?}",
+                    entrance.addr, e
+                );
+                address_book.select_outbound(DEFAULT_OUTBOUND_TARGET, now, &[], |&e| e == my)
+            }
+        };
+        let inner = BackendInner::bind(my, neighbors, address_book)?;
         let inner = Arc::new(inner);
 
         let join_handle_heartbeat_publisher =
             Publisher::from_backend_inner(inner.clone()).start_heartbeat(heartbeat_config.period);
         let join_handle_heartbeat_subscriber = Subscriber::from_backend_inner(inner.clone())
             .start_heartbeat_subscription(heartbeat_config.timeout, heartbeat_config.period);
+        let join_handle_peer_exchange = Publisher::from_backend_inner(inner.clone())
+            .start_peer_exchange(heartbeat_config.timeout);
 
         let backend = Self {
             inner,
             _join_handle_heartbeat_publisher: join_handle_heartbeat_publisher,
             _join_handle_heartbeat_subscriber: join_handle_heartbeat_subscriber,
+            _join_handle_peer_exchange: join_handle_peer_exchange,
         };
         Ok(backend)
     }
 
+    /// Keep `target` outbound connections from now on.
+    /// Extra ones are not closed, but are not replaced when they drop.
+    pub fn set_outbound_target(&self, target: usize) {
+        self.inner.outbound_target.store(target, Ordering::Relaxed);
+    }
+
+    /// Accept at most `limit` inbound neighbors from now on, evicting for newcomers beyond.
+    pub fn set_inbound_limit(&self, limit: usize) {
+        self.inner.inbound_limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.inner.connection_stats()
+    }
+
+    /// Addresses known so far, to be saved for the next run.
+    pub fn address_book(&self) -> AddressBook {
+        self.inner
+            .address_book
+            .lock()
+            .expect("Lock failure")
+            .clone()
+    }
+
     fn inner(&self) -> Arc<BackendInner> {
         self.inner.clone()
     }
@@ -363,12 +738,40 @@ impl<T: Topic> Publisher<T> {
     }
 }
 
+impl Publisher<NotifyGetAddr> {
+    /// Ask neighbors for addresses every `period`, and handle the exchange in between.
+    pub fn start_peer_exchange(self, period: Duration) -> BackendJoinHandle {
+        let (terminate_sender, terminate_receiver) = std::sync::mpsc::channel();
+
+        let join_handle = std::thread::spawn(move || {
+            let mut last_request = None::<Instant>;
+            while terminate_receiver.try_recv().is_err() {
+                if last_request.is_none_or(|t| t.elapsed() >= period) {
+                    let request = GetAddr {
+                        from: self.inner.endpoint,
+                    };
+                    self.inner.publish::<NotifyGetAddr>(&request).ok();
+                    last_request = Some(Instant::now());
+                }
+                self.inner.exchange_addresses();
+                self.inner.maintain_outbound();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+
+        BackendJoinHandle {
+            terminate_sender: Mutex::new(terminate_sender),
+            join_handle: Some(join_handle),
+        }
+    }
+}
+
 impl Publisher<NotifyHeartbeat> {
     pub fn start_heartbeat(self, period: Duration) -> BackendJoinHandle {
         let (terminate_sender, terminate_receiver) = std::sync::mpsc::channel();
 
         let join_handle = std::thread::spawn(move || {
-            while let Err(_) = terminate_receiver.try_recv() {
+            while terminate_receiver.try_recv().is_err() {
                 let heartbeat = Heartbeat::new(self.inner.endpoint);
                 self.inner.publish::<NotifyHeartbeat>(&heartbeat).ok();
                 println!("Send heartbeat");
@@ -394,12 +797,12 @@ impl<T: Topic> Subscriber<T> {
     }
 
     pub fn try_recv(&self) -> Result<T::Sub> {
-        let mut map = self.inner.topics_map.lock().expect("Lock failure");
-        let queue = map.get_mut(T::NAME).ok_or(NetError::NoMessage)?;
-        let bytes = queue.pop_front().ok_or(NetError::NoMessage)?;
-        let topic = bincode::deserialize(&bytes)?;
+        self.inner.try_recv::<T>()
+    }
 
-        Ok(topic)
+    /// Receive a topic with its sender, for decisions which inbound neighbors must not make alone.
+    pub fn try_recv_with_origin(&self) -> Result<(T::Sub, Origin)> {
+        self.inner.try_recv_with_origin::<T>()
     }
 
     fn from_backend_inner(inner: Arc<BackendInner>) -> Self {
@@ -419,22 +822,19 @@ impl Subscriber<NotifyHeartbeat> {
         let (terminate_sender, terminate_receiver) = std::sync::mpsc::channel();
 
         let join_handle = std::thread::spawn(move || {
-            while let Err(_) = terminate_receiver.try_recv() {
+            while terminate_receiver.try_recv().is_err() {
                 // Pop all received heartbeats
                 while let Ok(heartbeat) = self.try_recv() {
                     println!("Heartbeat from {}", heartbeat.from.addr);
-                    // Update heartbeat reception timestamp
-                    let mut neighbors = self.inner.neighbors.lock().expect("Lock failure");
-                    match neighbors
-                        .iter_mut()
-                        .find(|neighbor| neighbor.endpoint == heartbeat.from)
-                    {
-                        Some(neighbor) => neighbor.update_heartbeat(),
-                        // Add newcomer as a neighbor.
-                        // The newcomer sends heartbeat to me
-                        // because the entrance thinks me as a neighbor of the newcomer.
-                        None => neighbors.push(EndpointState::new(heartbeat.from)),
-                    }
+                    self.inner
+                        .address_book
+                        .lock()
+                        .expect("Lock failure")
+                        .seen(heartbeat.from, crate::unix_now());
+                    // Update heartbeat reception timestamp, or add newcomer as a neighbor.
+                    // The newcomer sends heartbeat to me
+                    // because the entrance thinks me as a neighbor of the newcomer.
+                    self.inner.accept_inbound(heartbeat.from);
                 }
                 // Scan, then remove inactive endpoints
                 let mut neighbors = self.inner.neighbors.lock().expect("Lock failure");
@@ -445,7 +845,16 @@ impl Subscriber<NotifyHeartbeat> {
                         state.is_active(timeout)
                     )
                 });
-                neighbors.retain(|state| state.is_active(timeout));
+                let mut dropped = self.inner.dropped.lock().expect("Lock failure");
+                neighbors.retain(|state| {
+                    let active = state.is_active(timeout);
+                    if !active && state.outbound {
+                        dropped.insert(state.endpoint, Instant::now());
+                    }
+                    active
+                });
+                drop(dropped);
+                drop(neighbors);
                 std::thread::sleep(period);
             }
         });
@@ -489,3 +898,164 @@ impl From<reqwest::Error> for NetError {
         NetError::EntranceConnection(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(s: &str) -> Endpoint {
+        SocketAddr::from_str(s).unwrap().into()
+    }
+
+    #[test]
+    fn test_eviction_protects_long_lived_and_useful() {
+        let now = Instant::now();
+        let inbound = |s: &str, age_secs: u64, useful: bool| EndpointState {
+            connected: now - Duration::from_secs(age_secs),
+            last_useful: useful.then_some(now),
+            ..EndpointState::new(endpoint(s))
+        };
+        let mut neighbors = vec![EndpointState::outbound(endpoint("192.0.2.1:1"))];
+        // Long-lived ones
+        for i in 0..PROTECTED_LONG_LIVED {
+            neighbors.push(inbound(
+                &format!("198.51.100.{}:1", i),
+                1000 + i as u64,
+                false,
+            ));
+        }
+        // Useful ones, although new
+        for i in 0..PROTECTED_USEFUL {
+            neighbors.push(inbound(&format!("203.0.113.{}:1", i), 1, true));
+        }
+        assert_eq!(None, eviction_candidate(&neighbors));
+
+        // Newest of the network holding the most slots
+        neighbors.push(inbound("10.0.0.1:1", 20, false));
+        neighbors.push(inbound("10.0.0.2:1", 10, false));
+        neighbors.push(inbound("10.1.0.1:1", 5, false));
+        let evicted = eviction_candidate(&neighbors).unwrap();
+        assert_eq!(endpoint("10.0.0.2:1"), neighbors[evicted].endpoint);
+    }
+
+    #[test]
+    fn test_distance() {
+        let me = endpoint("[2001:db8::1]:8000");
+        let mut endpoints = vec![
+            endpoint("192.0.2.1:8000"),
+            endpoint("[2001:db9::1]:8000"),
+            endpoint("[2001:db8::3]:8000"),
+            endpoint("[2001:db8::1]:8001"),
+        ];
+        endpoints.sort_by_cached_key(|&ep| Entrance::distance(me, ep));
+        assert_eq!(
+            vec![
+                endpoint("[2001:db8::1]:8001"),
+                endpoint("[2001:db8::3]:8000"),
+                endpoint("[2001:db9::1]:8000"),
+                endpoint("192.0.2.1:8000"),
+            ],
+            endpoints
+        );
+
+        // Same host seen by a dual-stack listener
+        let v4 = endpoint("192.0.2.1:8000");
+        let mapped = endpoint("[::ffff:192.0.2.1]:8000");
+        assert!(
+            Entrance::distance(v4, mapped) < Entrance::distance(v4, endpoint("192.0.2.2:8000"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_entrance_on_ipv6_loopback() {
+        let entrance = endpoint("[::1]:42011");
+        tokio::spawn(Entrance::new(EntranceConfig::new(entrance, 1)).start());
+
+        let request = |my: &str| {
+            let my = endpoint(my);
+            tokio::task::spawn_blocking(move || Entrance::request_neighbors(entrance, my))
+        };
+        // Retry until the entrance binds its endpoint
+        let mut neighbors = request("[::1]:42012").await.unwrap();
+        for _ in 0..50 {
+            if neighbors.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            neighbors = request("[::1]:42012").await.unwrap();
+        }
+        assert_eq!(vec![entrance], neighbors.unwrap());
+
+        request("127.0.0.1:42013").await.unwrap().unwrap();
+        let neighbors = request("[::1]:42014").await.unwrap().unwrap();
+        assert_eq!(vec![endpoint("[::1]:42012"), entrance], neighbors);
+    }
+
+    #[tokio::test]
+    async fn test_peer_exchange_without_entrance() {
+        let entrance = endpoint("127.0.0.1:42021");
+        tokio::spawn(Entrance::new(EntranceConfig::new(entrance, 1)).start());
+        let heartbeat = HeartbeatConfig::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        let bind = |my: &str, book: AddressBook, entrance: Endpoint| {
+            let my = endpoint(my);
+            tokio::task::spawn_blocking(move || {
+                Backend::bind_with_address_book(entrance, my, heartbeat, book)
+            })
+        };
+        // Retry until the entrance binds its endpoint
+        let mut a = bind("127.0.0.1:42022", AddressBook::new(), entrance)
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            if a.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            a = bind("127.0.0.1:42022", AddressBook::new(), entrance)
+                .await
+                .unwrap();
+        }
+        let _a = a.unwrap();
+        let _b = bind("127.0.0.1:42023", AddressBook::new(), entrance)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // C knows only A, and its entrance is gone
+        let mut book = AddressBook::new();
+        book.seen(endpoint("127.0.0.1:42022"), crate::unix_now());
+        let unreachable = endpoint("127.0.0.1:42029");
+        let c = bind("127.0.0.1:42024", book, unreachable)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // A tells C about B, which A knows from its heartbeats
+        let b = endpoint("127.0.0.1:42023");
+        for _ in 0..50 {
+            if c.address_book().last_seen(&b).is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(c.address_book().last_seen(&b).is_some());
+
+        // C dials B too, as it is below its outbound target
+        for _ in 0..50 {
+            if c.connection_stats().outbound == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let stats = c.connection_stats();
+        assert_eq!(2, stats.outbound);
+        assert_eq!(DEFAULT_OUTBOUND_TARGET, stats.outbound_target);
+
+        // Without any known address, the entrance is required
+        let orphan = bind("127.0.0.1:42025", AddressBook::new(), unreachable)
+            .await
+            .unwrap();
+        assert!(orphan.is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use futures::Stream;
+use socket2::{Domain, Socket, Type};
+use std::net::{SocketAddr, TcpListener};
+
+/// Pending connections each listener keeps before accepting them
+const BACKLOG: i32 = 128;
+
+/// Bind a TCP listener at `addr`.
+/// If `addr` is the unspecified IPv6 address `[::]`, the listener accepts IPv4 connections as well,
+/// whatever the platform default is.
+/// Their peers appear as IPv4-mapped IPv6 addresses, which `IpAddr::to_canonical` turns back.
+pub fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if let SocketAddr::V6(v6) = addr {
+        socket.set_only_v6(!v6.ip().is_unspecified())?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Bind `addr` with `bind`, for tokio.
+pub fn bind_async(addr: SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
+    let listener = bind(addr)?;
+    listener.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(listener)
+}
+
+/// Connections accepted by `listener`, to serve with `warp::Server::run_incoming`.
+pub fn incoming(
+    listener: tokio::net::TcpListener,
+) -> impl Stream<Item = std::io::Result<tokio::net::TcpStream>> {
+    futures::stream::unfold(listener, |listener| async {
+        let stream = listener.accept().await.map(|(stream, _)| stream);
+        Some((stream, listener))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+
+    #[test]
+    fn test_bind_dual_stack() {
+        let listener = bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        for ip in [Ipv6Addr::LOCALHOST.into(), Ipv4Addr::LOCALHOST.into()] {
+            let mut client = TcpStream::connect(SocketAddr::new(ip, port)).unwrap();
+            client.write_all(b"hello").unwrap();
+            drop(client);
+
+            let (mut stream, peer) = listener.accept().unwrap();
+            assert_eq!(ip, peer.ip().to_canonical());
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).unwrap();
+            assert_eq!(b"hello", buf.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_bind_v6_only() {
+        let listener = bind(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(TcpStream::connect(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port)).is_ok());
+        assert!(TcpStream::connect(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)).is_err());
+    }
+}
@@ -1,10 +1,10 @@
-use crate::{Service, Topic};
+use crate::middleware::{Layers, Rejection, RequestContext};
+use crate::Service;
 use bytes::Bytes;
 use reqwest::blocking::{Client, ClientBuilder, Response};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
-use std::io::Read;
 use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -13,35 +13,60 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::oneshot::{Receiver, Sender};
 use tokio::task::JoinHandle;
+use warp::http::StatusCode;
 use warp::Filter;
 
+/// Address of a node, listening for topics and services on their own ports.
+/// Binding the unspecified IPv6 address `[::]` accepts both IPv4 and IPv6 peers.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Endpoint {
     ip: IpAddr,
+    topic_port: u16,
+    service_port: u16,
 }
 
 impl Endpoint {
+    pub const DEFAULT_TOPIC_PORT: u16 = 32001;
+    pub const DEFAULT_SERVICE_PORT: u16 = 32002;
+
+    /// Endpoint of the default ports.
     pub const fn new(ip: IpAddr) -> Self {
-        Self { ip }
+        Self {
+            ip,
+            topic_port: Self::DEFAULT_TOPIC_PORT,
+            service_port: Self::DEFAULT_SERVICE_PORT,
+        }
+    }
+
+    pub const fn with_ports(mut self, topic_port: u16, service_port: u16) -> Self {
+        self.topic_port = topic_port;
+        self.service_port = service_port;
+        self
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
     }
 
-    const fn topic_port() -> u16 {
-        32001
+    pub fn topic_port(&self) -> u16 {
+        self.topic_port
     }
 
-    const fn service_port() -> u16 {
-        32002
+    pub fn service_port(&self) -> u16 {
+        self.service_port
     }
 
     pub fn topic_socket(&self) -> SocketAddr {
-        SocketAddr::new(self.ip, Self::topic_port())
+        SocketAddr::new(self.ip, self.topic_port)
     }
 
     pub fn service_socket(&self) -> SocketAddr {
-        SocketAddr::new(self.ip, Self::service_port())
+        SocketAddr::new(self.ip, self.service_port)
     }
 }
 
+// Backends below are not reachable from the public API until Publisher and Subscriber get methods.
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum EndpointState {
     Active(Endpoint),
@@ -49,6 +74,7 @@ enum EndpointState {
     Inactive(Endpoint),
 }
 
+#[allow(dead_code)]
 impl EndpointState {
     fn endpoint(&self) -> Endpoint {
         use EndpointState::*;
@@ -73,31 +99,41 @@ impl EndpointState {
     }
 }
 
+/// Topic sent to a neighbor, with ports of the sender,
+/// since the source port of the connection tells nothing about them.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TopicTransferOwned {
     name: String,
     data: Vec<u8>,
+    topic_port: u16,
+    service_port: u16,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TopicTransfer<'a, 'b> {
     name: &'a str,
     data: &'b [u8],
+    topic_port: u16,
+    service_port: u16,
 }
 
+#[allow(dead_code)]
 struct TopicBackend {
+    endpoint: Endpoint,
     topic_queue: Arc<Mutex<VecDeque<TopicTransferOwned>>>,
     neighbors: Arc<Mutex<Vec<EndpointState>>>,
     subscription_join_handle: JoinHandle<()>,
     shutdown_sender: Sender<()>,
 }
 
+#[allow(dead_code)]
 impl TopicBackend {
     async fn bind(
         endpoint: Endpoint,
         neighbors: Arc<Mutex<Vec<EndpointState>>>,
     ) -> Result<Self, Error> {
-        let listener = TcpListener::bind(endpoint.topic_socket()).await?;
+        let listener = crate::dual_stack::bind_async(endpoint.topic_socket())?;
         let timeout = Duration::from_secs(10);
         let topic_queue = Arc::new(Mutex::new(VecDeque::new()));
         let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel();
@@ -110,6 +146,7 @@ impl TopicBackend {
         );
 
         let backend = Self {
+            endpoint,
             topic_queue,
             neighbors,
             subscription_join_handle,
@@ -118,10 +155,14 @@ impl TopicBackend {
         Ok(backend)
     }
 
+    // Neighbors are locked for the whole round, so that their states follow the sends in order
+    #[allow(clippy::await_holding_lock)]
     async fn send(&self, topic_name: &str, data: &[u8]) {
         let transfer = TopicTransfer {
             name: topic_name,
             data,
+            topic_port: self.endpoint.topic_port(),
+            service_port: self.endpoint.service_port(),
         };
         let bytes = bincode::serialize(&transfer).expect("Serialization fail");
         if let Ok(mut neighbors) = self.neighbors.lock() {
@@ -129,7 +170,7 @@ impl TopicBackend {
                 let addr = neighbor.endpoint().topic_socket();
                 // Update neighbor state
                 if let Ok(mut stream) = TcpStream::connect(addr).await {
-                    if let Ok(_) = stream.write_all(&bytes).await {
+                    if stream.write_all(&bytes).await.is_ok() {
                         neighbor.next_ok();
                     } else {
                         neighbor.next_err();
@@ -149,7 +190,7 @@ impl TopicBackend {
         mut shutdown_receiver: Receiver<()>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            while let Err(_) = shutdown_receiver.try_recv() {
+            while shutdown_receiver.try_recv().is_err() {
                 let (mut stream, remote_addr) =
                     match tokio::time::timeout(timeout, listener.accept()).await {
                         Ok(Ok(tuple)) => tuple,
@@ -157,7 +198,7 @@ impl TopicBackend {
                     };
 
                 let mut buf = vec![];
-                if let Err(_) = stream.read_to_end(&mut buf).await {
+                if stream.read_to_end(&mut buf).await.is_err() {
                     continue;
                 }
 
@@ -166,14 +207,16 @@ impl TopicBackend {
                     Err(_) => continue,
                 };
 
+                // IPv4 peers of a dual-stack listener appear as IPv4-mapped addresses
+                let endpoint = Endpoint::new(remote_addr.ip().to_canonical())
+                    .with_ports(transfer.topic_port, transfer.service_port);
+
                 if let Ok(mut topic_queue) = topic_queue.lock() {
                     topic_queue.push_back(transfer);
                 }
 
                 // Update neighbor state
                 if let Ok(mut neighbors) = neighbors.lock() {
-                    let remote_ip = remote_addr.ip();
-                    let endpoint = Endpoint::new(remote_ip);
                     let state = EndpointState::Active(endpoint);
                     match neighbors.iter_mut().find(|n| n.endpoint() == endpoint) {
                         Some(n) => *n = state,
@@ -185,21 +228,26 @@ impl TopicBackend {
     }
 }
 
+#[allow(dead_code)]
 pub struct Publisher<T> {
     backend: Arc<TopicBackend>,
     _phantom: PhantomData<fn() -> T>,
 }
 
+#[allow(dead_code)]
 pub struct Subscriber<T> {
     backend: Arc<TopicBackend>,
     _phantom: PhantomData<fn() -> T>,
 }
 
+type ServeHandler = Box<dyn FnMut(&str) -> Option<String> + Send + 'static>;
+
 struct Serve {
     service: &'static str,
-    handler: Box<dyn FnMut(&str) -> Option<String> + Send + 'static>,
+    handler: ServeHandler,
 }
 
+#[allow(dead_code)]
 pub struct ServiceBackend {
     servers: Arc<Mutex<Vec<Serve>>>,
     neighbors: Arc<Mutex<Vec<EndpointState>>>,
@@ -207,17 +255,24 @@ pub struct ServiceBackend {
 }
 
 impl ServiceBackend {
-    async fn start(endpoint: Endpoint, neighbors: Arc<Mutex<Vec<EndpointState>>>) -> Self {
+    async fn start(
+        endpoint: Endpoint,
+        neighbors: Arc<Mutex<Vec<EndpointState>>>,
+    ) -> Result<Self, Error> {
         let servers = Arc::new(Mutex::new(vec![]));
-        let shutdown_sender = Self::start_server(endpoint, servers.clone());
-        Self {
+        let shutdown_sender = Self::start_server(endpoint, servers.clone())?;
+        let backend = Self {
             servers,
             neighbors,
             shutdown_sender,
-        }
+        };
+        Ok(backend)
     }
 
-    fn start_server(endpoint: Endpoint, servers: Arc<Mutex<Vec<Serve>>>) -> Sender<()> {
+    fn start_server(
+        endpoint: Endpoint,
+        servers: Arc<Mutex<Vec<Serve>>>,
+    ) -> Result<Sender<()>, Error> {
         let service = warp::path::param().and(warp::body::bytes()).map(
             move |service_name: String, req: Bytes| {
                 let req_string = std::str::from_utf8(&req).unwrap();
@@ -236,18 +291,23 @@ impl ServiceBackend {
             },
         );
 
+        let listener = crate::dual_stack::bind_async(endpoint.service_socket())?;
+
         let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel();
-        let (_, server) =
-            warp::serve(service).bind_with_graceful_shutdown(endpoint.service_socket(), async {
+        let server = warp::serve(service).serve_incoming_with_graceful_shutdown(
+            crate::dual_stack::incoming(listener),
+            async {
                 shutdown_receiver.await.ok();
-            });
+            },
+        );
 
         tokio::spawn(server);
 
-        shutdown_sender
+        Ok(shutdown_sender)
     }
 }
 
+#[allow(dead_code)]
 pub struct Backend {
     topic_backend: Arc<TopicBackend>,
     service_backend: Arc<ServiceBackend>,
@@ -258,7 +318,7 @@ impl Backend {
     pub async fn start(endpoint: Endpoint) -> Result<Self, Error> {
         let neighbors = Arc::new(Mutex::new(vec![]));
         let topic_backend = TopicBackend::bind(endpoint, neighbors.clone()).await?;
-        let service_backend = ServiceBackend::start(endpoint, neighbors.clone()).await;
+        let service_backend = ServiceBackend::start(endpoint, neighbors.clone()).await?;
         let backend = Self {
             topic_backend: Arc::new(topic_backend),
             service_backend: Arc::new(service_backend),
@@ -268,6 +328,65 @@ impl Backend {
     }
 }
 
+/// Route of `S` at `/<S::NAME>`, taking a JSON request and replying `handler`'s response in JSON.
+/// Replies an empty string if `handler` declines the request.
+pub fn service_route<S, F>(
+    handler: F,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: Service,
+    F: Fn(S::Req) -> Option<S::Res> + Clone + Send + Sync + 'static,
+{
+    layered_service_route::<S, F>(handler, Layers::new())
+}
+
+/// `service_route` running every request through `layers` first.
+/// Callers are told apart by IP address, and send tokens as `Authorization: Bearer <token>`.
+/// Rejected requests get 401 or 429 with the `Rejection` in JSON.
+pub fn layered_service_route<S, F>(
+    handler: F,
+    layers: Layers,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+where
+    S: Service,
+    F: Fn(S::Req) -> Option<S::Res> + Clone + Send + Sync + 'static,
+{
+    warp::path(S::NAME)
+        .and(warp::path::end())
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::json::<S::Req>())
+        .map(
+            move |peer: Option<SocketAddr>, authorization: Option<String>, req| {
+                let peer = peer.map(|addr| addr.ip().to_string());
+                let ctx = RequestContext {
+                    service: S::NAME,
+                    peer: peer.as_deref(),
+                    token: authorization
+                        .as_deref()
+                        .and_then(|a| a.strip_prefix("Bearer ")),
+                };
+
+                match layers.handle(&ctx, || handler(req)) {
+                    Ok(res) => {
+                        let json = res
+                            .as_ref()
+                            .map(warp::reply::json)
+                            .unwrap_or(warp::reply::json(&""));
+                        warp::reply::with_status(json, StatusCode::OK)
+                    }
+                    Err(rejection) => {
+                        let status = match rejection {
+                            Rejection::Unauthorized => StatusCode::UNAUTHORIZED,
+                            Rejection::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+                        };
+                        warp::reply::with_status(warp::reply::json(&rejection), status)
+                    }
+                }
+            },
+        )
+}
+
 pub struct HttpServer<S> {
     _phantom: PhantomData<fn() -> S>,
 }
@@ -275,19 +394,21 @@ pub struct HttpServer<S> {
 impl<S: Service> HttpServer<S> {
     pub async fn start<F>(addr: impl Into<SocketAddr>, handler: F)
     where
+        S: 'static,
         F: Fn(S::Req) -> Option<S::Res> + Clone + Send + Sync + 'static,
     {
-        let service = warp::path(S::NAME)
-            .and(warp::body::json::<S::Req>())
-            .map(move |req| {
-                println!("DEBUG: request: {}", serde_json::to_string(&req).unwrap());
-                let req = handler(req);
-                req.as_ref()
-                    .map(warp::reply::json)
-                    .unwrap_or(warp::reply::json(&""))
-            });
-
-        warp::serve(service).run(addr).await;
+        Self::start_with(addr, handler, Layers::new()).await;
+    }
+
+    /// Start serving, running every request through `layers` before `handler`.
+    pub async fn start_with<F>(addr: impl Into<SocketAddr>, handler: F, layers: Layers)
+    where
+        S: 'static,
+        F: Fn(S::Req) -> Option<S::Res> + Clone + Send + Sync + 'static,
+    {
+        warp::serve(layered_service_route::<S, F>(handler, layers))
+            .run(addr)
+            .await;
     }
 }
 
@@ -305,6 +426,7 @@ impl DestinationCollection {
 pub struct HttpClient<S> {
     destination: DestinationCollection,
     client: Client,
+    token: Option<String>,
     _phantom: PhantomData<fn() -> S>,
 }
 
@@ -314,16 +436,27 @@ impl<S: Service> HttpClient<S> {
         let httpclient = Self {
             destination,
             client,
+            token: None,
             _phantom: PhantomData,
         };
         Ok(httpclient)
     }
 
+    /// Send `token` along with every request, for servers requiring auth.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
     pub fn call(&self, req: &S::Req) -> Result<S::Res, ClientError> {
         let json = serde_json::to_string(req)?;
 
         for url in self.urls() {
-            let req = self.client.get(url).body(json.clone()).build()?;
+            let mut req = self.client.get(url).body(json.clone());
+            if let Some(token) = &self.token {
+                req = req.bearer_auth(token);
+            }
+            let req = req.build()?;
             if let Ok(res_text) = self.client.execute(req).and_then(Response::text) {
                 if let Ok(res) = serde_json::from_str::<S::Res>(&res_text) {
                     return Ok(res);
@@ -335,14 +468,9 @@ impl<S: Service> HttpClient<S> {
     }
 
     fn urls(&self) -> impl Iterator<Item = Url> + '_ {
-        self.destination
-            .sockets
-            .iter()
-            .map(|socket| {
-                Url::parse(&format!("http://{}", socket.to_string()))
-                    .and_then(|url| url.join(S::NAME))
-            })
-            .flatten()
+        self.destination.sockets.iter().flat_map(|socket| {
+            Url::parse(&format!("http://{}", socket)).and_then(|url| url.join(S::NAME))
+        })
     }
 }
 
@@ -375,3 +503,95 @@ impl From<reqwest::Error> for ClientError {
         ClientError::Request(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::TokenAuth;
+    use crate::service::QueryExample;
+
+    #[tokio::test]
+    async fn test_service_route() {
+        let route =
+            service_route::<QueryExample, _>(|req: i32| (req >= 0).then(|| req.to_string()));
+
+        let res = warp::test::request()
+            .path("/QueryExample")
+            .json(&42)
+            .reply(&route)
+            .await;
+        assert_eq!("\"42\"", res.body());
+
+        let res = warp::test::request()
+            .path("/QueryExample")
+            .json(&-1)
+            .reply(&route)
+            .await;
+        assert_eq!("\"\"", res.body());
+
+        let res = warp::test::request()
+            .path("/QueryChainInfo")
+            .json(&42)
+            .reply(&route)
+            .await;
+        assert_eq!(404, res.status());
+    }
+
+    #[tokio::test]
+    async fn test_topic_over_ipv6_loopback() {
+        let ip = "::1".parse().unwrap();
+        let sender = Endpoint::new(ip).with_ports(42021, 42022);
+        let receiver = Endpoint::new(ip).with_ports(42023, 42024);
+        let sender_backend = Backend::start(sender).await.unwrap();
+        let receiver_backend = Backend::start(receiver).await.unwrap();
+
+        sender_backend
+            .neighbors
+            .lock()
+            .unwrap()
+            .push(EndpointState::Active(receiver));
+        sender_backend.topic_backend.send("Example", b"data").await;
+
+        for _ in 0..50 {
+            if !receiver_backend.neighbors.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let transfer = receiver_backend.topic_backend.topic_queue.lock().unwrap()[0].clone();
+        assert_eq!("Example", transfer.name);
+        assert_eq!(b"data", transfer.data.as_slice());
+        // Receiver learns the ports of the sender, not those of the connection
+        assert_eq!(
+            vec![EndpointState::Active(sender)],
+            *receiver_backend.neighbors.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layered_service_route() {
+        let layers = Layers::new().with(TokenAuth::new(vec!["secret".to_string()]));
+        let route =
+            layered_service_route::<QueryExample, _>(|req: i32| Some(req.to_string()), layers);
+
+        let res = warp::test::request()
+            .path("/QueryExample")
+            .header("authorization", "Bearer secret")
+            .json(&42)
+            .reply(&route)
+            .await;
+        assert_eq!("\"42\"", res.body());
+
+        let res = warp::test::request()
+            .path("/QueryExample")
+            .header("authorization", "Bearer wrong")
+            .json(&42)
+            .reply(&route)
+            .await;
+        assert_eq!(401, res.status());
+        assert_eq!(
+            Rejection::Unauthorized,
+            serde_json::from_slice::<Rejection>(res.body()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use blockchain_core::signature::{SighashDomain, SighashVersion, Signature, SignatureBuilder};
+use blockchain_core::{Address, SecretAddress};
+use serde::{Deserialize, Serialize};
+
+/// Identity of a node, which stays the same across restarts and network addresses.
+/// It is an ed25519 key like wallet addresses, but kept in a key file of its own.
+pub type NodeId = Address;
+
+/// Gossip message signed by the node which sent it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signed<T> {
+    node: NodeId,
+    message: T,
+    sign: Signature,
+}
+
+impl<T: Serialize> Signed<T> {
+    pub fn new(node_key: &SecretAddress, message: T) -> Self {
+        let sign = node_key.sign(&sighash(&message));
+        Self {
+            node: node_key.to_public_address(),
+            message,
+            sign,
+        }
+    }
+
+    /// Whether the message is signed by its node.
+    pub fn verify(&self) -> bool {
+        self.node.verify(&sighash(&self.message), &self.sign)
+    }
+}
+
+impl<T> Signed<T> {
+    pub fn node(&self) -> &NodeId {
+        &self.node
+    }
+
+    pub fn message(&self) -> &T {
+        &self.message
+    }
+
+    pub fn into_message(self) -> T {
+        self.message
+    }
+}
+
+fn sighash<T: Serialize>(message: &T) -> Vec<u8> {
+    let mut builder =
+        SignatureBuilder::sighash(SighashVersion::CURRENT, SighashDomain::NodeMessage);
+    builder.write_bytes(&bincode::serialize(message).expect("Message is serializable"));
+    builder.finalize_sighash()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_message() {
+        let node_key = SecretAddress::create();
+        let signed = Signed::new(&node_key, 42u64);
+
+        assert!(signed.verify());
+        assert_eq!(&node_key.to_public_address(), signed.node());
+        assert_eq!(&42, signed.message());
+
+        let raw = bincode::serialize(&signed).unwrap();
+        let received: Signed<u64> = bincode::deserialize(&raw).unwrap();
+        assert!(received.verify());
+
+        // Another node cannot claim the message
+        let forged = Signed {
+            node: SecretAddress::create().to_public_address(),
+            ..signed.clone()
+        };
+        assert!(!forged.verify());
+
+        let tampered = Signed {
+            message: 43,
+            ..signed
+        };
+        assert!(!tampered.verify());
+    }
+}
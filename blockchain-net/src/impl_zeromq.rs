@@ -1,26 +1,56 @@
 use crate::async_net::{Client, Publisher, Server, Subscriber};
+use crate::middleware::{Layers, Rejection, RequestContext};
+use crate::service::QueryTopicHistory;
 use crate::{Service, Topic};
 use async_trait::async_trait;
+use bytes::Bytes;
+use std::any::Any;
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender;
 use tokio::task::{JoinError, JoinHandle};
+use tokio::time::Instant;
 use zeromq::{
     PubSocket, RepSocket, ReqSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqError,
+    ZmqMessage,
 };
 
+/// Broker whose endpoints have no prefix, used by `connect` and `bind`.
+pub const DEFAULT_BROKER: &str = "";
+
+/// Number of recent message ids each subscriber remembers to drop duplicates.
+const RECENT_ID_CAPACITY: usize = 4096;
+
 pub struct TopicPublisher<T> {
     socket: PubSocket,
+    /// Id of the next message, starting at random so that publishers hardly share ids
+    next_id: u64,
     _phantom: PhantomData<fn() -> T>,
 }
 
 impl<T: Topic> TopicPublisher<T> {
     pub async fn connect() -> Result<Self, NetError> {
+        Self::connect_to(&[DEFAULT_BROKER]).await
+    }
+
+    /// Publish each message through all of `brokers`, so that it arrives while any of them runs.
+    pub async fn connect_to(brokers: &[impl AsRef<str>]) -> Result<Self, NetError> {
         let mut socket = PubSocket::new();
-        socket.connect(&pub_endpoint_name::<T>()).await?;
+        for broker in brokers {
+            socket
+                .connect(&pub_endpoint_name::<T>(broker.as_ref()))
+                .await?;
+        }
 
         let publisher = Self {
             socket,
+            next_id: RandomState::new().build_hasher().finish(),
             _phantom: PhantomData,
         };
         Ok(publisher)
@@ -33,24 +63,41 @@ impl<T: Topic> Publisher<T> for TopicPublisher<T> {
 
     async fn publish(&mut self, topic: &T::Pub) -> Result<(), Self::Error> {
         let raw = bincode::serialize(topic)?;
-        self.socket.send(raw.into()).await?;
+        // Payload comes first, so that the id frame is optional for subscribers
+        let mut msg = ZmqMessage::from(raw);
+        msg.push_back(self.next_id.to_le_bytes().to_vec().into());
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.socket.send(msg).await?;
         Ok(())
     }
 }
 
 pub struct TopicSubscriber<T> {
     socket: SubSocket,
+    recent_ids: RecentIds,
     _phantom: PhantomData<fn() -> T>,
 }
 
 impl<T: Topic> TopicSubscriber<T> {
     pub async fn connect() -> Result<Self, NetError> {
+        Self::connect_to(&[DEFAULT_BROKER]).await
+    }
+
+    /// Receive messages through any of `brokers`.
+    /// A message relayed by several brokers is received once.
+    pub async fn connect_to(brokers: &[impl AsRef<str>]) -> Result<Self, NetError> {
         let mut socket = SubSocket::new();
-        socket.connect(&sub_endpoint_name::<T>()).await?;
+        for broker in brokers {
+            socket
+                .connect(&sub_endpoint_name::<T>(broker.as_ref()))
+                .await?;
+        }
         socket.subscribe("").await?;
 
         let subscriber = Self {
             socket,
+            recent_ids: RecentIds::new(RECENT_ID_CAPACITY),
             _phantom: PhantomData,
         };
         Ok(subscriber)
@@ -62,30 +109,111 @@ impl<T: Topic> Subscriber<T> for TopicSubscriber<T> {
     type Error = NetError;
 
     async fn recv(&mut self) -> Result<T::Sub, NetError> {
-        let msg = self.socket.recv().await?;
-        let raw = msg.iter().next().ok_or(NetError::Empty)?;
+        loop {
+            let msg = self.socket.recv().await?;
+            let raw = msg.get(0).ok_or(NetError::Empty)?;
+            let id = msg
+                .get(1)
+                .and_then(|id| <[u8; 8]>::try_from(id.as_ref()).ok())
+                .map(u64::from_le_bytes);
+            if let Some(id) = id {
+                if !self.recent_ids.insert(id) {
+                    continue;
+                }
+            }
 
-        let sub = bincode::deserialize(raw)?;
-        Ok(sub)
+            let sub = bincode::deserialize(raw)?;
+            return Ok(sub);
+        }
+    }
+}
+
+/// Ids of recently received messages, forgetting the oldest one first.
+#[derive(Debug)]
+struct RecentIds {
+    capacity: usize,
+    ids: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl RecentIds {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether the id is new.
+    fn insert(&mut self, id: u64) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
     }
 }
 
+/// First frame of a reply to a request rejected by middleware. The second frame is the `Rejection`.
+const REJECTED_FRAME: &[u8] = b"rejected";
+
 pub struct ServiceServer<T> {
-    socket: RepSocket,
+    /// One socket per broker, since a socket connected to several brokers stops after its first reply
+    sockets: Vec<RepSocket>,
+    layers: Layers,
     _phantom: PhantomData<fn() -> T>,
 }
 
 impl<S: Service> ServiceServer<S> {
     pub async fn connect() -> Result<Self, NetError> {
+        Self::connect_to(&[DEFAULT_BROKER]).await
+    }
+
+    /// Serve requests of clients directly at the endpoint of `broker`, instead of through its proxy.
+    /// Used by the proxy itself.
+    pub async fn bind_as(broker: &str) -> Result<Self, NetError> {
         let mut socket = RepSocket::new();
-        socket.connect(&server_endpoint_name::<S>()).await?;
+        socket.bind(&client_endpoint_name::<S>(broker)).await?;
 
         let server = Self {
-            socket,
+            sockets: vec![socket],
+            layers: Layers::new(),
+            _phantom: PhantomData,
+        };
+        Ok(server)
+    }
+
+    /// Serve requests coming through any of `brokers`.
+    pub async fn connect_to(brokers: &[impl AsRef<str>]) -> Result<Self, NetError> {
+        let mut sockets = vec![];
+        for broker in brokers {
+            let mut socket = RepSocket::new();
+            socket
+                .connect(&server_endpoint_name::<S>(broker.as_ref()))
+                .await?;
+            sockets.push(socket);
+        }
+
+        let server = Self {
+            sockets,
+            layers: Layers::new(),
             _phantom: PhantomData,
         };
         Ok(server)
     }
+
+    /// Run every request through `layers` before the handler.
+    pub fn with_layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
 }
 
 #[async_trait]
@@ -96,14 +224,35 @@ impl<S: Service> Server<S> for ServiceServer<S> {
     where
         F: FnMut(S::Req) -> Option<S::Res> + Send,
     {
-        let req = self.socket.recv().await?;
-        let raw = req.iter().next().ok_or(NetError::Empty)?;
+        if self.sockets.is_empty() {
+            return std::future::pending().await;
+        }
+        // Reply through the socket which the request came from
+        let receives = self
+            .sockets
+            .iter_mut()
+            .map(|socket| Box::pin(socket.recv()));
+        let (req, index, _) = futures::future::select_all(receives).await;
+        let req = req?;
+        let mut frames = req.iter();
+        let raw = frames.next().ok_or(NetError::Empty)?;
+        let ctx = RequestContext {
+            service: S::NAME,
+            peer: None,
+            token: frames.next().and_then(|t| std::str::from_utf8(t).ok()),
+        };
 
         let req = bincode::deserialize(raw)?;
-        let req = f(req).ok_or(NetError::Res)?;
-
-        let raw = bincode::serialize(&req)?;
-        self.socket.send(raw.into()).await?;
+        let reply = match self.layers.handle(&ctx, || f(req)) {
+            Ok(Some(res)) => ZmqMessage::from(bincode::serialize(&res)?),
+            Ok(None) => return Err(NetError::Res),
+            Err(rejection) => {
+                let mut reply = ZmqMessage::from(Bytes::from_static(REJECTED_FRAME));
+                reply.push_back(bincode::serialize(&rejection)?.into());
+                reply
+            }
+        };
+        self.sockets[index].send(reply).await?;
 
         Ok(())
     }
@@ -111,20 +260,37 @@ impl<S: Service> Server<S> for ServiceServer<S> {
 
 pub struct ServiceClient<T> {
     socket: ReqSocket,
+    token: Option<String>,
     _phantom: PhantomData<fn() -> T>,
 }
 
 impl<S: Service> ServiceClient<S> {
     pub async fn connect() -> Result<Self, NetError> {
+        Self::connect_to(&[DEFAULT_BROKER]).await
+    }
+
+    /// Send requests through any of `brokers` in turn.
+    pub async fn connect_to(brokers: &[impl AsRef<str>]) -> Result<Self, NetError> {
         let mut socket = ReqSocket::new();
-        socket.connect(&client_endpoint_name::<S>()).await?;
+        for broker in brokers {
+            socket
+                .connect(&client_endpoint_name::<S>(broker.as_ref()))
+                .await?;
+        }
 
         let client = Self {
             socket,
+            token: None,
             _phantom: PhantomData,
         };
         Ok(client)
     }
+
+    /// Send `token` along with every request, for servers requiring auth.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
 }
 
 #[async_trait]
@@ -132,11 +298,17 @@ impl<S: Service> Client<S> for ServiceClient<S> {
     type Error = NetError;
 
     async fn request(&mut self, req: &S::Req) -> Result<S::Res, Self::Error> {
-        let raw = bincode::serialize(req)?;
-        self.socket.send(raw.into()).await?;
+        let mut msg = ZmqMessage::from(bincode::serialize(req)?);
+        if let Some(token) = &self.token {
+            msg.push_back(token.clone().into());
+        }
+        self.socket.send(msg).await?;
 
         let res = self.socket.recv().await?;
         let raw = res.iter().next().ok_or(NetError::Empty)?;
+        if let (REJECTED_FRAME, Some(rejection)) = (&raw[..], res.get(1)) {
+            return Err(NetError::Rejected(bincode::deserialize(rejection)?));
+        }
 
         let res = bincode::deserialize(raw)?;
 
@@ -144,54 +316,170 @@ impl<S: Service> Client<S> for ServiceClient<S> {
     }
 }
 
+/// Client of every service through the same brokers.
+/// Services declared with a method name by `create_service!` get a typed method of this client.
+pub struct RpcClient {
+    brokers: Vec<String>,
+    token: Option<String>,
+    /// `ServiceClient` of each service requested so far, by service name
+    clients: HashMap<&'static str, Box<dyn Any + Send>>,
+}
+
+impl RpcClient {
+    /// Client through `brokers`, which connects to each service on its first request.
+    pub fn new(brokers: &[impl AsRef<str>]) -> Self {
+        Self {
+            brokers: brokers.iter().map(|b| b.as_ref().to_string()).collect(),
+            token: None,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Send `token` along with every request, for servers requiring auth.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self.clients.clear();
+        self
+    }
+
+    pub async fn request<S: Service + 'static>(
+        &mut self,
+        req: &S::Req,
+    ) -> Result<S::Res, NetError> {
+        let client = match self.clients.entry(S::NAME) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut client = ServiceClient::<S>::connect_to(&self.brokers).await?;
+                client.token = self.token.clone();
+                entry.insert(Box::new(client))
+            }
+        };
+        let client = client
+            .downcast_mut::<ServiceClient<S>>()
+            .expect("Service names are unique");
+        client.request(req).await
+    }
+}
+
+impl Default for RpcClient {
+    /// Client through the default proxy.
+    fn default() -> Self {
+        Self::new(&[DEFAULT_BROKER])
+    }
+}
+
+/// Recent messages of `T` kept by the default proxy, oldest first.
+/// Lets a late subscriber catch up on what was published before it connected.
+pub async fn topic_history<T: Topic>() -> Result<Vec<T::Sub>, NetError> {
+    topic_history_from::<T>(&[DEFAULT_BROKER]).await
+}
+
+/// Same as `topic_history`, but asks the proxies of `brokers`.
+pub async fn topic_history_from<T: Topic>(
+    brokers: &[impl AsRef<str>],
+) -> Result<Vec<T::Sub>, NetError> {
+    let mut client = ServiceClient::<QueryTopicHistory>::connect_to(brokers).await?;
+    let history = client.request(&T::NAME.to_string()).await?;
+
+    let history = history
+        .iter()
+        .map(|raw| bincode::deserialize(raw))
+        .collect::<Result<_, _>>()?;
+    Ok(history)
+}
+
 pub struct TopicProxy<T> {
     frontend: SubSocket,
     backend: PubSocket,
+    /// Time each message is held before relayed
+    delay: Duration,
     _phantom: PhantomData<fn() -> T>,
 }
 
 impl<T> TopicProxy<T> {
     pub async fn bind() -> Result<Self, NetError>
+    where
+        T: Topic,
+    {
+        Self::bind_as(DEFAULT_BROKER).await
+    }
+
+    /// Bind endpoints of `broker`, so that several proxies can run side by side.
+    pub async fn bind_as(broker: &str) -> Result<Self, NetError>
     where
         T: Topic,
     {
         let mut frontend = SubSocket::new();
-        frontend.bind(&pub_endpoint_name::<T>()).await?;
+        frontend.bind(&pub_endpoint_name::<T>(broker)).await?;
         frontend.subscribe("").await?;
 
         let mut backend = PubSocket::new();
-        backend.bind(&sub_endpoint_name::<T>()).await?;
+        backend.bind(&sub_endpoint_name::<T>(broker)).await?;
 
         let proxy = Self {
             frontend,
             backend,
+            delay: Duration::ZERO,
             _phantom: PhantomData,
         };
 
         Ok(proxy)
     }
 
-    pub fn start(mut self) -> ProxyHandle<T> {
+    /// Hold each message for `delay` before relaying it, to emulate network latency.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn start(self) -> ProxyHandle<T> {
+        self.start_observed(|_| {})
+    }
+
+    /// Start relaying, passing the payload of each relayed message to `observe`.
+    pub fn start_observed<F>(self, mut observe: F) -> ProxyHandle<T>
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let Self {
+            mut frontend,
+            mut backend,
+            delay,
+            ..
+        } = self;
         let (exit_sender, mut exit_receiver) = tokio::sync::oneshot::channel();
         let join_handle = tokio::spawn(async move {
-            while let Err(_) = exit_receiver.try_recv() {
-                if let Ok(raw) = self.frontend.recv().await {
-                    let _res = self.backend.send(raw).await;
+            // Messages wait for their relay time in order of arrival
+            let (relay_sender, mut relay_receiver) =
+                tokio::sync::mpsc::unbounded_channel::<(Instant, ZmqMessage)>();
+            let relay = tokio::spawn(async move {
+                while let Some((relay_at, raw)) = relay_receiver.recv().await {
+                    tokio::time::sleep_until(relay_at).await;
+                    let _res = backend.send(raw).await;
+                }
+                backend.unbind_all().await;
+            });
+
+            while exit_receiver.try_recv().is_err() {
+                if let Ok(raw) = frontend.recv().await {
+                    if let Some(payload) = raw.get(0) {
+                        observe(payload);
+                    }
+                    relay_sender.send((Instant::now() + delay, raw)).ok();
                 }
             }
 
-            self.frontend.unbind_all().await;
-            self.frontend.unsubscribe("").await.ok();
-            self.backend.unbind_all().await;
+            drop(relay_sender);
+            relay.await.ok();
+            frontend.unbind_all().await;
+            frontend.unsubscribe("").await.ok();
         });
 
-        let proxy_handle = ProxyHandle {
+        ProxyHandle {
             exit_sender,
             join_handle,
             _phantom: PhantomData,
-        };
-
-        proxy_handle
+        }
     }
 }
 
@@ -203,14 +491,22 @@ pub struct ServiceProxy<S> {
 
 impl<S: Service> ServiceProxy<S> {
     pub async fn bind() -> Result<Self, NetError>
+    where
+        S: Service,
+    {
+        Self::bind_as(DEFAULT_BROKER).await
+    }
+
+    /// Bind endpoints of `broker`, so that several proxies can run side by side.
+    pub async fn bind_as(broker: &str) -> Result<Self, NetError>
     where
         S: Service,
     {
         let mut frontend = RepSocket::new();
-        frontend.bind(&client_endpoint_name::<S>()).await?;
+        frontend.bind(&client_endpoint_name::<S>(broker)).await?;
 
         let mut backend = ReqSocket::new();
-        backend.bind(&server_endpoint_name::<S>()).await?;
+        backend.bind(&server_endpoint_name::<S>(broker)).await?;
 
         let proxy = Self {
             frontend,
@@ -224,7 +520,7 @@ impl<S: Service> ServiceProxy<S> {
     pub fn start(mut self) -> ProxyHandle<S> {
         let (exit_sender, mut exit_receiver) = tokio::sync::oneshot::channel();
         let join_handle = tokio::spawn(async move {
-            while let Err(_) = exit_receiver.try_recv() {
+            while exit_receiver.try_recv().is_err() {
                 if let Ok(raw) = self.frontend.recv().await {
                     self.backend.send(raw).await.ok();
                 }
@@ -237,14 +533,186 @@ impl<S: Service> ServiceProxy<S> {
             self.backend.unbind_all().await;
         });
 
-        let proxy_handle = ProxyHandle {
+        ProxyHandle {
             exit_sender,
             join_handle,
             _phantom: PhantomData,
+        }
+    }
+}
+
+type MuxSubscriptions = Arc<Mutex<HashMap<String, Vec<UnboundedSender<Bytes>>>>>;
+
+/// One socket pair to the proxy which carries every topic, instead of one pair per topic.
+/// Each message has the topic name in its first frame and the payload in the second.
+/// Clones share the sockets.
+#[derive(Clone)]
+pub struct MuxConnection {
+    publisher: Arc<tokio::sync::Mutex<PubSocket>>,
+    subscriptions: MuxSubscriptions,
+}
+
+impl MuxConnection {
+    pub async fn connect() -> Result<Self, NetError> {
+        let mut publisher = PubSocket::new();
+        publisher
+            .connect(&mux_pub_endpoint_name(DEFAULT_BROKER))
+            .await?;
+
+        let mut subscriber = SubSocket::new();
+        subscriber
+            .connect(&mux_sub_endpoint_name(DEFAULT_BROKER))
+            .await?;
+        subscriber.subscribe("").await?;
+
+        let subscriptions = MuxSubscriptions::default();
+        Self::spawn_dispatcher(subscriber, subscriptions.clone());
+
+        let connection = Self {
+            publisher: Arc::new(tokio::sync::Mutex::new(publisher)),
+            subscriptions,
         };
+        Ok(connection)
+    }
+
+    pub fn publisher<T: Topic>(&self) -> MuxPublisher<T> {
+        MuxPublisher {
+            socket: self.publisher.clone(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Subscriber receiving messages of `T` published after this call.
+    pub fn subscriber<T: Topic>(&self) -> MuxSubscriber<T> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.subscriptions
+            .lock()
+            .expect("Lock failure")
+            .entry(T::NAME.to_string())
+            .or_default()
+            .push(sender);
+
+        MuxSubscriber {
+            receiver,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Route each incoming message to the subscribers of its topic.
+    /// Subscribers see `NetError::Disconnected` once the socket fails.
+    fn spawn_dispatcher(mut socket: SubSocket, subscriptions: MuxSubscriptions) {
+        tokio::spawn(async move {
+            while let Ok(msg) = socket.recv().await {
+                let (name, payload) = match split_mux_message(msg) {
+                    Some(frames) => frames,
+                    None => continue,
+                };
+
+                let mut subscriptions = subscriptions.lock().expect("Lock failure");
+                if let Some(senders) = subscriptions.get_mut(&name) {
+                    // Forget dropped subscribers
+                    senders.retain(|sender| sender.send(payload.clone()).is_ok());
+                }
+            }
+
+            subscriptions.lock().expect("Lock failure").clear();
+        });
+    }
+}
+
+pub struct MuxPublisher<T> {
+    socket: Arc<tokio::sync::Mutex<PubSocket>>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+#[async_trait]
+impl<T: Topic> Publisher<T> for MuxPublisher<T> {
+    type Error = NetError;
+
+    async fn publish(&mut self, topic: &T::Pub) -> Result<(), Self::Error> {
+        let msg = mux_message::<T>(topic)?;
+        self.socket.lock().await.send(msg).await?;
+        Ok(())
+    }
+}
+
+pub struct MuxSubscriber<T> {
+    receiver: UnboundedReceiver<Bytes>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+#[async_trait]
+impl<T: Topic> Subscriber<T> for MuxSubscriber<T> {
+    type Error = NetError;
+
+    async fn recv(&mut self) -> Result<T::Sub, NetError> {
+        let raw = self.receiver.recv().await.ok_or(NetError::Disconnected)?;
+
+        let sub = bincode::deserialize(&raw)?;
+        Ok(sub)
+    }
+}
+
+/// Relays every topic of multiplexed connections through one socket pair.
+pub struct MuxProxy {
+    frontend: SubSocket,
+    backend: PubSocket,
+}
+
+impl MuxProxy {
+    pub async fn bind() -> Result<Self, NetError> {
+        Self::bind_as(DEFAULT_BROKER).await
+    }
+
+    /// Bind endpoints of `broker`, so that several proxies can run side by side.
+    pub async fn bind_as(broker: &str) -> Result<Self, NetError> {
+        let mut frontend = SubSocket::new();
+        frontend.bind(&mux_pub_endpoint_name(broker)).await?;
+        frontend.subscribe("").await?;
+
+        let mut backend = PubSocket::new();
+        backend.bind(&mux_sub_endpoint_name(broker)).await?;
 
-        proxy_handle
+        let proxy = Self { frontend, backend };
+        Ok(proxy)
     }
+
+    pub fn start(mut self) -> ProxyHandle<Self> {
+        let (exit_sender, mut exit_receiver) = tokio::sync::oneshot::channel();
+        let join_handle = tokio::spawn(async move {
+            while exit_receiver.try_recv().is_err() {
+                if let Ok(raw) = self.frontend.recv().await {
+                    let _res = self.backend.send(raw).await;
+                }
+            }
+
+            self.frontend.unbind_all().await;
+            self.frontend.unsubscribe("").await.ok();
+            self.backend.unbind_all().await;
+        });
+
+        ProxyHandle {
+            exit_sender,
+            join_handle,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+fn mux_message<T: Topic>(topic: &T::Pub) -> Result<ZmqMessage, NetError> {
+    let raw = bincode::serialize(topic)?;
+    let mut msg = ZmqMessage::from(T::NAME);
+    msg.push_back(raw.into());
+    Ok(msg)
+}
+
+/// Topic name and payload of a multiplexed message.
+fn split_mux_message(msg: ZmqMessage) -> Option<(String, Bytes)> {
+    let mut frames = msg.into_vecdeque();
+    let name = frames.pop_front()?;
+    let name = String::from_utf8(name.to_vec()).ok()?;
+    let payload = frames.pop_front()?;
+    Some((name, payload))
 }
 
 pub struct ProxyHandle<T> {
@@ -268,6 +736,10 @@ pub enum NetError {
     Empty,
     Runtime(JoinError),
     Res,
+    /// Multiplexed connection has been closed.
+    Disconnected,
+    /// Middleware of the server refused the request.
+    Rejected(Rejection),
 }
 
 impl From<ZmqError> for NetError {
@@ -296,6 +768,8 @@ impl Display for NetError {
             NetError::Empty => write!(f, "Empty message"),
             NetError::Runtime(e) => e.fmt(f),
             NetError::Res => write!(f, "Failed to create response"),
+            NetError::Disconnected => write!(f, "Connection has been closed"),
+            NetError::Rejected(e) => e.fmt(f),
         }
     }
 }
@@ -308,22 +782,66 @@ impl std::error::Error for NetError {
             NetError::Empty => None,
             NetError::Runtime(e) => Some(e),
             NetError::Res => None,
+            NetError::Disconnected => None,
+            NetError::Rejected(e) => Some(e),
         }
     }
 }
 
-fn pub_endpoint_name<T: Topic>() -> String {
-    format!("ipc://{}-pub.ipc", T::NAME)
+/// Brokers are told apart by a prefix of their endpoint names.
+fn pub_endpoint_name<T: Topic>(broker: &str) -> String {
+    format!("ipc://{}{}-pub.ipc", broker, T::NAME)
 }
 
-fn sub_endpoint_name<T: Topic>() -> String {
-    format!("ipc://{}-sub.ipc", T::NAME)
+fn sub_endpoint_name<T: Topic>(broker: &str) -> String {
+    format!("ipc://{}{}-sub.ipc", broker, T::NAME)
 }
 
-fn server_endpoint_name<S: Service>() -> String {
-    format!("ipc://{}-srv.ipc", S::NAME)
+fn server_endpoint_name<S: Service>(broker: &str) -> String {
+    format!("ipc://{}{}-srv.ipc", broker, S::NAME)
 }
 
-fn client_endpoint_name<S: Service>() -> String {
-    format!("ipc://{}-cli.ipc", S::NAME)
+fn client_endpoint_name<S: Service>(broker: &str) -> String {
+    format!("ipc://{}{}-cli.ipc", broker, S::NAME)
+}
+
+fn mux_pub_endpoint_name(broker: &str) -> String {
+    format!("ipc://{}mux-pub.ipc", broker)
+}
+
+fn mux_sub_endpoint_name(broker: &str) -> String {
+    format!("ipc://{}mux-sub.ipc", broker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topic::PubsubExample;
+
+    #[test]
+    fn test_mux_message() {
+        let msg = mux_message::<PubsubExample>(&42).unwrap();
+        let (name, payload) = split_mux_message(msg).unwrap();
+
+        assert_eq!(PubsubExample::NAME, name);
+        assert_eq!(42, bincode::deserialize::<i32>(&payload).unwrap());
+
+        assert_eq!(
+            None,
+            split_mux_message(ZmqMessage::from(PubsubExample::NAME))
+        );
+    }
+
+    #[test]
+    fn test_recent_ids() {
+        let mut recent = RecentIds::new(2);
+
+        assert!(recent.insert(1));
+        assert!(!recent.insert(1));
+        assert!(recent.insert(2));
+        assert!(recent.insert(3));
+        // The oldest id is forgotten
+        assert!(recent.insert(1));
+        assert!(!recent.insert(3));
+    }
 }
@@ -1,5 +1,6 @@
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::SystemTime;
 
 #[cfg(feature = "async-net")]
 pub mod async_net;
@@ -7,9 +8,31 @@ pub mod async_net;
 #[cfg(feature = "zeromq")]
 pub mod impl_zeromq;
 
+#[cfg(feature = "blocking")]
+pub mod address_book;
+#[cfg(feature = "blocking")]
 pub mod blocking;
+
+#[cfg(feature = "tcp")]
+pub mod dual_stack;
+
+#[cfg(feature = "http")]
 pub mod http;
 
+pub mod bans;
+pub mod identity;
+pub mod middleware;
+pub mod propagation;
+pub mod sync;
+
+/// Current time in seconds since the UNIX epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
 pub trait Topic {
     type Pub: Send + Sync + Serialize;
     type Sub: Send + Sync + DeserializeOwned;
@@ -49,7 +72,7 @@ macro_rules! create_topic {
     ($topic_name: tt; $pub: ty => $sub: ty) => {
         pub struct $topic_name;
 
-        impl crate::Topic for $topic_name {
+        impl $crate::Topic for $topic_name {
             type Pub = $pub;
             type Sub = $sub;
 
@@ -58,18 +81,35 @@ macro_rules! create_topic {
     };
 }
 
+/// Declare a service. Services of this crate may also name a method,
+/// which `RpcClient` gets to request the service with typed arguments.
 #[macro_export]
 macro_rules! create_service {
     ($service_name: tt; $req: ty => $res: ty) => {
         pub struct $service_name;
 
-        impl crate::Service for $service_name {
+        impl $crate::Service for $service_name {
             type Req = $req;
             type Res = $res;
 
             const NAME: &'static str = stringify!($service_name);
         }
     };
+
+    ($service_name: tt; $req: ty => $res: ty; fn $method: ident) => {
+        create_service!($service_name; $req => $res);
+
+        #[cfg(feature = "zeromq")]
+        impl $crate::impl_zeromq::RpcClient {
+            #[doc = concat!("Request `", stringify!($service_name), "`.")]
+            pub async fn $method(
+                &mut self,
+                req: &$req,
+            ) -> Result<$res, $crate::impl_zeromq::NetError> {
+                self.request::<$service_name>(req).await
+            }
+        }
+    };
 }
 
 pub mod topic {
@@ -80,8 +120,10 @@ pub mod topic {
     create_topic!(NotifyAddress; Address);
     create_topic!(NotifyTransfer; Transfer<Verified> => Transfer<Yet>);
     create_topic!(CreateTransaction; VerifiedTransaction => UnverifiedTransaction);
-    create_topic!(NotifyBlock; VerifiedBlock => UnverifiedBlock);
-    create_topic!(NotifyBlockHeight; Option<BlockHeight>);
+    create_topic!(NotifyBlock; compact::CompactBlock);
+    create_topic!(NotifyBlockHeight; identity::Signed<sync::ChainStatus>);
+    create_topic!(NotifyBlockHeader; block::BlockHeader);
+    create_topic!(NotifyBlockRejected; rejection::BlockRejection);
     create_topic!(RequestUtxoByAddress; Address);
     create_topic!(RespondUtxoByAddress; Vec<Transition<Verified>> => Vec<Transition<Yet>>);
 }
@@ -91,8 +133,48 @@ pub mod service {
     use blockchain_core::*;
 
     create_service!(QueryExample; i32 => String);
-    create_service!(QueryBlockByHeight; BlockHeight => UnverifiedBlock);
-    create_service!(QueryUtxoByAddress; Address => Vec<Transfer<Yet>>);
+    // Responds with the full block, for clients without the chain to expand a compact one.
+    create_service!(QueryBlockByHeight; BlockHeight => Result<UnverifiedBlock, sync::SyncError>; fn block_by_height);
+    // Request the first and last heights, both inclusive. Responds with consecutive compact
+    // blocks of the best chain from the first height, as many as the node serves up to
+    // MAX_BLOCKS_PER_RANGE. Each is expanded after its parent enters the ledger.
+    create_service!(QueryBlocksByRange; (BlockHeight, BlockHeight) => Vec<compact::CompactBlock>; fn blocks_by_range);
+    create_service!(QueryUtxoByAddress; Address => Vec<Transition<Yet>>; fn utxo_by_address);
+    // Responds with the confirmed and pending balance of each address
+    create_service!(QueryBalances; Vec<Address> => Vec<(Address, Coin, Coin)>; fn balances);
+    create_service!(QueryTotalSupply; digest::BlockDigest => Result<Coin, ledger::SupplyError>; fn total_supply);
+    create_service!(QueryHeaderByHeight; BlockHeight => Option<block::BlockHeader>; fn header_by_height);
+    create_service!(QueryChainStats; () => Vec<analysis::DailyStats>; fn chain_stats);
+    create_service!(QueryBalanceHistory; Address => Vec<(BlockHeight, Coin)>; fn balance_history);
+    // Request how many holders to list. Nodes may list fewer.
+    create_service!(QueryRichList; usize => Vec<(Address, Coin)>; fn rich_list);
+    create_service!(QueryDailyVolume; () => analysis::DailyVolume; fn daily_volume);
+    // Responds with total UTXO quantity keyed by how many blocks ago they were created
+    create_service!(QueryUtxoAges; () => std::collections::BTreeMap<u64, Coin>; fn utxo_ages);
+    create_service!(QueryChainInfo; () => Option<sync::ChainInfo>; fn chain_info);
+    create_service!(QueryDeployments; () => Vec<deployment::DeploymentStatus>; fn deployments);
+    create_service!(QueryAlerts; () => Vec<sync::Alert>; fn alerts);
+    create_service!(QueryMempoolUsage; () => mempool::MempoolUsage; fn mempool_usage);
+    create_service!(QueryMempoolInfo; () => mempool::MempoolInfo; fn mempool_info);
+    // Request whether to include full transactions
+    create_service!(QueryRawMempool; bool => Vec<mempool::MempoolEntry>; fn raw_mempool);
+    create_service!(SendTransaction; UnverifiedTransaction => Result<digest::BlockDigest, tracker::SendTransactionError>; fn send_transaction);
+    create_service!(QueryTransactionStatus; digest::BlockDigest => tracker::TransactionStatus; fn transaction_status);
+    // Request a hex prefix of a block digest, txid or address
+    create_service!(QuerySearch; String => Result<search::SearchResults, search::SearchError>; fn search);
+    create_service!(QueryMerkleProof; digest::BlockDigest => Option<(block::BlockHeader, light::MerkleProof)>; fn merkle_proof);
+    create_service!(QuerySyncProgress; () => sync::SyncProgress; fn sync_progress);
+    create_service!(QueryBlockPropagation; () => crate::propagation::PropagationReport; fn block_propagation);
+    create_service!(QueryTimers; () => sync::Timers; fn timers);
+    create_service!(SetTimers; sync::Timers => Result<(), sync::TimersError>; fn set_timers);
+    create_service!(QueryBans; () => Vec<crate::bans::Ban>; fn bans);
+    // Responds with the lifted ban, if the node was banned
+    create_service!(Unban; crate::identity::NodeId => Option<crate::bans::Ban>; fn unban);
+    // Request a topic name. Served by the proxy with raw payloads of its recent messages.
+    create_service!(QueryTopicHistory; String => Vec<Vec<u8>>; fn topic_history);
+
+    /// Services which change the state of the node, unlike read-only queries.
+    pub const PRIVILEGED: &[&str] = &[SendTransaction::NAME, SetTimers::NAME, Unban::NAME];
 }
 
 #[cfg(test)]
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What middleware knows about a request before its handler runs.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestContext<'a> {
+    /// Name of the requested service
+    pub service: &'static str,
+    /// Address of the caller, if the transport tells it
+    pub peer: Option<&'a str>,
+    /// Token sent along with the request
+    pub token: Option<&'a str>,
+}
+
+impl RequestContext<'_> {
+    /// Key of the caller: its token, else its address, else empty.
+    pub fn caller(&self) -> &str {
+        self.token.or(self.peer).unwrap_or_default()
+    }
+}
+
+/// Reason for which middleware refused a request before its handler ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rejection {
+    Unauthorized,
+    RateLimited,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Unauthorized => write!(f, "Request has no valid token."),
+            Rejection::RateLimited => write!(f, "Too many requests. Retry later."),
+        }
+    }
+}
+
+impl Error for Rejection {}
+
+/// How a request ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Responded,
+    /// Handler returned no response.
+    Declined,
+    Rejected(Rejection),
+}
+
+/// Concern shared by services, such as logging or auth, run around their handlers.
+pub trait Middleware: Send + Sync {
+    /// Run before the handler. An error rejects the request, so that neither the handler
+    /// nor later middleware see it.
+    fn before(&self, _ctx: &RequestContext) -> Result<(), Rejection> {
+        Ok(())
+    }
+
+    /// Run after every request, including rejected ones, with the time it took.
+    fn after(&self, _ctx: &RequestContext, _outcome: &Outcome, _elapsed: Duration) {}
+}
+
+/// Middleware run in order of addition. Clones share the middleware.
+#[derive(Clone, Default)]
+pub struct Layers {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Layers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Run `handler` for the request of `ctx`, unless any middleware rejects it.
+    pub fn handle<T>(
+        &self,
+        ctx: &RequestContext,
+        handler: impl FnOnce() -> Option<T>,
+    ) -> Result<Option<T>, Rejection> {
+        let start = Instant::now();
+        let res = self
+            .middleware
+            .iter()
+            .try_for_each(|m| m.before(ctx))
+            .map(|_| handler());
+
+        let outcome = match &res {
+            Ok(Some(_)) => Outcome::Responded,
+            Ok(None) => Outcome::Declined,
+            Err(rejection) => Outcome::Rejected(rejection.clone()),
+        };
+        let elapsed = start.elapsed();
+        for m in &self.middleware {
+            m.after(ctx, &outcome, elapsed);
+        }
+
+        res
+    }
+}
+
+/// Log every request with how it ended.
+pub struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn after(&self, ctx: &RequestContext, outcome: &Outcome, elapsed: Duration) {
+        log::debug!(
+            "{} from {:?}: {:?} in {:?}",
+            ctx.service,
+            ctx.peer,
+            outcome,
+            elapsed
+        );
+    }
+}
+
+/// Reject requests without one of the accepted tokens.
+pub struct TokenAuth {
+    tokens: HashSet<String>,
+}
+
+impl TokenAuth {
+    pub fn new(tokens: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            tokens: tokens.into_iter().collect(),
+        }
+    }
+}
+
+impl Middleware for TokenAuth {
+    fn before(&self, ctx: &RequestContext) -> Result<(), Rejection> {
+        match ctx.token {
+            Some(token) if self.tokens.contains(token) => Ok(()),
+            _ => Err(Rejection::Unauthorized),
+        }
+    }
+}
+
+/// Middleware applied only to some services, letting requests of others through.
+pub struct Scoped<M> {
+    services: HashSet<&'static str>,
+    inner: M,
+}
+
+impl<M> Scoped<M> {
+    pub fn new(services: impl IntoIterator<Item = &'static str>, inner: M) -> Self {
+        Self {
+            services: services.into_iter().collect(),
+            inner,
+        }
+    }
+}
+
+impl<M: Middleware> Middleware for Scoped<M> {
+    fn before(&self, ctx: &RequestContext) -> Result<(), Rejection> {
+        match self.services.contains(ctx.service) {
+            true => self.inner.before(ctx),
+            false => Ok(()),
+        }
+    }
+
+    fn after(&self, ctx: &RequestContext, outcome: &Outcome, elapsed: Duration) {
+        if self.services.contains(ctx.service) {
+            self.inner.after(ctx, outcome, elapsed);
+        }
+    }
+}
+
+/// Allow each caller at most `limit` requests in every `window`.
+/// Callers are told apart by `RequestContext::caller`.
+pub struct RateLimit {
+    limit: u32,
+    window: Duration,
+    /// Start of the current window of each caller, and requests in it
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimit {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, ctx: &RequestContext) -> Result<(), Rejection> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("Lock failure");
+        if !windows.contains_key(ctx.caller()) {
+            // Forget callers whose windows have passed
+            windows.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = windows.entry(ctx.caller().to_string()).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(Rejection::RateLimited);
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(token: Option<&str>) -> RequestContext<'_> {
+        RequestContext {
+            service: "QueryExample",
+            peer: None,
+            token,
+        }
+    }
+
+    #[test]
+    fn test_layers() {
+        let layers = Layers::new()
+            .with(TokenAuth::new(vec!["secret".to_string()]))
+            .with(RateLimit::new(2, Duration::from_secs(3600)));
+
+        let ctx = context(Some("secret"));
+        assert_eq!(Ok(Some(1)), layers.handle(&ctx, || Some(1)));
+        assert_eq!(Ok(None::<i32>), layers.handle(&ctx, || None));
+        assert_eq!(Err(Rejection::RateLimited), layers.handle(&ctx, || Some(1)));
+
+        // Handler does not run for rejected requests
+        for token in [None, Some("wrong")] {
+            let res = layers.handle(&context(token), || -> Option<i32> { unreachable!() });
+            assert_eq!(Err(Rejection::Unauthorized), res);
+        }
+    }
+
+    #[test]
+    fn test_scoped() {
+        let auth = Scoped::new(
+            ["SendTransaction"],
+            TokenAuth::new(vec!["secret".to_string()]),
+        );
+
+        assert_eq!(Ok(()), auth.before(&context(None)));
+        let ctx = RequestContext {
+            service: "SendTransaction",
+            peer: None,
+            token: None,
+        };
+        assert_eq!(Err(Rejection::Unauthorized), auth.before(&ctx));
+        let ctx = RequestContext {
+            token: Some("secret"),
+            ..ctx
+        };
+        assert_eq!(Ok(()), auth.before(&ctx));
+    }
+
+    #[test]
+    fn test_rate_limit_by_caller() {
+        let limit = RateLimit::new(1, Duration::from_secs(3600));
+
+        assert_eq!(Ok(()), limit.before(&context(Some("alice"))));
+        assert_eq!(Ok(()), limit.before(&context(Some("bob"))));
+        assert_eq!(
+            Err(Rejection::RateLimited),
+            limit.before(&context(Some("alice")))
+        );
+
+        let limit = RateLimit::new(1, Duration::ZERO);
+        assert_eq!(Ok(()), limit.before(&context(None)));
+        assert_eq!(Ok(()), limit.before(&context(None)));
+    }
+}
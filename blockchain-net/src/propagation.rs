@@ -0,0 +1,247 @@
+use blockchain_core::digest::BlockDigest;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Point of the relay pipeline of a node which a block passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PropagationStage {
+    /// Arrived from another node, before waiting for the block worker
+    Received,
+    /// Connected to the ledger, whether received or mined
+    Validated,
+    /// Published by this node to other nodes
+    Relayed,
+}
+
+impl PropagationStage {
+    fn index(self) -> usize {
+        match self {
+            PropagationStage::Received => 0,
+            PropagationStage::Validated => 1,
+            PropagationStage::Relayed => 2,
+        }
+    }
+}
+
+/// Distribution of the latency between two stages over recent blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub p90: Duration,
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// Summary of `latencies`, or `None` if there is none.
+    pub fn of(mut latencies: Vec<Duration>) -> Option<Self> {
+        latencies.sort();
+        let nearest_rank = |percent: usize| {
+            let rank = (latencies.len() * percent).div_ceil(100).max(1);
+            latencies[rank - 1]
+        };
+        Some(LatencySummary {
+            samples: latencies.len(),
+            min: *latencies.first()?,
+            median: nearest_rank(50),
+            p90: nearest_rank(90),
+            max: *latencies.last()?,
+        })
+    }
+}
+
+impl Display for LatencySummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {}ms, median {}ms, p90 {}ms, max {}ms over {} blocks",
+            self.min.as_millis(),
+            self.median.as_millis(),
+            self.p90.as_millis(),
+            self.max.as_millis(),
+            self.samples
+        )
+    }
+}
+
+/// Latencies of the relay pipeline of a node, over the blocks it tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PropagationReport {
+    /// Blocks tracked, which the latencies are taken from
+    pub blocks: usize,
+    /// Including the wait for the block worker
+    pub received_to_validated: Option<LatencySummary>,
+    pub validated_to_relayed: Option<LatencySummary>,
+    pub received_to_relayed: Option<LatencySummary>,
+}
+
+impl Display for PropagationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let latencies = [
+            ("received to validated", &self.received_to_validated),
+            ("validated to relayed", &self.validated_to_relayed),
+            ("received to relayed", &self.received_to_relayed),
+        ];
+        write!(f, "{} blocks tracked", self.blocks)?;
+        for (name, summary) in latencies {
+            match summary {
+                Some(summary) => write!(f, "; {}: {}", name, summary)?,
+                None => write!(f, "; {}: no samples", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// First time each recent block reached each stage of the relay pipeline.
+/// Holds up to `capacity` blocks, forgetting the earliest tracked one first.
+#[derive(Debug, Clone)]
+pub struct PropagationTracker {
+    capacity: usize,
+    blocks: HashMap<BlockDigest, [Option<Instant>; 3]>,
+    /// Digests in order of first record
+    order: VecDeque<BlockDigest>,
+}
+
+impl PropagationTracker {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Record that the block reached `stage` at `now`, unless it did before.
+    /// A relay starts no tracking, since blocks replayed to lagging nodes were received long ago.
+    pub fn record(&mut self, digest: &BlockDigest, stage: PropagationStage, now: Instant) {
+        if let Some(stages) = self.blocks.get_mut(digest) {
+            stages[stage.index()].get_or_insert(now);
+            return;
+        }
+        if stage == PropagationStage::Relayed || self.capacity == 0 {
+            return;
+        }
+
+        let mut stages = [None; 3];
+        stages[stage.index()] = Some(now);
+        self.blocks.insert(digest.clone(), stages);
+        self.order.push_back(digest.clone());
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.blocks.remove(&oldest);
+            }
+        }
+    }
+
+    /// Latencies between stages of the tracked blocks. Stages reached out of order,
+    /// such as a mined block coming back from the proxy, are left out.
+    pub fn report(&self) -> PropagationReport {
+        let latencies = |from: PropagationStage, to: PropagationStage| {
+            let latencies = self
+                .blocks
+                .values()
+                .filter_map(|stages| {
+                    let from = stages[from.index()]?;
+                    stages[to.index()]?.checked_duration_since(from)
+                })
+                .collect();
+            LatencySummary::of(latencies)
+        };
+
+        use PropagationStage::*;
+        PropagationReport {
+            blocks: self.blocks.len(),
+            received_to_validated: latencies(Received, Validated),
+            validated_to_relayed: latencies(Validated, Relayed),
+            received_to_relayed: latencies(Received, Relayed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(n: u8) -> BlockDigest {
+        BlockDigest::digest(&[n])
+    }
+
+    #[test]
+    fn test_latency_summary() {
+        assert_eq!(None, LatencySummary::of(vec![]));
+
+        let latencies = (1..=10).rev().map(Duration::from_millis).collect();
+        let summary = LatencySummary::of(latencies).unwrap();
+        assert_eq!(10, summary.samples);
+        assert_eq!(Duration::from_millis(1), summary.min);
+        assert_eq!(Duration::from_millis(5), summary.median);
+        assert_eq!(Duration::from_millis(9), summary.p90);
+        assert_eq!(Duration::from_millis(10), summary.max);
+    }
+
+    #[test]
+    fn test_first_seen_counts() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut tracker = PropagationTracker::new(10);
+
+        tracker.record(&digest(0), PropagationStage::Received, at(0));
+        tracker.record(&digest(0), PropagationStage::Received, at(5));
+        tracker.record(&digest(0), PropagationStage::Validated, at(20));
+        tracker.record(&digest(0), PropagationStage::Relayed, at(30));
+
+        let report = tracker.report();
+        assert_eq!(1, report.blocks);
+        assert_eq!(
+            Duration::from_millis(20),
+            report.received_to_validated.unwrap().median
+        );
+        assert_eq!(
+            Duration::from_millis(10),
+            report.validated_to_relayed.unwrap().median
+        );
+        assert_eq!(
+            Duration::from_millis(30),
+            report.received_to_relayed.unwrap().median
+        );
+    }
+
+    #[test]
+    fn test_own_block_echo_is_left_out() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut tracker = PropagationTracker::new(10);
+
+        // Mined, published, then back from the proxy
+        tracker.record(&digest(0), PropagationStage::Validated, at(0));
+        tracker.record(&digest(0), PropagationStage::Relayed, at(1));
+        tracker.record(&digest(0), PropagationStage::Received, at(9));
+        // Replayed without being tracked
+        tracker.record(&digest(1), PropagationStage::Relayed, at(9));
+
+        let report = tracker.report();
+        assert_eq!(1, report.blocks);
+        assert_eq!(None, report.received_to_validated);
+        assert_eq!(None, report.received_to_relayed);
+        assert_eq!(1, report.validated_to_relayed.unwrap().samples);
+    }
+
+    #[test]
+    fn test_forgets_earliest_block() {
+        let now = Instant::now();
+        let mut tracker = PropagationTracker::new(2);
+        for n in 0..3 {
+            tracker.record(&digest(n), PropagationStage::Received, now);
+        }
+        tracker.record(&digest(0), PropagationStage::Validated, now);
+        tracker.record(&digest(2), PropagationStage::Validated, now);
+
+        let report = tracker.report();
+        assert_eq!(2, report.blocks);
+        assert_eq!(1, report.received_to_validated.unwrap().samples);
+    }
+}
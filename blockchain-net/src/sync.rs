@@ -0,0 +1,547 @@
+use blockchain_core::difficulty::ChainWork;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{BlockHeight, Difficulty};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Which blocks a node keeps available for other nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockRetention {
+    /// All blocks from genesis are served.
+    Archival,
+    /// Only blocks at or above `lowest` are served.
+    Pruned { lowest: BlockHeight },
+}
+
+impl BlockRetention {
+    pub fn is_archival(&self) -> bool {
+        matches!(self, BlockRetention::Archival)
+    }
+
+    /// Check whether a block body at `height` can be served.
+    pub fn check(&self, height: BlockHeight) -> Result<(), SyncError> {
+        match *self {
+            BlockRetention::Archival => Ok(()),
+            BlockRetention::Pruned { lowest } if height >= lowest => Ok(()),
+            BlockRetention::Pruned { lowest } => Err(SyncError::Pruned {
+                requested: height,
+                lowest,
+            }),
+        }
+    }
+}
+
+/// Chain status periodically announced by each node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainStatus {
+    height: Option<BlockHeight>,
+    retention: BlockRetention,
+    min_fee_rate: u64,
+    blocks_only: bool,
+}
+
+impl ChainStatus {
+    pub fn new(height: Option<BlockHeight>, retention: BlockRetention) -> Self {
+        Self {
+            height,
+            retention,
+            min_fee_rate: 0,
+            blocks_only: false,
+        }
+    }
+
+    /// Advertise the minimum fee rate for the node to accept and relay transactions.
+    pub fn with_min_fee_rate(mut self, rate: u64) -> Self {
+        self.min_fee_rate = rate;
+        self
+    }
+
+    /// Advertise that the node neither accepts nor relays transactions from other nodes.
+    pub fn with_blocks_only(mut self, blocks_only: bool) -> Self {
+        self.blocks_only = blocks_only;
+        self
+    }
+
+    pub fn height(&self) -> Option<BlockHeight> {
+        self.height
+    }
+
+    pub fn retention(&self) -> BlockRetention {
+        self.retention
+    }
+
+    /// Base units per 1000 bytes, below which the node denies transactions.
+    pub fn min_fee_rate(&self) -> u64 {
+        self.min_fee_rate
+    }
+
+    /// Whether sending transactions to the node is useless.
+    pub fn blocks_only(&self) -> bool {
+        self.blocks_only
+    }
+}
+
+/// Highest chain among nodes which serve every block from `first` up to their tip,
+/// such as archival nodes. `None` if no node can fill the range from `first`.
+pub fn best_serving_height<'a>(
+    statuses: impl IntoIterator<Item = &'a ChainStatus>,
+    first: BlockHeight,
+) -> Option<BlockHeight> {
+    statuses
+        .into_iter()
+        .filter(|status| status.retention().check(first).is_ok())
+        .filter_map(ChainStatus::height)
+        .filter(|height| *height >= first)
+        .max()
+}
+
+/// Summary of a node's best chain, for monitoring consensus health.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainInfo {
+    pub best_digest: BlockDigest,
+    pub height: BlockHeight,
+    /// Sum of expected hashes from genesis to the best block
+    pub cumulative_work: ChainWork,
+    pub difficulty: Difficulty,
+    pub median_time_past: Timestamp,
+    pub retention: BlockRetention,
+    /// Number of branches other than the best chain
+    pub fork_count: usize,
+}
+
+/// Condition of a node which its operator should look into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    /// No block has joined the best chain for `secs`, while transactions wait.
+    ChainStall {
+        height: Option<BlockHeight>,
+        secs: u64,
+    },
+    /// The oldest transaction of the mempool has waited for `secs`.
+    StaleMempool { txid: BlockDigest, secs: u64 },
+    /// Every other node stopped announcing its chain.
+    NoPeers,
+}
+
+impl Alert {
+    /// Whether both are the same kind of alert, however long each has lasted.
+    pub fn is_same_kind(&self, other: &Alert) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::ChainStall {
+                height: Some(height),
+                secs,
+            } => write!(f, "No block after height {} for {} seconds", height, secs),
+            Alert::ChainStall { height: None, secs } => {
+                write!(f, "No genesis block for {} seconds", secs)
+            }
+            Alert::StaleMempool { txid, secs } => write!(
+                f,
+                "Transaction {} has waited in mempool for {} seconds",
+                txid.fmt_short(),
+                secs
+            ),
+            Alert::NoPeers => write!(f, "All other nodes are disconnected"),
+        }
+    }
+}
+
+/// Most blocks served for one `QueryBlocksByRange` request.
+/// Longer ranges are cut short, and the rest is requested again.
+pub const MAX_BLOCKS_PER_RANGE: u64 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncError {
+    /// Requested block body has been pruned by the node.
+    Pruned {
+        requested: BlockHeight,
+        lowest: BlockHeight,
+    },
+    /// The node does not know the requested block.
+    NotFound(BlockHeight),
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Pruned { requested, lowest } => write!(
+                f,
+                "Block {} has been pruned. The lowest available block is {}",
+                requested, lowest
+            ),
+            SyncError::NotFound(height) => write!(f, "Block {} is not found", height),
+        }
+    }
+}
+
+impl Error for SyncError {}
+
+/// Intervals of periodic tasks of a node. Can be changed while the node runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timers {
+    /// Period of announcing the chain height to other nodes
+    pub height_announce: Duration,
+    /// Period of republishing unconfirmed transactions, for nodes which missed them
+    pub mempool_rebroadcast: Duration,
+    /// Period of checking which nodes are still announcing
+    pub peer_ping: Duration,
+    /// Wait before checking again for blocks from other nodes, while the chain is empty or far behind
+    pub sync_retry: Duration,
+    /// Wait before mining again, while no transaction comes
+    pub mining_idle: Duration,
+}
+
+impl Timers {
+    pub fn check(&self) -> Result<(), TimersError> {
+        let intervals = [
+            ("height_announce", self.height_announce),
+            ("mempool_rebroadcast", self.mempool_rebroadcast),
+            ("peer_ping", self.peer_ping),
+            ("sync_retry", self.sync_retry),
+            ("mining_idle", self.mining_idle),
+        ];
+        match intervals.iter().find(|(_, interval)| interval.is_zero()) {
+            Some((name, _)) => Err(TimersError::ZeroInterval(name.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self {
+            height_announce: Duration::from_secs(60),
+            mempool_rebroadcast: Duration::from_secs(120),
+            peer_ping: Duration::from_secs(30),
+            sync_retry: Duration::from_secs(60),
+            mining_idle: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Progress of the initial block download, reported by a node.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    /// Whether the node is far behind other nodes, and neither mines nor relays transactions
+    pub syncing: bool,
+    pub local_height: Option<BlockHeight>,
+    /// Highest chain announced by other nodes
+    pub best_known_height: Option<BlockHeight>,
+    /// Share of known blocks which the node has, in percent
+    pub percent: f64,
+    /// Download rate since the sync began
+    pub blocks_per_sec: f64,
+    /// Expected time to catch up, if blocks are coming
+    pub eta: Option<Duration>,
+}
+
+impl Display for SyncProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} blocks ({:.1}%), {:.2} blocks/s",
+            block_count(self.local_height),
+            block_count(self.best_known_height),
+            self.percent,
+            self.blocks_per_sec
+        )?;
+        match self.eta {
+            Some(eta) => write!(f, ", ETA {}s", eta.as_secs()),
+            None => write!(f, ", ETA unknown"),
+        }
+    }
+}
+
+/// Decides whether a node is in the initial block download, by comparing its chain
+/// with the highest one announced by other nodes.
+#[derive(Debug, Clone)]
+pub struct SyncTracker {
+    /// Number of blocks behind, beyond which the node is syncing
+    threshold: u64,
+    local_height: Option<BlockHeight>,
+    best_known_height: Option<BlockHeight>,
+    /// Time and local block count when the current sync began
+    started: Option<(Instant, u64)>,
+}
+
+impl SyncTracker {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            local_height: None,
+            best_known_height: None,
+            started: None,
+        }
+    }
+
+    pub fn set_local_height(&mut self, height: Option<BlockHeight>, now: Instant) {
+        self.local_height = height;
+        self.update(now);
+    }
+
+    pub fn set_best_known_height(&mut self, height: Option<BlockHeight>, now: Instant) {
+        self.best_known_height = height;
+        self.update(now);
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn progress(&self, now: Instant) -> SyncProgress {
+        let local = block_count(self.local_height);
+        let best_known = block_count(self.best_known_height).max(local);
+        let percent = if best_known == 0 {
+            100.0
+        } else {
+            local as f64 * 100.0 / best_known as f64
+        };
+
+        let blocks_per_sec = match self.started {
+            Some((since, start_count)) => {
+                let secs = now.duration_since(since).as_secs_f64();
+                if secs > 0.0 {
+                    local.saturating_sub(start_count) as f64 / secs
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        let eta = (blocks_per_sec > 0.0)
+            .then(|| Duration::from_secs_f64((best_known - local) as f64 / blocks_per_sec));
+
+        SyncProgress {
+            syncing: self.is_syncing(),
+            local_height: self.local_height,
+            best_known_height: self.best_known_height,
+            percent,
+            blocks_per_sec,
+            eta,
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        let local = block_count(self.local_height);
+        let behind = block_count(self.best_known_height).saturating_sub(local);
+        if behind <= self.threshold {
+            self.started = None;
+        } else if self.started.is_none() {
+            self.started = Some((now, local));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Announcement {
+    height: Option<BlockHeight>,
+    outbound: bool,
+    at: Instant,
+}
+
+/// Chain heights announced by peers, and whether this node chose each peer.
+/// Anyone can become an inbound peer, so a chain announced only by inbound peers
+/// starts no download until an outbound peer announces one as high.
+#[derive(Debug, Clone)]
+pub struct AnnouncedHeights<K> {
+    peers: HashMap<K, Announcement>,
+}
+
+impl<K> Default for AnnouncedHeights<K> {
+    fn default() -> Self {
+        Self {
+            peers: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> AnnouncedHeights<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn announce(&mut self, peer: K, height: Option<BlockHeight>, outbound: bool, now: Instant) {
+        let announcement = Announcement {
+            height,
+            outbound,
+            at: now,
+        };
+        self.peers.insert(peer, announcement);
+    }
+
+    /// Forget peers which have not announced for `timeout`.
+    pub fn forget_older_than(&mut self, timeout: Duration, now: Instant) {
+        self.peers
+            .retain(|_, announcement| now.duration_since(announcement.at) < timeout);
+    }
+
+    /// Highest chain to download, which is the highest announced by an outbound peer.
+    /// This is the height to give `SyncTracker::set_best_known_height`.
+    pub fn download_target(&self) -> Option<BlockHeight> {
+        self.highest(|announcement| announcement.outbound)
+    }
+
+    /// Highest chain announced only by inbound peers, if it is above the download target.
+    pub fn unconfirmed_height(&self) -> Option<BlockHeight> {
+        let highest = self.highest(|_| true);
+        (highest > self.download_target())
+            .then_some(highest)
+            .flatten()
+    }
+
+    fn highest(&self, filter: impl Fn(&Announcement) -> bool) -> Option<BlockHeight> {
+        self.peers
+            .values()
+            .filter(|announcement| filter(announcement))
+            .filter_map(|announcement| announcement.height)
+            .max()
+    }
+}
+
+/// Number of blocks in a chain whose tip is at `height`.
+fn block_count(height: Option<BlockHeight>) -> u64 {
+    height.map(|height| u64::from(height) + 1).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimersError {
+    /// Named interval is zero, which would make its task spin.
+    ZeroInterval(String),
+}
+
+impl Display for TimersError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TimersError::ZeroInterval(name) => write!(f, "Interval {} must not be zero", name),
+        }
+    }
+}
+
+impl Error for TimersError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archival_serves_genesis() {
+        let retention = BlockRetention::Archival;
+
+        assert_eq!(Ok(()), retention.check(BlockHeight::genesis()));
+    }
+
+    #[test]
+    fn test_pruned_declines_old_block() {
+        let lowest = BlockHeight::genesis().next().next();
+        let retention = BlockRetention::Pruned { lowest };
+
+        assert_eq!(Ok(()), retention.check(lowest));
+        assert_eq!(Ok(()), retention.check(lowest.next()));
+        assert_eq!(
+            Err(SyncError::Pruned {
+                requested: BlockHeight::genesis(),
+                lowest
+            }),
+            retention.check(BlockHeight::genesis())
+        );
+    }
+
+    #[test]
+    fn test_best_serving_height_skips_pruned_nodes() {
+        let first = BlockHeight::from(3);
+        let archival = ChainStatus::new(Some(BlockHeight::from(10)), BlockRetention::Archival);
+        let pruned = ChainStatus::new(
+            Some(BlockHeight::from(20)),
+            BlockRetention::Pruned {
+                lowest: BlockHeight::from(15),
+            },
+        );
+        let behind = ChainStatus::new(Some(BlockHeight::from(2)), BlockRetention::Archival);
+
+        assert_eq!(
+            Some(BlockHeight::from(10)),
+            best_serving_height([&archival, &pruned, &behind], first)
+        );
+        assert_eq!(None, best_serving_height([&pruned, &behind], first));
+    }
+
+    #[test]
+    fn test_sync_tracker_syncs_while_far_behind() {
+        let now = Instant::now();
+        let mut tracker = SyncTracker::new(6);
+        tracker.set_local_height(Some(BlockHeight::from(9)), now);
+        assert!(!tracker.is_syncing());
+
+        tracker.set_best_known_height(Some(BlockHeight::from(99)), now);
+        assert!(tracker.is_syncing());
+
+        let later = now + Duration::from_secs(10);
+        tracker.set_local_height(Some(BlockHeight::from(59)), later);
+        let progress = tracker.progress(later);
+        assert!(progress.syncing);
+        assert_eq!(60.0, progress.percent);
+        assert_eq!(5.0, progress.blocks_per_sec);
+        assert_eq!(Some(Duration::from_secs(8)), progress.eta);
+
+        tracker.set_local_height(Some(BlockHeight::from(93)), later);
+        assert!(!tracker.is_syncing());
+    }
+
+    #[test]
+    fn test_inbound_announcement_alone_starts_no_download() {
+        let now = Instant::now();
+        let mut heights = AnnouncedHeights::new();
+        heights.announce("outbound", Some(BlockHeight::from(10)), true, now);
+        heights.announce("inbound", Some(BlockHeight::from(50)), false, now);
+        assert_eq!(Some(BlockHeight::from(10)), heights.download_target());
+        assert_eq!(Some(BlockHeight::from(50)), heights.unconfirmed_height());
+
+        // An outbound peer confirms the chain
+        heights.announce("other", Some(BlockHeight::from(50)), true, now);
+        assert_eq!(Some(BlockHeight::from(50)), heights.download_target());
+        assert_eq!(None, heights.unconfirmed_height());
+
+        let later = now + Duration::from_secs(10);
+        heights.announce("inbound", Some(BlockHeight::from(60)), false, later);
+        heights.forget_older_than(Duration::from_secs(5), later);
+        assert_eq!(None, heights.download_target());
+        assert_eq!(Some(BlockHeight::from(60)), heights.unconfirmed_height());
+    }
+
+    #[test]
+    fn test_timers_deny_zero_interval() {
+        assert_eq!(Ok(()), Timers::default().check());
+
+        let timers = Timers {
+            peer_ping: Duration::ZERO,
+            ..Timers::default()
+        };
+        assert_eq!(
+            Err(TimersError::ZeroInterval("peer_ping".to_string())),
+            timers.check()
+        );
+    }
+    #[test]
+    fn test_alert_kind_ignores_duration() {
+        let stall = |secs| Alert::ChainStall {
+            height: Some(BlockHeight::from(3)),
+            secs,
+        };
+        assert!(stall(600).is_same_kind(&stall(610)));
+        assert!(!stall(600).is_same_kind(&Alert::NoPeers));
+
+        let json = serde_json::to_value(stall(600)).unwrap();
+        assert_eq!(Some("chain_stall"), json["alert"].as_str());
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::Result;
+use blockchain_client::{NodeClient, RemoteNode};
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::{Address, BlockHeight, Coin, SecretAddress, Transaction, Transfer};
+use blockchain_core::{Transition, Verified};
+use blockchain_net::async_net::Subscriber;
+use blockchain_net::identity::NodeId;
+use blockchain_net::impl_zeromq::{ServiceClient, TopicSubscriber, DEFAULT_BROKER};
+use blockchain_net::service::QueryTopicHistory;
+use blockchain_net::topic::{NotifyBlock, NotifyBlockHeight};
+use clap::Parser;
+use process::{LogMux, Process};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+use topology::Topology;
+
+mod process;
+mod topology;
+
+/// Coins sent to the test wallet by each payment, in base units.
+const PAYMENT: Coin = Coin::from(1000);
+
+/// Timer flags of fullnodes, short enough for blocks to follow each payment within seconds.
+const NODE_TIMERS: &[&str] = &[
+    "--height-announce-secs=1",
+    "--peer-ping-secs=1",
+    "--sync-retry-secs=1",
+    "--mining-idle-secs=1",
+];
+
+#[derive(Debug, Parser)]
+struct BcDevnetArgs {
+    /// Number of fullnodes of equal power, sharing one proxy.
+    /// The first one mines the genesis block and funds the test wallet.
+    #[clap(long, default_value_t = 3)]
+    nodes: usize,
+
+    /// Read nodes, links between them, their latencies and mining power from this TOML file
+    /// instead of --nodes. The first node mines the genesis block and funds the test wallet.
+    #[clap(long, conflicts_with = "nodes")]
+    topology: Option<PathBuf>,
+
+    /// Working directory of the network, keeping addresses, IPC endpoints and logs of all processes.
+    #[clap(long, default_value = "devnet")]
+    data_dir: PathBuf,
+
+    /// Directory of the proxy and bcfnode binaries. Defaults to the directory of this binary.
+    #[clap(long)]
+    bin_dir: Option<PathBuf>,
+
+    /// Number of payments to the test wallet, each of which waits for all nodes to agree.
+    #[clap(long, default_value_t = 3)]
+    sends: usize,
+
+    /// Seconds to wait for each step before the run fails
+    #[clap(long, default_value_t = 120)]
+    timeout: u64,
+
+    /// Print the logs of all processes as they are written, each line prefixed by its process name.
+    /// The log files are written either way.
+    #[clap(long)]
+    follow_logs: bool,
+}
+
+/// Running proxies and fullnodes, with the latest chain height announced by each fullnode
+/// and all blocks found so far.
+struct Devnet {
+    processes: Vec<Process>,
+    brokers: Vec<String>,
+    announcements: TopicSubscriber<NotifyBlockHeight>,
+    heights: HashMap<NodeId, Option<BlockHeight>>,
+    blocks: TopicSubscriber<NotifyBlock>,
+    found: HashMap<BlockHeight, HashSet<BlockDigest>>,
+    node_count: usize,
+    timeout: Duration,
+}
+
+impl Devnet {
+    /// Fail if any process has exited.
+    fn check_running(&mut self) -> Result<()> {
+        self.processes
+            .iter_mut()
+            .try_for_each(Process::check_running)
+    }
+
+    /// Wait until all nodes announce the same height of at least `min_height`, then return it.
+    async fn wait_for_convergence(&mut self, min_height: BlockHeight) -> Result<BlockHeight> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            self.check_running()?;
+            if let Some(height) = self.agreed_height().filter(|&h| h >= min_height) {
+                return Ok(height);
+            }
+
+            let received = tokio::time::timeout_at(deadline, async {
+                tokio::select! {
+                    status = self.announcements.recv() => status.map(Some),
+                    block = self.blocks.recv() => block.map(|block| {
+                        self.found
+                            .entry(block.height())
+                            .or_default()
+                            .insert(block.digest().clone());
+                        None
+                    }),
+                }
+            });
+            let status = match received.await {
+                Ok(status) => status?,
+                Err(_) => anyhow::bail!(
+                    "Nodes did not agree on height {} or later. Announced: {:?}",
+                    min_height,
+                    self.heights.values().collect::<Vec<_>>()
+                ),
+            };
+            if let Some(status) = status.filter(|status| status.verify()) {
+                self.heights
+                    .insert(status.node().clone(), status.message().height());
+            }
+        }
+    }
+
+    /// Number of distinct blocks found up to `height`, and how many of them lost a fork.
+    fn stale_blocks(&self, height: BlockHeight) -> (usize, usize) {
+        let found = self
+            .found
+            .iter()
+            .filter(|(&h, _)| h <= height)
+            .map(|(_, digests)| digests.len());
+        found.fold((0, 0), |(total, stale), count| {
+            (total + count, stale + count.saturating_sub(1))
+        })
+    }
+
+    fn agreed_height(&self) -> Option<BlockHeight> {
+        if self.heights.len() < self.node_count {
+            return None;
+        }
+        let mut heights = self.heights.values();
+        let first = *heights.next()?;
+        match heights.all(|&height| height == first) {
+            true => first,
+            false => None,
+        }
+    }
+
+    /// Wait until `address` has `count` UTXOs in the view of the node serving the request.
+    async fn wait_for_utxos(
+        &mut self,
+        node: &mut RemoteNode,
+        address: &Address,
+        count: usize,
+    ) -> Result<Vec<Transition<Verified>>> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            self.check_running()?;
+            let utxos = node.get_utxos(address).await?;
+            if utxos.len() >= count {
+                let utxos = utxos
+                    .into_iter()
+                    .map(Transition::verify)
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(utxos);
+            }
+            if Instant::now() > deadline {
+                anyhow::bail!("{} has only {} of {} UTXOs.", address, utxos.len(), count);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// Name of the proxy process of `broker`, which also names its log file.
+fn proxy_name(broker: &str) -> String {
+    match broker {
+        DEFAULT_BROKER => "proxy".to_string(),
+        _ => format!("{}proxy", broker),
+    }
+}
+
+/// Start a proxy of `broker`, then wait until it accepts connections.
+async fn launch_proxy(
+    bin_dir: &Path,
+    broker: &str,
+    latency_ms: u64,
+    timeout: Duration,
+    mux: Option<&LogMux>,
+) -> Result<Process> {
+    let args = match broker {
+        DEFAULT_BROKER => vec![],
+        _ => vec![
+            format!("--broker={}", broker),
+            format!("--latency-ms={}", latency_ms),
+        ],
+    };
+    let name = proxy_name(broker);
+    let mut proxy = Process::spawn(&name, &bin_dir.join("proxy"), &args, mux)?;
+    // Connecting fails until the proxy binds the endpoint, which it binds last
+    let deadline = Instant::now() + timeout;
+    loop {
+        proxy.check_running()?;
+        match ServiceClient::<QueryTopicHistory>::connect_to(&[broker]).await {
+            Ok(_) => return Ok(proxy),
+            Err(e) if Instant::now() > deadline => return Err(e.into()),
+            Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Start proxies and fullnodes of `topology` in the current directory.
+/// Their logs are printed as well if `follow_logs`.
+async fn launch(
+    bin_dir: &Path,
+    topology: &Topology,
+    timeout: Duration,
+    follow_logs: bool,
+) -> Result<Devnet> {
+    let proxies = match topology.links.is_empty() {
+        true => vec![(DEFAULT_BROKER.to_string(), 0)],
+        false => topology.proxies(),
+    };
+    let mux = follow_logs.then(|| {
+        let proxy_names = proxies.iter().map(|(broker, _)| proxy_name(broker));
+        let node_names = topology.nodes.iter().map(|node| node.name.clone());
+        let width = proxy_names.chain(node_names).map(|name| name.len()).max();
+        LogMux::new(width.unwrap_or_default())
+    });
+
+    let mut processes = vec![];
+    for (broker, latency_ms) in &proxies {
+        let proxy = launch_proxy(bin_dir, broker, *latency_ms, timeout, mux.as_ref()).await?;
+        processes.push(proxy);
+    }
+    let brokers = proxies
+        .into_iter()
+        .map(|(broker, _)| broker)
+        .collect::<Vec<_>>();
+    // Subscribe before nodes start, so that no announcement is missed
+    let announcements = TopicSubscriber::<NotifyBlockHeight>::connect_to(&brokers).await?;
+    let blocks = TopicSubscriber::<NotifyBlock>::connect_to(&brokers).await?;
+
+    for (i, node) in topology.nodes.iter().enumerate() {
+        let address_path = format!("{}.addr", node.name);
+        bcaddr::write_address(&address_path, &SecretAddress::create())?;
+
+        let mut args = vec![
+            format!("--address={}", address_path),
+            format!("--node-name={}", node.name),
+            format!("--mining-attempt-ms={}", topology.mining_attempt_ms(node)),
+        ];
+        if i == 0 {
+            args.push("--mine-genesis-block".to_string());
+        }
+        args.extend(
+            topology
+                .brokers_of(&node.name)
+                .into_iter()
+                .map(|broker| format!("--brokers={}", broker)),
+        );
+        args.extend(NODE_TIMERS.iter().map(|flag| flag.to_string()));
+
+        let bin = bin_dir.join("bcfnode");
+        processes.push(Process::spawn(&node.name, &bin, &args, mux.as_ref())?);
+        println!("Started {}.", node.name);
+    }
+
+    let devnet = Devnet {
+        processes,
+        brokers,
+        announcements,
+        heights: HashMap::new(),
+        blocks,
+        found: HashMap::new(),
+        node_count: topology.nodes.len(),
+        timeout,
+    };
+    Ok(devnet)
+}
+
+/// Remove IPC endpoints left by a previous run, which would fail binding them again.
+fn remove_endpoints(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "ipc") {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = BcDevnetArgs::parse();
+    let topology = match &args.topology {
+        Some(path) => Topology::load(path)?,
+        None if args.nodes == 0 => anyhow::bail!("At least 1 node is required."),
+        None => Topology::uniform(args.nodes),
+    };
+
+    let bin_dir = match args.bin_dir {
+        Some(dir) => dir.canonicalize()?,
+        None => std::env::current_exe()?
+            .parent()
+            .expect("Executable is in a directory")
+            .to_path_buf(),
+    };
+
+    // All processes share IPC endpoints relative to this directory
+    std::fs::create_dir_all(&args.data_dir)?;
+    std::env::set_current_dir(&args.data_dir)?;
+    remove_endpoints(Path::new("."))?;
+
+    let timeout = Duration::from_secs(args.timeout);
+    let mut devnet = launch(&bin_dir, &topology, timeout, args.follow_logs).await?;
+    let mut node = RemoteNode::connect_to(&devnet.brokers).await?;
+
+    let mut height = devnet.wait_for_convergence(BlockHeight::genesis()).await?;
+    println!("All nodes share the genesis block.");
+
+    let funder = bcaddr::read_address(format!("{}.addr", topology.nodes[0].name))?;
+    let wallet = SecretAddress::create().to_public_address();
+
+    for i in 0..args.sends {
+        let utxos = devnet
+            .wait_for_utxos(&mut node, &funder.to_public_address(), 1)
+            .await?;
+        let total = utxos.iter().map(Transition::quantity).sum::<Coin>();
+        let change = match total.checked_sub(PAYMENT) {
+            Some(change) => change,
+            None => anyhow::bail!("Funder has only {}.", total),
+        };
+
+        let mut outputs = vec![Transfer::offer(&funder, wallet.clone(), PAYMENT)];
+        if change > Coin::default() {
+            outputs.push(Transfer::offer(&funder, funder.to_public_address(), change));
+        }
+        let transaction = Transaction::offer(&funder, utxos, outputs).verify_transaction()?;
+        let txid = node.send_transaction(&transaction).await?;
+        println!("Sent payment {} in {}.", i + 1, txid.fmt_short());
+
+        devnet.wait_for_utxos(&mut node, &wallet, i + 1).await?;
+        height = devnet.wait_for_convergence(height.next()).await?;
+        println!("All nodes agree on height {}.", height);
+    }
+
+    let balance = node
+        .get_utxos(&wallet)
+        .await?
+        .iter()
+        .map(Transition::quantity)
+        .sum::<Coin>();
+    let expected = (0..args.sends).map(|_| PAYMENT).sum::<Coin>();
+    if balance != expected {
+        anyhow::bail!("Test wallet has {}, but {} was sent.", balance, expected);
+    }
+
+    let (total, stale) = devnet.stale_blocks(height);
+    println!(
+        "Passed. {} nodes converged at height {}. {} of {} blocks were stale ({:.1}%).",
+        devnet.node_count,
+        height,
+        stale,
+        total,
+        100.0 * stale as f64 / total.max(1) as f64
+    );
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// ANSI colors given to processes in turn: red, green, yellow, blue, magenta and cyan.
+const COLORS: &[u8] = &[31, 32, 33, 34, 35, 36];
+
+/// Copies output of all processes to the terminal as well as their log files,
+/// each line prefixed by the name of its process in a color of its own.
+#[derive(Debug, Clone)]
+pub struct LogMux {
+    /// Names are padded to this width, so that lines of all processes align
+    width: usize,
+    color: bool,
+    next_color: Arc<AtomicUsize>,
+}
+
+impl LogMux {
+    /// Colors are used only if the standard output is a terminal.
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            color: std::io::stdout().is_terminal(),
+            next_color: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn prefix(&self, name: &str) -> String {
+        match self.color {
+            true => {
+                let i = self.next_color.fetch_add(1, Ordering::Relaxed);
+                let color = COLORS[i % COLORS.len()];
+                format!("\x1b[{}m{:>w$}\x1b[0m |", color, name, w = self.width)
+            }
+            false => format!("{:>w$} |", name, w = self.width),
+        }
+    }
+}
+
+/// Write each line of `output` into `log` and print it after `prefix`, until `output` closes.
+fn forward(output: impl Read + Send + 'static, log: Arc<Mutex<File>>, prefix: Arc<str>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let Ok(line) = line else { break };
+            writeln!(log.lock().expect("Lock failure"), "{}", line).ok();
+            println!("{} {}", prefix, line);
+        }
+    });
+}
+
+/// Child process which is killed when dropped, so that a failed run leaves nothing behind.
+pub struct Process {
+    name: String,
+    log: String,
+    child: Child,
+}
+
+impl Process {
+    /// Run `bin` with `args`, writing its stdout and stderr to `<name>.log`,
+    /// and to the terminal through `mux` if given.
+    pub fn spawn(name: &str, bin: &Path, args: &[String], mux: Option<&LogMux>) -> Result<Self> {
+        let log = format!("{}.log", name);
+        let file = File::create(&log)?;
+        let (stdout, stderr) = match mux {
+            Some(_) => (Stdio::piped(), Stdio::piped()),
+            None => (file.try_clone()?.into(), file.try_clone()?.into()),
+        };
+        let mut child = Command::new(bin)
+            .args(args)
+            .env("RUST_LOG", "info")
+            // Proxy shuts down when its stdin closes, which happens when the child is dropped
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Cannot run {}. {}", bin.display(), e))?;
+
+        if let Some(mux) = mux {
+            let file = Arc::new(Mutex::new(file));
+            let prefix = Arc::<str>::from(mux.prefix(name));
+            let stdout = child.stdout.take().expect("Stdout is piped");
+            let stderr = child.stderr.take().expect("Stderr is piped");
+            forward(stdout, file.clone(), prefix.clone());
+            forward(stderr, file, prefix);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            log,
+            child,
+        })
+    }
+
+    /// Fail if the process has exited.
+    pub fn check_running(&mut self) -> Result<()> {
+        match self.child.try_wait()? {
+            None => Ok(()),
+            Some(status) => anyhow::bail!(
+                "{} exited with {}. See {} for details.",
+                self.name,
+                status,
+                self.log
+            ),
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Milliseconds between nonce attempts of the most powerful node.
+const FASTEST_MINING_ATTEMPT_MS: u64 = 10;
+
+/// Nodes and links of a network, read from a TOML file such as
+///
+/// ```toml
+/// [[nodes]]
+/// name = "tokyo"
+/// mining_power = 3
+///
+/// [[nodes]]
+/// name = "paris"
+///
+/// [[links]]
+/// nodes = ["tokyo", "paris"]
+/// latency_ms = 200
+/// ```
+///
+/// Each link is a proxy shared only by its two nodes, delaying every message by its latency.
+/// If no link is given, all nodes share the default proxy without delay.
+#[derive(Debug, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<NodeSpec>,
+    #[serde(default)]
+    pub links: Vec<LinkSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeSpec {
+    pub name: String,
+    /// Relative hash power. A node of power 2 tries nonces twice as often as one of power 1.
+    #[serde(default = "default_mining_power")]
+    pub mining_power: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkSpec {
+    pub nodes: [String; 2],
+    #[serde(default)]
+    pub latency_ms: u64,
+}
+
+fn default_mining_power() -> u64 {
+    1
+}
+
+impl Topology {
+    /// `count` nodes of equal power sharing the default proxy.
+    pub fn uniform(count: usize) -> Self {
+        let nodes = (0..count)
+            .map(|i| NodeSpec {
+                name: format!("node{}", i),
+                mining_power: default_mining_power(),
+            })
+            .collect();
+        Self {
+            nodes,
+            links: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let topology: Self = toml::from_str(&text)?;
+        topology.validate()?;
+        Ok(topology)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.nodes.is_empty() {
+            anyhow::bail!("At least 1 node is required.");
+        }
+        let mut names = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(node.name.as_str()) {
+                anyhow::bail!("Node {} is defined twice.", node.name);
+            }
+            if node.mining_power == 0 {
+                anyhow::bail!("Mining power of {} must be positive.", node.name);
+            }
+        }
+        for link in &self.links {
+            if let Some(name) = link.nodes.iter().find(|n| !names.contains(n.as_str())) {
+                anyhow::bail!("Link refers to unknown node {}.", name);
+            }
+            if link.nodes[0] == link.nodes[1] {
+                anyhow::bail!("Node {} is linked to itself.", link.nodes[0]);
+            }
+        }
+        if !self.links.is_empty() {
+            if let Some(node) = self
+                .nodes
+                .iter()
+                .find(|n| self.brokers_of(&n.name).is_empty())
+            {
+                anyhow::bail!("Node {} has no link.", node.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Broker name of the proxy of each link, with its latency in milliseconds.
+    pub fn proxies(&self) -> Vec<(String, u64)> {
+        self.links
+            .iter()
+            .enumerate()
+            .map(|(i, link)| (link_broker(i), link.latency_ms))
+            .collect()
+    }
+
+    /// Brokers of the links of node `name`. Empty if the network has no link.
+    pub fn brokers_of(&self, name: &str) -> Vec<String> {
+        self.links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.nodes.iter().any(|n| n == name))
+            .map(|(i, _)| link_broker(i))
+            .collect()
+    }
+
+    /// Milliseconds between nonce attempts of `node`, inversely proportional to its power.
+    pub fn mining_attempt_ms(&self, node: &NodeSpec) -> u64 {
+        let max_power = self.nodes.iter().map(|n| n.mining_power).max().unwrap_or(1);
+        FASTEST_MINING_ATTEMPT_MS * max_power / node.mining_power
+    }
+}
+
+fn link_broker(index: usize) -> String {
+    format!("link{}-", index)
+}
@@ -0,0 +1,182 @@
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::ledger::Ledger;
+use blockchain_core::rejection::BlockRejection;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::BlockHeight;
+use log::error;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Consensus decision of this node.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    BlockAccepted {
+        digest: BlockDigest,
+        height: BlockHeight,
+        /// Whether this node mined the block
+        mined: bool,
+    },
+    BlockRejected {
+        rejection: BlockRejection,
+    },
+    /// The best chain switched to another branch.
+    Reorg {
+        old_tip: BlockDigest,
+        new_tip: BlockDigest,
+        /// Blocks left the best chain, from the old tip
+        disconnected: Vec<BlockDigest>,
+        /// Blocks joined the best chain, from the fork point
+        connected: Vec<BlockDigest>,
+    },
+    TransactionDropped {
+        txid: BlockDigest,
+        reason: String,
+    },
+}
+
+impl AuditEvent {
+    /// Reorg event if the best chain moved from `old_tip` to a branch which does not contain it.
+    pub fn reorg(ledger: &Ledger, old_tip: &BlockDigest, new_tip: &BlockDigest) -> Option<Self> {
+        let old_chain = ledger.upstream_chain_from(old_tip).collect::<Vec<_>>();
+        let mut connected = vec![];
+        for block in ledger.upstream_chain_from(new_tip) {
+            if block.digest() == old_tip {
+                return None;
+            }
+            if old_chain.iter().any(|b| b.digest() == block.digest()) {
+                let fork = block.digest();
+                let disconnected = old_chain
+                    .iter()
+                    .map(|b| b.digest().clone())
+                    .take_while(|digest| digest != fork)
+                    .collect();
+                connected.reverse();
+                return Some(AuditEvent::Reorg {
+                    old_tip: old_tip.clone(),
+                    new_tip: new_tip.clone(),
+                    disconnected,
+                    connected,
+                });
+            }
+            connected.push(block.digest().clone());
+        }
+        None
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    timestamp: Timestamp,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+#[derive(Debug)]
+struct Output {
+    file: File,
+    written: u64,
+}
+
+/// Append-only JSONL log of consensus decisions.
+/// When the file exceeds `max_bytes`, it is renamed to `<path>.1`, shifting older files,
+/// and only `keep` rotated files are kept.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    output: Mutex<Option<Output>>,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let output = Self::open_output(&path)?;
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            output: Mutex::new(Some(output)),
+        })
+    }
+
+    /// Log which records nothing.
+    pub fn disabled() -> Self {
+        Self {
+            path: PathBuf::new(),
+            max_bytes: 0,
+            keep: 0,
+            output: Mutex::new(None),
+        }
+    }
+
+    /// Append the event. Failures are logged, since consensus must go on without the audit log.
+    pub fn record(&self, event: AuditEvent) {
+        let mut output = self.output.lock().expect("Lock failure");
+        if output.is_none() {
+            return;
+        }
+
+        let record = Record {
+            timestamp: Timestamp::now(),
+            event: &event,
+        };
+        let mut line = serde_json::to_string(&record).expect("Audit event is serializable");
+        line.push('\n');
+
+        if let Err(e) = self.write(&mut output, line.as_bytes()) {
+            error!(
+                "Error during writing audit log {}. {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn write(&self, output: &mut Option<Output>, line: &[u8]) -> std::io::Result<()> {
+        let needs_rotation = output
+            .as_ref()
+            .is_some_and(|o| o.written > 0 && o.written + line.len() as u64 > self.max_bytes);
+        if needs_rotation {
+            // Close the current file before renaming it
+            *output = None;
+            self.rotate()?;
+            *output = Some(Self::open_output(&self.path)?);
+        }
+
+        if let Some(output) = output {
+            output.file.write_all(line)?;
+            output.written += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for index in (1..self.keep).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn open_output(path: &Path) -> std::io::Result<Output> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Output { file, written })
+    }
+}
@@ -0,0 +1,108 @@
+use blockchain_net::bans::{Ban, BanList, MisbehaviorScores};
+use blockchain_net::identity::NodeId;
+use blockchain_net::unix_now;
+use log::{info, warn};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Misbehavior score at which a node is banned.
+const BAN_SCORE: f64 = 100.0;
+
+/// Misbehavior scores halve in this time.
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+
+/// Banned nodes and misbehavior scores of the others.
+/// The ban list is saved to its file on every change, if a file is given.
+pub struct PeerBans {
+    list: BanList,
+    path: Option<String>,
+    scores: MisbehaviorScores,
+    /// Length of bans for misbehavior
+    duration: Duration,
+}
+
+impl PeerBans {
+    /// Load the ban list from `path` if it exists.
+    pub fn open(path: Option<String>, duration: Duration) -> anyhow::Result<Self> {
+        let list = match &path {
+            Some(path) if Path::new(path).exists() => {
+                let list = BanList::load(path)?;
+                info!("Loaded {} bans from {}.", list.bans(unix_now()).len(), path);
+                list
+            }
+            _ => BanList::new(),
+        };
+        Ok(Self {
+            list,
+            path,
+            scores: MisbehaviorScores::new(SCORE_HALF_LIFE),
+            duration,
+        })
+    }
+
+    /// Ban `node` for `duration`, or forever if `None`.
+    pub fn ban(&mut self, node: NodeId, reason: impl Into<String>, duration: Option<Duration>) {
+        let ban = Ban {
+            node,
+            reason: reason.into(),
+            until: duration.map(|d| unix_now().saturating_add(d.as_secs())),
+        };
+        warn!("Ban node {}. {}", ban.node, ban.reason);
+        self.scores.forget(&ban.node);
+        self.list.ban(ban);
+        self.save();
+    }
+
+    /// Whether `node` is banned. Forgets expired bans on the way.
+    pub fn is_banned(&mut self, node: &NodeId) -> bool {
+        let now = unix_now();
+        let expired = self.list.remove_expired(now);
+        for ban in expired.iter() {
+            info!("Ban of node {} expired.", ban.node);
+        }
+        if !expired.is_empty() {
+            self.save();
+        }
+        self.list.is_banned(node, now)
+    }
+
+    /// Add `points` to the misbehavior score of `node`, banning it if the score reaches the limit.
+    /// Returns whether it was banned.
+    pub fn misbehaved(&mut self, node: &NodeId, points: u32, reason: &str) -> bool {
+        let now = Instant::now();
+        let score = self.scores.add(node, points, now);
+        self.scores.remove_below(1.0, now);
+        info!(
+            "Node {} misbehaved: {}. Score: {:.0}/{}",
+            node, reason, score, BAN_SCORE
+        );
+        if score < BAN_SCORE {
+            return false;
+        }
+        let reason = format!("Misbehavior score reached {:.0}. Last: {}", score, reason);
+        self.ban(node.clone(), reason, Some(self.duration));
+        true
+    }
+
+    pub fn unban(&mut self, node: &NodeId) -> Option<Ban> {
+        let ban = self.list.unban(node);
+        if ban.is_some() {
+            info!("Unbanned node {}.", node);
+            self.save();
+        }
+        ban
+    }
+
+    /// Bans in effect.
+    pub fn bans(&self) -> Vec<Ban> {
+        self.list.bans(unix_now())
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            if let Err(e) = self.list.save(path) {
+                warn!("Failed to save ban list to {}. {}", path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::persistent_ledger::PersistentLedger;
+use blockchain_core::snapshot::ChainSnapshot;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{Address, BlockHeight, Coin, Transition, Verified, VerifiedBlock};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How often the best chain is compared with the one last streamed.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Events kept while the sink is unreachable. The oldest ones are dropped beyond this.
+const MAX_PENDING_EVENTS: usize = 100_000;
+
+/// A sink slower than this to accept a write is treated as unreachable.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where chain events are written, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEventSink {
+    /// Socket of `tcp://HOST:PORT`, which is connected again after failures
+    Tcp(String),
+    /// File appended to
+    File(PathBuf),
+}
+
+/// Parse `tcp://HOST:PORT`, or any other string as a file path.
+impl FromStr for ChainEventSink {
+    type Err = ParseChainEventSinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("tcp://") {
+            Some(address) if address.rsplit_once(':').is_some() => {
+                Ok(ChainEventSink::Tcp(address.to_string()))
+            }
+            Some(_) => Err(ParseChainEventSinkError),
+            None if s.is_empty() => Err(ParseChainEventSinkError),
+            None => Ok(ChainEventSink::File(PathBuf::from(s))),
+        }
+    }
+}
+
+impl Display for ChainEventSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainEventSink::Tcp(address) => write!(f, "tcp://{}", address),
+            ChainEventSink::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseChainEventSinkError;
+
+impl Display for ParseChainEventSinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Expected tcp://HOST:PORT or a file path")
+    }
+}
+
+impl std::error::Error for ParseChainEventSinkError {}
+
+/// Coins moved to or from an address.
+#[derive(Debug, Clone, Serialize)]
+pub struct Flow {
+    pub address: Address,
+    pub quantity: Coin,
+}
+
+impl From<&Transition<Verified>> for Flow {
+    fn from(transition: &Transition<Verified>) -> Self {
+        Self {
+            address: transition.receiver().clone(),
+            quantity: transition.quantity(),
+        }
+    }
+}
+
+/// Change of the best chain. A reorganization disconnects blocks from the old tip down to the
+/// fork, then connects blocks from the fork up to the new tip.
+/// Transactions follow their block on connection and precede it on disconnection.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChainEvent {
+    BlockConnected {
+        digest: BlockDigest,
+        height: BlockHeight,
+        previous_digest: BlockDigest,
+        timestamp: Timestamp,
+        transactions: usize,
+    },
+    BlockDisconnected {
+        digest: BlockDigest,
+        height: BlockHeight,
+    },
+    TransactionConnected {
+        txid: BlockDigest,
+        block: BlockDigest,
+        height: BlockHeight,
+        inputs: Vec<Flow>,
+        outputs: Vec<Flow>,
+    },
+    TransactionDisconnected {
+        txid: BlockDigest,
+        block: BlockDigest,
+        height: BlockHeight,
+    },
+}
+
+impl ChainEvent {
+    fn connected(block: &VerifiedBlock) -> impl Iterator<Item = ChainEvent> + '_ {
+        let block_event = ChainEvent::BlockConnected {
+            digest: block.digest().clone(),
+            height: block.height(),
+            previous_digest: block.previous_digest().clone(),
+            timestamp: block.timestamp(),
+            transactions: block.transactions().len(),
+        };
+        let tx_events = block
+            .transactions()
+            .iter()
+            .map(|tx| ChainEvent::TransactionConnected {
+                txid: tx.txid(),
+                block: block.digest().clone(),
+                height: block.height(),
+                inputs: tx.inputs().iter().map(Flow::from).collect(),
+                outputs: tx.outputs().iter().map(Flow::from).collect(),
+            });
+        std::iter::once(block_event).chain(tx_events)
+    }
+
+    fn disconnected(block: &VerifiedBlock) -> impl Iterator<Item = ChainEvent> + '_ {
+        let tx_events =
+            block
+                .transactions()
+                .iter()
+                .rev()
+                .map(|tx| ChainEvent::TransactionDisconnected {
+                    txid: tx.txid(),
+                    block: block.digest().clone(),
+                    height: block.height(),
+                });
+        let block_event = ChainEvent::BlockDisconnected {
+            digest: block.digest().clone(),
+            height: block.height(),
+        };
+        tx_events.chain(std::iter::once(block_event))
+    }
+}
+
+/// Events which move the best chain from `old` to `new`.
+fn chain_events(old: &ChainSnapshot, new: &ChainSnapshot) -> Vec<ChainEvent> {
+    let fork = old
+        .blocks()
+        .zip(new.blocks())
+        .take_while(|(old, new)| old.digest() == new.digest())
+        .count();
+    let disconnected = old
+        .blocks()
+        .skip(fork)
+        .rev()
+        .flat_map(ChainEvent::disconnected);
+    let connected = new.blocks().skip(fork).flat_map(ChainEvent::connected);
+    disconnected.chain(connected).collect()
+}
+
+/// Writes events to the sink, keeping them while it is unreachable.
+struct ChainEventWriter {
+    sink: ChainEventSink,
+    output: Option<Box<dyn Write + Send>>,
+    pending: VecDeque<String>,
+    /// Whether the last write failed, so that an outage is logged once
+    failing: bool,
+}
+
+impl ChainEventWriter {
+    fn new(sink: ChainEventSink) -> Self {
+        Self {
+            sink,
+            output: None,
+            pending: VecDeque::new(),
+            failing: false,
+        }
+    }
+
+    fn push(&mut self, events: Vec<ChainEvent>) {
+        for event in events {
+            let mut line = serde_json::to_string(&event).expect("Chain event is serializable");
+            line.push('\n');
+            self.pending.push_back(line);
+        }
+        let overflow = self.pending.len().saturating_sub(MAX_PENDING_EVENTS);
+        if overflow > 0 {
+            warn!(
+                "Dropped {} chain events which {} did not accept in time.",
+                overflow, self.sink
+            );
+            self.pending.drain(..overflow);
+        }
+    }
+
+    /// Write pending events in order, stopping at the first failure to retry later.
+    fn flush(&mut self) {
+        while let Some(line) = self.pending.pop_front() {
+            if let Err(e) = self.write(&line) {
+                self.output = None;
+                self.pending.push_front(line);
+                if !self.failing {
+                    error!("Error during writing chain events to {}. {}", self.sink, e);
+                    self.failing = true;
+                }
+                return;
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        let output = match &mut self.output {
+            Some(output) => output,
+            None => {
+                let output = self.open()?;
+                info!("Streaming chain events to {}.", self.sink);
+                self.failing = false;
+                self.output.insert(output)
+            }
+        };
+        output.write_all(line.as_bytes())
+    }
+
+    fn open(&self) -> std::io::Result<Box<dyn Write + Send>> {
+        match &self.sink {
+            ChainEventSink::Tcp(address) => {
+                let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "No such host")
+                })?;
+                let stream = TcpStream::connect_timeout(&address, IO_TIMEOUT)?;
+                stream.set_write_timeout(Some(IO_TIMEOUT))?;
+                Ok(Box::new(stream))
+            }
+            ChainEventSink::File(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Ok(Box::new(file))
+            }
+        }
+    }
+}
+
+/// Stream changes of the best chain to `sink`, starting from the current tip.
+/// Runs on a blocking thread, so that a slow sink holds up neither the ledger nor other tasks.
+pub fn spawn_chain_event_streamer(
+    sink: ChainEventSink,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut streamed = ledger.lock().expect("Lock failure").snapshot();
+        let mut writer = ChainEventWriter::new(sink);
+        match streamed.tip() {
+            Some(tip) => info!(
+                "Chain events start after block {} ({}).",
+                tip.height(),
+                tip.digest().fmt_short()
+            ),
+            None => info!("Chain events start from genesis."),
+        }
+
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+
+            let snapshot = ledger.lock().expect("Lock failure").snapshot();
+            if snapshot.tip().map(|b| b.digest()) != streamed.tip().map(|b| b.digest()) {
+                writer.push(chain_events(&streamed, &snapshot));
+                streamed = snapshot;
+            }
+            writer.flush();
+        }
+    })
+}
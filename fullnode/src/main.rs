@@ -1,79 +1,445 @@
 use anyhow::Result;
-use blockchain_core::block::block_coin_generation_rule;
-use blockchain_core::digest::BlockDigest;
+use audit::{AuditEvent, AuditLog};
+use bans::PeerBans;
+use blockchain_core::analysis::{ChainAnalysis, ChainStats, ChainStatsError};
+use blockchain_core::authority::{Authorities, Consensus, SEALED_DIFFICULTY};
+use blockchain_core::checkpoint::AssumeValid;
+use blockchain_core::compact::CompactBlock;
+use blockchain_core::deployment::DeploymentState;
+use blockchain_core::digest::{BlockDigest, PowAlgorithm};
 use blockchain_core::ledger::{Ledger, LedgerError};
-use blockchain_core::{Block, BlockHeight, BlockSource, SecretAddress, VerifiedBlock, Yet};
-use blockchain_core::{Difficulty, Transaction, UnverifiedBlock, Verified};
-use blockchain_net::async_net::{Publisher, Subscriber};
-use blockchain_net::impl_zeromq::{TopicPublisher, TopicSubscriber};
+use blockchain_core::light::{HeaderChain, HeaderChainError};
+use blockchain_core::mempool::Mempool;
+use blockchain_core::persistent_ledger::{PersistentLedger, PersistentLedgerError};
+use blockchain_core::rejection::{BlockRejection, ValidationStage};
+use blockchain_core::search::{SearchError, SearchIndex};
+use blockchain_core::snapshot::ChainSnapshot;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::tracker::{SendTransactionError, TransactionTracker};
+use blockchain_core::utxo_db::{BlockChanges, UtxoDb, UtxoDbConfig, UtxoDbError};
+use blockchain_core::SecretAddress;
+use blockchain_core::Transition;
+use blockchain_core::VerifiedTransaction;
+use blockchain_core::{Address, Block, BlockHeader, BlockHeight, BlockSource, ChainParams};
+use blockchain_core::{
+    Coin, Difficulty, NonceIter, UnverifiedBlock, UnverifiedTransaction, Verified,
+};
+use blockchain_core::{VerifiedBlock, Yet};
+use blockchain_net::async_net::{Publisher, Server, Subscriber};
+use blockchain_net::identity::{NodeId, Signed};
+use blockchain_net::impl_zeromq::{
+    RpcClient, ServiceServer, TopicPublisher, TopicSubscriber, DEFAULT_BROKER,
+};
+use blockchain_net::middleware::{Layers, RateLimit, RequestLog, Scoped, TokenAuth};
+use blockchain_net::propagation::{PropagationStage, PropagationTracker};
+use blockchain_net::service::{
+    QueryAlerts, QueryBalanceHistory, QueryBalances, QueryBans, QueryBlockByHeight,
+    QueryBlockPropagation, QueryBlocksByRange, QueryChainInfo, QueryChainStats, QueryDailyVolume,
+    QueryDeployments, QueryHeaderByHeight, QueryMempoolInfo, QueryMempoolUsage, QueryMerkleProof,
+    QueryRawMempool, QueryRichList, QuerySearch, QuerySyncProgress, QueryTimers, QueryTotalSupply,
+    QueryTransactionStatus, QueryUtxoAges, QueryUtxoByAddress, SendTransaction, SetTimers, Unban,
+    PRIVILEGED,
+};
+use blockchain_net::sync::{
+    best_serving_height, Alert, BlockRetention, ChainInfo, ChainStatus, SyncError, SyncTracker,
+    Timers, MAX_BLOCKS_PER_RANGE,
+};
 use blockchain_net::topic::{
-    CreateTransaction, NotifyBlock, NotifyBlockHeight, RequestUtxoByAddress, RespondUtxoByAddress,
+    CreateTransaction, NotifyBlock, NotifyBlockHeader, NotifyBlockHeight, NotifyBlockRejected,
+    RequestUtxoByAddress, RespondUtxoByAddress,
 };
+use blockchain_net::Service;
+use chain_events::{spawn_chain_event_streamer, ChainEventSink};
 use clap::Parser;
 use log::{error, info, warn};
-use rand::Rng;
+use queue::{DropOldestQueue, PriorityGate};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rpc_auth::load_rpc_auth;
+use seen::SeenCache;
+use selftest::{run_selftest, SelftestTargets};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
+use watchdog::{spawn_watchdog, WatchdogConfig};
+use webhook::{load_webhooks, spawn_webhook_dispatcher};
+
+#[cfg(not(feature = "zeromq"))]
+compile_error!("bcfnode needs a transport. Enable the `zeromq` feature.");
+
+mod audit;
+mod bans;
+mod chain_events;
+mod queue;
+mod rpc_auth;
+mod seen;
+mod selftest;
+mod watchdog;
+mod webhook;
 
 const DIFFICULTY: Difficulty = Difficulty::new(10);
 
+/// A node is considered gone after missing this many height announcements.
+const PEER_TIMEOUT_ANNOUNCEMENTS: u32 = 3;
+
+/// A node announcing its chain status again sooner than this is flooding.
+const MIN_ANNOUNCE_SPACING: Duration = Duration::from_secs(1);
+
+/// Misbehavior score added for each announcement of a flooding node.
+const FLOOD_SCORE: u32 = 10;
+
+/// Time between moves of the UTXO database to the tip of the longest chain.
+const UTXO_DB_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Blocks applied to the UTXO database per round, whose changes are held in memory meanwhile
+const UTXO_DB_SYNC_BLOCKS: usize = 1000;
+
+/// Time between moves of the search index to the tip of the longest chain.
+const SEARCH_INDEX_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time between moves of chain statistics to the tip of the longest chain.
+const CHAIN_STATS_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most holders listed by the QueryRichList service.
+const MAX_RICH_LIST: usize = 1000;
+
+/// Time between checks of deployment states on the longest chain.
+const DEPLOYMENT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Empty answers to a block range request before the download waits for the next announcement.
+/// Requests may reach this node itself or another lagging node instead of the announcing one.
+const RANGE_REQUEST_ATTEMPTS: u32 = 3;
+
+/// Wait before retrying an empty range answer, multiplied by the empty answers so far.
+const RANGE_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Time between headers replayed to a header-only node, so that the proxy is not flooded.
+const HEADER_REPLAY_SPACING: Duration = Duration::from_millis(10);
+
+/// Time between removals of fork branches left behind by the best chain.
+const STALE_BRANCH_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Other nodes by the time and content of their last chain status announcement.
+type Peers = HashMap<NodeId, (Instant, ChainStatus)>;
+
 fn verify_block_after_mining(
     block: Block<Verified, Yet, Yet, Yet, Yet, Yet>,
     ledger: &Ledger,
-) -> Result<VerifiedBlock> {
+) -> Result<VerifiedBlock, BlockRejection> {
+    let (digest, height) = (block.digest().clone(), block.height());
+    let reject = |stage, e: &dyn Display| BlockRejection::new(digest.clone(), height, stage, e);
+
+    let block = block
+        .verify_transaction_relation_with(ledger.params())
+        .map_err(|e| reject(ValidationStage::TransactionRelation, &e))?;
+    let block = match ledger.params().consensus() {
+        Consensus::ProofOfWork => block
+            .verify_difficulty(&DIFFICULTY)
+            .map_err(|e| reject(ValidationStage::Difficulty, &e))?,
+        Consensus::ProofOfAuthority(authorities) => block
+            .verify_authority(&authorities)
+            .map_err(|e| reject(ValidationStage::Authority, &e))?,
+    };
     let block = block
-        .verify_transaction_relation(block_coin_generation_rule)
-        .and_then(|b| b.verify_difficulty(&DIFFICULTY))
-        .and_then(|b| b.verify_digest())?;
-    let block = ledger.verify_block(block)?;
+        .verify_digest_with(ledger.params())
+        .map_err(|e| reject(ValidationStage::Digest, &e))?;
+    let block = ledger
+        .verify_block(block)
+        .map_err(|e| reject(ValidationStage::Ledger, &e))?;
 
     Ok(block)
 }
 
-fn verify_block(block: UnverifiedBlock, ledger: &Ledger) -> Result<VerifiedBlock> {
-    let block = block.verify_transaction_itself()?;
-    let block = verify_block_after_mining(block, ledger)?;
-    Ok(block)
+/// Verify a received block. Signs are skipped for blocks under the assumed-valid checkpoint.
+fn verify_block(
+    block: UnverifiedBlock,
+    ledger: &Ledger,
+    assume_valid: Option<&AssumeValid>,
+) -> Result<VerifiedBlock, BlockRejection> {
+    let (digest, height) = (block.digest().clone(), block.height());
+    let reject = |stage, e: &dyn Display| BlockRejection::new(digest.clone(), height, stage, e);
+
+    let block = match assume_valid {
+        Some(assume_valid) => {
+            assume_valid
+                .check(height, &digest)
+                .map_err(|e| reject(ValidationStage::Checkpoint, &e))?;
+            if assume_valid.covers(height, &digest) {
+                block.verify_transaction_itself_except_signs()
+            } else {
+                block.verify_transaction_itself()
+            }
+        }
+        None => block.verify_transaction_itself(),
+    };
+    let block = block.map_err(|e| reject(ValidationStage::TransactionItself, &e))?;
+    verify_block_after_mining(block, ledger)
 }
 
-fn block_subscription_event(block: UnverifiedBlock, ledger: Arc<Mutex<Ledger>>) -> Result<()> {
+fn block_subscription_event(
+    block: CompactBlock,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    assume_valid: Option<&AssumeValid>,
+) -> Result<(), BlockRejection> {
     let mut ledger = ledger.lock().expect("Lock failure");
-    let block = verify_block(block, &ledger)?;
+    enter_compact_block(&mut ledger, block, assume_valid)
+}
+
+/// Restore inputs of a compact block of another node, then verify and append it to the ledger.
+fn enter_compact_block(
+    ledger: &mut PersistentLedger,
+    block: CompactBlock,
+    assume_valid: Option<&AssumeValid>,
+) -> Result<(), BlockRejection> {
+    let (digest, height) = (block.digest().clone(), block.height());
+    let block = ledger
+        .expand_block(block)
+        .map_err(|e| BlockRejection::new(digest, height, ValidationStage::Ledger, e))?;
+    enter_received_block(ledger, block, assume_valid)
+}
+
+/// Verify a block of another node and append it to the ledger.
+fn enter_received_block(
+    ledger: &mut PersistentLedger,
+    block: UnverifiedBlock,
+    assume_valid: Option<&AssumeValid>,
+) -> Result<(), BlockRejection> {
+    let block = verify_block(block, ledger, assume_valid)?;
+    let (digest, height) = (block.digest().clone(), block.height());
 
     match ledger.entry(block) {
         Ok(_) => Ok(()),
         // These events catch a block published from this node.
         // So ignore block duplication error, which occurs everytime on block publication.
-        Err(LedgerError::DuplicatedBlock) => Ok(()),
-        Err(LedgerError::DuplicatedGenesisBlock) => Ok(()),
-        Err(e) => Err(e.into()),
+        Err(PersistentLedgerError::Ledger(LedgerError::DuplicatedBlock)) => Ok(()),
+        Err(PersistentLedgerError::Ledger(LedgerError::DuplicatedGenesisBlock)) => Ok(()),
+        Err(e) => Err(BlockRejection::new(
+            digest,
+            height,
+            ValidationStage::Entry,
+            e,
+        )),
+    }
+}
+
+/// Pass a block of another node, which the ledger has just accepted, to the webhooks,
+/// the audit log and the sync tracker.
+fn report_received_block(
+    ledger: &Ledger,
+    digest: &BlockDigest,
+    old_tip: Option<BlockDigest>,
+    connected: &UnboundedSender<VerifiedBlock>,
+    audit: &AuditLog,
+    sync: &Mutex<SyncTracker>,
+) {
+    if let Some(block) = ledger.get(digest) {
+        connected.send(block.clone()).ok();
+    }
+    audit_accepted_block(audit, ledger, old_tip, digest, false);
+    sync.lock()
+        .expect("Lock failure")
+        .set_local_height(latest_height(ledger), Instant::now());
+}
+
+fn latest_digest(ledger: &Ledger) -> Option<BlockDigest> {
+    ledger
+        .search_latest_block()
+        .map(|block| block.digest().clone())
+}
+
+fn latest_height(ledger: &Ledger) -> Option<BlockHeight> {
+    ledger.search_latest_block().map(Block::height)
+}
+
+/// Whether the next block on the longest chain may include `transaction` by its lock time.
+fn is_final_for_next_block(ledger: &Ledger, transaction: &VerifiedTransaction) -> bool {
+    let (height, time) = match ledger.search_latest_block() {
+        Some(block) => (
+            block.height().next(),
+            ledger
+                .median_time_past(block.digest())
+                .unwrap_or(block.timestamp()),
+        ),
+        None => (BlockHeight::genesis(), Timestamp::now()),
+    };
+    transaction.is_final(height, time)
+}
+
+/// Whether the status is of a header-only node, which serves no block body, not even of its tip.
+fn is_header_only(status: &ChainStatus) -> bool {
+    status.blocks_only()
+        && match status.height() {
+            Some(height) => status.retention().check(height).is_err(),
+            None => !status.retention().is_archival(),
+        }
+}
+
+/// Highest chain announced by nodes which are still announcing.
+fn best_known_height(peers: &Peers) -> Option<BlockHeight> {
+    peers
+        .values()
+        .filter_map(|(_, status)| status.height())
+        .max()
+}
+
+/// Whether any node takes transactions from this node.
+/// While no node is known, transactions are relayed for whoever listens.
+fn has_transaction_peers(peers: &Peers) -> bool {
+    peers.is_empty() || peers.values().any(|(_, status)| !status.blocks_only())
+}
+
+/// Record the accepted block, and the reorg if the best chain left `old_tip`.
+fn audit_accepted_block(
+    audit: &AuditLog,
+    ledger: &Ledger,
+    old_tip: Option<BlockDigest>,
+    digest: &BlockDigest,
+    mined: bool,
+) {
+    if let Some(block) = ledger.get(digest) {
+        audit.record(AuditEvent::BlockAccepted {
+            digest: digest.clone(),
+            height: block.height(),
+            mined,
+        });
+    }
+
+    if let (Some(old_tip), Some(new_tip)) = (old_tip, latest_digest(ledger)) {
+        if let Some(reorg) = AuditEvent::reorg(ledger, &old_tip, &new_tip) {
+            warn!(
+                "Reorganized the best chain from {} to {}.",
+                old_tip.fmt_short(),
+                new_tip.fmt_short()
+            );
+            audit.record(reorg);
+        }
+    }
+}
+
+fn audit_dropped_transaction(audit: &AuditLog, txid: BlockDigest, reason: impl Display) {
+    audit.record(AuditEvent::TransactionDropped {
+        txid,
+        reason: reason.to_string(),
+    });
+}
+
+/// Compute which blocks this node serves to others.
+/// A pruned node serves only the latest `prune_depth + 1` blocks of its longest chain,
+/// whose tip is at `tip_height`.
+fn block_retention(tip_height: Option<BlockHeight>, prune_depth: Option<u64>) -> BlockRetention {
+    match prune_depth {
+        Some(depth) => {
+            let lowest = tip_height
+                .map(|height| height.saturating_sub(depth))
+                .unwrap_or(BlockHeight::genesis());
+            BlockRetention::Pruned { lowest }
+        }
+        None => BlockRetention::Archival,
     }
 }
 
 fn spawn_transaction_subscriber(
     mut subscriber: TopicSubscriber<CreateTransaction>,
-    incoming_transactions: Arc<Mutex<Vec<Transaction<Verified, Verified>>>>,
+    queue: Arc<DropOldestQueue<UnverifiedTransaction>>,
+    audit: Arc<AuditLog>,
 ) -> JoinHandle<()> {
     tokio::task::spawn(async move {
         loop {
             match subscriber.recv().await {
                 Ok(transaction) => {
                     info!("Received a transaction.");
-                    match transaction.verify() {
-                        Ok(transaction) => {
-                            info!("Verified the received transaction.");
-                            let mut incoming_transactions =
-                                incoming_transactions.lock().expect("Lock failure");
-                            incoming_transactions.push(transaction);
-                            incoming_transactions.sort_by_key(Transaction::timestamp);
+                    // Drop the oldest one rather than stall reception under a burst
+                    if let Some(dropped) = queue.push(transaction) {
+                        warn!(
+                            "Transaction queue is full. Dropped the oldest transaction. Total dropped: {}",
+                            queue.dropped()
+                        );
+                        audit_dropped_transaction(
+                            &audit,
+                            dropped.txid(),
+                            "Transaction queue is full",
+                        );
+                    }
+                }
+                Err(e) => error!("Error during subscribing transaction. {}", e),
+            }
+        }
+    })
+}
+
+/// Verify queued transactions into the mempool, while no received block waits,
+/// so that a flood of transactions does not delay blocks.
+fn spawn_transaction_worker(
+    queue: Arc<DropOldestQueue<UnverifiedTransaction>>,
+    incoming_transactions: Arc<Mutex<Mempool>>,
+    seen: Arc<Mutex<SeenCache>>,
+    tracker: Arc<Mutex<TransactionTracker>>,
+    audit: Arc<AuditLog>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    blocks: Arc<PriorityGate>,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        loop {
+            blocks.wait_idle().await;
+            let transaction = queue.pop().await;
+            // Skip signature verification of duplicates
+            let txid = transaction.txid();
+            if seen.lock().expect("Lock failure").contains(&txid) {
+                info!("Ignore already seen transaction {}.", txid.fmt_short());
+                continue;
+            }
+            match transaction.verify() {
+                Ok(transaction) => {
+                    info!("Verified the received transaction.");
+                    seen.lock().expect("Lock failure").insert(txid.clone());
+                    // Mempool keeps only transactions which the next block can include
+                    if !is_final_for_next_block(&ledger.lock().expect("Lock failure"), &transaction)
+                    {
+                        warn!(
+                            "Deny incoming transaction {} before its lock time.",
+                            txid.fmt_short()
+                        );
+                        audit_dropped_transaction(&audit, txid, "Locked until later block");
+                        continue;
+                    }
+                    let transaction = Arc::new(transaction);
+                    let mut incoming_transactions =
+                        incoming_transactions.lock().expect("Lock failure");
+                    match incoming_transactions.insert(transaction.clone()) {
+                        Ok(evicted) => {
                             info!("Verified transaction was queued to incoming transactions.");
+                            tracker.lock().expect("Lock failure").track(transaction);
+                            if !evicted.is_empty() {
+                                warn!("Evicted {} transactions paying lower fee.", evicted.len());
+                            }
+                            for tx in evicted {
+                                audit_dropped_transaction(
+                                    &audit,
+                                    tx.txid(),
+                                    "Evicted by a transaction paying higher fee rate",
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Deny incoming transaction. {}", e);
+                            audit_dropped_transaction(&audit, txid, e);
                         }
-                        Err(e) => error!("Error during transaction verification. {}", e),
                     }
+                    let usage = incoming_transactions.usage();
+                    info!(
+                        "Mempool usage: {} transactions, {}/{} bytes",
+                        usage.count, usage.bytes, usage.capacity
+                    );
+                }
+                Err(e) => {
+                    error!("Error during transaction verification. {}", e);
+                    audit_dropped_transaction(&audit, txid, e);
                 }
-                Err(e) => error!("Error during subscribing transaction. {}", e),
             }
         }
     })
@@ -81,8 +447,9 @@ fn spawn_transaction_subscriber(
 
 fn spawn_block_subscriber(
     mut subscriber: TopicSubscriber<NotifyBlock>,
-    ledger: Arc<Mutex<Ledger>>,
-    incoming_transactions: Arc<Mutex<Vec<Transaction<Verified, Verified>>>>,
+    sender: Sender<CompactBlock>,
+    gate: Arc<PriorityGate>,
+    propagation: Arc<Mutex<PropagationTracker>>,
 ) -> JoinHandle<()> {
     tokio::task::spawn(async move {
         loop {
@@ -91,15 +458,19 @@ fn spawn_block_subscriber(
                     info!(
                         "Received block. Height: {}, Digest: {}",
                         block.height(),
-                        hex::encode(block.digest())
+                        block.digest().fmt_short()
                     );
-                    match block_subscription_event(block, ledger.clone()) {
-                        Ok(_) => {
-                            // Clear incoming transaction, since they are verified and added to new block
-                            incoming_transactions.lock().expect("Lock failure").clear();
-                            info!("Successfully append the received block to ledger")
-                        }
-                        Err(e) => warn!("Deny incoming block. {}", e),
+                    propagation.lock().expect("Lock failure").record(
+                        block.digest(),
+                        PropagationStage::Received,
+                        Instant::now(),
+                    );
+                    // Hold transaction workers back until the worker handles the block
+                    gate.enter();
+                    // Never drop blocks. Wait for the worker if the queue is full.
+                    if sender.send(block).await.is_err() {
+                        error!("Block worker finished. Stop subscribing blocks.");
+                        break;
                     }
                 }
                 Err(e) => error!("Error during subscribing block. {}", e),
@@ -108,95 +479,446 @@ fn spawn_block_subscriber(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
+fn spawn_block_worker(
+    mut receiver: Receiver<CompactBlock>,
+    mut rejection_publisher: TopicPublisher<NotifyBlockRejected>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    incoming_transactions: Arc<Mutex<Mempool>>,
+    seen: Arc<Mutex<SeenCache>>,
+    rejections: Arc<Mutex<HashMap<ValidationStage, u64>>>,
+    connected: UnboundedSender<VerifiedBlock>,
+    audit: Arc<AuditLog>,
+    sync: Arc<Mutex<SyncTracker>>,
+    assume_valid: Option<AssumeValid>,
+    gate: Arc<PriorityGate>,
+    propagation: Arc<Mutex<PropagationTracker>>,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        while let Some(block) = receiver.recv().await {
+            let _handling = gate.handle();
+            // Blocks published by this node or relayed twice need no verification.
+            // Digest is not verified yet, but only verified digests are in the cache.
+            let digest = block.digest().clone();
+            if seen.lock().expect("Lock failure").contains(&digest) {
+                info!("Ignore already seen block {}.", digest.fmt_short());
+                continue;
+            }
+            let old_tip = latest_digest(&ledger.lock().expect("Lock failure"));
+            match block_subscription_event(block, ledger.clone(), assume_valid.as_ref()) {
+                Ok(_) => {
+                    propagation.lock().expect("Lock failure").record(
+                        &digest,
+                        PropagationStage::Validated,
+                        Instant::now(),
+                    );
+                    report_received_block(
+                        &ledger.lock().expect("Lock failure"),
+                        &digest,
+                        old_tip,
+                        &connected,
+                        &audit,
+                        &sync,
+                    );
+                    seen.lock().expect("Lock failure").insert(digest);
+                    // Clear incoming transaction, since they are verified and added to new block
+                    incoming_transactions.lock().expect("Lock failure").clear();
+                    info!("Successfully append the received block to ledger")
+                }
+                Err(rejection) => {
+                    warn!("Deny incoming block. {}", rejection);
+                    *rejections
+                        .lock()
+                        .expect("Lock failure")
+                        .entry(rejection.stage())
+                        .or_default() += 1;
+                    audit.record(AuditEvent::BlockRejected {
+                        rejection: rejection.clone(),
+                    });
+                    if let Err(e) = rejection_publisher.publish(&rejection).await {
+                        error!("Error during publishing block rejection. {}", e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Periodically report how many received items wait for workers, how many blocks were denied,
+/// and how long blocks took to pass this node.
+fn spawn_queue_monitor(
+    transaction_queue: Arc<DropOldestQueue<UnverifiedTransaction>>,
+    block_sender: Sender<CompactBlock>,
+    rejections: Arc<Mutex<HashMap<ValidationStage, u64>>>,
+    propagation: Arc<Mutex<PropagationTracker>>,
+) -> JoinHandle<()> {
+    tokio::task::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            info!(
+                "Queue depth: transactions {}/{} (dropped {}), blocks {}/{}",
+                transaction_queue.depth(),
+                transaction_queue.capacity(),
+                transaction_queue.dropped(),
+                block_sender.max_capacity() - block_sender.capacity(),
+                block_sender.max_capacity()
+            );
+
+            let rejections = rejections.lock().expect("Lock failure");
+            if !rejections.is_empty() {
+                let counts = rejections
+                    .iter()
+                    .map(|(stage, count)| format!("{} {}", stage, count))
+                    .collect::<Vec<_>>();
+                info!("Denied blocks by stage: {}", counts.join(", "));
+            }
+
+            let propagation = propagation.lock().expect("Lock failure").report();
+            if propagation.blocks > 0 {
+                info!("Block propagation: {}", propagation);
+            }
+        }
+    })
+}
+
 fn spawn_block_height_publisher(
     mut height_publisher: TopicPublisher<NotifyBlockHeight>,
-    ledger: Arc<Mutex<Ledger>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    prune_depth: Option<u64>,
+    min_fee_rate: u64,
+    blocks_only: bool,
+    node_key: SecretAddress,
+    timers: Arc<Mutex<Timers>>,
 ) -> JoinHandle<()> {
     tokio::task::spawn(async move {
         loop {
-            let height = ledger
-                .lock()
-                .expect("Lock failure")
-                .search_latest_block()
-                .map(Block::height);
+            let status = {
+                let ledger = ledger.lock().expect("Lock failure");
+                let height = ledger.search_latest_block().map(Block::height);
+                ChainStatus::new(height, block_retention(height, prune_depth))
+                    .with_min_fee_rate(min_fee_rate)
+                    .with_blocks_only(blocks_only)
+            };
 
-            match height {
+            match status.height() {
                 Some(height) => info!("Publishing local chain height: {:?}...", height),
                 None => info!("Publishing local chain height: None..."),
             }
 
-            match height_publisher.publish(&height).await {
+            match height_publisher
+                .publish(&Signed::new(&node_key, status))
+                .await
+            {
                 Ok(()) => {}
                 Err(e) => error!("Error during publishing local chain height: {}", e),
             }
 
-            tokio::time::sleep(Duration::from_secs(60)).await;
+            let interval = timers.lock().expect("Lock failure").height_announce;
+            tokio::time::sleep(interval).await;
         }
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_block_height_subscriber(
     mut height_subscriber: TopicSubscriber<NotifyBlockHeight>,
-    publish_sender: Sender<VerifiedBlock>,
-    ledger: Arc<Mutex<Ledger>>,
+    header_replay_sender: Sender<BlockHeight>,
+    download_sender: Sender<BlockHeight>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    prune_depth: Option<u64>,
+    node_id: NodeId,
+    bans: Arc<Mutex<PeerBans>>,
+    peers: Arc<Mutex<Peers>>,
+    sync: Arc<Mutex<SyncTracker>>,
 ) -> JoinHandle<()> {
     tokio::task::spawn(async move {
         loop {
             match height_subscriber.recv().await {
-                Ok(other_node_height) => {
+                Ok(other_status) => {
+                    if !other_status.verify() {
+                        warn!(
+                            "Ignore chain status with invalid sign of node {}.",
+                            other_status.node()
+                        );
+                        continue;
+                    }
+                    // Own status comes back through the proxy
+                    if other_status.node() == &node_id {
+                        continue;
+                    }
+                    let node = other_status.node();
+                    if bans.lock().expect("Lock failure").is_banned(node) {
+                        info!("Ignore chain status of banned node {}.", node);
+                        continue;
+                    }
+                    {
+                        let mut peers = peers.lock().expect("Lock failure");
+                        let now = Instant::now();
+                        let last_seen = peers.insert(node.clone(), (now, *other_status.message()));
+                        match last_seen {
+                            None if other_status.message().blocks_only() => {
+                                info!("Found node {}, which relays no transaction.", node)
+                            }
+                            None => info!("Found node {}.", node),
+                            Some((last_seen, _)) if now - last_seen < MIN_ANNOUNCE_SPACING => {
+                                let banned = bans.lock().expect("Lock failure").misbehaved(
+                                    node,
+                                    FLOOD_SCORE,
+                                    "Announces chain status too often",
+                                );
+                                if banned {
+                                    peers.remove(node);
+                                }
+                            }
+                            Some(_) => {}
+                        }
+                        sync.lock()
+                            .expect("Lock failure")
+                            .set_best_known_height(best_known_height(&peers), now);
+                    }
+                    let other_status = other_status.into_message();
+
                     // Longest chain's height
-                    let local_block_height =
-                        match ledger.lock().expect("Lock failure").search_latest_block() {
-                            Some(block) => block.height(),
-                            None => continue,
-                        };
-                    // If this ledger has longer chain than other,
-                    // publish the longest chain of local ledger
-                    match other_node_height {
-                        Some(other) if other >= local_block_height => continue,
-                        Some(_) => {}
-                        None => {}
+                    let (local_block_height, retention) = {
+                        let ledger = ledger.lock().expect("Lock failure");
+                        let height = latest_height(&ledger);
+                        (height, block_retention(height, prune_depth))
+                    };
+                    // If other has longer chain than this ledger, pull the missing blocks
+                    let next_height = local_block_height
+                        .map(BlockHeight::next)
+                        .unwrap_or(BlockHeight::genesis());
+                    match (other_status.height(), local_block_height) {
+                        (Some(other), local) if Some(other) > local => {
+                            // A pruned node cannot fill the gap below its lowest block,
+                            // so pull up to the longest chain of the nodes which can
+                            let target = match other_status.retention().check(next_height) {
+                                Ok(()) => Some(other),
+                                Err(e) => {
+                                    let peers = peers.lock().expect("Lock failure");
+                                    let archival = best_serving_height(
+                                        peers.values().map(|(_, status)| status),
+                                        next_height,
+                                    )
+                                    .filter(|height| Some(*height) > local);
+                                    match archival {
+                                        Some(height) => info!("Another node has longer chain but cannot serve all missing blocks. Pulling blocks up to {} served by archival nodes. {}", height, e),
+                                        None => info!("Another node has longer chain but cannot serve all missing blocks. Waiting for archival nodes. {}", e),
+                                    }
+                                    archival
+                                }
+                            };
+                            // Dropped while a download waits. Later announcements bring it again.
+                            if let Some(target) = target {
+                                download_sender.try_send(target).ok();
+                            }
+                            continue;
+                        }
+                        // Nothing to send from an empty ledger
+                        (_, None) => continue,
+                        (other, local) if other == local => continue,
+                        _ => {}
                     }
 
-                    info!("Another node has shorter chain than this node's. Publishing the longest chain of this node...");
+                    // Full nodes pull the blocks they miss, but header-only nodes have nothing
+                    // to pull from, so they are sent the headers
+                    if !is_header_only(&other_status) {
+                        continue;
+                    }
+                    // Leave the sync to archival nodes if this node has pruned required blocks
+                    let first_required_height = other_status
+                        .height()
+                        .map(BlockHeight::next)
+                        .unwrap_or(BlockHeight::genesis());
+                    if let Err(e) = retention.check(first_required_height) {
+                        info!("A header-only node has shorter chain than this node's, but this node declines the sync. {}", e);
+                        continue;
+                    }
 
-                    let mut current_height = BlockHeight::genesis();
-                    loop {
-                        // Get block at current target height
-                        let block = ledger
-                            .lock()
-                            .expect("Lock failure")
-                            .search_latest_chain()
-                            .find(|block| block.height() == current_height)
-                            .cloned();
-                        // Publish
-                        match block {
-                            Some(block) => match publish_sender.send(block).await {
-                                Ok(_) => info!("Published block {}", current_height),
-                                Err(e) => error!("Error during publishing block: {}", e),
-                            },
-                            None => break,
+                    info!("A header-only node has shorter chain than this node's. Publishing the headers it misses...");
+                    // Dropped while a replay runs. Later announcements bring it again.
+                    header_replay_sender.try_send(first_required_height).ok();
+                }
+                Err(e) => error!("Error during subscribing block height. {}", e),
+            }
+        }
+    })
+}
+
+/// Publish headers of the longest chain from each requested height to the tip, for header-only nodes.
+/// Runs apart from the height subscriber, so that announcements are handled during a replay.
+fn spawn_header_replayer(
+    mut header_publisher: TopicPublisher<NotifyBlockHeader>,
+    mut requests: Receiver<BlockHeight>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(first) = requests.recv().await {
+            let snapshot = ledger.lock().expect("Lock failure").snapshot();
+            let tip = match snapshot.height() {
+                Some(tip) => tip,
+                None => continue,
+            };
+            let blocks = first
+                .to_inclusive(tip)
+                .map_while(|height| snapshot.block_at(height));
+            for block in blocks {
+                if let Err(e) = header_publisher.publish(block.header()).await {
+                    error!("Error during publishing block header: {}", e);
+                }
+                tokio::time::sleep(HEADER_REPLAY_SPACING).await;
+            }
+        }
+    })
+}
+
+/// Pull blocks from other nodes up to the heights they announce, a range at a time.
+/// If the blocks fork below the local tip, earlier ranges are pulled until they connect.
+#[allow(clippy::too_many_arguments)]
+fn spawn_block_downloader(
+    mut rpc: RpcClient,
+    mut targets: Receiver<BlockHeight>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    incoming_transactions: Arc<Mutex<Mempool>>,
+    seen: Arc<Mutex<SeenCache>>,
+    connected: UnboundedSender<VerifiedBlock>,
+    audit: Arc<AuditLog>,
+    sync: Arc<Mutex<SyncTracker>>,
+    assume_valid: Option<AssumeValid>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(target) = targets.recv().await {
+            let mut first = latest_height(&ledger.lock().expect("Lock failure"))
+                .map(BlockHeight::next)
+                .unwrap_or(BlockHeight::genesis());
+            let mut empty_answers = 0;
+            'download: while first <= target {
+                let last = first
+                    .checked_add(MAX_BLOCKS_PER_RANGE - 1)
+                    .map_or(target, |last| last.min(target));
+                let blocks = match rpc.blocks_by_range(&(first, last)).await {
+                    Ok(blocks) => blocks,
+                    Err(e) => {
+                        error!(
+                            "Error during requesting blocks {} to {}. {}",
+                            first, last, e
+                        );
+                        break;
+                    }
+                };
+                let parent = match blocks.first() {
+                    Some(block) => block.previous_digest().clone(),
+                    None => {
+                        empty_answers += 1;
+                        if empty_answers >= RANGE_REQUEST_ATTEMPTS {
+                            info!(
+                                "No node served blocks from {}. Waiting for the next announcement.",
+                                first
+                            );
+                            break;
                         }
-                        current_height = current_height.next();
-                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        tokio::time::sleep(RANGE_RETRY_BACKOFF * empty_answers).await;
+                        continue;
                     }
+                };
+                empty_answers = 0;
+                let forked = !first.is_genesis()
+                    && ledger.lock().expect("Lock failure").get(&parent).is_none();
+                if forked {
+                    info!(
+                        "Blocks from {} fork below the local tip. Pulling earlier blocks...",
+                        first
+                    );
+                    first = first.saturating_sub(MAX_BLOCKS_PER_RANGE);
+                    continue;
                 }
-                Err(e) => error!("Error during subscribing block height. {}", e),
+
+                for block in blocks {
+                    let (digest, height) = (block.digest().clone(), block.height());
+                    if height != first {
+                        warn!(
+                            "Node served block {} for height {}. Stop the download.",
+                            height, first
+                        );
+                        break 'download;
+                    }
+                    let mut ledger = ledger.lock().expect("Lock failure");
+                    if ledger.get(&digest).is_none() {
+                        let old_tip = latest_digest(&ledger);
+                        if let Err(rejection) =
+                            enter_compact_block(&mut ledger, block, assume_valid.as_ref())
+                        {
+                            warn!("Deny downloaded block. {}", rejection);
+                            audit.record(AuditEvent::BlockRejected { rejection });
+                            break 'download;
+                        }
+                        report_received_block(&ledger, &digest, old_tip, &connected, &audit, &sync);
+                        seen.lock().expect("Lock failure").insert(digest);
+                    }
+                    first = height.next();
+                }
+                info!(
+                    "Downloaded blocks up to {}.",
+                    first.previous().unwrap_or(first)
+                );
+                // Clear incoming transaction, since they may be included in the downloaded blocks
+                incoming_transactions.lock().expect("Lock failure").clear();
             }
         }
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_mining_join_handle(
-    incoming_transactions: Arc<Mutex<Vec<Transaction<Verified, Verified>>>>,
+    incoming_transactions: Arc<Mutex<Mempool>>,
     publish_sender: Sender<VerifiedBlock>,
-    ledger: Arc<Mutex<Ledger>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
     secret_address: SecretAddress,
     mine_genesis_block: bool,
+    seen: Arc<Mutex<SeenCache>>,
+    connected: UnboundedSender<VerifiedBlock>,
+    audit: Arc<AuditLog>,
+    timers: Arc<Mutex<Timers>>,
+    sync: Arc<Mutex<SyncTracker>>,
+    mining_attempt: Duration,
+    propagation: Arc<Mutex<PropagationTracker>>,
+    mut nonces: NonceIter<StdRng>,
 ) -> JoinHandle<()> {
     tokio::task::spawn(async move {
+        let mut was_syncing = false;
         loop {
-            let transactions = incoming_transactions.lock().expect("Lock failure").to_vec();
+            // Blocks mined on a stale tip would be orphaned, so catch up first
+            let syncing = sync.lock().expect("Lock failure").is_syncing();
+            if syncing != was_syncing {
+                if syncing {
+                    warn!("This node is far behind other nodes. Suspend mining and transaction relay until synced.");
+                } else {
+                    info!("Initial block download finished. Resume mining.");
+                }
+                was_syncing = syncing;
+            }
+            if syncing {
+                let interval = timers.lock().expect("Lock failure").sync_retry;
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+
+            let transactions = incoming_transactions
+                .lock()
+                .expect("Lock failure")
+                .snapshot();
+            let transactions = {
+                let ledger = ledger.lock().expect("Lock failure");
+                // Transactions breaking a deployed rule wait in the mempool, which old wallets may still fill
+                let rules = ledger.active_rules(ledger.search_latest_block().map(Block::digest));
+                transactions
+                    .into_iter()
+                    .filter(|tx| {
+                        is_final_for_next_block(&ledger, tx)
+                            && rules.iter().all(|rule| rule.allows(tx.version()))
+                    })
+                    .collect::<Vec<_>>()
+            };
             let (next_height, previous_digest) =
                 match ledger.lock().expect("Lock failure").search_latest_block() {
                     Some(block) => (block.height().next(), block.digest().clone()),
@@ -206,28 +928,68 @@ fn spawn_mining_join_handle(
             // Check whether mine genesis block
             if next_height == BlockHeight::genesis() && !mine_genesis_block {
                 warn!("Mining genesis block is disabled. Wait for genesis block from other nodes.");
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                let interval = timers.lock().expect("Lock failure").sync_retry;
+                tokio::time::sleep(interval).await;
                 continue;
             }
 
             if next_height > BlockHeight::genesis() && transactions.is_empty() {
                 warn!("No transaction come yet. Wait for transactions...");
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                let interval = timers.lock().expect("Lock failure").mining_idle;
+                tokio::time::sleep(interval).await;
                 continue;
             }
 
+            let params = *ledger.lock().expect("Lock failure").params();
+            let difficulty = match params.consensus() {
+                Consensus::ProofOfWork => DIFFICULTY.clone(),
+                Consensus::ProofOfAuthority(authorities) => {
+                    let authority = authorities.in_turn(next_height);
+                    if authority != &secret_address.to_public_address() {
+                        info!(
+                            "Waiting for authority {} to seal block {}...",
+                            authority, next_height
+                        );
+                        let interval = timers.lock().expect("Lock failure").mining_idle;
+                        tokio::time::sleep(interval).await;
+                        continue;
+                    }
+                    SEALED_DIFFICULTY
+                }
+            };
             let block_src = BlockSource::new(
                 next_height,
                 transactions,
-                previous_digest,
-                DIFFICULTY.clone(),
-                rand::thread_rng().gen(),
+                previous_digest.clone(),
+                difficulty,
+                0,
                 &secret_address,
-                blockchain_core::block::block_coin_generation_rule,
-            );
+                params.generation_rule(),
+            )
+            .map(|source| source.with_pow_algorithm(params.pow_algorithm()));
+
+            if let Ok(mut block_src) = block_src {
+                // Commit to the resulting UTXO set, so that fast-sync clients can verify snapshots
+                let previous = (!next_height.is_genesis()).then_some(&previous_digest);
+                let (commitment, signals) = {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    (
+                        ledger.utxo_commitment_after(previous, block_src.transactions()),
+                        ledger.signals_for_next(previous),
+                    )
+                };
+                if let Some(commitment) = commitment {
+                    block_src.commit_utxos(commitment);
+                }
+                // This node follows every deployment it knows, so it is ready for all started ones
+                block_src.signal(signals);
 
-            if let Ok(block_src) = block_src {
-                if let Ok(block) = block_src.try_into_block() {
+                let block = match params.consensus() {
+                    // One nonce per attempt, so that --mining-attempt-ms bounds hash power
+                    Consensus::ProofOfWork => block_src.search_nonce(nonces.by_ref().take(1)).ok(),
+                    Consensus::ProofOfAuthority(_) => Some(block_src.seal(&secret_address)),
+                };
+                if let Some(block) = block {
                     let res = {
                         let ledger = ledger.lock().expect("Lock failure");
                         verify_block_after_mining(block, &ledger)
@@ -237,7 +999,17 @@ fn spawn_mining_join_handle(
                             info!(
                                 "Found new block. Height: {}, Digest: {}",
                                 block.height(),
-                                hex::encode(block.digest())
+                                block.digest().fmt_short()
+                            );
+
+                            // This block comes back from the proxy, which needs no verification
+                            seen.lock()
+                                .expect("Lock failure")
+                                .insert(block.digest().clone());
+                            propagation.lock().expect("Lock failure").record(
+                                block.digest(),
+                                PropagationStage::Validated,
+                                Instant::now(),
                             );
 
                             // Publish found block
@@ -251,8 +1023,22 @@ fn spawn_mining_join_handle(
 
                             // Append new block to ledger
                             let mut ledger = ledger.lock().expect("Lock failure");
+                            let old_tip = latest_digest(&ledger);
                             match ledger.entry(block.clone()) {
-                                Ok(_) => info!("Successfully appended new block."),
+                                Ok(_) => {
+                                    info!("Successfully appended new block.");
+                                    audit_accepted_block(
+                                        &audit,
+                                        &ledger,
+                                        old_tip,
+                                        block.digest(),
+                                        true,
+                                    );
+                                    connected.send(block).ok();
+                                    sync.lock()
+                                        .expect("Lock failure")
+                                        .set_local_height(latest_height(&ledger), Instant::now());
+                                }
                                 Err(e) => error!("Error during adding new block. {}", e),
                             }
                         }
@@ -261,27 +1047,57 @@ fn spawn_mining_join_handle(
                             // which may prevent next verification process.
                             warn!("Block verification failed: {}", e);
                             warn!("Clear incoming transactions.");
-                            incoming_transactions.lock().expect("Lock failure").clear();
+                            let mut incoming_transactions =
+                                incoming_transactions.lock().expect("Lock failure");
+                            for tx in incoming_transactions.transactions() {
+                                audit_dropped_transaction(
+                                    &audit,
+                                    tx.txid(),
+                                    "Mined block was denied",
+                                );
+                            }
+                            incoming_transactions.clear();
+                            audit.record(AuditEvent::BlockRejected { rejection: e });
                         }
                     }
                 }
             }
 
             // Wait next mining
-            tokio::time::sleep(Duration::from_millis(10)).await;
+            tokio::time::sleep(mining_attempt).await;
         }
     })
 }
 
 fn spawn_block_publisher(
     mut publisher: TopicPublisher<NotifyBlock>,
+    mut header_publisher: TopicPublisher<NotifyBlockHeader>,
     mut receiver: Receiver<VerifiedBlock>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    propagation: Arc<Mutex<PropagationTracker>>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         while let Some(block) = receiver.recv().await {
-            match publisher.publish(&block).await {
+            // Peers restore inputs from their own chain, so relay outpoints instead
+            let compact = ledger.lock().expect("Lock failure").compact_block(&block);
+            match compact {
+                Some(compact) => match publisher.publish(&compact).await {
+                    Ok(()) => propagation.lock().expect("Lock failure").record(
+                        block.digest(),
+                        PropagationStage::Relayed,
+                        Instant::now(),
+                    ),
+                    Err(e) => error!("Error during publishing block: {}", e),
+                },
+                None => error!(
+                    "Block {} spends outputs not in its chain. Skip publishing it.",
+                    block.digest().fmt_short()
+                ),
+            }
+            // For header-only nodes
+            match header_publisher.publish(block.header()).await {
                 Ok(()) => {}
-                Err(e) => error!("Error during publishing block: {}", e),
+                Err(e) => error!("Error during publishing block header: {}", e),
             }
         }
         warn!("Block publisher thread finished. Inner block publication functionality may have finished");
@@ -291,7 +1107,8 @@ fn spawn_block_publisher(
 fn spawn_utxo_pubsub(
     mut publisher: TopicPublisher<RespondUtxoByAddress>,
     mut subscriber: TopicSubscriber<RequestUtxoByAddress>,
-    ledger: Arc<Mutex<Ledger>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    utxo_db: Option<Arc<Mutex<UtxoDb>>>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         loop {
@@ -303,14 +1120,8 @@ fn spawn_utxo_pubsub(
                 }
             };
 
-            // List UTXO of requested address in the longest chain
-            let utxos = {
-                let ledger = ledger.lock().expect("Lock failure");
-                match ledger.search_latest_block() {
-                    Some(latest_block) => ledger.build_utxos(latest_block.digest(), &address),
-                    None => vec![],
-                }
-            };
+            let snapshot = ledger.lock().expect("Lock failure").snapshot();
+            let utxos = latest_utxos(&snapshot, utxo_db.as_deref(), &address);
 
             match publisher.publish(&utxos).await {
                 Ok(_) => info!("Publish {} UTXO of {}.", utxos.len(), address),
@@ -320,78 +1131,2181 @@ fn spawn_utxo_pubsub(
     })
 }
 
-#[derive(Debug, Parser)]
-struct FullnodeArgs {
-    /// Address file path
-    #[clap(long)]
-    address: String,
+fn spawn_utxo_server(
+    mut server: ServiceServer<QueryUtxoByAddress>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    utxo_db: Option<Arc<Mutex<UtxoDb>>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|address| {
+                    let snapshot = ledger.lock().expect("Lock failure").snapshot();
+                    let utxos = latest_utxos(&snapshot, utxo_db.as_deref(), &address);
+                    info!("Serve {} UTXO of {}.", utxos.len(), address);
+                    Some(utxos.iter().map(Transition::to_unverified).collect())
+                })
+                .await;
 
-    /// Enable when mine genesis block. Otherwise, download genesis block from other nodes.
-    #[clap(long)]
-    mine_genesis_block: bool,
+            if let Err(e) = res {
+                error!("Error during serving UTXO: {}", e);
+            }
+        }
+    })
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+fn spawn_balances_server(
+    mut server: ServiceServer<QueryBalances>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    utxo_db: Option<Arc<Mutex<UtxoDb>>>,
+    mempool: Arc<Mutex<Mempool>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|addresses| {
+                    let snapshot = ledger.lock().expect("Lock failure").snapshot();
+                    let confirmed = addresses
+                        .into_iter()
+                        .map(|address| {
+                            let utxos = latest_utxos(&snapshot, utxo_db.as_deref(), &address);
+                            let balance = utxos.iter().map(Transition::quantity).sum::<Coin>();
+                            (address, balance)
+                        })
+                        .collect::<Vec<_>>();
+                    let mempool = mempool.lock().expect("Lock failure");
+                    let balances = confirmed
+                        .into_iter()
+                        .map(|(address, confirmed)| {
+                            let pending = mempool.pending_balance(&address, confirmed);
+                            (address, confirmed, pending)
+                        })
+                        .collect::<Vec<_>>();
+                    info!("Serve balances of {} addresses.", balances.len());
+                    Some(balances)
+                })
+                .await;
 
-    let arg = FullnodeArgs::parse();
+            if let Err(e) = res {
+                error!("Error during serving balances: {}", e);
+            }
+        }
+    })
+}
 
-    info!("Initializing blockchain full node...");
+/// UTXO of `address` in the longest chain, read from the UTXO database if it is at the tip.
+fn latest_utxos(
+    snapshot: &ChainSnapshot,
+    utxo_db: Option<&Mutex<UtxoDb>>,
+    address: &Address,
+) -> Vec<Transition<Verified>> {
+    let latest_block = match snapshot.tip() {
+        Some(latest_block) => latest_block,
+        None => return vec![],
+    };
+    if let Some(utxo_db) = utxo_db {
+        let utxo_db = utxo_db.lock().expect("Lock failure");
+        if utxo_db.tip().map(|(_, digest)| digest) == Some(latest_block.digest()) {
+            let utxos = utxo_db.utxos_of(address).map(|utxos| {
+                utxos
+                    .into_iter()
+                    .map(Transition::verify)
+                    .collect::<Result<Vec<_>, _>>()
+            });
+            match utxos {
+                Ok(Ok(utxos)) => return utxos,
+                Ok(Err(e)) => error!("UTXO database holds an invalid UTXO. {}", e),
+                Err(e) => error!("Error during reading UTXO database. {}", e),
+            }
+        }
+    }
+    snapshot.utxos_of(address)
+}
 
-    let secret_address = bcaddr::read_address(&arg.address)?;
-    info!("Loaded self address from {}.", &arg.address);
+/// Blocks to disconnect from `from` down to the fork, and to connect up to the longest chain.
+/// `None` while `from` is not in the ledger, such as during initial download.
+fn path_to_best_tip<'a>(
+    ledger: &'a Ledger,
+    from: Option<&(BlockHeight, BlockDigest)>,
+) -> Option<(Vec<&'a VerifiedBlock>, Vec<&'a VerifiedBlock>)> {
+    let latest_block = ledger.search_latest_block()?;
+    ledger.fork_path(from.map(|(_, digest)| digest), latest_block.digest())
+}
 
-    let incoming_transactions = Arc::new(Mutex::new(vec![]));
-    let ledger = Arc::new(Mutex::new(Ledger::new()));
-    info!("Spawning connection functionality...");
+/// Change to the UTXO database, resolved while the ledger is locked and applied after.
+enum UtxoDbStep {
+    Disconnect(BlockChanges),
+    Connect(BlockChanges),
+    /// Tip of the database was pruned from the ledger, so the database starts over from genesis
+    Clear,
+}
 
-    let transaction_subscriber = TopicSubscriber::<CreateTransaction>::connect().await?;
-    let block_subscriber = TopicSubscriber::<NotifyBlock>::connect().await?;
-    let block_publisher = TopicPublisher::<NotifyBlock>::connect().await?;
-    let block_height_publisher = TopicPublisher::<NotifyBlockHeight>::connect().await?;
-    let block_height_subscriber = TopicSubscriber::<NotifyBlockHeight>::connect().await?;
-    let utxo_publisher = TopicPublisher::<RespondUtxoByAddress>::connect().await?;
-    let utxo_subscriber = TopicSubscriber::<RequestUtxoByAddress>::connect().await?;
+/// Steps which move the UTXO database from `tip` toward the longest chain,
+/// at most `UTXO_DB_SYNC_BLOCKS` blocks of them.
+fn utxo_db_steps(
+    ledger: &Ledger,
+    tip: Option<&(BlockHeight, BlockDigest)>,
+) -> Result<Vec<UtxoDbStep>, UtxoDbError> {
+    let (disconnected, connected) = match path_to_best_tip(ledger, tip) {
+        Some(path) => path,
+        // A tip missing at or below the best height is on a removed branch.
+        // Above it, the ledger may still be downloading the chain of the tip.
+        None => {
+            let pruned = match (tip, ledger.search_latest_block()) {
+                (Some((height, digest)), Some(best)) => {
+                    ledger.get(digest).is_none() && *height <= best.height()
+                }
+                _ => false,
+            };
+            return Ok(if pruned {
+                vec![UtxoDbStep::Clear]
+            } else {
+                vec![]
+            });
+        }
+    };
+    let disconnected = disconnected
+        .into_iter()
+        .map(|block| Ok(UtxoDbStep::Disconnect(BlockChanges::new(block, ledger)?)));
+    let connected = connected
+        .into_iter()
+        .map(|block| Ok(UtxoDbStep::Connect(BlockChanges::new(block, ledger)?)));
+    disconnected
+        .chain(connected)
+        .take(UTXO_DB_SYNC_BLOCKS)
+        .collect()
+}
 
-    let (block_publish_sender, block_publish_receiver) = tokio::sync::mpsc::channel(10);
+/// Apply `steps` to the UTXO database, and write it when due.
+fn apply_utxo_db_steps(utxo_db: &mut UtxoDb, steps: Vec<UtxoDbStep>) -> Result<(), UtxoDbError> {
+    for step in steps {
+        match step {
+            UtxoDbStep::Disconnect(changes) => utxo_db.disconnect(changes)?,
+            UtxoDbStep::Connect(changes) => utxo_db.connect(changes)?,
+            UtxoDbStep::Clear => {
+                warn!("Tip of the UTXO database was pruned. Rebuilding it from genesis...");
+                utxo_db.clear()?;
+            }
+        }
+    }
+    utxo_db.flush_if_due()
+}
 
-    info!("Spawning threads...");
+/// Move the UTXO database to the tip of the longest chain.
+/// Runs on a blocking thread, and holds the ledger only to resolve changes, not during disk I/O.
+fn spawn_utxo_db_sync(
+    utxo_db: Arc<Mutex<UtxoDb>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut catching_up = false;
+        loop {
+            if !catching_up {
+                std::thread::sleep(UTXO_DB_SYNC_INTERVAL);
+            }
 
-    let transaction_subsctiber_join_handle =
-        spawn_transaction_subscriber(transaction_subscriber, incoming_transactions.clone());
-    let block_subscriber_join_handle = spawn_block_subscriber(
-        block_subscriber,
-        ledger.clone(),
-        incoming_transactions.clone(),
-    );
-    let block_height_publisher_join_handle =
-        spawn_block_height_publisher(block_height_publisher, ledger.clone());
-    let block_height_subscriber_join_handle = spawn_block_height_subscriber(
-        block_height_subscriber,
-        block_publish_sender.clone(),
-        ledger.clone(),
-    );
-    let mining_join_handle = spawn_mining_join_handle(
-        incoming_transactions.clone(),
+            // Only this thread changes the database, so its tip stays until the steps are applied
+            let tip = utxo_db.lock().expect("Lock failure").tip().cloned();
+            let steps = utxo_db_steps(&ledger.lock().expect("Lock failure"), tip.as_ref());
+            let res = steps.and_then(|steps| {
+                catching_up = steps.len() == UTXO_DB_SYNC_BLOCKS;
+                apply_utxo_db_steps(&mut utxo_db.lock().expect("Lock failure"), steps)
+            });
+            if let Err(e) = res {
+                catching_up = false;
+                error!("Error during updating UTXO database: {}", e);
+            }
+        }
+    })
+}
+
+/// Periodically remove fork branches more than `min_depth_behind` blocks behind the best tip,
+/// logging how many blocks were removed.
+fn spawn_stale_branch_pruner(
+    ledger: Arc<Mutex<PersistentLedger>>,
+    min_depth_behind: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut total = 0;
+        loop {
+            tokio::time::sleep(STALE_BRANCH_PRUNE_INTERVAL).await;
+
+            let pruned = ledger
+                .lock()
+                .expect("Lock failure")
+                .prune_stale_branches(min_depth_behind);
+            let pruned = match pruned {
+                Ok(pruned) => pruned,
+                Err(e) => {
+                    error!("Error during rewriting the block database. {}", e);
+                    continue;
+                }
+            };
+            if pruned > 0 {
+                total += pruned;
+                info!(
+                    "Pruned {} blocks of stale branches, {} since start.",
+                    pruned, total
+                );
+            }
+        }
+    })
+}
+
+fn spawn_total_supply_server(
+    mut server: ServiceServer<QueryTotalSupply>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|digest| {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    let supply = ledger.total_supply_at(&digest);
+                    match &supply {
+                        Ok(supply) => info!("Total supply at {}: {}", digest.fmt_short(), supply),
+                        Err(e) => warn!("Supply audit at {} failed: {}", digest.fmt_short(), e),
+                    }
+                    Some(supply)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving total supply: {}", e);
+            }
+        }
+    })
+}
+
+fn chain_info(ledger: &Ledger, prune_depth: Option<u64>) -> Option<ChainInfo> {
+    let tip = ledger.search_latest_block()?;
+    let info = ChainInfo {
+        best_digest: tip.digest().clone(),
+        height: tip.height(),
+        cumulative_work: ledger.cumulative_work(tip.digest()),
+        difficulty: tip.difficulty().clone(),
+        median_time_past: ledger.median_time_past(tip.digest())?,
+        retention: block_retention(Some(tip.height()), prune_depth),
+        fork_count: ledger.leaf_blocks().count() - 1,
+    };
+    Some(info)
+}
+
+fn spawn_chain_info_server(
+    mut server: ServiceServer<QueryChainInfo>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    prune_depth: Option<u64>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    Some(chain_info(&ledger, prune_depth))
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving chain info: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_mempool_usage_server(
+    mut server: ServiceServer<QueryMempoolUsage>,
+    mempool: Arc<Mutex<Mempool>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(mempool.lock().expect("Lock failure").usage()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving mempool usage: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_mempool_info_server(
+    mut server: ServiceServer<QueryMempoolInfo>,
+    mempool: Arc<Mutex<Mempool>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(mempool.lock().expect("Lock failure").info()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving mempool info: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_raw_mempool_server(
+    mut server: ServiceServer<QueryRawMempool>,
+    mempool: Arc<Mutex<Mempool>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|verbose| Some(mempool.lock().expect("Lock failure").entries(verbose)))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving raw mempool: {}", e);
+            }
+        }
+    })
+}
+
+/// Accept transactions submitted by clients, then relay them to other nodes.
+fn spawn_send_transaction_server(
+    mut server: ServiceServer<SendTransaction>,
+    relay: UnboundedSender<Arc<VerifiedTransaction>>,
+    mempool: Arc<Mutex<Mempool>>,
+    seen: Arc<Mutex<SeenCache>>,
+    tracker: Arc<Mutex<TransactionTracker>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|transaction| {
+                    let res = transaction
+                        .verify()
+                        .map_err(|e| SendTransactionError::Invalid(e.to_string()))
+                        .and_then(|transaction| {
+                            let ledger = ledger.lock().expect("Lock failure");
+                            match transaction.lock_time() {
+                                Some(lock_time)
+                                    if !is_final_for_next_block(&ledger, &transaction) =>
+                                {
+                                    Err(SendTransactionError::Locked(lock_time))
+                                }
+                                _ => Ok(transaction),
+                            }
+                        })
+                        .and_then(|transaction| {
+                            let transaction = Arc::new(transaction);
+                            let txid = transaction.txid();
+                            mempool
+                                .lock()
+                                .expect("Lock failure")
+                                .insert(transaction.clone())?;
+                            // The relayed transaction comes back to this node, which needs no verification
+                            seen.lock().expect("Lock failure").insert(txid.clone());
+                            tracker
+                                .lock()
+                                .expect("Lock failure")
+                                .track(transaction.clone());
+                            relay.send(transaction).ok();
+                            Ok(txid)
+                        });
+                    match &res {
+                        Ok(txid) => info!("Accepted submitted transaction {}.", txid.fmt_short()),
+                        Err(e) => warn!("Deny submitted transaction. {}", e),
+                    }
+                    Some(res)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving transaction submission: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_transaction_relay(
+    mut publisher: TopicPublisher<CreateTransaction>,
+    mut receiver: UnboundedReceiver<Arc<VerifiedTransaction>>,
+    sync: Arc<Mutex<SyncTracker>>,
+    peers: Arc<Mutex<Peers>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(transaction) = receiver.recv().await {
+            // The transaction stays in mempool, and is rebroadcasted after the sync
+            if sync.lock().expect("Lock failure").is_syncing() {
+                info!(
+                    "Hold relaying transaction {} until synced.",
+                    transaction.txid().fmt_short()
+                );
+                continue;
+            }
+            // Likewise until a node which takes transactions appears
+            if !has_transaction_peers(&peers.lock().expect("Lock failure")) {
+                info!(
+                    "Hold relaying transaction {}, since every node is blocks-only.",
+                    transaction.txid().fmt_short()
+                );
+                continue;
+            }
+            if let Err(e) = publisher.publish(&transaction).await {
+                error!("Error during relaying submitted transaction. {}", e);
+            }
+        }
+    })
+}
+
+/// Periodically relay unconfirmed transactions again, for nodes which missed them.
+/// Nodes which already verified them skip them by their seen cache.
+fn spawn_mempool_rebroadcaster(
+    mempool: Arc<Mutex<Mempool>>,
+    relay: UnboundedSender<Arc<VerifiedTransaction>>,
+    timers: Arc<Mutex<Timers>>,
+    sync: Arc<Mutex<SyncTracker>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let interval = timers.lock().expect("Lock failure").mempool_rebroadcast;
+            tokio::time::sleep(interval).await;
+
+            if sync.lock().expect("Lock failure").is_syncing() {
+                continue;
+            }
+
+            let transactions = mempool.lock().expect("Lock failure").snapshot();
+            if transactions.is_empty() {
+                continue;
+            }
+            info!("Rebroadcasting {} transactions.", transactions.len());
+            for transaction in transactions {
+                if relay.send(transaction).is_err() {
+                    error!("Transaction relay finished. Stop rebroadcasting.");
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// Periodically forget nodes which stopped announcing their chain height,
+/// and report the sync progress while far behind them.
+fn spawn_peer_monitor(
+    peers: Arc<Mutex<Peers>>,
+    timers: Arc<Mutex<Timers>>,
+    sync: Arc<Mutex<SyncTracker>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let (interval, timeout) = {
+                let timers = timers.lock().expect("Lock failure");
+                (
+                    timers.peer_ping,
+                    timers.height_announce * PEER_TIMEOUT_ANNOUNCEMENTS,
+                )
+            };
+            tokio::time::sleep(interval).await;
+
+            let mut peers = peers.lock().expect("Lock failure");
+            peers.retain(|node, (last_seen, _)| {
+                let alive = last_seen.elapsed() < timeout;
+                if !alive {
+                    warn!("Lost node {}.", node);
+                }
+                alive
+            });
+            info!("Connected nodes: {}", peers.len());
+
+            let now = Instant::now();
+            let mut sync = sync.lock().expect("Lock failure");
+            sync.set_best_known_height(best_known_height(&peers), now);
+            if sync.is_syncing() {
+                info!("Syncing: {}", sync.progress(now));
+            }
+        }
+    })
+}
+
+fn spawn_sync_progress_server(
+    mut server: ServiceServer<QuerySyncProgress>,
+    sync: Arc<Mutex<SyncTracker>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| {
+                    let sync = sync.lock().expect("Lock failure");
+                    Some(sync.progress(Instant::now()))
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving sync progress: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_block_propagation_server(
+    mut server: ServiceServer<QueryBlockPropagation>,
+    propagation: Arc<Mutex<PropagationTracker>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(propagation.lock().expect("Lock failure").report()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving block propagation: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_bans_server(
+    mut server: ServiceServer<QueryBans>,
+    bans: Arc<Mutex<PeerBans>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(bans.lock().expect("Lock failure").bans()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving bans: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_unban_server(
+    mut server: ServiceServer<Unban>,
+    bans: Arc<Mutex<PeerBans>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|node| Some(bans.lock().expect("Lock failure").unban(&node)))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving unban: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_timers_server(
+    mut server: ServiceServer<QueryTimers>,
+    timers: Arc<Mutex<Timers>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(*timers.lock().expect("Lock failure")))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving timers: {}", e);
+            }
+        }
+    })
+}
+
+/// Replace intervals of periodic tasks. Each task applies the new interval from its next wait.
+fn spawn_set_timers_server(
+    mut server: ServiceServer<SetTimers>,
+    timers: Arc<Mutex<Timers>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|new_timers| {
+                    let res = new_timers.check();
+                    match &res {
+                        Ok(()) => {
+                            info!("Updated timers: {:?}", new_timers);
+                            *timers.lock().expect("Lock failure") = new_timers;
+                        }
+                        Err(e) => warn!("Deny timers update. {}", e),
+                    }
+                    Some(res)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving timers update: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_transaction_status_server(
+    mut server: ServiceServer<QueryTransactionStatus>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    mempool: Arc<Mutex<Mempool>>,
+    tracker: Arc<Mutex<TransactionTracker>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|txid| {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    let mempool = mempool.lock().expect("Lock failure");
+                    let tracker = tracker.lock().expect("Lock failure");
+                    Some(tracker.status(&txid, &mempool, &ledger))
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving transaction status: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_merkle_proof_server(
+    mut server: ServiceServer<QueryMerkleProof>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|txid| {
+                    let snapshot = ledger.lock().expect("Lock failure").snapshot();
+                    let proof = snapshot.blocks().rev().find_map(|block| {
+                        let index = block.transactions().iter().position(|t| t.txid() == txid)?;
+                        let proof = block.merkle_proof(index)?;
+                        Some((block.header().clone(), proof))
+                    });
+                    match &proof {
+                        Some((header, _)) => info!(
+                            "Proved transaction {} in block {}",
+                            txid.fmt_short(),
+                            header.digest().fmt_short()
+                        ),
+                        None => warn!("Transaction {} not found", txid.fmt_short()),
+                    }
+                    Some(proof)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving merkle proof: {}", e);
+            }
+        }
+    })
+}
+
+/// Move the search index to the tip of the longest chain.
+fn sync_search_index(index: &mut SearchIndex, ledger: &Ledger) -> Result<(), SearchError> {
+    if let Some((disconnected, connected)) = path_to_best_tip(ledger, index.tip()) {
+        for block in disconnected {
+            index.disconnect_block(block)?;
+        }
+        for block in connected {
+            index.connect_block(block)?;
+        }
+    }
+    Ok(())
+}
+
+fn spawn_search_index_sync(
+    index: Arc<Mutex<SearchIndex>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SEARCH_INDEX_SYNC_INTERVAL).await;
+
+            let ledger = ledger.lock().expect("Lock failure");
+            let mut index = index.lock().expect("Lock failure");
+            if let Err(e) = sync_search_index(&mut index, &ledger) {
+                error!("Error during updating search index: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_search_server(
+    mut server: ServiceServer<QuerySearch>,
+    index: Arc<Mutex<SearchIndex>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|prefix| Some(index.lock().expect("Lock failure").search(&prefix)))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving search: {}", e);
+            }
+        }
+    })
+}
+
+/// Move chain statistics to the tip of the longest chain.
+fn sync_chain_stats(stats: &mut ChainStats, ledger: &Ledger) -> Result<(), ChainStatsError> {
+    if let Some((disconnected, connected)) = path_to_best_tip(ledger, stats.tip()) {
+        for block in disconnected {
+            stats.disconnect_block(block)?;
+        }
+        for block in connected {
+            stats.connect_block(block)?;
+        }
+    }
+    Ok(())
+}
+
+fn spawn_chain_stats_sync(
+    stats: Arc<Mutex<ChainStats>>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CHAIN_STATS_SYNC_INTERVAL).await;
+
+            let ledger = ledger.lock().expect("Lock failure");
+            let mut stats = stats.lock().expect("Lock failure");
+            if let Err(e) = sync_chain_stats(&mut stats, &ledger) {
+                error!("Error during updating chain statistics: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_chain_stats_server(
+    mut server: ServiceServer<QueryChainStats>,
+    stats: Arc<Mutex<ChainStats>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(stats.lock().expect("Lock failure").daily()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving chain statistics: {}", e);
+            }
+        }
+    })
+}
+
+/// Serve `S` by an analysis of the longest chain, which walks the whole chain on each request.
+fn spawn_chain_analysis_server<S, F>(
+    mut server: ServiceServer<S>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    mut analyze: F,
+) -> JoinHandle<()>
+where
+    S: Service + 'static,
+    F: FnMut(&ChainAnalysis, S::Req) -> S::Res + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|req| {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    Some(analyze(&ChainAnalysis::of_best_chain(&ledger), req))
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving {}: {}", S::NAME, e);
+            }
+        }
+    })
+}
+
+/// Log deployments whose state on the longest chain changed.
+fn spawn_deployment_monitor(ledger: Arc<Mutex<PersistentLedger>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut states = HashMap::new();
+        loop {
+            let statuses = ledger.lock().expect("Lock failure").deployment_statuses();
+            for status in statuses {
+                let previous = states.insert(status.name.clone(), status.state);
+                if previous == Some(status.state) {
+                    continue;
+                }
+                match status.state {
+                    DeploymentState::Started | DeploymentState::LockedIn => info!(
+                        "Deployment {} is {}. {}/{} blocks of this window signal bit {}, {} needed.",
+                        status.name,
+                        status.state,
+                        status.signalling,
+                        status.elapsed,
+                        status.bit,
+                        status.threshold
+                    ),
+                    DeploymentState::Active if previous.is_some() => {
+                        warn!("Deployment {} is active. Its rule is enforced from now on.", status.name)
+                    }
+                    state => info!("Deployment {} is {}.", status.name, state),
+                }
+            }
+
+            tokio::time::sleep(DEPLOYMENT_CHECK_INTERVAL).await;
+        }
+    })
+}
+
+fn spawn_alerts_server(
+    mut server: ServiceServer<QueryAlerts>,
+    alerts: Arc<Mutex<Vec<Alert>>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(alerts.lock().expect("Lock failure").clone()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving alerts: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_deployments_server(
+    mut server: ServiceServer<QueryDeployments>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|()| Some(ledger.lock().expect("Lock failure").deployment_statuses()))
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving deployments: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_header_server<F>(
+    mut server: ServiceServer<QueryHeaderByHeight>,
+    mut header_at: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(BlockHeight) -> Option<BlockHeader> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let res = server.serve(|height| Some(header_at(height))).await;
+
+            if let Err(e) = res {
+                error!("Error during serving block header: {}", e);
+            }
+        }
+    })
+}
+
+/// Serve ranges of compact blocks of the longest chain, stopping at the first pruned one.
+fn spawn_blocks_by_range_server(
+    mut server: ServiceServer<QueryBlocksByRange>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    prune_depth: Option<u64>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|(first, last)| {
+                    let ledger = ledger.lock().expect("Lock failure");
+                    let snapshot = ledger.snapshot();
+                    let retention = block_retention(snapshot.height(), prune_depth);
+                    let blocks = first
+                        .to_inclusive(last)
+                        .take(MAX_BLOCKS_PER_RANGE as usize)
+                        .take_while(|height| retention.check(*height).is_ok())
+                        .map_while(|height| snapshot.block_at(height))
+                        .map_while(|block| ledger.compact_block(block))
+                        .collect();
+                    Some(blocks)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving block range: {}", e);
+            }
+        }
+    })
+}
+
+/// Serve blocks of the longest chain, unless pruned.
+fn spawn_block_server(
+    mut server: ServiceServer<QueryBlockByHeight>,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    prune_depth: Option<u64>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|height| {
+                    let snapshot = ledger.lock().expect("Lock failure").snapshot();
+                    let block = block_retention(snapshot.height(), prune_depth)
+                        .check(height)
+                        .and_then(|()| {
+                            snapshot
+                                .block_at(height)
+                                .map(Block::to_unverified)
+                                .ok_or(SyncError::NotFound(height))
+                        });
+                    Some(block)
+                })
+                .await;
+
+            if let Err(e) = res {
+                error!("Error during serving block: {}", e);
+            }
+        }
+    })
+}
+
+/// Append incoming headers to the header chain, then relay new ones to other nodes.
+fn spawn_header_relay(
+    mut subscriber: TopicSubscriber<NotifyBlockHeader>,
+    mut publisher: TopicPublisher<NotifyBlockHeader>,
+    headers: Arc<Mutex<HeaderChain>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let header = match subscriber.recv().await {
+                Ok(header) => header,
+                Err(e) => {
+                    error!("Error during subscribing block header. {}", e);
+                    continue;
+                }
+            };
+
+            let digest = header.digest().clone();
+            let res = headers.lock().expect("Lock failure").push(header.clone());
+            match res {
+                Ok(()) => {
+                    info!(
+                        "Appended header. Height: {}, Digest: {}",
+                        header.height(),
+                        digest.fmt_short()
+                    );
+                    if let Err(e) = publisher.publish(&header).await {
+                        error!("Error during relaying block header: {}", e);
+                    }
+                }
+                // Relayed headers come back to this node
+                Err(HeaderChainError::DuplicatedHeader) => {}
+                Err(e) => warn!("Deny incoming header {}. {}", digest.fmt_short(), e),
+            }
+        }
+    })
+}
+
+/// Announce the header chain height so that full nodes publish the headers it misses.
+/// This node serves no block body, so the retention starts above its tip.
+fn spawn_header_height_publisher(
+    mut height_publisher: TopicPublisher<NotifyBlockHeight>,
+    headers: Arc<Mutex<HeaderChain>>,
+    node_key: SecretAddress,
+    timers: Arc<Mutex<Timers>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let status = {
+                let headers = headers.lock().expect("Lock failure");
+                let height = headers.tip().map(BlockHeader::height);
+                let lowest = height
+                    .map(BlockHeight::next)
+                    .unwrap_or(BlockHeight::genesis());
+                ChainStatus::new(height, BlockRetention::Pruned { lowest }).with_blocks_only(true)
+            };
+
+            if let Err(e) = height_publisher
+                .publish(&Signed::new(&node_key, status))
+                .await
+            {
+                error!("Error during publishing header chain height: {}", e);
+            }
+
+            let interval = timers.lock().expect("Lock failure").height_announce;
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Load the key identifying this node, creating one on first run.
+fn load_node_key(path: &str) -> Result<SecretAddress> {
+    if Path::new(path).exists() {
+        return Ok(bcaddr::read_address(path)?);
+    }
+
+    let node_key = SecretAddress::create();
+    bcaddr::write_address(path, &node_key)?;
+    info!("Created node key {}.", path);
+    Ok(node_key)
+}
+
+/// Run a node which keeps only block headers.
+async fn run_header_only(
+    node_key: SecretAddress,
+    brokers: &[String],
+    pow_algorithm: PowAlgorithm,
+    timers: Arc<Mutex<Timers>>,
+    layers: Layers,
+) -> Result<()> {
+    let headers = Arc::new(Mutex::new(
+        HeaderChain::new(DIFFICULTY).with_pow_algorithm(pow_algorithm),
+    ));
+
+    let header_subscriber = TopicSubscriber::<NotifyBlockHeader>::connect_to(brokers).await?;
+    let header_publisher = TopicPublisher::<NotifyBlockHeader>::connect_to(brokers).await?;
+    let block_height_publisher = TopicPublisher::<NotifyBlockHeight>::connect_to(brokers).await?;
+    let header_server = ServiceServer::<QueryHeaderByHeight>::connect_to(brokers)
+        .await?
+        .with_layers(layers.clone());
+    let timers_server = ServiceServer::<QueryTimers>::connect_to(brokers)
+        .await?
+        .with_layers(layers.clone());
+    let set_timers_server = ServiceServer::<SetTimers>::connect_to(brokers)
+        .await?
+        .with_layers(layers.clone());
+
+    info!("Spawning threads...");
+
+    let header_relay_join_handle =
+        spawn_header_relay(header_subscriber, header_publisher, headers.clone());
+    let header_height_publisher_join_handle = spawn_header_height_publisher(
+        block_height_publisher,
+        headers.clone(),
+        node_key,
+        timers.clone(),
+    );
+    let header_server_join_handle = spawn_header_server(header_server, move |height| {
+        headers
+            .lock()
+            .expect("Lock failure")
+            .header_at(height)
+            .cloned()
+    });
+    let timers_join_handle = spawn_timers_server(timers_server, timers.clone());
+    let set_timers_join_handle = spawn_set_timers_server(set_timers_server, timers);
+
+    info!("Initialization done. A header-only node running...");
+
+    header_relay_join_handle.await?;
+    header_height_publisher_join_handle.await?;
+    header_server_join_handle.await?;
+    timers_join_handle.await?;
+    set_timers_join_handle.await?;
+
+    Ok(())
+}
+
+/// Rebuild the ledger from blocks exported to `path`, verifying each block from scratch.
+/// Stops at the first denied block, keeping the blocks before it.
+/// Enter `blocks` into `ledger` in order, until one of them is denied.
+/// Returns the number of entered blocks.
+fn reindex(
+    blocks: Vec<UnverifiedBlock>,
+    ledger: &mut Ledger,
+    assume_valid: Option<&AssumeValid>,
+) -> usize {
+    let mut count = 0;
+    for block in blocks {
+        let (digest, height) = (block.digest().clone(), block.height());
+        let res = verify_block(block, ledger, assume_valid).and_then(|block| {
+            ledger
+                .entry(block)
+                .map_err(|e| BlockRejection::new(digest, height, ValidationStage::Entry, e))
+        });
+        if let Err(rejection) = res {
+            warn!("Stop reindex at a denied block. {}", rejection);
+            break;
+        }
+
+        count += 1;
+        if count % 1000 == 0 {
+            info!("Reindexed {} blocks.", count);
+        }
+    }
+
+    count
+}
+
+/// Reindex the chain file at `path` up to its first damaged or denied block,
+/// then rewrite the file without the rest, which the sync downloads from other nodes again.
+fn recover_chain(
+    path: &str,
+    ledger: &mut Ledger,
+    assume_valid: Option<&AssumeValid>,
+) -> Result<usize> {
+    let (blocks, damage) = replay::read_chain_prefix(path)?;
+    let decoded = blocks.len();
+    if let Some(e) = &damage {
+        warn!("{} is damaged after {} blocks. {}", path, decoded, e);
+    }
+
+    info!("Reindexing {} blocks from {}...", decoded, path);
+    let count = reindex(blocks, ledger, assume_valid);
+    if damage.is_some() || count < decoded {
+        let chain = ledger.search_latest_chain().collect::<Vec<_>>();
+        replay::write_chain(path, chain.into_iter().rev())?;
+        warn!(
+            "Truncated {} to {} valid blocks. Later blocks are downloaded from other nodes again.",
+            path, count
+        );
+    }
+    Ok(count)
+}
+
+/// Why the UTXO database at `path` cannot serve the chain of `ledger`, if it cannot.
+/// Reads every output, so this is slow for a large database.
+fn check_utxo_db(utxo_db: &UtxoDb, ledger: &Ledger) -> Option<String> {
+    if let Some(Err(e)) = utxo_db.utxos().find(Result::is_err) {
+        return Some(format!("An output is unreadable. {}", e));
+    }
+
+    // A tip above the chain is fine, as the sync reaches it again
+    let (height, digest) = utxo_db.tip()?;
+    let block = ledger
+        .search_latest_chain()
+        .find(|block| block.height() == *height)?;
+    if block.digest() != digest {
+        return Some(format!(
+            "Its tip {} at height {} is not on the chain, which has {}.",
+            digest.fmt_short(),
+            height,
+            block.digest().fmt_short()
+        ));
+    }
+    let snapshot = ledger.utxo_snapshot(digest);
+    let mut outpoints = ledger
+        .downstream_chain_to(digest)
+        .flat_map(|block| block.created_outputs())
+        .filter(|(_, output)| snapshot.contains(output))
+        .map(|(outpoint, _)| outpoint);
+    if snapshot.len() != utxo_db.len() || !outpoints.all(|outpoint| utxo_db.contains(&outpoint)) {
+        return Some(format!(
+            "Its outputs differ from the chain at height {}.",
+            height
+        ));
+    }
+    None
+}
+
+/// Open the UTXO database in `path`. If `recover`, a database which is damaged or does not
+/// match `ledger` is moved aside and rebuilt from genesis.
+fn open_utxo_db(
+    path: &str,
+    config: UtxoDbConfig,
+    ledger: &Ledger,
+    recover: bool,
+) -> Result<UtxoDb> {
+    let damage = match UtxoDb::open(path, config) {
+        Ok(utxo_db) if !recover => return Ok(utxo_db),
+        Ok(utxo_db) => match check_utxo_db(&utxo_db, ledger) {
+            None => return Ok(utxo_db),
+            Some(damage) => damage,
+        },
+        Err(e) if !recover => anyhow::bail!(
+            "Cannot open UTXO database in {}. {} Start with --recover to rebuild it.",
+            path,
+            e
+        ),
+        Err(e) => e.to_string(),
+    };
+
+    let aside = format!("{}.damaged", path);
+    warn!("UTXO database in {} is damaged. {}", path, damage);
+    if Path::new(&aside).exists() {
+        std::fs::remove_dir_all(&aside)?;
+    }
+    std::fs::rename(path, &aside)?;
+    warn!("Moved it to {}, and rebuild it from genesis.", aside);
+    Ok(UtxoDb::open(path, config)?)
+}
+
+fn spawn_chain_exporter(path: String, ledger: Arc<Mutex<PersistentLedger>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            let res = {
+                let ledger = ledger.lock().expect("Lock failure");
+                let blocks = ledger
+                    .search_latest_chain()
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .rev();
+                replay::write_chain(&path, blocks)
+            };
+
+            match res {
+                Ok(()) => info!("Exported the longest chain to {}.", path),
+                Err(e) => error!("Error during exporting chain: {}", e),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Parser)]
+struct FullnodeArgs {
+    /// Address file path
+    #[clap(long, required_unless_present = "header_only")]
+    address: Option<String>,
+
+    /// Enable when mine genesis block. Otherwise, download genesis block from other nodes.
+    #[clap(long)]
+    mine_genesis_block: bool,
+
+    /// Hash function of block digests and Proof-of-Work, which all nodes of the chain must share.
+    /// blake3 requires the blake3 feature.
+    #[clap(long, default_value = "sha256")]
+    pow_algorithm: PowAlgorithm,
+
+    /// Seal blocks by these addresses in turn instead of Proof-of-Work, for private networks.
+    /// Every node of the chain must list the same addresses in the same order.
+    /// Header-only nodes cannot follow such chains.
+    #[clap(long, conflicts_with = "header_only")]
+    authorities: Vec<Address>,
+
+    /// Serve only the latest N blocks to other nodes.
+    /// If not specified, this node serves all blocks as an archival node.
+    #[clap(long)]
+    prune_depth: Option<u64>,
+
+    /// Remove fork branches whose tips are more than this many blocks behind the best tip.
+    #[clap(long, default_value_t = 100)]
+    stale_branch_depth: u64,
+
+    /// Periodically export the longest chain to this file for bcreplay.
+    #[clap(long)]
+    export_chain: Option<String>,
+
+    /// Store every block entered into the ledger in this directory, synced before it is used,
+    /// and load them at start. Otherwise, the chain is downloaded again after every restart.
+    #[clap(long)]
+    block_db: Option<String>,
+
+    /// Keep the UTXO set of the longest chain on disk in this directory,
+    /// and answer UTXO queries from it. The ledger still keeps its UTXO sets in memory.
+    #[clap(long)]
+    utxo_db: Option<String>,
+
+    /// Changed UTXOs held in memory, beyond which they are written to --utxo-db at once.
+    #[clap(long, default_value_t = UtxoDbConfig::default().cache_size)]
+    utxo_cache_size: usize,
+
+    /// Blocks applied to --utxo-db between writes to disk.
+    #[clap(long, default_value_t = UtxoDbConfig::default().flush_blocks)]
+    utxo_flush_blocks: u64,
+
+    /// Seconds between writes to --utxo-db, while fewer blocks than --utxo-flush-blocks come.
+    #[clap(long, default_value_t = UtxoDbConfig::default().flush_interval.as_secs())]
+    utxo_flush_secs: u64,
+
+    /// Rebuild the ledger at start by verifying all blocks in the --export-chain file again,
+    /// to recover from corrupted state or after its format changed.
+    #[clap(long, requires = "export_chain")]
+    reindex: bool,
+
+    /// Start even if --export-chain, --block-db or --utxo-db is damaged, instead of failing.
+    /// Reindexes the chain file up to its first damaged or invalid block and truncates it there.
+    /// Stored blocks are loaded up to the first invalid or undecodable one, and the rest are
+    /// dropped. A block database of another format version is emptied.
+    /// Blocks after it are downloaded from other nodes again by the usual sync.
+    /// A UTXO database which is damaged or does not match the chain is rebuilt.
+    #[clap(long)]
+    recover: bool,
+
+    /// Trust signs of the chain up to this block, given as HEIGHT:DIGEST, and skip checking them
+    /// for received blocks below it, which speeds up the first sync. Everything else is still
+    /// checked, and a block at HEIGHT other than DIGEST is denied.
+    #[clap(long)]
+    assume_valid: Option<AssumeValid>,
+
+    /// Upper limit of the total serialized size of unconfirmed transactions, in bytes.
+    /// When exceeded, transactions paying the lowest fee per byte are evicted.
+    #[clap(long, default_value_t = 64 * 1024 * 1024)]
+    mempool_bytes: usize,
+
+    /// Minimum fee in base units per 1000 bytes, below which transactions are
+    /// neither accepted into mempool nor relayed. Advertised with the chain height.
+    #[clap(long, default_value_t = 0)]
+    min_fee_rate: u64,
+
+    /// Neither accept nor relay transactions from other nodes, only blocks, to save bandwidth.
+    /// Advertised with the chain height. Transactions submitted to this node are still relayed.
+    #[clap(long)]
+    blocksonly: bool,
+
+    /// Number of received transactions waiting for verification.
+    /// When exceeded, the oldest waiting transaction is dropped.
+    #[clap(long, default_value_t = 1024)]
+    transaction_queue: usize,
+
+    /// Number of tasks verifying received transactions.
+    #[clap(long, default_value_t = 2)]
+    transaction_workers: usize,
+
+    /// Number of received blocks waiting for verification.
+    /// When exceeded, block reception waits instead of dropping blocks.
+    #[clap(long, default_value_t = 64)]
+    block_queue: usize,
+
+    /// Number of recently verified blocks and transactions remembered to skip duplicates.
+    #[clap(long, default_value_t = 4096)]
+    seen_capacity: usize,
+
+    /// Seconds to remember a verified block or transaction since it was last seen.
+    #[clap(long, default_value_t = 600)]
+    seen_ttl: u64,
+
+    /// Number of recent blocks whose receive, validation and relay times are kept
+    /// for the block propagation report.
+    #[clap(long, default_value_t = 1024)]
+    propagation_blocks: usize,
+
+    /// Number of accepted transactions whose status is traced after leaving mempool.
+    #[clap(long, default_value_t = 4096)]
+    tracked_transactions: usize,
+
+    /// JSON file of webhooks `[{"address": "<hex>", "url": "<url>"}]`.
+    /// Each URL is posted an event when its address receives or spends coins.
+    #[clap(long)]
+    webhooks: Option<String>,
+
+    /// Expected seconds between blocks, in which the chain stall alert is measured.
+    #[clap(long, default_value_t = 60)]
+    target_block_secs: u64,
+
+    /// Alert when no block joins the longest chain for this many target intervals
+    /// while transactions wait. Alerts are logged and listed by the QueryAlerts service.
+    #[clap(long, default_value_t = 10)]
+    stall_alert_intervals: u32,
+
+    /// Alert when a transaction has waited in mempool for this many seconds.
+    #[clap(long, default_value_t = 60 * 60)]
+    mempool_alert_secs: u64,
+
+    /// URL posted a JSON event when an alert is raised or resolved.
+    #[clap(long)]
+    alert_webhook: Option<String>,
+
+    /// Append consensus decisions to this JSONL file: accepted and denied blocks,
+    /// reorganizations and dropped transactions.
+    #[clap(long)]
+    audit_log: Option<String>,
+
+    /// Rotate the audit log when it exceeds this size in bytes.
+    #[clap(long, default_value_t = 16 * 1024 * 1024)]
+    audit_log_bytes: u64,
+
+    /// Number of rotated audit log files to keep.
+    #[clap(long, default_value_t = 5)]
+    audit_log_files: usize,
+
+    /// Stream blocks and transactions connected to and disconnected from the longest chain,
+    /// one JSON object per line, to `tcp://HOST:PORT` or appended to a file.
+    #[clap(long)]
+    chain_events: Option<ChainEventSink>,
+
+    /// Index block digests, txids and addresses of the longest chain in memory,
+    /// and serve the QuerySearch service which finds them by a prefix of their hex.
+    #[clap(long)]
+    search_index: bool,
+
+    /// Count blocks, supply, fees and active addresses of the longest chain per day,
+    /// and serve them for charts by the QueryChainStats service.
+    #[clap(long)]
+    chain_stats: bool,
+
+    /// Serve balance histories, the rich list, daily volume and UTXO ages of the longest chain
+    /// by the QueryBalanceHistory, QueryRichList, QueryDailyVolume and QueryUtxoAges services.
+    /// Each request walks the whole chain.
+    #[clap(long)]
+    chain_analysis: bool,
+
+    /// Check key files, writable paths, disk space, the proxies and the clock,
+    /// print a report and exit without starting the node.
+    #[clap(long)]
+    selftest: bool,
+
+    /// Keep only block headers. The node neither mines nor verifies transactions,
+    /// but serves header queries and relays headers.
+    #[clap(long)]
+    header_only: bool,
+
+    /// Name of this node, written into every log line to tell apart nodes on one host.
+    #[clap(long)]
+    node_name: Option<String>,
+
+    /// Key file identifying this node to others, created on first run.
+    /// Keep it apart from wallet addresses.
+    /// Defaults to node.key, or NAME.key with --node-name.
+    #[clap(long)]
+    node_key: Option<String>,
+
+    /// Ignore announcements of these nodes, given as node ids in hex.
+    #[clap(long)]
+    banned_nodes: Vec<NodeId>,
+
+    /// JSON file keeping banned nodes across restarts. Bans are listed by the QueryBans service,
+    /// and lifted by the Unban service.
+    #[clap(long)]
+    ban_list: Option<String>,
+
+    /// Length of a ban of a node for misbehavior, such as flooding chain status announcements.
+    #[clap(long, default_value_t = 24 * 60 * 60)]
+    ban_secs: u64,
+
+    /// Proxies to connect to, by the name given to their --broker.
+    /// Topics are published through all of them, so the node keeps running while any proxy does.
+    /// Connects only to the default proxy if not specified.
+    #[clap(long)]
+    brokers: Vec<String>,
+
+    /// Number of blocks behind other nodes, beyond which this node suspends mining
+    /// and transaction relay until it catches up.
+    #[clap(long, default_value_t = 6)]
+    ibd_threshold: u64,
+
+    /// Seconds between announcements of the chain height.
+    #[clap(long, default_value_t = Timers::default().height_announce.as_secs())]
+    height_announce_secs: u64,
+
+    /// Seconds between rebroadcasts of unconfirmed transactions.
+    #[clap(long, default_value_t = Timers::default().mempool_rebroadcast.as_secs())]
+    mempool_rebroadcast_secs: u64,
+
+    /// Seconds between checks for nodes which stopped announcing.
+    #[clap(long, default_value_t = Timers::default().peer_ping.as_secs())]
+    peer_ping_secs: u64,
+
+    /// Seconds to wait for blocks from other nodes before checking again, while the chain is empty.
+    #[clap(long, default_value_t = Timers::default().sync_retry.as_secs())]
+    sync_retry_secs: u64,
+
+    /// Seconds to wait before mining again, while no transaction comes.
+    #[clap(long, default_value_t = Timers::default().mining_idle.as_secs())]
+    mining_idle_secs: u64,
+
+    /// Milliseconds between nonce attempts while mining.
+    /// Hash power of this node is inversely proportional to it.
+    #[clap(long, default_value_t = 10)]
+    mining_attempt_ms: u64,
+
+    /// Seed of the nonces tried while mining, for reproducible runs. Random if not specified.
+    #[clap(long)]
+    mining_seed: Option<u64>,
+
+    /// Serve requests only with one of these tokens. Serves anyone if not specified.
+    #[clap(long)]
+    rpc_tokens: Vec<String>,
+
+    /// JSON file `{"tokens": ["<token>"]}` of tokens required by services which change the node,
+    /// such as SendTransaction. Read-only queries stay open.
+    /// If --rpc-tokens is also given, privileged requests need a token listed in both.
+    #[clap(long)]
+    rpc_auth: Option<String>,
+
+    /// Requests served per second to each caller, told apart by token. Unlimited if not specified.
+    #[clap(long)]
+    rpc_rate_limit: Option<u32>,
+}
+
+impl FullnodeArgs {
+    fn node_key_path(&self) -> String {
+        match (&self.node_key, &self.node_name) {
+            (Some(path), _) => path.clone(),
+            (None, Some(name)) => format!("{}.key", name),
+            (None, None) => "node.key".to_string(),
+        }
+    }
+
+    fn selftest_targets(&self, brokers: &[String]) -> SelftestTargets {
+        let outputs = [
+            ("--block-db", self.block_db.clone()),
+            ("--utxo-db", self.utxo_db.clone()),
+            ("--export-chain", self.export_chain.clone()),
+            ("--audit-log", self.audit_log.clone()),
+            ("--ban-list", self.ban_list.clone()),
+            (
+                "--chain-events",
+                match &self.chain_events {
+                    Some(ChainEventSink::File(path)) => Some(path.display().to_string()),
+                    _ => None,
+                },
+            ),
+        ];
+        SelftestTargets {
+            address: match self.header_only {
+                true => None,
+                false => self.address.as_ref().map(PathBuf::from),
+            },
+            node_key: PathBuf::from(self.node_key_path()),
+            outputs: outputs
+                .into_iter()
+                .filter_map(|(option, path)| Some((option, PathBuf::from(path?))))
+                .collect(),
+            brokers: brokers.to_vec(),
+        }
+    }
+
+    fn timers(&self) -> Timers {
+        Timers {
+            height_announce: Duration::from_secs(self.height_announce_secs),
+            mempool_rebroadcast: Duration::from_secs(self.mempool_rebroadcast_secs),
+            peer_ping: Duration::from_secs(self.peer_ping_secs),
+            sync_retry: Duration::from_secs(self.sync_retry_secs),
+            mining_idle: Duration::from_secs(self.mining_idle_secs),
+        }
+    }
+
+    /// Middleware of all services.
+    fn layers(&self) -> Result<Layers> {
+        let mut layers = Layers::new().with(RequestLog);
+        if !self.rpc_tokens.is_empty() {
+            layers = layers.with(TokenAuth::new(self.rpc_tokens.clone()));
+        }
+        if let Some(path) = &self.rpc_auth {
+            let auth = load_rpc_auth(path)?;
+            info!(
+                "Privileged services require one of {} tokens.",
+                auth.tokens.len()
+            );
+            layers = layers.with(Scoped::new(
+                PRIVILEGED.iter().copied(),
+                TokenAuth::new(auth.tokens),
+            ));
+        }
+        if let Some(limit) = self.rpc_rate_limit {
+            layers = layers.with(RateLimit::new(limit, Duration::from_secs(1)));
+        }
+        Ok(layers)
+    }
+}
+
+/// Start logging as configured by RUST_LOG, with `node_name` in every line if given.
+fn init_logger(node_name: Option<&str>) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(name) = node_name.map(str::to_string) {
+        builder.format(move |buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                name,
+                record.target(),
+                record.args()
+            )
+        });
+    }
+    builder.init();
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arg = FullnodeArgs::parse();
+    init_logger(arg.node_name.as_deref());
+
+    let brokers = if arg.brokers.is_empty() {
+        vec![DEFAULT_BROKER.to_string()]
+    } else {
+        arg.brokers.clone()
+    };
+
+    if arg.selftest {
+        let report = run_selftest(&arg.selftest_targets(&brokers)).await;
+        print!("{}", report);
+        if !report.passed() {
+            anyhow::bail!("Self-test failed.");
+        }
+        return Ok(());
+    }
+
+    let node_key = load_node_key(&arg.node_key_path())?;
+    let node_id = node_key.to_public_address();
+    info!("Node id: {}", node_id);
+
+    let timers = arg.timers();
+    timers.check()?;
+    let timers = Arc::new(Mutex::new(timers));
+    let layers = arg.layers()?;
+
+    if arg.header_only {
+        info!("Initializing blockchain header-only node...");
+        return run_header_only(node_key, &brokers, arg.pow_algorithm, timers, layers).await;
+    }
+
+    info!("Initializing blockchain full node...");
+
+    let address = arg.address.expect("Address is required unless header-only");
+    let secret_address = bcaddr::read_address(&address)?;
+    info!("Loaded self address from {}.", &address);
+
+    let mempool = Mempool::new(arg.mempool_bytes).with_min_fee_rate(arg.min_fee_rate);
+    let incoming_transactions = Arc::new(Mutex::new(mempool));
+    let mut params = ChainParams::default().with_pow_algorithm(arg.pow_algorithm);
+    if arg.pow_algorithm != PowAlgorithm::default() {
+        info!("Hashing blocks by {}.", arg.pow_algorithm);
+    }
+    // Chain parameters live as long as the node
+    if let Some(authorities) = Authorities::new(arg.authorities.clone().leak()) {
+        params = params.with_authorities(authorities);
+        info!("Following {}.", params.consensus());
+        if !authorities.contains(&secret_address.to_public_address()) {
+            info!("This node is not an authority, so it only follows the chain.");
+        }
+    }
+    let mut ledger = Ledger::with_params(params);
+    let reindexed = match (&arg.export_chain, arg.reindex, arg.recover) {
+        (Some(path), _, true) => Some(recover_chain(path, &mut ledger, arg.assume_valid.as_ref())?),
+        (Some(path), true, false) => {
+            let blocks = replay::read_chain(path).map_err(|e| {
+                anyhow::anyhow!(
+                    "Cannot read {}. {} Start with --recover to repair it.",
+                    path,
+                    e
+                )
+            })?;
+            info!("Reindexing {} blocks from {}...", blocks.len(), path);
+            Some(reindex(blocks, &mut ledger, arg.assume_valid.as_ref()))
+        }
+        _ => None,
+    };
+    if let Some(count) = reindexed {
+        match ledger.search_latest_block() {
+            Some(block) => info!(
+                "Reindexed {} blocks. Best block height: {}, Digest: {}",
+                count,
+                block.height(),
+                block.digest().fmt_short()
+            ),
+            None => info!("Reindexed no block."),
+        }
+    }
+    let ledger = match &arg.block_db {
+        Some(path) => {
+            let assume_valid = arg.assume_valid.as_ref();
+            let ledger = PersistentLedger::open(path, ledger, arg.recover, |block, ledger| {
+                verify_block(block, ledger, assume_valid)
+            })
+            .map_err(|e| match e {
+                PersistentLedgerError::Denied { .. } => anyhow::anyhow!(
+                    "Cannot load blocks from {}. {} Start with --recover to drop it and later blocks.",
+                    path,
+                    e
+                ),
+                PersistentLedgerError::Corrupt { .. } => anyhow::anyhow!(
+                    "Cannot load blocks from {}. {} Start with --recover to drop the record and later ones.",
+                    path,
+                    e
+                ),
+                PersistentLedgerError::UnsupportedVersion(_) => anyhow::anyhow!(
+                    "Cannot load blocks from {}. {} Start with --recover to download the chain again.",
+                    path,
+                    e
+                ),
+                e => anyhow::anyhow!("Cannot open block database in {}. {}", path, e),
+            })?;
+            match ledger.search_latest_block() {
+                Some(block) => info!(
+                    "Loaded {} blocks from {}. Best block height: {}, Digest: {}",
+                    ledger.blocks().count(),
+                    path,
+                    block.height(),
+                    block.digest().fmt_short()
+                ),
+                None => info!("Block database in {} is empty.", path),
+            }
+            ledger
+        }
+        None => PersistentLedger::in_memory(ledger),
+    };
+    let local_height = latest_height(&ledger);
+    let ledger = Arc::new(Mutex::new(ledger));
+    let utxo_db = match &arg.utxo_db {
+        Some(path) => {
+            let config = UtxoDbConfig {
+                cache_size: arg.utxo_cache_size,
+                flush_blocks: arg.utxo_flush_blocks.max(1),
+                flush_interval: Duration::from_secs(arg.utxo_flush_secs),
+            };
+            let utxo_db = {
+                let ledger = ledger.lock().expect("Lock failure");
+                open_utxo_db(path, config, &ledger, arg.recover)?
+            };
+            match utxo_db.tip() {
+                Some((height, digest)) => info!(
+                    "Opened UTXO database at block {} ({}).",
+                    height,
+                    digest.fmt_short()
+                ),
+                None => info!("Created UTXO database in {}.", path),
+            }
+            Some(Arc::new(Mutex::new(utxo_db)))
+        }
+        None => None,
+    };
+    info!("Spawning connection functionality...");
+
+    // Without subscription, brokers do not forward transactions to this node at all
+    let transaction_subscriber = match arg.blocksonly {
+        true => None,
+        false => Some(TopicSubscriber::<CreateTransaction>::connect_to(&brokers).await?),
+    };
+    let block_subscriber = TopicSubscriber::<NotifyBlock>::connect_to(&brokers).await?;
+    let block_publisher = TopicPublisher::<NotifyBlock>::connect_to(&brokers).await?;
+    let block_height_publisher = TopicPublisher::<NotifyBlockHeight>::connect_to(&brokers).await?;
+    let block_height_subscriber =
+        TopicSubscriber::<NotifyBlockHeight>::connect_to(&brokers).await?;
+    let utxo_publisher = TopicPublisher::<RespondUtxoByAddress>::connect_to(&brokers).await?;
+    let utxo_subscriber = TopicSubscriber::<RequestUtxoByAddress>::connect_to(&brokers).await?;
+    let total_supply_server = ServiceServer::<QueryTotalSupply>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let utxo_server = ServiceServer::<QueryUtxoByAddress>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let balances_server = ServiceServer::<QueryBalances>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let merkle_proof_server = ServiceServer::<QueryMerkleProof>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let header_server = ServiceServer::<QueryHeaderByHeight>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let blocks_by_range_server = ServiceServer::<QueryBlocksByRange>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let block_server = ServiceServer::<QueryBlockByHeight>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let chain_info_server = ServiceServer::<QueryChainInfo>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let deployments_server = ServiceServer::<QueryDeployments>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let alerts_server = ServiceServer::<QueryAlerts>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let mempool_usage_server = ServiceServer::<QueryMempoolUsage>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let mempool_info_server = ServiceServer::<QueryMempoolInfo>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let raw_mempool_server = ServiceServer::<QueryRawMempool>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let search_server = match arg.search_index {
+        true => Some(
+            ServiceServer::<QuerySearch>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+        ),
+        false => None,
+    };
+    let chain_stats_server = match arg.chain_stats {
+        true => Some(
+            ServiceServer::<QueryChainStats>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+        ),
+        false => None,
+    };
+    let chain_analysis_servers = match arg.chain_analysis {
+        true => Some((
+            ServiceServer::<QueryBalanceHistory>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+            ServiceServer::<QueryRichList>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+            ServiceServer::<QueryDailyVolume>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+            ServiceServer::<QueryUtxoAges>::connect_to(&brokers)
+                .await?
+                .with_layers(layers.clone()),
+        )),
+        false => None,
+    };
+    let header_publisher = TopicPublisher::<NotifyBlockHeader>::connect_to(&brokers).await?;
+    let header_replay_publisher = TopicPublisher::<NotifyBlockHeader>::connect_to(&brokers).await?;
+    // Nodes sharing --rpc-tokens can serve each other blocks
+    let download_rpc = match arg.rpc_tokens.first() {
+        Some(token) => RpcClient::new(&brokers).with_token(token.clone()),
+        None => RpcClient::new(&brokers),
+    };
+    let rejection_publisher = TopicPublisher::<NotifyBlockRejected>::connect_to(&brokers).await?;
+    let transaction_publisher = TopicPublisher::<CreateTransaction>::connect_to(&brokers).await?;
+    let send_transaction_server = ServiceServer::<SendTransaction>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let transaction_status_server = ServiceServer::<QueryTransactionStatus>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let timers_server = ServiceServer::<QueryTimers>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let set_timers_server = ServiceServer::<SetTimers>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let sync_progress_server = ServiceServer::<QuerySyncProgress>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let block_propagation_server = ServiceServer::<QueryBlockPropagation>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let bans_server = ServiceServer::<QueryBans>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+    let unban_server = ServiceServer::<Unban>::connect_to(&brokers)
+        .await?
+        .with_layers(layers.clone());
+
+    let (block_publish_sender, block_publish_receiver) = tokio::sync::mpsc::channel(10);
+    let transaction_queue = Arc::new(DropOldestQueue::new(arg.transaction_queue));
+    let seen_ttl = Duration::from_secs(arg.seen_ttl);
+    let seen_blocks = Arc::new(Mutex::new(SeenCache::new(arg.seen_capacity, seen_ttl)));
+    let seen_transactions = Arc::new(Mutex::new(SeenCache::new(arg.seen_capacity, seen_ttl)));
+    let (block_queue_sender, block_queue_receiver) = tokio::sync::mpsc::channel(arg.block_queue);
+    let (download_sender, download_receiver) = tokio::sync::mpsc::channel(1);
+    let (header_replay_sender, header_replay_receiver) = tokio::sync::mpsc::channel(1);
+    let block_gate = Arc::new(PriorityGate::new());
+    let rejections = Arc::new(Mutex::new(HashMap::new()));
+    let propagation = Arc::new(Mutex::new(PropagationTracker::new(arg.propagation_blocks)));
+    let tracker = Arc::new(Mutex::new(TransactionTracker::new(
+        arg.tracked_transactions,
+    )));
+    let (relay_sender, relay_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (connected_sender, connected_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let webhooks = match &arg.webhooks {
+        Some(path) => load_webhooks(path)?,
+        None => vec![],
+    };
+    info!("Loaded {} webhooks.", webhooks.len());
+    let audit = match &arg.audit_log {
+        Some(path) => AuditLog::open(path, arg.audit_log_bytes, arg.audit_log_files)?,
+        None => AuditLog::disabled(),
+    };
+    let audit = Arc::new(audit);
+    let peers = Arc::new(Mutex::new(HashMap::new()));
+    let mut bans = PeerBans::open(arg.ban_list, Duration::from_secs(arg.ban_secs))?;
+    for node in arg.banned_nodes {
+        bans.ban(node, "Given by --banned-nodes", None);
+    }
+    let bans = Arc::new(Mutex::new(bans));
+    let mut sync = SyncTracker::new(arg.ibd_threshold);
+    sync.set_local_height(local_height, Instant::now());
+    let sync = Arc::new(Mutex::new(sync));
+
+    info!("Spawning threads...");
+
+    if arg.blocksonly {
+        info!("Blocks-only mode. Transactions from other nodes are not accepted.");
+    }
+    let transaction_subsctiber_join_handle = transaction_subscriber.map(|subscriber| {
+        spawn_transaction_subscriber(subscriber, transaction_queue.clone(), audit.clone())
+    });
+    let transaction_workers = match arg.blocksonly {
+        true => 0,
+        false => arg.transaction_workers,
+    };
+    let transaction_worker_join_handles = (0..transaction_workers)
+        .map(|_| {
+            spawn_transaction_worker(
+                transaction_queue.clone(),
+                incoming_transactions.clone(),
+                seen_transactions.clone(),
+                tracker.clone(),
+                audit.clone(),
+                ledger.clone(),
+                block_gate.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let queue_monitor_join_handle = spawn_queue_monitor(
+        transaction_queue,
+        block_queue_sender.clone(),
+        rejections.clone(),
+        propagation.clone(),
+    );
+    let block_subscriber_join_handle = spawn_block_subscriber(
+        block_subscriber,
+        block_queue_sender,
+        block_gate.clone(),
+        propagation.clone(),
+    );
+    let block_worker_join_handle = spawn_block_worker(
+        block_queue_receiver,
+        rejection_publisher,
+        ledger.clone(),
+        incoming_transactions.clone(),
+        seen_blocks.clone(),
+        rejections,
+        connected_sender.clone(),
+        audit.clone(),
+        sync.clone(),
+        arg.assume_valid.clone(),
+        block_gate,
+        propagation.clone(),
+    );
+    let block_height_publisher_join_handle = spawn_block_height_publisher(
+        block_height_publisher,
+        ledger.clone(),
+        arg.prune_depth,
+        arg.min_fee_rate,
+        arg.blocksonly,
+        node_key,
+        timers.clone(),
+    );
+    let block_height_subscriber_join_handle = spawn_block_height_subscriber(
+        block_height_subscriber,
+        header_replay_sender,
+        download_sender,
+        ledger.clone(),
+        arg.prune_depth,
+        node_id,
+        bans.clone(),
+        peers.clone(),
+        sync.clone(),
+    );
+    let header_replayer_join_handle = spawn_header_replayer(
+        header_replay_publisher,
+        header_replay_receiver,
+        ledger.clone(),
+    );
+    let block_downloader_join_handle = spawn_block_downloader(
+        download_rpc,
+        download_receiver,
+        ledger.clone(),
+        incoming_transactions.clone(),
+        seen_blocks.clone(),
+        connected_sender.clone(),
+        audit.clone(),
+        sync.clone(),
+        arg.assume_valid.clone(),
+    );
+    let peer_monitor_join_handle = spawn_peer_monitor(peers.clone(), timers.clone(), sync.clone());
+    let mining_join_handle = spawn_mining_join_handle(
+        incoming_transactions.clone(),
         block_publish_sender,
         ledger.clone(),
         secret_address,
         arg.mine_genesis_block,
+        seen_blocks,
+        connected_sender,
+        audit,
+        timers.clone(),
+        sync.clone(),
+        Duration::from_millis(arg.mining_attempt_ms),
+        propagation.clone(),
+        NonceIter::new(match arg.mining_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }),
+    );
+    let webhook_join_handle = spawn_webhook_dispatcher(webhooks, connected_receiver);
+    let block_publisher_join_handle = spawn_block_publisher(
+        block_publisher,
+        header_publisher,
+        block_publish_receiver,
+        ledger.clone(),
+        propagation.clone(),
+    );
+    let utxo_pubsub_join_handle = spawn_utxo_pubsub(
+        utxo_publisher,
+        utxo_subscriber,
+        ledger.clone(),
+        utxo_db.clone(),
+    );
+    let utxo_server_join_handle = spawn_utxo_server(utxo_server, ledger.clone(), utxo_db.clone());
+    let balances_join_handle = spawn_balances_server(
+        balances_server,
+        ledger.clone(),
+        utxo_db.clone(),
+        incoming_transactions.clone(),
+    );
+    let utxo_db_join_handle = utxo_db.map(|utxo_db| spawn_utxo_db_sync(utxo_db, ledger.clone()));
+    let stale_branch_pruner_join_handle =
+        spawn_stale_branch_pruner(ledger.clone(), arg.stale_branch_depth);
+    let total_supply_join_handle = spawn_total_supply_server(total_supply_server, ledger.clone());
+    let merkle_proof_join_handle = spawn_merkle_proof_server(merkle_proof_server, ledger.clone());
+    let chain_info_join_handle =
+        spawn_chain_info_server(chain_info_server, ledger.clone(), arg.prune_depth);
+    let deployments_join_handle = spawn_deployments_server(deployments_server, ledger.clone());
+    let deployment_monitor_join_handle = spawn_deployment_monitor(ledger.clone());
+    let alerts = Arc::new(Mutex::new(vec![]));
+    let watchdog_config = WatchdogConfig {
+        stall_after: Duration::from_secs(arg.target_block_secs) * arg.stall_alert_intervals,
+        mempool_age: Duration::from_secs(arg.mempool_alert_secs),
+        webhook: arg.alert_webhook.clone(),
+    };
+    let watchdog_join_handle = spawn_watchdog(
+        watchdog_config,
+        ledger.clone(),
+        incoming_transactions.clone(),
+        peers.clone(),
+        alerts.clone(),
     );
-    let block_publisher_join_handle =
-        spawn_block_publisher(block_publisher, block_publish_receiver);
-    let utxo_pubsub_join_handle = spawn_utxo_pubsub(utxo_publisher, utxo_subscriber, ledger);
+    let alerts_join_handle = spawn_alerts_server(alerts_server, alerts);
+    let mempool_usage_join_handle =
+        spawn_mempool_usage_server(mempool_usage_server, incoming_transactions.clone());
+    let mempool_info_join_handle =
+        spawn_mempool_info_server(mempool_info_server, incoming_transactions.clone());
+    let raw_mempool_join_handle =
+        spawn_raw_mempool_server(raw_mempool_server, incoming_transactions.clone());
+    let mempool_rebroadcaster_join_handle = spawn_mempool_rebroadcaster(
+        incoming_transactions.clone(),
+        relay_sender.clone(),
+        timers.clone(),
+        sync.clone(),
+    );
+    let send_transaction_join_handle = spawn_send_transaction_server(
+        send_transaction_server,
+        relay_sender,
+        incoming_transactions.clone(),
+        seen_transactions.clone(),
+        tracker.clone(),
+        ledger.clone(),
+    );
+    let transaction_relay_join_handle =
+        spawn_transaction_relay(transaction_publisher, relay_receiver, sync.clone(), peers);
+    let transaction_status_join_handle = spawn_transaction_status_server(
+        transaction_status_server,
+        ledger.clone(),
+        incoming_transactions.clone(),
+        tracker,
+    );
+    let timers_join_handle = spawn_timers_server(timers_server, timers.clone());
+    let set_timers_join_handle = spawn_set_timers_server(set_timers_server, timers);
+    let sync_progress_join_handle = spawn_sync_progress_server(sync_progress_server, sync);
+    let block_propagation_join_handle =
+        spawn_block_propagation_server(block_propagation_server, propagation);
+    let bans_join_handle = spawn_bans_server(bans_server, bans.clone());
+    let unban_join_handle = spawn_unban_server(unban_server, bans);
+    let header_server_join_handle = {
+        let ledger = ledger.clone();
+        spawn_header_server(header_server, move |height| {
+            let snapshot = ledger.lock().expect("Lock failure").snapshot();
+            snapshot
+                .block_at(height)
+                .map(|block| block.header().clone())
+        })
+    };
+    let block_server_join_handle =
+        spawn_block_server(block_server, ledger.clone(), arg.prune_depth);
+    let blocks_by_range_join_handle =
+        spawn_blocks_by_range_server(blocks_by_range_server, ledger.clone(), arg.prune_depth);
+    let search_join_handles = search_server.map(|server| {
+        let index = Arc::new(Mutex::new(SearchIndex::new()));
+        (
+            spawn_search_index_sync(index.clone(), ledger.clone()),
+            spawn_search_server(server, index),
+        )
+    });
+    let chain_stats_join_handles = chain_stats_server.map(|server| {
+        let stats = Arc::new(Mutex::new(ChainStats::new()));
+        (
+            spawn_chain_stats_sync(stats.clone(), ledger.clone()),
+            spawn_chain_stats_server(server, stats),
+        )
+    });
+    let chain_analysis_join_handles =
+        chain_analysis_servers.map(|(balance_history, rich_list, daily_volume, utxo_ages)| {
+            vec![
+                spawn_chain_analysis_server(
+                    balance_history,
+                    ledger.clone(),
+                    |analysis, address| analysis.balance_history(&address),
+                ),
+                spawn_chain_analysis_server(rich_list, ledger.clone(), |analysis, count| {
+                    analysis.rich_list(count.min(MAX_RICH_LIST))
+                }),
+                spawn_chain_analysis_server(daily_volume, ledger.clone(), |analysis, ()| {
+                    analysis.daily_volume()
+                }),
+                spawn_chain_analysis_server(utxo_ages, ledger.clone(), |analysis, ()| {
+                    analysis.utxo_age_distribution()
+                }),
+            ]
+        });
+    let chain_events_join_handle = arg
+        .chain_events
+        .map(|sink| spawn_chain_event_streamer(sink, ledger.clone()));
+    let chain_exporter_join_handle = arg
+        .export_chain
+        .map(|path| spawn_chain_exporter(path, ledger));
 
     info!("Initialization done. A blockchain-fullnode runnning...");
 
-    transaction_subsctiber_join_handle.await?;
+    if let Some(handle) = transaction_subsctiber_join_handle {
+        handle.await?;
+    }
+    for handle in transaction_worker_join_handles {
+        handle.await?;
+    }
+    queue_monitor_join_handle.await?;
     block_subscriber_join_handle.await?;
+    block_worker_join_handle.await?;
     block_height_publisher_join_handle.await?;
     block_height_subscriber_join_handle.await?;
+    header_replayer_join_handle.await?;
+    block_downloader_join_handle.await?;
+    peer_monitor_join_handle.await?;
     mining_join_handle.await?;
     block_publisher_join_handle.await?;
     utxo_pubsub_join_handle.await?;
+    utxo_server_join_handle.await?;
+    balances_join_handle.await?;
+    stale_branch_pruner_join_handle.await?;
+    total_supply_join_handle.await?;
+    merkle_proof_join_handle.await?;
+    header_server_join_handle.await?;
+    block_server_join_handle.await?;
+    blocks_by_range_join_handle.await?;
+    chain_info_join_handle.await?;
+    deployments_join_handle.await?;
+    deployment_monitor_join_handle.await?;
+    watchdog_join_handle.await?;
+    alerts_join_handle.await?;
+    mempool_usage_join_handle.await?;
+    mempool_info_join_handle.await?;
+    raw_mempool_join_handle.await?;
+    send_transaction_join_handle.await?;
+    webhook_join_handle.await?;
+    transaction_relay_join_handle.await?;
+    transaction_status_join_handle.await?;
+    mempool_rebroadcaster_join_handle.await?;
+    timers_join_handle.await?;
+    set_timers_join_handle.await?;
+    sync_progress_join_handle.await?;
+    block_propagation_join_handle.await?;
+    bans_join_handle.await?;
+    unban_join_handle.await?;
+    if let Some((sync_handle, server_handle)) = search_join_handles {
+        sync_handle.await?;
+        server_handle.await?;
+    }
+    if let Some((sync_handle, server_handle)) = chain_stats_join_handles {
+        sync_handle.await?;
+        server_handle.await?;
+    }
+    for handle in chain_analysis_join_handles.into_iter().flatten() {
+        handle.await?;
+    }
+    if let Some(handle) = chain_events_join_handle {
+        handle.await?;
+    }
+    if let Some(handle) = chain_exporter_join_handle {
+        handle.await?;
+    }
+    if let Some(handle) = utxo_db_join_handle {
+        handle.await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Bounded queue which drops the oldest item when full, so that pushing never waits.
+#[derive(Debug)]
+pub struct DropOldestQueue<T> {
+    inner: Mutex<Inner<T>>,
+    notify: Notify,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    items: VecDeque<T>,
+    dropped: u64,
+}
+
+impl<T> DropOldestQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                items: VecDeque::with_capacity(capacity),
+                dropped: 0,
+            }),
+            notify: Notify::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of items waiting.
+    pub fn depth(&self) -> usize {
+        self.inner.lock().expect("Lock failure").items.len()
+    }
+
+    /// Number of items dropped since creation.
+    pub fn dropped(&self) -> u64 {
+        self.inner.lock().expect("Lock failure").dropped
+    }
+
+    /// Push an item, returning the oldest one if it was dropped to make room.
+    pub fn push(&self, item: T) -> Option<T> {
+        let dropped = {
+            let mut inner = self.inner.lock().expect("Lock failure");
+            let dropped = if inner.items.len() >= self.capacity {
+                inner.dropped += 1;
+                inner.items.pop_front()
+            } else {
+                None
+            };
+            inner.items.push_back(item);
+            dropped
+        };
+        self.notify.notify_one();
+        dropped
+    }
+
+    /// Wait for the oldest item.
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.inner.lock().expect("Lock failure").items.pop_front() {
+                return item;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Number of received high priority items not handled yet, which low priority work waits for.
+/// Lets blocks be handled ahead of a flood of transactions.
+#[derive(Debug, Default)]
+pub struct PriorityGate {
+    pending: AtomicUsize,
+    idle: Notify,
+}
+
+impl PriorityGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of high priority items not handled yet.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Count a received high priority item, until the guard of `handle` drops.
+    pub fn enter(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Guard which counts one high priority item as handled when dropped.
+    pub fn handle(&self) -> Handling<'_> {
+        Handling(self)
+    }
+
+    /// Wait until no high priority item is pending.
+    pub async fn wait_idle(&self) {
+        loop {
+            // Created before the check, so that a notification between them is not missed
+            let idle = self.idle.notified();
+            if self.pending() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    fn leave(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+/// Returned by `PriorityGate::handle`.
+pub struct Handling<'a>(&'a PriorityGate);
+
+impl Drop for Handling<'_> {
+    fn drop(&mut self) {
+        self.0.leave();
+    }
+}
@@ -0,0 +1,23 @@
+use serde::Deserialize;
+use std::path::Path;
+
+/// Auth of privileged services, such as SendTransaction and SetTimers.
+/// Read-only queries stay open to anyone.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcAuth {
+    /// Bearer tokens accepted by privileged services
+    pub tokens: Vec<String>,
+}
+
+/// Load auth from a JSON file such as `{"tokens": ["<token>"]}`.
+pub fn load_rpc_auth<P: AsRef<Path>>(path: P) -> anyhow::Result<RpcAuth> {
+    let json = std::fs::read_to_string(path)?;
+    let auth: RpcAuth = serde_json::from_str(&json)?;
+    if auth.tokens.iter().any(String::is_empty) {
+        anyhow::bail!("RPC tokens must not be empty.");
+    }
+    if auth.tokens.is_empty() {
+        anyhow::bail!("No RPC token is given, so privileged services would refuse every request.");
+    }
+    Ok(auth)
+}
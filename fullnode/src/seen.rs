@@ -0,0 +1,74 @@
+use blockchain_core::digest::BlockDigest;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Digests of recently verified blocks or transactions.
+/// Holds up to `capacity` digests, forgetting the least recently seen one first,
+/// and forgets digests seen more than `ttl` ago.
+#[derive(Debug)]
+pub struct SeenCache {
+    capacity: usize,
+    ttl: Duration,
+    /// Last seen time and generation of each digest
+    entries: HashMap<BlockDigest, (Instant, u64)>,
+    /// Digests in order of access. Stale generations are skipped on eviction.
+    order: VecDeque<(BlockDigest, u64)>,
+    generation: u64,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            generation: 0,
+        }
+    }
+
+    /// Whether the digest was seen within TTL. A hit counts as a recent use.
+    pub fn contains(&mut self, digest: &BlockDigest) -> bool {
+        match self.entries.get(digest) {
+            Some((seen_at, _)) if seen_at.elapsed() <= self.ttl => {
+                self.touch(digest.clone());
+                true
+            }
+            Some(_) => {
+                self.entries.remove(digest);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, digest: BlockDigest) {
+        self.touch(digest);
+
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some((digest, generation)) => {
+                    if self.entries.get(&digest).map(|(_, g)| *g) == Some(generation) {
+                        self.entries.remove(&digest);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn touch(&mut self, digest: BlockDigest) {
+        self.generation += 1;
+        self.entries
+            .insert(digest.clone(), (Instant::now(), self.generation));
+        self.order.push_back((digest, self.generation));
+
+        // Order keeps stale generations until they reach the front
+        if self.order.len() > self.capacity * 2 {
+            let entries = &self.entries;
+            self.order.retain(|(digest, generation)| {
+                entries.get(digest).map(|(_, g)| g) == Some(generation)
+            });
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use blockchain_core::ledger::MAX_FUTURE_DRIFT_SECS;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_net::impl_zeromq::topic_history_from;
+use blockchain_net::topic::NotifyBlockHeader;
+use std::fmt::{self, Display, Formatter};
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A proxy slower than this to answer is treated as unreachable.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Free space below which a directory the node writes to is warned about.
+const MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// What the node would read and write if it started with the same arguments.
+#[derive(Debug, Clone, Default)]
+pub struct SelftestTargets {
+    /// Wallet address of the miner, unless header-only
+    pub address: Option<PathBuf>,
+    /// Node key, created on the first real start if missing
+    pub node_key: PathBuf,
+    /// Files and directories written by the node, by the option naming them
+    pub outputs: Vec<(&'static str, PathBuf)>,
+    pub brokers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// The node starts, but may run into trouble.
+    Warn,
+    /// The node would fail to start or to work.
+    Fail,
+    /// Nothing to check against.
+    Skip,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        };
+        f.pad(status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Results of every check, printed one per line.
+#[derive(Debug, Clone, Default)]
+pub struct SelftestReport {
+    pub checks: Vec<Check>,
+}
+
+impl SelftestReport {
+    /// Whether no check failed. Warnings pass.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+}
+
+impl Display for SelftestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in self.checks.iter() {
+            writeln!(
+                f,
+                "[{:<4}] {:<width$}  {}",
+                check.status,
+                check.name,
+                check.detail,
+                width = width
+            )?;
+        }
+        let count = |status| self.checks.iter().filter(|c| c.status == status).count();
+        writeln!(
+            f,
+            "{} ok, {} warnings, {} failures, {} skipped",
+            count(CheckStatus::Ok),
+            count(CheckStatus::Warn),
+            count(CheckStatus::Fail),
+            count(CheckStatus::Skip)
+        )
+    }
+}
+
+/// Check the environment of the node without changing it, except for a probe file in each
+/// directory written to, which is removed at once.
+pub async fn run_selftest(targets: &SelftestTargets) -> SelftestReport {
+    let mut checks = vec![];
+
+    if let Some(path) = &targets.address {
+        checks.push(check_key("address", path));
+    }
+    checks.push(match targets.node_key.exists() {
+        true => check_key("node key", &targets.node_key),
+        false => {
+            let dir = parent_dir(&targets.node_key);
+            match probe_writable(dir) {
+                Ok(()) => Check::new(
+                    "node key",
+                    CheckStatus::Ok,
+                    format!("{} is created on start", targets.node_key.display()),
+                ),
+                Err(e) => Check::new(
+                    "node key",
+                    CheckStatus::Fail,
+                    format!("Cannot create {}. {}", targets.node_key.display(), e),
+                ),
+            }
+        }
+    });
+
+    let mut dirs = vec![parent_dir(&targets.node_key).to_path_buf()];
+    for (option, path) in targets.outputs.iter() {
+        // Directory options such as --utxo-db are written into, other files next to
+        let dir = match path.is_dir() {
+            true => path.as_path(),
+            false => parent_dir(path),
+        };
+        checks.push(match probe_writable(dir) {
+            Ok(()) => Check::new(
+                *option,
+                CheckStatus::Ok,
+                format!("{} is writable", dir.display()),
+            ),
+            Err(e) => Check::new(
+                *option,
+                CheckStatus::Fail,
+                format!("Cannot write to {}. {}", dir.display(), e),
+            ),
+        });
+        dirs.push(dir.to_path_buf());
+    }
+    dirs.sort();
+    dirs.dedup();
+    checks.extend(dirs.iter().map(|dir| check_disk_space(dir)));
+
+    let mut latest_header = None;
+    for broker in targets.brokers.iter() {
+        let name = match broker.is_empty() {
+            true => "proxy (default)".to_string(),
+            false => format!("proxy {}", broker),
+        };
+        let history = tokio::time::timeout(
+            PROXY_TIMEOUT,
+            topic_history_from::<NotifyBlockHeader>(&[broker]),
+        )
+        .await;
+        checks.push(match history {
+            Ok(Ok(headers)) => {
+                let latest = headers.iter().map(|h| h.timestamp()).max();
+                latest_header = latest_header.max(latest);
+                Check::new(
+                    name,
+                    CheckStatus::Ok,
+                    format!("Reachable, relayed {} recent headers", headers.len()),
+                )
+            }
+            Ok(Err(e)) => Check::new(name, CheckStatus::Fail, format!("Unreachable. {}", e)),
+            Err(_) => Check::new(
+                name,
+                CheckStatus::Fail,
+                format!(
+                    "No answer in {} seconds. Is the proxy running?",
+                    PROXY_TIMEOUT.as_secs()
+                ),
+            ),
+        });
+    }
+    checks.push(check_clock(latest_header));
+
+    SelftestReport { checks }
+}
+
+fn check_key(name: &str, path: &Path) -> Check {
+    match bcaddr::read_address(path) {
+        Ok(address) => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("{} holds {}", path.display(), address.to_public_address()),
+        ),
+        Err(e) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("Cannot read {}. {}", path.display(), e),
+        ),
+    }
+}
+
+fn check_disk_space(dir: &Path) -> Check {
+    let name = format!("disk space {}", dir.display());
+    match fs2::available_space(dir) {
+        Ok(bytes) if bytes < MIN_FREE_BYTES => Check::new(
+            name,
+            CheckStatus::Warn,
+            format!("Only {} MiB free", bytes / 1024 / 1024),
+        ),
+        Ok(bytes) => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("{} MiB free", bytes / 1024 / 1024),
+        ),
+        Err(e) => Check::new(name, CheckStatus::Skip, format!("Unknown. {}", e)),
+    }
+}
+
+/// Compare the local clock with the latest header relayed by other nodes.
+/// A clock far behind them makes the node deny their blocks as coming from the future.
+fn check_clock(latest_header: Option<Timestamp>) -> Check {
+    let now = Timestamp::now();
+    let latest = match latest_header {
+        Some(latest) => latest,
+        None => {
+            return Check::new(
+                "clock",
+                CheckStatus::Skip,
+                "No recent header of other nodes to compare with",
+            )
+        }
+    };
+    match latest.duration_since(now) {
+        Some(ahead) if ahead.as_secs() as i64 > MAX_FUTURE_DRIFT_SECS => Check::new(
+            "clock",
+            CheckStatus::Fail,
+            format!(
+                "Latest header is {} seconds ahead of the local clock, beyond the allowed drift",
+                ahead.as_secs()
+            ),
+        ),
+        Some(ahead) => Check::new(
+            "clock",
+            CheckStatus::Ok,
+            format!("Latest header is {} seconds ahead", ahead.as_secs()),
+        ),
+        None => Check::new(
+            "clock",
+            CheckStatus::Ok,
+            format!("Latest header is at {}", latest),
+        ),
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Create and remove a file in `dir`.
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".bcfnode-selftest-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(&probe)
+}
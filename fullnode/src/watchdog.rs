@@ -0,0 +1,160 @@
+use crate::Peers;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::mempool::Mempool;
+use blockchain_core::persistent_ledger::PersistentLedger;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::BlockHeight;
+use blockchain_net::sync::Alert;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Time between checks of the conditions alerted.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Limits beyond which the node raises alerts.
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    /// Time without a new block before a chain stall is alerted
+    pub stall_after: Duration,
+    /// Wait of the oldest mempool transaction before it is alerted
+    pub mempool_age: Duration,
+    /// URL posted an `AlertEvent` when an alert is raised or resolved
+    pub webhook: Option<String>,
+}
+
+/// JSON body posted to the alert webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent<'a> {
+    #[serde(flatten)]
+    pub alert: &'a Alert,
+    /// `false` once the condition is over
+    pub raised: bool,
+}
+
+/// What the watchdog remembers between checks.
+struct Watch {
+    tip: Option<BlockDigest>,
+    tip_changed: Instant,
+    /// Whether any node has announced since the start, so that a lone node raises no alert
+    had_peers: bool,
+}
+
+impl Watch {
+    fn new() -> Self {
+        Self {
+            tip: None,
+            tip_changed: Instant::now(),
+            had_peers: false,
+        }
+    }
+
+    /// Alerts for the current state of the node, whose best chain ends at `tip`.
+    fn check(
+        &mut self,
+        config: &WatchdogConfig,
+        tip: Option<(BlockHeight, BlockDigest)>,
+        mempool: &Mempool,
+        peers: usize,
+    ) -> Vec<Alert> {
+        let mut alerts = vec![];
+
+        let (height, digest) = tip.unzip();
+        if digest != self.tip {
+            self.tip = digest;
+            self.tip_changed = Instant::now();
+        }
+        // Miners of this chain wait for transactions, so a chain without them is idle rather than stalled
+        let stalled = self.tip_changed.elapsed();
+        if stalled >= config.stall_after && !mempool.is_empty() {
+            alerts.push(Alert::ChainStall {
+                height,
+                secs: stalled.as_secs(),
+            });
+        }
+
+        let now = Timestamp::now();
+        let oldest = mempool
+            .transactions()
+            .filter_map(|tx| Some((tx.txid(), now.duration_since(tx.timestamp())?)))
+            .max_by_key(|(_, age)| *age);
+        if let Some((txid, age)) = oldest.filter(|(_, age)| *age >= config.mempool_age) {
+            alerts.push(Alert::StaleMempool {
+                txid,
+                secs: age.as_secs(),
+            });
+        }
+
+        if peers > 0 {
+            self.had_peers = true;
+        } else if self.had_peers {
+            alerts.push(Alert::NoPeers);
+        }
+
+        alerts
+    }
+}
+
+/// Raise alerts on chain stalls, stale mempool transactions and losing all other nodes.
+/// Each alert is logged and posted to the webhook once when raised and once when resolved,
+/// while `alerts` always holds the ones in effect.
+pub fn spawn_watchdog(
+    config: WatchdogConfig,
+    ledger: Arc<Mutex<PersistentLedger>>,
+    mempool: Arc<Mutex<Mempool>>,
+    peers: Arc<Mutex<Peers>>,
+    alerts: Arc<Mutex<Vec<Alert>>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut watch = Watch::new();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let peers = peers.lock().expect("Lock failure").len();
+            let tip = ledger
+                .lock()
+                .expect("Lock failure")
+                .search_latest_block()
+                .map(|block| (block.height(), block.digest().clone()));
+            let current = {
+                let mempool = mempool.lock().expect("Lock failure");
+                watch.check(&config, tip, &mempool, peers)
+            };
+            let previous =
+                std::mem::replace(&mut *alerts.lock().expect("Lock failure"), current.clone());
+            for alert in current.iter() {
+                if !previous.iter().any(|a| a.is_same_kind(alert)) {
+                    error!("Alert: {}.", alert);
+                    notify(&client, &config, alert, true);
+                }
+            }
+            for alert in previous.iter() {
+                if !current.iter().any(|a| a.is_same_kind(alert)) {
+                    info!("Resolved alert: {}.", alert);
+                    notify(&client, &config, alert, false);
+                }
+            }
+        }
+    })
+}
+
+/// Post the alert to the webhook, if any, without holding up the watchdog.
+fn notify(client: &reqwest::Client, config: &WatchdogConfig, alert: &Alert, raised: bool) {
+    let url = match &config.webhook {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let body = serde_json::to_string(&AlertEvent { alert, raised }).expect("Alert is serializable");
+    let request = client
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body);
+    tokio::spawn(async move {
+        if let Err(e) = request.send().await.and_then(|res| res.error_for_status()) {
+            warn!("Alert webhook {} failed. {}", url, e);
+        }
+    });
+}
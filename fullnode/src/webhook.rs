@@ -0,0 +1,126 @@
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::{Address, BlockHeight, Coin, VerifiedBlock};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+
+/// Deliveries are given up after this many failed attempts.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled after each failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// URL notified when the address receives or spends coins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub address: Address,
+    pub url: String,
+}
+
+/// Load webhooks from a JSON array of `{"address": "<hex>", "url": "<url>"}`.
+pub fn load_webhooks<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Webhook>> {
+    let json = std::fs::read_to_string(path)?;
+    let webhooks = serde_json::from_str(&json)?;
+    Ok(webhooks)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Activity {
+    Received,
+    Spent,
+}
+
+/// JSON body posted to a webhook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressEvent {
+    pub address: Address,
+    pub activity: Activity,
+    pub quantity: Coin,
+    pub txid: BlockDigest,
+    pub block: BlockDigest,
+    pub height: BlockHeight,
+    /// Events are sent when the block joins the ledger, which is its first confirmation.
+    pub confirmations: u64,
+}
+
+/// Activities of watched addresses in a block.
+fn address_events(block: &VerifiedBlock, webhooks: &[Webhook]) -> Vec<(String, AddressEvent)> {
+    let mut events = vec![];
+    for tx in block.transactions() {
+        let activities = tx
+            .inputs()
+            .iter()
+            .map(|i| (i, Activity::Spent))
+            .chain(tx.outputs().iter().map(|o| (o, Activity::Received)));
+        for (transition, activity) in activities {
+            for webhook in webhooks
+                .iter()
+                .filter(|w| &w.address == transition.receiver())
+            {
+                let event = AddressEvent {
+                    address: webhook.address.clone(),
+                    activity,
+                    quantity: transition.quantity(),
+                    txid: tx.txid(),
+                    block: block.digest().clone(),
+                    height: block.height(),
+                    confirmations: 1,
+                };
+                events.push((webhook.url.clone(), event));
+            }
+        }
+    }
+    events
+}
+
+/// POST the event, retrying with exponential backoff.
+async fn deliver(client: reqwest::Client, url: String, event: AddressEvent) {
+    let body = serde_json::to_string(&event).expect("Event is serializable");
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let res = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(_) => {
+                info!(
+                    "Notified {} of transaction {}.",
+                    url,
+                    event.txid.fmt_short()
+                );
+                return;
+            }
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!("Webhook {} failed (attempt {}). {}", url, attempt, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => error!("Give up webhook {} after {} attempts. {}", url, attempt, e),
+        }
+    }
+}
+
+/// Notify webhooks of watched address activities in each block joining the ledger.
+/// Each delivery runs on its own task, so a slow endpoint delays no other.
+pub fn spawn_webhook_dispatcher(
+    webhooks: Vec<Webhook>,
+    mut receiver: UnboundedReceiver<VerifiedBlock>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        while let Some(block) = receiver.recv().await {
+            for (url, event) in address_events(&block, &webhooks) {
+                tokio::spawn(deliver(client.clone(), url, event));
+            }
+        }
+    })
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use blockchain_core::signature::Signature;
+use blockchain_core::{Coin, SecretAddress, Transaction, Transfer, Transition};
+use blockchain_core::{Verified, VerifiedTransaction};
+use blockchain_net::async_net::{Publisher, Subscriber};
+use blockchain_net::impl_zeromq::{TopicPublisher, TopicSubscriber};
+use blockchain_net::topic::{
+    CreateTransaction, NotifyBlock, RequestUtxoByAddress, RespondUtxoByAddress,
+};
+use clap::Parser;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Parser)]
+struct BcLoadgenArgs {
+    /// File path to secret address which owns coins mined by a fullnode.
+    /// Its UTXO funds all generated accounts.
+    #[clap(short, long)]
+    address: String,
+
+    /// Number of generated accounts
+    #[clap(long, default_value_t = 1000)]
+    accounts: usize,
+
+    /// Transactions sent per second
+    #[clap(long, default_value_t = 10.0)]
+    tps: f64,
+
+    /// How long the load lasts, in seconds
+    #[clap(long, default_value_t = 60)]
+    duration: u64,
+
+    /// Seconds after which an unconfirmed transaction is regarded as dropped by the node
+    #[clap(long, default_value_t = 600)]
+    timeout: u64,
+}
+
+/// Generated account and the only UTXO it spends next.
+struct Account {
+    secret: SecretAddress,
+    utxo: Transition<Verified>,
+}
+
+/// Transaction sent but not confirmed yet.
+struct Pending {
+    account: usize,
+    output: Transition<Verified>,
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: usize,
+    dropped: usize,
+    /// Ticks skipped because every account waited for confirmation
+    stalled: usize,
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    fn percentile(&self, p: usize) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let index = (latencies.len() * p / 100).min(latencies.len().checked_sub(1)?);
+        latencies.get(index).copied()
+    }
+
+    fn report(&self, in_flight: usize) {
+        println!(
+            "sent: {}, confirmed: {}, in flight: {}, dropped: {}, stalled: {}, latency p50: {:?}, p95: {:?}",
+            self.sent,
+            self.latencies.len(),
+            in_flight,
+            self.dropped,
+            self.stalled,
+            self.percentile(50),
+            self.percentile(95),
+        );
+    }
+}
+
+/// Split UTXO of the funder into `count` accounts, returning the funding transaction.
+fn fund_accounts(
+    funder: &SecretAddress,
+    utxos: Vec<Transition<Verified>>,
+    count: usize,
+) -> Result<(VerifiedTransaction, Vec<Account>)> {
+    let total = utxos.iter().map(Transition::quantity).sum::<Coin>();
+    let each = Coin::from(total.base_units() / count as u64);
+    if each == Coin::default() {
+        anyhow::bail!("UTXO {} is too small to fund {} accounts.", total, count);
+    }
+
+    let secrets = (0..count)
+        .map(|_| SecretAddress::create())
+        .collect::<Vec<_>>();
+    let outputs = secrets
+        .iter()
+        .map(|secret| Transfer::offer(funder, secret.to_public_address(), each))
+        .collect::<Vec<_>>();
+    let transaction = Transaction::offer(funder, utxos, outputs).verify_transaction()?;
+
+    let accounts = secrets
+        .into_iter()
+        .zip(transaction.outputs().iter().cloned())
+        .map(|(secret, utxo)| Account { secret, utxo })
+        .collect();
+    Ok((transaction, accounts))
+}
+
+/// Send the whole UTXO of the account back to itself.
+fn self_transfer(account: &Account) -> Result<VerifiedTransaction> {
+    let address = account.secret.to_public_address();
+    let output = Transfer::offer(&account.secret, address, account.utxo.quantity());
+    let transaction = Transaction::offer(&account.secret, vec![account.utxo.clone()], vec![output])
+        .verify_transaction()?;
+    Ok(transaction)
+}
+
+/// Blocks are relayed without inputs, whose txids are unknown.
+/// So find a transaction by its first output instead.
+async fn wait_for_confirmation(
+    subscriber: &mut TopicSubscriber<NotifyBlock>,
+    output: &Signature,
+) -> Result<()> {
+    loop {
+        let block = subscriber.recv().await?;
+        let mut outputs = block.transactions().iter().flat_map(|tx| tx.outputs());
+        if outputs.any(|o| o.sign() == output) {
+            println!(
+                "Funding transaction confirmed at height {}.",
+                block.height()
+            );
+            return Ok(());
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = BcLoadgenArgs::parse();
+    if args.accounts == 0 || args.tps <= 0.0 {
+        anyhow::bail!("Both accounts and TPS must be positive.");
+    }
+
+    let funder = bcaddr::read_address(&args.address)?;
+
+    let mut utxo_requester = TopicPublisher::<RequestUtxoByAddress>::connect().await?;
+    let mut utxo_subscriber = TopicSubscriber::<RespondUtxoByAddress>::connect().await?;
+    let mut transaction_publisher = TopicPublisher::<CreateTransaction>::connect().await?;
+    let mut block_subscriber = TopicSubscriber::<NotifyBlock>::connect().await?;
+
+    utxo_requester.publish(&funder.to_public_address()).await?;
+    let utxos = utxo_subscriber
+        .recv()
+        .await?
+        .into_iter()
+        .filter_map(|utxo| utxo.verify().ok())
+        .collect::<Vec<_>>();
+
+    let (funding, accounts) = fund_accounts(&funder, utxos, args.accounts)?;
+    transaction_publisher.publish(&funding).await?;
+    println!(
+        "Funding {} accounts. Wait for confirmation...",
+        accounts.len()
+    );
+    wait_for_confirmation(&mut block_subscriber, funding.outputs()[0].sign()).await?;
+
+    let mut accounts = accounts;
+    let mut ready = (0..accounts.len()).collect::<VecDeque<_>>();
+    let mut pending = HashMap::<Signature, Pending>::new();
+    let mut stats = Stats::default();
+
+    let timeout = Duration::from_secs(args.timeout);
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / args.tps));
+    let mut reporter = tokio::time::interval(Duration::from_secs(5));
+    let deadline = tokio::time::sleep(Duration::from_secs(args.duration));
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = ticker.tick() => {
+                // No account has confirmed UTXO, so the node cannot keep up with the load
+                let index = match ready.pop_front() {
+                    Some(index) => index,
+                    None => {
+                        stats.stalled += 1;
+                        continue;
+                    }
+                };
+                let transaction = self_transfer(&accounts[index])?;
+                transaction_publisher.publish(&transaction).await?;
+                stats.sent += 1;
+
+                let pending_tx = Pending {
+                    account: index,
+                    output: transaction.outputs()[0].clone(),
+                    sent_at: Instant::now(),
+                };
+                pending.insert(transaction.outputs()[0].sign().clone(), pending_tx);
+            }
+            block = block_subscriber.recv() => {
+                let block = block?;
+                for output in block.transactions().iter().flat_map(|tx| tx.outputs()) {
+                    if let Some(confirmed) = pending.remove(output.sign()) {
+                        stats.latencies.push(confirmed.sent_at.elapsed());
+                        accounts[confirmed.account].utxo = confirmed.output;
+                        ready.push_back(confirmed.account);
+                    }
+                }
+            }
+            _ = reporter.tick() => {
+                // The node discards its queued transactions on some failures.
+                // Such transactions never confirm, so their UTXO is reused.
+                let expired = pending
+                    .iter()
+                    .filter(|(_, p)| p.sent_at.elapsed() > timeout)
+                    .map(|(output, _)| output.clone())
+                    .collect::<Vec<_>>();
+                for output in expired {
+                    if let Some(dropped) = pending.remove(&output) {
+                        stats.dropped += 1;
+                        ready.push_back(dropped.account);
+                    }
+                }
+                stats.report(pending.len());
+            }
+        }
+    }
+
+    println!("Finished.");
+    stats.report(pending.len());
+
+    Ok(())
+}
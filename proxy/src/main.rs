@@ -1,21 +1,151 @@
-use blockchain_net::impl_zeromq::TopicProxy;
+mod stats;
+
+use blockchain_net::async_net::Server;
+use blockchain_net::impl_zeromq::{MuxProxy, ServiceProxy, ServiceServer, TopicProxy};
+use blockchain_net::service::*;
 use blockchain_net::topic::*;
+use clap::Parser;
+use stats::{observer, ProxyStats};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use warp::Filter;
+
+#[derive(Debug, Parser)]
+struct ProxyArgs {
+    /// Name of this proxy, which prefixes its endpoints.
+    /// Run proxies with distinct names to let nodes fail over between them.
+    #[clap(long, default_value = "")]
+    broker: String,
+    /// Print message counts and byte rates of each topic every this many seconds.
+    #[clap(long)]
+    stats_interval: Option<u64>,
+    /// Serve the status page of topics over HTTP at this address.
+    #[clap(long)]
+    status_addr: Option<SocketAddr>,
+    /// Number of recent messages kept per topic for late subscribers.
+    #[clap(long, default_value = "16")]
+    history_len: usize,
+    /// Load kept messages from this file at start, and save them at shutdown.
+    #[clap(long)]
+    history_file: Option<PathBuf>,
+    /// Delay every topic message by this many milliseconds, to emulate a slow link.
+    #[clap(long, default_value_t = 0)]
+    latency_ms: u64,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = ProxyArgs::parse();
+
+    let stats = match &args.history_file {
+        Some(path) if path.exists() => ProxyStats::load_history(path, args.history_len)?,
+        _ => ProxyStats::new(args.history_len),
+    };
+    let stats = Arc::new(Mutex::new(stats));
+
+    let latency = Duration::from_millis(args.latency_ms);
+
     println!("Creating proxy...");
-    let proxy_tx = TopicProxy::<CreateTransaction>::bind().await?;
-    let proxy_block = TopicProxy::<NotifyBlock>::bind().await?;
-    let proxy_block_height = TopicProxy::<NotifyBlockHeight>::bind().await?;
-    let utxo_req = TopicProxy::<RequestUtxoByAddress>::bind().await?;
-    let utxo_res = TopicProxy::<RespondUtxoByAddress>::bind().await?;
+    let proxy_tx = TopicProxy::<CreateTransaction>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let proxy_block = TopicProxy::<NotifyBlock>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let proxy_block_height = TopicProxy::<NotifyBlockHeight>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let proxy_block_header = TopicProxy::<NotifyBlockHeader>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let proxy_block_rejected = TopicProxy::<NotifyBlockRejected>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let utxo_req = TopicProxy::<RequestUtxoByAddress>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    // All topics of multiplexed connections
+    let mux = MuxProxy::bind_as(&args.broker).await?;
+    let utxo_res = TopicProxy::<RespondUtxoByAddress>::bind_as(&args.broker)
+        .await?
+        .with_delay(latency);
+    let total_supply = ServiceProxy::<QueryTotalSupply>::bind_as(&args.broker).await?;
+    let utxo_by_address = ServiceProxy::<QueryUtxoByAddress>::bind_as(&args.broker).await?;
+    let balances = ServiceProxy::<QueryBalances>::bind_as(&args.broker).await?;
+    let merkle_proof = ServiceProxy::<QueryMerkleProof>::bind_as(&args.broker).await?;
+    let search = ServiceProxy::<QuerySearch>::bind_as(&args.broker).await?;
+    let header_by_height = ServiceProxy::<QueryHeaderByHeight>::bind_as(&args.broker).await?;
+    let block_by_height = ServiceProxy::<QueryBlockByHeight>::bind_as(&args.broker).await?;
+    let blocks_by_range = ServiceProxy::<QueryBlocksByRange>::bind_as(&args.broker).await?;
+    let chain_info = ServiceProxy::<QueryChainInfo>::bind_as(&args.broker).await?;
+    let chain_stats = ServiceProxy::<QueryChainStats>::bind_as(&args.broker).await?;
+    let balance_history = ServiceProxy::<QueryBalanceHistory>::bind_as(&args.broker).await?;
+    let rich_list = ServiceProxy::<QueryRichList>::bind_as(&args.broker).await?;
+    let daily_volume = ServiceProxy::<QueryDailyVolume>::bind_as(&args.broker).await?;
+    let utxo_ages = ServiceProxy::<QueryUtxoAges>::bind_as(&args.broker).await?;
+    let deployments = ServiceProxy::<QueryDeployments>::bind_as(&args.broker).await?;
+    let alerts = ServiceProxy::<QueryAlerts>::bind_as(&args.broker).await?;
+    let mempool_usage = ServiceProxy::<QueryMempoolUsage>::bind_as(&args.broker).await?;
+    let mempool_info = ServiceProxy::<QueryMempoolInfo>::bind_as(&args.broker).await?;
+    let raw_mempool = ServiceProxy::<QueryRawMempool>::bind_as(&args.broker).await?;
+    let send_transaction = ServiceProxy::<SendTransaction>::bind_as(&args.broker).await?;
+    let transaction_status = ServiceProxy::<QueryTransactionStatus>::bind_as(&args.broker).await?;
+    let timers = ServiceProxy::<QueryTimers>::bind_as(&args.broker).await?;
+    let set_timers = ServiceProxy::<SetTimers>::bind_as(&args.broker).await?;
+    let sync_progress = ServiceProxy::<QuerySyncProgress>::bind_as(&args.broker).await?;
+    let block_propagation = ServiceProxy::<QueryBlockPropagation>::bind_as(&args.broker).await?;
+    let bans = ServiceProxy::<QueryBans>::bind_as(&args.broker).await?;
+    let unban = ServiceProxy::<Unban>::bind_as(&args.broker).await?;
+    let history_server = ServiceServer::<QueryTopicHistory>::bind_as(&args.broker).await?;
 
     println!("Running proxy...");
-    let handle_tx = proxy_tx.start();
-    let handle_block = proxy_block.start();
-    let handle_block_height = proxy_block_height.start();
-    let utxo_req = utxo_req.start();
-    let utxo_res = utxo_res.start();
+    let handle_tx = proxy_tx.start_observed(observer::<CreateTransaction>(&stats));
+    let handle_block = proxy_block.start_observed(observer::<NotifyBlock>(&stats));
+    let handle_block_height =
+        proxy_block_height.start_observed(observer::<NotifyBlockHeight>(&stats));
+    let handle_block_header =
+        proxy_block_header.start_observed(observer::<NotifyBlockHeader>(&stats));
+    let handle_block_rejected =
+        proxy_block_rejected.start_observed(observer::<NotifyBlockRejected>(&stats));
+    let utxo_req = utxo_req.start_observed(observer::<RequestUtxoByAddress>(&stats));
+    let mux = mux.start();
+    let utxo_res = utxo_res.start_observed(observer::<RespondUtxoByAddress>(&stats));
+    let total_supply = total_supply.start();
+    let utxo_by_address = utxo_by_address.start();
+    let balances = balances.start();
+    let merkle_proof = merkle_proof.start();
+    let search = search.start();
+    let header_by_height = header_by_height.start();
+    let block_by_height = block_by_height.start();
+    let blocks_by_range = blocks_by_range.start();
+    let chain_info = chain_info.start();
+    let chain_stats = chain_stats.start();
+    let balance_history = balance_history.start();
+    let rich_list = rich_list.start();
+    let daily_volume = daily_volume.start();
+    let utxo_ages = utxo_ages.start();
+    let deployments = deployments.start();
+    let alerts = alerts.start();
+    let mempool_usage = mempool_usage.start();
+    let mempool_info = mempool_info.start();
+    let raw_mempool = raw_mempool.start();
+    let send_transaction = send_transaction.start();
+    let transaction_status = transaction_status.start();
+    let timers = timers.start();
+    let set_timers = set_timers.start();
+    let sync_progress = sync_progress.start();
+    let block_propagation = block_propagation.start();
+    let bans = bans.start();
+    let unban = unban.start();
+    let history_server = spawn_history_server(history_server, stats.clone());
+    let stats_logger = args
+        .stats_interval
+        .map(|secs| spawn_stats_logger(stats.clone(), Duration::from_secs(secs)));
+    let status_page = args
+        .status_addr
+        .map(|addr| tokio::spawn(warp::serve(status_route(stats.clone())).run(addr)));
 
     // Wait enter key
     {
@@ -28,9 +158,86 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     handle_tx.join().await?;
     handle_block.join().await?;
     handle_block_height.join().await?;
+    handle_block_header.join().await?;
+    handle_block_rejected.join().await?;
     utxo_req.join().await?;
+    mux.join().await?;
     utxo_res.join().await?;
+    total_supply.join().await?;
+    utxo_by_address.join().await?;
+    balances.join().await?;
+    merkle_proof.join().await?;
+    search.join().await?;
+    header_by_height.join().await?;
+    block_by_height.join().await?;
+    blocks_by_range.join().await?;
+    chain_info.join().await?;
+    chain_stats.join().await?;
+    balance_history.join().await?;
+    rich_list.join().await?;
+    daily_volume.join().await?;
+    utxo_ages.join().await?;
+    deployments.join().await?;
+    alerts.join().await?;
+    mempool_usage.join().await?;
+    mempool_info.join().await?;
+    raw_mempool.join().await?;
+    send_transaction.join().await?;
+    transaction_status.join().await?;
+    timers.join().await?;
+    set_timers.join().await?;
+    sync_progress.join().await?;
+    block_propagation.join().await?;
+    bans.join().await?;
+    unban.join().await?;
+    history_server.abort();
+    stats_logger.iter().for_each(|handle| handle.abort());
+    status_page.iter().for_each(|handle| handle.abort());
+
+    if let Some(path) = &args.history_file {
+        stats.lock().expect("Lock failure").save_history(path)?;
+    }
 
     println!("Bye.");
     Ok(())
 }
+
+/// Answer late subscribers with recent messages of the requested topic.
+fn spawn_history_server(
+    mut server: ServiceServer<QueryTopicHistory>,
+    stats: Arc<Mutex<ProxyStats>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let res = server
+                .serve(|topic| Some(stats.lock().expect("Lock failure").history(&topic)))
+                .await;
+
+            if let Err(e) = res {
+                println!("Error during serving topic history: {}", e);
+            }
+        }
+    })
+}
+
+fn spawn_stats_logger(
+    stats: Arc<Mutex<ProxyStats>>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            print!("{}", stats.lock().expect("Lock failure").report());
+        }
+    })
+}
+
+/// GET /status shows the traffic of each topic as plain text.
+fn status_route(
+    stats: Arc<Mutex<ProxyStats>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("status")
+        .and(warp::get())
+        .map(move || stats.lock().expect("Lock failure").report())
+}
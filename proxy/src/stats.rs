@@ -0,0 +1,150 @@
+use blockchain_net::Topic;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Messages relayed for one topic.
+#[derive(Debug, Default)]
+struct TopicStats {
+    messages: u64,
+    bytes: u64,
+    /// Payloads of recent messages, oldest first
+    history: VecDeque<Vec<u8>>,
+}
+
+/// Traffic of each topic relayed by the proxy, and recent messages for late subscribers.
+#[derive(Debug)]
+pub struct ProxyStats {
+    topics: BTreeMap<String, TopicStats>,
+    history_len: usize,
+    started_at: Instant,
+}
+
+impl ProxyStats {
+    /// Keep up to `history_len` messages per topic.
+    pub fn new(history_len: usize) -> Self {
+        Self {
+            topics: BTreeMap::new(),
+            history_len,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Restore messages saved by `save_history`. Counts start from zero.
+    pub fn load_history(path: impl AsRef<Path>, history_len: usize) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        let histories: BTreeMap<String, VecDeque<Vec<u8>>> = bincode::deserialize_from(reader)?;
+
+        let mut stats = Self::new(history_len);
+        for (name, mut history) in histories {
+            while history.len() > history_len {
+                history.pop_front();
+            }
+            let topic = TopicStats {
+                history,
+                ..TopicStats::default()
+            };
+            stats.topics.insert(name, topic);
+        }
+        Ok(stats)
+    }
+
+    pub fn save_history(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let histories = self
+            .topics
+            .iter()
+            .map(|(name, topic)| (name, &topic.history))
+            .collect::<BTreeMap<_, _>>();
+        let writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(writer, &histories)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, topic: &str, payload: &[u8]) {
+        let history_len = self.history_len;
+        let topic = self.topics.entry(topic.to_string()).or_default();
+        topic.messages += 1;
+        topic.bytes += payload.len() as u64;
+
+        if history_len > 0 {
+            if topic.history.len() >= history_len {
+                topic.history.pop_front();
+            }
+            topic.history.push_back(payload.to_vec());
+        }
+    }
+
+    /// Recent payloads of the topic, oldest first.
+    pub fn history(&self, topic: &str) -> Vec<Vec<u8>> {
+        self.topics
+            .get(topic)
+            .map(|topic| topic.history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// One line per topic with counts and average rates since start.
+    pub fn report(&self) -> String {
+        let secs = self.started_at.elapsed().as_secs_f64().max(1.0);
+        let mut report = String::new();
+        for (name, topic) in self.topics.iter() {
+            writeln!(
+                report,
+                "{}: {} messages, {} bytes, {:.2} messages/s, {:.1} bytes/s, {} kept",
+                name,
+                topic.messages,
+                topic.bytes,
+                topic.messages as f64 / secs,
+                topic.bytes as f64 / secs,
+                topic.history.len()
+            )
+            .expect("Writing to string never fails");
+        }
+        report
+    }
+}
+
+/// Callback recording each message relayed for `T`.
+pub fn observer<T: Topic>(stats: &Arc<Mutex<ProxyStats>>) -> impl FnMut(&[u8]) + Send + 'static {
+    let stats = stats.clone();
+    move |payload| stats.lock().expect("Lock failure").record(T::NAME, payload)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(std::io::Error),
+    Serde(bincode::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IO(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Serde(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::IO(e) => e.fmt(f),
+            Error::Serde(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(e) => Some(e),
+            Error::Serde(e) => Some(e),
+        }
+    }
+}
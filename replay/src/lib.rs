@@ -0,0 +1,125 @@
+use blockchain_core::compact::{CompactBlock, UnspentOutputs};
+use blockchain_core::ledger::LedgerError;
+use blockchain_core::{BlockHeight, UnverifiedBlock, VerifiedBlock};
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Read blocks exported by `write_chain`, ordered from genesis.
+pub fn read_chain(path: impl AsRef<Path>) -> Result<Vec<UnverifiedBlock>, Error> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+    let blocks = bincode::deserialize::<Vec<CompactBlock>>(&buf)?;
+
+    let mut outputs = UnspentOutputs::new();
+    let blocks = blocks
+        .into_iter()
+        .map(|block| outputs.expand(block))
+        .collect::<Result<_, _>>()?;
+    Ok(blocks)
+}
+
+/// Read blocks exported by `write_chain` up to the first one which cannot be decoded or expanded,
+/// such as in a file cut off or damaged on disk. Returns them with the decoding error, if any.
+pub fn read_chain_prefix(
+    path: impl AsRef<Path>,
+) -> Result<(Vec<UnverifiedBlock>, Option<Error>), Error> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    // Length prefix of the sequence, as written by bincode
+    let len = match bincode::deserialize_from::<_, u64>(&mut reader) {
+        Ok(len) => len,
+        Err(e) => return Ok((vec![], Some(e.into()))),
+    };
+
+    let mut outputs = UnspentOutputs::new();
+    let mut blocks = vec![];
+    for _ in 0..len {
+        let block = bincode::deserialize_from(&mut reader)
+            .map_err(Error::from)
+            .and_then(|block| Ok(outputs.expand(block)?));
+        match block {
+            Ok(block) => blocks.push(block),
+            Err(e) => return Ok((blocks, Some(e))),
+        }
+    }
+    Ok((blocks, None))
+}
+
+/// Export blocks ordered from genesis, whose inputs are written as the outpoints they spend.
+pub fn write_chain<'a>(
+    path: impl AsRef<Path>,
+    blocks: impl IntoIterator<Item = &'a VerifiedBlock>,
+) -> Result<(), Error> {
+    let mut outputs = UnspentOutputs::new();
+    let blocks = blocks
+        .into_iter()
+        .map(|block| {
+            outputs
+                .compact(block)
+                .ok_or(Error::UnknownInput(block.height()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    let buf = bincode::serialize(&blocks)?;
+    writer.write_all(&buf)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(std::io::Error),
+    Serde(bincode::Error),
+    /// Block spends an output which is not in the chain before it.
+    UnknownInput(BlockHeight),
+    Ledger(LedgerError),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IO(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Serde(e)
+    }
+}
+
+impl From<LedgerError> for Error {
+    fn from(e: LedgerError) -> Self {
+        Error::Ledger(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IO(e) => e.fmt(f),
+            Error::Serde(e) => e.fmt(f),
+            Error::UnknownInput(height) => write!(
+                f,
+                "Block {} spends an output which is not in the chain before it",
+                height
+            ),
+            Error::Ledger(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(e) => Some(e),
+            Error::Serde(e) => Some(e),
+            Error::UnknownInput(_) => None,
+            Error::Ledger(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use blockchain_core::ledger::Ledger;
+use blockchain_core::{Difficulty, UnverifiedBlock, VerifiedBlock};
+use clap::Parser;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Parser)]
+struct BcReplayArgs {
+    /// Chain file exported by bcfnode
+    #[clap(short, long)]
+    chain: String,
+
+    /// Minimum difficulty required for each block
+    #[clap(short, long, default_value_t = 10)]
+    difficulty: u8,
+
+    /// Continue replay after a block is rejected
+    #[clap(long)]
+    keep_going: bool,
+}
+
+const STAGES: [&str; 6] = [
+    "transaction",
+    "transaction relation",
+    "difficulty",
+    "digest",
+    "ledger",
+    "entry",
+];
+
+/// Elapsed time of each verification stage.
+#[derive(Debug, Default)]
+struct StageTimer {
+    elapsed: [Duration; STAGES.len()],
+    counts: [u32; STAGES.len()],
+}
+
+impl StageTimer {
+    fn measure<T>(&mut self, stage: usize, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.elapsed[stage] += start.elapsed();
+        self.counts[stage] += 1;
+        res
+    }
+
+    fn print(&self) {
+        println!(
+            "{:<24}{:>8}{:>16}{:>16}",
+            "stage", "count", "total", "average"
+        );
+        for (i, stage) in STAGES.iter().enumerate() {
+            let average = match self.counts[i] {
+                0 => Duration::ZERO,
+                n => self.elapsed[i] / n,
+            };
+            println!(
+                "{:<24}{:>8}{:>16?}{:>16?}",
+                stage, self.counts[i], self.elapsed[i], average
+            );
+        }
+    }
+}
+
+fn replay_block(
+    block: UnverifiedBlock,
+    ledger: &mut Ledger,
+    difficulty: &Difficulty,
+    timer: &mut StageTimer,
+) -> anyhow::Result<()> {
+    let block = timer.measure(0, || block.verify_transaction_itself())?;
+    let params = *ledger.params();
+    let block = timer.measure(1, || block.verify_transaction_relation_with(&params))?;
+    let block = timer.measure(2, || block.verify_difficulty(difficulty))?;
+    let block = timer.measure(3, || block.verify_digest())?;
+    let block: VerifiedBlock = timer.measure(4, || ledger.verify_block(block))?;
+    timer.measure(5, || ledger.entry(block))?;
+
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = BcReplayArgs::parse();
+
+    let blocks = replay::read_chain(&args.chain)?;
+    println!("Loaded {} blocks from {}.", blocks.len(), args.chain);
+
+    let difficulty = Difficulty::new(args.difficulty);
+    let mut ledger = Ledger::new();
+    let mut timer = StageTimer::default();
+    let (mut accepted, mut rejected) = (0, 0);
+
+    let start = Instant::now();
+    for block in blocks.into_iter() {
+        let height = block.height();
+        match replay_block(block, &mut ledger, &difficulty, &mut timer) {
+            Ok(()) => accepted += 1,
+            Err(e) => {
+                rejected += 1;
+                println!("Rejected block {}: {}", height, e);
+                if !args.keep_going {
+                    break;
+                }
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "Accepted: {}, Rejected: {}, Elapsed: {:?}",
+        accepted, rejected, elapsed
+    );
+    timer.print();
+
+    Ok(())
+}
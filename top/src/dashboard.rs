@@ -0,0 +1,153 @@
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::mempool::MempoolInfo;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{BlockHeight, UnverifiedBlock};
+use blockchain_net::identity::{NodeId, Signed};
+use blockchain_net::sync::{ChainInfo, ChainStatus, SyncProgress};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Bytes read from the end of the log file, which hold far more lines than the dashboard shows.
+const LOG_TAIL_BYTES: u64 = 64 * 1024;
+
+/// Block of the best chain, as listed by the dashboard.
+#[derive(Debug, Clone)]
+pub struct BlockRow {
+    pub digest: BlockDigest,
+    pub timestamp: Timestamp,
+    pub transactions: usize,
+    /// Expected number of hashes to find the block
+    pub work: u128,
+}
+
+impl From<&UnverifiedBlock> for BlockRow {
+    fn from(block: &UnverifiedBlock) -> Self {
+        Self {
+            digest: block.digest().clone(),
+            timestamp: block.timestamp(),
+            transactions: block.transactions().len(),
+            work: block.difficulty().work(),
+        }
+    }
+}
+
+/// Everything the dashboard shows, updated by polling the node and listening to announcements.
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    pub chain: Option<ChainInfo>,
+    pub sync: Option<SyncProgress>,
+    pub mempool: Option<MempoolInfo>,
+    /// Nodes announcing their chain height, with when they did last
+    peers: HashMap<NodeId, (Instant, Option<BlockHeight>)>,
+    /// Latest blocks of the best chain
+    blocks: BTreeMap<BlockHeight, BlockRow>,
+    block_limit: usize,
+    pub log: Vec<String>,
+    /// Why the last refresh failed, if it did
+    pub error: Option<String>,
+}
+
+impl Dashboard {
+    /// Dashboard listing at most `block_limit` recent blocks.
+    pub fn new(block_limit: usize) -> Self {
+        Self {
+            block_limit,
+            ..Self::default()
+        }
+    }
+
+    /// Record a height announcement, ignoring forged ones.
+    pub fn announce(&mut self, status: Signed<ChainStatus>) {
+        if status.verify() {
+            let height = status.message().height();
+            self.peers
+                .insert(status.node().clone(), (Instant::now(), height));
+        }
+    }
+
+    /// Forget nodes which have not announced their height within `timeout`.
+    pub fn expire_peers(&mut self, timeout: Duration) {
+        self.peers
+            .retain(|_, (last_seen, _)| last_seen.elapsed() < timeout);
+    }
+
+    /// Nodes with their announced heights, highest first.
+    pub fn peers(&self) -> Vec<(&NodeId, Option<BlockHeight>)> {
+        let mut peers = self
+            .peers
+            .iter()
+            .map(|(node, (_, height))| (node, *height))
+            .collect::<Vec<_>>();
+        peers.sort_by_cached_key(|(node, height)| (Reverse(*height), node.to_string()));
+        peers
+    }
+
+    pub fn block_limit(&self) -> usize {
+        self.block_limit
+    }
+
+    /// Forget blocks above `tip`, which a reorganization has removed from the best chain.
+    pub fn prune_above(&mut self, tip: BlockHeight) {
+        self.blocks.retain(|&height, _| height <= tip);
+    }
+
+    /// Whether the list has the block `digest` at `height`.
+    pub fn contains_block(&self, height: BlockHeight, digest: &BlockDigest) -> bool {
+        self.blocks
+            .get(&height)
+            .is_some_and(|block| block.digest == *digest)
+    }
+
+    /// Put a block of the best chain into the list, replacing any other block at its height.
+    pub fn insert_block(&mut self, block: &UnverifiedBlock) {
+        self.blocks.insert(block.height(), BlockRow::from(block));
+        while self.blocks.len() > self.block_limit {
+            self.blocks.pop_first();
+        }
+    }
+
+    /// Latest blocks, highest first.
+    pub fn blocks(&self) -> impl Iterator<Item = (&BlockHeight, &BlockRow)> {
+        self.blocks.iter().rev()
+    }
+
+    /// Hashes per second the network spent on the listed blocks,
+    /// estimated from their work and timestamps. `None` until two blocks are apart in time.
+    pub fn hash_rate(&self) -> Option<f64> {
+        let (_, first) = self.blocks.first_key_value()?;
+        let (_, last) = self.blocks.last_key_value()?;
+        let elapsed = last.timestamp.duration_since(first.timestamp)?;
+        if elapsed.is_zero() {
+            return None;
+        }
+        // Work of the first block was done before its timestamp
+        let work = self
+            .blocks
+            .values()
+            .skip(1)
+            .map(|b| b.work as f64)
+            .sum::<f64>();
+        Some(work / elapsed.as_secs_f64())
+    }
+
+    /// Replace the log tail with the last `lines` lines of the file at `path`.
+    pub fn tail_log(&mut self, path: &Path, lines: usize) -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(LOG_TAIL_BYTES)))?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let text = String::from_utf8_lossy(&bytes);
+        let all = text.lines().collect::<Vec<_>>();
+        self.log = all[all.len().saturating_sub(lines)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        Ok(())
+    }
+}
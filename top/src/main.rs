@@ -0,0 +1,170 @@
+use anyhow::Result;
+use blockchain_client::{NodeClient, RemoteNode};
+use blockchain_net::async_net::Subscriber;
+use blockchain_net::impl_zeromq::{TopicSubscriber, DEFAULT_BROKER};
+use blockchain_net::topic::NotifyBlockHeight;
+use clap::Parser;
+use dashboard::Dashboard;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+mod dashboard;
+mod ui;
+
+/// Time to wait for each refresh, after which the node is considered unreachable.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lines of the log kept, more than any terminal shows.
+const LOG_LINES: usize = 200;
+
+#[derive(Debug, Parser)]
+struct BcNodeTopArgs {
+    /// Proxies to connect to, by the name given to their --broker.
+    /// Connects only to the default proxy if not specified.
+    #[clap(long)]
+    brokers: Vec<String>,
+
+    /// Token sent with every request, for nodes started with --rpc-tokens
+    #[clap(long)]
+    token: Option<String>,
+
+    /// Log file of the node to tail, such as its redirected stderr
+    #[clap(long)]
+    log: Option<PathBuf>,
+
+    /// Milliseconds between refreshes
+    #[clap(long, default_value_t = 1000)]
+    refresh_ms: u64,
+
+    /// Number of recent blocks to list
+    #[clap(long, default_value_t = 10)]
+    blocks: usize,
+
+    /// Seconds without a height announcement after which a node is no longer listed.
+    /// Nodes announce every --height-announce-secs.
+    #[clap(long, default_value_t = 180)]
+    peer_timeout_secs: u64,
+}
+
+async fn connect(args: &BcNodeTopArgs, brokers: &[String]) -> Result<RemoteNode> {
+    let node = RemoteNode::connect_to(brokers).await?;
+    let node = match &args.token {
+        Some(token) => node.with_token(token),
+        None => node,
+    };
+    Ok(node)
+}
+
+/// Query the node, then fetch best blocks which the dashboard does not list yet.
+async fn refresh(dashboard: &mut Dashboard, node: &mut RemoteNode) -> Result<()> {
+    dashboard.sync = Some(node.rpc().sync_progress(&()).await?);
+    dashboard.mempool = Some(node.rpc().mempool_info(&()).await?);
+    let chain = node.rpc().chain_info(&()).await?;
+
+    if let Some(chain) = &chain {
+        dashboard.prune_above(chain.height);
+        // From the tip down to the first block already listed, replacing stale ones
+        let mut next = Some((chain.height, chain.best_digest.clone()));
+        for _ in 0..dashboard.block_limit() {
+            let (height, digest) = match next {
+                Some((height, digest)) if !dashboard.contains_block(height, &digest) => {
+                    (height, digest)
+                }
+                _ => break,
+            };
+            let block = match node.get_block(height).await? {
+                Some(block) if *block.digest() == digest => block,
+                // Best chain changed meanwhile. Catch up on the next refresh.
+                _ => break,
+            };
+            dashboard.insert_block(&block);
+            next = height
+                .previous()
+                .map(|previous| (previous, block.previous_digest().clone()));
+        }
+    }
+    dashboard.chain = chain;
+    Ok(())
+}
+
+/// Forward key presses from a thread, since reading the terminal blocks.
+fn spawn_key_reader() -> UnboundedReceiver<KeyEvent> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event {
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }
+}
+
+async fn run(args: BcNodeTopArgs, terminal: &mut ratatui::DefaultTerminal) -> Result<()> {
+    let (brokers, title) = if args.brokers.is_empty() {
+        (vec![DEFAULT_BROKER.to_string()], " bcnode-top ".to_string())
+    } else {
+        let title = format!(" bcnode-top: {} ", args.brokers.join(", "));
+        (args.brokers.clone(), title)
+    };
+
+    let mut node = connect(&args, &brokers).await?;
+    let mut announcements = TopicSubscriber::<NotifyBlockHeight>::connect_to(&brokers).await?;
+    let mut keys = spawn_key_reader();
+    let mut dashboard = Dashboard::new(args.blocks.max(1));
+    let mut interval = tokio::time::interval(Duration::from_millis(args.refresh_ms.max(1)));
+
+    loop {
+        terminal.draw(|frame| ui::draw(frame, &dashboard, &title))?;
+
+        tokio::select! {
+            _ = interval.tick() => {
+                let refreshed = refresh(&mut dashboard, &mut node);
+                dashboard.error = match tokio::time::timeout(REQUEST_TIMEOUT, refreshed).await {
+                    Ok(res) => res.err().map(|e| e.to_string()),
+                    Err(_) => {
+                        // Pending requests would leave sockets waiting for their replies
+                        node = connect(&args, &brokers).await?;
+                        Some("Node did not respond.".to_string())
+                    }
+                };
+                if let Some(path) = &args.log {
+                    if let Err(e) = dashboard.tail_log(path, LOG_LINES) {
+                        dashboard.log = vec![format!("Cannot read {}. {}", path.display(), e)];
+                    }
+                }
+                dashboard.expire_peers(Duration::from_secs(args.peer_timeout_secs));
+            }
+            status = announcements.recv() => dashboard.announce(status?),
+            key = keys.recv() => match key {
+                Some(key) if is_quit(&key) => return Ok(()),
+                Some(_) => {}
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = BcNodeTopArgs::parse();
+
+    let mut terminal = ratatui::init();
+    let res = run(args, &mut terminal).await;
+    ratatui::restore();
+
+    res
+}
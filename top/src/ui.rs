@@ -0,0 +1,130 @@
+use crate::dashboard::Dashboard;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::Frame;
+
+/// Hex characters of node ids shown, which is enough to tell nodes apart.
+const SHORT_NODE_LEN: usize = 16;
+
+pub fn draw(frame: &mut Frame, dashboard: &Dashboard, title: &str) {
+    let [summary, lists, log, footer] = Layout::vertical([
+        Constraint::Length(7),
+        Constraint::Percentage(50),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [blocks, peers] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(lists);
+
+    frame.render_widget(
+        Paragraph::new(summary_lines(dashboard)).block(Block::bordered().title(title.to_string())),
+        summary,
+    );
+
+    let rows = dashboard.blocks().map(|(height, block)| {
+        Row::new(vec![
+            height.to_string(),
+            block.digest.fmt_short(),
+            block.transactions.to_string(),
+            block.timestamp.to_string(),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(4),
+            Constraint::Min(20),
+        ],
+    )
+    .header(Row::new(vec!["Height", "Digest", "Txs", "Timestamp"]).style(Style::new().bold()))
+    .block(Block::bordered().title("Recent blocks"));
+    frame.render_widget(table, blocks);
+
+    let rows = dashboard.peers().into_iter().map(|(node, height)| {
+        let mut node = node.to_string();
+        node.truncate(SHORT_NODE_LEN);
+        Row::new(vec![node, format_height(height)])
+    });
+    let table = Table::new(rows, [Constraint::Length(17), Constraint::Min(6)])
+        .header(Row::new(vec!["Node", "Height"]).style(Style::new().bold()))
+        .block(Block::bordered().title(format!("Nodes ({})", dashboard.peers().len())));
+    frame.render_widget(table, peers);
+
+    // Show the latest lines which fit
+    let visible = log.height.saturating_sub(2) as usize;
+    let lines = dashboard.log[dashboard.log.len().saturating_sub(visible)..]
+        .iter()
+        .map(|line| Line::raw(line.as_str()))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Log")),
+        log,
+    );
+
+    let footer_line = match &dashboard.error {
+        Some(e) => Line::raw(format!("{} Press q to quit.", e)).red(),
+        None => Line::raw("Press q to quit.").dim(),
+    };
+    frame.render_widget(footer_line, footer);
+}
+
+fn summary_lines(dashboard: &Dashboard) -> Vec<Line<'static>> {
+    let chain = match &dashboard.chain {
+        Some(chain) => format!(
+            "Height {}  Best {}  Forks {}  Work per block {}",
+            chain.height,
+            chain.best_digest.fmt_short(),
+            chain.fork_count,
+            chain.difficulty.work()
+        ),
+        None => "No block yet".to_string(),
+    };
+    let sync = match &dashboard.sync {
+        Some(sync) if sync.syncing => format!("Syncing {}", sync),
+        Some(sync) => format!("In sync at {}", format_height(sync.local_height)),
+        None => "Unknown".to_string(),
+    };
+    let hash_rate = match dashboard.hash_rate() {
+        Some(rate) => format_hash_rate(rate),
+        None => "Unknown".to_string(),
+    };
+    let mempool = match &dashboard.mempool {
+        Some(mempool) => format!(
+            "{} transactions, {} of {} bytes, min fee rate {}",
+            mempool.usage.count, mempool.usage.bytes, mempool.usage.capacity, mempool.min_fee_rate
+        ),
+        None => "Unknown".to_string(),
+    };
+
+    vec![
+        Line::raw(format!("Chain     {}", chain)),
+        Line::raw(format!("Sync      {}", sync)),
+        Line::raw(format!("Hash rate {}", hash_rate)),
+        Line::raw(format!("Mempool   {}", mempool)),
+        Line::raw(format!("Nodes     {}", dashboard.peers().len())),
+    ]
+}
+
+fn format_height(height: Option<blockchain_core::BlockHeight>) -> String {
+    height.map_or("-".to_string(), |h| h.to_string())
+}
+
+/// Hashes per second with an SI prefix, such as `1.50 kH/s`.
+fn format_hash_rate(rate: f64) -> String {
+    let prefixes = ["", "k", "M", "G", "T", "P"];
+    let mut rate = rate;
+    let mut prefix = prefixes[0];
+    for p in &prefixes[1..] {
+        if rate < 1000.0 {
+            break;
+        }
+        rate /= 1000.0;
+        prefix = p;
+    }
+    format!("{:.2} {}H/s", rate, prefix)
+}
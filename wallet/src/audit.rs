@@ -0,0 +1,203 @@
+use crate::cache::WalletCache;
+use crate::report::ScannedBlock;
+use bcaddr::output::{self, OutputFormat};
+use blockchain_core::{Address, Block, BlockHeight, Coin};
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+/// Where the audit learned an address from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// Key file given to the audit
+    KeyFile(String),
+    /// Archive written by `--rotate-key`, decrypted by a key of the wallet
+    Archive(String),
+    /// Public address whose key the wallet has given up
+    Retired,
+}
+
+impl Display for KeySource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::KeyFile(path) => write!(f, "key file {}", path),
+            KeySource::Archive(path) => write!(f, "archive {}", path),
+            KeySource::Retired => write!(f, "retired"),
+        }
+    }
+}
+
+/// Address under audit, and its scan results.
+struct Audited {
+    address: Address,
+    source: KeySource,
+    cache: WalletCache,
+}
+
+/// Scans the chain once for every address of the wallet.
+pub struct Audit {
+    addresses: Vec<Audited>,
+}
+
+impl Audit {
+    /// Addresses of `key_files`, of `archives` decrypted by any of their keys, and `retired`.
+    /// An archive may be decrypted by the key of another archive, as after repeated rotation.
+    pub fn new(
+        key_files: &[String],
+        archives: &[String],
+        retired: &[Address],
+    ) -> anyhow::Result<Self> {
+        let mut secrets = vec![];
+        let mut addresses = vec![];
+        for path in key_files {
+            let secret = bcaddr::read_address(path)?;
+            addresses.push((secret.to_public_address(), KeySource::KeyFile(path.clone())));
+            secrets.push(secret);
+        }
+
+        let mut remaining = archives.iter().collect::<Vec<_>>();
+        while !remaining.is_empty() {
+            let before = remaining.len();
+            remaining.retain(|&path| {
+                let secret = secrets
+                    .iter()
+                    .find_map(|owner| bcaddr::read_archived_address(path, owner).ok());
+                match secret {
+                    Some(secret) => {
+                        addresses
+                            .push((secret.to_public_address(), KeySource::Archive(path.clone())));
+                        secrets.push(secret);
+                        false
+                    }
+                    None => true,
+                }
+            });
+            if remaining.len() == before {
+                anyhow::bail!("No key of the wallet decrypts {}.", remaining[0]);
+            }
+        }
+
+        addresses.extend(retired.iter().map(|a| (a.clone(), KeySource::Retired)));
+
+        // An address given twice is audited as where it was found first
+        let mut audited: Vec<Audited> = vec![];
+        for (address, source) in addresses {
+            if audited.iter().all(|a| a.address != address) {
+                audited.push(Audited {
+                    address,
+                    source,
+                    cache: WalletCache::default(),
+                });
+            }
+        }
+        Ok(Self { addresses: audited })
+    }
+
+    /// Height of the block to scan next.
+    pub fn next_height(&self) -> BlockHeight {
+        self.addresses[0].cache.next_height()
+    }
+
+    /// Scan the block following the last scanned one for every address.
+    pub fn apply_block<VT, VTS, VU, VP, VDG, VDI>(
+        &mut self,
+        block: &Block<VT, VTS, VU, VP, VDG, VDI>,
+    ) -> anyhow::Result<()> {
+        for audited in self.addresses.iter_mut() {
+            audited.cache.apply_block(block, &audited.address)?;
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> AuditReport {
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|audited| {
+                let history = audited.cache.history();
+                let receipts = history.iter().filter(|e| e.received > Coin::default());
+                let receipts = receipts.count();
+                AddressAudit {
+                    address: audited.address.clone(),
+                    held: matches!(audited.source, KeySource::KeyFile(_)),
+                    source: audited.source.clone(),
+                    transactions: history.len(),
+                    unused: history.is_empty(),
+                    reused: receipts > 1,
+                    utxos: audited.cache.utxos().len(),
+                    balance: audited.cache.balance(),
+                }
+            })
+            .collect();
+
+        let scanned = self.addresses[0]
+            .cache
+            .tip()
+            .map(|(height, digest)| ScannedBlock {
+                height: *height,
+                digest: digest.clone(),
+            });
+        AuditReport { scanned, addresses }
+    }
+}
+
+/// Usage of one address in the scanned chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressAudit {
+    pub address: Address,
+    pub source: KeySource,
+    /// Whether a key file of the address was given
+    pub held: bool,
+    pub transactions: usize,
+    /// No transaction has paid or spent from the address
+    pub unused: bool,
+    /// More than one transaction has paid the address, including change
+    pub reused: bool,
+    pub utxos: usize,
+    pub balance: Coin,
+}
+
+/// Result of `audit`. Coin quantities are in base units in JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditReport {
+    pub scanned: Option<ScannedBlock>,
+    pub addresses: Vec<AddressAudit>,
+}
+
+impl AuditReport {
+    pub fn print(&self, format: OutputFormat) -> serde_json::Result<()> {
+        match format {
+            OutputFormat::Text => {
+                match &self.scanned {
+                    Some(s) => println!(
+                        "Scanned up to block {} ({})",
+                        s.height,
+                        s.digest.fmt_short()
+                    ),
+                    None => println!("No block has been scanned."),
+                }
+
+                for a in self.addresses.iter() {
+                    let usage = match (a.unused, a.reused) {
+                        (true, _) => ", never used",
+                        (false, true) => ", reused",
+                        (false, false) => "",
+                    };
+                    println!(
+                        "{} ({}): {} transactions, {} in {} UTXO{}",
+                        a.address, a.source, a.transactions, a.balance, a.utxos, usage
+                    );
+                }
+
+                for a in self.addresses.iter().filter(|a| !a.held && a.utxos > 0) {
+                    println!(
+                        "Warning: {} of {} is spendable only by a key without key file ({}).",
+                        a.balance, a.address, a.source
+                    );
+                }
+                Ok(())
+            }
+            OutputFormat::Json => output::print_json(self),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{Address, Block, BlockHeight, Coin, SecretAddress, Transition, Yet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// Message signed by the wallet owner to derive the cache key.
+const KEY_CONTEXT: &[u8] = b"bcwallet cache";
+
+/// Transaction which moved coins of the wallet owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub height: BlockHeight,
+    pub txid: BlockDigest,
+    pub timestamp: Timestamp,
+    /// Sum of outputs to the owner
+    pub received: Coin,
+    /// Sum of the owner's UTXOs spent by the transaction
+    pub spent: Coin,
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} +{} -{}",
+            self.height,
+            self.timestamp,
+            self.txid.fmt_short(),
+            self.received,
+            self.spent
+        )
+    }
+}
+
+/// Chain scan results of one address, kept encrypted in the wallet data directory.
+/// Updated block by block, so that each run only scans blocks found since the last one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WalletCache {
+    /// Height and digest of the last scanned block
+    tip: Option<(BlockHeight, BlockDigest)>,
+    utxos: Vec<Transition<Yet>>,
+    history: Vec<HistoryEntry>,
+    /// Notes of the owner on transactions, kept only here rather than on-chain
+    labels: HashMap<BlockDigest, String>,
+}
+
+impl WalletCache {
+    /// Load the cache of `secret` from `path`, or an empty one if it does not exist yet.
+    pub fn load(path: impl AsRef<Path>, secret: &SecretAddress) -> Result<Self, CacheError> {
+        let buf = match fs::read(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let plaintext = bcaddr::decrypt(secret, KEY_CONTEXT, &buf)?;
+        let cache = bincode::deserialize(&plaintext)?;
+
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, secret: &SecretAddress) -> Result<(), CacheError> {
+        let ciphertext = bcaddr::encrypt(secret, KEY_CONTEXT, &bincode::serialize(self)?)?;
+
+        // Replace the old cache only after the new one is fully written
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&ciphertext)?;
+        file.sync_all()?;
+        fs::rename(temp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Height and digest of the last scanned block.
+    pub fn tip(&self) -> Option<&(BlockHeight, BlockDigest)> {
+        self.tip.as_ref()
+    }
+
+    /// Height of the block to scan next.
+    pub fn next_height(&self) -> BlockHeight {
+        self.tip
+            .as_ref()
+            .map(|(height, _)| height.next())
+            .unwrap_or(BlockHeight::genesis())
+    }
+
+    pub fn utxos(&self) -> &[Transition<Yet>] {
+        &self.utxos
+    }
+
+    /// Transactions in order of blocks.
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    pub fn balance(&self) -> Coin {
+        self.utxos.iter().map(Transition::quantity).sum()
+    }
+
+    pub fn label(&self, txid: &BlockDigest) -> Option<&str> {
+        self.labels.get(txid).map(String::as_str)
+    }
+
+    pub fn labels(&self) -> &HashMap<BlockDigest, String> {
+        &self.labels
+    }
+
+    /// Attach `label` to `txid`, or remove its label if `label` is empty.
+    pub fn set_label(&mut self, txid: BlockDigest, label: String) {
+        match label.is_empty() {
+            true => self.labels.remove(&txid),
+            false => self.labels.insert(txid, label),
+        };
+    }
+
+    /// Forget scanned blocks to scan again from genesis. Labels are kept.
+    pub fn reset_scan(&mut self) {
+        self.tip = None;
+        self.utxos.clear();
+        self.history.clear();
+    }
+
+    /// Scan the block following the last scanned one.
+    pub fn apply_block<VT, VTS, VU, VP, VDG, VDI>(
+        &mut self,
+        block: &Block<VT, VTS, VU, VP, VDG, VDI>,
+        owner: &Address,
+    ) -> Result<(), CacheError> {
+        let extends_tip = match &self.tip {
+            Some((height, digest)) => {
+                block.height() == height.next() && block.previous_digest() == digest
+            }
+            None => block.height().is_genesis(),
+        };
+        if !extends_tip {
+            return Err(CacheError::Disconnected(block.height()));
+        }
+
+        for tx in block.transactions() {
+            let mut spent = Coin::default();
+            for input in tx.inputs() {
+                if let Some(index) = self.utxos.iter().position(|u| u.sign() == input.sign()) {
+                    spent = spent + self.utxos.remove(index).quantity();
+                }
+            }
+
+            let mut received = Coin::default();
+            for output in tx.outputs().iter().filter(|o| o.receiver() == owner) {
+                received = received + output.quantity();
+                self.utxos.push(output.to_unverified());
+            }
+
+            if received > Coin::default() || spent > Coin::default() {
+                self.history.push(HistoryEntry {
+                    height: block.height(),
+                    txid: tx.txid(),
+                    timestamp: tx.timestamp(),
+                    received,
+                    spent,
+                });
+            }
+        }
+
+        self.tip = Some((block.height(), block.digest().clone()));
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    IO(std::io::Error),
+    Serde(bincode::Error),
+    /// Cache is corrupted or encrypted by another address.
+    Cipher(bcaddr::Error),
+    /// Block does not follow the last scanned block, as after a reorg.
+    Disconnected(BlockHeight),
+}
+
+impl From<std::io::Error> for CacheError {
+    fn from(e: std::io::Error) -> Self {
+        CacheError::IO(e)
+    }
+}
+
+impl From<bincode::Error> for CacheError {
+    fn from(e: bincode::Error) -> Self {
+        CacheError::Serde(e)
+    }
+}
+
+impl From<bcaddr::Error> for CacheError {
+    fn from(e: bcaddr::Error) -> Self {
+        CacheError::Cipher(e)
+    }
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::IO(e) => e.fmt(f),
+            CacheError::Serde(e) => e.fmt(f),
+            CacheError::Cipher(e) => write!(f, "Wallet cache is unreadable. {}", e),
+            CacheError::Disconnected(height) => {
+                write!(f, "Block {} does not follow the last scanned block", height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::IO(e) => Some(e),
+            CacheError::Serde(e) => Some(e),
+            CacheError::Cipher(e) => Some(e),
+            CacheError::Disconnected(_) => None,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use blockchain_core::coin::UNIT_SYMBOL;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{Address, Block, Coin, Transition, Verified, Yet};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter, Write};
+use std::str::FromStr;
+
+/// Block whose transaction signatures are verified.
+pub type SignedBlock = Block<Verified, Yet, Yet, Yet, Yet, Yet>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Beancount,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "beancount" => Ok(ExportFormat::Beancount),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Mining,
+    Receive,
+    Send,
+}
+
+impl Display for EntryKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryKind::Mining => write!(f, "mining"),
+            EntryKind::Receive => write!(f, "receive"),
+            EntryKind::Send => write!(f, "send"),
+        }
+    }
+}
+
+/// One accounting entry seen from the wallet owner.
+#[derive(Debug, Clone)]
+struct Entry {
+    timestamp: Timestamp,
+    kind: EntryKind,
+    counterparty: Option<Address>,
+    quantity: Coin,
+    fee: Coin,
+    /// Label of the transaction in the wallet cache
+    label: Option<String>,
+}
+
+/// Collect entries related to `owner` from blocks ordered from genesis.
+fn collect_entries(
+    blocks: &[SignedBlock],
+    owner: &Address,
+    labels: &HashMap<BlockDigest, String>,
+) -> Vec<Entry> {
+    let mut entries = vec![];
+
+    for tx in blocks.iter().flat_map(|block| block.transactions()) {
+        let label = labels.get(&tx.txid()).cloned();
+        // Coin generation
+        if tx.inputs().is_empty() {
+            let quantity = tx
+                .outputs()
+                .iter()
+                .filter(|o| o.receiver() == owner)
+                .map(Transition::quantity)
+                .sum::<Coin>();
+            if quantity > Coin::default() {
+                entries.push(Entry {
+                    timestamp: tx.timestamp(),
+                    kind: EntryKind::Mining,
+                    counterparty: None,
+                    quantity,
+                    fee: Coin::default(),
+                    label: label.clone(),
+                });
+            }
+        }
+        // Sent by owner. Outputs back to owner are change.
+        else if tx.contractor() == owner {
+            let in_qty = tx.inputs().iter().map(Transition::quantity).sum::<Coin>();
+            let o_qty = tx.outputs().iter().map(Transition::quantity).sum::<Coin>();
+            let mut fee = in_qty.checked_sub(o_qty).unwrap_or_default();
+
+            for output in tx.outputs().iter().filter(|o| o.receiver() != owner) {
+                entries.push(Entry {
+                    timestamp: tx.timestamp(),
+                    kind: EntryKind::Send,
+                    counterparty: Some(output.receiver().clone()),
+                    quantity: output.quantity(),
+                    fee,
+                    label: label.clone(),
+                });
+                // Book the fee only once per transaction
+                fee = Coin::default();
+            }
+        }
+        // Received from others
+        else {
+            for output in tx.outputs().iter().filter(|o| o.receiver() == owner) {
+                entries.push(Entry {
+                    timestamp: tx.timestamp(),
+                    kind: EntryKind::Receive,
+                    counterparty: Some(tx.contractor().clone()),
+                    quantity: output.quantity(),
+                    fee: Coin::default(),
+                    label: label.clone(),
+                });
+            }
+        }
+    }
+
+    entries
+}
+
+/// Quote a CSV field if it has a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Escape a beancount string, which is double-quoted.
+fn beancount_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_csv(entries: &[Entry], out: &mut String) -> fmt::Result {
+    writeln!(out, "timestamp,kind,counterparty,quantity,fee,label")?;
+    for entry in entries {
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            entry.timestamp,
+            entry.kind,
+            entry
+                .counterparty
+                .as_ref()
+                .map(Address::to_string)
+                .unwrap_or_default(),
+            entry.quantity.to_decimal_string(),
+            entry.fee.to_decimal_string(),
+            csv_field(entry.label.as_deref().unwrap_or_default())
+        )?;
+    }
+    Ok(())
+}
+
+fn write_beancount(entries: &[Entry], out: &mut String) -> fmt::Result {
+    const WALLET: &str = "Assets:Wallet";
+
+    for entry in entries {
+        let date = entry.timestamp.date();
+        let counterparty = entry
+            .counterparty
+            .as_ref()
+            .map(Address::to_string)
+            .unwrap_or_default();
+        let quantity = entry.quantity.to_decimal_string();
+        let label = entry.label.as_deref().map(beancount_string);
+
+        match entry.kind {
+            EntryKind::Mining => {
+                let narration = label.as_deref().unwrap_or("Mining reward");
+                writeln!(out, "{} * \"{}\"", date, narration)?;
+                writeln!(out, "  {}  {} {}", WALLET, quantity, UNIT_SYMBOL)?;
+                writeln!(out, "  Income:Mining")?;
+            }
+            EntryKind::Receive => {
+                let narration = label.as_deref().unwrap_or("Receive");
+                writeln!(out, "{} * \"{}\" \"{}\"", date, counterparty, narration)?;
+                writeln!(out, "  {}  {} {}", WALLET, quantity, UNIT_SYMBOL)?;
+                writeln!(out, "  Income:Transfer")?;
+            }
+            EntryKind::Send => {
+                let total = (entry.quantity + entry.fee).to_decimal_string();
+                let narration = label.as_deref().unwrap_or("Send");
+                writeln!(out, "{} * \"{}\" \"{}\"", date, counterparty, narration)?;
+                writeln!(out, "  {}  -{} {}", WALLET, total, UNIT_SYMBOL)?;
+                writeln!(out, "  Expenses:Transfer  {} {}", quantity, UNIT_SYMBOL)?;
+                if entry.fee > Coin::default() {
+                    let fee = entry.fee.to_decimal_string();
+                    writeln!(out, "  Expenses:Fee  {} {}", fee, UNIT_SYMBOL)?;
+                }
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Render wallet history of `owner` as accounting entries, described by `labels` if any.
+pub fn export(
+    blocks: &[SignedBlock],
+    owner: &Address,
+    labels: &HashMap<BlockDigest, String>,
+    format: ExportFormat,
+) -> String {
+    let entries = collect_entries(blocks, owner, labels);
+    let mut out = String::new();
+    let res = match format {
+        ExportFormat::Csv => write_csv(&entries, &mut out),
+        ExportFormat::Beancount => write_beancount(&entries, &mut out),
+    };
+    res.expect("Writing to String never fails");
+    out
+}
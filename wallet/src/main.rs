@@ -1,80 +1,600 @@
-use blockchain_core::{Address, Coin, Transaction, Transfer, Transition};
-use blockchain_net::async_net::{Publisher, Subscriber};
-use blockchain_net::impl_zeromq::{TopicPublisher, TopicSubscriber};
-use blockchain_net::topic::{CreateTransaction, RequestUtxoByAddress, RespondUtxoByAddress};
-use clap::Parser;
+use audit::Audit;
+use bcaddr::output::OutputFormat;
+use blockchain_client::{NodeClient, RemoteNode};
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::psbt::{PartiallySignedTransaction, UnsignedTransfer};
+use blockchain_core::signer::{SignatureResponse, SigningRequest};
+use blockchain_core::{Address, Coin, SecretAddress, Transaction, Transfer, Transition};
+use blockchain_core::{Verified, VerifiedTransaction};
+use cache::WalletCache;
+use clap::{Parser, Subcommand};
+use export::ExportFormat;
+use rand::seq::SliceRandom;
+use report::{ImportedSignature, Output, SigningRequestOutput};
+use std::path::Path;
+use watch::Notifier;
+
+mod audit;
+mod cache;
+mod export;
+mod psbt;
+mod report;
+mod restore;
+mod watch;
 
 #[derive(Debug, Parser)]
 struct BcWalletArgs {
-    /// File path to secret address
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// File path to secret address. Required unless a subcommand is given.
     #[clap(short, long)]
-    address: String,
+    address: Option<String>,
 
     /// Coin sending destination.
     /// If not specified, bcwallet only display your UTXO.
     #[clap(short, long)]
     destination: Option<Address>,
 
-    /// How much send coin
+    /// How much send coin, in decimal coin units such as 1.5.
     /// If not specified, bcwallet only display your UTXO.
     #[clap(short, long)]
     quantity: Option<Coin>,
 
-    /// Fee to paid for miner.
+    /// Fee to paid for miner, in decimal coin units.
     #[clap(short, long)]
     fee: Option<Coin>,
+
+    /// Export your history as accounting entries, in csv or beancount format.
+    /// Requires --chain.
+    #[clap(long)]
+    export: Option<ExportFormat>,
+
+    /// Chain file exported by bcfnode, read by --export and audit
+    #[clap(long)]
+    chain: Option<String>,
+
+    /// Directory keeping the encrypted cache of scanned blocks
+    #[clap(long, default_value = ".bcwallet")]
+    data_dir: String,
+
+    /// Display the cached UTXO and history without connecting to the node
+    #[clap(long)]
+    offline: bool,
+
+    /// Token sent with every request, for nodes started with --rpc-tokens
+    #[clap(long)]
+    token: Option<String>,
+
+    /// Create a new address into this file, send all your coin to it,
+    /// then replace your address file with an archive encrypted by the new address.
+    /// Requires --fee unless your UTXO is empty.
+    #[clap(long)]
+    rotate_key: Option<String>,
+
+    /// Print results as text or json. JSON is printed as one document after the run.
+    /// Does not apply to --export.
+    #[clap(long, alias = "output-format", default_value = "text")]
+    output: OutputFormat,
+
+    /// Write the payment of --destination, --quantity and --fee into this file unsigned,
+    /// instead of sending it. Sign it by --sign-psbt.
+    #[clap(long)]
+    create_psbt: Option<String>,
+
+    /// Merge these partially signed transaction files into the first one and add your signs.
+    /// Sends the transaction once every party has signed, unless --offline.
+    #[clap(long, num_args = 1..)]
+    sign_psbt: Vec<String>,
+
+    /// Make the payment harder to link with your change. Change goes to a new address derived
+    /// from your key, whose key file is written next to your address file,
+    /// and outputs are shuffled.
+    #[clap(long)]
+    privacy: bool,
+
+    /// Split change into up to this many outputs of 1, 2 or 5 times a power of ten,
+    /// which look like payments. Requires --privacy.
+    #[clap(long, requires = "privacy")]
+    split_change: Option<usize>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Keep scanning new blocks of the node, and announce each transaction which pays your
+    /// address or confirms your payment. Requires --address.
+    /// With --output json, prints one JSON line per transaction.
+    Watch {
+        /// Show a desktop notification for each transaction
+        #[clap(long)]
+        notify: bool,
+
+        /// Run this shell command for each transaction, with BCWALLET_EVENT (received or
+        /// confirmed), BCWALLET_HEIGHT, BCWALLET_TXID, BCWALLET_RECEIVED and BCWALLET_SPENT set
+        #[clap(long)]
+        notify_command: Option<String>,
+    },
+    /// Attach a note to a transaction, shown in the history and --export.
+    /// Kept only in the wallet cache, never on-chain. Requires --address.
+    Label {
+        txid: BlockDigest,
+
+        /// Empty to remove the label
+        label: String,
+    },
+    /// Report usage of each address of the wallet: transactions, reuse and balance,
+    /// and coin left on addresses whose key files are gone.
+    /// Scans the whole chain of the node, or of --chain.
+    Audit {
+        /// Key files of the wallet
+        #[clap(required = true)]
+        keys: Vec<String>,
+
+        /// Archives written by --rotate-key, decrypted by the key files
+        #[clap(long)]
+        archive: Vec<String>,
+
+        /// Addresses whose keys the wallet no longer holds
+        #[clap(long)]
+        retired: Vec<Address>,
+    },
+    /// Print a hex blob of what the signer has to sign, for `bcaddr --sign-request` on the device
+    ExportSigningRequest {
+        /// Partially signed transaction file
+        psbt: String,
+
+        /// Public address of the key on the signer
+        #[clap(long)]
+        signer: Address,
+    },
+    /// Add the signs of the signer's answer into the file.
+    /// Sends the transaction once every party has signed, unless --offline.
+    ImportSignature {
+        /// Partially signed transaction file
+        psbt: String,
+
+        /// Hex blob printed by the signer
+        signature: String,
+    },
+    /// Write the key of your receiving address at this index next to your address file,
+    /// and print the address. Derived from your key, so `restore` finds it again.
+    /// Hand out indexes in order. Requires --address.
+    Receive { index: u32 },
+    /// Find your receiving addresses holding coin, as on a new machine with only your
+    /// address file, and write their keys next to it. Requires --address.
+    Restore {
+        /// Unused addresses in a row after which the scan stops
+        #[clap(long, default_value = "20")]
+        gap_limit: u32,
+
+        /// Addresses asked about per request to the node
+        #[clap(long, default_value = "20")]
+        batch: u32,
+    },
+}
+
+async fn connect(args: &BcWalletArgs) -> anyhow::Result<RemoteNode> {
+    let node = RemoteNode::connect().await?;
+    Ok(match &args.token {
+        Some(token) => node.with_token(token.clone()),
+        None => node,
+    })
+}
+
+/// Run a subcommand which does not need the secret address on this host,
+/// such as working on partially signed transactions with an external signer.
+async fn run_command(args: &BcWalletArgs, command: &Command) -> anyhow::Result<()> {
+    match command {
+        Command::Audit {
+            keys,
+            archive,
+            retired,
+        } => {
+            let mut audit = Audit::new(keys, archive, retired)?;
+            match &args.chain {
+                Some(chain) => {
+                    for block in replay::read_chain(chain)? {
+                        audit.apply_block(&block.verify_transaction_itself()?)?;
+                    }
+                }
+                None => {
+                    let mut node = connect(args).await?;
+                    while let Some(block) = node.get_block(audit.next_height()).await? {
+                        audit.apply_block(&block.verify_transaction_itself()?)?;
+                    }
+                }
+            }
+            audit.report().print(args.output)?;
+        }
+        Command::Watch { .. }
+        | Command::Label { .. }
+        | Command::Receive { .. }
+        | Command::Restore { .. } => unreachable!("Needs the address"),
+        Command::ExportSigningRequest { psbt, signer } => {
+            let psbt = psbt::read_psbt(psbt)?;
+            let request = SigningRequest::new(&psbt, signer.clone())?;
+            let output = SigningRequestOutput {
+                signer: signer.clone(),
+                targets: request.messages().iter().map(|(t, _)| *t).collect(),
+                request: request.to_hex(),
+            };
+            output.print(args.output)?;
+        }
+        Command::ImportSignature {
+            psbt: path,
+            signature,
+        } => {
+            let response = SignatureResponse::from_hex(signature)?;
+            let mut psbt = psbt::read_psbt(path)?;
+            response.apply_to(&mut psbt)?;
+            psbt::write_psbt(path, &psbt)?;
+
+            let mut output = ImportedSignature {
+                file: path.clone(),
+                signer: response.signer().clone(),
+                missing_signers: psbt.missing_signers().into_iter().cloned().collect(),
+                sent: None,
+            };
+            if psbt.is_complete() && !args.offline {
+                let transaction = psbt.finalize()?.verify()?;
+                let mut node = connect(args).await?;
+                output.sent = Some(node.send_transaction(&transaction).await?);
+            }
+            output.print(args.output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Scan blocks the node has found since the last run.
+/// Rescan from genesis if the cached chain is no longer the longest one.
+async fn sync_cache(
+    cache: &mut WalletCache,
+    node: &mut impl NodeClient,
+    address: &Address,
+) -> anyhow::Result<()> {
+    loop {
+        let block = match node.get_block(cache.next_height()).await? {
+            Some(block) => block.verify_transaction_itself()?,
+            None => return Ok(()),
+        };
+
+        if let Err(e) = cache.apply_block(&block, address) {
+            println!("{}. Rescanning from genesis.", e);
+            cache.reset_scan();
+        }
+    }
+}
+
+async fn send_transaction(
+    node: &mut impl NodeClient,
+    transaction: &VerifiedTransaction,
+    out: &mut Output,
+) -> anyhow::Result<()> {
+    let txid = node.send_transaction(transaction).await?;
+    out.sent(txid);
+
+    Ok(())
+}
+
+/// Sign the merged `paths` as far as `secret_address` can, and write them into the first one.
+/// Send the transaction if it is complete and the node is available.
+async fn sign_psbt(
+    node: Option<&mut RemoteNode>,
+    paths: &[String],
+    secret_address: &SecretAddress,
+    out: &mut Output,
+) -> anyhow::Result<()> {
+    let mut psbt = psbt::read_merged(paths)?;
+    psbt.sign(secret_address)?;
+    psbt::write_psbt(&paths[0], &psbt)?;
+    out.psbt_written(&paths[0], &psbt.missing_signers());
+
+    if let (true, Some(node)) = (psbt.is_complete(), node) {
+        let transaction = psbt.finalize()?.verify()?;
+        send_transaction(node, &transaction, out).await?;
+    }
+    Ok(())
+}
+
+/// Message prefix signed to derive the change address of a payment, followed by its inputs.
+const CHANGE_CONTEXT: &[u8] = b"bcwallet change";
+
+/// Derive a change address from the inputs of a payment, and write its key next to
+/// `address_path`. Inputs are spent only once, so every payment gets a new address.
+fn derive_change_address(
+    address_path: &str,
+    secret_address: &SecretAddress,
+    utxos: &[Transition<Verified>],
+) -> anyhow::Result<(String, Address)> {
+    let mut context = CHANGE_CONTEXT.to_vec();
+    for utxo in utxos {
+        context.extend_from_slice(&utxo.sign().as_ref().to_bytes());
+    }
+    let change = secret_address.derive(&context);
+    let address = change.to_public_address();
+    let path = format!("{}.change-{}", address_path, &address.to_string()[..8]);
+    bcaddr::write_address(&path, &change)?;
+    Ok((path, address))
+}
+
+/// Receivers and quantities of a payment.
+/// With `privacy`, change is split by `split_change` and all outputs are shuffled.
+fn payment_outputs(
+    payment: (Address, Coin),
+    change: (Address, Coin),
+    privacy: bool,
+    split_change: Option<usize>,
+) -> Vec<(Address, Coin)> {
+    if !privacy {
+        return vec![payment, change];
+    }
+    let (change_address, change_qty) = change;
+    let mut outputs = change_qty
+        .split_denominations(split_change.unwrap_or(1))
+        .into_iter()
+        .map(|quantity| (change_address.clone(), quantity))
+        .chain(std::iter::once(payment))
+        .collect::<Vec<_>>();
+    outputs.shuffle(&mut rand::thread_rng());
+    outputs
+}
+
+/// Send all `utxos` to a new address, then archive the old address file encrypted by the new one.
+async fn rotate_key(
+    node: &mut impl NodeClient,
+    address_path: &str,
+    new_address_path: &str,
+    secret_address: &SecretAddress,
+    utxos: Vec<Transition<Verified>>,
+    fee: Option<Coin>,
+    out: &mut Output,
+) -> anyhow::Result<()> {
+    if Path::new(new_address_path).exists() {
+        anyhow::bail!("{} already exists.", new_address_path);
+    }
+
+    let utxo_qty = utxos.iter().map(Transition::quantity).sum::<Coin>();
+    let sweep_qty = match fee {
+        _ if utxos.is_empty() => None,
+        Some(fee) if fee < utxo_qty => Some(utxo_qty - fee),
+        Some(fee) => anyhow::bail!("Fee {} leaves nothing of your UTXO {}.", fee, utxo_qty),
+        None => anyhow::bail!("Provide fee to send your coin to the new address."),
+    };
+
+    // Write the new address first, so that swept coin never lacks its key
+    let new_secret_address = SecretAddress::create();
+    bcaddr::write_address(new_address_path, &new_secret_address)?;
+    let new_address = new_secret_address.to_public_address();
+    out.created_address(&new_address);
+
+    if let Some(sweep_qty) = sweep_qty {
+        let sweep = Transfer::offer(secret_address, new_address, sweep_qty);
+        let transaction =
+            Transaction::offer(secret_address, utxos, vec![sweep]).verify_transaction()?;
+        send_transaction(node, &transaction, out).await?;
+    }
+
+    let archive_path = format!("{}.archived", address_path);
+    bcaddr::archive_address(&archive_path, secret_address, &new_secret_address)?;
+    std::fs::remove_file(address_path)?;
+    out.archived(&archive_path);
+
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = BcWalletArgs::parse();
 
-    let secret_address = bcaddr::read_address(args.address)?;
+    match &args.command {
+        Some(
+            Command::Watch { .. }
+            | Command::Label { .. }
+            | Command::Receive { .. }
+            | Command::Restore { .. },
+        )
+        | None => {}
+        Some(command) => return run_command(&args, command).await,
+    }
+    let address_path = match &args.address {
+        Some(path) => path,
+        None => anyhow::bail!("Provide address file."),
+    };
+    let secret_address = bcaddr::read_address(address_path)?;
     let address = secret_address.to_public_address();
 
-    let mut utxo_requester = TopicPublisher::<RequestUtxoByAddress>::connect().await?;
-    let mut utxo_subscriber = TopicSubscriber::<RespondUtxoByAddress>::connect().await?;
+    match &args.command {
+        Some(Command::Receive { index }) => {
+            let receive = restore::receive_address(&secret_address, *index);
+            let key_file = restore::receive_key_path(address_path, *index);
+            bcaddr::write_address(&key_file, &receive)?;
+            println!("{}", receive.to_public_address());
+            return Ok(());
+        }
+        Some(Command::Restore { gap_limit, batch }) => {
+            let mut node = connect(&args).await?;
+            let report =
+                restore::restore(&mut node, address_path, &secret_address, *gap_limit, *batch)
+                    .await?;
+            return Ok(report.print(args.output)?);
+        }
+        _ => {}
+    }
 
-    // Request UTXO
-    utxo_requester.publish(&address).await?;
-    // Wait for UTXO response
-    let utxos = utxo_subscriber.recv().await?;
-    let utxos = utxos
-        .into_iter()
+    let mut out = Output::new(args.output, address.clone());
+
+    let cache_path = Path::new(&args.data_dir).join(format!("{}.cache", address));
+    let mut cache = match WalletCache::load(&cache_path, &secret_address) {
+        Ok(cache) => cache,
+        Err(e) => {
+            // Keep the standard output for the exported entries
+            let notice = format!("Discarding wallet cache. {}", e);
+            match args.export {
+                Some(_) => eprintln!("{}", notice),
+                None => out.notice(notice),
+            }
+            WalletCache::default()
+        }
+    };
+
+    if let Some(Command::Label { txid, label }) = &args.command {
+        if cache.history().iter().all(|entry| &entry.txid != txid) {
+            out.notice(format!("{} is not in your history yet.", txid));
+        }
+        cache.set_label(txid.clone(), label.clone());
+        std::fs::create_dir_all(&args.data_dir)?;
+        cache.save(&cache_path, &secret_address)?;
+        out.labeled(txid, label);
+        return Ok(out.finish()?);
+    }
+
+    if let Some(format) = args.export {
+        let chain = match &args.chain {
+            Some(chain) => chain,
+            None => anyhow::bail!("Provide chain file to export history."),
+        };
+        let blocks = replay::read_chain(chain)?
+            .into_iter()
+            .map(|block| block.verify_transaction_itself())
+            .collect::<Result<Vec<_>, _>>()?;
+        print!(
+            "{}",
+            export::export(&blocks, &address, cache.labels(), format)
+        );
+        return Ok(());
+    }
+
+    let mut node = match args.offline {
+        true => None,
+        false => Some(connect(&args).await?),
+    };
+    if let Some(node) = node.as_mut() {
+        sync_cache(&mut cache, node, &address).await?;
+        std::fs::create_dir_all(&args.data_dir)?;
+        cache.save(&cache_path, &secret_address)?;
+    }
+
+    out.scanned(cache.tip());
+    out.holdings(&cache);
+
+    if let Some(Command::Watch {
+        notify,
+        notify_command,
+    }) = &args.command
+    {
+        let node = match node.as_mut() {
+            Some(node) => node,
+            None => anyhow::bail!("Watching requires the node. Run without --offline."),
+        };
+        let notifier = Notifier {
+            desktop: *notify,
+            command: notify_command.clone(),
+        };
+        return watch::watch(
+            node,
+            &mut cache,
+            &cache_path,
+            &secret_address,
+            &address,
+            &notifier,
+            args.output,
+        )
+        .await;
+    }
+
+    if !args.sign_psbt.is_empty() {
+        sign_psbt(node.as_mut(), &args.sign_psbt, &secret_address, &mut out).await?;
+        return Ok(out.finish()?);
+    }
+
+    let sends = args.rotate_key.is_some()
+        || (args.destination.is_some() && args.quantity.is_some() && args.fee.is_some());
+    if !sends {
+        return Ok(out.finish()?);
+    }
+    // A payment written by --create-psbt is sent later
+    let node = match (node.as_mut(), &args.create_psbt) {
+        (Some(node), _) => Some(node),
+        (None, Some(_)) if args.rotate_key.is_none() => None,
+        (None, _) => anyhow::bail!("Sending coin requires the node. Run without --offline."),
+    };
+
+    let utxos = cache
+        .utxos()
+        .iter()
+        .cloned()
         .filter_map(|tx| tx.verify().ok())
         .collect::<Vec<_>>();
 
-    println!("UTXO:");
-    for utxo in utxos.iter() {
-        println!("{}", utxo);
+    if let Some(new_address_path) = &args.rotate_key {
+        let node = node.expect("Checked above");
+        rotate_key(
+            node,
+            address_path,
+            new_address_path,
+            &secret_address,
+            utxos,
+            args.fee,
+            &mut out,
+        )
+        .await?;
+        return Ok(out.finish()?);
     }
 
     let (dest, send_qty, fee_qty) = match (args.destination, args.quantity, args.fee) {
         (Some(d), Some(q), Some(f)) => (d, q, f),
-        _ => return Ok(()),
+        _ => return Ok(out.finish()?),
     };
 
     let utxo_qty = utxos.iter().map(Transition::quantity).sum::<Coin>();
     let change_qty = if send_qty <= utxo_qty - fee_qty {
         utxo_qty - send_qty - fee_qty
     } else {
-        println!(
-            "You offer sending {} coin, but your UTXO has only {} coin in total.",
+        out.notice(format!(
+            "You offer sending {}, but your UTXO has only {} in total.",
             send_qty, utxo_qty
-        );
-        return Ok(());
+        ));
+        return Ok(out.finish()?);
     };
 
-    let transfer = Transfer::offer(&secret_address, dest, send_qty);
-    let change = Transfer::offer(&secret_address, address, change_qty);
+    // Write the change key first, so that change never lacks its key
+    let change_address = match args.privacy && change_qty > Coin::default() {
+        true => {
+            let (path, change_address) =
+                derive_change_address(address_path, &secret_address, &utxos)?;
+            out.change_address(&change_address, &path);
+            change_address
+        }
+        false => address.clone(),
+    };
+    let outputs = payment_outputs(
+        (dest, send_qty),
+        (change_address, change_qty),
+        args.privacy,
+        args.split_change,
+    );
 
-    let transaction =
-        Transaction::offer(&secret_address, utxos, vec![transfer, change]).verify_transaction()?;
+    if let Some(path) = &args.create_psbt {
+        let inputs = utxos.iter().map(Transition::to_unverified).collect();
+        let outputs = outputs
+            .into_iter()
+            .map(|(receiver, quantity)| UnsignedTransfer { receiver, quantity })
+            .collect();
+        let psbt = PartiallySignedTransaction::new(address, inputs, outputs);
+        psbt::write_psbt(path, &psbt)?;
+        out.psbt_written(path, &psbt.missing_signers());
+        return Ok(out.finish()?);
+    }
+    let node = node.expect("Checked above");
 
-    let mut transaction_publisher = TopicPublisher::<CreateTransaction>::connect().await?;
-    transaction_publisher.publish(&transaction).await?;
+    let outputs = outputs
+        .into_iter()
+        .map(|(receiver, quantity)| Transfer::offer(&secret_address, receiver, quantity))
+        .collect();
 
-    println!("Notified transaction");
+    let transaction = Transaction::offer(&secret_address, utxos, outputs).verify_transaction()?;
 
-    Ok(())
+    send_transaction(node, &transaction, &mut out).await?;
+    Ok(out.finish()?)
 }
@@ -0,0 +1,32 @@
+use blockchain_core::psbt::PartiallySignedTransaction;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Read a partially signed transaction written by `write_psbt`.
+pub fn read_psbt(path: impl AsRef<Path>) -> anyhow::Result<PartiallySignedTransaction> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Write a partially signed transaction as JSON, which other parties can read.
+pub fn write_psbt(path: impl AsRef<Path>, psbt: &PartiallySignedTransaction) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, psbt)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Merge the files into the first one.
+pub fn read_merged(paths: &[String]) -> anyhow::Result<PartiallySignedTransaction> {
+    let (first, rest) = match paths.split_first() {
+        Some(split) => split,
+        None => anyhow::bail!("Provide partially signed transaction files."),
+    };
+    let mut psbt = read_psbt(first)?;
+    for path in rest {
+        psbt.merge(&read_psbt(path)?)?;
+    }
+    Ok(psbt)
+}
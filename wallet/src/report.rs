@@ -0,0 +1,285 @@
+use crate::cache::{HistoryEntry, WalletCache};
+use bcaddr::output::{self, OutputFormat};
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::psbt::SignTarget;
+use blockchain_core::timestamp::Timestamp;
+use blockchain_core::{Address, BlockHeight, Coin, Transition, Yet};
+use serde::Serialize;
+
+/// Last block scanned into the wallet cache.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScannedBlock {
+    pub height: BlockHeight,
+    pub digest: BlockDigest,
+}
+
+/// Transaction in the history, with the owner's label if any.
+#[derive(Debug, Clone, Serialize)]
+pub struct LabeledEntry {
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+    pub label: Option<String>,
+}
+
+/// Unspent coin of the wallet owner.
+#[derive(Debug, Clone, Serialize)]
+pub struct Utxo {
+    /// transfer, generation, htlc or multisig
+    pub kind: &'static str,
+    pub sender: Option<Address>,
+    pub receiver: Address,
+    pub quantity: Coin,
+    pub timestamp: Timestamp,
+}
+
+impl From<&Transition<Yet>> for Utxo {
+    fn from(utxo: &Transition<Yet>) -> Self {
+        let kind = match utxo {
+            Transition::Transfer(_) => "transfer",
+            Transition::Generation(_) => "generation",
+            Transition::Htlc(_) => "htlc",
+            Transition::Multisig(_) => "multisig",
+        };
+        Self {
+            kind,
+            sender: utxo.sender().cloned(),
+            receiver: utxo.receiver().clone(),
+            quantity: utxo.quantity(),
+            timestamp: utxo.timestamp(),
+        }
+    }
+}
+
+/// Address created by `--rotate-key`, and the archive of the old one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rotation {
+    pub new_address: Address,
+    pub archive: Option<String>,
+}
+
+/// Address derived by `--privacy` to receive change, and its key file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeAddress {
+    pub address: Address,
+    pub key_file: String,
+}
+
+/// Partially signed transaction written by `--create-psbt` or `--sign-psbt`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PsbtFile {
+    pub file: String,
+    /// Parties who have not signed yet
+    pub missing_signers: Vec<Address>,
+}
+
+/// Request printed by `export-signing-request`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SigningRequestOutput {
+    pub signer: Address,
+    pub targets: Vec<SignTarget>,
+    /// Hex blob for the signer
+    pub request: String,
+}
+
+impl SigningRequestOutput {
+    pub fn print(&self, format: OutputFormat) -> serde_json::Result<()> {
+        match format {
+            OutputFormat::Text => {
+                let targets = self.targets.iter().map(ToString::to_string);
+                let targets = targets.collect::<Vec<_>>().join(", ");
+                println!("Signing request of {} for {}:", self.signer, targets);
+                println!("{}", self.request);
+                Ok(())
+            }
+            OutputFormat::Json => output::print_json(self),
+        }
+    }
+}
+
+/// Result of `import-signature`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedSignature {
+    pub file: String,
+    pub signer: Address,
+    /// Parties who have not signed yet
+    pub missing_signers: Vec<Address>,
+    /// Transaction accepted by the node, once every party has signed
+    pub sent: Option<BlockDigest>,
+}
+
+impl ImportedSignature {
+    pub fn print(&self, format: OutputFormat) -> serde_json::Result<()> {
+        match format {
+            OutputFormat::Text => {
+                println!("Added signs of {} to {}", self.signer, self.file);
+                for signer in self.missing_signers.iter() {
+                    println!("Waiting for the sign of {}", signer);
+                }
+                if let Some(txid) = &self.sent {
+                    println!("Node accepted transaction {}", txid);
+                }
+                Ok(())
+            }
+            OutputFormat::Json => output::print_json(self),
+        }
+    }
+}
+
+/// Results of a run, printed as one JSON document by `--output json`.
+/// Coin quantities are in base units.
+#[derive(Debug, Clone, Serialize)]
+pub struct WalletReport {
+    pub address: Address,
+    pub scanned: Option<ScannedBlock>,
+    pub history: Vec<LabeledEntry>,
+    pub utxos: Vec<Utxo>,
+    pub balance: Coin,
+    /// Transactions accepted by the node
+    pub sent: Vec<BlockDigest>,
+    pub rotation: Option<Rotation>,
+    pub change: Option<ChangeAddress>,
+    pub psbt: Option<PsbtFile>,
+    /// Messages which are not results, such as warnings
+    pub notices: Vec<String>,
+}
+
+/// Prints results as they come in text format, or collects them into a `WalletReport`
+/// printed by `finish` in JSON format.
+pub struct Output {
+    format: OutputFormat,
+    report: WalletReport,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, address: Address) -> Self {
+        let report = WalletReport {
+            address,
+            scanned: None,
+            history: vec![],
+            utxos: vec![],
+            balance: Coin::default(),
+            sent: vec![],
+            rotation: None,
+            change: None,
+            psbt: None,
+            notices: vec![],
+        };
+        Self { format, report }
+    }
+
+    pub fn notice(&mut self, text: String) {
+        match self.format {
+            OutputFormat::Text => println!("{}", text),
+            OutputFormat::Json => self.report.notices.push(text),
+        }
+    }
+
+    pub fn scanned(&mut self, tip: Option<&(BlockHeight, BlockDigest)>) {
+        if self.format == OutputFormat::Text {
+            match tip {
+                Some((height, digest)) => {
+                    println!("Scanned up to block {} ({})", height, digest.fmt_short())
+                }
+                None => println!("No block has been scanned."),
+            }
+        }
+        self.report.scanned = tip.map(|(height, digest)| ScannedBlock {
+            height: *height,
+            digest: digest.clone(),
+        });
+    }
+
+    pub fn holdings(&mut self, cache: &WalletCache) {
+        let history = cache.history().iter().map(|entry| LabeledEntry {
+            entry: entry.clone(),
+            label: cache.label(&entry.txid).map(str::to_string),
+        });
+        self.report.history = history.collect();
+        self.report.utxos = cache.utxos().iter().map(Utxo::from).collect();
+        self.report.balance = cache.balance();
+
+        if self.format == OutputFormat::Text {
+            println!("History:");
+            for LabeledEntry { entry, label } in self.report.history.iter() {
+                match label {
+                    Some(label) => println!("{} {}", entry, label),
+                    None => println!("{}", entry),
+                }
+            }
+
+            println!("UTXO:");
+            for utxo in cache.utxos() {
+                println!("{}", utxo);
+            }
+            println!("Balance: {}", self.report.balance);
+        }
+    }
+
+    pub fn labeled(&mut self, txid: &BlockDigest, label: &str) {
+        let text = match label.is_empty() {
+            true => format!("Removed the label of {}", txid),
+            false => format!("Labeled {} as {}", txid, label),
+        };
+        self.notice(text);
+    }
+
+    pub fn sent(&mut self, txid: BlockDigest) {
+        if self.format == OutputFormat::Text {
+            println!("Node accepted transaction {}", txid);
+        }
+        self.report.sent.push(txid);
+    }
+
+    pub fn created_address(&mut self, address: &Address) {
+        if self.format == OutputFormat::Text {
+            println!("Created new address {}", address);
+        }
+        self.report.rotation = Some(Rotation {
+            new_address: address.clone(),
+            archive: None,
+        });
+    }
+
+    pub fn archived(&mut self, path: &str) {
+        if self.format == OutputFormat::Text {
+            println!(
+                "Archived old address to {}, which only the new address can decrypt.",
+                path
+            );
+        }
+        if let Some(rotation) = self.report.rotation.as_mut() {
+            rotation.archive = Some(path.to_string());
+        }
+    }
+
+    pub fn change_address(&mut self, address: &Address, key_file: &str) {
+        if self.format == OutputFormat::Text {
+            println!("Paying change to {}, whose key is in {}", address, key_file);
+        }
+        self.report.change = Some(ChangeAddress {
+            address: address.clone(),
+            key_file: key_file.to_string(),
+        });
+    }
+
+    pub fn psbt_written(&mut self, path: &str, missing_signers: &[&Address]) {
+        if self.format == OutputFormat::Text {
+            println!("Wrote partially signed transaction to {}", path);
+            for signer in missing_signers {
+                println!("Waiting for the sign of {}", signer);
+            }
+        }
+        self.report.psbt = Some(PsbtFile {
+            file: path.to_string(),
+            missing_signers: missing_signers.iter().map(|&a| a.clone()).collect(),
+        });
+    }
+
+    /// Print the report in JSON format. Text has been printed already.
+    pub fn finish(self) -> serde_json::Result<()> {
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => output::print_json(&self.report),
+        }
+    }
+}
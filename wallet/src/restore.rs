@@ -0,0 +1,125 @@
+use bcaddr::output::{self, OutputFormat};
+use blockchain_client::RemoteNode;
+use blockchain_core::{Address, Coin, SecretAddress};
+use serde::Serialize;
+
+/// Message prefix signed to derive a receiving address, followed by its index.
+const RECEIVE_CONTEXT: &[u8] = b"bcwallet receive";
+
+/// Receiving address at `index`, which only `secret_address` derives.
+/// Indexes are handed out in order, so that a restore finds them by scanning.
+pub fn receive_address(secret_address: &SecretAddress, index: u32) -> SecretAddress {
+    let mut context = RECEIVE_CONTEXT.to_vec();
+    context.extend_from_slice(&index.to_le_bytes());
+    secret_address.derive(&context)
+}
+
+/// Key file of the receiving address at `index`, next to the address file.
+pub fn receive_key_path(address_path: &str, index: u32) -> String {
+    format!("{}.receive-{}", address_path, index)
+}
+
+/// Receiving address found holding coin.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredAddress {
+    pub index: u32,
+    pub address: Address,
+    pub key_file: String,
+    pub confirmed: Coin,
+    /// Balance once the transactions in the mempool of the node are included
+    pub pending: Coin,
+}
+
+/// Result of `restore`. Coin quantities are in base units in JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub addresses: Vec<RestoredAddress>,
+    /// Receiving addresses asked about, from index 0
+    pub scanned: u32,
+}
+
+impl RestoreReport {
+    pub fn print(&self, format: OutputFormat) -> serde_json::Result<()> {
+        match format {
+            OutputFormat::Text => {
+                for a in self.addresses.iter() {
+                    println!(
+                        "#{} {}: {} confirmed, {} pending, key written to {}",
+                        a.index, a.address, a.confirmed, a.pending, a.key_file
+                    );
+                }
+                let total = self.addresses.iter().map(|a| a.confirmed).sum::<Coin>();
+                println!(
+                    "Restored {} receiving addresses holding {}, out of {} scanned.",
+                    self.addresses.len(),
+                    total,
+                    self.scanned
+                );
+                Ok(())
+            }
+            OutputFormat::Json => output::print_json(self),
+        }
+    }
+}
+
+/// Derive receiving addresses in order and ask the node for their balances, `batch` at a time,
+/// until `gap_limit` addresses in a row hold nothing. Key files of the addresses holding coin
+/// are written next to `address_path`.
+///
+/// An address whose coin has all been spent looks unused, so a gap of such addresses longer
+/// than `gap_limit` ends the scan early. Nothing is lost by this, as they hold nothing.
+pub async fn restore(
+    node: &mut RemoteNode,
+    address_path: &str,
+    secret_address: &SecretAddress,
+    gap_limit: u32,
+    batch: u32,
+) -> anyhow::Result<RestoreReport> {
+    let mut report = RestoreReport {
+        addresses: vec![],
+        scanned: 0,
+    };
+    let mut gap = 0;
+    while gap < gap_limit {
+        let start = report.scanned;
+        let secrets = (start..start + batch.max(1))
+            .map(|index| receive_address(secret_address, index))
+            .collect::<Vec<_>>();
+        let addresses = secrets
+            .iter()
+            .map(SecretAddress::to_public_address)
+            .collect::<Vec<_>>();
+        let balances = node.rpc().balances(&addresses).await?;
+        if balances.len() != addresses.len() {
+            anyhow::bail!(
+                "Node answered {} balances for {} addresses.",
+                balances.len(),
+                addresses.len()
+            );
+        }
+
+        for (index, (secret, (address, confirmed, pending))) in
+            (start..).zip(secrets.iter().zip(balances))
+        {
+            report.scanned = index + 1;
+            if confirmed == Coin::default() && pending == Coin::default() {
+                gap += 1;
+                if gap >= gap_limit {
+                    break;
+                }
+                continue;
+            }
+            gap = 0;
+            let key_file = receive_key_path(address_path, index);
+            bcaddr::write_address(&key_file, secret)?;
+            report.addresses.push(RestoredAddress {
+                index,
+                address,
+                key_file,
+                confirmed,
+                pending,
+            });
+        }
+    }
+    Ok(report)
+}
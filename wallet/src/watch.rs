@@ -0,0 +1,141 @@
+use crate::cache::{HistoryEntry, WalletCache};
+use bcaddr::output::OutputFormat;
+use blockchain_client::NodeClient;
+use blockchain_core::digest::BlockDigest;
+use blockchain_core::{Address, BlockHeight, Coin, SecretAddress};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Command;
+
+/// Change of the wallet found in a new block.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchEvent {
+    /// received, or confirmed for a payment of the owner
+    pub kind: &'static str,
+    pub height: BlockHeight,
+    pub txid: BlockDigest,
+    pub received: Coin,
+    pub spent: Coin,
+}
+
+impl WatchEvent {
+    /// Only the owner can spend its coin, so a transaction spending any is the owner's payment.
+    fn new(entry: &HistoryEntry) -> Self {
+        let kind = match entry.spent > Coin::default() {
+            true => "confirmed",
+            false => "received",
+        };
+        Self {
+            kind,
+            height: entry.height,
+            txid: entry.txid.clone(),
+            received: entry.received,
+            spent: entry.spent,
+        }
+    }
+
+    fn summary(&self) -> String {
+        match self.kind {
+            "confirmed" => format!("Payment {} confirmed", self.txid.fmt_short()),
+            _ => format!("Received {}", self.received),
+        }
+    }
+
+    fn body(&self) -> String {
+        format!(
+            "Transaction {} in block {}: +{} -{}",
+            self.txid, self.height, self.received, self.spent
+        )
+    }
+}
+
+/// Where events are announced besides the standard output.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    /// Show a desktop notification
+    pub desktop: bool,
+    /// Shell command to run for each event
+    pub command: Option<String>,
+}
+
+impl Notifier {
+    /// Announce `event`. A failed notification is reported and does not stop watching.
+    fn notify(&self, event: &WatchEvent) {
+        if self.desktop {
+            let res = notify_rust::Notification::new()
+                .appname("bcwallet")
+                .summary(&event.summary())
+                .body(&event.body())
+                .show();
+            if let Err(e) = res {
+                eprintln!("Failed to show desktop notification. {}", e);
+            }
+        }
+
+        if let Some(command) = &self.command {
+            let res = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("BCWALLET_EVENT", event.kind)
+                .env("BCWALLET_HEIGHT", event.height.to_string())
+                .env("BCWALLET_TXID", event.txid.to_string())
+                .env("BCWALLET_RECEIVED", event.received.to_string())
+                .env("BCWALLET_SPENT", event.spent.to_string())
+                .status();
+            match res {
+                Ok(status) if !status.success() => {
+                    eprintln!("Notification command exited with {}", status)
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to run notification command. {}", e),
+            }
+        }
+    }
+}
+
+/// Scan each block the node finds, and announce transactions which moved the owner's coin.
+/// Runs until the subscription ends.
+pub async fn watch(
+    node: &mut impl NodeClient,
+    cache: &mut WalletCache,
+    cache_path: &Path,
+    secret: &SecretAddress,
+    address: &Address,
+    notifier: &Notifier,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut blocks = node.subscribe_blocks().await?;
+    if format == OutputFormat::Text {
+        println!("Watching {} for new blocks", address);
+    }
+
+    // Scan once before waiting, for blocks found while subscribing
+    loop {
+        // A reorg rescans the cache, so compare transactions instead of counting them
+        let known = cache
+            .history()
+            .iter()
+            .map(|entry| entry.txid.clone())
+            .collect::<HashSet<_>>();
+        crate::sync_cache(cache, node, address).await?;
+        cache.save(cache_path, secret)?;
+
+        let events = cache
+            .history()
+            .iter()
+            .filter(|entry| !known.contains(&entry.txid))
+            .map(WatchEvent::new);
+        for event in events {
+            match format {
+                OutputFormat::Text => println!("{}. {}", event.summary(), event.body()),
+                OutputFormat::Json => println!("{}", serde_json::to_string(&event)?),
+            }
+            notifier.notify(&event);
+        }
+
+        if blocks.recv().await.is_none() {
+            anyhow::bail!("Node closed the block subscription.");
+        }
+    }
+}